@@ -1,16 +1,15 @@
-use anyhow::anyhow;
 use crate::message_types::{PayloadEncodeError};
 
-pub fn encode_array<const N: usize>(field_name: &str, src: &[u8]) -> Result<[u8; N], PayloadEncodeError> {
+pub fn encode_array<const N: usize>(field_name: &'static str, src: &[u8]) -> Result<[u8; N], PayloadEncodeError> {
   let mut arr = [0u8; N];
   let _ = encode_array_internal(field_name, &mut arr, src)?;
   Ok(arr)
 }
 
-fn encode_array_internal<'a>(field_name: &str, dst: &'a mut [u8], src: &[u8]) -> Result<&'a [u8], PayloadEncodeError> {
+fn encode_array_internal<'a>(field_name: &'static str, dst: &'a mut [u8], src: &[u8]) -> Result<&'a [u8], PayloadEncodeError> {
   let max = dst.len();
   if src.len() > max {
-    return Err(PayloadEncodeError::GenericError(anyhow!("{field_name}={src:?} exceeds max size {max}")));
+    return Err(PayloadEncodeError::FieldTooLong { field_name, len: src.len(), max });
   }
   for (index, b) in src.iter().take(dst.len()).enumerate() {
     dst[index] = *b;
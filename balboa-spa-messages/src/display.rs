@@ -0,0 +1,253 @@
+//! Human readable rendering of protocol messages, intended for logging and sniffing tools where
+//! the raw [Debug] output of [Message] (hex bytes) or [MessageType] (deeply nested structs) is
+//! too noisy to follow a live conversation on the bus.
+//!
+//! Use [compact] for a one-line summary highlighting the fields that typically matter
+//! (temperatures, pump/light states, channels, etc) and [verbose] for a multiline dump that
+//! includes every parsed field along with the raw payload.
+
+use std::fmt::{Debug, Display, Formatter};
+
+use num_traits::FromPrimitive;
+
+use crate::message::Message;
+use crate::message_types::{MessageType, MessageTypeKind, StatusUpdateResponseV1};
+use crate::parsed_enum::ParsedEnum;
+
+/// One-line summary of a raw message, falling back to the raw bytes if it can't be parsed.
+pub fn compact(message: &Message) -> CompactMessage<'_> {
+  CompactMessage { message }
+}
+
+/// Multiline summary of a raw message that includes all parsed fields and the raw payload.
+pub fn verbose(message: &Message) -> VerboseMessage<'_> {
+  VerboseMessage { message }
+}
+
+pub struct CompactMessage<'a> {
+  message: &'a Message,
+}
+
+impl Display for CompactMessage<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "[{:?}] ", self.message.channel)?;
+    match MessageType::try_from(self.message) {
+      Ok(mt) => write!(f, "{}", CompactMessageType(&mt)),
+      Err(e) => {
+        write!(f, "{} ({e}): ", RawKind(self.message.message_type))?;
+        write_hex(f, &self.message.payload)
+      }
+    }
+  }
+}
+
+pub struct VerboseMessage<'a> {
+  message: &'a Message,
+}
+
+impl Display for VerboseMessage<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let message = self.message;
+    writeln!(
+        f,
+        "[{:?}] {} (type={:02X}, len={})",
+        message.channel,
+        RawKind(message.message_type),
+        message.message_type,
+        message.payload.len())?;
+    match MessageType::try_from(message) {
+      Ok(mt) => {
+        for line in format!("{mt:#?}").lines() {
+          writeln!(f, "  {line}")?;
+        }
+      }
+      Err(e) => writeln!(f, "  Parse error: {e}")?,
+    }
+    write!(f, "  raw: ")?;
+    write_hex(f, &message.payload)
+  }
+}
+
+/// One-line summary of an already parsed message.
+pub struct CompactMessageType<'a>(pub &'a MessageType);
+
+impl Display for CompactMessageType<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let mt = self.0;
+    write!(f, "{:?}", MessageTypeKind::from(mt))?;
+    match mt {
+      MessageType::NewClientClearToSend() |
+      MessageType::ChannelAssignmentAck() |
+      MessageType::ExistingClientRequest() |
+      MessageType::ClearToSend() |
+      MessageType::NothingToSend() => Ok(()),
+      MessageType::ChannelAssignmentRequest { device_type, client_hash } =>
+        write!(f, " device_type={device_type:02X} hash={client_hash:04X}"),
+      MessageType::ChannelAssignmentResponse { channel, client_hash } =>
+        write!(f, " channel={channel:?} hash={client_hash:04X}"),
+      MessageType::ExistingClientResponse { unknown } => {
+        write!(f, " ")?;
+        write_hex(f, unknown)
+      }
+      MessageType::ToggleItemRequest { item_code, .. } =>
+        write!(f, " item={item_code:?}"),
+      MessageType::StatusUpdate(status) => write_status(f, &status.v1),
+      MessageType::SetTemperatureRequest { temperature } =>
+        write!(f, " {temperature:?}"),
+      MessageType::SetTimeRequest { time, clock_mode } =>
+        write!(f, " time={time} clock_mode={clock_mode:?}"),
+      MessageType::SettingsRequest(request) =>
+        write!(f, " {request:?}"),
+      MessageType::FilterCycles { cycles } =>
+        write!(f, " {cycles:?}"),
+      MessageType::InformationResponse(info) => {
+        write!(
+            f,
+            " model={} version={} heater={:?}/{:?}",
+            info.system_model_number.trim_end_matches('\0'),
+            info.software_version,
+            info.heater_voltage,
+            info.heater_type)
+      }
+      MessageType::Settings0x04Response(settings) => {
+        let temps = &settings.min_max_temps;
+        write!(
+            f,
+            " low={:.0}-{:.0}F high={:.0}-{:.0}F",
+            temps.low_range.0.as_fahrenheit(),
+            temps.low_range.1.as_fahrenheit(),
+            temps.high_range.0.as_fahrenheit(),
+            temps.high_range.1.as_fahrenheit())
+      }
+      MessageType::PreferencesResponse(prefs) => {
+        write!(
+            f,
+            " scale={:?} clock={:?} reminders={:?}",
+            prefs.temperature_scale,
+            prefs.clock_mode,
+            prefs.reminder_set)
+      }
+      MessageType::SetPreferenceRequest(pref) =>
+        write!(f, " {pref:?}"),
+      MessageType::FaultLogResponse(fault) => {
+        write!(
+            f,
+            " entry={}/{} code={:?} days_ago={} time={}",
+            fault.entry_number,
+            fault.total_entries,
+            fault.fault_code,
+            fault.days_ago,
+            fault.time)
+      }
+      MessageType::ChangeSetupRequest { setup_number } =>
+        write!(f, " setup={setup_number}"),
+      MessageType::GfciTestResponse { result } =>
+        write!(f, " result={result:?}"),
+      MessageType::LockRequest(request) =>
+        write!(f, " {request:?}"),
+      MessageType::ConfigurationResponse(config) => {
+        write!(f, " pumps=")?;
+        write_list(f, &config.pumps)?;
+        write!(f, " lights=")?;
+        write_list(f, &config.has_lights)?;
+        write!(
+            f,
+            " blower={} circ={}",
+            config.has_blower,
+            config.has_circulation_pump)
+      }
+      MessageType::WifiModuleConfigurationResponse(wifi) => {
+        let mac = wifi.mac;
+        write!(
+            f,
+            " mac={:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5])
+      }
+      MessageType::ToggleTestSettingRequest(request) =>
+        write!(f, " {request:?}"),
+    }
+  }
+}
+
+fn write_status(f: &mut Formatter<'_>, status: &StatusUpdateResponseV1) -> std::fmt::Result {
+  match &status.current_temperature {
+    Some(t) => write!(f, " temp={t}")?,
+    None => write!(f, " temp=?")?,
+  }
+  write!(
+      f,
+      " set={} state={:?} heating={:?} time={}",
+      status.set_temperature,
+      status.spa_state,
+      status.heating_state,
+      status.time)?;
+  write!(f, " pumps=")?;
+  write_list(f, &status.pump_status)?;
+  write!(f, " lights=")?;
+  write_list(f, &status.light_status)?;
+  write!(f, " blower={:?}", status.blower_status)?;
+  if status.panel_locked {
+    write!(f, " locked")?;
+  }
+  if status.settings_locked {
+    write!(f, " settings-locked")?;
+  }
+  Ok(())
+}
+
+fn write_list<T: Debug>(f: &mut Formatter<'_>, values: &[ParsedEnum<T, u8>]) -> std::fmt::Result {
+  write!(f, "[")?;
+  for (i, value) in values.iter().enumerate() {
+    if i > 0 {
+      write!(f, ",")?;
+    }
+    write!(f, "{value:?}")?;
+  }
+  write!(f, "]")
+}
+
+fn write_hex(f: &mut Formatter<'_>, bytes: &[u8]) -> std::fmt::Result {
+  for (i, b) in bytes.iter().enumerate() {
+    if i > 0 {
+      write!(f, " ")?;
+    }
+    write!(f, "{b:02X}")?;
+  }
+  Ok(())
+}
+
+struct RawKind(u8);
+
+impl Display for RawKind {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match MessageTypeKind::from_u8(self.0) {
+      Some(kind) => write!(f, "{kind:?}"),
+      None => write!(f, "Unknown({:02X})", self.0),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::channel::Channel;
+  use super::*;
+
+  #[test]
+  fn test_compact_simple() {
+    let message = MessageType::ChannelAssignmentResponse {
+      channel: Channel::Client(0x10),
+      client_hash: 0xf247,
+    }.to_message(Channel::MulticastChannelAssignment).unwrap();
+    assert_eq!(
+        compact(&message).to_string(),
+        "[MulticastChannelAssignment] ChannelAssignmentResponse channel=Client(16) hash=F247");
+  }
+
+  #[test]
+  fn test_compact_unknown() {
+    let message = Message::new(Channel::Client(0x10), 0x99, vec![0x01, 0x02]);
+    assert_eq!(
+        compact(&message).to_string(),
+        "[Client(16)] Unknown(99) (Wrong message type): 01 02");
+  }
+}
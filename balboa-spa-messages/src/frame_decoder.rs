@@ -1,16 +1,23 @@
 use std::fmt::Debug;
 use crc::{Algorithm, Crc};
 use log::{error, info, trace, warn};
+use crate::channel::Channel;
 use crate::message::Message;
 use crate::ring_buffer::ByteRingBuffer;
 
 const ERROR_BUF_SIZE: usize = 128;
 
+/// Largest value the length field can take on the wire.  See [DecoderState::GotStart] handling
+/// for why this is one less than [START_OF_MESSAGE].
+pub const DEFAULT_MAX_FRAME_LEN: usize = START_OF_MESSAGE as usize - 1;
+
 #[derive(Debug)]
 pub struct FrameDecoder {
   state: DecoderState,
   num_bytes_expected: Option<usize>,
+  max_frame_len: usize,
   current_message: Vec<u8>,
+  decoded_message: Message,
   frames_with_errors: usize,
   latest_lost_bytes: ByteRingBuffer,
 }
@@ -43,29 +50,56 @@ pub(crate) const CRC_ENGINE: Crc<u8> = Crc::<u8>::new(&CRC_ALGORITHM);
 
 impl Default for FrameDecoder {
   fn default() -> Self {
+    Self::with_max_frame_len(DEFAULT_MAX_FRAME_LEN)
+  }
+}
+
+impl FrameDecoder {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Bound the size of frames we're willing to decode.  Frames whose length field exceeds
+  /// `max_frame_len` are treated as a communication error.  All buffers are allocated up front
+  /// so that no further allocations occur while decoding.
+  pub fn with_max_frame_len(max_frame_len: usize) -> Self {
     Self {
       state: DecoderState::Ready,
       num_bytes_expected: None,
-      current_message: vec![],
+      max_frame_len,
+      current_message: Vec::with_capacity(max_frame_len),
+      decoded_message: Message::new(
+          Channel::Unknown(0),
+          0,
+          Vec::with_capacity(max_frame_len.saturating_sub(5))),
       frames_with_errors: 0,
       latest_lost_bytes: ByteRingBuffer::with_max_size(ERROR_BUF_SIZE),
     }
   }
-}
 
-impl FrameDecoder {
-  pub fn new() -> Self {
-    Default::default()
+  pub fn max_frame_len(&self) -> usize {
+    self.max_frame_len
   }
 
   pub fn accept(&mut self, byte: u8) -> Option<Message> {
+    if self.accept_in_place(byte) {
+      Some(self.decoded_message.clone())
+    } else {
+      None
+    }
+  }
+
+  /// Allocation-free variant of [FrameDecoder::accept].  Returns true when a complete message
+  /// was decoded, which can then be borrowed with [FrameDecoder::last_message] until the next
+  /// byte is accepted.
+  pub fn accept_in_place(&mut self, byte: u8) -> bool {
     if self.handle_byte(byte) {
       if self.state == DecoderState::Ready {
-        let message = Message::from_bytes(&self.current_message);
+        let result = self.decoded_message.decode_from(&self.current_message);
         self.current_message.clear();
-        match message {
-          Ok(message) => {
-            return Some(message)
+        match result {
+          Ok(_) => {
+            return true
           },
           Err(e) => {
             error!("Failed to parse message: {e:?}");
@@ -81,7 +115,11 @@ impl FrameDecoder {
       self.latest_lost_bytes.push(byte);
     }
 
-    None
+    false
+  }
+
+  pub fn last_message(&self) -> &Message {
+    &self.decoded_message
   }
 
   fn handle_byte(&mut self, byte: u8) -> bool {
@@ -100,7 +138,7 @@ impl FrameDecoder {
           // Maximum length set at START_OF_MESSAGE-1 so that we can better catch a
           // misaligned sequence of bytes that would cause us to get "stuck" reading for quite
           // some time.
-          c @ 5..=START_OF_MESSAGE if c != START_OF_MESSAGE => {
+          c @ 5..=START_OF_MESSAGE if c != START_OF_MESSAGE && usize::from(c) <= self.max_frame_len => {
             self.num_bytes_expected = Some(usize::from(byte) - 2);
             self.current_message.push(byte);
             self.move_to_state(DecoderState::GotLength);
@@ -279,6 +317,20 @@ mod tests {
     assert_eq!(reader.frames_with_errors, 1);
   }
 
+  #[test]
+  fn test_max_frame_len() {
+    let writer = FrameEncoder::new();
+    let message = Message::new(Channel::MulticastChannelAssignment, 0x1, vec![0x02, 0x03, 0x04]);
+    let encoded = writer.encode(&message).unwrap();
+
+    let mut reader = FrameDecoder::with_max_frame_len(7);
+    assert_eq!(decode_one(&mut reader, &encoded), None);
+    assert_eq!(reader.frames_with_errors(), 1);
+
+    let mut reader = FrameDecoder::with_max_frame_len(8);
+    assert_eq!(decode_one(&mut reader, &encoded), Some(message));
+  }
+
   #[test]
   fn test_reflexive_simple() {
     let mut reader = FrameDecoder::new();
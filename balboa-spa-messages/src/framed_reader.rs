@@ -1,7 +1,7 @@
 use std::io;
-use std::io::{BufReader, Read};
+use std::io::{BufRead, BufReader, ErrorKind, Read};
 use log::debug;
-use crate::frame_decoder::FrameDecoder;
+use crate::frame_decoder::{DEFAULT_MAX_FRAME_LEN, FrameDecoder};
 use crate::message::Message;
 
 #[derive(Debug)]
@@ -13,9 +13,14 @@ pub struct FramedReader<R> {
 
 impl<R: Read> FramedReader<R> {
   pub fn new(raw_reader: R) -> Self {
+    Self::with_max_frame_len(raw_reader, DEFAULT_MAX_FRAME_LEN)
+  }
+
+  /// See [FrameDecoder::with_max_frame_len].
+  pub fn with_max_frame_len(raw_reader: R, max_frame_len: usize) -> Self {
     Self {
       buf_reader: BufReader::with_capacity(32, raw_reader),
-      framed_reader: FrameDecoder::new(),
+      framed_reader: FrameDecoder::with_max_frame_len(max_frame_len),
       debug_bytes: false,
     }
   }
@@ -25,16 +30,38 @@ impl<R: Read> FramedReader<R> {
     self
   }
 
+  /// See [FrameDecoder::frames_with_errors].
+  pub fn frames_with_errors(&self) -> usize {
+    self.framed_reader.frames_with_errors()
+  }
+
   pub fn next_message(&mut self) -> io::Result<Message> {
-    let mut buf = [0u8; 1];
+    self.next_message_ref().cloned()
+  }
+
+  /// Read the next message without allocating, borrowing it from an internal buffer that is
+  /// reused across calls.  Use [FramedReader::next_message] if an owned copy is needed.
+  pub fn next_message_ref(&mut self) -> io::Result<&Message> {
     loop {
-      self.buf_reader.read_exact(&mut buf)?;
-      let byte = buf[0];
-      if self.debug_bytes {
-        debug!("Got {byte:02X}");
+      let available = self.buf_reader.fill_buf()?;
+      if available.is_empty() {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "EOF before end of frame"));
+      }
+      let mut consumed = 0;
+      let mut got_message = false;
+      for &byte in available {
+        consumed += 1;
+        if self.debug_bytes {
+          debug!("Got {byte:02X}");
+        }
+        if self.framed_reader.accept_in_place(byte) {
+          got_message = true;
+          break;
+        }
       }
-      if let Some(message) = self.framed_reader.accept(byte) {
-        return Ok(message);
+      self.buf_reader.consume(consumed);
+      if got_message {
+        return Ok(self.framed_reader.last_message());
       }
     }
   }
@@ -103,4 +130,19 @@ mod tests {
         .collect();
     assert_eq!(got_types, expected_types);
   }
+
+  #[test]
+  fn test_next_message_ref_reuses_buffer() {
+    let encoded = [
+      0x7E, 0x05, 0xFE, 0xBF, 0x00, 0xAC, 0x7E,
+      0x7E, 0x05, 0xFE, 0xBF, 0x00, 0xAC, 0x7E,
+    ];
+
+    let mut reader = FramedReader::new(Cursor::new(encoded));
+    let first_ptr = reader.next_message_ref().unwrap().payload.as_ptr();
+    let second = reader.next_message_ref().unwrap();
+    assert_eq!(second.message_type, 0x0);
+    assert_eq!(second.payload.as_ptr(), first_ptr);
+    assert_eq!(reader.next_message_ref().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+  }
 }
@@ -1,6 +1,7 @@
+use std::io;
 use std::io::Write;
 use crate::frame_encoder::FrameEncoder;
-use crate::message::Message;
+use crate::message::{EncodeError, Message};
 
 #[derive(Debug)]
 pub struct FramedWriter<W> {
@@ -16,10 +17,30 @@ impl<W: Write> FramedWriter<W> {
     }
   }
 
-  pub fn write(&mut self, message: &Message) -> anyhow::Result<()> {
+  pub fn write(&mut self, message: &Message) -> Result<(), FramedWriteError> {
     let encoded = self.framed_writer.encode(message)?;
     self.raw_writer.write_all(&encoded)?;
     self.raw_writer.flush()?;
     Ok(())
   }
+
+  /// Write an already framed message as-is, bypassing encoding.  Mostly useful for
+  /// deliberately sending malformed frames.
+  pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+    self.raw_writer.write_all(frame)?;
+    self.raw_writer.flush()
+  }
+
+  pub fn into_inner(self) -> W {
+    self.raw_writer
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FramedWriteError {
+  #[error("Encoding error: {0}")]
+  EncodeError(#[from] EncodeError),
+
+  #[error("I/O error: {0}")]
+  IoError(#[from] io::Error),
 }
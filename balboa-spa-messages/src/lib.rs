@@ -3,6 +3,7 @@
 pub use measurements;
 pub mod message;
 pub mod message_types;
+pub mod display;
 pub mod temperature;
 pub mod frame_decoder;
 pub mod channel;
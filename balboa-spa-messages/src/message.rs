@@ -27,6 +27,23 @@ impl Message {
   pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError> {
     Vec::<u8>::try_from(self)
   }
+
+  /// Same as [Message::from_bytes] but overwrites this message in place, reusing the
+  /// existing payload allocation where possible.
+  pub fn decode_from(&mut self, packet: &[u8]) -> Result<(), ParseError> {
+    let mut cursor = Cursor::new(packet);
+    let length = cursor.read_u8()?;
+    if length < 5 {
+      return Err(ParseError::InvalidPayloadLength(length));
+    }
+    self.channel = Channel::from(cursor.read_u8()?);
+    let _magic_byte = cursor.read_u8()?;
+    self.message_type = cursor.read_u8()?;
+    self.payload.clear();
+    self.payload.resize(usize::from(length) - 5, 0);
+    cursor.read_exact(self.payload.as_mut_slice())?;
+    Ok(())
+  }
 }
 
 impl Debug for Message {
@@ -47,17 +64,9 @@ impl TryFrom<&[u8]> for Message {
   type Error = ParseError;
 
   fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-    let mut cursor = Cursor::new(value);
-    let length = cursor.read_u8()?;
-    if length < 5 {
-      return Err(ParseError::InvalidPayloadLength(length));
-    }
-    let channel = Channel::from(cursor.read_u8()?);
-    let _magic_byte = cursor.read_u8()?;
-    let message_type = cursor.read_u8()?;
-    let mut payload: Vec<u8> = vec![0; usize::from(length) - 5];
-    cursor.read_exact(payload.as_mut_slice())?;
-    Ok(Message::new(channel, message_type, payload))
+    let mut message = Message::new(Channel::Unknown(0), 0, vec![]);
+    message.decode_from(value)?;
+    Ok(message)
   }
 }
 
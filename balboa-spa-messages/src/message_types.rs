@@ -16,7 +16,6 @@ use std::io::{Cursor, Read, Write};
 use std::string::FromUtf8Error;
 use std::time::Duration;
 
-use anyhow::anyhow;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use measurements::Temperature;
 use num_derive::FromPrimitive;
@@ -33,6 +32,9 @@ use crate::time::ProtocolTime;
 
 const MINUTES_30: Duration = Duration::from_secs(30 * 60);
 
+/// Set in the hour byte of [MessageType::SetTimeRequest] to select the 24 hour display.
+const SET_TIME_24_HOUR_FLAG: u8 = 0x80;
+
 #[derive(Debug, Clone)]
 #[repr(u8)]
 pub enum MessageType {
@@ -62,6 +64,9 @@ pub enum MessageType {
   } = 0x20,
   SetTimeRequest {
     time: ProtocolTime,
+    /// Sent along with every time change, so callers must preserve the current mode unless
+    /// they mean to change it.
+    clock_mode: ClockMode,
   } = 0x21,
   SettingsRequest(SettingsRequestMessage) = 0x22,
   FilterCycles {
@@ -84,7 +89,7 @@ pub enum MessageType {
   ToggleTestSettingRequest(ToggleTestMessage) = 0xe0,
 }
 
-#[derive(FromPrimitive, ToPrimitive, Debug, Copy, PartialEq, Clone)]
+#[derive(FromPrimitive, ToPrimitive, Debug, Copy, PartialEq, Eq, Hash, Clone)]
 #[repr(u8)]
 pub enum MessageTypeKind {
   NewClientClearToSend = 0x00,
@@ -130,7 +135,7 @@ impl From<Boolean> for bool {
   fn from(value: Boolean) -> Self {
     match value {
       Boolean::False => false,
-      Boolean::True => false,
+      Boolean::True => true,
     }
   }
 }
@@ -150,26 +155,112 @@ impl From<bool> for Boolean {
   }
 }
 
-#[derive(FromPrimitive, ToPrimitive, Hash, PartialEq, Eq, Debug, Copy, Clone)]
+/// Items that can be toggled with [MessageType::ToggleItemRequest].  Codes not listed here can
+/// still be sent and received via [ItemCode::Other], which makes it possible to experiment with
+/// (or simply relay) codes we haven't cataloged yet.
+#[derive(Hash, PartialEq, Eq, Debug, Copy, Clone)]
 pub enum ItemCode {
-  NormalOperation = 0x01,
-  ClearNotification = 0x03,
-  Pump1 = 0x04,
-  Pump2 = 0x05,
-  Pump3 = 0x06,
-  Pump4 = 0x07,
-  Pump5 = 0x08,
-  Pump6 = 0x09,
-  Blower = 0x0c,
-  Mister = 0x0e,
-  Light1 = 0x11,
-  Light2 = 0x12,
-  Aux1 = 0x16,
-  Aux2 = 0x17,
-  SoakMode = 0x1d,
-  HoldMode = 0x3c,
-  TemperatureRange = 0x50,
-  HeatMode = 0x51,
+  NormalOperation,
+  ClearNotification,
+  Pump1,
+  Pump2,
+  Pump3,
+  Pump4,
+  Pump5,
+  Pump6,
+  Blower,
+  Mister,
+  Light1,
+  Light2,
+  Aux1,
+  Aux2,
+  SoakMode,
+  HoldMode,
+  TemperatureRange,
+  HeatMode,
+  Other(u8),
+}
+
+impl ItemCode {
+  pub fn from_raw(raw: u8) -> Self {
+    match raw {
+      0x01 => Self::NormalOperation,
+      0x03 => Self::ClearNotification,
+      0x04 => Self::Pump1,
+      0x05 => Self::Pump2,
+      0x06 => Self::Pump3,
+      0x07 => Self::Pump4,
+      0x08 => Self::Pump5,
+      0x09 => Self::Pump6,
+      0x0c => Self::Blower,
+      0x0e => Self::Mister,
+      0x11 => Self::Light1,
+      0x12 => Self::Light2,
+      0x16 => Self::Aux1,
+      0x17 => Self::Aux2,
+      0x1d => Self::SoakMode,
+      0x3c => Self::HoldMode,
+      0x50 => Self::TemperatureRange,
+      0x51 => Self::HeatMode,
+      other => Self::Other(other),
+    }
+  }
+
+  pub fn as_raw(&self) -> u8 {
+    match self {
+      Self::NormalOperation => 0x01,
+      Self::ClearNotification => 0x03,
+      Self::Pump1 => 0x04,
+      Self::Pump2 => 0x05,
+      Self::Pump3 => 0x06,
+      Self::Pump4 => 0x07,
+      Self::Pump5 => 0x08,
+      Self::Pump6 => 0x09,
+      Self::Blower => 0x0c,
+      Self::Mister => 0x0e,
+      Self::Light1 => 0x11,
+      Self::Light2 => 0x12,
+      Self::Aux1 => 0x16,
+      Self::Aux2 => 0x17,
+      Self::SoakMode => 0x1d,
+      Self::HoldMode => 0x3c,
+      Self::TemperatureRange => 0x50,
+      Self::HeatMode => 0x51,
+      Self::Other(raw) => *raw,
+    }
+  }
+
+  /// Pump item code for the 0-based pump index, if one exists.
+  pub fn pump(index: usize) -> Option<Self> {
+    [Self::Pump1, Self::Pump2, Self::Pump3, Self::Pump4, Self::Pump5, Self::Pump6]
+        .get(index)
+        .copied()
+  }
+
+  /// Light item code for the 0-based light index, if one exists.
+  pub fn light(index: usize) -> Option<Self> {
+    [Self::Light1, Self::Light2].get(index).copied()
+  }
+}
+
+impl FromPrimitive for ItemCode {
+  fn from_i64(n: i64) -> Option<Self> {
+    u8::try_from(n).ok().map(Self::from_raw)
+  }
+
+  fn from_u64(n: u64) -> Option<Self> {
+    u8::try_from(n).ok().map(Self::from_raw)
+  }
+}
+
+impl ToPrimitive for ItemCode {
+  fn to_i64(&self) -> Option<i64> {
+    Some(i64::from(self.as_raw()))
+  }
+
+  fn to_u64(&self) -> Option<u64> {
+    Some(u64::from(self.as_raw()))
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -180,7 +271,7 @@ pub struct StatusUpdateMessage {
 }
 
 impl TryFrom<&StatusUpdateMessage> for Vec<u8> {
-  type Error = anyhow::Error;
+  type Error = PayloadEncodeError;
 
   fn try_from(value: &StatusUpdateMessage) -> Result<Self, Self::Error> {
     assert!(value.v2.is_none(), "StatusUpdateResponseV2 not supported yet!");
@@ -190,7 +281,7 @@ impl TryFrom<&StatusUpdateMessage> for Vec<u8> {
 }
 
 impl TryFrom<&[u8]> for StatusUpdateMessage {
-  type Error = anyhow::Error;
+  type Error = PayloadParseError;
 
   fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
     let v1 = StatusUpdateResponseV1::try_from(value)?;
@@ -225,6 +316,7 @@ pub struct StatusUpdateResponseV1 {
   pub light_status: Vec<ParsedEnum<RelayStatus, u8>>,
   pub reminder_set: ParsedEnum<Boolean, u8>,
   pub notification_set: ParsedEnum<Boolean, u8>,
+  pub settings_locked: bool,
 }
 
 #[derive(PackedStruct)]
@@ -375,7 +467,7 @@ pub enum RelayStatus {
 }
 
 impl TryFrom<&StatusUpdateResponseV1> for Vec<u8> {
-  type Error = anyhow::Error;
+  type Error = PayloadEncodeError;
 
   fn try_from(value: &StatusUpdateResponseV1) -> Result<Self, Self::Error> {
     let mut cursor = Cursor::new(Vec::new());
@@ -390,15 +482,20 @@ impl TryFrom<&StatusUpdateResponseV1> for Vec<u8> {
     let is_ab_temps_on = value.spa_state.as_ref()
         .map(|s| s == &SpaState::AbTempsOn)
         .unwrap_or(false);
+    let hold_minutes = value.hold_timer
+        .map(|t| u8::try_from(t.as_duration().as_secs() / 60).unwrap_or(u8::MAX))
+        .unwrap_or(0);
 
-    let (sensor_a, sensor_b) = match is_ab_temps_on {
-      true => {
+    // Sensor A doubles as the hold timer (in minutes) while in hold mode.
+    let (sensor_a, sensor_b) = match value.spa_state.as_ref() {
+      Some(SpaState::AbTempsOn) => {
         (
-          value.hold_timer.unwrap().to_minutes(),
+          hold_minutes,
           value.current_temperature.as_ref().unwrap().raw_value,
         )
       }
-      false => (0x0, 0x0)
+      Some(SpaState::HoldMode) => (hold_minutes, 0x0),
+      _ => (0x0, 0x0)
     };
     cursor.write_u8(sensor_a)?;
     cursor.write_u8(sensor_b)?;
@@ -455,7 +552,7 @@ impl TryFrom<&StatusUpdateResponseV1> for Vec<u8> {
     let flags21 = StatusFlags21 {
       sensor_ab: is_ab_temps_on,
       timeouts_are_8hr: false,
-      settings_locked: false,
+      settings_locked: value.settings_locked,
     };
     let packed21 = flags21.pack()?;
     cursor.write_all(&packed21)?;
@@ -465,7 +562,7 @@ impl TryFrom<&StatusUpdateResponseV1> for Vec<u8> {
 }
 
 impl TryFrom<&[u8]> for StatusUpdateResponseV1 {
-  type Error = anyhow::Error;
+  type Error = PayloadParseError;
 
   fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
     let mut cursor = Cursor::new(value);
@@ -477,7 +574,7 @@ impl TryFrom<&[u8]> for StatusUpdateResponseV1 {
     let time = ProtocolTime::from_hm(time_hour, time_minute);
     let heating_mode = ParsedEnum::from_raw(cursor.read_u8()?);
     let reminder_type = ParsedEnum::from_raw(cursor.read_u8()?);
-    let _sensor_a_temp = cursor.read_u8()?;
+    let sensor_a_temp = cursor.read_u8()?;
     let _sensor_b_temp = cursor.read_u8()?;
     let mut flags9_14 = [0u8; 6];
     cursor.read_exact(&mut flags9_14)?;
@@ -491,7 +588,15 @@ impl TryFrom<&[u8]> for StatusUpdateResponseV1 {
     let raw_set_temperature = cursor.read_u8()?;
     let mut flags21 = [0u8; 1];
     cursor.read_exact(&mut flags21)?;
-    let _unpacked21 = StatusFlags21::unpack(&flags21)?;
+    let unpacked21 = StatusFlags21::unpack(&flags21)?;
+
+    let hold_timer = match spa_state.as_ref() {
+      Some(SpaState::HoldMode) => {
+        let duration = Duration::from_secs(u64::from(sensor_a_temp) * 60);
+        ProtocolTime::from_duration(duration).ok()
+      }
+      _ => None,
+    };
 
     let current_temperature = match raw_current_temperature {
       0xff => None,
@@ -527,7 +632,7 @@ impl TryFrom<&[u8]> for StatusUpdateResponseV1 {
       time,
       heating_mode,
       reminder_type,
-      hold_timer: None,
+      hold_timer,
       filter_mode: ParsedEnum::new(unpacked9_14.filter_mode),
       panel_locked: unpacked9_14.panel_locked,
       temperate_range: unpacked9_14.temperature_range,
@@ -542,6 +647,7 @@ impl TryFrom<&[u8]> for StatusUpdateResponseV1 {
       light_status,
       reminder_set: ParsedEnum::new(unpacked18_19.reminder.into()),
       notification_set: ParsedEnum::new(unpacked18_19.notification.into()),
+      settings_locked: unpacked21.settings_locked,
     })
   }
 }
@@ -608,11 +714,85 @@ impl TryFrom<&[u8]> for SettingsRequestMessage {
   }
 }
 
-#[derive(Debug, Clone)]
+/// One of the two filter cycles configurable on the main board.  Note that the first cycle is
+/// always enabled, its `enabled` flag is ignored on the wire.
+#[derive(Debug, Clone, PartialEq)]
 pub struct FilterCycle {
-  enabled: bool,
-  start_at: Duration,
-  duration: Duration,
+  pub enabled: bool,
+
+  /// Time of day the cycle starts at.
+  pub start_at: Duration,
+
+  pub duration: Duration,
+}
+
+impl FilterCycle {
+  pub fn new(enabled: bool, start_at: Duration, duration: Duration) -> Self {
+    Self { enabled, start_at, duration }
+  }
+
+  /// Whether the cycle is running at the given time of day, accounting for cycles that wrap
+  /// past midnight.
+  pub fn is_active_at(&self, time_of_day: Duration) -> bool {
+    if !self.enabled {
+      return false;
+    }
+    let day = Duration::from_secs(24 * 60 * 60);
+    let end = self.start_at + self.duration;
+    if end <= day {
+      time_of_day >= self.start_at && time_of_day < end
+    } else {
+      time_of_day >= self.start_at || time_of_day < end - day
+    }
+  }
+
+  fn encode_pair(cycles: &[FilterCycle]) -> Result<Vec<u8>, PayloadEncodeError> {
+    let mut cursor = Cursor::new(Vec::with_capacity(8));
+    for (i, cycle) in cycles.iter().take(2).enumerate() {
+      let (start_hour, start_minute) = Self::split_hm(cycle.start_at)?;
+      let (duration_hour, duration_minute) = Self::split_hm(cycle.duration)?;
+      let enabled_flag = if i > 0 && cycle.enabled { 0x80 } else { 0 };
+      cursor.write_u8(start_hour | enabled_flag)?;
+      cursor.write_u8(start_minute)?;
+      cursor.write_u8(duration_hour)?;
+      cursor.write_u8(duration_minute)?;
+    }
+    for _ in cycles.len()..2 {
+      cursor.write_all(&[0u8; 4])?;
+    }
+    Ok(cursor.into_inner())
+  }
+
+  fn decode_pair(payload: &[u8]) -> Result<Vec<FilterCycle>, PayloadParseError> {
+    let mut cursor = Cursor::new(payload);
+    let mut cycles = Vec::with_capacity(2);
+    for i in 0..2 {
+      let raw_start_hour = cursor.read_u8()?;
+      let start_minute = cursor.read_u8()?;
+      let duration_hour = cursor.read_u8()?;
+      let duration_minute = cursor.read_u8()?;
+      let enabled = i == 0 || (raw_start_hour & 0x80) != 0;
+      cycles.push(FilterCycle {
+        enabled,
+        start_at: Self::join_hm(raw_start_hour & 0x7f, start_minute),
+        duration: Self::join_hm(duration_hour, duration_minute),
+      });
+    }
+    Ok(cycles)
+  }
+
+  fn split_hm(value: Duration) -> Result<(u8, u8), PayloadEncodeError> {
+    let total_minutes = value.as_secs() / 60;
+    let hours = u8::try_from(total_minutes / 60)
+        .ok()
+        .filter(|h| *h <= 24)
+        .ok_or(PayloadEncodeError::FilterCycleOutOfRange(value))?;
+    Ok((hours, u8::try_from(total_minutes % 60).unwrap()))
+  }
+
+  fn join_hm(hours: u8, minutes: u8) -> Duration {
+    Duration::from_secs((u64::from(hours) * 60 + u64::from(minutes)) * 60)
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -785,7 +965,7 @@ pub struct SoftwareVersion {
 
 impl Display for SoftwareVersion {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    let suffix = match self.version[4] {
+    let suffix = match self.version[3] {
       0 => "".to_owned(),
       n => format!(".{}", n),
     };
@@ -793,17 +973,21 @@ impl Display for SoftwareVersion {
   }
 }
 
-#[derive(FromPrimitive, ToPrimitive, Debug, Clone)]
+/// Heater supply voltage as reported in [InformationResponseMessage].  Values not listed here
+/// are preserved by [ParsedEnum] and can be inspected with [ParsedEnum::as_raw].
+#[derive(FromPrimitive, ToPrimitive, Debug, PartialEq, Copy, Clone)]
 pub enum HeaterVoltage {
   V240 = 0x01,
 }
 
-#[derive(FromPrimitive, ToPrimitive, Debug, Clone)]
+/// Heater element type as reported in [InformationResponseMessage].  Likewise, other values are
+/// kept as raw.
+#[derive(FromPrimitive, ToPrimitive, Debug, PartialEq, Copy, Clone)]
 pub enum HeaterType {
   Standard = 0x0a,
 }
 
-#[derive(FromPrimitive, ToPrimitive, PrimitiveEnum_u8, Debug, Copy, Clone)]
+#[derive(FromPrimitive, ToPrimitive, PrimitiveEnum_u8, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ClockMode {
   Hour12 = 0,
   Hour24 = 1,
@@ -823,7 +1007,7 @@ impl TryFrom<&CleanupCycle> for u8 {
       Some(duration) => {
         let divided = duration.as_secs_f64() / MINUTES_30.as_secs_f64();
         divided.round().to_u8()
-            .ok_or_else(|| anyhow!("Cannot convert to u8: {divided}"))?
+            .ok_or(PayloadEncodeError::CleanupCycleOutOfRange(duration))?
       }
     };
     Ok(encoded)
@@ -885,7 +1069,7 @@ pub enum SetPreferenceMessage {
 }
 
 impl TryFrom<&SetPreferenceMessage> for Vec<u8> {
-  type Error = anyhow::Error;
+  type Error = PayloadEncodeError;
 
   fn try_from(value: &SetPreferenceMessage) -> Result<Self, Self::Error> {
     let result = match value {
@@ -906,6 +1090,28 @@ impl TryFrom<&SetPreferenceMessage> for Vec<u8> {
   }
 }
 
+impl TryFrom<&[u8]> for SetPreferenceMessage {
+  type Error = PayloadParseError;
+
+  fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+    let mut cursor = Cursor::new(value);
+    let code = cursor.read_u8()?;
+    let raw = cursor.read_u8()?;
+    let result = match code {
+      0x00 => Self::Reminders(raw != 0),
+      0x01 => Self::TemperatureScale(TemperatureScale::from_u8(raw)
+          .ok_or(PayloadParseError::InvalidEnumValue { field_name: "temperature_scale", raw })?),
+      0x02 => Self::ClockMode(ClockMode::from_u8(raw)
+          .ok_or(PayloadParseError::InvalidEnumValue { field_name: "clock_mode", raw })?),
+      0x03 => Self::CleanupCycle(CleanupCycle::try_from(raw)?),
+      0x04 => Self::DolphinAddress(raw),
+      0x06 => Self::M8ArtificialIntelligence(raw != 0),
+      _ => return Err(PayloadParseError::InvalidEnumValue { field_name: "preference", raw: code }),
+    };
+    Ok(result)
+  }
+}
+
 #[derive(FromPrimitive, ToPrimitive, thiserror::Error, Debug, Clone)]
 pub enum FaultCode {
   #[error("Sensors are out of sync")]
@@ -966,7 +1172,7 @@ pub enum FaultCode {
   StandbyMode = 37,
 }
 
-#[derive(FromPrimitive, ToPrimitive, Debug, Clone)]
+#[derive(FromPrimitive, ToPrimitive, Debug, PartialEq, Copy, Clone)]
 pub enum GfciTestResult {
   Fail = 0x0,
   Pass = 0x1,
@@ -1119,9 +1325,7 @@ impl TryFrom<&ConfigurationResponseMessage> for Vec<u8> {
       unknown,
     };
 
-    Ok(packed_struct.pack()
-        .map_err(anyhow::Error::msg)?
-        .to_vec())
+    Ok(packed_struct.pack()?.to_vec())
   }
 }
 
@@ -1129,8 +1333,7 @@ impl TryFrom<&[u8]> for ConfigurationResponseMessage {
   type Error = PayloadParseError;
 
   fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-    let unpacked = ConfigurationResponsePack::unpack_from_slice(value)
-        .map_err(anyhow::Error::msg)?;
+    let unpacked = ConfigurationResponsePack::unpack_from_slice(value)?;
 
     let pumps = [
       unpacked.pump1,
@@ -1321,31 +1524,60 @@ impl TryFrom<&Message> for MessageType {
         let mut cursor = Cursor::new(&value.payload);
         let hour = cursor.read_u8()?;
         let minute = cursor.read_u8()?;
-        let time = ProtocolTime::from_hm(hour, minute);
-        MessageType::SetTimeRequest { time }
+        let clock_mode = if hour & SET_TIME_24_HOUR_FLAG != 0 {
+          ClockMode::Hour24
+        } else {
+          ClockMode::Hour12
+        };
+        let time = ProtocolTime::from_hm(hour & !SET_TIME_24_HOUR_FLAG, minute);
+        MessageType::SetTimeRequest { time, clock_mode }
       }
       MessageTypeKind::SettingsRequest => {
         MessageType::SettingsRequest(SettingsRequestMessage::try_from(value.payload.as_slice())?)
       },
-      MessageTypeKind::FilterCycles => todo!(),
+      MessageTypeKind::FilterCycles => {
+        MessageType::FilterCycles { cycles: FilterCycle::decode_pair(&value.payload)? }
+      }
       MessageTypeKind::InformationResponse => {
         MessageType::InformationResponse(InformationResponseMessage::try_from(value.payload.as_slice())?)
       }
       MessageTypeKind::Settings0x04Response => {
         MessageType::Settings0x04Response(Settings0x04ResponseMessage::try_from(value.payload.as_slice())?)
       }
-      MessageTypeKind::PreferencesResponse => todo!(),
-      MessageTypeKind::SetPreferenceRequest => todo!(),
+      MessageTypeKind::PreferencesResponse =>
+        MessageType::PreferencesResponse(PreferencesResponseMessage::try_from(value.payload.as_slice())?),
+      MessageTypeKind::SetPreferenceRequest =>
+        MessageType::SetPreferenceRequest(SetPreferenceMessage::try_from(value.payload.as_slice())?),
       MessageTypeKind::FaultLogResponse =>
         MessageType::FaultLogResponse(FaultResponseMessage::try_from(value.payload.as_slice())?),
-      MessageTypeKind::ChangeSetupRequest => todo!(),
-      MessageTypeKind::GfciTestResponse => todo!(),
-      MessageTypeKind::LockRequest => todo!(),
+      MessageTypeKind::ChangeSetupRequest => {
+        let mut cursor = Cursor::new(&value.payload);
+        let setup_number = cursor.read_u8()?;
+        MessageType::ChangeSetupRequest { setup_number }
+      }
+      MessageTypeKind::GfciTestResponse => {
+        let mut cursor = Cursor::new(&value.payload);
+        let result = ParsedEnum::from_raw(cursor.read_u8()?);
+        MessageType::GfciTestResponse { result }
+      }
+      MessageTypeKind::LockRequest => {
+        let mut cursor = Cursor::new(&value.payload);
+        let raw = cursor.read_u8()?;
+        let message = LockRequestMessage::from_u8(raw)
+            .ok_or(PayloadParseError::InvalidEnumValue { field_name: "lock_request", raw })?;
+        MessageType::LockRequest(message)
+      }
       MessageTypeKind::ConfigurationResponse =>
         MessageType::ConfigurationResponse(ConfigurationResponseMessage::try_from(value.payload.as_slice())?),
       MessageTypeKind::WifiModuleConfigurationResponse =>
         MessageType::WifiModuleConfigurationResponse(WifiModuleIdentificationMessage::try_from(value.payload.as_slice())?),
-      MessageTypeKind::ToggleTestSettingRequest => todo!(),
+      MessageTypeKind::ToggleTestSettingRequest => {
+        let mut cursor = Cursor::new(&value.payload);
+        let raw = cursor.read_u8()?;
+        let message = ToggleTestMessage::from_u8(raw)
+            .ok_or(PayloadParseError::InvalidEnumValue { field_name: "toggle_test", raw })?;
+        MessageType::ToggleTestSettingRequest(message)
+      }
     };
     Ok(parsed)
   }
@@ -1380,16 +1612,19 @@ impl TryFrom<MessageType> for Vec<u8> {
         Vec::<u8>::try_from(&message)?,
       MessageType::SetTemperatureRequest { temperature } =>
         vec![temperature.raw_value],
-      MessageType::SetTimeRequest { time } => {
+      MessageType::SetTimeRequest { time, clock_mode } => {
+        let mut raw = time.as_raw();
+        if clock_mode == ClockMode::Hour24 {
+          raw |= u16::from(SET_TIME_24_HOUR_FLAG) << 8;
+        }
         let mut cursor = Cursor::new(Vec::with_capacity(2));
-        cursor.write_u16::<BigEndian>(time.as_raw())?;
+        cursor.write_u16::<BigEndian>(raw)?;
         cursor.into_inner()
       }
       MessageType::SettingsRequest(message) =>
         Vec::<u8>::from(&message),
-      MessageType::FilterCycles { .. } => {
-        return Err(PayloadEncodeError::NotSupported)
-      }
+      MessageType::FilterCycles { cycles } =>
+        FilterCycle::encode_pair(&cycles)?,
       MessageType::InformationResponse(message) =>
         Vec::<u8>::try_from(&message)?,
       MessageType::Settings0x04Response(message) =>
@@ -1425,8 +1660,14 @@ pub enum PayloadParseError {
   #[error("Unexpected EOF")]
   UnexpectedEof(#[from] io::Error),
 
-  #[error("Generic error for any malformed or misunderstood data")]
-  InvalidData(#[from] anyhow::Error),
+  #[error("Malformed bit-packed field: {0}")]
+  PackingError(#[from] PackingError),
+
+  #[error("Unknown value {raw:#04x} for {field_name}")]
+  InvalidEnumValue {
+    field_name: &'static str,
+    raw: u8,
+  },
 
   #[error("Utf8-decoding error")]
   Utf8Error(#[from] FromUtf8Error),
@@ -1437,9 +1678,109 @@ pub enum PayloadEncodeError {
   #[error("Generic I/O error")]
   GenericIoError(#[from] io::Error),
 
-  #[error("Generic internal error")]
-  GenericError(#[from] anyhow::Error),
+  #[error("Field {field_name} has length {len}, exceeding max size {max}")]
+  FieldTooLong {
+    field_name: &'static str,
+    len: usize,
+    max: usize,
+  },
+
+  #[error("Cleanup cycle of {0:?} cannot be represented")]
+  CleanupCycleOutOfRange(Duration),
+
+  #[error("Filter cycle time of {0:?} cannot be represented")]
+  FilterCycleOutOfRange(Duration),
+
+  #[error("Bit-packing error: {0}")]
+  PackingError(#[from] PackingError),
 
   #[error("Message type encoding not yet supported")]
   NotSupported,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_boolean_to_bool() {
+    assert!(!bool::from(Boolean::False));
+    assert!(bool::from(Boolean::True));
+    for value in [false, true] {
+      assert_eq!(bool::from(&Boolean::from(value)), value);
+    }
+  }
+
+  #[test]
+  fn test_item_code_round_trip() {
+    for raw in 0..=u8::MAX {
+      let parsed = ParsedEnum::<ItemCode, u8>::from_raw(raw);
+      assert_eq!(parsed.as_ref().map(|c| c.as_raw()), Some(raw));
+    }
+    assert_eq!(ItemCode::from_raw(0x04), ItemCode::Pump1);
+    assert_eq!(ItemCode::from_raw(0xaa), ItemCode::Other(0xaa));
+    assert_eq!(ParsedEnum::<ItemCode, u8>::new(ItemCode::Other(0xaa)).as_raw(), 0xaa);
+  }
+
+  #[test]
+  fn test_set_time_clock_mode() {
+    for clock_mode in [ClockMode::Hour12, ClockMode::Hour24] {
+      let request = MessageType::SetTimeRequest { time: ProtocolTime::from_hm(21, 7), clock_mode };
+      let message = request.to_message(Channel::WifiModule).unwrap();
+      let expected_hour = if clock_mode == ClockMode::Hour24 { 0x95 } else { 0x15 };
+      assert_eq!(message.payload, vec![expected_hour, 7]);
+      match MessageType::try_from(&message).unwrap() {
+        MessageType::SetTimeRequest { time, clock_mode: parsed_mode } => {
+          assert_eq!(time, ProtocolTime::from_hm(21, 7));
+          assert_eq!(parsed_mode, clock_mode);
+        }
+        other => panic!("Unexpected {other:?}"),
+      }
+    }
+  }
+
+  #[test]
+  fn test_information_heater_fields() {
+    let info = InformationResponseMessage {
+      software_version: SoftwareVersion { version: [100, 201, 4, 0] },
+      system_model_number: "BP2100G1".to_owned(),
+      current_configuration_setup: 1,
+      configuration_signature: [0x1, 0x2, 0x3, 0x4],
+      heater_voltage: ParsedEnum::new(HeaterVoltage::V240),
+      heater_type: ParsedEnum::from_raw(0x42),
+      dip_switch_settings: 0,
+    };
+    let encoded = Vec::<u8>::try_from(&info).unwrap();
+    let decoded = InformationResponseMessage::try_from(encoded.as_slice()).unwrap();
+    assert_eq!(decoded.heater_voltage.as_ref(), Some(&HeaterVoltage::V240));
+    assert_eq!(decoded.heater_type.as_ref(), None);
+    assert_eq!(decoded.heater_type.as_raw(), 0x42);
+  }
+
+  #[test]
+  fn test_filter_cycles_reflexive() {
+    let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+    let cycles = vec![
+      FilterCycle::new(true, hours(20), hours(2)),
+      FilterCycle::new(true, hours(8) + Duration::from_secs(30 * 60), hours(1)),
+    ];
+    let message = MessageType::FilterCycles { cycles: cycles.clone() }
+        .to_message(Channel::Client(0x10))
+        .unwrap();
+    assert_eq!(message.payload, vec![20, 0, 2, 0, 0x88, 30, 1, 0]);
+    match MessageType::try_from(&message).unwrap() {
+      MessageType::FilterCycles { cycles: decoded } => assert_eq!(decoded, cycles),
+      other => panic!("Unexpected {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_filter_cycle_wraps_midnight() {
+    let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+    let cycle = FilterCycle::new(true, hours(23), hours(2));
+    assert!(cycle.is_active_at(hours(23)));
+    assert!(cycle.is_active_at(Duration::from_secs(30 * 60)));
+    assert!(!cycle.is_active_at(hours(1)));
+    assert!(!cycle.is_active_at(hours(12)));
+  }
+}
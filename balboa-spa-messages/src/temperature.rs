@@ -1,5 +1,4 @@
 use std::fmt::{Debug, Display, Formatter};
-use anyhow::anyhow;
 pub use measurements::Temperature;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::FromPrimitive;
@@ -17,7 +16,7 @@ pub struct ProtocolTemperature {
 }
 
 impl ProtocolTemperature {
-  pub fn step(&self, direction: Direction, range: &TemperatureRange, min_maxes: &TemperatureMinMax) -> anyhow::Result<SetTemperature> {
+  pub fn step(&self, direction: Direction, range: &TemperatureRange, min_maxes: &TemperatureMinMax) -> Result<SetTemperature, TemperatureError> {
     let factor = if direction == Direction::Up { 1.0 } else { -1.0 };
     let temperature = match self.raw_scale {
       TemperatureScale::Fahrenheit => {
@@ -27,16 +26,14 @@ impl ProtocolTemperature {
         Temperature::from_celsius(self.temperature.as_celsius() + CELSIUS_SCALE * factor)
       }
     };
-    let min_max = match range {
-      TemperatureRange::Low => min_maxes.low_range,
-      TemperatureRange::High => min_maxes.high_range,
-    };
-    if temperature > min_max.1 || temperature < min_max.0 {
-      return Err(anyhow!("Step to {:?} outside of min/max range ({:?} - {:?})",
-          temperature, min_max.0, min_max.1));
-    }
+    min_maxes.check(range, temperature)?;
     self.raw_scale.new_set_temperature(&temperature)
   }
+
+  /// Value as encoded on the wire, interpreted according to [Self::raw_scale].
+  pub fn raw_value(&self) -> u8 {
+    self.raw_value
+  }
 }
 
 impl Debug for ProtocolTemperature {
@@ -54,6 +51,20 @@ impl Display for ProtocolTemperature {
   }
 }
 
+impl TemperatureMinMax {
+  /// Whether `target` is a valid set point while in `range`.
+  pub fn check(&self, range: &TemperatureRange, target: Temperature) -> Result<(), TemperatureError> {
+    let (min, max) = match range {
+      TemperatureRange::Low => self.low_range,
+      TemperatureRange::High => self.high_range,
+    };
+    if target > max || target < min {
+      return Err(TemperatureError::OutOfRange { target, min, max });
+    }
+    Ok(())
+  }
+}
+
 #[derive(FromPrimitive, ToPrimitive, PrimitiveEnum_u8, Debug, PartialEq, Copy, Clone)]
 pub enum TemperatureScale {
   Fahrenheit = 0,
@@ -61,13 +72,13 @@ pub enum TemperatureScale {
 }
 
 impl TemperatureScale {
-  pub fn new_set_temperature(&self, target: &Temperature) -> anyhow::Result<SetTemperature> {
+  pub fn new_set_temperature(&self, target: &Temperature) -> Result<SetTemperature, TemperatureError> {
     let raw_target = match self {
       TemperatureScale::Fahrenheit => target.as_fahrenheit() / FAHRENHEIT_SCALE,
       TemperatureScale::Celsius => target.as_celsius() / CELSIUS_SCALE,
     };
     let scaled_target = u8::from_f64(raw_target.round())
-        .ok_or_else(|| anyhow!("Cannot scale {raw_target}"))?;
+        .ok_or(TemperatureError::CannotScale(raw_target))?;
     Ok(SetTemperature { raw_value: scaled_target })
   }
 
@@ -88,7 +99,7 @@ impl TemperatureScale {
     }
   }
 
-  pub fn new_protocol_temperature(&self, target: Temperature) -> anyhow::Result<ProtocolTemperature> {
+  pub fn new_protocol_temperature(&self, target: Temperature) -> Result<ProtocolTemperature, TemperatureError> {
     let set_temp = self.new_set_temperature(&target)?;
     Ok(ProtocolTemperature {
       raw_scale: *self,
@@ -103,8 +114,27 @@ pub struct SetTemperature {
   pub(crate) raw_value: u8,
 }
 
+impl SetTemperature {
+  pub fn raw_value(&self) -> u8 {
+    self.raw_value
+  }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Direction {
   Up,
   Down,
 }
+
+#[derive(thiserror::Error, Debug)]
+pub enum TemperatureError {
+  #[error("Step to {target:?} outside of min/max range ({min:?} - {max:?})")]
+  OutOfRange {
+    target: Temperature,
+    min: Temperature,
+    max: Temperature,
+  },
+
+  #[error("Cannot scale {0} to protocol units")]
+  CannotScale(f64),
+}
@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter};
 use std::time::Duration;
 
 #[derive(Debug, Copy, PartialEq, Clone)]
@@ -33,6 +34,12 @@ impl ProtocolTime {
   }
 }
 
+impl Display for ProtocolTime {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:02}:{:02}", self.hour, self.minute)
+  }
+}
+
 impl TryFrom<Duration> for ProtocolTime {
   type Error = ProtocolTimeError;
 
@@ -1,22 +1,51 @@
 //! Pretty print a stream of Balboa spa packets for easy debugging of what's going on.
 
-use std::io::stdin;
+use std::io::{Read, stdin};
+use clap::Parser;
+use balboa_spa_messages::display;
 use balboa_spa_messages::framed_reader::FramedReader;
-use balboa_spa_messages::message_types::{MessageType, PayloadParseError};
-
-fn main() {
-  let stdin = stdin().lock();
-  let reader = FramedReader::new(stdin);
-
-  for message in reader {
-    match MessageType::try_from(&message) {
-      Ok(mt) => {
-        let channel = &message.channel;
-        println!("[{channel:?}] {mt:?}");
-      }
-      Err(e) => {
-        println!("Parse error {e} on: {message:?}");
-      }
+#[cfg(feature = "serial")]
+use common_lib::serial_transport::SerialTransport;
+#[cfg(feature = "serial")]
+use common_lib::transport::Transport;
+
+#[derive(Parser, Debug)]
+pub struct Args {
+  /// Print every parsed field and the raw payload instead of a one-line summary
+  #[arg(short, long, default_value_t = false)]
+  pub verbose: bool,
+
+  /// Read directly from a serial port (e.g. /dev/ttyUSB0) instead of stdin
+  #[cfg(feature = "serial")]
+  #[arg(short, long)]
+  pub serial: Option<String>,
+}
+
+fn main() -> anyhow::Result<()> {
+  let args = Args::parse();
+
+  let mut reader = FramedReader::new(open_input(&args)?);
+
+  while let Ok(message) = reader.next_message_ref() {
+    if args.verbose {
+      println!("{}", display::verbose(message));
+    } else {
+      println!("{}", display::compact(message));
     }
   }
-}
\ No newline at end of file
+
+  Ok(())
+}
+
+#[cfg(feature = "serial")]
+fn open_input(args: &Args) -> anyhow::Result<Box<dyn Read>> {
+  Ok(match &args.serial {
+    Some(path) => Box::new(SerialTransport::open(path)?.split().0),
+    None => Box::new(stdin().lock()),
+  })
+}
+
+#[cfg(not(feature = "serial"))]
+fn open_input(_args: &Args) -> anyhow::Result<Box<dyn Read>> {
+  Ok(Box::new(stdin().lock()))
+}
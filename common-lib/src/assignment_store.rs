@@ -0,0 +1,133 @@
+//! Persistence for [ChannelAssignment]s so that a client keeps the same hash and channel across
+//! restarts.  Real main boards never forget a client, so without this every reboot would grow
+//! the board's channel table until it runs out.
+
+use std::fmt::Debug;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Context};
+use balboa_spa_messages::channel::Channel;
+use crate::client_ident::ClientIdent;
+use crate::cts_state_machine::ChannelAssignment;
+
+pub trait AssignmentStore: Debug + Send {
+  /// Previously saved assignment, or None if nothing has been saved yet.
+  fn load(&self) -> anyhow::Result<Option<ChannelAssignment>>;
+
+  fn save(&mut self, assignment: &ChannelAssignment) -> anyhow::Result<()>;
+}
+
+/// Serialized form shared by stores that just need to put some bytes somewhere.
+pub fn encode_assignment(assignment: &ChannelAssignment) -> [u8; 4] {
+  let [hash_hi, hash_lo] = assignment.ident.client_hash.to_be_bytes();
+  [
+    assignment.ident.device_type,
+    hash_hi,
+    hash_lo,
+    u8::from(&assignment.channel),
+  ]
+}
+
+pub fn decode_assignment(data: &[u8]) -> anyhow::Result<ChannelAssignment> {
+  let [device_type, hash_hi, hash_lo, channel] = <[u8; 4]>::try_from(data)
+      .map_err(|_| anyhow!("Expected 4 bytes, got {}", data.len()))?;
+  let channel = match Channel::from(channel) {
+    channel @ Channel::Client(_) => channel,
+    other => return Err(anyhow!("Not a client channel: {other:?}")),
+  };
+  Ok(ChannelAssignment {
+    ident: ClientIdent {
+      device_type,
+      client_hash: u16::from_be_bytes([hash_hi, hash_lo]),
+    },
+    channel,
+  })
+}
+
+/// Keeps the assignment in a small file, for desktop builds.
+#[derive(Debug)]
+pub struct FileAssignmentStore {
+  path: PathBuf,
+}
+
+impl FileAssignmentStore {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self { path: path.into() }
+  }
+}
+
+impl AssignmentStore for FileAssignmentStore {
+  fn load(&self) -> anyhow::Result<Option<ChannelAssignment>> {
+    match fs::read(&self.path) {
+      Ok(data) => {
+        let assignment = decode_assignment(&data)
+            .with_context(|| format!("Corrupt assignment in {}", self.path.display()))?;
+        Ok(Some(assignment))
+      }
+      Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  fn save(&mut self, assignment: &ChannelAssignment) -> anyhow::Result<()> {
+    fs::write(&self.path, encode_assignment(assignment))?;
+    Ok(())
+  }
+}
+
+/// Doesn't outlive the process, mostly useful for tests.  Clones share the same assignment.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryAssignmentStore {
+  assignment: Arc<Mutex<Option<ChannelAssignment>>>,
+}
+
+impl MemoryAssignmentStore {
+  pub fn new() -> Self {
+    Default::default()
+  }
+}
+
+impl AssignmentStore for MemoryAssignmentStore {
+  fn load(&self) -> anyhow::Result<Option<ChannelAssignment>> {
+    Ok(self.assignment.lock().unwrap().clone())
+  }
+
+  fn save(&mut self, assignment: &ChannelAssignment) -> anyhow::Result<()> {
+    *self.assignment.lock().unwrap() = Some(assignment.clone());
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_file_round_trip() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(format!("assignment-{}", std::process::id()));
+    let mut store = FileAssignmentStore::new(&path);
+    assert!(store.load()?.is_none());
+
+    store.save(&ChannelAssignment {
+      ident: ClientIdent { device_type: 2, client_hash: 0xf247 },
+      channel: Channel::Client(0x11),
+    })?;
+    let loaded = store.load()?.unwrap();
+    fs::remove_file(&path)?;
+
+    assert_eq!(loaded.ident.client_hash, 0xf247);
+    assert_eq!(loaded.ident.device_type, 2);
+    assert_eq!(loaded.channel, Channel::Client(0x11));
+    Ok(())
+  }
+
+  #[test]
+  fn test_decode_rejects_non_client_channels() {
+    assert!(decode_assignment(&[2, 0xf2, 0x47, 0x11]).is_ok());
+    for channel in [0x00, 0x0a, 0x35, 0xfe, 0xff] {
+      assert!(decode_assignment(&[2, 0xf2, 0x47, channel]).is_err(), "channel={channel:#x}");
+    }
+  }
+}
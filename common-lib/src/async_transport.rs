@@ -0,0 +1,145 @@
+//! Async counterpart to [Transport] for tokio based consumers (e.g. a Linux gateway) that would
+//! otherwise need a thread per stream.
+//!
+//! Existing sync transports can be used through [SyncTransportAdapter], which still dedicates a
+//! thread to each direction of the wrapped transport but confines that to the edge.
+
+use std::io;
+use std::io::{Read, Write};
+use std::thread;
+use log::debug;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::runtime::Handle;
+
+use crate::transport::Transport;
+
+/// Size of the in-memory pipe between the sync and async sides, as well as of each read from
+/// the sync reader.  Frames are well under this so it only needs to absorb short bursts.
+const ADAPTER_BUFFER_SIZE: usize = 256;
+
+pub trait AsyncTransport<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> {
+  fn split(self) -> (R, W);
+}
+
+impl AsyncTransport<OwnedReadHalf, OwnedWriteHalf> for TcpStream {
+  fn split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+    self.into_split()
+  }
+}
+
+/// Exposes any sync [Transport] as an [AsyncTransport] by pumping it from background threads.
+///
+/// EOF or a read error on the sync side shows up as EOF on the async reader.  Shutting down the
+/// async writer stops the writing thread, and the reading thread exits on its next read once the
+/// async side has been dropped.
+pub struct SyncTransportAdapter {
+  reader: ReadHalf<DuplexStream>,
+  writer: WriteHalf<DuplexStream>,
+}
+
+impl SyncTransportAdapter {
+  /// Must be called from within a tokio runtime, which the pump threads use to talk to the
+  /// async side.
+  pub fn new<T, R, W>(transport: T) -> Self
+  where
+      T: Transport<R, W>,
+      R: Read + Send + 'static,
+      W: Write + Send + 'static,
+  {
+    let (sync_reader, sync_writer) = transport.split();
+    let (local, remote) = tokio::io::duplex(ADAPTER_BUFFER_SIZE);
+    let (remote_rx, remote_tx) = tokio::io::split(remote);
+    let handle = Handle::current();
+
+    let read_handle = handle.clone();
+    thread::spawn(move || {
+      let result = pump_from_sync(sync_reader, remote_tx, &read_handle);
+      debug!("sync reader pump exit: {result:?}");
+    });
+    thread::spawn(move || {
+      let result = pump_to_sync(remote_rx, sync_writer, &handle);
+      debug!("sync writer pump exit: {result:?}");
+    });
+
+    let (reader, writer) = tokio::io::split(local);
+    Self { reader, writer }
+  }
+}
+
+impl AsyncTransport<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>> for SyncTransportAdapter {
+  fn split(self) -> (ReadHalf<DuplexStream>, WriteHalf<DuplexStream>) {
+    (self.reader, self.writer)
+  }
+}
+
+fn pump_from_sync<R: Read>(
+    mut reader: R,
+    mut tx: WriteHalf<DuplexStream>,
+    handle: &Handle,
+) -> io::Result<()> {
+  let mut buf = [0u8; ADAPTER_BUFFER_SIZE];
+  loop {
+    let n = reader.read(&mut buf)?;
+    if n == 0 {
+      return handle.block_on(tx.shutdown());
+    }
+    handle.block_on(tx.write_all(&buf[..n]))?;
+  }
+}
+
+fn pump_to_sync<W: Write>(
+    mut rx: ReadHalf<DuplexStream>,
+    mut writer: W,
+    handle: &Handle,
+) -> io::Result<()> {
+  let mut buf = [0u8; ADAPTER_BUFFER_SIZE];
+  loop {
+    let n = handle.block_on(rx.read(&mut buf))?;
+    if n == 0 {
+      return Ok(());
+    }
+    // Flush every chunk as the async side has no way to ask us to.
+    writer.write_all(&buf[..n])?;
+    writer.flush()?;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::channel::Channel;
+  use balboa_spa_messages::frame_decoder::FrameDecoder;
+  use balboa_spa_messages::frame_encoder::FrameEncoder;
+  use balboa_spa_messages::message_types::MessageType;
+  use crate::tcp_transport::TcpTransport;
+  use super::*;
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn test_sync_adapter() -> anyhow::Result<()> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    thread::spawn(move || -> io::Result<()> {
+      let (mut reader, mut writer) = TcpTransport::accept(&listener)?.split();
+      io::copy(&mut reader, &mut writer)?;
+      Ok(())
+    });
+
+    let stream = TcpTransport::connect(addr)?;
+    let (mut rx, mut tx) = SyncTransportAdapter::new(stream).split();
+    let message = MessageType::ClearToSend().to_message(Channel::Client(0x10))?;
+    let encoded = FrameEncoder::new().encode(&message)?;
+    tx.write_all(&encoded).await?;
+
+    let mut decoder = FrameDecoder::new();
+    let mut buf = [0u8; 1];
+    let received = loop {
+      rx.read_exact(&mut buf).await?;
+      if let Some(received) = decoder.accept(buf[0]) {
+        break received;
+      }
+    };
+    assert_eq!(received, message);
+    Ok(())
+  }
+}
@@ -3,15 +3,24 @@
 //! some low-level transport buses work (e.g. RS485) and allows a single physical endpoint to
 //! become two or more logical endpoints.
 //!
+//! Connections can be added either before [BusSwitch::start] or afterwards through the returned
+//! [BusSwitchHandle], and are detached simply by dropping them.  This lets one party (e.g. the
+//! Wi-Fi module) join the bus well after another is already talking on it.
+//!
 //! Note that this implementation is quite buffer heavy in order to be user friendly and avoid
-//! falling into nasty thread safety traps.  Might need some tuning if memory gets tight.
+//! falling into nasty thread safety traps.  Might need some tuning if memory gets tight.  Each
+//! connection has a bounded receive queue whose [OverflowPolicy] decides what happens when that
+//! connection stops reading.
 
 use std::{io, mem, thread};
 use std::cmp::min;
-use std::io::{BufRead, ErrorKind, Read, Write};
+use std::collections::VecDeque;
+use std::io::{BufRead, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, Weak};
 use std::sync::mpsc::{Receiver, sync_channel, SyncSender};
 use log::debug;
 
+use crate::metrics::{Counter, Metrics};
 use crate::transport::Transport;
 
 /// Kind of a silly large value to encourage callers to call flush frequently between writes.
@@ -31,11 +40,30 @@ const DEFAULT_RECV_BUFFER_SIZE: usize = 128;
 /// attachment to the bus.
 const DEFAULT_RECV_QUEUE_LEN: usize = 8;
 
+/// What to do with incoming data for a connection whose receive queue is already full.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+  /// Wait for the connection to catch up.  Lossless, but one stuck connection stalls the bus
+  /// for everyone else.
+  #[default]
+  Block,
+
+  /// Discard the oldest queued data to make room, favouring fresh traffic.
+  DropOldest,
+
+  /// Discard the incoming data, keeping what's already queued.
+  DropNewest,
+
+  /// Disconnect the connection, which sees an error once it drains what was queued.
+  Error,
+}
+
 pub struct BusSwitch<R, W> {
   raw_reader: R,
   raw_writer: W,
   recv_buffer_size: usize,
   recv_queue_len: usize,
+  overflow_policy: OverflowPolicy,
   read_listeners: ReadListeners,
   writer_rx: Receiver<WriteAndFlushEvent>,
   writer_tx_tmp: SyncSender<WriteAndFlushEvent>,
@@ -71,21 +99,37 @@ impl<R, W> BusSwitch<R, W>
       raw_writer,
       recv_buffer_size,
       recv_queue_len,
+      overflow_policy: OverflowPolicy::default(),
       read_listeners: Default::default(),
       writer_rx,
       writer_tx_tmp,
     }
   }
 
+  /// Default overflow policy for connections created by [Self::new_connection] and
+  /// [BusSwitchHandle::new_connection].
+  pub fn set_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+    self.overflow_policy = policy;
+    self
+  }
+
   pub fn new_connection(&mut self) -> BusTransport {
-    let (reader_tx, rx) = sync_channel(self.recv_queue_len);
-    let listener_handle = self.read_listeners.add_listener(reader_tx);
-    let tx = self.writer_tx_tmp.clone();
-    BusTransport { rx, tx, listener_handle }
+    self.new_connection_with_policy(self.overflow_policy)
+  }
+
+  pub fn new_connection_with_policy(&mut self, policy: OverflowPolicy) -> BusTransport {
+    new_connection(&self.read_listeners, &self.writer_tx_tmp, self.recv_queue_len, policy)
   }
 
-  pub fn start(self) {
-    drop(self.writer_tx_tmp);
+  /// Start servicing the bus.  The returned handle can be used to attach further connections
+  /// while running; once it and every connection have been dropped the switch shuts down.
+  pub fn start(self) -> BusSwitchHandle {
+    let handle = BusSwitchHandle {
+      read_listeners: self.read_listeners.clone(),
+      writer_tx: self.writer_tx_tmp,
+      recv_queue_len: self.recv_queue_len,
+      overflow_policy: self.overflow_policy,
+    };
     let listeners_for_reader = self.read_listeners.clone();
     let listeners_for_writer = self.read_listeners;
     thread::spawn(move || {
@@ -109,6 +153,58 @@ impl<R, W> BusSwitch<R, W>
       debug!("writer exit: {result:?})");
       result
     });
+
+    handle
+  }
+}
+
+/// Allows attaching connections to a [BusSwitch] that has already been started.
+#[derive(Debug)]
+pub struct BusSwitchHandle {
+  read_listeners: ReadListeners,
+  writer_tx: SyncSender<WriteAndFlushEvent>,
+  recv_queue_len: usize,
+  overflow_policy: OverflowPolicy,
+}
+
+impl BusSwitchHandle {
+  /// New logical endpoint on the bus, receiving everything sent from this point forward.
+  pub fn new_connection(&self) -> BusTransport {
+    self.new_connection_with_policy(self.overflow_policy)
+  }
+
+  pub fn new_connection_with_policy(&self, policy: OverflowPolicy) -> BusTransport {
+    new_connection(&self.read_listeners, &self.writer_tx, self.recv_queue_len, policy)
+  }
+
+  /// Number of connections currently attached.
+  pub fn connection_count(&self) -> usize {
+    self.read_listeners.len()
+  }
+}
+
+impl Drop for BusSwitchHandle {
+  fn drop(&mut self) {
+    self.read_listeners.close();
+  }
+}
+
+fn new_connection(
+    read_listeners: &ReadListeners,
+    writer_tx: &SyncSender<WriteAndFlushEvent>,
+    recv_queue_len: usize,
+    policy: OverflowPolicy,
+) -> BusTransport {
+  let queue = Arc::new(ListenerQueue::new(recv_queue_len, policy));
+  let listener_handle = read_listeners.add_listener(queue.clone());
+  BusTransport {
+    rx: ListenerRx {
+      queue,
+      listeners: read_listeners.downgrade(),
+      handle: listener_handle,
+    },
+    tx: writer_tx.clone(),
+    listener_handle,
   }
 }
 
@@ -130,7 +226,7 @@ impl<R: Read> ReaderRunner<R> {
         }
         Ok(n) => {
           self.listeners.broadcast_to_all(Ok(&buf[0..n]));
-          if !self.listeners.has_listeners() || n == 0 {
+          if self.listeners.is_finished() || n == 0 {
             return Ok(());
           }
         }
@@ -175,55 +271,232 @@ impl<W: Write> WriterRunner<W> {
   }
 }
 
+/// Listeners shared between the reader and writer threads and any [BusSwitchHandle] so that
+/// connections can come and go at runtime.
 #[derive(Debug, Clone, Default)]
 struct ReadListeners {
-  listeners: Vec<Listener<ReadEvent>>,
+  inner: Arc<Mutex<ReadListenersInner>>,
+}
+
+#[derive(Debug, Default)]
+struct ReadListenersInner {
+  listeners: Vec<Listener>,
+  next_handle: usize,
+
+  /// Set once no more listeners can be added.
+  closed: bool,
+}
+
+impl ReadListenersInner {
+  fn retain_listeners(&mut self, keep: impl FnMut(&Listener) -> bool) {
+    let before = self.listeners.len();
+    self.listeners.retain(keep);
+    let removed = before - self.listeners.len();
+    Metrics::global().gauge("bus.connections").add(-(removed as isize));
+  }
+}
+
+impl Drop for ReadListenersInner {
+  fn drop(&mut self) {
+    // Both the reader and writer have exited so nothing more will ever arrive.
+    for listener in &self.listeners {
+      listener.queue.close_producer();
+    }
+    self.retain_listeners(|_| false);
+  }
 }
 
 impl ReadListeners {
-  pub fn add_listener(&mut self, tx: SyncSender<ReadEvent>) -> ListenerHandle {
-    let next_index = self.listeners.len();
-    let handle = ListenerHandle(next_index);
-    self.listeners.push(Listener { handle, tx });
+  pub fn add_listener(&self, queue: Arc<ListenerQueue>) -> ListenerHandle {
+    let mut inner = self.inner.lock().unwrap();
+    let handle = ListenerHandle(inner.next_handle);
+    inner.next_handle += 1;
+    inner.listeners.push(Listener { handle, queue });
+    Metrics::global().gauge("bus.connections").add(1);
     handle
   }
 
-  pub fn broadcast_to_all(&mut self, result: io::Result<&[u8]>) {
+  /// Connections only hold a weak reference so that they don't keep the switch's shared state
+  /// alive after the reader and writer have gone, which is how they learn of EOF.
+  fn downgrade(&self) -> Weak<Mutex<ReadListenersInner>> {
+    Arc::downgrade(&self.inner)
+  }
+
+  pub fn broadcast_to_all(&self, result: io::Result<&[u8]>) {
     self.broadcast_to_some(result, None)
   }
 
-  pub fn broadcast_to_some(&mut self, result: io::Result<&[u8]>, exclude: Option<ListenerHandle>) {
-    self.listeners.retain(|listener| {
+  pub fn broadcast_to_some(&self, result: io::Result<&[u8]>, exclude: Option<ListenerHandle>) {
+    // Push from a snapshot rather than under the lock as a push can block on a slow listener,
+    // and that listener may itself be trying to detach.
+    let snapshot = self.inner.lock().unwrap().listeners.clone();
+    let mut disconnected = vec![];
+    for listener in snapshot {
       if exclude != Some(listener.handle) {
         let result_owned = result
             .as_ref()
             .map(|x| x.to_vec())
             .map_err(copy_io_error);
-        listener.tx.send(ReadEvent(result_owned)).is_ok()
-      } else {
-        true
+        if !listener.queue.push(ReadEvent(result_owned)) {
+          disconnected.push(listener.handle);
+        }
       }
-    });
+    }
+    if !disconnected.is_empty() {
+      self.inner.lock().unwrap().retain_listeners(|listener| {
+        !disconnected.contains(&listener.handle)
+      });
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.inner.lock().unwrap().listeners.len()
+  }
+
+  pub fn close(&self) {
+    self.inner.lock().unwrap().closed = true;
   }
 
-  pub fn has_listeners(&self) -> bool {
-    !self.listeners.is_empty()
+  /// True when nobody is listening and nobody ever can again.
+  pub fn is_finished(&self) -> bool {
+    let inner = self.inner.lock().unwrap();
+    inner.closed && inner.listeners.is_empty()
   }
 }
 
+/// Receiving end of a connection's queue.  Detaches the listener as soon as it's dropped, rather
+/// than waiting for the next broadcast to discover it's gone.
 #[derive(Debug)]
-struct Listener<T> {
+struct ListenerRx {
+  queue: Arc<ListenerQueue>,
+  listeners: Weak<Mutex<ReadListenersInner>>,
+  handle: ListenerHandle,
+}
+
+impl Drop for ListenerRx {
+  fn drop(&mut self) {
+    self.queue.close_consumer();
+    if let Some(inner) = self.listeners.upgrade() {
+      inner.lock().unwrap().retain_listeners(|listener| listener.handle != self.handle);
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Listener {
   handle: ListenerHandle,
-  tx: SyncSender<T>,
+  queue: Arc<ListenerQueue>,
 }
 
-impl<T> Clone for Listener<T> {
-  fn clone(&self) -> Self {
+/// Bounded queue between the switch and a single connection.  Hand rolled rather than using
+/// [sync_channel] so that we can implement [OverflowPolicy::DropOldest] from the sending side.
+#[derive(Debug)]
+struct ListenerQueue {
+  state: Mutex<ListenerQueueState>,
+  changed: Condvar,
+  capacity: usize,
+  policy: OverflowPolicy,
+
+  /// Drops across every connection in the process, see [BusTransport::dropped_count] for this
+  /// connection alone.
+  dropped_metric: Counter,
+}
+
+#[derive(Debug, Default)]
+struct ListenerQueueState {
+  events: VecDeque<ReadEvent>,
+  dropped: u64,
+  overflowed: bool,
+  producer_closed: bool,
+  consumer_closed: bool,
+}
+
+impl ListenerQueue {
+  pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
     Self {
-      handle: self.handle.clone(),
-      tx: self.tx.clone(),
+      state: Default::default(),
+      changed: Condvar::new(),
+      // A rendezvous queue would deadlock under Block, as we only push from one thread.
+      capacity: capacity.max(1),
+      policy,
+      dropped_metric: Metrics::global().counter("bus.dropped_segments"),
+    }
+  }
+
+  /// Queue an event according to the overflow policy, returning false if the listener is gone
+  /// and should be removed.
+  pub fn push(&self, event: ReadEvent) -> bool {
+    let mut state = self.state.lock().unwrap();
+    loop {
+      if state.consumer_closed || state.producer_closed {
+        return false;
+      }
+      if state.events.len() < self.capacity {
+        state.events.push_back(event);
+        self.changed.notify_all();
+        return true;
+      }
+      match self.policy {
+        OverflowPolicy::Block => {
+          state = self.changed.wait(state).unwrap();
+        }
+        OverflowPolicy::DropOldest => {
+          state.events.pop_front();
+          state.events.push_back(event);
+          state.dropped += 1;
+          self.dropped_metric.inc();
+          return true;
+        }
+        OverflowPolicy::DropNewest => {
+          state.dropped += 1;
+          self.dropped_metric.inc();
+          return true;
+        }
+        OverflowPolicy::Error => {
+          state.dropped += 1;
+          self.dropped_metric.inc();
+          state.overflowed = true;
+          state.producer_closed = true;
+          self.changed.notify_all();
+          return false;
+        }
+      }
+    }
+  }
+
+  /// Next event, blocking until one arrives.  Returns None once the queue is drained and
+  /// nothing more will arrive.
+  pub fn pop(&self) -> Option<ReadEvent> {
+    let mut state = self.state.lock().unwrap();
+    loop {
+      if let Some(event) = state.events.pop_front() {
+        self.changed.notify_all();
+        return Some(event);
+      }
+      if state.producer_closed {
+        return None;
+      }
+      state = self.changed.wait(state).unwrap();
     }
   }
+
+  pub fn close_producer(&self) {
+    self.state.lock().unwrap().producer_closed = true;
+    self.changed.notify_all();
+  }
+
+  pub fn close_consumer(&self) {
+    self.state.lock().unwrap().consumer_closed = true;
+    self.changed.notify_all();
+  }
+
+  pub fn dropped_count(&self) -> u64 {
+    self.state.lock().unwrap().dropped
+  }
+
+  pub fn has_overflowed(&self) -> bool {
+    self.state.lock().unwrap().overflowed
+  }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -234,7 +507,7 @@ fn copy_io_error(e: &io::Error) -> io::Error {
 }
 
 pub struct BusTransport {
-  rx: Receiver<ReadEvent>,
+  rx: ListenerRx,
   tx: SyncSender<WriteAndFlushEvent>,
   listener_handle: ListenerHandle,
 }
@@ -248,6 +521,11 @@ impl BusTransport {
   {
     BusSwitch::from_existing(transport, DEFAULT_RECV_BUFFER_SIZE, DEFAULT_RECV_QUEUE_LEN)
   }
+
+  /// Number of received segments discarded so far because this connection fell behind.
+  pub fn dropped_count(&self) -> u64 {
+    self.rx.queue.dropped_count()
+  }
 }
 
 impl Transport<BusTransportRx, BusTransportTx> for BusTransport {
@@ -268,11 +546,18 @@ impl Transport<BusTransportRx, BusTransportTx> for BusTransport {
 }
 
 pub struct BusTransportRx {
-  rx: Receiver<ReadEvent>,
+  rx: ListenerRx,
   buffer: Vec<u8>,
   position: usize,
 }
 
+impl BusTransportRx {
+  /// See [BusTransport::dropped_count].
+  pub fn dropped_count(&self) -> u64 {
+    self.rx.queue.dropped_count()
+  }
+}
+
 impl Read for BusTransportRx {
   fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
     if buf.is_empty() {
@@ -293,9 +578,12 @@ impl Read for BusTransportRx {
 impl BufRead for BusTransportRx {
   fn fill_buf(&mut self) -> io::Result<&[u8]> {
     while self.position >= self.buffer.len() {
-      match self.rx.recv() {
-        Err(_) => break,
-        Ok(data) => {
+      match self.rx.queue.pop() {
+        None if self.rx.queue.has_overflowed() => {
+          return Err(io::Error::other("receive queue overflowed"));
+        }
+        None => break,
+        Some(data) => {
           match data.0 {
             Ok(data) => {
               self.buffer = data;
@@ -326,7 +614,7 @@ pub struct BusTransportTx {
 impl Write for BusTransportTx {
   fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
     if self.buffer.len() + buf.len() > self.max_buffer_size {
-      Err(io::Error::new(ErrorKind::Other, "write buffer exceeded!"))
+      Err(io::Error::other("write buffer exceeded!"))
     } else {
       self.buffer.extend(buf);
       Ok(buf.len())
@@ -357,7 +645,7 @@ mod tests {
   use std::fmt::{Display, Formatter};
   use std::hash::{Hash, Hasher};
   use super::*;
-  use std::io::{BufRead, BufReader, Write};
+  use std::io::{BufRead, BufReader, ErrorKind, Write};
   use std::sync::mpsc;
   use std::sync::mpsc::{channel, Receiver, sync_channel, SyncSender};
   use std::thread;
@@ -390,6 +678,128 @@ mod tests {
     Ok(())
   }
 
+  #[test]
+  #[timeout(10000)]
+  fn test_attach_detach_after_start() -> anyhow::Result<()> {
+    let _ = env_logger::builder().filter_level(LevelFilter::Trace).is_test(true).try_init();
+
+    let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+    let transport = StdTransport::new(client_in, client_out);
+
+    let mut switch = BusTransport::new_switch(transport);
+    let client0 = switch.new_connection();
+    let handle = switch.start();
+
+    let mut harness = BusTestHarness::new();
+    let s = harness.add_splits(server_in, server_out);
+    let c0 = harness.add_transport(client0);
+    harness.send_from(s, "before attach")?;
+
+    let c1 = harness.add_transport(handle.new_connection());
+    assert_eq!(handle.connection_count(), 2);
+    harness.send_from(s, "after attach")?;
+    harness.send_from(c1, "hello from the new client!")?;
+
+    harness.pop();
+    assert_eq!(handle.connection_count(), 1);
+    harness.send_from(s, "after detach")?;
+    harness.send_from(c0, "still here")?;
+    Ok(())
+  }
+
+  #[test]
+  fn test_overflow_policies() {
+    let event = |b: u8| ReadEvent(Ok(vec![b]));
+    let popped = |queue: &ListenerQueue| queue.pop().map(|e| e.0.unwrap()[0]);
+
+    let queue = ListenerQueue::new(2, OverflowPolicy::DropOldest);
+    assert!((1..=3).all(|b| queue.push(event(b))));
+    assert_eq!(queue.dropped_count(), 1);
+    assert_eq!(popped(&queue), Some(2));
+    assert_eq!(popped(&queue), Some(3));
+
+    let queue = ListenerQueue::new(2, OverflowPolicy::DropNewest);
+    assert!((1..=3).all(|b| queue.push(event(b))));
+    assert_eq!(queue.dropped_count(), 1);
+    assert_eq!(popped(&queue), Some(1));
+    assert_eq!(popped(&queue), Some(2));
+
+    let queue = ListenerQueue::new(2, OverflowPolicy::Error);
+    assert!(queue.push(event(1)));
+    assert!(queue.push(event(2)));
+    assert!(!queue.push(event(3)));
+    assert_eq!(popped(&queue), Some(1));
+    assert_eq!(popped(&queue), Some(2));
+    assert_eq!(popped(&queue), None);
+    assert!(queue.has_overflowed());
+  }
+
+  #[test]
+  #[timeout(10000)]
+  fn test_stuck_connection_does_not_stall_bus() -> anyhow::Result<()> {
+    let _ = env_logger::builder().filter_level(LevelFilter::Trace).is_test(true).try_init();
+
+    let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+    let transport = StdTransport::new(client_in, client_out);
+
+    let mut switch = BusTransport::new_switch(transport)
+        .set_overflow_policy(OverflowPolicy::DropOldest);
+    let client0 = switch.new_connection();
+    let stuck = switch.new_connection();
+    switch.start();
+
+    let mut harness = BusTestHarness::new();
+    let s = harness.add_splits(server_in, server_out);
+    harness.add_transport(client0);
+
+    // Well past the queue length, which would hang forever under the Block policy.
+    for i in 0..(DEFAULT_RECV_QUEUE_LEN * 4) {
+      harness.send_from(s, &format!("message {i}"))?;
+    }
+    assert!(stuck.dropped_count() > 0);
+    Ok(())
+  }
+
+  #[test]
+  #[timeout(10000)]
+  fn test_slow_connection_resumes_with_latest() -> anyhow::Result<()> {
+    let _ = env_logger::builder().filter_level(LevelFilter::Trace).is_test(true).try_init();
+
+    let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+    let transport = StdTransport::new(client_in, client_out);
+
+    let mut switch = BusTransport::new_switch(transport)
+        .set_overflow_policy(OverflowPolicy::DropOldest);
+    let client0 = switch.new_connection();
+    let (slow_rx, _slow_tx) = switch.new_connection().split();
+    switch.start();
+
+    let mut harness = BusTestHarness::new();
+    let s = harness.add_splits(server_in, server_out);
+    harness.add_transport(client0);
+
+    let num_messages = DEFAULT_RECV_QUEUE_LEN * 4;
+    for i in 0..num_messages {
+      harness.send_from(s, &format!("message {i}"))?;
+    }
+
+    // The slow reader only gets around to reading now, and should find the freshest traffic
+    // waiting for it rather than having held everyone else up.
+    let last = format!("message {}", num_messages - 1);
+    let mut lines = BufReader::new(slow_rx).lines();
+    let mut seen = vec![];
+    loop {
+      let line = lines.next().unwrap()?;
+      if line == last {
+        break;
+      }
+      seen.push(line);
+    }
+    assert!(seen.len() < DEFAULT_RECV_QUEUE_LEN, "unexpected backlog: {seen:?}");
+    assert!(!seen.contains(&"message 0".to_owned()));
+    Ok(())
+  }
+
   #[test]
   #[timeout(7000)]
   fn stress_test() -> anyhow::Result<()> {
@@ -547,6 +957,11 @@ mod tests {
       index
     }
 
+    /// Detach the most recently added pair, dropping its reader and writer.
+    pub fn pop(&mut self) {
+      self.pairs.pop();
+    }
+
     pub fn send_from(&mut self, pair_index: PairIndex, data: &str) -> io::Result<()> {
       // Need to use another thread because PipeReader/Writer use a bounded channel of size 0 so
       // the write will block forever.
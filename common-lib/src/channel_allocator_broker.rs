@@ -1,35 +1,60 @@
+//! Coordinates channel negotiation between clients living in the same process.
+//!
+//! The main board hands out channels one at a time in response to a `ChannelAssignmentRequest`
+//! sent after a `NewClientClearToSend`.  When two clients share a bus (see
+//! [crate::bus_transport::BusTransport]) they see that poll at almost precisely the same time
+//! and would otherwise both answer, colliding on the wire.  Each client must hold an
+//! [AllocatorToken] while negotiating; whoever fails to get one yields and tries again on a
+//! later poll.
+//!
+//! [crate::cts_state_machine::CtsStateMachine] does this automatically using
+//! [ChannelAllocatorBroker::global] unless given a specific broker with
+//! [crate::cts_state_machine::CtsStateMachine::set_allocator_broker].
+
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::lazy_static;
 
 lazy_static! {
-  pub(crate) static ref GLOBAL_BROKER: Arc<ChannelAllocatorBroker> =
+  static ref GLOBAL_BROKER: Arc<ChannelAllocatorBroker> =
     Arc::new(ChannelAllocatorBroker::new());
 }
 
 /// Mechanism to allow only a single CtsStateMachine to acquire a new channel at a time.  This
 /// is particularly a problem for us with the BusTransport because the WiFi and Topside panel
 /// modules are receiving the NewClientClearToSend message at almost precisely the same time.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ChannelAllocatorBroker {
   active_token: Arc<AtomicBool>,
 }
 
 impl ChannelAllocatorBroker {
   pub fn new() -> Self {
-    Self {
-      active_token: Arc::new(AtomicBool::new(false)),
-    }
+    Default::default()
   }
 
+  /// Broker shared by every client in the process that wasn't given its own.
+  pub fn global() -> Arc<ChannelAllocatorBroker> {
+    GLOBAL_BROKER.clone()
+  }
+
+  /// Claim the right to negotiate a channel, or None if another client currently holds it.
+  /// Never blocks, as the caller is expected to be racing a poll from the main board.
   pub fn try_allocate(&self) -> Option<AllocatorToken> {
     match self.active_token.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst) {
       Ok(_) => Some(AllocatorToken { inner: self.active_token.clone() }),
       Err(_) => None
     }
   }
+
+  /// Whether some client is negotiating right now.
+  pub fn is_allocated(&self) -> bool {
+    self.active_token.load(Ordering::SeqCst)
+  }
 }
 
+/// Proof of holding the broker, released on drop.  Holders should let go as soon as the
+/// negotiation finishes or times out so that other clients aren't starved.
 #[derive(Debug)]
 pub struct AllocatorToken {
   inner: Arc<AtomicBool>,
@@ -49,6 +74,8 @@ impl Drop for AllocatorToken {
 
 #[cfg(test)]
 mod tests {
+  use std::sync::Barrier;
+  use std::thread;
   use super::*;
 
   #[test]
@@ -56,7 +83,41 @@ mod tests {
     let allocator = ChannelAllocatorBroker::new();
     let token = allocator.try_allocate().unwrap();
     assert!(allocator.try_allocate().is_none());
+    assert!(allocator.is_allocated());
     drop(token);
+    assert!(!allocator.is_allocated());
     assert!(allocator.try_allocate().is_some());
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_contention() {
+    const CLIENTS: usize = 8;
+    let allocator = Arc::new(ChannelAllocatorBroker::new());
+    let barrier = Arc::new(Barrier::new(CLIENTS));
+    let handles = (0..CLIENTS).map(|_| {
+      let allocator = allocator.clone();
+      let barrier = barrier.clone();
+      thread::spawn(move || {
+        barrier.wait();
+        let token = allocator.try_allocate();
+        // Hold on until everyone has tried.
+        barrier.wait();
+        token.is_some()
+      })
+    }).collect::<Vec<_>>();
+    let winners = handles.into_iter()
+        .map(|h| h.join().unwrap())
+        .filter(|won| *won)
+        .count();
+    assert_eq!(winners, 1);
+    assert!(!allocator.is_allocated());
+  }
+
+  #[test]
+  fn test_brokers_are_independent() {
+    let first = ChannelAllocatorBroker::new();
+    let second = ChannelAllocatorBroker::new();
+    let _token = first.try_allocate().unwrap();
+    assert!(second.try_allocate().is_some());
+  }
+}
@@ -0,0 +1,166 @@
+//! Fans out a single stream of messages to independent subscribers by channel, so that a process
+//! hosting several roles on one bus (e.g. topside panel and Wi-Fi module) decodes the stream once
+//! rather than each role parsing and filtering everything.
+//!
+//! Unlike [crate::bus_transport::BusTransport], which duplicates raw bytes, subscribers receive
+//! already framed [Message]s and only those their [ChannelFilter] lets through.
+
+use std::io;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, sync_channel, SyncSender};
+use balboa_spa_messages::framed_reader::FramedReader;
+use balboa_spa_messages::message::Message;
+use crate::channel_filter::{ChannelFilter, FilterResult};
+
+/// Messages queued per subscriber before the demux waits for it to catch up.
+const DEFAULT_QUEUE_LEN: usize = 16;
+
+pub struct ChannelDemux<R> {
+  reader: FramedReader<R>,
+  subscribers: Subscribers,
+  queue_len: usize,
+}
+
+type Subscribers = Arc<Mutex<SubscriberList>>;
+
+#[derive(Default)]
+struct SubscriberList {
+  list: Vec<Subscriber>,
+  next_id: usize,
+}
+
+struct Subscriber {
+  id: usize,
+  filter: Arc<Mutex<ChannelFilter>>,
+  tx: SyncSender<Message>,
+}
+
+impl<R: Read> ChannelDemux<R> {
+  pub fn new(reader: FramedReader<R>) -> Self {
+    Self::with_queue_len(reader, DEFAULT_QUEUE_LEN)
+  }
+
+  pub fn with_queue_len(reader: FramedReader<R>, queue_len: usize) -> Self {
+    Self {
+      reader,
+      subscribers: Default::default(),
+      queue_len,
+    }
+  }
+
+  /// Handle for subscribing once [Self::run_loop] has taken ownership of the demux.
+  pub fn handle(&self) -> DemuxHandle {
+    DemuxHandle {
+      subscribers: self.subscribers.clone(),
+      queue_len: self.queue_len,
+    }
+  }
+
+  pub fn subscribe(&self, filter: ChannelFilter) -> Subscription {
+    self.handle().subscribe(filter)
+  }
+
+  /// Read and dispatch until the stream ends or fails.  Subscribers see their receiver
+  /// disconnect once this returns.
+  ///
+  /// A subscriber that stops reading will eventually stall delivery to everyone else, so drop
+  /// subscriptions that are no longer needed.
+  pub fn run_loop(mut self) -> io::Result<()> {
+    loop {
+      let message = self.reader.next_message()?;
+
+      // Send from a snapshot so that a slow subscriber doesn't block new subscriptions.
+      let matching = self.subscribers.lock().unwrap().list.iter()
+          .filter(|s| s.filter.lock().unwrap().apply(&message.channel) != FilterResult::Blocked)
+          .map(|s| (s.id, s.tx.clone()))
+          .collect::<Vec<_>>();
+      let mut disconnected = vec![];
+      for (id, tx) in matching {
+        if tx.send(message.clone()).is_err() {
+          disconnected.push(id);
+        }
+      }
+      if !disconnected.is_empty() {
+        self.subscribers.lock().unwrap().list.retain(|s| !disconnected.contains(&s.id));
+      }
+    }
+  }
+}
+
+/// Allows subscribing to a running [ChannelDemux].
+#[derive(Clone)]
+pub struct DemuxHandle {
+  subscribers: Subscribers,
+  queue_len: usize,
+}
+
+impl DemuxHandle {
+  pub fn subscribe(&self, filter: ChannelFilter) -> Subscription {
+    let (tx, rx) = sync_channel(self.queue_len);
+    let filter = Arc::new(Mutex::new(filter));
+    let mut subscribers = self.subscribers.lock().unwrap();
+    let id = subscribers.next_id;
+    subscribers.next_id += 1;
+    subscribers.list.push(Subscriber {
+      id,
+      filter: filter.clone(),
+      tx,
+    });
+    Subscription { rx, filter }
+  }
+}
+
+/// Receives the messages on the channels selected by its filter, in stream order.
+pub struct Subscription {
+  rx: Receiver<Message>,
+  filter: Arc<Mutex<ChannelFilter>>,
+}
+
+impl Subscription {
+  /// Change which channels are delivered from here on, typically once a channel has been
+  /// assigned.
+  pub fn set_filter(&self, filter: ChannelFilter) {
+    *self.filter.lock().unwrap() = filter;
+  }
+
+  pub fn receiver(&self) -> &Receiver<Message> {
+    &self.rx
+  }
+
+  pub fn into_receiver(self) -> Receiver<Message> {
+    self.rx
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::channel::Channel;
+  use balboa_spa_messages::frame_encoder::FrameEncoder;
+  use balboa_spa_messages::message_types::MessageType;
+  use super::*;
+
+  #[test]
+  fn test_demux() -> anyhow::Result<()> {
+    let encoder = FrameEncoder::new();
+    let mut stream = vec![];
+    for channel in [Channel::Client(0x10), Channel::Client(0x11), Channel::MulticastBroadcast] {
+      stream.extend(encoder.encode(&MessageType::ClearToSend().to_message(channel)?)?);
+    }
+
+    let demux = ChannelDemux::new(FramedReader::new(stream.as_slice()));
+    let first = demux.subscribe(ChannelFilter::RelevantTo(vec![Channel::Client(0x10)]));
+    let second = demux.subscribe(ChannelFilter::RelevantTo(vec![Channel::Client(0x11)]));
+    let nothing = demux.subscribe(ChannelFilter::BlockEverything);
+    drop(demux.subscribe(ChannelFilter::None));
+
+    let err = demux.run_loop().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let channels = |s: Subscription| s.into_receiver().iter().map(|m| m.channel).collect::<Vec<_>>();
+    assert_eq!(channels(first), vec![Channel::Client(0x10), Channel::MulticastBroadcast]);
+    assert_eq!(channels(second), vec![Channel::Client(0x11), Channel::MulticastBroadcast]);
+    assert!(channels(nothing).is_empty());
+    Ok(())
+  }
+}
@@ -1,13 +1,24 @@
+use std::fmt::{Debug, Formatter};
 use balboa_spa_messages::channel::Channel;
 
-#[derive(Debug)]
 pub enum ChannelFilter {
   None,
   RelevantTo(Vec<Channel>),
+
+  /// Everything except the given channels, for example to observe all traffic other than a
+  /// noisy client's.
+  Except(Vec<Channel>),
+
+  /// Arbitrary filtering, passing only channels for which the function returns true.
+  Predicate(Box<dyn Fn(&Channel) -> bool + Send + Sync>),
   BlockEverything,
 }
 
 impl ChannelFilter {
+  pub fn predicate(f: impl Fn(&Channel) -> bool + Send + Sync + 'static) -> Self {
+    ChannelFilter::Predicate(Box::new(f))
+  }
+
   pub fn apply(&self, channel: &Channel) -> FilterResult {
     match self {
       ChannelFilter::None => FilterResult::Any,
@@ -20,9 +31,31 @@ impl ChannelFilter {
         }
         FilterResult::Blocked
       }
+      ChannelFilter::Except(excluded) => Self::passed_if(!excluded.contains(channel), channel),
+      ChannelFilter::Predicate(f) => Self::passed_if(f(channel), channel),
       ChannelFilter::BlockEverything => FilterResult::Blocked,
     }
   }
+
+  fn passed_if(passed: bool, channel: &Channel) -> FilterResult {
+    match (passed, channel) {
+      (false, _) => FilterResult::Blocked,
+      (true, Channel::MulticastBroadcast) => FilterResult::Broadcast,
+      (true, _) => FilterResult::Any,
+    }
+  }
+}
+
+impl Debug for ChannelFilter {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ChannelFilter::None => write!(f, "None"),
+      ChannelFilter::RelevantTo(targets) => f.debug_tuple("RelevantTo").field(targets).finish(),
+      ChannelFilter::Except(excluded) => f.debug_tuple("Except").field(excluded).finish(),
+      ChannelFilter::Predicate(_) => write!(f, "Predicate(..)"),
+      ChannelFilter::BlockEverything => write!(f, "BlockEverything"),
+    }
+  }
 }
 
 #[derive(Debug, PartialEq)]
@@ -31,4 +64,26 @@ pub enum FilterResult {
   Broadcast,
   Any,
   Blocked,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_except_and_predicate() {
+    let mine = Channel::Client(0x10);
+    let other = Channel::Client(0x11);
+
+    let except = ChannelFilter::Except(vec![other]);
+    assert_eq!(except.apply(&mine), FilterResult::Any);
+    assert_eq!(except.apply(&other), FilterResult::Blocked);
+    assert_eq!(except.apply(&Channel::MulticastBroadcast), FilterResult::Broadcast);
+
+    let predicate = ChannelFilter::predicate(move |c| !matches!(c, Channel::Client(_)) || *c == mine);
+    assert_eq!(predicate.apply(&mine), FilterResult::Any);
+    assert_eq!(predicate.apply(&other), FilterResult::Blocked);
+    assert_eq!(predicate.apply(&Channel::MulticastBroadcast), FilterResult::Broadcast);
+    assert_eq!(predicate.apply(&Channel::WifiModule), FilterResult::Any);
+  }
+}
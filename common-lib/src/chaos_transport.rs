@@ -0,0 +1,277 @@
+//! Decorator that makes any [Transport] misbehave in the ways a real RS485 line does, so that
+//! the clients' resilience can be exercised without hardware.
+//!
+//! Faults are applied as data is read, which is where a client would see them on a real bus.
+//! Writes are only affected by a disconnect, after which both halves fail.
+
+use std::{io, thread};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use log::info;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::transport::Transport;
+
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+  latency: Duration,
+  jitter: Duration,
+  byte_flip_probability: f64,
+  short_read_probability: f64,
+  disconnect_after_bytes: Option<usize>,
+  seed: Option<u64>,
+}
+
+impl ChaosConfig {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Delay added before each read returns.
+  pub fn set_latency(mut self, latency: Duration) -> Self {
+    self.latency = latency;
+    self
+  }
+
+  /// Random extra delay of up to `jitter` on top of the latency.
+  pub fn set_jitter(mut self, jitter: Duration) -> Self {
+    self.jitter = jitter;
+    self
+  }
+
+  /// Chance of each byte read having a random bit flipped.  Panics unless `probability` is
+  /// between 0 and 1.
+  pub fn set_byte_flip_probability(mut self, probability: f64) -> Self {
+    self.byte_flip_probability = checked_probability(probability);
+    self
+  }
+
+  /// Chance of a read returning only part of what the underlying transport gave us.  The rest is
+  /// held back for the next read, not lost.  Panics unless `probability` is between 0 and 1.
+  pub fn set_short_read_probability(mut self, probability: f64) -> Self {
+    self.short_read_probability = checked_probability(probability);
+    self
+  }
+
+  /// Fail all reads and writes with [ErrorKind::ConnectionAborted] once this many bytes have been
+  /// read.
+  pub fn set_disconnect_after_bytes(mut self, bytes: usize) -> Self {
+    self.disconnect_after_bytes = Some(bytes);
+    self
+  }
+
+  /// Make the faults reproducible across runs.
+  pub fn set_seed(mut self, seed: u64) -> Self {
+    self.seed = Some(seed);
+    self
+  }
+}
+
+/// Catch bad values when configuring rather than on some later read, NaN in particular which
+/// [Rng::gen_bool] would otherwise panic on.
+fn checked_probability(probability: f64) -> f64 {
+  assert!((0.0..=1.0).contains(&probability), "Probability must be between 0 and 1, got {probability}");
+  probability
+}
+
+pub struct ChaosTransport<T> {
+  inner: T,
+  config: ChaosConfig,
+}
+
+impl<T> ChaosTransport<T> {
+  pub fn new(inner: T, config: ChaosConfig) -> Self {
+    Self { inner, config }
+  }
+}
+
+impl<T, R, W> Transport<ChaosReader<R>, ChaosWriter<W>> for ChaosTransport<T>
+where
+    T: Transport<R, W>,
+    R: Read,
+    W: Write,
+{
+  fn split(self) -> (ChaosReader<R>, ChaosWriter<W>) {
+    let (reader, writer) = self.inner.split();
+    let rng = match self.config.seed {
+      Some(seed) => StdRng::seed_from_u64(seed),
+      None => StdRng::from_entropy(),
+    };
+    let disconnected = Arc::new(AtomicBool::new(false));
+    let chaos_reader = ChaosReader {
+      inner: reader,
+      config: self.config,
+      rng,
+      pending: Vec::new(),
+      bytes_read: 0,
+      disconnected: disconnected.clone(),
+    };
+    let chaos_writer = ChaosWriter {
+      inner: writer,
+      disconnected,
+    };
+    (chaos_reader, chaos_writer)
+  }
+}
+
+pub struct ChaosReader<R> {
+  inner: R,
+  config: ChaosConfig,
+  rng: StdRng,
+
+  /// Data held back by a short read.
+  pending: Vec<u8>,
+  bytes_read: usize,
+  disconnected: Arc<AtomicBool>,
+}
+
+impl<R: Read> ChaosReader<R> {
+  fn delay(&mut self) {
+    let jitter = if self.config.jitter.is_zero() {
+      Duration::ZERO
+    } else {
+      self.config.jitter.mul_f64(self.rng.gen::<f64>())
+    };
+    let delay = self.config.latency + jitter;
+    if !delay.is_zero() {
+      thread::sleep(delay);
+    }
+  }
+
+  fn fill_pending(&mut self, max: usize) -> io::Result<()> {
+    if !self.pending.is_empty() {
+      return Ok(());
+    }
+    let mut buf = vec![0u8; max];
+    let n = self.inner.read(&mut buf)?;
+    buf.truncate(n);
+    for b in &mut buf {
+      if self.rng.gen_bool(self.config.byte_flip_probability) {
+        *b ^= 1 << self.rng.gen_range(0..8);
+      }
+    }
+    self.pending = buf;
+    Ok(())
+  }
+}
+
+impl<R: Read> Read for ChaosReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.disconnected.load(Ordering::SeqCst) {
+      return Err(disconnected_error());
+    }
+    if buf.is_empty() {
+      return Ok(0);
+    }
+    self.fill_pending(buf.len())?;
+    self.delay();
+
+    let mut n = self.pending.len().min(buf.len());
+    if n > 1 && self.rng.gen_bool(self.config.short_read_probability) {
+      n = self.rng.gen_range(1..n);
+    }
+
+    if let Some(limit) = self.config.disconnect_after_bytes {
+      let so_far = self.bytes_read;
+      if so_far + n >= limit {
+        info!("Simulating disconnect after {limit} bytes");
+        self.disconnected.store(true, Ordering::SeqCst);
+        n = limit.saturating_sub(so_far);
+        if n == 0 {
+          return Err(disconnected_error());
+        }
+      }
+    }
+
+    buf[..n].copy_from_slice(&self.pending[..n]);
+    self.pending.drain(..n);
+    self.bytes_read += n;
+    Ok(n)
+  }
+}
+
+pub struct ChaosWriter<W> {
+  inner: W,
+  disconnected: Arc<AtomicBool>,
+}
+
+impl<W: Write> Write for ChaosWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if self.disconnected.load(Ordering::SeqCst) {
+      return Err(disconnected_error());
+    }
+    self.inner.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    if self.disconnected.load(Ordering::SeqCst) {
+      return Err(disconnected_error());
+    }
+    self.inner.flush()
+  }
+}
+
+fn disconnected_error() -> io::Error {
+  io::Error::new(ErrorKind::ConnectionAborted, "simulated disconnect")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use crate::transport::StdTransport;
+  use super::*;
+
+  fn chaos(input: Vec<u8>, config: ChaosConfig) -> (ChaosReader<Cursor<Vec<u8>>>, ChaosWriter<Vec<u8>>) {
+    ChaosTransport::new(StdTransport::new(Cursor::new(input), Vec::new()), config).split()
+  }
+
+  #[test]
+  fn test_short_reads_and_disconnect() {
+    let input: Vec<u8> = (0..100).collect();
+    let config = ChaosConfig::new()
+        .set_seed(1)
+        .set_short_read_probability(1.0)
+        .set_disconnect_after_bytes(50);
+    let (mut reader, mut writer) = chaos(input.clone(), config);
+
+    let mut received = vec![];
+    let mut buf = [0u8; 16];
+    let err = loop {
+      match reader.read(&mut buf) {
+        Ok(n) => {
+          assert!(n < buf.len());
+          received.extend_from_slice(&buf[..n]);
+        }
+        Err(e) => break e,
+      }
+    };
+    assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+    assert_eq!(received, input[..50]);
+    assert_eq!(writer.write(&[0]).unwrap_err().kind(), ErrorKind::ConnectionAborted);
+  }
+
+  #[test]
+  fn test_byte_flips() {
+    let input = vec![0u8; 64];
+    let (mut reader, _) = chaos(input.clone(), ChaosConfig::new().set_seed(1).set_byte_flip_probability(1.0));
+    let mut received = vec![];
+    reader.read_to_end(&mut received).unwrap();
+    assert_eq!(received.len(), input.len());
+    assert!(received.iter().all(|b| b.count_ones() == 1));
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_rejects_nan_probability() {
+    ChaosConfig::new().set_byte_flip_probability(f64::NAN);
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_rejects_out_of_range_probability() {
+    ChaosConfig::new().set_short_read_probability(1.5);
+  }
+}
@@ -0,0 +1,60 @@
+//! Client side view of how well we keep up with the main board's ClearToSend polling, used to
+//! tune UART turnaround on real hardware.  See [crate::message_state_machine::MessageStateMachine::set_cts_metrics].
+
+use std::time::Duration;
+use crate::metrics::{Counter, Histogram, Metrics};
+
+#[derive(Debug, Clone)]
+pub struct CtsMetrics {
+  received: Counter,
+  replied: Counter,
+  nothing_to_send: Counter,
+  missed: Counter,
+  reply_latency_us: Histogram,
+}
+
+/// What happened to a ClearToSend addressed to us.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CtsOutcome {
+  /// Sent something useful.
+  Replied,
+
+  /// Had nothing queued so answered with NothingToSend, wasting the window.
+  NothingToSend,
+
+  /// Didn't answer at all, which the main board will see as a missed window.
+  Missed,
+}
+
+impl CtsMetrics {
+  /// Registers `<prefix>.cts.*` metrics in [Metrics::global].
+  pub fn new(prefix: &str) -> Self {
+    Self::with_registry(Metrics::global(), prefix)
+  }
+
+  pub fn with_registry(metrics: &Metrics, prefix: &str) -> Self {
+    Self {
+      received: metrics.counter(&format!("{prefix}.cts.received")),
+      replied: metrics.counter(&format!("{prefix}.cts.replied")),
+      nothing_to_send: metrics.counter(&format!("{prefix}.cts.nothing_to_send")),
+      missed: metrics.counter(&format!("{prefix}.cts.missed")),
+      reply_latency_us: metrics.histogram(&format!("{prefix}.cts.reply_latency_us")),
+    }
+  }
+
+  /// Record the outcome of a ClearToSend, with `latency` being the time from starting to handle
+  /// it to the reply being written (ignored for [CtsOutcome::Missed]).
+  pub fn record(&self, outcome: CtsOutcome, latency: Duration) {
+    self.received.inc();
+    let counter = match outcome {
+      CtsOutcome::Replied => &self.replied,
+      CtsOutcome::NothingToSend => &self.nothing_to_send,
+      CtsOutcome::Missed => {
+        self.missed.inc();
+        return;
+      }
+    };
+    counter.inc();
+    self.reply_latency_us.record(u64::try_from(latency.as_micros()).unwrap_or(u64::MAX));
+  }
+}
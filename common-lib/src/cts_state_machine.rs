@@ -1,34 +1,50 @@
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use balboa_spa_messages::channel::Channel;
 use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::AtomicUsize;
-use log::{debug, info};
+use log::{debug, info, warn};
 use balboa_spa_messages::message_types::MessageType;
-use crate::channel_allocator_broker::{AllocatorToken, ChannelAllocatorBroker, GLOBAL_BROKER};
+use crate::assignment_store::AssignmentStore;
+use crate::channel_allocator_broker::{AllocatorToken, ChannelAllocatorBroker};
 use crate::client_ident::ClientIdent;
-use crate::message_state_machine::{MessageState, MessageStateMachine, SmResult, StateArgs};
+use crate::metrics::Metrics;
+use crate::message_state_machine::{MessageState, MessageStateMachine, SmResult, StateArgs, StateTimeout};
 use crate::message_state_machine::SmResult::{HandledNoReply, NotHandled, SendReply};
 
 const DEFAULT_NEW_CLIENT_RETRY_WAIT: Duration = Duration::from_secs(2);
 
 pub type CtsStateMachine = MessageStateMachine<StateWaitingForNewClientCTS>;
 
+/// Identity and channel of a client that has been assigned a channel, which can be persisted
+/// and handed back via [CtsStateMachine::set_previous_assignment] after a restart.
+#[derive(Debug, Clone)]
+pub struct ChannelAssignment {
+  pub ident: ClientIdent,
+  pub channel: Channel,
+}
+
 #[derive(Debug)]
 pub struct CtsContext {
   client_ident: ClientIdent,
   got_channel: Option<Channel>,
+  assigned_channel: Option<Channel>,
+  previous_channel: Option<Channel>,
   allocator_broker: Arc<ChannelAllocatorBroker>,
   allocator_token: Option<AllocatorToken>,
+  assignment_store: Option<Box<dyn AssignmentStore>>,
 }
 
 impl Default for CtsContext {
   fn default() -> Self {
     Self {
-      allocator_broker: GLOBAL_BROKER.clone(),
+      allocator_broker: ChannelAllocatorBroker::global(),
       client_ident: Default::default(),
       got_channel: None,
+      assigned_channel: None,
+      previous_channel: None,
       allocator_token: None,
+      assignment_store: None,
     }
   }
 }
@@ -37,6 +53,61 @@ impl CtsStateMachine {
   pub fn take_got_channel(&mut self) -> Option<Channel> {
     std::mem::take(&mut self.context.got_channel)
   }
+
+  /// Reuse the identity and channel from a previous session, answering the main board's
+  /// ExistingClientRequest to reclaim the channel rather than negotiating a new one.  This is
+  /// much faster after a reboot and matches what real panels do.  If the main board doesn't
+  /// honour the claim we fall back to negotiating as a new client.
+  pub fn set_previous_assignment(&mut self, assignment: ChannelAssignment) {
+    self.context.client_ident = assignment.ident;
+    self.context.previous_channel = Some(assignment.channel);
+  }
+
+  /// Restore the assignment from `store` as per [Self::set_previous_assignment] and save every
+  /// new assignment back to it.  Failing to load just means negotiating from scratch.
+  pub fn set_assignment_store(&mut self, store: Box<dyn AssignmentStore>) {
+    match store.load() {
+      Ok(Some(assignment)) => {
+        info!("Restoring previous assignment {assignment:?}");
+        self.set_previous_assignment(assignment);
+      }
+      Ok(None) => {}
+      Err(e) => warn!("Failed to load channel assignment: {e:?}"),
+    }
+    self.context.assignment_store = Some(store);
+  }
+
+  /// Coordinate with a specific set of clients rather than everything in the process, see
+  /// [crate::channel_allocator_broker].
+  pub fn set_allocator_broker(&mut self, broker: Arc<ChannelAllocatorBroker>) {
+    self.context.allocator_broker = broker;
+  }
+
+  /// Current assignment, suitable for persisting across restarts.
+  pub fn current_assignment(&self) -> Option<ChannelAssignment> {
+    Some(ChannelAssignment {
+      ident: self.context.client_ident.clone(),
+      channel: self.context.assigned_channel?,
+    })
+  }
+}
+
+impl CtsContext {
+  fn on_assigned(&mut self, channel: Channel) {
+    Metrics::global().counter("cts.channel_assignments").inc();
+    self.got_channel = Some(channel);
+    self.assigned_channel = Some(channel);
+    self.allocator_token = None;
+    if let Some(store) = &mut self.assignment_store {
+      let assignment = ChannelAssignment {
+        ident: self.client_ident.clone(),
+        channel,
+      };
+      if let Err(e) = store.save(&assignment) {
+        warn!("Failed to save channel assignment: {e:?}");
+      }
+    }
+  }
 }
 
 #[derive(Default, Debug)]
@@ -58,7 +129,6 @@ impl MessageState for StateWaitingForNewClientCTS {
             args.context.allocator_token = Some(token);
             args.sm.move_to_state(StateWaitingForChannelAssignment {
               ident: args.context.client_ident.clone(),
-              requested_at: Instant::now(),
             });
             SendReply(MessageType::ChannelAssignmentRequest {
               device_type: args.context.client_ident.device_type,
@@ -71,15 +141,73 @@ impl MessageState for StateWaitingForNewClientCTS {
           },
         }
       }
+      (&Channel::MulticastChannelAssignment, &MessageType::ExistingClientRequest()) => {
+        let Some(channel) = args.context.previous_channel else {
+          return NotHandled;
+        };
+        // Take the token so that we don't talk over another client reclaiming at the same time.
+        let Some(token) = args.context.allocator_broker.try_allocate() else {
+          debug!("Yielding to other channel allocator...");
+          return NotHandled;
+        };
+        args.context.allocator_token = Some(token);
+        args.sm.move_to_state(StateReclaimingChannel(channel));
+        let ident = &args.context.client_ident;
+        let [hash_hi, hash_lo] = ident.client_hash.to_be_bytes();
+        SendReply(MessageType::ExistingClientResponse {
+          unknown: vec![ident.device_type, hash_hi, hash_lo],
+        }.to_message(channel))
+      }
       _ => NotHandled,
     }
   }
 }
 
+/// Answered an ExistingClientRequest and waiting for the main board to confirm by clearing us to
+/// send on our old channel.
+#[derive(Debug)]
+struct StateReclaimingChannel(Channel);
+
+impl MessageState for StateReclaimingChannel {
+  type Kind = CtsStateKind;
+  type Context = CtsContext;
+
+  fn kind(&self) -> Self::Kind {
+    CtsStateKind::ReclaimingChannel
+  }
+
+  fn handle_message(&self, args: &mut StateArgs<Self::Kind, Self::Context>) -> SmResult {
+    match args.mt {
+      MessageType::ClearToSend() if args.channel == &self.0 => {
+        info!("Reclaimed previous channel {:?}", self.0);
+        Metrics::global().counter("cts.channel_reclaims").inc();
+        args.context.on_assigned(self.0);
+        args.sm.move_to_state(StateChannelAssigned(self.0));
+
+        // Let the caller's other state machines answer it now that they know the channel.
+        NotHandled
+      }
+      _ => NotHandled,
+    }
+  }
+
+  fn timeout(&self) -> Option<Duration> {
+    Some(DEFAULT_NEW_CLIENT_RETRY_WAIT)
+  }
+
+  fn handle_timeout(&self, args: &mut StateTimeout<Self::Kind, Self::Context>) -> SmResult {
+    info!("Main board did not honour our claim to {:?}, negotiating a new channel", self.0);
+    Metrics::global().counter("cts.reclaim_timeouts").inc();
+    args.context.previous_channel = None;
+    args.context.allocator_token = None;
+    args.sm.move_to_state(StateWaitingForNewClientCTS);
+    HandledNoReply
+  }
+}
+
 #[derive(Debug)]
 struct StateWaitingForChannelAssignment {
   ident: ClientIdent,
-  requested_at: Instant,
 }
 
 impl MessageState for StateWaitingForChannelAssignment {
@@ -93,16 +221,11 @@ impl MessageState for StateWaitingForChannelAssignment {
   fn handle_message(&self, args: &mut StateArgs<Self::Kind, Self::Context>) -> SmResult {
     match (args.channel, args.mt) {
       (&Channel::MulticastChannelAssignment, &MessageType::NewClientClearToSend()) => {
-        if self.requested_at.elapsed() >= DEFAULT_NEW_CLIENT_RETRY_WAIT {
-          args.context.allocator_token = None;
-          args.sm.move_to_state(StateWaitingForNewClientCTS);
-        }
         HandledNoReply
       }
       (&Channel::MulticastChannelAssignment, &MessageType::ChannelAssignmentResponse { channel, client_hash }) => {
         if self.ident.client_hash == client_hash {
-          args.context.got_channel = Some(channel);
-          args.context.allocator_token = None;
+          args.context.on_assigned(channel);
           args.sm.move_to_state(StateChannelAssigned(channel));
           SendReply(MessageType::ChannelAssignmentAck().to_message(channel))
         } else {
@@ -113,6 +236,18 @@ impl MessageState for StateWaitingForChannelAssignment {
       _ => NotHandled,
     }
   }
+
+  fn timeout(&self) -> Option<Duration> {
+    Some(DEFAULT_NEW_CLIENT_RETRY_WAIT)
+  }
+
+  fn handle_timeout(&self, args: &mut StateTimeout<Self::Kind, Self::Context>) -> SmResult {
+    // Give other allocators a chance and try again on the next NewClientClearToSend.
+    Metrics::global().counter("cts.assignment_timeouts").inc();
+    args.context.allocator_token = None;
+    args.sm.move_to_state(StateWaitingForNewClientCTS);
+    HandledNoReply
+  }
 }
 
 #[derive(Debug)]
@@ -135,5 +270,151 @@ impl MessageState for StateChannelAssigned {
 pub enum CtsStateKind {
   WaitingForNewClientCTS,
   WaitingForChannelAssignment,
+  ReclaimingChannel,
   ChannelAssigned,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Instant;
+  use balboa_spa_messages::framed_reader::FramedReader;
+  use balboa_spa_messages::framed_writer::FramedWriter;
+  use crate::assignment_store::MemoryAssignmentStore;
+  use crate::message_logger::MessageLogger;
+  use super::*;
+
+  fn new_reattaching_sm() -> CtsStateMachine {
+    let mut sm = CtsStateMachine::new();
+    sm.set_allocator_broker(Arc::new(ChannelAllocatorBroker::new()));
+    sm.set_previous_assignment(ChannelAssignment {
+      ident: ClientIdent { device_type: 0x2, client_hash: 0xf247 },
+      channel: Channel::Client(0x11),
+    });
+    sm
+  }
+
+  #[test]
+  fn test_reclaims_previous_channel() -> anyhow::Result<()> {
+    let mut sm = new_reattaching_sm();
+    let mut writer = FramedWriter::new(Vec::new());
+    let logger = MessageLogger::new("test");
+
+    sm.handle_message(
+      &mut writer,
+      &logger,
+      &Channel::MulticastChannelAssignment,
+      &MessageType::ExistingClientRequest())?;
+    assert_eq!(sm.state_kind(), CtsStateKind::ReclaimingChannel);
+    let sent = FramedReader::new(writer.into_inner().as_slice()).next_message()?;
+    assert_eq!(sent.channel, Channel::Client(0x11));
+    let expected = MessageType::ExistingClientResponse { unknown: vec![0x2, 0xf2, 0x47] }
+        .to_message(Channel::Client(0x11))?;
+    assert_eq!(sent, expected);
+
+    let mut writer = FramedWriter::new(Vec::new());
+    sm.handle_message(&mut writer, &logger, &Channel::Client(0x11), &MessageType::ClearToSend())?;
+    assert_eq!(sm.state_kind(), CtsStateKind::ChannelAssigned);
+    assert_eq!(sm.take_got_channel(), Some(Channel::Client(0x11)));
+    assert_eq!(sm.current_assignment().map(|a| a.channel), Some(Channel::Client(0x11)));
+    Ok(())
+  }
+
+  #[test]
+  fn test_falls_back_when_claim_ignored() -> anyhow::Result<()> {
+    let mut sm = new_reattaching_sm();
+    let mut writer = FramedWriter::new(Vec::new());
+    let logger = MessageLogger::new("test");
+    let start = Instant::now();
+
+    sm.handle_message(
+      &mut writer,
+      &logger,
+      &Channel::MulticastChannelAssignment,
+      &MessageType::ExistingClientRequest())?;
+    sm.tick(&mut writer, &logger, start)?;
+    sm.tick(&mut writer, &logger, start + DEFAULT_NEW_CLIENT_RETRY_WAIT)?;
+    assert_eq!(sm.state_kind(), CtsStateKind::WaitingForNewClientCTS);
+
+    // Only one attempt, after that we behave as a new client.
+    sm.handle_message(
+      &mut writer,
+      &logger,
+      &Channel::MulticastChannelAssignment,
+      &MessageType::ExistingClientRequest())?;
+    assert_eq!(sm.state_kind(), CtsStateKind::WaitingForNewClientCTS);
+    sm.handle_message(
+      &mut writer,
+      &logger,
+      &Channel::MulticastChannelAssignment,
+      &MessageType::NewClientClearToSend())?;
+    assert_eq!(sm.state_kind(), CtsStateKind::WaitingForChannelAssignment);
+    Ok(())
+  }
+
+  #[test]
+  fn test_assignment_store() -> anyhow::Result<()> {
+    let store = MemoryAssignmentStore::new();
+    let mut sm = CtsStateMachine::new();
+    sm.set_allocator_broker(Arc::new(ChannelAllocatorBroker::new()));
+    sm.set_assignment_store(Box::new(store.clone()));
+    let client_hash = sm.context.client_ident.client_hash;
+
+    let mut writer = FramedWriter::new(Vec::new());
+    let logger = MessageLogger::new("test");
+    sm.handle_message(
+      &mut writer,
+      &logger,
+      &Channel::MulticastChannelAssignment,
+      &MessageType::NewClientClearToSend())?;
+    sm.handle_message(
+      &mut writer,
+      &logger,
+      &Channel::MulticastChannelAssignment,
+      &MessageType::ChannelAssignmentResponse { channel: Channel::Client(0x12), client_hash })?;
+    assert_eq!(store.load()?.map(|a| a.channel), Some(Channel::Client(0x12)));
+
+    // After a restart the same identity is used to reclaim the channel.
+    let mut restarted = CtsStateMachine::new();
+    restarted.set_assignment_store(Box::new(store));
+    assert_eq!(restarted.context.client_ident.client_hash, client_hash);
+    assert_eq!(restarted.context.previous_channel, Some(Channel::Client(0x12)));
+    Ok(())
+  }
+
+  #[test]
+  fn test_shared_broker_yields() -> anyhow::Result<()> {
+    let broker = Arc::new(ChannelAllocatorBroker::new());
+    let mut first = CtsStateMachine::new();
+    first.set_allocator_broker(broker.clone());
+    let mut second = CtsStateMachine::new();
+    second.set_allocator_broker(broker.clone());
+    let client_hash = first.context.client_ident.client_hash;
+
+    let logger = MessageLogger::new("test");
+    let mut writer = FramedWriter::new(Vec::new());
+    let new_client_cts = |sm: &mut CtsStateMachine, writer: &mut FramedWriter<Vec<u8>>| {
+      sm.handle_message(
+        writer,
+        &logger,
+        &Channel::MulticastChannelAssignment,
+        &MessageType::NewClientClearToSend())
+    };
+    new_client_cts(&mut first, &mut writer)?;
+    new_client_cts(&mut second, &mut writer)?;
+    assert_eq!(first.state_kind(), CtsStateKind::WaitingForChannelAssignment);
+    assert_eq!(second.state_kind(), CtsStateKind::WaitingForNewClientCTS);
+
+    first.handle_message(
+      &mut writer,
+      &logger,
+      &Channel::MulticastChannelAssignment,
+      &MessageType::ChannelAssignmentResponse { channel: Channel::Client(0x10), client_hash })?;
+    assert_eq!(first.state_kind(), CtsStateKind::ChannelAssigned);
+    assert!(!broker.is_allocated());
+
+    // Now it's the second client's turn.
+    new_client_cts(&mut second, &mut writer)?;
+    assert_eq!(second.state_kind(), CtsStateKind::WaitingForChannelAssignment);
+    Ok(())
+  }
+}
@@ -1,9 +1,23 @@
 pub mod transport;
+#[cfg(feature = "async")]
+pub mod async_transport;
 pub mod bus_transport;
+pub mod tcp_transport;
+pub mod chaos_transport;
+#[cfg(feature = "serial")]
+pub mod serial_transport;
+#[cfg(unix)]
+pub mod pty_transport;
 pub mod message_logger;
+pub mod message_history;
+pub mod metrics;
+pub mod cts_metrics;
 pub mod cts_state_machine;
 pub mod client_ident;
+pub mod assignment_store;
 pub mod message_state_machine;
+pub mod request_tracker;
 pub mod channel_filter;
-mod channel_allocator_broker;
+pub mod channel_demux;
+pub mod channel_allocator_broker;
 pub mod view_model_event_handle;
@@ -0,0 +1,71 @@
+//! Bounded in-memory record of recent messages, giving diagnostics screens and debug endpoints
+//! some visibility into bus traffic on devices where nobody is watching the serial log.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use balboa_spa_messages::message::Message;
+use crate::message_logger::MessageDirection;
+
+pub const DEFAULT_HISTORY_LEN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+  pub timestamp: SystemTime,
+  pub logger: &'static str,
+  pub direction: MessageDirection,
+  pub message: Message,
+}
+
+/// Clones share the same buffer, so one can be handed to a [crate::message_logger::MessageLogger]
+/// and another kept by whoever renders it.
+#[derive(Debug, Clone)]
+pub struct MessageHistory {
+  inner: Arc<Mutex<VecDeque<HistoryEntry>>>,
+  capacity: usize,
+}
+
+impl Default for MessageHistory {
+  fn default() -> Self {
+    Self::with_capacity(DEFAULT_HISTORY_LEN)
+  }
+}
+
+impl MessageHistory {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn with_capacity(capacity: usize) -> Self {
+    Self {
+      inner: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+      capacity,
+    }
+  }
+
+  /// Add a message, evicting the oldest one if full.
+  pub fn push(&self, logger: &'static str, direction: MessageDirection, message: &Message) {
+    if self.capacity == 0 {
+      return;
+    }
+    let mut entries = self.inner.lock().unwrap();
+    if entries.len() == self.capacity {
+      entries.pop_front();
+    }
+    entries.push_back(HistoryEntry {
+      timestamp: SystemTime::now(),
+      logger,
+      direction,
+      message: message.clone(),
+    });
+  }
+
+  /// Copy of the current contents, oldest first.
+  pub fn snapshot(&self) -> Vec<HistoryEntry> {
+    self.inner.lock().unwrap().iter().cloned().collect()
+  }
+
+  pub fn clear(&self) {
+    self.inner.lock().unwrap().clear();
+  }
+}
@@ -1,21 +1,160 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter, Write as _};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use balboa_spa_messages::display;
 use balboa_spa_messages::message::Message;
 use balboa_spa_messages::message_types::MessageTypeKind;
-use log::{Level, log};
+use log::{Level, log, warn};
 use num_traits::FromPrimitive;
+use crate::message_history::MessageHistory;
 
-#[derive(Debug, Clone)]
+/// Roughly once a second for the messages the main board sends on every tick.
+pub const CHATTY_LOG_INTERVAL: u32 = 66;
+
+/// Message types that can be sampled by [MessageLogger::set_quiet_policies] without losing
+/// anything interesting.
+const CHATTY_KINDS: [MessageTypeKind; 4] = [
+  MessageTypeKind::NewClientClearToSend,
+  MessageTypeKind::ClearToSend,
+  MessageTypeKind::StatusUpdate,
+  MessageTypeKind::NothingToSend,
+];
+
+/// How often to pass messages of a given kind on to the sink.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum LogPolicy {
+  #[default]
+  Always,
+
+  /// Only the first of every `n` messages, counted per direction.
+  EveryNth(u32),
+
+  Never,
+}
+
+/// Clones share the same policies, so that changes made at runtime apply everywhere the logger
+/// has been handed out.
+#[derive(Clone)]
 pub struct MessageLogger {
   debug_name: &'static str,
+  sink: Arc<dyn MessageSink>,
+  policies: Arc<Mutex<HashMap<MessageTypeKind, PolicyState>>>,
+  history: Arc<Mutex<Option<MessageHistory>>>,
+}
+
+#[derive(Debug, Default)]
+struct PolicyState {
+  policy: LogPolicy,
+  inbound_seen: u32,
+  outbound_seen: u32,
+}
+
+impl PolicyState {
+  fn should_log(&mut self, direction: MessageDirection) -> bool {
+    let n = match self.policy {
+      LogPolicy::Always => return true,
+      LogPolicy::Never => return false,
+      LogPolicy::EveryNth(n) => n.max(1),
+    };
+    let seen = match direction {
+      MessageDirection::Inbound => &mut self.inbound_seen,
+      MessageDirection::Outbound => &mut self.outbound_seen,
+    };
+    let log = *seen % n == 0;
+    *seen = seen.wrapping_add(1);
+    log
+  }
 }
 
 impl MessageLogger {
   pub fn new(debug_name: &'static str) -> Self {
+    Self::with_sink(debug_name, Arc::new(LogSink))
+  }
+
+  /// Send messages somewhere other than the `log` crate, for example a [JsonLinesSink] to
+  /// capture traffic for later analysis.
+  pub fn with_sink(debug_name: &'static str, sink: Arc<dyn MessageSink>) -> Self {
     Self {
       debug_name,
+      sink,
+      policies: Default::default(),
+      history: Default::default(),
+    }
+  }
+
+  /// Change how often messages of `kind` are logged.  Messages of unrecognized types are always
+  /// logged as they're likely to be of interest.
+  pub fn set_policy(&self, kind: MessageTypeKind, policy: LogPolicy) {
+    let mut policies = self.policies.lock().unwrap();
+    *policies.entry(kind).or_default() = PolicyState { policy, ..Default::default() };
+  }
+
+  /// Sample the high frequency polling and status messages at [CHATTY_LOG_INTERVAL] so that
+  /// everything else (channel assignment, settings changes, etc) stands out.
+  pub fn set_quiet_policies(&self) {
+    for kind in CHATTY_KINDS {
+      self.set_policy(kind, LogPolicy::EveryNth(CHATTY_LOG_INTERVAL));
     }
   }
 
+  /// A logger with the same name, sink, history and policies, but sampling independently and
+  /// unaffected by later policy changes.  Useful for one of many similar peers, so that a busy
+  /// peer doesn't use up the sampling budget of the others.
+  pub fn fork(&self) -> Self {
+    let policies = self.policies.lock().unwrap().iter()
+        .map(|(kind, state)| (*kind, PolicyState { policy: state.policy, ..Default::default() }))
+        .collect();
+    Self {
+      debug_name: self.debug_name,
+      sink: self.sink.clone(),
+      policies: Arc::new(Mutex::new(policies)),
+      history: Arc::new(Mutex::new(self.history.lock().unwrap().clone())),
+    }
+  }
+
+  /// Also keep the most recent messages that pass the log policies in `history`.
+  pub fn set_history(&self, history: MessageHistory) {
+    *self.history.lock().unwrap() = Some(history);
+  }
+
   pub fn log(&self, direction: MessageDirection, message: &Message) {
+    if let Some(kind) = MessageTypeKind::from_u8(message.message_type) {
+      let mut policies = self.policies.lock().unwrap();
+      if let Some(state) = policies.get_mut(&kind) {
+        if !state.should_log(direction) {
+          return;
+        }
+      }
+    }
+    self.sink.record(self.debug_name, direction, message);
+    if let Some(history) = self.history.lock().unwrap().as_ref() {
+      history.push(self.debug_name, direction, message);
+    }
+  }
+}
+
+impl Debug for MessageLogger {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("MessageLogger")
+        .field("debug_name", &self.debug_name)
+        .finish_non_exhaustive()
+  }
+}
+
+/// Destination for messages passed to [MessageLogger].  Shared between threads, so
+/// implementations that write somewhere must synchronize internally.
+pub trait MessageSink: Send + Sync {
+  fn record(&self, debug_name: &str, direction: MessageDirection, message: &Message);
+}
+
+/// Human readable one line summaries through the `log` crate, using `debug_name` as the target.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LogSink;
+
+impl MessageSink for LogSink {
+  fn record(&self, debug_name: &str, direction: MessageDirection, message: &Message) {
     let (suffix, level) = match MessageTypeKind::from_u8(message.message_type) {
       None => ("(unknown!)", Level::Warn),
       Some(kind) => {
@@ -35,12 +174,162 @@ impl MessageLogger {
       MessageDirection::Inbound => "<=",
       MessageDirection::Outbound => "=>",
     };
-    log!(target: self.debug_name, level, "{direction_label} Message{suffix}: {message:?}");
+    log!(target: debug_name, level, "{direction_label} Message{suffix}: {}", display::compact(message));
   }
 }
 
+/// One JSON object per line, suitable for `jq` and other tooling:
+///
+/// ```json
+/// {"timestamp_us":1700000000000000,"logger":"main_board","direction":"outbound","channel":255,"kind":"StatusUpdate","message_type":19,"payload":"0a1b..."}
+/// ```
+///
+/// `timestamp_us` is wall clock time since the UNIX epoch, `kind` is null for message types we
+/// don't recognize, and `payload` is lowercase hex.
+#[derive(Debug)]
+pub struct JsonLinesSink<W> {
+  writer: Mutex<W>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+  pub fn new(writer: W) -> Self {
+    Self { writer: Mutex::new(writer) }
+  }
+
+  pub fn into_inner(self) -> W {
+    self.writer.into_inner().unwrap()
+  }
+
+  pub fn format_line(debug_name: &str, direction: MessageDirection, message: &Message) -> String {
+    let timestamp_us = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros())
+        .unwrap_or_default();
+    let direction = match direction {
+      MessageDirection::Inbound => "inbound",
+      MessageDirection::Outbound => "outbound",
+    };
+    let kind = match MessageTypeKind::from_u8(message.message_type) {
+      Some(kind) => format!("\"{kind:?}\""),
+      None => "null".to_owned(),
+    };
+    let mut payload = String::with_capacity(message.payload.len() * 2);
+    for b in &message.payload {
+      let _ = write!(payload, "{b:02x}");
+    }
+    format!(
+      "{{\"timestamp_us\":{timestamp_us},\"logger\":\"{}\",\"direction\":\"{direction}\",\"channel\":{},\"kind\":{kind},\"message_type\":{},\"payload\":\"{payload}\"}}",
+      escape_json(debug_name),
+      u8::from(&message.channel),
+      message.message_type)
+  }
+}
+
+impl<W: Write + Send> MessageSink for JsonLinesSink<W> {
+  fn record(&self, debug_name: &str, direction: MessageDirection, message: &Message) {
+    let line = Self::format_line(debug_name, direction, message);
+    let mut writer = self.writer.lock().unwrap();
+    if let Err(e) = writeln!(writer, "{line}").and_then(|_| writer.flush()) {
+      warn!("Failed to write message log: {e}");
+    }
+  }
+}
+
+fn escape_json(s: &str) -> String {
+  s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum MessageDirection {
   Inbound,
   Outbound,
 }
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::channel::Channel;
+  use balboa_spa_messages::message_types::MessageType;
+  use super::*;
+
+  #[test]
+  fn test_json_lines() -> anyhow::Result<()> {
+    let sink = Arc::new(JsonLinesSink::new(Vec::new()));
+    let logger = MessageLogger::with_sink("test", sink.clone());
+    logger.log(
+      MessageDirection::Inbound,
+      &MessageType::ClearToSend().to_message(Channel::Client(0x10))?);
+    logger.log(
+      MessageDirection::Outbound,
+      &Message { channel: Channel::MulticastBroadcast, message_type: 0x77, payload: vec![0xab, 0x01] });
+    drop(logger);
+
+    let output = String::from_utf8(Arc::try_unwrap(sink).unwrap().into_inner())?;
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("{\"timestamp_us\":"));
+    assert!(lines[0].ends_with(
+      "\"logger\":\"test\",\"direction\":\"inbound\",\"channel\":16,\"kind\":\"ClearToSend\",\"message_type\":6,\"payload\":\"\"}"));
+    assert!(lines[1].ends_with(
+      "\"direction\":\"outbound\",\"channel\":255,\"kind\":null,\"message_type\":119,\"payload\":\"ab01\"}"));
+    Ok(())
+  }
+
+  #[derive(Default)]
+  struct CountingSink(Mutex<usize>);
+
+  impl MessageSink for CountingSink {
+    fn record(&self, _debug_name: &str, _direction: MessageDirection, _message: &Message) {
+      *self.0.lock().unwrap() += 1;
+    }
+  }
+
+  #[test]
+  fn test_policies() -> anyhow::Result<()> {
+    let sink = Arc::new(CountingSink::default());
+    let logger = MessageLogger::with_sink("test", sink.clone());
+    let cts = MessageType::ClearToSend().to_message(Channel::Client(0x10))?;
+    let assignment = MessageType::ChannelAssignmentResponse {
+      channel: Channel::Client(0x10),
+      client_hash: 0xf247,
+    }.to_message(Channel::MulticastChannelAssignment)?;
+    let count = || *sink.0.lock().unwrap();
+
+    logger.clone().set_quiet_policies();
+    for _ in 0..CHATTY_LOG_INTERVAL * 2 {
+      logger.log(MessageDirection::Inbound, &cts);
+    }
+    assert_eq!(count(), 2);
+
+    logger.log(MessageDirection::Outbound, &assignment);
+    assert_eq!(count(), 3);
+
+    // Forks keep the policy but count on their own.
+    let fork = logger.fork();
+    fork.log(MessageDirection::Inbound, &cts);
+    assert_eq!(count(), 4);
+
+    logger.set_policy(MessageTypeKind::ClearToSend, LogPolicy::Never);
+    logger.log(MessageDirection::Inbound, &cts);
+    assert_eq!(count(), 4);
+    Ok(())
+  }
+
+  #[test]
+  fn test_history() -> anyhow::Result<()> {
+    let logger = MessageLogger::with_sink("test", Arc::new(CountingSink::default()));
+    let history = MessageHistory::with_capacity(2);
+    logger.set_history(history.clone());
+    logger.set_policy(MessageTypeKind::NothingToSend, LogPolicy::Never);
+
+    for i in 0..3 {
+      logger.log(MessageDirection::Inbound, &MessageType::ClearToSend().to_message(Channel::Client(i))?);
+    }
+    logger.log(MessageDirection::Outbound, &MessageType::NothingToSend().to_message(Channel::Client(2))?);
+
+    let entries = history.snapshot();
+    let channels: Vec<_> = entries.iter().map(|e| e.message.channel).collect();
+    assert_eq!(channels, vec![Channel::Client(1), Channel::Client(2)]);
+    assert!(entries.iter().all(|e| e.logger == "test"));
+    Ok(())
+  }
+}
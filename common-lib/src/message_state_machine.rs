@@ -1,11 +1,14 @@
 use balboa_spa_messages::channel::Channel;
-use balboa_spa_messages::message_types::{MessageType, PayloadEncodeError};
+use balboa_spa_messages::message_types::{MessageType, MessageTypeKind, PayloadEncodeError};
 use std::io::Write;
 use balboa_spa_messages::framed_writer::FramedWriter;
 use log::debug;
 use balboa_spa_messages::message::Message;
-use std::fmt::{Debug};
+use std::fmt::{Debug, Formatter};
+use std::time::{Duration, Instant};
+use num_traits::FromPrimitive;
 use crate::channel_filter::{ChannelFilter, FilterResult};
+use crate::cts_metrics::{CtsMetrics, CtsOutcome};
 use crate::message_logger::{MessageDirection, MessageLogger};
 
 #[derive(Debug)]
@@ -14,6 +17,12 @@ pub struct MessageStateMachine<IS: MessageState> {
   state_mover: StateMover<IS::Kind, IS::Context>,
   pub context: IS::Context,
   channel_filter: ChannelFilter,
+
+  /// When the current state was first ticked, for [MessageState::timeout].
+  entered_at: Option<Instant>,
+
+  cts_metrics: Option<CtsMetrics>,
+  observers: StateObservers<IS::Kind>,
 }
 
 impl <IS> Default for MessageStateMachine<IS>
@@ -27,6 +36,9 @@ where
       state_mover: Default::default(),
       context: Default::default(),
       channel_filter: ChannelFilter::None,
+      entered_at: None,
+      cts_metrics: None,
+      observers: StateObservers(Vec::new()),
     }
   }
 }
@@ -43,12 +55,33 @@ where
   pub fn set_channel_filter(&mut self, channel_filter: ChannelFilter) {
     self.channel_filter = channel_filter;
   }
+
+  /// Record how this state machine answers ClearToSend on its own channel (as decided by the
+  /// channel filter).  Only set this on the state machine responsible for answering.
+  pub fn set_cts_metrics(&mut self, cts_metrics: CtsMetrics) {
+    self.cts_metrics = Some(cts_metrics);
+  }
 }
 
 impl <IS: MessageState> MessageStateMachine<IS> {
   pub fn state_kind(&self) -> IS::Kind {
     self.state.kind()
   }
+
+  /// Call `observer` on every state transition, e.g. to assert on the exact sequence of states
+  /// in tests or to explain to a user why we're stuck in a given state.
+  pub fn add_state_observer(
+      &mut self,
+      observer: impl FnMut(&StateTransition<IS::Kind>) + Send + 'static,
+  ) {
+    self.observers.0.push(Box::new(observer));
+  }
+
+  /// When the current state will time out, if it has a timeout and has been ticked at least
+  /// once.  Useful for callers that want to sleep until the next [Self::tick] is due.
+  pub fn deadline(&self) -> Option<Instant> {
+    Some(self.entered_at? + self.state.timeout()?)
+  }
 }
 
 impl <IS> MessageStateMachine<IS>
@@ -69,6 +102,9 @@ where
       return Ok(());
     }
 
+    let started_at = Instant::now();
+    let is_my_cts = filter_result == FilterResult::MyChannel
+        && matches!(mt, MessageType::ClearToSend());
     let state_mover = &mut self.state_mover;
     state_mover.state = None;
     let mut args = StateArgs {
@@ -78,24 +114,73 @@ where
       context: &mut self.context,
       channel_match: filter_result,
     };
-    let result = Self::dispatch_handle_message(
-        &self.state,
+    let sm_result = self.state.handle_message(&mut args);
+    let cts_outcome = match &sm_result {
+      SmResult::SendReply(Ok(reply)) => {
+        match MessageTypeKind::from_u8(reply.message_type) {
+          Some(MessageTypeKind::NothingToSend) => CtsOutcome::NothingToSend,
+          _ => CtsOutcome::Replied,
+        }
+      }
+      _ => CtsOutcome::Missed,
+    };
+    let result = Self::dispatch_result(sm_result, writer, message_logger);
+    if let Some(cts_metrics) = self.cts_metrics.as_ref().filter(|_| is_my_cts) {
+      cts_metrics.record(cts_outcome, started_at.elapsed());
+    }
+    if let Some(new_state) = std::mem::take(&mut state_mover.state) {
+      self.maybe_move_to_state(new_state, TransitionCause::Message { channel, mt });
+    }
+    result
+  }
+
+  /// Advance time for the state machine, firing [MessageState::handle_timeout] if the current
+  /// state has been active for longer than its [MessageState::timeout].  The clock starts on the
+  /// first tick after entering a state, and restarts after each timeout that doesn't move to a
+  /// new state so that states can implement periodic retries.
+  ///
+  /// Callers are expected to tick at least as often as messages arrive; the bus is chatty enough
+  /// that ticking just before each [Self::handle_message] is typically sufficient.
+  pub fn tick<W: Write>(
+      &mut self,
+      writer: &mut FramedWriter<W>,
+      message_logger: &MessageLogger,
+      now: Instant,
+  ) -> Result<(), MessageHandlingError> {
+    let Some(timeout) = self.state.timeout() else {
+      return Ok(());
+    };
+    let entered_at = *self.entered_at.get_or_insert(now);
+    let elapsed = now.saturating_duration_since(entered_at);
+    if elapsed < timeout {
+      return Ok(());
+    }
+
+    debug!("{:?} timed out after {elapsed:?}", self.state);
+    let state_mover = &mut self.state_mover;
+    state_mover.state = None;
+    let mut args = StateTimeout {
+      sm: state_mover,
+      context: &mut self.context,
+      elapsed,
+    };
+    let result = Self::dispatch_result(
+        self.state.handle_timeout(&mut args),
         writer,
-        message_logger,
-        &mut args);
+        message_logger);
+    self.entered_at = Some(now);
     if let Some(new_state) = std::mem::take(&mut state_mover.state) {
-      self.maybe_move_to_state(new_state);
+      self.maybe_move_to_state(new_state, TransitionCause::Timeout { elapsed });
     }
     result
   }
 
-  fn dispatch_handle_message(
-      to_state: &Box<dyn MessageState<Context=IS::Context, Kind=IS::Kind> + Send + 'static>,
+  fn dispatch_result(
+      sm_result: SmResult,
       writer: &mut FramedWriter<impl Write>,
       message_logger: &MessageLogger,
-      args: &mut StateArgs<IS::Kind, IS::Context>,
   ) -> Result<(), MessageHandlingError> {
-    match to_state.handle_message(args) {
+    match sm_result {
       SmResult::HandledNoReply => Ok(()),
       SmResult::SendReply(message_result) => {
         match message_result {
@@ -118,16 +203,50 @@ where
 
   fn maybe_move_to_state(
       &mut self,
-      new_state: Box<dyn MessageState<Context=IS::Context, Kind=IS::Kind> + Send + 'static>
+      new_state: Box<dyn MessageState<Context=IS::Context, Kind=IS::Kind> + Send + 'static>,
+      cause: TransitionCause,
   ) {
     if self.state.kind() != new_state.kind() {
       let old_state = &self.state;
       debug!("Moving from {old_state:?} to {new_state:?}");
+      let transition = StateTransition {
+        from: old_state.kind(),
+        to: new_state.kind(),
+        cause,
+      };
+      for observer in &mut self.observers.0 {
+        observer(&transition);
+      }
       self.state = new_state;
+      self.entered_at = None;
     }
   }
 }
 
+/// Passed to observers registered with [MessageStateMachine::add_state_observer].
+#[derive(Debug)]
+pub struct StateTransition<'a, K> {
+  pub from: K,
+  pub to: K,
+  pub cause: TransitionCause<'a>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TransitionCause<'a> {
+  Message { channel: &'a Channel, mt: &'a MessageType },
+  Timeout { elapsed: Duration },
+}
+
+type StateObserver<K> = Box<dyn FnMut(&StateTransition<K>) + Send>;
+
+struct StateObservers<K>(Vec<StateObserver<K>>);
+
+impl<K> Debug for StateObservers<K> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "[{} observers]", self.0.len())
+  }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum MessageHandlingError {
   #[error("Unrecoverable error that likely requires software updates: {0}")]
@@ -142,6 +261,16 @@ pub struct StateArgs<'a, K, C> {
   pub channel_match: FilterResult,
 }
 
+/// Arguments for [MessageState::handle_timeout].
+pub struct StateTimeout<'a, K, C> {
+  pub sm: &'a mut StateMover<K, C>,
+  pub context: &'a mut C,
+
+  /// How long the state was active, which may be somewhat longer than its timeout depending on
+  /// how often the state machine is ticked.
+  pub elapsed: Duration,
+}
+
 #[derive(Debug)]
 pub struct StateMover<K, C> {
   state: Option<Box<dyn MessageState<Context=C, Kind=K> + Send + 'static>>,
@@ -166,10 +295,162 @@ pub trait MessageState: Debug {
 
   fn kind(&self) -> Self::Kind;
   fn handle_message(&self, args: &mut StateArgs<Self::Kind, Self::Context>) -> SmResult;
+
+  /// How long this state may remain active before [Self::handle_timeout] is called, or None to
+  /// wait indefinitely.
+  fn timeout(&self) -> Option<Duration> {
+    None
+  }
+
+  /// Called from [MessageStateMachine::tick] once [Self::timeout] has elapsed, typically to
+  /// retry or give up by moving to another state.
+  fn handle_timeout(&self, _args: &mut StateTimeout<Self::Kind, Self::Context>) -> SmResult {
+    SmResult::HandledNoReply
+  }
 }
 
 pub enum SmResult {
   SendReply(Result<Message, PayloadEncodeError>),
   HandledNoReply,
   NotHandled,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use crate::metrics::{MetricValue, Metrics};
+  use super::*;
+
+  #[derive(Debug, Default)]
+  struct StateRequesting;
+
+  impl MessageState for StateRequesting {
+    type Kind = TestKind;
+    type Context = usize;
+
+    fn kind(&self) -> Self::Kind {
+      TestKind::Requesting
+    }
+
+    fn handle_message(&self, _args: &mut StateArgs<Self::Kind, Self::Context>) -> SmResult {
+      SmResult::NotHandled
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+      Some(Duration::from_secs(1))
+    }
+
+    fn handle_timeout(&self, args: &mut StateTimeout<Self::Kind, Self::Context>) -> SmResult {
+      *args.context += 1;
+      if *args.context >= 2 {
+        args.sm.move_to_state(StateGaveUp);
+      }
+      SmResult::SendReply(MessageType::ClearToSend().to_message(Channel::Client(0x10)))
+    }
+  }
+
+  #[derive(Debug)]
+  struct StateGaveUp;
+
+  impl MessageState for StateGaveUp {
+    type Kind = TestKind;
+    type Context = usize;
+
+    fn kind(&self) -> Self::Kind {
+      TestKind::GaveUp
+    }
+
+    fn handle_message(&self, _args: &mut StateArgs<Self::Kind, Self::Context>) -> SmResult {
+      SmResult::NotHandled
+    }
+  }
+
+  #[derive(Debug, PartialEq)]
+  enum TestKind {
+    Requesting,
+    GaveUp,
+  }
+
+  #[test]
+  fn test_timeout_retries_then_moves() -> anyhow::Result<()> {
+    let mut sm = MessageStateMachine::<StateRequesting>::new();
+    let transitions = Arc::new(Mutex::new(Vec::new()));
+    let transitions_for_observer = transitions.clone();
+    sm.add_state_observer(move |t| {
+      let cause = match t.cause {
+        TransitionCause::Message { .. } => "message",
+        TransitionCause::Timeout { .. } => "timeout",
+      };
+      transitions_for_observer.lock().unwrap().push(format!("{:?}->{:?} ({cause})", t.from, t.to));
+    });
+    let mut writer = FramedWriter::new(Vec::new());
+    let logger = MessageLogger::new("test");
+    let start = Instant::now();
+    let at = |millis| start + Duration::from_millis(millis);
+
+    assert_eq!(sm.deadline(), None);
+    sm.tick(&mut writer, &logger, at(0))?;
+    assert_eq!(sm.deadline(), Some(at(1000)));
+    sm.tick(&mut writer, &logger, at(999))?;
+    assert_eq!(sm.context, 0);
+
+    // Retry, restarting the clock.
+    sm.tick(&mut writer, &logger, at(1000))?;
+    assert_eq!(sm.context, 1);
+    assert_eq!(sm.state_kind(), TestKind::Requesting);
+    assert_eq!(sm.deadline(), Some(at(2000)));
+
+    sm.tick(&mut writer, &logger, at(2100))?;
+    assert_eq!(sm.context, 2);
+    assert_eq!(sm.state_kind(), TestKind::GaveUp);
+    assert_eq!(sm.deadline(), None);
+    assert_eq!(*transitions.lock().unwrap(), vec!["Requesting->GaveUp (timeout)"]);
+
+    assert!(!writer.into_inner().is_empty());
+    Ok(())
+  }
+
+  /// Has nothing to say the first time it's polled.
+  #[derive(Debug, Default)]
+  struct StateAnswering;
+
+  impl MessageState for StateAnswering {
+    type Kind = ();
+    type Context = usize;
+
+    fn kind(&self) -> Self::Kind {}
+
+    fn handle_message(&self, args: &mut StateArgs<Self::Kind, Self::Context>) -> SmResult {
+      *args.context += 1;
+      let reply = match *args.context {
+        1 => MessageType::NothingToSend(),
+        2 => MessageType::ExistingClientResponse { unknown: vec![] },
+        _ => return SmResult::NotHandled,
+      };
+      SmResult::SendReply(reply.to_message(*args.channel))
+    }
+  }
+
+  #[test]
+  fn test_cts_metrics() -> anyhow::Result<()> {
+    let metrics = Metrics::new();
+    let mut sm = MessageStateMachine::<StateAnswering>::new();
+    sm.set_channel_filter(ChannelFilter::RelevantTo(vec![Channel::Client(0x10)]));
+    sm.set_cts_metrics(CtsMetrics::with_registry(&metrics, "test"));
+    let mut writer = FramedWriter::new(Vec::new());
+    let logger = MessageLogger::new("test");
+
+    for channel in [0x10, 0x11, 0x10, 0x10] {
+      sm.handle_message(&mut writer, &logger, &Channel::Client(channel), &MessageType::ClearToSend())?;
+    }
+
+    let snapshot = metrics.snapshot();
+    let counter = |name: &str| snapshot.get(&format!("test.cts.{name}")).cloned();
+    assert_eq!(counter("received"), Some(MetricValue::Counter(3)));
+    assert_eq!(counter("nothing_to_send"), Some(MetricValue::Counter(1)));
+    assert_eq!(counter("replied"), Some(MetricValue::Counter(1)));
+    assert_eq!(counter("missed"), Some(MetricValue::Counter(1)));
+    assert!(matches!(counter("reply_latency_us"), Some(MetricValue::Histogram(h)) if h.count == 2));
+    Ok(())
+  }
+}
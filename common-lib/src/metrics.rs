@@ -0,0 +1,263 @@
+//! Lightweight counters, gauges and histograms shared across the whole process so that
+//! diagnostics (the Wi-Fi module's HTTP endpoint, the topside panel's diagnostics screen, etc)
+//! can render everything from a single [Metrics::snapshot].
+//!
+//! Handles are cheap to clone and update without touching the registry, so hot paths should
+//! look them up once and hold on to them.  Values are pointer sized as the ESP32 has no 64-bit
+//! atomics; counters simply wrap if they ever get that far.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+
+lazy_static! {
+  static ref GLOBAL_METRICS: Metrics = Metrics::new();
+}
+
+/// Registry of named metrics.  Clones share the same metrics.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+  inner: Arc<Mutex<BTreeMap<String, Metric>>>,
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+  Counter(Counter),
+  Gauge(Gauge),
+  Histogram(Histogram),
+}
+
+impl Metrics {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Registry used by everything in this workspace.
+  pub fn global() -> &'static Metrics {
+    &GLOBAL_METRICS
+  }
+
+  /// Look up or register the counter `name`.
+  ///
+  /// Panics if `name` is already registered as a different type of metric.
+  pub fn counter(&self, name: &str) -> Counter {
+    match self.get_or_insert(name, || Metric::Counter(Default::default())) {
+      Metric::Counter(c) => c,
+      other => panic!("{name} is already registered as {other:?}"),
+    }
+  }
+
+  /// Look up or register the gauge `name`, with the same caveat as [Self::counter].
+  pub fn gauge(&self, name: &str) -> Gauge {
+    match self.get_or_insert(name, || Metric::Gauge(Default::default())) {
+      Metric::Gauge(g) => g,
+      other => panic!("{name} is already registered as {other:?}"),
+    }
+  }
+
+  /// Look up or register the histogram `name`, with the same caveat as [Self::counter].
+  pub fn histogram(&self, name: &str) -> Histogram {
+    match self.get_or_insert(name, || Metric::Histogram(Default::default())) {
+      Metric::Histogram(h) => h,
+      other => panic!("{name} is already registered as {other:?}"),
+    }
+  }
+
+  fn get_or_insert(&self, name: &str, create: impl FnOnce() -> Metric) -> Metric {
+    let mut inner = self.inner.lock().unwrap();
+    if let Some(metric) = inner.get(name) {
+      return metric.clone();
+    }
+    let metric = create();
+    inner.insert(name.to_owned(), metric.clone());
+    metric
+  }
+
+  /// Point in time copy of every registered metric, ordered by name.
+  pub fn snapshot(&self) -> MetricsSnapshot {
+    let inner = self.inner.lock().unwrap();
+    let values = inner.iter()
+        .map(|(name, metric)| {
+          let value = match metric {
+            Metric::Counter(c) => MetricValue::Counter(c.get()),
+            Metric::Gauge(g) => MetricValue::Gauge(g.get()),
+            Metric::Histogram(h) => MetricValue::Histogram(h.summary()),
+          };
+          (name.clone(), value)
+        })
+        .collect();
+    MetricsSnapshot { values }
+  }
+}
+
+/// Monotonically increasing count of events.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicUsize>);
+
+impl Counter {
+  pub fn inc(&self) {
+    self.add(1);
+  }
+
+  pub fn add(&self, n: usize) {
+    self.0.fetch_add(n, Ordering::Relaxed);
+  }
+
+  pub fn get(&self) -> usize {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+/// Value that can go up and down, such as a queue depth or number of connections.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicIsize>);
+
+impl Gauge {
+  pub fn set(&self, value: isize) {
+    self.0.store(value, Ordering::Relaxed);
+  }
+
+  pub fn add(&self, delta: isize) {
+    self.0.fetch_add(delta, Ordering::Relaxed);
+  }
+
+  pub fn get(&self) -> isize {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+/// Distribution of observed values such as latencies.  Only keeps a running summary, not the
+/// individual samples.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram(Arc<Mutex<HistogramSummary>>);
+
+impl Histogram {
+  pub fn record(&self, value: u64) {
+    let mut summary = self.0.lock().unwrap();
+    summary.min = if summary.count == 0 { value } else { summary.min.min(value) };
+    summary.max = summary.max.max(value);
+    summary.count += 1;
+    summary.sum = summary.sum.saturating_add(value);
+  }
+
+  pub fn summary(&self) -> HistogramSummary {
+    self.0.lock().unwrap().clone()
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistogramSummary {
+  pub count: u64,
+  pub sum: u64,
+  pub min: u64,
+  pub max: u64,
+}
+
+impl HistogramSummary {
+  pub fn mean(&self) -> Option<u64> {
+    self.sum.checked_div(self.count)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetricValue {
+  Counter(usize),
+  Gauge(isize),
+  Histogram(HistogramSummary),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+  pub values: BTreeMap<String, MetricValue>,
+}
+
+impl MetricsSnapshot {
+  pub fn get(&self, name: &str) -> Option<&MetricValue> {
+    self.values.get(name)
+  }
+
+  /// Prometheus text exposition format, with each name prefixed by `prefix` and its dots turned
+  /// into underscores, e.g. `balboa_bus_dropped_segments`.  Histograms become summaries (count
+  /// and sum) plus `_min` and `_max` gauges.
+  pub fn to_prometheus(&self, prefix: &str) -> String {
+    let mut out = String::new();
+    for (name, value) in &self.values {
+      let name = format!("{prefix}{}", name.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+      match value {
+        MetricValue::Counter(v) => {
+          out.push_str(&format!("# TYPE {name} counter\n{name} {v}\n"));
+        }
+        MetricValue::Gauge(v) => {
+          out.push_str(&format!("# TYPE {name} gauge\n{name} {v}\n"));
+        }
+        MetricValue::Histogram(h) => {
+          out.push_str(&format!(
+            "# TYPE {name} summary\n{name}_count {}\n{name}_sum {}\n",
+            h.count,
+            h.sum));
+          out.push_str(&format!("# TYPE {name}_min gauge\n{name}_min {}\n", h.min));
+          out.push_str(&format!("# TYPE {name}_max gauge\n{name}_max {}\n", h.max));
+        }
+      }
+    }
+    out
+  }
+}
+
+/// One metric per line, e.g. `bus.dropped_segments 3`, suitable for plain text endpoints and
+/// simple diagnostics screens.
+impl Display for MetricsSnapshot {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    for (name, value) in &self.values {
+      match value {
+        MetricValue::Counter(v) => writeln!(f, "{name} {v}")?,
+        MetricValue::Gauge(v) => writeln!(f, "{name} {v}")?,
+        MetricValue::Histogram(h) => {
+          writeln!(
+            f,
+            "{name} count={} min={} max={} mean={}",
+            h.count,
+            h.min,
+            h.max,
+            h.mean().unwrap_or_default())?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_snapshot() {
+    let metrics = Metrics::new();
+    metrics.counter("a.count").inc();
+    metrics.counter("a.count").add(2);
+    metrics.gauge("b.depth").set(5);
+    metrics.gauge("b.depth").add(-1);
+    let latency = metrics.histogram("c.latency");
+    latency.record(10);
+    latency.record(30);
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.get("a.count"), Some(&MetricValue::Counter(3)));
+    assert_eq!(snapshot.get("b.depth"), Some(&MetricValue::Gauge(4)));
+    assert_eq!(
+      snapshot.get("c.latency"),
+      Some(&MetricValue::Histogram(HistogramSummary { count: 2, sum: 40, min: 10, max: 30 })));
+    assert_eq!(
+      snapshot.to_string(),
+      "a.count 3\nb.depth 4\nc.latency count=2 min=10 max=30 mean=20\n");
+
+    let prometheus = snapshot.to_prometheus("spa_");
+    assert!(prometheus.starts_with("# TYPE spa_a_count counter\nspa_a_count 3\n"));
+    assert!(prometheus.contains("# TYPE spa_b_depth gauge\nspa_b_depth 4\n"));
+    assert!(prometheus.contains("spa_c_latency_count 2\nspa_c_latency_sum 40\n"));
+    assert!(prometheus.contains("spa_c_latency_max 30\n"));
+  }
+}
@@ -0,0 +1,158 @@
+//! Transport over a pseudo-terminal, for wiring external tools that expect a serial device (the
+//! BWA ruby app, socat, minicom, etc) against our implementations for cross-implementation
+//! compatibility testing.  Unix only.
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use log::info;
+
+use crate::transport::Transport;
+
+#[derive(Debug)]
+pub struct PtyTransport {
+  reader: PtyReader,
+  writer: File,
+  peer_path: Option<PathBuf>,
+}
+
+impl PtyTransport {
+  /// Allocate a new pseudo-terminal pair and hold the master side.  External tools should open
+  /// [Self::peer_path].
+  pub fn create() -> io::Result<Self> {
+    // SAFETY: plain libc calls, the fd is immediately owned by the File.
+    let master = unsafe {
+      let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+      if fd < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      File::from_raw_fd(fd)
+    };
+    let fd = master.as_raw_fd();
+    check(unsafe { libc::grantpt(fd) })?;
+    check(unsafe { libc::unlockpt(fd) })?;
+    let peer_path = peer_name(fd)?;
+
+    // The master side doesn't have line discipline of its own but the peer does, and we must
+    // stop it from mangling our binary frames before anyone opens it.
+    let peer = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&peer_path)?;
+    make_raw(peer.as_raw_fd())?;
+
+    info!("Created pty, peer at {}", peer_path.display());
+    let writer = master.try_clone()?;
+    Ok(Self {
+      reader: PtyReader { file: master, _peer: Some(peer) },
+      writer,
+      peer_path: Some(peer_path),
+    })
+  }
+
+  /// Attach to an existing terminal device, such as one end of a `socat -d -d pty,raw,echo=0
+  /// pty,raw,echo=0` pair.
+  pub fn attach(path: impl AsRef<Path>) -> io::Result<Self> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path.as_ref())?;
+    make_raw(file.as_raw_fd())?;
+    let writer = file.try_clone()?;
+    Ok(Self {
+      reader: PtyReader { file, _peer: None },
+      writer,
+      peer_path: None,
+    })
+  }
+
+  /// Device path the external tool should open, if we created the pair.
+  pub fn peer_path(&self) -> Option<&Path> {
+    self.peer_path.as_deref()
+  }
+}
+
+impl Transport<PtyReader, File> for PtyTransport {
+  fn split(self) -> (PtyReader, File) {
+    (self.reader, self.writer)
+  }
+}
+
+#[derive(Debug)]
+pub struct PtyReader {
+  file: File,
+
+  /// Our own handle to the peer side, if we created the pair.  Reading from the master fails
+  /// with EIO whenever nothing has the peer open, so holding it lets external tools come and go
+  /// (e.g. restarting between test runs) without taking us down.
+  _peer: Option<File>,
+}
+
+impl Read for PtyReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.file.read(buf)
+  }
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+  if ret < 0 {
+    Err(io::Error::last_os_error())
+  } else {
+    Ok(())
+  }
+}
+
+fn peer_name(fd: RawFd) -> io::Result<PathBuf> {
+  // SAFETY: ptsname returns a pointer to static storage which we copy out immediately.  Not
+  // thread safe, but ptsname_r isn't available on macOS.
+  let name = unsafe {
+    let ptr = libc::ptsname(fd);
+    if ptr.is_null() {
+      return Err(io::Error::last_os_error());
+    }
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+  };
+  Ok(PathBuf::from(name))
+}
+
+fn make_raw(fd: RawFd) -> io::Result<()> {
+  // SAFETY: termios is plain old data and fully initialized by tcgetattr.
+  unsafe {
+    let mut termios = std::mem::zeroed::<libc::termios>();
+    check(libc::tcgetattr(fd, &mut termios))?;
+    libc::cfmakeraw(&mut termios);
+    check(libc::tcsetattr(fd, libc::TCSANOW, &termios))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Read, Write};
+  use super::*;
+
+  #[test]
+  fn test_round_trip() -> anyhow::Result<()> {
+    let pty = PtyTransport::create()?;
+    let peer_path = pty.peer_path().unwrap().to_owned();
+    let (mut master_rx, mut master_tx) = pty.split();
+    let (mut peer_rx, mut peer_tx) = PtyTransport::attach(&peer_path)?.split();
+
+    // Bytes that a cooked terminal would translate or swallow.
+    let data = [0x7e, 0x05, 0x0a, 0x0d, 0x03, 0x7e];
+    master_tx.write_all(&data)?;
+    let mut received = [0u8; 6];
+    peer_rx.read_exact(&mut received)?;
+    assert_eq!(received, data);
+
+    peer_tx.write_all(&data)?;
+    master_rx.read_exact(&mut received)?;
+    assert_eq!(received, data);
+    Ok(())
+  }
+}
@@ -0,0 +1,288 @@
+//! Correlates requests we send to the main board with the responses that eventually come back,
+//! retrying with backoff when they don't.  The protocol has no request ids, so responses are
+//! matched by type (and content where it matters) against the oldest outstanding request.
+//!
+//! The tracker doesn't send anything itself: callers queue the request as usual, hand it to
+//! [RequestTracker::track], feed every inbound message to [RequestTracker::on_message], and call
+//! [RequestTracker::poll] regularly to learn what needs resending.
+
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::time::{Duration, Instant};
+use balboa_spa_messages::message_types::{MessageType, SettingsRequestMessage};
+
+const DEFAULT_INITIAL_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_BACKOFF_FACTOR: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+  /// How long to wait for a response to the first attempt.
+  pub initial_timeout: Duration,
+
+  /// Total number of attempts, including the first, before giving up.
+  pub max_attempts: u32,
+
+  /// Each retry waits this many times longer than the previous attempt.
+  pub backoff_factor: u32,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      initial_timeout: DEFAULT_INITIAL_TIMEOUT,
+      max_attempts: DEFAULT_MAX_ATTEMPTS,
+      backoff_factor: DEFAULT_BACKOFF_FACTOR,
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(u64);
+
+/// Decides whether an inbound message answers a request.
+pub type ResponseMatcher = Box<dyn Fn(&MessageType) -> bool + Send>;
+
+#[derive(Debug)]
+pub enum RequestEvent {
+  /// The response arrived.
+  Completed {
+    id: RequestId,
+    request: MessageType,
+    response: MessageType,
+    attempts: u32,
+    elapsed: Duration,
+  },
+
+  /// No response in time, the caller should send `request` again.
+  Retry {
+    id: RequestId,
+    request: MessageType,
+    attempt: u32,
+  },
+
+  /// Out of attempts, the request has been forgotten.
+  TimedOut {
+    id: RequestId,
+    request: MessageType,
+    attempts: u32,
+  },
+}
+
+struct PendingRequest {
+  id: RequestId,
+  request: MessageType,
+  matcher: ResponseMatcher,
+  first_sent_at: Instant,
+  deadline: Instant,
+  attempts: u32,
+  timeout: Duration,
+}
+
+impl Debug for PendingRequest {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PendingRequest")
+        .field("id", &self.id)
+        .field("request", &self.request)
+        .field("deadline", &self.deadline)
+        .field("attempts", &self.attempts)
+        .finish_non_exhaustive()
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct RequestTracker {
+  policy: RetryPolicy,
+  pending: VecDeque<PendingRequest>,
+  next_id: u64,
+}
+
+impl RequestTracker {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn set_retry_policy(mut self, policy: RetryPolicy) -> Self {
+    self.policy = policy;
+    self
+  }
+
+  /// Start tracking `request` using [default_matcher], returning None for requests that have no
+  /// identifiable response.
+  pub fn track(&mut self, request: MessageType, now: Instant) -> Option<RequestId> {
+    let matcher = default_matcher(&request)?;
+    Some(self.track_with(request, matcher, now))
+  }
+
+  pub fn track_with(
+      &mut self,
+      request: MessageType,
+      matcher: ResponseMatcher,
+      now: Instant,
+  ) -> RequestId {
+    let id = RequestId(self.next_id);
+    self.next_id += 1;
+    let timeout = self.policy.initial_timeout;
+    self.pending.push_back(PendingRequest {
+      id,
+      request,
+      matcher,
+      first_sent_at: now,
+      deadline: now + timeout,
+      attempts: 1,
+      timeout,
+    });
+    id
+  }
+
+  /// Complete the oldest pending request that `mt` answers, if any.
+  pub fn on_message(&mut self, mt: &MessageType, now: Instant) -> Option<RequestEvent> {
+    let index = self.pending.iter().position(|p| (p.matcher)(mt))?;
+    let pending = self.pending.remove(index)?;
+    Some(RequestEvent::Completed {
+      id: pending.id,
+      request: pending.request,
+      response: mt.clone(),
+      attempts: pending.attempts,
+      elapsed: now.saturating_duration_since(pending.first_sent_at),
+    })
+  }
+
+  /// Retry or give up on requests whose deadline has passed.
+  pub fn poll(&mut self, now: Instant) -> Vec<RequestEvent> {
+    let mut events = vec![];
+    let mut still_pending = VecDeque::with_capacity(self.pending.len());
+    for mut pending in self.pending.drain(..) {
+      if now < pending.deadline {
+        still_pending.push_back(pending);
+      } else if pending.attempts >= self.policy.max_attempts {
+        events.push(RequestEvent::TimedOut {
+          id: pending.id,
+          request: pending.request,
+          attempts: pending.attempts,
+        });
+      } else {
+        pending.attempts += 1;
+        pending.timeout *= self.policy.backoff_factor;
+        pending.deadline = now + pending.timeout;
+        events.push(RequestEvent::Retry {
+          id: pending.id,
+          request: pending.request.clone(),
+          attempt: pending.attempts,
+        });
+        still_pending.push_back(pending);
+      }
+    }
+    self.pending = still_pending;
+    events
+  }
+
+  /// Forget pending requests that a newer one has made moot, such as an earlier set point while
+  /// the user is still adjusting it, so they don't get retried over the top of it.
+  pub fn cancel_where(&mut self, predicate: impl Fn(&MessageType) -> bool) {
+    self.pending.retain(|p| !predicate(&p.request));
+  }
+
+  pub fn pending_count(&self) -> usize {
+    self.pending.len()
+  }
+}
+
+/// Response matching for the requests we know how to correlate.
+pub fn default_matcher(request: &MessageType) -> Option<ResponseMatcher> {
+  let matcher: ResponseMatcher = match request {
+    MessageType::SettingsRequest(SettingsRequestMessage::Information) => {
+      Box::new(|mt| matches!(mt, MessageType::InformationResponse(_)))
+    }
+    MessageType::SettingsRequest(SettingsRequestMessage::Settings0x04) => {
+      Box::new(|mt| matches!(mt, MessageType::Settings0x04Response(_)))
+    }
+    MessageType::SettingsRequest(SettingsRequestMessage::Configuration) => {
+      Box::new(|mt| matches!(mt, MessageType::ConfigurationResponse(_)))
+    }
+    MessageType::SettingsRequest(SettingsRequestMessage::Preferences) => {
+      Box::new(|mt| matches!(mt, MessageType::PreferencesResponse(_)))
+    }
+    MessageType::SettingsRequest(SettingsRequestMessage::FilterCycles) => {
+      Box::new(|mt| matches!(mt, MessageType::FilterCycles { .. }))
+    }
+    MessageType::SettingsRequest(SettingsRequestMessage::FaultLog { .. }) => {
+      Box::new(|mt| matches!(mt, MessageType::FaultLogResponse(_)))
+    }
+    MessageType::SettingsRequest(SettingsRequestMessage::GfciTest) => {
+      Box::new(|mt| matches!(mt, MessageType::GfciTestResponse { .. }))
+    }
+    MessageType::SetTemperatureRequest { temperature } => {
+      // Acknowledged implicitly by the next status update reflecting the new set point.
+      let raw_value = temperature.raw_value();
+      Box::new(move |mt| matches!(
+          mt,
+          MessageType::StatusUpdate(m) if m.v1.set_temperature.raw_value() == raw_value))
+    }
+    _ => return None,
+  };
+  Some(matcher)
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::message_types::GfciTestResult;
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use super::*;
+
+  #[test]
+  fn test_retry_backoff_and_complete() {
+    let mut tracker = RequestTracker::new();
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    let request = MessageType::SettingsRequest(SettingsRequestMessage::GfciTest);
+    let id = tracker.track(request, start).unwrap();
+
+    assert!(tracker.poll(at(0)).is_empty());
+    assert!(matches!(tracker.poll(at(1)).as_slice(), [RequestEvent::Retry { attempt: 2, .. }]));
+
+    // Backed off to 2s, and unrelated messages don't complete it.
+    assert!(tracker.poll(at(2)).is_empty());
+    assert!(tracker.on_message(&MessageType::ClearToSend(), at(2)).is_none());
+
+    let response = MessageType::GfciTestResponse { result: ParsedEnum::new(GfciTestResult::Pass) };
+    match tracker.on_message(&response, at(2)) {
+      Some(RequestEvent::Completed { id: completed, attempts: 2, elapsed, .. }) => {
+        assert_eq!(completed, id);
+        assert_eq!(elapsed, Duration::from_secs(2));
+      }
+      other => panic!("Unexpected {other:?}"),
+    }
+    assert_eq!(tracker.pending_count(), 0);
+  }
+
+  #[test]
+  fn test_gives_up() {
+    let mut tracker = RequestTracker::new().set_retry_policy(RetryPolicy {
+      initial_timeout: Duration::from_secs(1),
+      max_attempts: 1,
+      backoff_factor: 2,
+    });
+    let start = Instant::now();
+    tracker.track(MessageType::SettingsRequest(SettingsRequestMessage::Information), start);
+    assert!(matches!(
+        tracker.poll(start + Duration::from_secs(1)).as_slice(),
+        [RequestEvent::TimedOut { attempts: 1, .. }]));
+    assert_eq!(tracker.pending_count(), 0);
+  }
+
+  #[test]
+  fn test_cancel_where() {
+    let mut tracker = RequestTracker::new();
+    let start = Instant::now();
+    tracker.track(MessageType::SettingsRequest(SettingsRequestMessage::Information), start);
+    tracker.track(MessageType::SettingsRequest(SettingsRequestMessage::GfciTest), start);
+    tracker.cancel_where(|mt| {
+      matches!(mt, MessageType::SettingsRequest(SettingsRequestMessage::GfciTest))
+    });
+    assert_eq!(tracker.pending_count(), 1);
+    assert!(matches!(
+        tracker.poll(start + Duration::from_secs(1)).as_slice(),
+        [RequestEvent::Retry { request: MessageType::SettingsRequest(SettingsRequestMessage::Information), .. }]));
+  }
+}
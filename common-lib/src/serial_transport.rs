@@ -0,0 +1,75 @@
+//! Transport over a host serial port, typically a USB RS485 dongle wired to a real spa's bus.
+//! Only available with the `serial` feature as it pulls in platform specific dependencies that
+//! make no sense on the ESP32 (which has `EspUartTransport` instead).
+
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::time::Duration;
+use log::info;
+use serialport::SerialPort;
+
+use crate::transport::Transport;
+
+/// Fixed by the Balboa protocol, 8N1.
+pub const BALBOA_BAUD_RATE: u32 = 115200;
+
+/// How long a single read waits before we quietly try again.  The value hardly matters since
+/// [SerialRx] hides the timeouts, it just bounds how long a read can block internally.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct SerialTransport {
+  reader: SerialRx,
+  writer: SerialTx,
+}
+
+impl SerialTransport {
+  /// Open the serial port at `path` (e.g. `/dev/ttyUSB0` or `COM3`) at the bus baud rate.
+  pub fn open(path: &str) -> io::Result<Self> {
+    let port = serialport::new(path, BALBOA_BAUD_RATE)
+        .timeout(READ_TIMEOUT)
+        .open()?;
+    info!("Opened serial port {path}");
+    Self::from_port(port)
+  }
+
+  pub fn from_port(port: Box<dyn SerialPort>) -> io::Result<Self> {
+    let writer = port.try_clone()?;
+    Ok(Self {
+      reader: SerialRx(port),
+      writer: SerialTx(writer),
+    })
+  }
+}
+
+impl Transport<SerialRx, SerialTx> for SerialTransport {
+  fn split(self) -> (SerialRx, SerialTx) {
+    (self.reader, self.writer)
+  }
+}
+
+/// Read half of the port which blocks until data arrives rather than surfacing the driver's
+/// timeouts, matching what the framed readers expect of a stream.
+pub struct SerialRx(Box<dyn SerialPort>);
+
+impl Read for SerialRx {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+      match self.0.read(buf) {
+        Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+        result => return result,
+      }
+    }
+  }
+}
+
+pub struct SerialTx(Box<dyn SerialPort>);
+
+impl Write for SerialTx {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.0.flush()
+  }
+}
@@ -0,0 +1,79 @@
+//! Transport over a TCP connection, useful for attaching to a remote bus bridge (e.g. an
+//! RS485-to-TCP adapter or another instance of this project re-exporting its serial line) without
+//! custom glue in every binary.
+
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use log::info;
+
+use crate::transport::Transport;
+
+#[derive(Debug)]
+pub struct TcpTransport {
+  reader: TcpStream,
+  writer: TcpStream,
+}
+
+impl TcpTransport {
+  /// Connect to a remote bus bridge.
+  pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+    Self::from_stream(TcpStream::connect(addr)?)
+  }
+
+  /// Bind to `addr` and wait for a single peer to connect.
+  pub fn listen(addr: impl ToSocketAddrs) -> io::Result<Self> {
+    Self::accept(&TcpListener::bind(addr)?)
+  }
+
+  /// Wait for the next peer on an existing listener, for callers that need to know the bound
+  /// address up front (e.g. when binding to port 0) or want to accept more than once.
+  pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+    let (stream, peer) = listener.accept()?;
+    info!("Accepted bus connection from {peer}");
+    Self::from_stream(stream)
+  }
+
+  pub fn from_stream(stream: TcpStream) -> io::Result<Self> {
+    // Messages are tiny and latency sensitive given the bus timing, so don't let Nagle hold
+    // them back.
+    stream.set_nodelay(true)?;
+    let writer = stream.try_clone()?;
+    Ok(Self { reader: stream, writer })
+  }
+}
+
+impl Transport<TcpStream, TcpStream> for TcpTransport {
+  fn split(self) -> (TcpStream, TcpStream) {
+    (self.reader, self.writer)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Read, Write};
+  use std::thread;
+  use super::*;
+
+  #[test]
+  fn test_round_trip() -> anyhow::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let server = thread::spawn(move || -> io::Result<()> {
+      let (mut reader, mut writer) = TcpTransport::accept(&listener)?.split();
+      let mut buf = [0u8; 5];
+      reader.read_exact(&mut buf)?;
+      writer.write_all(&buf)?;
+      writer.flush()
+    });
+
+    let (mut reader, mut writer) = TcpTransport::connect(addr)?.split();
+    writer.write_all(b"hello")?;
+    writer.flush()?;
+    let mut echoed = [0u8; 5];
+    reader.read_exact(&mut echoed)?;
+    assert_eq!(&echoed, b"hello");
+
+    server.join().unwrap()?;
+    Ok(())
+  }
+}
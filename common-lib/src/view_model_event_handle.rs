@@ -1,4 +1,5 @@
-use std::sync::mpsc::{channel, Receiver, RecvError, Sender, TryRecvError};
+use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
+use std::time::Duration;
 
 pub struct ViewModelEventHandle<VM> {
   pub events_rx: Receiver<ViewEvent<VM>>,
@@ -21,6 +22,17 @@ impl<VM> ViewModelEventHandle<VM> {
     }
   }
 
+  /// Like [Self::recv_latest] but gives up after `timeout`, letting UI loops sleep until there's
+  /// something new to draw rather than polling with [Self::try_recv_latest].  Any updates queued
+  /// behind the first are coalesced into the newest one.
+  pub fn recv_latest_timeout(&self, timeout: Duration) -> Result<VM, RecvTimeoutError> {
+    let ViewEvent::ModelUpdated(first) = self.events_rx.recv_timeout(timeout)?;
+    match self.try_recv_latest() {
+      Ok(Some(latest)) => Ok(latest),
+      _ => Ok(first),
+    }
+  }
+
   pub fn try_recv_latest(&self) -> Result<Option<VM>, TryRecvError> {
     let mut latest = None;
     loop {
@@ -38,3 +50,23 @@ impl<VM> ViewModelEventHandle<VM> {
 pub enum ViewEvent<VM> {
   ModelUpdated(VM),
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_recv_latest_timeout() {
+    let (tx, handle) = ViewModelEventHandle::<u32>::new();
+    let timeout = Duration::from_millis(10);
+    assert_eq!(handle.recv_latest_timeout(timeout), Err(RecvTimeoutError::Timeout));
+
+    for i in 0..3 {
+      tx.send(ViewEvent::ModelUpdated(i)).unwrap();
+    }
+    assert_eq!(handle.recv_latest_timeout(timeout), Ok(2));
+
+    drop(tx);
+    assert_eq!(handle.recv_latest_timeout(timeout), Err(RecvTimeoutError::Disconnected));
+  }
+}
@@ -21,7 +21,8 @@ where
   fn set_brightness(&mut self, value: BacklightBrightness) {
     let state = match value {
       BacklightBrightness::Off => PinState::Low,
-      BacklightBrightness::FullOn => PinState::High,
+      // Just a plain GPIO, no PWM to dim with.
+      BacklightBrightness::Dimmed | BacklightBrightness::FullOn => PinState::High,
     };
     info!("Setting backlight to: {value:?}");
     if let Err(e) = self.pin.set_state(state) {
@@ -3,12 +3,16 @@ use std::thread;
 use std::time::Duration;
 use anyhow::anyhow;
 use common_lib::transport::Transport;
-use debounced_pin::{ActiveLow, Debounce, DebouncedInputPin, DebounceState};
 use display_interface_spi::SPIInterfaceNoCS;
 use embedded_hal::digital::v2::{InputPin, OutputPin, PinState};
+#[cfg(feature = "touch")]
+use embedded_graphics::geometry::Size;
 use embedded_hal::spi::MODE_0;
 use esp_idf_hal::delay::Ets;
 use esp_idf_hal::gpio::{AnyInputPin, AnyIOPin, AnyOutputPin, Gpio0, Input, IOPin, Output, PinDriver, Pull};
+#[cfg(feature = "touch")]
+use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
+use esp_idf_hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver};
 use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_hal::spi;
 use esp_idf_hal::spi::config::V02Type;
@@ -20,15 +24,41 @@ use esp_idf_svc::log::EspLogger;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use esp_idf_sys::esp_app_desc;
 use log::{error, info, LevelFilter};
+#[cfg(feature = "ota")]
+use log::warn;
 use mipidsi::{Builder, ColorOrder, Orientation};
 use topside_panel_lib::app::topside_panel_app::TopsidePanelApp;
 use topside_panel_lib::model::key_event::Key;
 use topside_panel_lib::view::lcd_device::{BacklightBrightness, BacklightControl};
+#[cfg(not(feature = "touch"))]
+use topside_panel_lib::view::touch_input::NoTouch;
 use wifi_module_lib::advertisement::Advertisement;
+use wifi_module_lib::frost::FrostConfig;
+#[cfg(feature = "http")]
+use wifi_module_lib::http_handler::DEFAULT_HTTP_PORT;
+#[cfg(feature = "mqtt")]
+use wifi_module_lib::mqtt::MqttBridge;
+use wifi_module_lib::settings::{Settings, TIMEZONE};
+#[cfg(feature = "mqtt")]
+use wifi_module_lib::settings::MQTT_BROKER;
+#[cfg(feature = "ota")]
+use wifi_module_lib::settings::OTA_TOKEN;
+use wifi_module_lib::time_sync::DEFAULT_SYNC_INTERVAL;
+#[cfg(feature = "mqtt")]
+use wifi_module_lib::wifi_manager::WifiManager;
 use esp_app::backlight_control::HalBacklightControl;
 use esp_app::esp_status_printer::EspStatusPrinter;
+#[cfg(feature = "mqtt")]
+use esp_app::esp_mqtt_client::EspMqttClientAdapter;
+#[cfg(feature = "ota")]
+use esp_app::esp_ota_target::EspOtaTarget;
 use esp_app::esp_uart_transport::EspUartTransport;
-use esp_app::membrane_switch;
+#[cfg(feature = "touch")]
+use esp_app::ft6236_touch::Ft6236Touch;
+use esp_app::nvs_assignment_store::NvsAssignmentStore;
+use esp_app::nvs_settings_store::NvsSettingsStore;
+use esp_app::pwm_buzzer::PwmBuzzer;
+use esp_app::sntp_clock::SntpLocalClock;
 use esp_app::membrane_switch::MembraneSwitchWindowProxy;
 use esp_app::ui_device::{EtsUiDelay, FreeRtosDelay, TftAndMembraneSwitchDevice};
 use esp_app::wifi::EspWifiManager;
@@ -37,6 +67,9 @@ esp_app_desc!();
 
 static LOGGER: EspLogger = EspLogger;
 
+/// Until somebody sets [TIMEZONE].
+const DEFAULT_TIMEZONE: &str = "UTC0";
+
 fn main() -> anyhow::Result<()> {
   esp_idf_sys::link_patches();
 
@@ -80,31 +113,126 @@ fn main() -> anyhow::Result<()> {
       .init(&mut Ets, Some(PinDriver::output(peripherals.pins.gpio18)?))
       .unwrap();
 
+  #[cfg(feature = "touch")]
+  let touch = {
+    info!("Initializing touch panel...");
+    // gpio20 is the console UART's RX, which nothing reads from anyway.
+    let i2c = I2cDriver::new(
+        peripherals.i2c0,
+        peripherals.pins.gpio19,
+        peripherals.pins.gpio20,
+        &I2cConfig::new().baudrate(400.kHz().into()))?;
+    // Portrait panel turned to match Orientation::Landscape(false) above.
+    Ft6236Touch::new(i2c, Size::new(240, 320))
+        .set_swap_xy(true)
+        .set_invert_y(true)
+  };
+  #[cfg(not(feature = "touch"))]
+  let touch = NoTouch;
+
+  #[cfg_attr(feature = "touch", allow(unused_mut))]
+  let mut key_map = vec![
+    (peripherals.pins.gpio2.downgrade(), Key::Up),
+    (peripherals.pins.gpio3.downgrade(), Key::Down),
+    (peripherals.pins.gpio10.downgrade(), Key::Jets1),
+    (peripherals.pins.gpio8.downgrade(), Key::Light),
+  ];
+  // Shares its pin with the touch panel, which can get to settings by holding Light instead.
+  #[cfg(not(feature = "touch"))]
+  key_map.push((peripherals.pins.gpio20.downgrade(), Key::Menu));
+
   info!("Setting up app...");
   let backlight_control = HalBacklightControl::new(PinDriver::output(peripherals.pins.gpio5)?);
   let lcd_device = TftAndMembraneSwitchDevice::new(
       display,
-      MembraneSwitchWindowProxy::new(vec![
-        (membrane_switch::debounced(peripherals.pins.gpio2.downgrade())?, Key::Up),
-        (membrane_switch::debounced(peripherals.pins.gpio3.downgrade())?, Key::Down),
-        (membrane_switch::debounced(peripherals.pins.gpio10.downgrade())?, Key::Jets1),
-        (membrane_switch::debounced(peripherals.pins.gpio8.downgrade())?, Key::Light),
-      ]),
-      backlight_control);
+      MembraneSwitchWindowProxy::new(key_map)?,
+      backlight_control,
+      touch);
 
   let nvs = EspDefaultNvsPartition::take()?;
+  let topside_store = NvsAssignmentStore::new(nvs.clone(), "topside_chan")?;
+  let wifi_store = NvsAssignmentStore::new(nvs.clone(), "wifi_chan")?;
+  let settings_store = NvsSettingsStore::new(nvs.clone())?;
+  let settings = Settings::new(Box::new(NvsSettingsStore::new(nvs.clone())?));
   let esp_wifi = EspWifiManager::new(
       peripherals.modem,
       event_loop,
       nvs,
       Advertisement::fake_balboa().name)?;
 
+  // Both need the network stack, which the Wi-Fi driver brings up, and then keep trying in the
+  // background until we're connected.
+  let timezone = settings.get(TIMEZONE)?.unwrap_or_else(|| DEFAULT_TIMEZONE.to_owned());
+  info!("Starting SNTP in {timezone}...");
+  let clock = SntpLocalClock::new(&timezone)?;
+
+  #[cfg(feature = "mqtt")]
+  let mqtt = match settings.get(MQTT_BROKER)? {
+    Some(broker) => {
+      info!("Starting MQTT client for {broker}...");
+      let device_id = format!(
+          "spa_{}",
+          esp_wifi.advertisement().mac_string().replace('-', "").to_lowercase());
+      let (client, incoming) = EspMqttClientAdapter::new(&broker, &device_id)?;
+      let bridge = MqttBridge::new(Box::new(client), settings.mqtt_config(&device_id)?);
+      Some((bridge, incoming))
+    }
+    None => None,
+  };
+
+  #[cfg(feature = "ota")]
+  let ota_token = settings.get(OTA_TOKEN)?;
+  #[cfg(feature = "ota")]
+  if ota_token.is_none() {
+    warn!("No {OTA_TOKEN} setting, firmware updates disabled");
+  }
+
   let topside_app = TopsidePanelApp::new(
       transport,
       lcd_device,
       Some(esp_wifi),
       FreeRtosDelay,
-      Some(EspStatusPrinter));
+      Some(EspStatusPrinter))
+      .set_assignment_stores(Box::new(topside_store), Box::new(wifi_store))
+      .set_settings_store(Box::new(settings_store))
+      .set_wifi_module_setup(move |wifi| {
+        #[allow(unused_mut)]
+        let mut wifi = wifi
+            .set_settings(settings)
+            .enable_time_sync(Box::new(clock.clone()), DEFAULT_SYNC_INTERVAL)
+            .enable_scheduler(Box::new(clock))
+            .enable_frost_protection(FrostConfig::new());
+        #[cfg(feature = "mqtt")]
+        if let Some((bridge, incoming)) = mqtt {
+          wifi = wifi.set_mqtt(bridge, incoming);
+        }
+        #[cfg(feature = "http")]
+        {
+          wifi = wifi.enable_http_api(DEFAULT_HTTP_PORT);
+        }
+        #[cfg(feature = "ota")]
+        if let Some(token) = ota_token {
+          wifi = wifi.enable_ota(Box::new(EspOtaTarget::new()), token, None);
+        }
+        #[cfg(feature = "tls")]
+        {
+          wifi = wifi.enable_tls();
+        }
+        wifi
+      });
+
+  #[cfg(feature = "buzzer")]
+  let topside_app = {
+    info!("Initializing buzzer...");
+    // Typical resonant frequency for small piezo buzzers.
+    // Leaked as the timer has to keep running for as long as the buzzer is around, which is
+    // for good.
+    let timer = Box::leak(Box::new(LedcTimerDriver::new(
+        peripherals.ledc.timer0,
+        &TimerConfig::new().frequency(2700.Hz().into()))?));
+    let driver = LedcDriver::new(peripherals.ledc.channel0, &*timer, peripherals.pins.gpio21)?;
+    topside_app.set_feedback_device(Box::new(PwmBuzzer::new(driver)?))
+  };
 
   info!("Starting app...");
   if let Err(e) = topside_app.run_loop() {
@@ -0,0 +1,95 @@
+//! ESP-IDF's BLE provisioning manager, offered alongside Wi-Fi Easy Connect for phones that
+//! have Espressif's "ESP BLE Provisioning" app but can't scan DPP codes.
+//!
+//! The provisioning manager applies (and persists) the credentials it receives itself, so once
+//! it reports a successful connection we simply restart and come back up as provisioned.  That
+//! avoids having to cancel the DPP listen, which esp-idf can't do cleanly.
+//!
+//! Requires Bluetooth in sdkconfig, see `sdkconfig.ble.defaults`.
+
+use std::ffi::{c_void, CString};
+use std::ptr;
+use esp_idf_sys::*;
+use log::{info, warn};
+use wifi_module_lib::advertisement::Advertisement;
+use wifi_module_lib::wifi_manager::BleProvisioningParams;
+
+/// Stops provisioning when dropped, e.g. because DPP won the race.
+pub struct BleProvisioner {
+  params: BleProvisioningParams,
+}
+
+impl BleProvisioner {
+  /// Must be called after the Wi-Fi driver has started.
+  pub fn start(advertisement: &Advertisement) -> Result<Self, EspError> {
+    let config = wifi_prov_mgr_config_t {
+      scheme: unsafe { wifi_prov_scheme_ble },
+      scheme_event_handler: wifi_prov_event_handler_t {
+        event_cb: Some(wifi_prov_scheme_ble_event_cb_free_btdm),
+        user_data: ptr::null_mut(),
+      },
+      app_event_handler: wifi_prov_event_handler_t {
+        event_cb: Some(handle_prov_event),
+        user_data: ptr::null_mut(),
+      },
+    };
+    esp!(unsafe { wifi_prov_mgr_init(config) })?;
+
+    let mac = advertisement.mac;
+    let device_name = format!("PROV_{:02X}{:02X}{:02X}", mac[3], mac[4], mac[5]);
+    let proof_of_possession = format!("{:08x}", unsafe { esp_random() });
+    let params = BleProvisioningParams { device_name, proof_of_possession };
+
+    let service_name = CString::new(params.device_name.as_str()).unwrap();
+    let pop = CString::new(params.proof_of_possession.as_str()).unwrap();
+    let result = esp!(unsafe {
+      wifi_prov_mgr_start_provisioning(
+          wifi_prov_security_WIFI_PROV_SECURITY_1,
+          pop.as_ptr() as *const c_void,
+          service_name.as_ptr(),
+          ptr::null())
+    });
+    if let Err(e) = result {
+      unsafe { wifi_prov_mgr_deinit() };
+      return Err(e);
+    }
+    info!("BLE provisioning started as {}", params.device_name);
+
+    Ok(Self { params })
+  }
+
+  pub fn params(&self) -> &BleProvisioningParams {
+    &self.params
+  }
+}
+
+impl Drop for BleProvisioner {
+  fn drop(&mut self) {
+    unsafe {
+      wifi_prov_mgr_stop_provisioning();
+      wifi_prov_mgr_deinit();
+    }
+  }
+}
+
+unsafe extern "C" fn handle_prov_event(
+    _user_data: *mut c_void,
+    event: wifi_prov_cb_event_t,
+    _event_data: *mut c_void,
+) {
+  #[allow(non_upper_case_globals)]
+  match event {
+    wifi_prov_cb_event_t_WIFI_PROV_CRED_RECV => {
+      info!("Received Wi-Fi credentials over BLE, connecting...");
+    }
+    wifi_prov_cb_event_t_WIFI_PROV_CRED_FAIL => {
+      warn!("BLE provisioned credentials didn't work, waiting for another try...");
+      wifi_prov_mgr_reset_sm_state_on_failure();
+    }
+    wifi_prov_cb_event_t_WIFI_PROV_CRED_SUCCESS => {
+      info!("BLE provisioning succeeded, restarting...");
+      esp_restart();
+    }
+    _ => {}
+  }
+}
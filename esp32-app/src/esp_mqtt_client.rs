@@ -0,0 +1,64 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::mpsc::{channel, Receiver};
+use embedded_svc::mqtt::client::{Client, Details, Event, Message, Publish, QoS};
+use esp_idf_svc::mqtt::client::{EspMqttClient, MqttClientConfiguration};
+use log::{info, warn};
+use wifi_module_lib::mqtt::{MqttClient, MqttIncoming};
+
+/// [MqttClient] on top of ESP-IDF's, which connects and reconnects by itself in the background.
+pub struct EspMqttClientAdapter {
+  client: EspMqttClient,
+}
+
+impl EspMqttClientAdapter {
+  /// Also returns what arrives on the topics the bridge subscribes to, for
+  /// [wifi_module_lib::wifi_module_client::WifiModuleClient::set_mqtt].
+  pub fn new(broker_url: &str, client_id: &str) -> anyhow::Result<(Self, Receiver<MqttIncoming>)> {
+    let (incoming_tx, incoming_rx) = channel();
+    let config = MqttClientConfiguration {
+      client_id: Some(client_id),
+      ..Default::default()
+    };
+    let client = EspMqttClient::new(broker_url, &config, move |event| {
+      match event {
+        Ok(Event::Connected(_)) => info!("MQTT connected"),
+        Ok(Event::Disconnected) => warn!("MQTT disconnected"),
+        Ok(Event::Received(message)) => {
+          // Our command payloads are tiny, so anything chunked isn't for us.
+          if !matches!(message.details(), Details::Complete) {
+            return;
+          }
+          let Some(topic) = message.topic() else {
+            return;
+          };
+          let _ = incoming_tx.send(MqttIncoming {
+            topic: topic.to_string(),
+            payload: message.data().to_vec(),
+          });
+        }
+        Ok(_) => {}
+        Err(e) => warn!("MQTT error: {e}"),
+      }
+    })?;
+    Ok((Self { client }, incoming_rx))
+  }
+}
+
+impl MqttClient for EspMqttClientAdapter {
+  fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> anyhow::Result<()> {
+    self.client.publish(topic, QoS::AtMostOnce, retain, payload)?;
+    Ok(())
+  }
+
+  fn subscribe(&mut self, topic: &str) -> anyhow::Result<()> {
+    self.client.subscribe(topic, QoS::AtMostOnce)?;
+    Ok(())
+  }
+}
+
+impl Debug for EspMqttClientAdapter {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("EspMqttClientAdapter")
+        .finish_non_exhaustive()
+  }
+}
@@ -0,0 +1,73 @@
+use std::ffi::c_void;
+use std::fmt::{Debug, Formatter};
+use std::ptr;
+use anyhow::anyhow;
+use esp_idf_sys::*;
+use wifi_module_lib::ota::OtaTarget;
+
+/// Writes images to the next OTA app partition, which needs a partition table with two of
+/// them (see sdkconfig.ota.defaults).
+pub struct EspOtaTarget {
+  update: Option<(esp_ota_handle_t, *const esp_partition_t)>,
+}
+
+// The partition pointer refers to the static partition table.
+unsafe impl Send for EspOtaTarget {}
+
+impl EspOtaTarget {
+  pub fn new() -> Self {
+    Self { update: None }
+  }
+}
+
+impl Default for EspOtaTarget {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl OtaTarget for EspOtaTarget {
+  fn begin(&mut self, size: Option<usize>) -> anyhow::Result<()> {
+    let partition = unsafe { esp_ota_get_next_update_partition(ptr::null()) };
+    if partition.is_null() {
+      return Err(anyhow!("No OTA partition to update"));
+    }
+    let size = size.unwrap_or(OTA_SIZE_UNKNOWN as usize);
+    let mut handle: esp_ota_handle_t = 0;
+    esp!(unsafe { esp_ota_begin(partition, size, &mut handle) })?;
+    self.update = Some((handle, partition));
+    Ok(())
+  }
+
+  fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+    let (handle, _) = self.update.ok_or_else(|| anyhow!("Not started"))?;
+    esp!(unsafe { esp_ota_write(handle, data.as_ptr() as *const c_void, data.len()) })?;
+    Ok(())
+  }
+
+  fn finish(&mut self) -> anyhow::Result<()> {
+    let (handle, partition) = self.update.take().ok_or_else(|| anyhow!("Not started"))?;
+    // Validates the image, so a truncated or corrupt one never becomes bootable.
+    esp!(unsafe { esp_ota_end(handle) })?;
+    esp!(unsafe { esp_ota_set_boot_partition(partition) })?;
+    Ok(())
+  }
+
+  fn abort(&mut self) {
+    if let Some((handle, _)) = self.update.take() {
+      unsafe { esp_ota_abort(handle) };
+    }
+  }
+
+  fn restart(&mut self) {
+    unsafe { esp_restart() };
+  }
+}
+
+impl Debug for EspOtaTarget {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("EspOtaTarget")
+        .field("in_progress", &self.update.is_some())
+        .finish()
+  }
+}
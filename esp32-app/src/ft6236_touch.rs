@@ -0,0 +1,98 @@
+use embedded_graphics::geometry::{Point, Size};
+use embedded_hal::blocking::i2c::WriteRead;
+use std::fmt::Display;
+use log::warn;
+use topside_panel_lib::view::touch_input::TouchInput;
+
+/// Fixed for the whole FT6x36 family.
+const FT6236_ADDRESS: u8 = 0x38;
+
+/// TD_STATUS, immediately followed by the first touch point's P1_XH, P1_XL, P1_YH and P1_YL.
+const REG_TD_STATUS: u8 = 0x02;
+
+/// The chip can track two points, anything more is garbage (it reads 0xf right after reset).
+const MAX_TOUCHES: u8 = 2;
+
+/// Polled driver for the FT6236 capacitive touch controller found on many small ILI9341
+/// modules.  The controller reports in the panel's native portrait orientation, use the
+/// `set_*` methods to line that up with the display's orientation.
+pub struct Ft6236Touch<I2C> {
+  i2c: I2C,
+  native_size: Size,
+  swap_xy: bool,
+  invert_x: bool,
+  invert_y: bool,
+  logged_error: bool,
+}
+
+impl<I2C> Ft6236Touch<I2C>
+where
+    I2C: WriteRead,
+    I2C::Error: Display,
+{
+  pub fn new(i2c: I2C, native_size: Size) -> Self {
+    Self {
+      i2c,
+      native_size,
+      swap_xy: false,
+      invert_x: false,
+      invert_y: false,
+      logged_error: false,
+    }
+  }
+
+  /// Swap the axes, for example when the display is in landscape.  Applied before inverting.
+  pub fn set_swap_xy(mut self, swap_xy: bool) -> Self {
+    self.swap_xy = swap_xy;
+    self
+  }
+
+  pub fn set_invert_x(mut self, invert_x: bool) -> Self {
+    self.invert_x = invert_x;
+    self
+  }
+
+  pub fn set_invert_y(mut self, invert_y: bool) -> Self {
+    self.invert_y = invert_y;
+    self
+  }
+
+  fn to_display(&self, x: i32, y: i32) -> Point {
+    let (width, height) = (self.native_size.width as i32, self.native_size.height as i32);
+    let (x, y, width, height) = if self.swap_xy {
+      (y, x, height, width)
+    } else {
+      (x, y, width, height)
+    };
+    let x = if self.invert_x { width - 1 - x } else { x };
+    let y = if self.invert_y { height - 1 - y } else { y };
+    Point::new(x, y)
+  }
+}
+
+impl<I2C> TouchInput for Ft6236Touch<I2C>
+where
+    I2C: WriteRead,
+    I2C::Error: Display,
+{
+  fn read(&mut self) -> Option<Point> {
+    let mut regs = [0u8; 5];
+    if let Err(e) = self.i2c.write_read(FT6236_ADDRESS, &[REG_TD_STATUS], &mut regs) {
+      // Polled every few ms, one warning is plenty.
+      if !self.logged_error {
+        warn!("Could not read touch controller: {e}");
+        self.logged_error = true;
+      }
+      return None;
+    }
+    self.logged_error = false;
+
+    let touches = regs[0] & 0x0f;
+    if touches == 0 || touches > MAX_TOUCHES {
+      return None;
+    }
+    let x = (i32::from(regs[1] & 0x0f) << 8) | i32::from(regs[2]);
+    let y = (i32::from(regs[3] & 0x0f) << 8) | i32::from(regs[4]);
+    Some(self.to_display(x, y))
+  }
+}
@@ -5,6 +5,17 @@ pub mod status_led;
 pub mod esp32c3_devkit_m;
 pub mod display_factory;
 pub mod membrane_switch;
+pub mod ft6236_touch;
 pub mod backlight_control;
+pub mod pwm_buzzer;
 pub mod ui_device;
 pub mod esp_status_printer;
+pub mod nvs_assignment_store;
+pub mod nvs_settings_store;
+pub mod sntp_clock;
+#[cfg(feature = "ble-provisioning")]
+pub mod ble_provisioning;
+#[cfg(feature = "ota")]
+pub mod esp_ota_target;
+#[cfg(feature = "mqtt")]
+pub mod esp_mqtt_client;
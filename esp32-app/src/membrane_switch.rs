@@ -1,71 +1,112 @@
 use topside_panel_lib::view::window_proxy::WindowProxy;
-use embedded_hal::digital::v2::InputPin;
-use std::fmt::Display;
-use std::time::Duration;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use topside_panel_lib::view::user_input_event::UserInputEvent;
-use debounced_pin::{ActiveLow, Debounce, DebouncedInputPin, DebounceState};
 use log::warn;
-use std::marker::PhantomData;
-use esp_idf_hal::gpio::{AnyIOPin, Input, PinDriver, Pull};
+use esp_idf_hal::gpio::{AnyIOPin, Input, InterruptType, PinDriver, Pull};
 use esp_idf_sys::EspError;
 use topside_panel_lib::model::key_event::{Key, KeyEvent};
 use topside_panel_lib::model::key_event::KeyEvent::{KeyDown, KeyUp};
 
-pub struct MembraneSwitchWindowProxy<I: InputPin, DISP> {
+/// How long a key has to settle at its new level before we believe it.
+const DEBOUNCE: Duration = Duration::from_millis(20);
+
+/// Membrane keys wired between a GPIO and ground.  Rather than reading every pin each time
+/// [WindowProxy::events] is called, an edge interrupt on any of them flags that something
+/// changed, and pins are only read from then until they've settled again.
+pub struct MembraneSwitchWindowProxy<DISP> {
   event_update_interval: Duration,
-  key_map: Vec<(DebouncedInputPin<I, ActiveLow>, Key)>,
+  buttons: Vec<Button>,
+  edge_seen: Arc<AtomicBool>,
   _phantom: PhantomData<DISP>,
 }
 
-impl<I: InputPin, DISP> MembraneSwitchWindowProxy<I, DISP> {
-  pub fn new(key_map: Vec<(DebouncedInputPin<I, ActiveLow>, Key)>) -> Self {
-    let debounced_map: Vec<_> = key_map.into_iter()
-        .map(|mapping| {
-          (mapping.0, mapping.1)
+struct Button {
+  pin: PinDriver<'static, AnyIOPin, Input>,
+  key: Key,
+  pressed: bool,
+
+  /// When the pin was first seen at a level other than [Self::pressed], while it's bouncing.
+  settling_since: Option<Instant>,
+}
+
+impl<DISP> MembraneSwitchWindowProxy<DISP> {
+  pub fn new(key_map: Vec<(AnyIOPin, Key)>) -> Result<Self, EspError> {
+    let edge_seen = Arc::new(AtomicBool::new(false));
+    let buttons = key_map.into_iter()
+        .map(|(pin, key)| {
+          let mut pin = PinDriver::input(pin)?;
+          pin.set_pull(Pull::Up)?;
+          pin.set_interrupt_type(InterruptType::AnyEdge)?;
+          let edge_seen = edge_seen.clone();
+          // SAFETY: runs in ISR context, which is fine for a lone atomic store.
+          unsafe {
+            pin.subscribe(move || edge_seen.store(true, Ordering::Relaxed))?;
+          }
+          pin.enable_interrupt()?;
+          Ok(Button {
+            pin,
+            key,
+            pressed: false,
+            settling_since: None,
+          })
         })
-        .collect();
-    Self {
-      event_update_interval: Duration::from_millis(1),
-      key_map: debounced_map,
+        .collect::<Result<Vec<_>, EspError>>()?;
+    Ok(Self {
+      event_update_interval: Duration::from_millis(5),
+      buttons,
+      edge_seen,
       _phantom: PhantomData,
+    })
+  }
+}
+
+impl Button {
+  fn update(&mut self, now: Instant) -> Option<KeyEvent> {
+    // Active low, the pull-up holds the pin high until the key shorts it to ground.
+    let pressed = self.pin.is_low();
+    if pressed == self.pressed {
+      self.settling_since = None;
+      return None;
+    }
+    let since = *self.settling_since.get_or_insert(now);
+    if now - since < DEBOUNCE {
+      return None;
     }
+
+    self.pressed = pressed;
+    self.settling_since = None;
+    let key = self.key;
+    Some(if pressed { KeyDown { key } } else { KeyUp { key } })
   }
 }
 
-impl<I, DISP> WindowProxy<DISP> for MembraneSwitchWindowProxy<I, DISP>
-where
-    I: InputPin,
-    I::Error: Display,
-{
+impl<DISP> WindowProxy<DISP> for MembraneSwitchWindowProxy<DISP> {
   fn event_update_interval(&self) -> Duration {
     self.event_update_interval
   }
 
   fn events(&mut self) -> Vec<UserInputEvent> {
-    self.key_map.iter_mut()
-        .filter_map(|(physical, virt)| {
-          let was_active = physical.is_active();
-          let now_active = match physical.update() {
-            Ok(DebounceState::Active) => true,
-            Ok(_) => false,
-            Err(e) => {
-              warn!("Could not detect key {:?}: {e}", virt);
-              false
-            }
-          };
+    let edge_seen = self.edge_seen.swap(false, Ordering::Relaxed);
+    let settling = self.buttons.iter().any(|b| b.settling_since.is_some());
+    if !edge_seen && !settling {
+      return Vec::new();
+    }
 
-          if was_active != now_active {
-            let key = virt.to_owned();
-            let event = if now_active {
-              KeyDown { key }
-            } else {
-              KeyUp { key }
-            };
-            Some(UserInputEvent::KeyEvent(event))
-          } else {
-            None
+    let now = Instant::now();
+    self.buttons.iter_mut()
+        .filter_map(|button| {
+          if edge_seen {
+            // Re-armed every time, newer esp-idf-hal versions disable it once it fires.
+            if let Err(e) = button.pin.enable_interrupt() {
+              warn!("Could not re-enable interrupt for {:?}: {e}", button.key);
+            }
           }
+          button.update(now)
         })
+        .map(UserInputEvent::KeyEvent)
         .collect()
   }
 
@@ -73,11 +114,3 @@ where
     // Not relevant for physical displays...
   }
 }
-
-pub fn debounced(
-    pin: AnyIOPin,
-) -> Result<DebouncedInputPin<PinDriver<'static, AnyIOPin, Input>, ActiveLow>, EspError> {
-  let mut raw_input = PinDriver::input(pin)?;
-  raw_input.set_pull(Pull::Up)?;
-  Ok(DebouncedInputPin::new(raw_input.into_input()?, ActiveLow))
-}
@@ -0,0 +1,42 @@
+use std::fmt::{Debug, Formatter};
+use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+use common_lib::assignment_store::{AssignmentStore, decode_assignment, encode_assignment};
+use common_lib::cts_state_machine::ChannelAssignment;
+
+const NAMESPACE: &str = "balboa";
+
+/// Keeps a channel assignment in NVS under `key`, which must be unique per client.
+pub struct NvsAssignmentStore {
+  nvs: EspDefaultNvs,
+  key: &'static str,
+}
+
+impl NvsAssignmentStore {
+  pub fn new(partition: EspDefaultNvsPartition, key: &'static str) -> anyhow::Result<Self> {
+    let nvs = EspDefaultNvs::new(partition, NAMESPACE, true)?;
+    Ok(Self { nvs, key })
+  }
+}
+
+impl AssignmentStore for NvsAssignmentStore {
+  fn load(&self) -> anyhow::Result<Option<ChannelAssignment>> {
+    let mut buf = [0u8; 4];
+    match self.nvs.get_raw(self.key, &mut buf)? {
+      Some(data) => Ok(Some(decode_assignment(data)?)),
+      None => Ok(None),
+    }
+  }
+
+  fn save(&mut self, assignment: &ChannelAssignment) -> anyhow::Result<()> {
+    self.nvs.set_raw(self.key, &encode_assignment(assignment))?;
+    Ok(())
+  }
+}
+
+impl Debug for NvsAssignmentStore {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("NvsAssignmentStore")
+        .field("key", &self.key)
+        .finish_non_exhaustive()
+  }
+}
@@ -0,0 +1,52 @@
+use std::fmt::{Debug, Formatter};
+use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+use wifi_module_lib::settings::SettingsStore;
+
+/// Separate from the "balboa" namespace used by the other NVS stores so keys can't collide.
+const NAMESPACE: &str = "settings";
+
+/// Plenty for any of the settings in [wifi_module_lib::settings], the largest being a TLS
+/// identity with an RSA chain.
+const MAX_VALUE_LEN: usize = 8192;
+
+/// Keeps each setting as an NVS entry of the same name.
+pub struct NvsSettingsStore {
+  nvs: EspDefaultNvs,
+}
+
+impl NvsSettingsStore {
+  pub fn new(partition: EspDefaultNvsPartition) -> anyhow::Result<Self> {
+    let nvs = EspDefaultNvs::new(partition, NAMESPACE, true)?;
+    Ok(Self { nvs })
+  }
+}
+
+impl SettingsStore for NvsSettingsStore {
+  fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+    let mut buf = vec![0u8; MAX_VALUE_LEN];
+    match self.nvs.get_raw(key, &mut buf)? {
+      Some(data) => Ok(Some(std::str::from_utf8(data)?.to_owned())),
+      None => Ok(None),
+    }
+  }
+
+  fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+    if value.len() > MAX_VALUE_LEN {
+      anyhow::bail!("Value for {key} too long: {} bytes", value.len());
+    }
+    self.nvs.set_raw(key, value.as_bytes())?;
+    Ok(())
+  }
+
+  fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+    self.nvs.remove(key)?;
+    Ok(())
+  }
+}
+
+impl Debug for NvsSettingsStore {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("NvsSettingsStore")
+        .finish_non_exhaustive()
+  }
+}
@@ -0,0 +1,57 @@
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+use esp_idf_hal::ledc::LedcDriver;
+use esp_idf_sys::EspError;
+use log::warn;
+use topside_panel_lib::view::feedback_device::{Feedback, FeedbackDevice};
+
+/// Piezo buzzer driven by a LEDC channel whose timer is set to the buzzer's resonant frequency.
+/// The tone can't change, so each kind of [Feedback] gets its own rhythm instead.  Patterns play
+/// on their own thread so that a long one doesn't hold up the UI.
+pub struct PwmBuzzer {
+  patterns_tx: Sender<Feedback>,
+}
+
+impl PwmBuzzer {
+  pub fn new(mut driver: LedcDriver<'static>) -> anyhow::Result<Self> {
+    driver.set_duty(0)?;
+    let (patterns_tx, patterns_rx) = channel();
+    thread::Builder::new()
+        .name("PwmBuzzer".to_owned())
+        .spawn(move || {
+          for feedback in patterns_rx {
+            if let Err(e) = play_pattern(&mut driver, pattern(feedback)) {
+              warn!("Could not drive buzzer: {e}");
+            }
+          }
+        })?;
+    Ok(Self { patterns_tx })
+  }
+}
+
+impl FeedbackDevice for PwmBuzzer {
+  fn play(&mut self, feedback: Feedback) {
+    let _ = self.patterns_tx.send(feedback);
+  }
+}
+
+/// Beep lengths in ms, with a gap of the same length after each.
+fn pattern(feedback: Feedback) -> &'static [u64] {
+  match feedback {
+    Feedback::KeyPress => &[15],
+    Feedback::LimitReached => &[60, 60],
+    Feedback::Fault => &[300, 300, 300],
+  }
+}
+
+fn play_pattern(driver: &mut LedcDriver<'static>, beeps: &[u64]) -> Result<(), EspError> {
+  for &ms in beeps {
+    // Square wave for the loudest output.
+    driver.set_duty(driver.get_max_duty() / 2)?;
+    thread::sleep(Duration::from_millis(ms));
+    driver.set_duty(0)?;
+    thread::sleep(Duration::from_millis(ms));
+  }
+  Ok(())
+}
@@ -0,0 +1,66 @@
+use std::ffi::CString;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use balboa_spa_messages::time::ProtocolTime;
+use esp_idf_svc::sntp::EspSntp;
+use esp_idf_sys::*;
+use wifi_module_lib::time_sync::{LocalClock, Weekday};
+
+/// Anything earlier means SNTP hasn't set the clock yet and we're counting up from boot.
+const MIN_VALID_UNIX_TIME: Duration = Duration::from_secs(1672531200); // 2023-01-01
+
+/// Local time from SNTP, which syncs by itself once Wi-Fi is connected.  The timezone is a
+/// POSIX TZ string (e.g. `PST8PDT,M3.2.0,M11.1.0`) so that daylight saving is handled.
+/// Clones share the one SNTP client, which only exists once.
+#[derive(Clone)]
+pub struct SntpLocalClock {
+  // Never locked, the Mutex only makes sharing it Send.
+  _sntp: Arc<Mutex<EspSntp>>,
+  tz: String,
+}
+
+impl SntpLocalClock {
+  pub fn new(tz: &str) -> anyhow::Result<Self> {
+    let sntp = EspSntp::new_default()?;
+    let name = CString::new("TZ").unwrap();
+    let value = CString::new(tz)?;
+    unsafe {
+      setenv(name.as_ptr(), value.as_ptr(), 1);
+      tzset();
+    }
+    Ok(Self { _sntp: Arc::new(Mutex::new(sntp)), tz: tz.to_owned() })
+  }
+
+  fn local_tm(&self) -> Option<tm> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    if since_epoch < MIN_VALID_UNIX_TIME {
+      return None;
+    }
+    let now = since_epoch.as_secs() as time_t;
+    let mut local: tm = Default::default();
+    if unsafe { localtime_r(&now, &mut local) }.is_null() {
+      return None;
+    }
+    Some(local)
+  }
+}
+
+impl LocalClock for SntpLocalClock {
+  fn local_time(&self) -> Option<ProtocolTime> {
+    let local = self.local_tm()?;
+    Some(ProtocolTime::from_hm(local.tm_hour as u8, local.tm_min as u8))
+  }
+
+  fn local_weekday(&self) -> Option<Weekday> {
+    Weekday::from_sunday_index(self.local_tm()?.tm_wday as u8)
+  }
+}
+
+impl Debug for SntpLocalClock {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("SntpLocalClock")
+        .field("tz", &self.tz)
+        .finish_non_exhaustive()
+  }
+}
@@ -1,46 +1,50 @@
 use topside_panel_lib::view::lcd_device::LcdDevice;
+use topside_panel_lib::view::touch_input::TouchInput;
 use embedded_graphics::draw_target::DrawTarget;
-use embedded_hal::digital::v2::{InputPin, OutputPin};
+use embedded_hal::digital::v2::OutputPin;
 use std::fmt::Display;
 use topside_panel_lib::view::ui_handler::UiDelayMs;
 use esp_idf_hal::delay::{Ets, FreeRtos};
 use crate::backlight_control::HalBacklightControl;
 use crate::membrane_switch::MembraneSwitchWindowProxy;
 
-pub struct TftAndMembraneSwitchDevice<DISP, BUTTON: InputPin, BACKLIGHT> {
+pub struct TftAndMembraneSwitchDevice<DISP, BACKLIGHT, TOUCH> {
   display: DISP,
-  buttons: MembraneSwitchWindowProxy<BUTTON, DISP>,
+  buttons: MembraneSwitchWindowProxy<DISP>,
   backlight: HalBacklightControl<BACKLIGHT>,
+  touch: TOUCH,
 }
 
-impl<DISP, BUTTON: InputPin, BACKLIGHT> TftAndMembraneSwitchDevice<DISP, BUTTON, BACKLIGHT> {
+impl<DISP, BACKLIGHT, TOUCH> TftAndMembraneSwitchDevice<DISP, BACKLIGHT, TOUCH> {
   pub fn new(
     display: DISP,
-    buttons: MembraneSwitchWindowProxy<BUTTON, DISP>,
+    buttons: MembraneSwitchWindowProxy<DISP>,
     backlight: HalBacklightControl<BACKLIGHT>,
+    touch: TOUCH,
   ) -> Self {
     Self {
       display,
       buttons,
       backlight,
+      touch,
     }
   }
 }
 
-impl<DISP, BUTTON, BACKLIGHT> LcdDevice for TftAndMembraneSwitchDevice<DISP, BUTTON, BACKLIGHT>
+impl<DISP, BACKLIGHT, TOUCH> LcdDevice for TftAndMembraneSwitchDevice<DISP, BACKLIGHT, TOUCH>
 where
     DISP: DrawTarget,
-    BUTTON: InputPin,
-    BUTTON::Error: Display,
     BACKLIGHT: OutputPin,
     BACKLIGHT::Error: Display,
+    TOUCH: TouchInput,
 {
   type Display = DISP;
-  type Window = MembraneSwitchWindowProxy<BUTTON, DISP>;
+  type Window = MembraneSwitchWindowProxy<DISP>;
   type Backlight = HalBacklightControl<BACKLIGHT>;
+  type Touch = TOUCH;
 
-  fn setup(self) -> (Self::Display, Self::Window, Self::Backlight) {
-    (self.display, self.buttons, self.backlight)
+  fn setup(self) -> (Self::Display, Self::Window, Self::Backlight, Self::Touch) {
+    (self.display, self.buttons, self.backlight, self.touch)
   }
 }
 
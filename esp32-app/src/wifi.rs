@@ -1,29 +1,54 @@
+use std::marker::PhantomData;
+use std::net::Ipv4Addr;
 use std::sync::mpsc::{channel, Sender};
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
-use embedded_svc::wifi::{ClientConfiguration, Configuration, Wifi};
+use embedded_svc::ipv4;
+use embedded_svc::ipv4::{ClientSettings, Mask, Subnet};
+use embedded_svc::wifi::{AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration, Wifi};
 use esp_idf_hal::modem::Modem;
 use esp_idf_hal::peripheral::Peripheral;
 use esp_idf_svc::eventloop::{EspEventLoop, System};
 use esp_idf_svc::handle::RawHandle;
-use esp_idf_svc::netif::IpEvent;
-use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::netif::{EspNetif, IpEvent, NetifConfiguration};
+use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
 use esp_idf_svc::wifi::{EspWifi, WifiEvent, WifiWait};
 use esp_idf_svc::wifi_dpp::{EspWifiDpp, QrCode};
 use esp_idf_sys::*;
 use log::{error, info, warn};
 use wifi_module_lib::advertisement::Advertisement;
-use wifi_module_lib::wifi_manager::{StaAssociationError, WifiDppBootstrapped, WifiManager};
+use wifi_module_lib::captive_portal::{CaptivePortal, PortalCredentials};
+use wifi_module_lib::ip_config::StaticIpConfig;
+#[cfg(feature = "ble-provisioning")]
+use wifi_module_lib::wifi_manager::BleProvisioningParams;
+use wifi_module_lib::wifi_manager::{ConnectionInfo, StaAssociationError, WifiDppBootstrapped, WifiManager, WifiSoftApBootstrapped};
+#[cfg(feature = "ble-provisioning")]
+use crate::ble_provisioning::BleProvisioner;
 
 const STARTED_TIMEOUT: Duration = Duration::from_secs(20);
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
 
+/// Same channel we listen for DPP on.
+const SOFT_AP_CHANNEL: u8 = 6;
+
+/// Stop retrying Wi-Fi Easy Connect after it's been failing this long and offer the access
+/// point instead.
+const DPP_TIMEOUT: Duration = Duration::from_secs(300);
+
+const NVS_NAMESPACE: &str = "wifi_prov";
+
+/// Set while we wait for Wi-Fi Easy Connect.  Nothing interrupts that wait if the user simply
+/// has no phone that can scan the code, so if we find this still set at boot it means they
+/// power cycled us to give up on it, and we go straight to the access point.
+const DPP_PENDING_KEY: &str = "dpp_pending";
+
 pub struct EspWifiManager<'w> {
   wifi: EspWifi<'w>,
   event_loop: EspEventLoop<System>,
   advertisement: Advertisement,
+  nvs: EspDefaultNvs,
 }
 
 impl<'w> EspWifiManager<'w> {
@@ -33,6 +58,7 @@ impl<'w> EspWifiManager<'w> {
       nvs: EspDefaultNvsPartition,
       advertised_name: String,
   ) -> Result<Self, EspError> {
+    let prov_nvs = EspDefaultNvs::new(nvs.clone(), NVS_NAMESPACE, true)?;
     let wifi = EspWifi::new(modem, event_loop.clone(), Some(nvs))?;
     let mac = wifi.sta_netif().get_mac()?;
     let advertisement = Advertisement::new(
@@ -42,8 +68,19 @@ impl<'w> EspWifiManager<'w> {
       wifi,
       event_loop,
       advertisement,
+      nvs: prov_nvs,
     })
   }
+
+  /// Clears [DPP_PENDING_KEY], returning whether it was set.
+  fn take_dpp_pending(&mut self) -> Result<bool, EspError> {
+    let mut buf = [0u8; 1];
+    let pending = self.nvs.get_raw(DPP_PENDING_KEY, &mut buf)?.is_some();
+    if pending {
+      self.nvs.remove(DPP_PENDING_KEY)?;
+    }
+    Ok(pending)
+  }
 }
 
 impl<'w> WifiManager<'w> for EspWifiManager<'w> {
@@ -54,6 +91,9 @@ impl<'w> WifiManager<'w> for EspWifiManager<'w> {
   type DppBootstrapped<'d> = EspDppBootstrappedAdapter<'d, 'w>
   where 'w: 'd, Self: 'd;
 
+  type SoftApBootstrapped<'d> = EspSoftApBootstrapped<'d, 'w>
+  where 'w: 'd, Self: 'd;
+
   fn advertisement(&self) -> &Advertisement {
     &self.advertisement
   }
@@ -80,9 +120,49 @@ impl<'w> WifiManager<'w> for EspWifiManager<'w> {
   }
 
   fn dpp_bootstrap(&mut self) -> Result<Self::DppBootstrapped<'_>, Self::Error> {
+    if self.take_dpp_pending()? {
+      // Cleared above, so the next power cycle tries Easy Connect again.
+      info!("Power cycled while waiting for Wi-Fi Easy Connect, skipping it this time");
+      return Err(EspError::from_infallible::<ESP_ERR_NOT_SUPPORTED>());
+    }
+    self.nvs.set_raw(DPP_PENDING_KEY, &[1])?;
+
     let bootstrapped = self.wifi.dpp_generate_qrcode(&[6], None, None)?;
+    #[cfg(feature = "ble-provisioning")]
+    let ble = match BleProvisioner::start(&self.advertisement) {
+      Ok(ble) => Some(ble),
+      Err(e) => {
+        warn!("BLE provisioning unavailable: {e}");
+        None
+      }
+    };
     Ok(EspDppBootstrappedAdapter {
       bootstrapped,
+      #[cfg(feature = "ble-provisioning")]
+      ble,
+    })
+  }
+
+  fn soft_ap_bootstrap(&mut self) -> Result<Self::SoftApBootstrapped<'_>, Self::Error> {
+    let mut ssid = format!("{}-setup", self.advertisement.name);
+    ssid.truncate(32);
+    self.wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+      ssid: ssid.as_str().into(),
+      channel: SOFT_AP_CHANNEL,
+      auth_method: AuthMethod::None,
+      ..Default::default()
+    }))?;
+
+    let ap_addr = self.wifi.ap_netif().get_ip_info()?.ip;
+    let portal = CaptivePortal::setup(ap_addr).map_err(|e| {
+      error!("Captive portal failed to start: {e}");
+      EspError::from_infallible::<ESP_FAIL>()
+    })?;
+    Ok(EspSoftApBootstrapped {
+      ssid,
+      portal_url: format!("http://{ap_addr}/"),
+      portal,
+      _wifi: PhantomData,
     })
   }
 
@@ -90,9 +170,34 @@ impl<'w> WifiManager<'w> for EspWifiManager<'w> {
     let network_name = get_network_name(&credentials)
         .expect("Must have a valid target network!");
     self.wifi.set_configuration(&Configuration::Client(credentials))?;
+    self.take_dpp_pending()?;
     Ok(network_name)
   }
 
+  fn set_static_ip(&mut self, config: Option<&StaticIpConfig>) -> Result<(), Self::Error> {
+    let ip_configuration = match config {
+      None => ipv4::ClientConfiguration::DHCP(Default::default()),
+      Some(config) => ipv4::ClientConfiguration::Fixed(ClientSettings {
+        ip: config.ip,
+        subnet: Subnet {
+          gateway: config.gateway,
+          mask: Mask(config.prefix_len),
+        },
+        dns: config.dns,
+        secondary_dns: config.secondary_dns,
+      }),
+    };
+    let sta_netif = EspNetif::new_with_conf(&NetifConfiguration {
+      ip_configuration: ipv4::Configuration::Client(ip_configuration),
+      ..NetifConfiguration::wifi_default_client()
+    })?;
+    let ap_netif = EspNetif::new_with_conf(&NetifConfiguration::wifi_default_router())?;
+    // With a fixed address esp-netif still posts the same got-IP event on connect that DHCP
+    // would, so do_sta_connect needs no special casing.
+    self.wifi.swap_netif(sta_netif, ap_netif)?;
+    Ok(())
+  }
+
   fn sta_connect(&mut self) -> Result<(), StaAssociationError> {
     let result = self.do_sta_connect()
         .map_err(|e| StaAssociationError::SystemError(e.to_string()))?;
@@ -102,9 +207,22 @@ impl<'w> WifiManager<'w> for EspWifiManager<'w> {
     }
   }
 
-  fn wait_while_connected(&mut self) -> Result<(), Self::Error> {
-    WifiWait::new(&self.event_loop)?.wait(|| !self.wifi.is_connected().unwrap());
-    Ok(())
+  fn wait_while_connected(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+    let disconnected = wifi_wait_ext(&WifiWait::new(&self.event_loop)?, Some(timeout), || {
+      self.wifi.is_connected().map(|connected| !connected)
+    })?;
+    Ok(!disconnected)
+  }
+
+  fn connection_info(&mut self) -> Result<ConnectionInfo, Self::Error> {
+    let mut ap_info: wifi_ap_record_t = Default::default();
+    esp!(unsafe { esp_wifi_sta_get_ap_info(&mut ap_info) })?;
+    let ip = self.wifi.sta_netif().get_ip_info()?.ip;
+    Ok(ConnectionInfo {
+      rssi: ap_info.rssi,
+      ip: Some(ip).filter(|ip| *ip != Ipv4Addr::UNSPECIFIED),
+      channel: ap_info.primary,
+    })
   }
 }
 
@@ -179,6 +297,9 @@ impl<'w> EspWifiManager<'w> {
 
 pub struct EspDppBootstrappedAdapter<'d, 'w> {
   bootstrapped: EspWifiDpp<'d, 'w, QrCode>,
+  /// Runs until we're dropped, restarting the device by itself if it wins.
+  #[cfg(feature = "ble-provisioning")]
+  ble: Option<BleProvisioner>,
 }
 
 impl<'d, 'w> WifiDppBootstrapped<'d, 'w> for EspDppBootstrappedAdapter<'d, 'w> {
@@ -190,23 +311,75 @@ impl<'d, 'w> WifiDppBootstrapped<'d, 'w> for EspDppBootstrappedAdapter<'d, 'w> {
     &self.bootstrapped.get_bootstrapped_data().0
   }
 
+  #[cfg(feature = "ble-provisioning")]
+  fn get_ble_provisioning(&self) -> Option<&BleProvisioningParams> {
+    self.ble.as_ref().map(|ble| ble.params())
+  }
+
   fn listen_then_wait(self) -> Result<Self::Credentials, Self::Error> {
     info!("Waiting for user to scan code...");
+    #[cfg(feature = "ble-provisioning")]
+    let _ble = self.ble;
     let mut bootstrapped = self.bootstrapped;
+    let start_time = Instant::now();
     loop {
       let listener = bootstrapped.start_listen()?;
       match listener.wait_for_credentials() {
         Ok(c) => return Ok(c),
+        Err(e) if start_time.elapsed() > DPP_TIMEOUT => {
+          warn!("DPP error: {e}, giving up after {}s", DPP_TIMEOUT.as_secs());
+          return Err(e);
+        }
         Err(e) => {
           warn!("DPP error: {e}, retrying...");
-          bootstrapped = listener.attempt_retry()
-              .expect("Please patch esp-idf with esp_supp_dpp_start_listen fix!");
+          // Needs the esp_supp_dpp_start_listen fix in esp-idf, otherwise this always fails.
+          bootstrapped = listener.attempt_retry()?;
         }
       }
     }
   }
 }
 
+pub struct EspSoftApBootstrapped<'d, 'w> {
+  ssid: String,
+  portal_url: String,
+  portal: CaptivePortal,
+  _wifi: PhantomData<&'d mut EspWifi<'w>>,
+}
+
+impl<'d, 'w> WifiSoftApBootstrapped<'d, 'w> for EspSoftApBootstrapped<'d, 'w> {
+  type Error = EspError;
+
+  type Credentials = ClientConfiguration;
+
+  fn get_ap_ssid(&self) -> &str {
+    &self.ssid
+  }
+
+  fn get_portal_url(&self) -> &str {
+    &self.portal_url
+  }
+
+  fn wait_for_credentials(self) -> Result<Self::Credentials, Self::Error> {
+    info!("Waiting for user to join {}...", self.ssid);
+    let PortalCredentials { ssid, password } = self.portal.wait_for_credentials().map_err(|e| {
+      error!("Captive portal failed: {e}");
+      EspError::from_infallible::<ESP_FAIL>()
+    })?;
+    let auth_method = if password.is_empty() {
+      AuthMethod::None
+    } else {
+      AuthMethod::WPA2Personal
+    };
+    Ok(ClientConfiguration {
+      ssid: ssid.as_str().into(),
+      password: password.as_str().into(),
+      auth_method,
+      ..Default::default()
+    })
+  }
+}
+
 fn get_network_name(config: &ClientConfiguration) -> Option<String> {
   if !config.ssid.is_empty() {
     Some(config.ssid.as_str().to_owned())
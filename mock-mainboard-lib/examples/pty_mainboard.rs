@@ -0,0 +1,28 @@
+//! Run the mock main board on a pseudo-terminal so that other implementations can be tested
+//! against it, for example:
+//!
+//! ```sh
+//! cargo run -p mock-mainboard-lib --example pty_mainboard
+//! # In another terminal, using the path printed above:
+//! bwa_mqtt_bridge /dev/pts/7
+//! ```
+
+use std::time::Duration;
+use log::{info, LevelFilter};
+use common_lib::pty_transport::PtyTransport;
+use mock_mainboard_lib::channel_manager::CtsEnforcementPolicy;
+use mock_mainboard_lib::main_board::MainBoard;
+
+fn main() -> anyhow::Result<()> {
+  env_logger::builder().filter_level(LevelFilter::Info).init();
+
+  let transport = PtyTransport::create()?;
+  let peer_path = transport.peer_path().unwrap().to_owned();
+  info!("Mock main board listening on {}", peer_path.display());
+
+  let (control_handle, runner) = MainBoard::new(transport)
+      .set_clear_to_send_policy(CtsEnforcementPolicy::Always, Duration::from_secs(30))
+      .into_runner();
+  control_handle.complete_init();
+  runner.run_loop()
+}
@@ -0,0 +1,237 @@
+//! Timestamped recordings of bus traffic that can be replayed through the mock main board, making
+//! it possible to reproduce bugs that were only ever seen against real hardware.
+//!
+//! A capture starts with [CAPTURE_MAGIC] and is followed by one record per frame: a big-endian
+//! u32 of microseconds since the capture started, then the message exactly as produced by
+//! [Message::to_bytes] (i.e. without framing or CRC).  The message's own length byte determines
+//! where the record ends.
+//!
+//! This format is our own and new with this module: neither this tree nor any Balboa tooling
+//! that I know of defines a capture file to be compatible with.  It's kept binary and minimal so
+//! that recording costs next to nothing when tapped off a real bus, and the trailing digit of the
+//! magic is there to version the layout should it ever need to change.  For post-processing with
+//! other tools, record with [common_lib::message_logger::JsonLinesSink] instead.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use balboa_spa_messages::message::Message;
+use log::warn;
+use common_lib::message_logger::{MessageDirection, MessageSink};
+use crate::clock::{Clock, SharedClock};
+
+pub const CAPTURE_MAGIC: &[u8; 8] = b"BSPACAP1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedFrame {
+  pub offset: Duration,
+  pub message: Message,
+}
+
+#[derive(Debug)]
+pub struct CaptureWriter<W> {
+  writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+  pub fn new(mut writer: W) -> io::Result<Self> {
+    writer.write_all(CAPTURE_MAGIC)?;
+    Ok(Self { writer })
+  }
+
+  pub fn write_frame(&mut self, frame: &CapturedFrame) -> io::Result<()> {
+    let offset_us = u32::try_from(frame.offset.as_micros())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Capture offset too large"))?;
+    let encoded = frame.message.to_bytes()
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    self.writer.write_all(&offset_us.to_be_bytes())?;
+    self.writer.write_all(&encoded)?;
+    Ok(())
+  }
+
+  pub fn into_inner(self) -> W {
+    self.writer
+  }
+}
+
+/// Records the frames a [common_lib::message_logger::MessageLogger] sends as a capture, timed
+/// from the first one by the given clock.  Inbound traffic is skipped since replay only ever
+/// plays frames back out onto the bus.
+#[derive(Debug)]
+pub struct CaptureSink<W> {
+  clock: SharedClock,
+  state: Mutex<CaptureSinkState<W>>,
+}
+
+#[derive(Debug)]
+struct CaptureSinkState<W> {
+  writer: CaptureWriter<W>,
+  started_at: Option<Instant>,
+}
+
+impl<W: Write> CaptureSink<W> {
+  pub fn new(writer: W, clock: SharedClock) -> io::Result<Self> {
+    Ok(Self {
+      clock,
+      state: Mutex::new(CaptureSinkState {
+        writer: CaptureWriter::new(writer)?,
+        started_at: None,
+      }),
+    })
+  }
+
+  pub fn into_inner(self) -> W {
+    self.state.into_inner().unwrap().writer.into_inner()
+  }
+}
+
+impl<W: Write + Send> MessageSink for CaptureSink<W> {
+  fn record(&self, _debug_name: &str, direction: MessageDirection, message: &Message) {
+    if !matches!(direction, MessageDirection::Outbound) {
+      return;
+    }
+    let mut state = self.state.lock().unwrap();
+    let now = self.clock.now();
+    let offset = self.clock.elapsed_since(*state.started_at.get_or_insert(now));
+    let frame = CapturedFrame { offset, message: message.clone() };
+    if let Err(e) = state.writer.write_frame(&frame) {
+      warn!("Failed to write capture: {e}");
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+  reader: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+  pub fn new(mut reader: R) -> io::Result<Self> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CAPTURE_MAGIC {
+      return Err(io::Error::new(ErrorKind::InvalidData, "Not a capture file"));
+    }
+    Ok(Self { reader })
+  }
+
+  /// Read the next frame, returning `Ok(None)` at a clean end of the capture.
+  pub fn next_frame(&mut self) -> io::Result<Option<CapturedFrame>> {
+    let mut offset_us = [0u8; 4];
+    match self.reader.read_exact(&mut offset_us) {
+      Ok(_) => {},
+      Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+      Err(e) => return Err(e),
+    }
+    let offset = Duration::from_micros(u64::from(u32::from_be_bytes(offset_us)));
+
+    let mut length = [0u8; 1];
+    self.reader.read_exact(&mut length)?;
+    // Length counts the (absent) CRC byte as well as itself.
+    let remaining = usize::from(length[0]).checked_sub(2)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid message length"))?;
+    let mut encoded = vec![0u8; 1 + remaining];
+    encoded[0] = length[0];
+    self.reader.read_exact(&mut encoded[1..])?;
+    let message = Message::from_bytes(&encoded)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Some(CapturedFrame { offset, message }))
+  }
+
+  pub fn read_all(mut self) -> io::Result<Vec<CapturedFrame>> {
+    let mut frames = Vec::new();
+    while let Some(frame) = self.next_frame()? {
+      frames.push(frame);
+    }
+    Ok(frames)
+  }
+}
+
+/// Feeds captured frames back out as their original offsets come due.  Like
+/// [crate::scenario::ScenarioRunner], the clock starts on the first poll.
+#[derive(Debug)]
+pub(crate) struct SessionReplay {
+  pending: VecDeque<CapturedFrame>,
+  started_at: Option<Instant>,
+}
+
+impl SessionReplay {
+  pub fn new(frames: Vec<CapturedFrame>) -> Self {
+    Self {
+      pending: frames.into(),
+      started_at: None,
+    }
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.pending.is_empty()
+  }
+
+  /// Next frame whose original offset has been reached, if any.  It remains pending until
+  /// [Self::pop] is called so that the caller can retry later if it can't send yet.
+  pub fn peek_due(&mut self, clock: &dyn Clock) -> Option<&Message> {
+    let started_at = *self.started_at.get_or_insert_with(|| clock.now());
+    let elapsed = clock.elapsed_since(started_at);
+    self.pending.front()
+        .filter(|frame| frame.offset <= elapsed)
+        .map(|frame| &frame.message)
+  }
+
+  pub fn pop(&mut self) {
+    self.pending.pop_front();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::channel::Channel;
+  use balboa_spa_messages::message_types::MessageType;
+  use std::sync::Arc;
+  use crate::clock::ManualClock;
+  use super::*;
+
+  #[test]
+  fn test_round_trip() -> anyhow::Result<()> {
+    let frames = vec![
+      CapturedFrame {
+        offset: Duration::ZERO,
+        message: MessageType::NewClientClearToSend().to_message(Channel::MulticastChannelAssignment)?,
+      },
+      CapturedFrame {
+        offset: Duration::from_millis(15),
+        message: MessageType::ChannelAssignmentRequest { device_type: 0x2, client_hash: 0xf247 }
+            .to_message(Channel::MulticastChannelAssignment)?,
+      },
+    ];
+
+    let mut writer = CaptureWriter::new(Vec::new())?;
+    for frame in &frames {
+      writer.write_frame(frame)?;
+    }
+    let encoded = writer.into_inner();
+
+    let decoded = CaptureReader::new(encoded.as_slice())?.read_all()?;
+    assert_eq!(decoded, frames);
+    assert!(CaptureReader::new(&encoded[1..]).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_capture_sink() -> anyhow::Result<()> {
+    let message = MessageType::NewClientClearToSend().to_message(Channel::MulticastChannelAssignment)?;
+    let clock = ManualClock::default();
+    let sink = CaptureSink::new(Vec::new(), Arc::new(clock.clone()))?;
+    sink.record("test", MessageDirection::Outbound, &message);
+    sink.record("test", MessageDirection::Inbound, &message);
+    clock.advance(Duration::from_millis(20));
+    sink.record("test", MessageDirection::Outbound, &message);
+
+    let decoded = CaptureReader::new(sink.into_inner().as_slice())?.read_all()?;
+    let offsets: Vec<_> = decoded.iter().map(|frame| frame.offset).collect();
+    assert_eq!(offsets, vec![Duration::ZERO, Duration::from_millis(20)]);
+    assert!(decoded.iter().all(|frame| frame.message == message));
+    Ok(())
+  }
+}
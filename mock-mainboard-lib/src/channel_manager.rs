@@ -1,8 +1,11 @@
+use std::collections::HashMap;
 use std::time::Duration;
 use log::{info, warn};
 use balboa_spa_messages::channel::Channel;
 use balboa_spa_messages::message::Message;
-use crate::channel_tracker::{ChannelTracker, CtsFailureAction, DeviceKey};
+use balboa_spa_messages::message_types::MessageTypeKind;
+use crate::clock::SharedClock;
+use crate::channel_tracker::{ChannelTracker, CtsFailureAction, DeviceKey, EvictedChannel};
 use crate::clear_to_send_tracker::{ClearToSendTracker, NoCtsReason, SendMessage, SendMessageFactory, TrySendMessageError};
 use crate::main_board::HandlingError;
 
@@ -12,6 +15,10 @@ pub(crate) struct ChannelManager {
   policy: CtsEnforcementPolicy,
   channel_tracker: ChannelTracker,
   clear_to_send_tracker: ClearToSendTracker,
+
+  /// Whether to service the fixed [Channel::WifiModule] channel alongside dynamically assigned
+  /// client channels.
+  wifi_module_enabled: bool,
 }
 
 /// Specifies the policy we should use when approving messages based on the protocol's
@@ -38,6 +45,7 @@ impl Default for ChannelManager {
       policy: CtsEnforcementPolicy::Always,
       channel_tracker: Default::default(),
       clear_to_send_tracker: Default::default(),
+      wifi_module_enabled: false,
     }
   }
 }
@@ -55,27 +63,76 @@ impl ChannelManager {
     }
   }
 
+  pub fn set_clock(&mut self, clock: SharedClock) {
+    self.channel_tracker.set_clock(clock.clone());
+    self.clear_to_send_tracker.set_clock(clock);
+  }
+
+  /// Number of consecutive clear-to-send failures (missed polls, out of turn messages, etc)
+  /// before a client's channel is reclaimed.
+  pub fn set_max_cts_failures(&mut self, max_cts_failures: usize) {
+    self.channel_tracker.set_max_failures(max_cts_failures);
+  }
+
+  pub fn set_wifi_module_enabled(&mut self, enabled: bool) {
+    self.wifi_module_enabled = enabled;
+  }
+
+  pub fn take_evicted(&mut self) -> Vec<EvictedChannel> {
+    self.channel_tracker.take_evicted()
+  }
+
+  /// Snapshot of the CTS statistics for every channel that has been cleared to send so far.
+  /// Statistics are retained even after a channel is evicted.
+  pub fn cts_stats(&self) -> HashMap<Channel, CtsChannelStats> {
+    self.clear_to_send_tracker.stats().clone()
+  }
+
   pub fn num_channels(&self) -> usize {
     self.channel_tracker.len()
   }
 
+  /// All channels we should be issuing ClearToSend for, including the Wi-Fi module's fixed
+  /// channel if enabled.
   pub fn allocated_channels(&self) -> impl Iterator<Item=&Channel> {
     self.channel_tracker.channels_iter()
+        .chain(self.wifi_module_enabled.then_some(&Channel::WifiModule))
   }
 
   pub fn is_channel_allocated(&self, channel: &Channel) -> bool {
-    self.channel_tracker.is_allocated(channel)
+    self.channel_tracker.is_allocated(channel) || self.is_wifi_module(channel)
+  }
+
+  fn is_wifi_module(&self, channel: &Channel) -> bool {
+    self.wifi_module_enabled && *channel == Channel::WifiModule
   }
 
   pub fn select_channel(&mut self, key: DeviceKey) -> Result<Channel, HandlingError> {
     self.channel_tracker.select_channel(key)
   }
 
+  pub fn has_reclaimable_channels(&self) -> bool {
+    self.channel_tracker.has_reclaimable()
+  }
+
+  pub fn reclaim_channel(&mut self, channel: Channel, key: DeviceKey) -> Result<(), HandlingError> {
+    self.channel_tracker.reclaim_channel(channel, key)
+  }
+
+  /// Change the reply window for subsequent clear-to-send grants.
+  pub fn set_cts_window(&mut self, cts_window: Duration) {
+    self.clear_to_send_tracker.set_window(cts_window);
+  }
+
   pub fn handle_presend(&mut self, sm: &SendMessage) {
     self.clear_to_send_tracker.on_send(sm)
   }
 
   pub fn validate_message(&mut self, message: &Message) -> Result<(), HandlingError> {
+    if message.message_type == MessageTypeKind::ExistingClientResponse as u8 {
+      return self.validate_existing_client(message);
+    }
+
     let cts_result = self.clear_to_send_tracker
         .try_accept_incoming_message(message);
     let channel = &message.channel;
@@ -94,6 +151,8 @@ impl ChannelManager {
           NoCtsReason::ExpiredWindow => format!("Window expired on {:?}", e.attempted_channel),
         };
         Err(match cts_action {
+          // The Wi-Fi module's channel is fixed so there's nothing to reconnect to.
+          _ if self.is_wifi_module(channel) => HandlingError::ClientRecoverable(err_msg),
           CtsFailureAction::ChannelNotFound |
           CtsFailureAction::ChannelRemoved => HandlingError::ClientNeedsReconnect(err_msg),
           CtsFailureAction::Tolerated => HandlingError::ClientRecoverable(err_msg),
@@ -109,6 +168,7 @@ impl ChannelManager {
         }
       }
       Channel::MulticastChannelAssignment => {}
+      Channel::WifiModule if self.wifi_module_enabled => {}
       _ => {
         return Err(HandlingError::ClientUnsupported(
           format!("Received message on unexpected channel={channel:?}, ignoring...")));
@@ -126,6 +186,29 @@ impl ChannelManager {
     })
   }
 
+  /// Existing clients answer the multicast ExistingClientRequest on the channel they believe
+  /// they own, which won't be allocated yet if we're expecting them to reclaim it.
+  fn validate_existing_client(&mut self, message: &Message) -> Result<(), HandlingError> {
+    let channel = &message.channel;
+    if !matches!(channel, Channel::Client(_)) {
+      return Err(HandlingError::ClientUnsupported(
+        format!("Existing client response on unexpected channel={channel:?}")));
+    }
+    let result = self.clear_to_send_tracker
+        .try_accept_incoming_on(&Channel::MulticastChannelAssignment)
+        .map_err(|e| HandlingError::ClientRecoverable(
+          format!("Existing client response on {channel:?} not cleared: {:?}", e.reason)));
+    result.or_else(|e| {
+      match self.resolve_policy() {
+        ResolvedCtsPolicy::Always => Err(e),
+        ResolvedCtsPolicy::Never => {
+          warn!("Suppressing CTS error by policy: {e:?}");
+          Ok(())
+        }
+      }
+    })
+  }
+
   pub fn start_send_message(&mut self) -> Result<Option<SendMessageFactory>, HandlingError> {
     match self.clear_to_send_tracker.start_send_message() {
       Ok(smf) => Ok(Some(smf)),
@@ -164,6 +247,26 @@ impl ChannelManager {
   }
 }
 
+/// Counters describing how promptly a channel's client replies after being cleared to send.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CtsChannelStats {
+  /// Number of times the channel was cleared to send, including optional clears such as
+  /// NewClientClearToSend on the multicast channel.
+  pub grants: u64,
+
+  /// Replies that arrived within the CTS window.
+  pub replies_in_window: u64,
+
+  /// Replies that arrived after the CTS window had already expired.
+  pub late_replies: u64,
+
+  /// Grants that were never answered at all before we moved on.
+  pub missed_windows: u64,
+
+  /// Slowest reply that still made it within the window.
+  pub max_reply_latency: Duration,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum ResolvedCtsPolicy {
   Always,
@@ -1,6 +1,8 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use balboa_spa_messages::channel::Channel;
+use std::time::{Duration, Instant};
+use balboa_spa_messages::channel::{Channel, CLIENT_CTS_RANGE};
+use crate::clock::{system_clock, SharedClock};
 use crate::main_board::HandlingError;
 
 /// Number of _consecutive_ failures to respond to ClearToSend before we remove the channel and
@@ -8,13 +10,20 @@ use crate::main_board::HandlingError;
 /// but it's very useful for testing.  Must be a very large number because the protocol is lossy
 /// and retransmits of certain messages can take up to 1s with dozens of messages passing in that
 /// time.
-const DEFAULT_MAX_CLEAR_TO_SEND_FAILURES: usize = 160;
+pub(crate) const DEFAULT_MAX_CLEAR_TO_SEND_FAILURES: usize = 160;
 
 #[derive(Debug)]
 pub(crate) struct ChannelTracker {
   lookup_by_device: HashMap<DeviceKey, Channel>,
   records: HashMap<Channel, ChannelRecord>,
   max_cts_failures: usize,
+  evicted: Vec<EvictedChannel>,
+
+  /// Last device to be assigned each channel, retained after eviction so that the client can
+  /// reclaim it later via ExistingClientResponse.
+  previous_owners: HashMap<Channel, DeviceKey>,
+
+  clock: SharedClock,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
@@ -28,6 +37,15 @@ struct ChannelRecord {
   device_key: DeviceKey,
   channel: Channel,
   consecutive_cts_failures: usize,
+  last_activity: Instant,
+}
+
+/// Record of a channel that was reclaimed after its client stopped responding.
+#[derive(Debug, Clone)]
+pub(crate) struct EvictedChannel {
+  pub device_key: DeviceKey,
+  pub channel: Channel,
+  pub idle: Duration,
 }
 
 impl Default for ChannelTracker {
@@ -36,6 +54,9 @@ impl Default for ChannelTracker {
       lookup_by_device: HashMap::new(),
       records: HashMap::new(),
       max_cts_failures: DEFAULT_MAX_CLEAR_TO_SEND_FAILURES,
+      evicted: Vec::new(),
+      previous_owners: HashMap::new(),
+      clock: system_clock(),
     }
   }
 }
@@ -60,23 +81,76 @@ impl ChannelTracker {
     self.records.keys()
   }
 
+  pub fn set_clock(&mut self, clock: SharedClock) {
+    self.clock = clock;
+  }
+
+  pub fn set_max_failures(&mut self, max_cts_failures: usize) {
+    self.max_cts_failures = max_cts_failures;
+  }
+
   pub fn select_channel(&mut self, key: DeviceKey) -> Result<Channel, HandlingError> {
-    let len = self.lookup_by_device.len();
+    let free_channel = self.find_free_channel(&key);
     let channel = match self.lookup_by_device.entry(key) {
       Entry::Occupied(o) => o.get().to_owned(),
       Entry::Vacant(v) => {
-        let new_channel = Channel::new_client_channel(len)
-            .map_err(|_| HandlingError::ClientNeedsReconnect("channel overflow".to_owned()))?;
-        let record = ChannelRecord::new(key, new_channel);
+        let new_channel = free_channel
+            .ok_or_else(|| HandlingError::ClientNeedsReconnect("channel overflow".to_owned()))?;
+        let record = ChannelRecord::new(key, new_channel, self.clock.now());
 
         v.insert(new_channel);
         self.records.insert(new_channel, record);
+        self.previous_owners.insert(new_channel, key);
         new_channel
       }
     };
     Ok(channel)
   }
 
+  /// Channels can be reclaimed out of order so we can't assume they're densely packed.  Prefer
+  /// giving a device back its old channel, then channels nobody might try to reclaim, and only
+  /// then hand out one that a previous owner could still come back for.
+  fn find_free_channel(&self, key: &DeviceKey) -> Option<Channel> {
+    let free: Vec<_> = (0..CLIENT_CTS_RANGE.len())
+        .filter_map(|i| Channel::new_client_channel(i).ok())
+        .filter(|c| !self.records.contains_key(c))
+        .collect();
+    free.iter().find(|c| self.previous_owners.get(c) == Some(key))
+        .or_else(|| free.iter().find(|c| !self.previous_owners.contains_key(c)))
+        .or_else(|| free.first())
+        .copied()
+  }
+
+  /// Whether any previously assigned channels are currently free to be reclaimed by their
+  /// old owners.
+  pub fn has_reclaimable(&self) -> bool {
+    self.previous_owners.keys().any(|c| !self.records.contains_key(c))
+  }
+
+  /// Restore a channel for a client that claims to have been assigned it before, validating that
+  /// it really was the last device to hold it.
+  pub fn reclaim_channel(&mut self, channel: Channel, key: DeviceKey) -> Result<(), HandlingError> {
+    if let Some(record) = self.records.get(&channel) {
+      return if record.device_key == key {
+        Ok(())
+      } else {
+        Err(HandlingError::ClientNeedsReconnect(
+          format!("{channel:?} is assigned to another device")))
+      };
+    }
+    if self.previous_owners.get(&channel) != Some(&key) {
+      return Err(HandlingError::ClientNeedsReconnect(
+        format!("{channel:?} was not previously assigned to {key:?}")));
+    }
+    if let Some(existing) = self.lookup_by_device.get(&key) {
+      return Err(HandlingError::ClientNeedsReconnect(
+        format!("{key:?} is already assigned {existing:?}")));
+    }
+    self.lookup_by_device.insert(key, channel);
+    self.records.insert(channel, ChannelRecord::new(key, channel, self.clock.now()));
+    Ok(())
+  }
+
   pub fn is_allocated(&self, channel: &Channel) -> bool {
     self.records.contains_key(channel)
   }
@@ -84,9 +158,15 @@ impl ChannelTracker {
   pub fn record_cts_success(&mut self, channel: &Channel) {
     if let Some(record) = self.records.get_mut(&channel) {
       record.consecutive_cts_failures = 0;
+      record.last_activity = self.clock.now();
     }
   }
 
+  /// Drain the channels evicted since the last call.
+  pub fn take_evicted(&mut self) -> Vec<EvictedChannel> {
+    std::mem::take(&mut self.evicted)
+  }
+
   pub fn record_cts_failure(&mut self, channel: Channel) -> CtsFailureAction {
     match self.records.entry(channel) {
       Entry::Occupied(mut o) => {
@@ -94,6 +174,11 @@ impl ChannelTracker {
         record.consecutive_cts_failures += 1;
         if record.consecutive_cts_failures >= self.max_cts_failures {
           self.lookup_by_device.remove(&record.device_key);
+          self.evicted.push(EvictedChannel {
+            device_key: record.device_key,
+            channel,
+            idle: self.clock.elapsed_since(record.last_activity),
+          });
           o.remove();
           CtsFailureAction::ChannelRemoved
         } else {
@@ -113,8 +198,13 @@ pub enum CtsFailureAction {
 }
 
 impl ChannelRecord {
-  pub fn new(device: DeviceKey, channel: Channel) -> Self {
-    Self { device_key: device, channel, consecutive_cts_failures: 0 }
+  pub fn new(device: DeviceKey, channel: Channel, now: Instant) -> Self {
+    Self {
+      device_key: device,
+      channel,
+      consecutive_cts_failures: 0,
+      last_activity: now,
+    }
   }
 }
 
@@ -139,5 +229,45 @@ mod tests {
 
     assert_eq!(tracker.records.len(), 0);
     assert_eq!(tracker.lookup_by_device.len(), 0);
+
+    let evicted = tracker.take_evicted();
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].channel, channel);
+    assert!(tracker.take_evicted().is_empty());
+  }
+
+  #[test]
+  fn test_reclaimed_channel_reused() {
+    let mut tracker = ChannelTracker::with_max_failures(1);
+    let key = |client_hash| DeviceKey { device_type: 0, client_hash };
+    let channels: Vec<_> = (0..CLIENT_CTS_RANGE.len())
+        .map(|i| tracker.select_channel(key(u16::try_from(i).unwrap())).unwrap())
+        .collect();
+    assert!(tracker.select_channel(key(0xffff)).is_err());
+
+    assert_eq!(tracker.record_cts_failure(channels[0]), CtsFailureAction::ChannelRemoved);
+
+    assert_eq!(tracker.select_channel(key(0xffff)).unwrap(), channels[0]);
+    assert_eq!(tracker.select_channel(key(1)).unwrap(), channels[1]);
+  }
+
+  #[test]
+  fn test_reclaim_channel() {
+    let mut tracker = ChannelTracker::with_max_failures(1);
+    let key = DeviceKey { device_type: 0, client_hash: 0xcafe };
+    let imposter = DeviceKey { device_type: 0, client_hash: 0xbeef };
+    let channel = tracker.select_channel(key).unwrap();
+    assert!(!tracker.has_reclaimable());
+
+    tracker.record_cts_failure(channel);
+    assert!(tracker.has_reclaimable());
+    assert!(tracker.reclaim_channel(channel, imposter).is_err());
+
+    // Another device arriving in the meantime shouldn't take the reclaimable channel.
+    assert_ne!(tracker.select_channel(imposter).unwrap(), channel);
+
+    tracker.reclaim_channel(channel, key).unwrap();
+    assert!(tracker.is_allocated(&channel));
+    assert!(!tracker.has_reclaimable());
   }
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::mem;
 use std::time::{Duration, Instant};
@@ -6,6 +7,8 @@ use log::{error, warn};
 
 use balboa_spa_messages::channel::Channel;
 use balboa_spa_messages::message::Message;
+use crate::channel_manager::CtsChannelStats;
+use crate::clock::{system_clock, SharedClock};
 
 /// Amount of time to wait when we issue NewClientClearToSend or ClearToSend for a reply
 /// before we can resume sending messages.
@@ -15,6 +18,8 @@ const DEFAULT_CLEAR_TO_SEND_WINDOW: Duration = Duration::from_millis(20);
 pub(crate) struct ClearToSendTracker {
   authorized_sender: Option<AuthorizedSender>,
   allowed_delay: Duration,
+  clock: SharedClock,
+  stats: HashMap<Channel, CtsChannelStats>,
 }
 
 impl Default for ClearToSendTracker {
@@ -23,6 +28,8 @@ impl Default for ClearToSendTracker {
       // Strict default for integration testing
       allowed_delay: DEFAULT_CLEAR_TO_SEND_WINDOW,
       authorized_sender: None,
+      clock: system_clock(),
+      stats: HashMap::new(),
     }
   }
 }
@@ -39,13 +46,31 @@ impl ClearToSendTracker {
     }
   }
 
+  pub fn set_clock(&mut self, clock: SharedClock) {
+    self.clock = clock;
+  }
+
+  pub fn set_window(&mut self, cts_window: Duration) {
+    self.allowed_delay = cts_window;
+  }
+
+  pub fn stats(&self) -> &HashMap<Channel, CtsChannelStats> {
+    &self.stats
+  }
+
   pub fn try_accept_incoming_message(&mut self, message: &Message) -> Result<(), IncomingMessageError> {
+    self.try_accept_incoming_on(&message.channel)
+  }
+
+  /// Same as [Self::try_accept_incoming_message] but for messages that are sent on a different
+  /// channel than the one that was cleared, such as an ExistingClientResponse answering a
+  /// multicast ExistingClientRequest.
+  pub fn try_accept_incoming_on(&mut self, channel: &Channel) -> Result<(), IncomingMessageError> {
     // Note that this means a denial of service is trivially possible if an unauthorized
     // sender spams the signal line.  That's already going to break RS485 communication though,
     // so nothing we can do about it.
     let authorized_sender = mem::take(&mut self.authorized_sender);
 
-    let channel = &message.channel;
     match authorized_sender {
       Some(authorized_sender) => {
         if &authorized_sender.channel != channel {
@@ -54,12 +79,18 @@ impl ClearToSendTracker {
               Some(authorized_sender.channel),
               NoCtsReason::ConflictsWithOther));
         }
-        if authorized_sender.is_expired() {
+        let now = self.clock.now();
+        let stats = self.stats.entry(authorized_sender.channel).or_default();
+        if authorized_sender.is_expired(now) {
+          stats.late_replies += 1;
           Err(IncomingMessageError::new(
               *channel,
               Some(authorized_sender.channel),
               NoCtsReason::ExpiredWindow))
         } else {
+          stats.replies_in_window += 1;
+          let latency = now.saturating_duration_since(authorized_sender.authorized_at);
+          stats.max_reply_latency = stats.max_reply_latency.max(latency);
           Ok(())
         }
       }
@@ -80,7 +111,7 @@ impl ClearToSendTracker {
       Some(authorized) => {
         if authorized.clear_on_next_send {
           Ok(SendMessageFactory)
-        } else if authorized.is_expired() {
+        } else if authorized.is_expired(self.clock.now()) {
           if let Channel::Client(_) = authorized.channel {
             Err(TrySendMessageError::ClientError(authorized.channel))
           } else {
@@ -96,15 +127,19 @@ impl ClearToSendTracker {
 
   pub fn on_send(&mut self, sm: &SendMessage) {
     let authorized_sender = sm.expect_reply_on.map(|channel| {
-      AuthorizedSender::from_now(channel, self.allowed_delay, sm.clear_on_next_send)
+      self.stats.entry(channel).or_default().grants += 1;
+      AuthorizedSender::new(channel, self.clock.now(), self.allowed_delay, sm.clear_on_next_send)
     });
     self.set_authorized_sender(authorized_sender);
   }
 
   fn set_authorized_sender(&mut self, authorized_sender: Option<AuthorizedSender>) {
     if let Some(authorized) = &self.authorized_sender {
+      // Replies take the authorized sender so anything left over here never answered.  Optional
+      // replies (e.g. NewClientClearToSend) commonly go unanswered so aren't counted.
       if !authorized.clear_on_next_send {
         warn!("Existing authorized sender on channel={:?} dropped implicitly!", authorized.channel);
+        self.stats.entry(authorized.channel).or_default().missed_windows += 1;
       }
     }
     self.authorized_sender = authorized_sender;
@@ -146,17 +181,22 @@ pub(crate) enum NoCtsReason {
 }
 
 impl AuthorizedSender {
-  pub fn from_now(channel: Channel, allowed_delay: Duration, clear_on_next_send: bool) -> Self {
+  pub fn new(
+      channel: Channel,
+      authorized_at: Instant,
+      allowed_delay: Duration,
+      clear_on_next_send: bool
+  ) -> Self {
     Self {
       channel,
-      authorized_at: Instant::now(),
+      authorized_at,
       allowed_delay,
       clear_on_next_send,
     }
   }
 
-  pub fn is_expired(&self) -> bool {
-    self.authorized_at.elapsed() > self.allowed_delay
+  pub fn is_expired(&self, now: Instant) -> bool {
+    now.saturating_duration_since(self.authorized_at) > self.allowed_delay
   }
 }
 
@@ -199,3 +239,41 @@ pub struct SendMessage {
   expect_reply_on: Option<Channel>,
   clear_on_next_send: bool,
 }
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use balboa_spa_messages::message_types::MessageType;
+  use crate::clock::ManualClock;
+  use super::*;
+
+  #[test]
+  fn test_stats() -> anyhow::Result<()> {
+    let clock = ManualClock::new();
+    let mut tracker = ClearToSendTracker::with_window(Duration::from_millis(20));
+    tracker.set_clock(Arc::new(clock.clone()));
+    let channel = Channel::Client(0x10);
+    let cts = || MessageType::ClearToSend().to_message(channel);
+    let reply = MessageType::ChannelAssignmentAck().to_message(channel)?;
+
+    tracker.on_send(&SendMessageFactory.expect_reply(cts()?));
+    clock.advance(Duration::from_millis(5));
+    assert!(tracker.try_accept_incoming_message(&reply).is_ok());
+
+    tracker.on_send(&SendMessageFactory.expect_reply(cts()?));
+    clock.advance(Duration::from_millis(50));
+    assert!(tracker.try_accept_incoming_message(&reply).is_err());
+
+    tracker.on_send(&SendMessageFactory.expect_reply(cts()?));
+    tracker.force_send_message();
+
+    assert_eq!(tracker.stats()[&channel], CtsChannelStats {
+      grants: 3,
+      replies_in_window: 1,
+      late_replies: 1,
+      missed_windows: 1,
+      max_reply_latency: Duration::from_millis(5),
+    });
+    Ok(())
+  }
+}
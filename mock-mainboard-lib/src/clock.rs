@@ -0,0 +1,319 @@
+//! Time source used by the mock main board for both reading the current time and driving its
+//! periodic timers.  Production usage relies on [SystemClock] while integration tests can swap in
+//! [ManualClock] to advance time explicitly instead of sleeping.
+
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use timer::Timer;
+
+pub trait Clock: Debug + Send + Sync {
+  fn now(&self) -> Instant;
+
+  fn elapsed_since(&self, earlier: Instant) -> Duration {
+    self.now().saturating_duration_since(earlier)
+  }
+}
+
+pub trait TimerService: Send + Sync {
+  /// Invoke `tick` every `interval` until the returned guard is dropped.
+  fn schedule_repeating(
+      &self,
+      interval: Duration,
+      tick: Box<dyn FnMut() + Send>,
+  ) -> anyhow::Result<TimerGuard>;
+
+  /// Invoke `tick` once after `delay` unless the returned guard is dropped first.
+  fn schedule_once(
+      &self,
+      delay: Duration,
+      tick: Box<dyn FnOnce() + Send>,
+  ) -> anyhow::Result<TimerGuard>;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Keeps a scheduled timer alive.
+pub struct TimerGuard {
+  _inner: Box<dyn Any + Send>,
+}
+
+impl TimerGuard {
+  fn new(inner: impl Any + Send) -> Self {
+    Self { _inner: Box::new(inner) }
+  }
+}
+
+pub fn system_clock() -> SharedClock {
+  Arc::new(SystemClock)
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+}
+
+impl TimerService for SystemClock {
+  fn schedule_repeating(
+      &self,
+      interval: Duration,
+      tick: Box<dyn FnMut() + Send>,
+  ) -> anyhow::Result<TimerGuard> {
+    let timer = Timer::new();
+    let guard = timer.schedule_repeating(chrono::Duration::from_std(interval)?, tick);
+    Ok(TimerGuard::new((timer, guard)))
+  }
+
+  fn schedule_once(
+      &self,
+      delay: Duration,
+      tick: Box<dyn FnOnce() + Send>,
+  ) -> anyhow::Result<TimerGuard> {
+    let timer = Timer::new();
+    let mut tick = Some(tick);
+    let guard = timer.schedule_with_delay(chrono::Duration::from_std(delay)?, move || {
+      if let Some(tick) = tick.take() {
+        tick();
+      }
+    });
+    Ok(TimerGuard::new((timer, guard)))
+  }
+}
+
+/// Runs `factor` times faster than the wrapped clock from the moment it was created, letting
+/// long-running spa behaviour (hold timers, heating, etc) play out in seconds.
+#[derive(Debug)]
+pub struct ScaledClock {
+  inner: SharedClock,
+  origin: Instant,
+  factor: f64,
+}
+
+impl ScaledClock {
+  /// Panics unless `factor` is finite and positive, anything else can't be turned back into a
+  /// [Duration].
+  pub fn new(inner: SharedClock, factor: f64) -> Self {
+    assert!(factor.is_finite() && factor > 0.0, "Clock factor must be finite and positive, got {factor}");
+    let origin = inner.now();
+    Self { inner, origin, factor }
+  }
+}
+
+impl Clock for ScaledClock {
+  fn now(&self) -> Instant {
+    self.origin + self.inner.elapsed_since(self.origin).mul_f64(self.factor)
+  }
+}
+
+/// Clock that only moves when told to, firing any timers that come due along the way in
+/// chronological order.  Clones share the same underlying time.
+#[derive(Clone)]
+pub struct ManualClock {
+  state: Arc<Mutex<ManualClockState>>,
+}
+
+struct ManualClockState {
+  base: Instant,
+  offset: Duration,
+  timers: Vec<ManualTimer>,
+  next_timer_id: usize,
+}
+
+struct ManualTimer {
+  id: usize,
+  /// None for timers that only fire once.
+  interval: Option<Duration>,
+  next_fire: Duration,
+  tick: Option<Box<dyn FnMut() + Send>>,
+}
+
+impl Default for ManualClock {
+  fn default() -> Self {
+    Self {
+      state: Arc::new(Mutex::new(ManualClockState {
+        base: Instant::now(),
+        offset: Duration::ZERO,
+        timers: Vec::new(),
+        next_timer_id: 0,
+      })),
+    }
+  }
+}
+
+impl ManualClock {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Move time forward, synchronously running each timer tick that falls within `duration`.
+  pub fn advance(&self, duration: Duration) {
+    let target = self.state.lock().unwrap().offset + duration;
+    loop {
+      // Callbacks are run without holding the lock as they typically hand off to other threads
+      // which may themselves want to read the time.
+      let (id, mut tick) = {
+        let mut state = self.state.lock().unwrap();
+        let due = state.timers.iter_mut()
+            .filter(|t| t.next_fire <= target && t.tick.is_some())
+            .min_by_key(|t| t.next_fire);
+        match due {
+          None => {
+            state.offset = target;
+            return;
+          }
+          Some(timer) => {
+            let fire_at = timer.next_fire;
+            let id = timer.id;
+            let tick = timer.tick.take().unwrap();
+            let repeating = match timer.interval {
+              Some(interval) => {
+                timer.next_fire += interval;
+                true
+              }
+              None => false,
+            };
+            if !repeating {
+              state.timers.retain(|t| t.id != id);
+            }
+            state.offset = fire_at;
+            (id, tick)
+          }
+        }
+      };
+      tick();
+      let mut state = self.state.lock().unwrap();
+      if let Some(timer) = state.timers.iter_mut().find(|t| t.id == id) {
+        timer.tick = Some(tick);
+      }
+    }
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    let state = self.state.lock().unwrap();
+    state.base + state.offset
+  }
+}
+
+impl TimerService for ManualClock {
+  fn schedule_repeating(
+      &self,
+      interval: Duration,
+      tick: Box<dyn FnMut() + Send>,
+  ) -> anyhow::Result<TimerGuard> {
+    if interval.is_zero() {
+      return Err(anyhow::anyhow!("Timer interval must be non-zero"));
+    }
+    Ok(self.add_timer(Some(interval), interval, tick))
+  }
+
+  fn schedule_once(
+      &self,
+      delay: Duration,
+      tick: Box<dyn FnOnce() + Send>,
+  ) -> anyhow::Result<TimerGuard> {
+    let mut tick = Some(tick);
+    Ok(self.add_timer(None, delay, Box::new(move || {
+      if let Some(tick) = tick.take() {
+        tick();
+      }
+    })))
+  }
+}
+
+impl ManualClock {
+  fn add_timer(
+      &self,
+      interval: Option<Duration>,
+      delay: Duration,
+      tick: Box<dyn FnMut() + Send>,
+  ) -> TimerGuard {
+    let mut state = self.state.lock().unwrap();
+    let id = state.next_timer_id;
+    state.next_timer_id += 1;
+    let next_fire = state.offset + delay;
+    state.timers.push(ManualTimer { id, interval, next_fire, tick: Some(tick) });
+    TimerGuard::new(ManualTimerGuard { id, state: Arc::downgrade(&self.state) })
+  }
+}
+
+impl Debug for ManualClock {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let offset = self.state.lock().unwrap().offset;
+    f.debug_struct("ManualClock").field("offset", &offset).finish()
+  }
+}
+
+struct ManualTimerGuard {
+  id: usize,
+  state: Weak<Mutex<ManualClockState>>,
+}
+
+impl Drop for ManualTimerGuard {
+  fn drop(&mut self) {
+    if let Some(state) = self.state.upgrade() {
+      state.lock().unwrap().timers.retain(|t| t.id != self.id);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use super::*;
+
+  #[test]
+  fn test_manual_clock_fires_timers() {
+    let clock = ManualClock::new();
+    let start = clock.now();
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let ticks_clone = ticks.clone();
+    let guard = clock.schedule_repeating(Duration::from_millis(100), Box::new(move || {
+      ticks_clone.fetch_add(1, Ordering::SeqCst);
+    })).unwrap();
+
+    clock.advance(Duration::from_millis(350));
+    assert_eq!(ticks.load(Ordering::SeqCst), 3);
+    assert_eq!(clock.elapsed_since(start), Duration::from_millis(350));
+
+    drop(guard);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(ticks.load(Ordering::SeqCst), 3);
+  }
+
+  #[test]
+  fn test_manual_clock_fires_once() {
+    let clock = ManualClock::new();
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let ticks_clone = ticks.clone();
+    let _guard = clock.schedule_once(Duration::from_millis(100), Box::new(move || {
+      ticks_clone.fetch_add(1, Ordering::SeqCst);
+    })).unwrap();
+
+    clock.advance(Duration::from_millis(50));
+    assert_eq!(ticks.load(Ordering::SeqCst), 0);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(ticks.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  fn test_scaled_clock() {
+    let clock = ManualClock::new();
+    let scaled = ScaledClock::new(Arc::new(clock.clone()), 60.0);
+    let start = scaled.now();
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(scaled.elapsed_since(start), Duration::from_secs(120));
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_scaled_clock_rejects_nan() {
+    ScaledClock::new(Arc::new(ManualClock::new()), f64::NAN);
+  }
+}
@@ -0,0 +1,240 @@
+//! Deliberately misbehave on the wire so that client resilience (retries, CTS recovery, CRC
+//! handling) can be exercised automatically rather than by yanking cables.
+
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+use log::debug;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use balboa_spa_messages::frame_encoder::FrameEncoder;
+use balboa_spa_messages::framed_writer::{FramedWriteError, FramedWriter};
+use balboa_spa_messages::message::Message;
+use crate::malformed_frames;
+use crate::malformed_frames::MalformedFrameKind;
+
+/// Percentage chance of each fault being applied to an outbound frame.  At most one fault is
+/// applied per frame, checked in the order the fields are declared.  Clear-to-send timing stress
+/// is applied independently of frame faults.
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjectionConfig {
+  drop_percent: u8,
+  corrupt_crc_percent: u8,
+  delay_percent: u8,
+  delay: Duration,
+  duplicate_percent: u8,
+  malformed_percent: u8,
+  cts_poll_jitter: Duration,
+  cts_window: Option<(Duration, Duration)>,
+  seed: Option<u64>,
+}
+
+impl FaultInjectionConfig {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn set_drop_percent(mut self, percent: u8) -> Self {
+    self.drop_percent = percent.min(100);
+    self
+  }
+
+  pub fn set_corrupt_crc_percent(mut self, percent: u8) -> Self {
+    self.corrupt_crc_percent = percent.min(100);
+    self
+  }
+
+  /// Hold back replies by `delay` before sending them, typically set longer than the
+  /// clear-to-send window to provoke timeouts on the client side.
+  pub fn set_delay(mut self, percent: u8, delay: Duration) -> Self {
+    self.delay_percent = percent.min(100);
+    self.delay = delay;
+    self
+  }
+
+  pub fn set_duplicate_percent(mut self, percent: u8) -> Self {
+    self.duplicate_percent = percent.min(100);
+    self
+  }
+
+  /// Precede frames with a random sequence from [crate::malformed_frames].  Unlike the other
+  /// faults the real frame is still sent afterward.
+  pub fn set_malformed_percent(mut self, percent: u8) -> Self {
+    self.malformed_percent = percent.min(100);
+    self
+  }
+
+  /// Hold back each ClearToSend poll by a random amount up to `max_jitter`, simulating a board
+  /// that is busy with other work and polls irregularly.
+  pub fn set_cts_poll_jitter(mut self, max_jitter: Duration) -> Self {
+    self.cts_poll_jitter = max_jitter;
+    self
+  }
+
+  /// Choose a random reply window between `min` and `max` for each clear-to-send grant,
+  /// overriding the fixed window from [crate::main_board::MainBoard::set_clear_to_send_policy].
+  pub fn set_cts_window(mut self, min: Duration, max: Duration) -> Self {
+    self.cts_window = Some((min, max.max(min)));
+    self
+  }
+
+  /// Fix the random seed so that a failing test run can be reproduced exactly.
+  pub fn set_seed(mut self, seed: u64) -> Self {
+    self.seed = Some(seed);
+    self
+  }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum FrameFault {
+  Drop,
+  CorruptCrc,
+  Delay(Duration),
+  Duplicate,
+  Malformed,
+}
+
+#[derive(Debug)]
+pub(crate) struct FaultInjector {
+  config: FaultInjectionConfig,
+  rng: StdRng,
+  encoder: FrameEncoder,
+}
+
+impl FaultInjector {
+  pub fn new(config: FaultInjectionConfig) -> Self {
+    let rng = match config.seed {
+      Some(seed) => StdRng::seed_from_u64(seed),
+      None => StdRng::from_entropy(),
+    };
+    Self {
+      config,
+      rng,
+      encoder: FrameEncoder::new(),
+    }
+  }
+
+  pub fn write<W: Write>(
+      &mut self,
+      writer: &mut FramedWriter<W>,
+      message: &Message,
+  ) -> Result<(), FramedWriteError> {
+    match self.next_fault() {
+      None => writer.write(message),
+      Some(fault) => {
+        debug!("Injecting {fault:?} for {message:?}");
+        match fault {
+          FrameFault::Drop => Ok(()),
+          FrameFault::CorruptCrc => {
+            let mut frame = self.encoder.encode(message)?;
+            let crc_index = frame.len() - 2;
+            frame[crc_index] ^= 0xff;
+            writer.write_frame(&frame)?;
+            Ok(())
+          }
+          FrameFault::Delay(delay) => {
+            thread::sleep(delay);
+            writer.write(message)
+          }
+          FrameFault::Duplicate => {
+            writer.write(message)?;
+            writer.write(message)
+          }
+          FrameFault::Malformed => {
+            let kinds = &MalformedFrameKind::ALL;
+            let kind = kinds[self.rng.gen_range(0..kinds.len())];
+            debug!("Interleaving {kind:?}");
+            writer.write_frame(&malformed_frames::generate(kind, message)?)?;
+            writer.write(message)
+          }
+        }
+      }
+    }
+  }
+
+  pub fn cts_poll_delay(&mut self) -> Option<Duration> {
+    let max = self.config.cts_poll_jitter;
+    if max.is_zero() {
+      return None;
+    }
+    Some(self.random_duration(Duration::ZERO, max))
+  }
+
+  pub fn cts_window(&mut self) -> Option<Duration> {
+    self.config.cts_window.map(|(min, max)| self.random_duration(min, max))
+  }
+
+  fn random_duration(&mut self, min: Duration, max: Duration) -> Duration {
+    let micros = self.rng.gen_range(min.as_micros()..=max.as_micros());
+    Duration::from_micros(u64::try_from(micros).unwrap_or(u64::MAX))
+  }
+
+  fn next_fault(&mut self) -> Option<FrameFault> {
+    let config = &self.config;
+    let candidates = [
+      (config.drop_percent, FrameFault::Drop),
+      (config.corrupt_crc_percent, FrameFault::CorruptCrc),
+      (config.delay_percent, FrameFault::Delay(config.delay)),
+      (config.duplicate_percent, FrameFault::Duplicate),
+      (config.malformed_percent, FrameFault::Malformed),
+    ];
+    candidates.into_iter()
+        .find(|(percent, _)| *percent > 0 && self.rng.gen_range(0..100) < *percent)
+        .map(|(_, fault)| fault)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::channel::Channel;
+  use balboa_spa_messages::framed_reader::FramedReader;
+  use balboa_spa_messages::message_types::MessageType;
+  use super::*;
+
+  fn write_one(config: FaultInjectionConfig) -> Vec<u8> {
+    let mut injector = FaultInjector::new(config.set_seed(1));
+    let mut writer = FramedWriter::new(Vec::new());
+    let message = MessageType::ClearToSend().to_message(Channel::Client(0x10)).unwrap();
+    injector.write(&mut writer, &message).unwrap();
+    writer.into_inner()
+  }
+
+  #[test]
+  fn test_faults() {
+    let clean = write_one(FaultInjectionConfig::new());
+    assert!(!clean.is_empty());
+
+    assert!(write_one(FaultInjectionConfig::new().set_drop_percent(100)).is_empty());
+
+    let duplicated = write_one(FaultInjectionConfig::new().set_duplicate_percent(100));
+    assert_eq!(duplicated, [clean.clone(), clean.clone()].concat());
+
+    let corrupted = write_one(FaultInjectionConfig::new().set_corrupt_crc_percent(100));
+    assert_eq!(corrupted.len(), clean.len());
+    assert_ne!(corrupted, clean);
+    let mut reader = FramedReader::new(corrupted.as_slice());
+    assert!(reader.next_message().is_err());
+
+    let malformed = write_one(FaultInjectionConfig::new().set_malformed_percent(100));
+    assert!(malformed.ends_with(&clean));
+    assert!(malformed.len() > clean.len());
+  }
+
+  #[test]
+  fn test_cts_timing() {
+    let mut injector = FaultInjector::new(FaultInjectionConfig::new().set_seed(1));
+    assert_eq!(injector.cts_poll_delay(), None);
+    assert_eq!(injector.cts_window(), None);
+
+    let (min, max) = (Duration::from_millis(5), Duration::from_millis(30));
+    let mut injector = FaultInjector::new(FaultInjectionConfig::new()
+        .set_cts_poll_jitter(max)
+        .set_cts_window(min, max)
+        .set_seed(1));
+    for _ in 0..100 {
+      assert!(injector.cts_poll_delay().unwrap() <= max);
+      let window = injector.cts_window().unwrap();
+      assert!(window >= min && window <= max);
+    }
+  }
+}
@@ -3,6 +3,13 @@
 
 pub mod main_board;
 pub mod mock_spa;
+pub mod scenario;
+pub mod fault_injection;
+pub mod malformed_frames;
+pub mod clock;
+pub mod thermal;
+pub mod capture;
+pub mod metrics;
 mod channel_tracker;
 mod timer_tracker;
 mod clear_to_send_tracker;
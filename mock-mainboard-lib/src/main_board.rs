@@ -3,35 +3,56 @@
 
 use std::{mem, thread};
 use std::borrow::{Borrow, BorrowMut};
+use std::collections::HashMap;
 use std::io::{Read, Write};
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, SendError, SyncSender};
+use std::sync::{Arc, mpsc};
+use std::sync::mpsc::{Receiver, SendError, Sender, SyncSender};
 use std::time::{Duration, Instant};
 
 use anyhow::anyhow;
 use log::{debug, error, info, trace, warn};
-use timer::{Guard, Timer};
 
 use balboa_spa_messages::channel::Channel;
 use balboa_spa_messages::framed_reader::FramedReader;
 use balboa_spa_messages::framed_writer::FramedWriter;
 use balboa_spa_messages::message::{EncodeError, Message};
-use balboa_spa_messages::message_types::{HeaterType, HeaterVoltage, InformationResponseMessage, MessageType, PayloadEncodeError, Settings0x04ResponseMessage, SettingsRequestMessage, SoftwareVersion};
+use balboa_spa_messages::message_types::{FaultCode, HeaterType, HeaterVoltage, InformationResponseMessage, ItemCode, MessageType, MessageTypeKind, PayloadEncodeError, SetPreferenceMessage, SettingsRequestMessage, SoftwareVersion};
 use balboa_spa_messages::parsed_enum::ParsedEnum;
+use balboa_spa_messages::temperature::SetTemperature;
 
 use crate::channel_tracker::{ChannelTracker, CtsFailureAction, DeviceKey};
-use crate::channel_manager::{ChannelManager, CtsEnforcementPolicy};
+use crate::channel_manager::{ChannelManager, CtsChannelStats, CtsEnforcementPolicy};
 use crate::clear_to_send_tracker::{ClearToSendTracker, NoCtsReason, SendMessage, SendMessageFactory, TrySendMessageError};
-use common_lib::message_logger::{MessageDirection, MessageLogger};
-use crate::mock_spa::{MockSpa, MockSpaState};
+use common_lib::message_logger::{MessageDirection, MessageLogger, MessageSink};
+use crate::mock_spa::{GfciTestConfig, MockSpa, MockSpaState};
+use crate::fault_injection::{FaultInjectionConfig, FaultInjector};
+use crate::metrics::{ErrorClass, MessageMetrics};
+use crate::capture::{CapturedFrame, SessionReplay};
+use crate::clock::{system_clock, Clock, ScaledClock, SharedClock, SystemClock, TimerGuard, TimerService};
+use crate::scenario;
+use crate::thermal::ThermalConfig;
+use crate::scenario::{Scenario, ScenarioAction, ScenarioRunner};
 use crate::timer_tracker::{TickAction, TimerTracker};
 use common_lib::transport::Transport;
 
 pub struct MainBoard<R, W> {
   framed_reader: FramedReader<R>,
   framed_writer: FramedWriter<W>,
-  init_delay: Option<Duration>,
+  scenario: Scenario,
   channel_manager: Option<ChannelManager>,
+  gfci_test_config: Option<GfciTestConfig>,
+  hold_duration: Option<Duration>,
+  thermal_config: Option<ThermalConfig>,
+  fault_injection: Option<FaultInjectionConfig>,
+  max_cts_failures: Option<usize>,
+  event_listener: Option<Sender<MainBoardEvent>>,
+  message_sink: Option<Arc<dyn MessageSink>>,
+  mock_spa: Option<MockSpa>,
+  replay: Option<Vec<CapturedFrame>>,
+  wifi_module_enabled: bool,
+  clock: SharedClock,
+  timer_service: Arc<dyn TimerService>,
+  time_acceleration: f64,
 }
 
 impl<R, W> MainBoard<R, W>
@@ -46,13 +67,34 @@ where
     Self {
       framed_reader,
       framed_writer,
-      init_delay: None,
+      scenario: Scenario::new(),
       channel_manager: None,
+      gfci_test_config: None,
+      hold_duration: None,
+      thermal_config: None,
+      fault_injection: None,
+      max_cts_failures: None,
+      event_listener: None,
+      message_sink: None,
+      mock_spa: None,
+      replay: None,
+      wifi_module_enabled: false,
+      clock: system_clock(),
+      timer_service: Arc::new(SystemClock),
+      time_acceleration: 1.0,
     }
   }
 
+  /// Shorthand for a [Scenario] step that completes initialization after the given delay.
   pub fn set_init_delay(mut self, init_delay: Duration) -> Self {
-    self.init_delay = Some(init_delay);
+    self.scenario = self.scenario.at(init_delay, scenario::complete_init());
+    self
+  }
+
+  /// Script changes to the mock spa over time, starting when the runner starts.  Steps are
+  /// combined with any added by [Self::set_init_delay].
+  pub fn set_scenario(mut self, scenario: Scenario) -> Self {
+    self.scenario = scenario.merge(self.scenario);
     self
   }
 
@@ -61,29 +103,150 @@ where
     self
   }
 
+  /// Replace the default mock spa, typically one created with
+  /// [crate::mock_spa::MockSpaBuilder] to simulate a different model's equipment.
+  pub fn set_mock_spa(mut self, mock_spa: MockSpa) -> Self {
+    self.mock_spa = Some(mock_spa);
+    self
+  }
+
+  /// Drive all timing (ticks, CTS windows, hold timers, scenarios, etc) from the given clock,
+  /// most usefully a [crate::clock::ManualClock] so tests can advance time explicitly.
+  pub fn set_clock<C: Clock + TimerService + Clone + 'static>(mut self, clock: C) -> Self {
+    self.clock = Arc::new(clock.clone());
+    self.timer_service = Arc::new(clock);
+    self
+  }
+
+  /// Replay a previously captured session (see [crate::capture]) at its original timing, in
+  /// place of the status updates the mock spa would normally send.  Channel assignment still
+  /// happens as usual so that a client under test can join, and captured frames which would
+  /// interfere with that client are skipped.  Timing is only as precise as the main tick.
+  pub fn set_replay(mut self, frames: Vec<CapturedFrame>) -> Self {
+    self.replay = Some(frames);
+    self
+  }
+
+  /// Poll and answer a Wi-Fi module on its fixed [Channel::WifiModule] channel, as a real main
+  /// board would, so that one can be tested without first negotiating a client channel.
+  pub fn set_wifi_module_enabled(mut self, enabled: bool) -> Self {
+    self.wifi_module_enabled = enabled;
+    self
+  }
+
+  /// Run the mock spa's notion of time (init delays and other scenario steps, hold timers, time
+  /// of day and therefore filter cycles, heating, etc) `factor` times faster than real time so
+  /// that long-running behaviour can be validated in seconds.  Protocol timing such as
+  /// clear-to-send windows is unaffected.
+  ///
+  /// Panics unless `factor` is finite and positive.
+  pub fn set_time_acceleration(mut self, factor: f64) -> Self {
+    assert!(factor.is_finite() && factor > 0.0, "Time acceleration must be finite and positive, got {factor}");
+    self.time_acceleration = factor;
+    self
+  }
+
+  pub fn set_gfci_test_config(mut self, config: GfciTestConfig) -> Self {
+    self.gfci_test_config = Some(config);
+    self
+  }
+
+  pub fn set_hold_duration(mut self, hold_duration: Duration) -> Self {
+    self.hold_duration = Some(hold_duration);
+    self
+  }
+
+  /// Adjust how quickly the simulated water heats and cools.
+  pub fn set_thermal_config(mut self, config: ThermalConfig) -> Self {
+    self.thermal_config = Some(config);
+    self
+  }
+
+  /// Randomly drop, corrupt, delay, or duplicate outbound frames, or stress clear-to-send timing,
+  /// to test client resilience.
+  pub fn set_fault_injection(mut self, config: FaultInjectionConfig) -> Self {
+    self.fault_injection = Some(config);
+    self
+  }
+
+  /// Reclaim a client's channel after this many consecutive clear-to-send failures, most
+  /// commonly because the client went away without telling us.
+  pub fn set_max_cts_failures(mut self, max_cts_failures: usize) -> Self {
+    self.max_cts_failures = Some(max_cts_failures);
+    self
+  }
+
+  /// Receive notable state changes inside the board, such as channel evictions, so that tests
+  /// can assert on them.
+  pub fn set_event_listener(mut self, listener: Sender<MainBoardEvent>) -> Self {
+    self.event_listener = Some(listener);
+    self
+  }
+
+  /// Record all traffic to the given sink instead of the log, such as a
+  /// [common_lib::message_logger::JsonLinesSink] or [crate::capture::CaptureSink].
+  pub fn set_message_sink(mut self, sink: Arc<dyn MessageSink>) -> Self {
+    self.message_sink = Some(sink);
+    self
+  }
+
   pub fn into_runner(self) -> (ControlHandle, Runner<R, W>) {
     let (tx, rx) = mpsc::sync_channel(32);
-    let state = MainBoardState {
+    let mut state = MainBoardState {
       channel_manager: self.channel_manager.unwrap_or_default(),
+      scenario: self.scenario.into_runner(),
+      mock_spa: self.mock_spa.unwrap_or_default(),
+      replay: self.replay.map(SessionReplay::new),
       ..Default::default()
     };
+    if let Some(config) = self.gfci_test_config {
+      state.mock_spa.set_gfci_test_config(config);
+    }
+    if let Some(hold_duration) = self.hold_duration {
+      state.mock_spa.set_hold_duration(hold_duration);
+    }
+    if let Some(config) = self.thermal_config {
+      state.mock_spa.set_thermal_config(config);
+    }
+    if let Some(max_cts_failures) = self.max_cts_failures {
+      state.channel_manager.set_max_cts_failures(max_cts_failures);
+    }
+    state.channel_manager.set_wifi_module_enabled(self.wifi_module_enabled);
+    let spa_clock: SharedClock = if self.time_acceleration == 1.0 {
+      self.clock.clone()
+    } else {
+      Arc::new(ScaledClock::new(self.clock.clone(), self.time_acceleration))
+    };
+    state.channel_manager.set_clock(self.clock.clone());
+    state.mock_spa.set_clock(spa_clock.clone());
     let message_reader = MessageReader {
       message_tx: tx.clone(),
       framed_reader: self.framed_reader,
     };
     let timer_setup = TimerSetup {
+      timer_service: self.timer_service.clone(),
       timer_tx: tx.clone(),
-      init_delay: self.init_delay,
       main_tick_hz: state.timer_tracker.total_ticks_per_cycle(),
     };
+    let message_logger = match self.message_sink {
+      Some(sink) => MessageLogger::with_sink(module_path!(), sink),
+      None => MessageLogger::new(module_path!()),
+    };
     let event_handler = EventHandler {
       event_rx: rx,
       framed_writer: self.framed_writer,
-      message_logger: MessageLogger::new(module_path!()),
+      fault_injector: self.fault_injection.map(FaultInjector::new),
+      event_listener: self.event_listener,
+      clock: self.clock,
+      spa_clock,
+      timer_service: self.timer_service,
+      event_tx: tx.clone(),
+      message_logger: message_logger.clone(),
+      metrics: MessageMetrics::new(),
       state,
     };
 
-    let shutdown_handle = ControlHandle { tx };
+    let shutdown_handle = ControlHandle { tx, message_logger };
     let runner = Runner { message_reader, timer_setup, event_handler };
     (shutdown_handle, runner)
   }
@@ -91,6 +254,7 @@ where
 
 pub struct ControlHandle {
   tx: SyncSender<Event>,
+  message_logger: MessageLogger,
 }
 
 impl ControlHandle {
@@ -101,6 +265,67 @@ impl ControlHandle {
   pub fn request_shutdown(&self) {
     let _ = self.tx.send(Event::Shutdown);
   }
+
+  /// The board's traffic logger, whose [LogPolicy](common_lib::message_logger::LogPolicy)s can
+  /// be adjusted while running to quiet down or focus on particular message kinds.
+  pub fn message_logger(&self) -> &MessageLogger {
+    &self.message_logger
+  }
+
+  fn send(&self, event: Event) -> anyhow::Result<()> {
+    // Events aren't necessarily Sync so can't be carried by anyhow::Error.
+    self.tx.send(event).map_err(|_| anyhow!("Main board is no longer running"))
+  }
+
+  /// Change the mock spa's state while running, for example from a test or simulator UI.  The
+  /// action is applied on the event handler thread in between handling messages, so it can never
+  /// be observed half applied.  See [crate::scenario] for the available actions.
+  pub fn apply(&self, action: ScenarioAction) -> anyhow::Result<()> {
+    self.send(Event::ApplyAction(action))?;
+    Ok(())
+  }
+
+  pub fn raise_fault(&self, fault_code: FaultCode) -> anyhow::Result<()> {
+    self.apply(scenario::fault(fault_code))
+  }
+
+  /// Force the run state, see [scenario::set_state] for caveats.
+  pub fn set_run_state(&self, state: MockSpaState) -> anyhow::Result<()> {
+    self.apply(scenario::set_state(state))
+  }
+
+  pub fn set_temperature(&self, temperature: SetTemperature) -> anyhow::Result<()> {
+    self.apply(scenario::set_temperature(temperature))
+  }
+
+  /// Toggle a pump, light, etc exactly as if a client had requested it.
+  pub fn toggle_item(&self, item_code: ItemCode) -> anyhow::Result<()> {
+    self.apply(scenario::toggle(item_code))
+  }
+
+  /// Snapshot of per-channel clear-to-send statistics, useful for measuring how well a client's
+  /// turnaround time fits within the CTS window.
+  pub fn cts_stats(&self) -> anyhow::Result<HashMap<Channel, CtsChannelStats>> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    self.send(Event::QueryCtsStats(reply_tx))?;
+    Ok(reply_rx.recv()?)
+  }
+
+  /// Number of ClearToSend polls scheduled for each channel so far, for asserting that clients
+  /// are polled round-robin and none are starved.  Compare with [Self::cts_stats] to see how
+  /// each client used its turns.
+  pub fn cts_schedule_counts(&self) -> anyhow::Result<HashMap<Channel, u64>> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    self.send(Event::QueryCtsScheduleCounts(reply_tx))?;
+    Ok(reply_rx.recv()?)
+  }
+
+  /// Counts of messages received and sent by type, as well as handling errors by class.
+  pub fn metrics(&self) -> anyhow::Result<MessageMetrics> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    self.send(Event::QueryMetrics(reply_tx))?;
+    Ok(reply_rx.recv()?)
+  }
 }
 
 impl Drop for ControlHandle {
@@ -182,48 +407,49 @@ impl<R: Read + Send> MessageReader<R> {
 }
 
 struct TimerSetup {
+  timer_service: Arc<dyn TimerService>,
   timer_tx: SyncSender<Event>,
-  init_delay: Option<Duration>,
   main_tick_hz: usize,
 }
 
 impl TimerSetup {
   pub fn setup(self) -> anyhow::Result<TimerHold> {
-    let timer = Timer::new();
     let mut guards = Vec::new();
 
     let main_tick_tx = self.timer_tx.clone();
     let main_tick_hz = u64::try_from(self.main_tick_hz)?;
     let main_tick_duration = Duration::from_millis(1000 / main_tick_hz);
     info!("Scheduling main timer @ {main_tick_hz} Hz...");
-    let guard = timer.schedule_repeating(
-        chrono::Duration::from_std(main_tick_duration)?, move || {
+    let guard = self.timer_service.schedule_repeating(main_tick_duration, Box::new(move || {
       let _ = main_tick_tx.send(Event::TimerTick(TimerId::SendTickMessage));
-    });
+    }))?;
     guards.push(guard);
 
-    if let Some(init_delay) = self.init_delay {
-      let init_tx = self.timer_tx;
-      let guard = timer.schedule_with_delay(
-          chrono::Duration::from_std(init_delay)?, move || {
-        let _ = init_tx.send(Event::InitFinished);
-      });
-      guards.push(guard);
-    }
-
-    Ok(TimerHold { _timer: timer, _guards: guards })
+    Ok(TimerHold { _guards: guards })
   }
 }
 
 struct TimerHold {
-  _guards: Vec<Guard>,
-  _timer: Timer,
+  _guards: Vec<TimerGuard>,
 }
 
 struct EventHandler<W> {
   framed_writer: FramedWriter<W>,
+  fault_injector: Option<FaultInjector>,
+  event_listener: Option<Sender<MainBoardEvent>>,
+  clock: SharedClock,
+
+  /// Possibly accelerated clock for the mock spa and scenario, see
+  /// [MainBoard::set_time_acceleration].
+  spa_clock: SharedClock,
+
+  /// Schedules jittered clear-to-send messages, see [EventHandler::delayed_cts].
+  timer_service: Arc<dyn TimerService>,
+  event_tx: SyncSender<Event>,
+
   event_rx: Receiver<Event>,
   message_logger: MessageLogger,
+  metrics: MessageMetrics,
   state: MainBoardState,
 }
 
@@ -232,6 +458,13 @@ struct MainBoardState {
   mock_spa: MockSpa,
   channel_manager: ChannelManager,
   timer_tracker: TimerTracker,
+  scenario: ScenarioRunner,
+  probe_existing_next: bool,
+  gfci_test_channel: Option<Channel>,
+  replay: Option<SessionReplay>,
+
+  /// Clear-to-send held back by jitter, during which nothing else may be sent.
+  delayed_cts: Option<TimerGuard>,
 }
 
 impl<W: Write + Send> EventHandler<W> {
@@ -242,6 +475,9 @@ impl<W: Write + Send> EventHandler<W> {
       self.log_event(&event);
 
       if let Err(e) = self.handle_event(event) {
+        if let Some(class) = ErrorClass::from_handling_error(&e) {
+          self.metrics.record_error(class);
+        }
         match e {
           HandlingError::ShutdownRequested => {
             info!("Graceful shutdown requested...");
@@ -254,11 +490,28 @@ impl<W: Write + Send> EventHandler<W> {
           _ => error!("Got {e:?}"),
         }
       }
+
+      self.publish_evictions();
     }
 
     Ok(())
   }
 
+  fn publish_evictions(&mut self) {
+    for evicted in self.channel_manager_mut().take_evicted() {
+      let event = MainBoardEvent::ChannelEvicted {
+        channel: evicted.channel,
+        device_type: evicted.device_key.device_type,
+        client_hash: evicted.device_key.client_hash,
+        idle: evicted.idle,
+      };
+      info!("{event:?}");
+      if let Some(listener) = &self.event_listener {
+        let _ = listener.send(event);
+      }
+    }
+  }
+
   /// Log a received event, deciding which log level to use based on verbosity in practice in
   /// the protocol.
   fn log_event(&self, event: &Event) {
@@ -268,14 +521,21 @@ impl<W: Write + Send> EventHandler<W> {
       }
       Event::ReadError(_) => error!("{event:?}"),
       Event::InitFinished => info!("{event:?}"),
+      Event::ApplyAction(_) => info!("{event:?}"),
       Event::TimerTick(_) => trace!("{event:?}"),
       Event::Shutdown => debug!("{event:?}"),
+      Event::QueryCtsStats(_) |
+      Event::QueryCtsScheduleCounts(_) |
+      Event::QueryMetrics(_) => debug!("{event:?}"),
     }
   }
 
   fn handle_event(&mut self, event: Event) -> Result<(), HandlingError> {
     match event {
-      Event::ReceivedMessage(message) => self.handle_message(message)?,
+      Event::ReceivedMessage(message) => {
+        self.metrics.record_received(&message);
+        self.handle_message(message)?
+      }
       Event::ReadError(e) => {
         return Err(HandlingError::FatalError(format!("Read error: {e:?}")))
       }
@@ -283,7 +543,17 @@ impl<W: Write + Send> EventHandler<W> {
       Event::InitFinished => {
         self.state.mock_spa.init_finished();
       },
+      Event::ApplyAction(action) => action.apply(&mut self.state.mock_spa),
       Event::Shutdown => return Err(HandlingError::ShutdownRequested),
+      Event::QueryCtsStats(reply_tx) => {
+        let _ = reply_tx.send(self.channel_manager().cts_stats());
+      }
+      Event::QueryCtsScheduleCounts(reply_tx) => {
+        let _ = reply_tx.send(self.state.timer_tracker.cts_schedule_counts().clone());
+      }
+      Event::QueryMetrics(reply_tx) => {
+        let _ = reply_tx.send(self.metrics.clone());
+      }
     }
     Ok(())
   }
@@ -316,6 +586,11 @@ impl<W: Write + Send> EventHandler<W> {
       smf: SendMessageFactory,
       parsed: MessageType
   ) -> Result<Option<SendMessage>, HandlingError> {
+    if self.state.mock_spa.is_locked_out(&parsed) {
+      warn!("Ignoring {:?} while spa is locked", MessageTypeKind::from(&parsed));
+      return Ok(None);
+    }
+
     let reply = match parsed {
       MessageType::ChannelAssignmentRequest { device_type, client_hash } => {
         let key = DeviceKey { device_type, client_hash };
@@ -332,21 +607,45 @@ impl<W: Write + Send> EventHandler<W> {
         info!("Got channel assignment ack on channel={src_channel:?}");
         None
       }
+      MessageType::ExistingClientResponse { unknown } => {
+        // Payload appears to mirror ChannelAssignmentRequest, device type followed by hash.
+        let key = match unknown.as_slice() {
+          &[device_type, hash_hi, hash_lo] => DeviceKey {
+            device_type,
+            client_hash: u16::from_be_bytes([hash_hi, hash_lo]),
+          },
+          _ => return Err(HandlingError::ClientUnsupported(
+            format!("Malformed existing client response: {unknown:02x?}"))),
+        };
+        self.channel_manager_mut().reclaim_channel(src_channel, key)?;
+        info!("Reclaimed {src_channel:?} for {key:?}");
+        None
+      }
       MessageType::NothingToSend() => {
         // Do nothing, general handling already removed the authorized sender state.
         None
       }
       MessageType::ToggleItemRequest { item_code, dummy1 } => {
         info!("Got request to toggle {item_code:?}, dummy1={dummy1}");
+        let toggled = item_code.as_ref()
+            .map(|code| self.state.mock_spa.toggle_item(*code))
+            .unwrap_or(false);
+        if !toggled {
+          warn!("Ignoring toggle request for unsupported item {item_code:?}");
+        }
         None
       }
       MessageType::SetTemperatureRequest { temperature } => {
         info!("Got set temp request: temperature={temperature:?}");
-        self.state.mock_spa.adjust_temperature(temperature);
+        let applied = self.state.mock_spa.adjust_temperature(temperature);
+        info!("Set temperature is now {applied}");
         None
       }
-      MessageType::SetTimeRequest { time } => {
-        info!("Got set time request: time={time:?}");
+      MessageType::SetTimeRequest { time, clock_mode } => {
+        info!("Got set time request: time={time:?}, clock_mode={clock_mode:?}");
+        self.state.mock_spa.set_time(time);
+        // Real panels change the display mode this way too.
+        self.state.mock_spa.set_preference(&SetPreferenceMessage::ClockMode(clock_mode));
         None
       }
       MessageType::SettingsRequest(settings) => {
@@ -373,23 +672,39 @@ impl<W: Write + Send> EventHandler<W> {
               self.state.mock_spa.as_fault_log(entry_num)
             ).to_message(src_channel)?))
           }
+          SettingsRequestMessage::GfciTest => {
+            // Reply is deferred until the test completes, see handle_timer.
+            self.state.mock_spa.start_gfci_test();
+            self.state.gfci_test_channel = Some(src_channel);
+            None
+          }
+          SettingsRequestMessage::Preferences => {
+            Some(smf.no_reply(MessageType::PreferencesResponse(
+              self.state.mock_spa.as_preferences()
+            ).to_message(src_channel)?))
+          }
+          SettingsRequestMessage::FilterCycles => {
+            Some(smf.no_reply(MessageType::FilterCycles {
+              cycles: self.state.mock_spa.as_filter_cycles(),
+            }.to_message(src_channel)?))
+          }
           SettingsRequestMessage::Settings0x04 => {
             Some(smf.no_reply(MessageType::Settings0x04Response(
               self.state.mock_spa.as_settings0x04()
             ).to_message(src_channel)?))
           }
-          n => {
-            error!("Unhandled settings request: {n:?}");
-            None
-          }
         }
       }
       MessageType::FilterCycles { cycles } => {
         info!("Got filter cycles: cycles={cycles:?}");
+        self.state.mock_spa.set_filter_cycles(cycles);
         None
       }
       MessageType::SetPreferenceRequest(prefs) => {
         info!("Got set preference request: prefs={prefs:?}");
+        if !self.state.mock_spa.set_preference(&prefs) {
+          warn!("Ignoring unsupported preference {prefs:?}");
+        }
         None
       }
       MessageType::ChangeSetupRequest { setup_number } => {
@@ -398,6 +713,7 @@ impl<W: Write + Send> EventHandler<W> {
       }
       MessageType::LockRequest(lock) => {
         info!("Got lock request: lock={lock:?}");
+        self.state.mock_spa.lock(&lock);
         None
       }
       MessageType::ToggleTestSettingRequest(test_setting) => {
@@ -415,7 +731,23 @@ impl<W: Write + Send> EventHandler<W> {
   fn handle_timer(&mut self, timer_id: TimerId) -> Result<(), HandlingError> {
     match timer_id {
       TimerId::SendTickMessage => {
+        self.state.scenario.poll(self.spa_clock.as_ref(), &mut self.state.mock_spa);
+        self.state.mock_spa.tick();
+        if self.state.delayed_cts.is_some() {
+          return Ok(());
+        }
+        let replaying = self.send_replayed_frames()?;
         if let Some(smf) = self.channel_manager_mut().start_send_message()? {
+          // Steal this tick to deliver a finished GFCI test result rather than waiting for the
+          // client's next CTS slot.
+          if let Some(result) = self.state.mock_spa.poll_gfci_test() {
+            info!("GFCI test finished: result={result:?}");
+            if let Some(channel) = self.state.gfci_test_channel.take() {
+              let reply = MessageType::GfciTestResponse { result: ParsedEnum::new(result) }
+                  .to_message(channel)?;
+              return self.send_message(smf.no_reply(reply));
+            }
+          }
           let channel_manager = &self.state.channel_manager;
           let tick_action = self.state.timer_tracker.next_action(|| {
             channel_manager
@@ -425,10 +757,22 @@ impl<W: Write + Send> EventHandler<W> {
           });
           let message = match tick_action {
             TickAction::NewClientClearToSend => {
+              // Alternate with probing for existing clients while they have channels to
+              // reclaim so that new clients aren't starved.
+              let probe_existing = self.state.probe_existing_next &&
+                  self.state.channel_manager.has_reclaimable_channels();
+              self.state.probe_existing_next = !self.state.probe_existing_next;
+              let message = match probe_existing {
+                true => MessageType::ExistingClientRequest(),
+                false => MessageType::NewClientClearToSend(),
+              };
               Some(smf.maybe_expect_reply(
-                MessageType::NewClientClearToSend()
-                    .to_message(Channel::MulticastChannelAssignment)?))
+                message.to_message(Channel::MulticastChannelAssignment)?))
             },
+            TickAction::StatusUpdate if replaying => {
+              // The captured session supplies its own status updates.
+              None
+            }
             TickAction::StatusUpdate => {
               Some(smf.no_reply(
                 MessageType::StatusUpdate(self.state.mock_spa.as_status())
@@ -436,6 +780,11 @@ impl<W: Write + Send> EventHandler<W> {
             }
             TickAction::ClearToSend { channel } => {
               if self.channel_manager().is_channel_allocated(&channel) {
+                if let Some(delay) = self.fault_injector.as_mut().and_then(|i| i.cts_poll_delay()) {
+                  trace!("Jittering CTS for {channel:?} by {delay:?}");
+                  self.delay_cts(channel, delay)?;
+                  return Ok(());
+                }
                 Some(smf.expect_reply(MessageType::ClearToSend().to_message(channel)?))
               } else {
                 // This happens if the channel is removed while issuing CTS messages, e.g.
@@ -454,14 +803,78 @@ impl<W: Write + Send> EventHandler<W> {
           }
         }
       }
+      TimerId::DelayedClearToSend(channel) => {
+        self.state.delayed_cts = None;
+        if !self.channel_manager().is_channel_allocated(&channel) {
+          debug!("Dropping delayed CTS for removed channel={channel:?}");
+          return Ok(());
+        }
+        if let Some(smf) = self.channel_manager_mut().start_send_message()? {
+          self.send_message(smf.expect_reply(MessageType::ClearToSend().to_message(channel)?))?;
+        }
+      }
     }
     Ok(())
   }
 
+  fn delay_cts(&mut self, channel: Channel, delay: Duration) -> Result<(), HandlingError> {
+    let tx = self.event_tx.clone();
+    let guard = self.timer_service
+        .schedule_once(delay, Box::new(move || {
+          let _ = tx.send(Event::TimerTick(TimerId::DelayedClearToSend(channel)));
+        }))
+        .map_err(|e| HandlingError::FatalError(format!("Failed to delay CTS: {e}")))?;
+    self.state.delayed_cts = Some(guard);
+    Ok(())
+  }
+
+  /// Send any captured frames that have come due, returning whether a replay is still active.
+  fn send_replayed_frames(&mut self) -> Result<bool, HandlingError> {
+    let clock = self.clock.clone();
+    loop {
+      let Some(replay) = &mut self.state.replay else {
+        return Ok(false);
+      };
+      let Some(message) = replay.peek_due(clock.as_ref()).cloned() else {
+        break;
+      };
+      // Channel assignment is ours to perform for the client under test, and anything on its
+      // channel would confuse it.
+      let channel = &message.channel;
+      if *channel == Channel::MulticastChannelAssignment ||
+          self.state.channel_manager.is_channel_allocated(channel) {
+        trace!("Skipping replayed {message:?}");
+        replay.pop();
+        continue;
+      }
+      match self.state.channel_manager.start_send_message()? {
+        Some(smf) => {
+          replay.pop();
+          self.send_message(smf.no_reply(message))?;
+        }
+        // Try again next tick once our client has had its turn.
+        None => break,
+      }
+    }
+    if self.state.replay.as_ref().is_some_and(|r| r.is_finished()) {
+      info!("Session replay finished");
+      self.state.replay = None;
+      if let Some(listener) = &self.event_listener {
+        let _ = listener.send(MainBoardEvent::ReplayFinished);
+      }
+      return Ok(false);
+    }
+    Ok(true)
+  }
+
   fn send_message(&mut self, send: SendMessage) -> Result<(), HandlingError> {
     self.message_logger.log(MessageDirection::Outbound, &send.message);
 
+    if let Some(window) = self.fault_injector.as_mut().and_then(|i| i.cts_window()) {
+      self.channel_manager_mut().set_cts_window(window);
+    }
     self.channel_manager_mut().handle_presend(&send);
+    self.metrics.record_sent(&send.message);
 
     // Note that this is a blocking write, meaning that we don't have to worry about
     // clear-to-send timing if it takes too long since our timer simply won't tick until we
@@ -470,8 +883,10 @@ impl<W: Write + Send> EventHandler<W> {
       HandlingError::FatalError(format!("Line write failure: {e:?}"))
     };
 
-    self.framed_writer.write(&send.message)
-        .map_err(err_mapper)?;
+    match &mut self.fault_injector {
+      None => self.framed_writer.write(&send.message),
+      Some(injector) => injector.write(&mut self.framed_writer, &send.message),
+    }.map_err(err_mapper)?;
 
     Ok(())
   }
@@ -506,8 +921,11 @@ pub(crate) enum HandlingError {
 impl From<PayloadEncodeError> for HandlingError {
   fn from(value: PayloadEncodeError) -> Self {
     match value {
-      PayloadEncodeError::GenericError(e) =>
-        HandlingError::ClientUnsupported(format!("{e:?})")),
+      e @ (PayloadEncodeError::FieldTooLong { .. } |
+          PayloadEncodeError::CleanupCycleOutOfRange(_) |
+          PayloadEncodeError::FilterCycleOutOfRange(_) |
+          PayloadEncodeError::PackingError(_)) =>
+        HandlingError::ClientUnsupported(format!("{e:?}")),
       PayloadEncodeError::GenericIoError(e) =>
         HandlingError::ClientRecoverable(format!("{e:?}")),
       PayloadEncodeError::NotSupported =>
@@ -516,6 +934,18 @@ impl From<PayloadEncodeError> for HandlingError {
   }
 }
 
+impl ErrorClass {
+  fn from_handling_error(error: &HandlingError) -> Option<Self> {
+    match error {
+      HandlingError::FatalError(_) => Some(ErrorClass::Fatal),
+      HandlingError::ClientNeedsReconnect(_) => Some(ErrorClass::ClientNeedsReconnect),
+      HandlingError::ClientRecoverable(_) => Some(ErrorClass::ClientRecoverable),
+      HandlingError::ClientUnsupported(_) => Some(ErrorClass::ClientUnsupported),
+      HandlingError::ShutdownRequested => None,
+    }
+  }
+}
+
 impl From<EncodeError> for HandlingError {
   fn from(value: EncodeError) -> Self {
     match value {
@@ -525,16 +955,36 @@ impl From<EncodeError> for HandlingError {
   }
 }
 
+/// Notable board state changes, see [MainBoard::set_event_listener].
+#[derive(Debug, Clone, PartialEq)]
+pub enum MainBoardEvent {
+  /// A client stopped responding to clear-to-send polls and its channel was reclaimed.
+  ChannelEvicted {
+    channel: Channel,
+    device_type: u8,
+    client_hash: u16,
+    idle: Duration,
+  },
+
+  /// All frames from [MainBoard::set_replay] have been sent.
+  ReplayFinished,
+}
+
 #[derive(Debug)]
 enum Event {
   ReceivedMessage(Message),
   ReadError(anyhow::Error),
   InitFinished,
+  ApplyAction(ScenarioAction),
   TimerTick(TimerId),
   Shutdown,
+  QueryCtsStats(Sender<HashMap<Channel, CtsChannelStats>>),
+  QueryCtsScheduleCounts(Sender<HashMap<Channel, u64>>),
+  QueryMetrics(Sender<MessageMetrics>),
 }
 
 #[derive(Debug)]
 enum TimerId {
   SendTickMessage,
+  DelayedClearToSend(Channel),
 }
\ No newline at end of file
@@ -0,0 +1,113 @@
+//! Library of malformed byte sequences seen (or plausibly seen) on a noisy RS485 bus, for
+//! hardening [balboa_spa_messages::frame_decoder::FrameDecoder] and the client state machines
+//! built on top of it.  These can be interleaved into the mock board's output with
+//! [crate::fault_injection::FaultInjectionConfig::set_malformed_percent].
+
+use balboa_spa_messages::frame_encoder::FrameEncoder;
+use balboa_spa_messages::message::{EncodeError, Message};
+
+const START_OF_MESSAGE: u8 = 0x7e;
+
+/// Line noise that deliberately avoids the frame delimiter so that it can't be confused with the
+/// start of a real frame.
+const GARBAGE: [u8; 8] = [0x00, 0xff, 0x13, 0x37, 0xaa, 0x55, 0x7d, 0x7f];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MalformedFrameKind {
+  /// Otherwise valid frame whose CRC doesn't match.
+  BadCrc,
+
+  /// Frame cut off partway through the payload, as if the sender was interrupted.
+  Truncated,
+
+  /// Length field that claims more bytes than are actually present.
+  OverstatedLength,
+
+  /// Length field below the protocol minimum.
+  UnderstatedLength,
+
+  /// Random noise with no framing at all.
+  Garbage,
+
+  /// Valid frame with a second start byte immediately after the first.
+  DuplicateStartByte,
+
+  /// Valid frame with noise injected between the header and the payload.
+  InterleavedGarbage,
+}
+
+impl MalformedFrameKind {
+  pub const ALL: [MalformedFrameKind; 7] = [
+    MalformedFrameKind::BadCrc,
+    MalformedFrameKind::Truncated,
+    MalformedFrameKind::OverstatedLength,
+    MalformedFrameKind::UnderstatedLength,
+    MalformedFrameKind::Garbage,
+    MalformedFrameKind::DuplicateStartByte,
+    MalformedFrameKind::InterleavedGarbage,
+  ];
+}
+
+/// Produce a malformed variant of `message`'s encoded frame.
+pub fn generate(kind: MalformedFrameKind, message: &Message) -> Result<Vec<u8>, EncodeError> {
+  let mut frame = FrameEncoder::new().encode(message)?;
+  let len = frame.len();
+  match kind {
+    MalformedFrameKind::BadCrc => {
+      frame[len - 2] ^= 0xff;
+    }
+    MalformedFrameKind::Truncated => {
+      // Keep at least the start byte and the length so it's recognizably a frame.
+      frame.truncate((len / 2).max(2));
+    }
+    MalformedFrameKind::OverstatedLength => {
+      frame[1] = frame[1].saturating_add(4).min(START_OF_MESSAGE - 1);
+    }
+    MalformedFrameKind::UnderstatedLength => {
+      frame[1] = 2;
+    }
+    MalformedFrameKind::Garbage => {
+      frame = GARBAGE.to_vec();
+    }
+    MalformedFrameKind::DuplicateStartByte => {
+      frame.insert(0, START_OF_MESSAGE);
+    }
+    MalformedFrameKind::InterleavedGarbage => {
+      frame.splice(5..5, GARBAGE);
+    }
+  }
+  Ok(frame)
+}
+
+/// Every kind of malformed frame for `message`, convenient for exhaustive decoder tests.
+pub fn library(message: &Message) -> Result<Vec<(MalformedFrameKind, Vec<u8>)>, EncodeError> {
+  MalformedFrameKind::ALL.iter()
+      .map(|kind| Ok((*kind, generate(*kind, message)?)))
+      .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::channel::Channel;
+  use balboa_spa_messages::framed_reader::FramedReader;
+  use balboa_spa_messages::message_types::MessageType;
+  use super::*;
+
+  #[test]
+  fn test_decoder_recovers() -> anyhow::Result<()> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let message = MessageType::ClearToSend().to_message(Channel::Client(0x10))?;
+    let valid = FrameEncoder::new().encode(&message)?;
+    for (kind, malformed) in library(&message)? {
+      assert_ne!(malformed, valid, "{kind:?}");
+
+      // Decoders are allowed to lose the frame immediately following the malformed one while
+      // they resynchronize, but must not get stuck.
+      let stream = [malformed, valid.clone(), valid.clone(), valid.clone()].concat();
+      let decoded: Vec<_> = FramedReader::new(stream.as_slice()).collect();
+      assert!(decoded.contains(&message), "{kind:?} never recovered");
+    }
+    Ok(())
+  }
+}
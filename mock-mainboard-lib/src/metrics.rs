@@ -0,0 +1,91 @@
+//! Counters kept by the main board's event handler so that tests can assert on traffic directly
+//! rather than scraping logs.
+
+use std::collections::HashMap;
+use balboa_spa_messages::message::Message;
+use balboa_spa_messages::message_types::MessageTypeKind;
+use num_traits::FromPrimitive;
+
+/// Broad classification of errors encountered while handling events, mirroring the severity
+/// levels the main board uses internally.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+  Fatal,
+  ClientNeedsReconnect,
+  ClientRecoverable,
+  ClientUnsupported,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MessageMetrics {
+  received: HashMap<MessageTypeKind, u64>,
+  received_unknown: u64,
+  sent: HashMap<MessageTypeKind, u64>,
+  errors: HashMap<ErrorClass, u64>,
+}
+
+impl MessageMetrics {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn received_count(&self, kind: MessageTypeKind) -> u64 {
+    self.received.get(&kind).copied().unwrap_or_default()
+  }
+
+  /// Messages received with a type we don't recognize at all.
+  pub fn received_unknown_count(&self) -> u64 {
+    self.received_unknown
+  }
+
+  pub fn sent_count(&self, kind: MessageTypeKind) -> u64 {
+    self.sent.get(&kind).copied().unwrap_or_default()
+  }
+
+  pub fn error_count(&self, class: ErrorClass) -> u64 {
+    self.errors.get(&class).copied().unwrap_or_default()
+  }
+
+  pub(crate) fn record_received(&mut self, message: &Message) {
+    match MessageTypeKind::from_u8(message.message_type) {
+      Some(kind) => *self.received.entry(kind).or_default() += 1,
+      None => self.received_unknown += 1,
+    }
+  }
+
+  pub(crate) fn record_sent(&mut self, message: &Message) {
+    // We never generate message types we don't know about.
+    if let Some(kind) = MessageTypeKind::from_u8(message.message_type) {
+      *self.sent.entry(kind).or_default() += 1;
+    }
+  }
+
+  pub(crate) fn record_error(&mut self, class: ErrorClass) {
+    *self.errors.entry(class).or_default() += 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::channel::Channel;
+  use super::*;
+
+  #[test]
+  fn test_counts() {
+    let message = |message_type| Message {
+      channel: Channel::Client(0x10),
+      message_type,
+      payload: vec![],
+    };
+    let mut metrics = MessageMetrics::new();
+    metrics.record_received(&message(0x06));
+    metrics.record_received(&message(0x06));
+    metrics.record_received(&message(0x77));
+    metrics.record_error(ErrorClass::ClientRecoverable);
+
+    assert_eq!(metrics.received_count(MessageTypeKind::ClearToSend), 2);
+    assert_eq!(metrics.received_unknown_count(), 1);
+    assert_eq!(metrics.sent_count(MessageTypeKind::ClearToSend), 0);
+    assert_eq!(metrics.error_count(ErrorClass::ClientRecoverable), 1);
+  }
+}
@@ -1,11 +1,21 @@
+use std::time::{Duration, Instant};
 use chrono::{Timelike, Utc};
-use balboa_spa_messages::message_types::{Boolean, ClockMode, ConfigurationResponseMessage, FaultResponseMessage, FilterMode, HeatingMode, HeatingState, InitializationMode, PumpConfig, PumpStatus, RelayStatus, ReminderType, Settings0x04ResponseMessage, SpaState, StatusUpdateMessage, StatusUpdateResponseV1, TemperatureMinMax, TemperatureRange};
+use crate::clock::{system_clock, SharedClock};
+use crate::thermal::{ThermalConfig, ThermalModel};
+use balboa_spa_messages::message_types::{Boolean, ClockMode, ConfigurationResponseMessage, FaultCode, FaultResponseMessage, FilterCycle, FilterMode, GfciTestResult, HeatingMode, HeatingState, InitializationMode, ItemCode, LockRequestMessage, MessageType, PreferencesResponseMessage, PumpConfig, PumpStatus, RelayStatus, ReminderType, SetPreferenceMessage, Settings0x04ResponseMessage, SpaState, StatusUpdateMessage, StatusUpdateResponseV1, TemperatureMinMax, TemperatureRange};
 use balboa_spa_messages::parsed_enum::ParsedEnum;
 use balboa_spa_messages::temperature::{ProtocolTemperature, SetTemperature, Temperature, TemperatureScale};
 use balboa_spa_messages::time::ProtocolTime;
 
 pub const DEFAULT_SET_TEMP_C: f64 = 39.5;
-pub const DEFAULT_HEATING_TEMP_C: f64 = 38.0;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// How long hold (or soak) mode lasts before the spa resumes normal operation on its own.
+pub const DEFAULT_HOLD_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Real main boards only retain this many fault log entries, dropping the oldest.
+pub const MAX_FAULT_LOG_ENTRIES: usize = 24;
 
 #[derive(Debug)]
 pub struct MockSpa {
@@ -13,6 +23,39 @@ pub struct MockSpa {
   pub run_state: MockSpaState,
   pub hardware: MockHardware,
   pub settings: UserSettings,
+  filter_cycles: Vec<FilterCycle>,
+  fault_log: Vec<FaultLogEntry>,
+  gfci_test_config: GfciTestConfig,
+  gfci_test_started: Option<Instant>,
+  hold_duration: Duration,
+  hold_started: Option<Instant>,
+  thermal: ThermalModel,
+  clock: SharedClock,
+}
+
+/// Controls the outcome of a simulated GFCI test, which on real hardware trips the breaker and
+/// takes a few seconds before the board reports back.
+#[derive(Debug, Clone)]
+pub struct GfciTestConfig {
+  pub result: GfciTestResult,
+  pub duration: Duration,
+}
+
+impl Default for GfciTestConfig {
+  fn default() -> Self {
+    Self {
+      result: GfciTestResult::Pass,
+      duration: Duration::from_secs(3),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct FaultLogEntry {
+  fault_code: FaultCode,
+  days_ago: u8,
+  time: ProtocolTime,
+  set_temperature: u8,
 }
 
 impl Default for MockSpa {
@@ -20,33 +63,151 @@ impl Default for MockSpa {
     Self {
       init_finished: false,
       run_state: MockSpaState::Initializing,
-      hardware: MockHardware {
-        pumps: vec![PumpDevice::default()],
-        blower: RelayDevice::default(),
-        lights: vec![RelayDevice::default()],
-      },
+      hardware: MockHardware::default(),
       settings: UserSettings {
         temp_range: TemperatureRange::High,
         clock_mode: ClockMode::Hour12,
         temperature_scale: TemperatureScale::Celsius,
         set_temperature: Temperature::from_celsius(DEFAULT_SET_TEMP_C),
-      }
+        reminders: true,
+        panel_locked: false,
+        settings_locked: false,
+        clock: None,
+      },
+      filter_cycles: vec![
+        FilterCycle::new(true, hours(20), hours(2)),
+        FilterCycle::new(false, hours(8), hours(2)),
+      ],
+      fault_log: Vec::new(),
+      gfci_test_config: GfciTestConfig::default(),
+      gfci_test_started: None,
+      hold_duration: DEFAULT_HOLD_DURATION,
+      hold_started: None,
+      thermal: ThermalModel::default(),
+      clock: system_clock(),
     }
   }
 }
 
+fn host_time_of_day() -> ProtocolTime {
+  let now = Utc::now();
+  ProtocolTime::from_hm(
+    u8::try_from(now.hour()).unwrap(),
+    u8::try_from(now.minute()).unwrap())
+}
+
+fn hours(h: u64) -> Duration {
+  Duration::from_secs(h * 60 * 60)
+}
+
 #[derive(Debug)]
 pub enum MockSpaState {
   Initializing,
   Heating,
+  Idle,
   Hold,
 }
 
 #[derive(Debug)]
 pub struct MockHardware {
   pub pumps: Vec<PumpDevice>,
-  pub blower: RelayDevice,
+  pub blower: Option<RelayDevice>,
   pub lights: Vec<RelayDevice>,
+  pub mister: Option<RelayDevice>,
+  pub aux: Vec<RelayDevice>,
+  pub has_circulation_pump: bool,
+}
+
+impl Default for MockHardware {
+  fn default() -> Self {
+    MockSpaBuilder::new().build_hardware()
+  }
+}
+
+/// Describes the equipment installed in the mock spa, which varies a lot between real models.
+/// Defaults to a single two speed pump, one light, a blower, and a circulation pump.
+#[derive(Debug, Clone)]
+pub struct MockSpaBuilder {
+  pumps: Vec<PumpConfig>,
+  lights: usize,
+  blower: bool,
+  mister: bool,
+  aux: usize,
+  circulation_pump: bool,
+}
+
+impl Default for MockSpaBuilder {
+  fn default() -> Self {
+    Self {
+      pumps: vec![PumpConfig::Speed2],
+      lights: 1,
+      blower: true,
+      mister: false,
+      aux: 0,
+      circulation_pump: true,
+    }
+  }
+}
+
+impl MockSpaBuilder {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Up to 6 pumps, each with the number of speeds it supports.
+  pub fn set_pumps(mut self, pumps: Vec<PumpConfig>) -> Self {
+    self.pumps = pumps;
+    self
+  }
+
+  /// Up to 2 lights.
+  pub fn set_lights(mut self, lights: usize) -> Self {
+    self.lights = lights;
+    self
+  }
+
+  pub fn set_blower(mut self, blower: bool) -> Self {
+    self.blower = blower;
+    self
+  }
+
+  pub fn set_mister(mut self, mister: bool) -> Self {
+    self.mister = mister;
+    self
+  }
+
+  /// Up to 2 auxiliary relays.
+  pub fn set_aux(mut self, aux: usize) -> Self {
+    self.aux = aux;
+    self
+  }
+
+  pub fn set_circulation_pump(mut self, circulation_pump: bool) -> Self {
+    self.circulation_pump = circulation_pump;
+    self
+  }
+
+  pub fn build(self) -> MockSpa {
+    MockSpa {
+      hardware: self.build_hardware(),
+      ..Default::default()
+    }
+  }
+
+  fn build_hardware(self) -> MockHardware {
+    let relays = |n: usize| (0..n).map(|_| RelayDevice::default()).collect();
+    MockHardware {
+      pumps: self.pumps.into_iter()
+          .take(6)
+          .map(|capability| PumpDevice { status: PumpStatus::Off, capability })
+          .collect(),
+      blower: self.blower.then(RelayDevice::default),
+      lights: relays(self.lights.min(2)),
+      mister: self.mister.then(RelayDevice::default),
+      aux: relays(self.aux.min(2)),
+      has_circulation_pump: self.circulation_pump,
+    }
+  }
 }
 
 #[derive(Debug)]
@@ -55,6 +216,20 @@ pub struct PumpDevice {
   pub capability: PumpConfig,
 }
 
+impl PumpDevice {
+  /// Cycle through the speeds supported by this pump, as a physical button on the topside would.
+  pub fn toggle(&mut self) {
+    self.status = match (self.capability, self.status) {
+      (PumpConfig::None, _) => PumpStatus::Off,
+      (PumpConfig::Speed1, PumpStatus::Off) => PumpStatus::High,
+      (PumpConfig::Speed1, _) => PumpStatus::Off,
+      (PumpConfig::Speed2, PumpStatus::Off) => PumpStatus::Low,
+      (PumpConfig::Speed2, PumpStatus::Low) => PumpStatus::High,
+      (PumpConfig::Speed2, PumpStatus::High) => PumpStatus::Off,
+    };
+  }
+}
+
 impl Default for PumpDevice {
   fn default() -> Self {
     Self {
@@ -69,6 +244,15 @@ pub struct RelayDevice {
   pub status: RelayStatus,
 }
 
+impl RelayDevice {
+  pub fn toggle(&mut self) {
+    self.status = match self.status {
+      RelayStatus::Off => RelayStatus::On,
+      RelayStatus::On => RelayStatus::Off,
+    };
+  }
+}
+
 impl Default for RelayDevice {
   fn default() -> Self {
     Self { status: RelayStatus::Off }
@@ -81,6 +265,18 @@ pub struct UserSettings {
   clock_mode: ClockMode,
   temperature_scale: TemperatureScale,
   set_temperature: Temperature,
+  reminders: bool,
+  panel_locked: bool,
+  settings_locked: bool,
+  clock: Option<SpaClock>,
+}
+
+/// Time of day as last set by a client, which then free runs from that point.  Before any client
+/// sets the time we just follow the host's clock.
+#[derive(Debug, Clone)]
+struct SpaClock {
+  time_at_set: ProtocolTime,
+  set_at: Instant,
 }
 
 impl MockSpa {
@@ -88,23 +284,201 @@ impl MockSpa {
     Default::default()
   }
 
+  /// Drive all time based behaviour from `clock`.  The time of day is anchored to the host's
+  /// clock at this point (unless a client already set it) so that it keeps pace with `clock`
+  /// even if it has been accelerated.
+  pub fn set_clock(&mut self, clock: SharedClock) {
+    if self.settings.clock.is_none() {
+      self.settings.set_time(host_time_of_day(), clock.now());
+    }
+    self.clock = clock;
+  }
+
   pub fn init_finished(&mut self) {
     self.init_finished = true;
     self.update_run_state();
   }
 
-  pub fn adjust_temperature(&mut self, value: SetTemperature) {
-    let new_temp = self.settings.temperature_scale.new_protocol_temperature_from_set(value);
-    self.settings.set_temperature = new_temp.temperature;
+  /// Apply a [ItemCode] toggle to the mock hardware, returning false if the item isn't something
+  /// this spa has or knows how to toggle.
+  pub fn toggle_item(&mut self, item_code: ItemCode) -> bool {
+    match item_code {
+      ItemCode::Pump1 | ItemCode::Pump2 | ItemCode::Pump3 |
+      ItemCode::Pump4 | ItemCode::Pump5 | ItemCode::Pump6 => {
+        let index = usize::from(item_code.as_raw() - ItemCode::Pump1.as_raw());
+        match self.hardware.pumps.get_mut(index) {
+          Some(pump) => {
+            pump.toggle();
+            true
+          }
+          None => false,
+        }
+      }
+      ItemCode::Light1 | ItemCode::Light2 => {
+        let index = usize::from(item_code.as_raw() - ItemCode::Light1.as_raw());
+        match self.hardware.lights.get_mut(index) {
+          Some(light) => {
+            light.toggle();
+            true
+          }
+          None => false,
+        }
+      }
+      ItemCode::Blower => toggle_optional(&mut self.hardware.blower),
+      ItemCode::Mister => toggle_optional(&mut self.hardware.mister),
+      ItemCode::Aux1 | ItemCode::Aux2 => {
+        let index = usize::from(item_code.as_raw() - ItemCode::Aux1.as_raw());
+        match self.hardware.aux.get_mut(index) {
+          Some(aux) => {
+            aux.toggle();
+            true
+          }
+          None => false,
+        }
+      }
+      ItemCode::HoldMode => {
+        self.toggle_hold();
+        true
+      }
+      ItemCode::SoakMode => {
+        // Soak is just hold with the jets forced off so the water can settle.
+        for pump in &mut self.hardware.pumps {
+          pump.status = PumpStatus::Off;
+        }
+        if let Some(blower) = &mut self.hardware.blower {
+          blower.status = RelayStatus::Off;
+        }
+        self.toggle_hold();
+        true
+      }
+      ItemCode::TemperatureRange => {
+        self.settings.temp_range = match self.settings.temp_range {
+          TemperatureRange::Low => TemperatureRange::High,
+          TemperatureRange::High => TemperatureRange::Low,
+        };
+        true
+      }
+      _ => false,
+    }
+  }
+
+  pub fn set_time(&mut self, time: ProtocolTime) {
+    self.settings.set_time(time, self.clock.now());
+  }
+
+  pub fn lock(&mut self, request: &LockRequestMessage) {
+    match request {
+      LockRequestMessage::LockSettings => self.settings.settings_locked = true,
+      LockRequestMessage::LockPanel => self.settings.panel_locked = true,
+      LockRequestMessage::UnlockSettings => self.settings.settings_locked = false,
+      LockRequestMessage::UnlockPanel => self.settings.panel_locked = false,
+    }
+  }
+
+  /// Determine whether a client request must be refused due to the current lock state.  A panel
+  /// lock blocks all controls, while a settings lock still allows toggling jets, lights, etc.
+  pub fn is_locked_out(&self, request: &MessageType) -> bool {
+    match request {
+      MessageType::ToggleItemRequest { .. } => self.settings.panel_locked,
+      MessageType::SetTemperatureRequest { .. } |
+      MessageType::SetTimeRequest { .. } |
+      MessageType::SetPreferenceRequest(_) |
+      MessageType::FilterCycles { .. } |
+      MessageType::ChangeSetupRequest { .. } =>
+        self.settings.panel_locked || self.settings.settings_locked,
+      _ => false,
+    }
+  }
+
+  pub fn as_preferences(&self) -> PreferencesResponseMessage {
+    self.settings.as_preferences()
+  }
+
+  pub fn set_preference(&mut self, preference: &SetPreferenceMessage) -> bool {
+    self.settings.set_preference(preference)
+  }
+
+  pub fn as_filter_cycles(&self) -> Vec<FilterCycle> {
+    self.filter_cycles.clone()
+  }
+
+  pub fn set_filter_cycles(&mut self, cycles: Vec<FilterCycle>) {
+    self.filter_cycles = cycles;
+  }
+
+  fn filter_mode(&self, time: &ProtocolTime) -> FilterMode {
+    let active: Vec<_> = self.filter_cycles.iter()
+        .map(|c| c.is_active_at(time.as_duration()))
+        .collect();
+    match (active.first().copied().unwrap_or(false), active.get(1).copied().unwrap_or(false)) {
+      (false, false) => FilterMode::Off,
+      (true, false) => FilterMode::Cycle1,
+      (false, true) => FilterMode::Cycle2,
+      (true, true) => FilterMode::Cycle1And2,
+    }
+  }
+
+  /// Replace the thermal model's parameters, resetting the water to the configured initial
+  /// temperature.
+  pub fn set_thermal_config(&mut self, config: ThermalConfig) {
+    self.thermal = ThermalModel::with_config(config);
+    self.update_run_state();
+  }
+
+  pub fn water_temperature(&self) -> Temperature {
+    self.thermal.water_temperature()
+  }
+
+  pub fn set_hold_duration(&mut self, duration: Duration) {
+    self.hold_duration = duration;
+  }
+
+  fn toggle_hold(&mut self) {
+    self.hold_started = match self.hold_started {
+      Some(_) => None,
+      None => Some(self.clock.now()),
+    };
+    self.update_run_state();
+  }
+
+  fn hold_remaining(&self) -> Option<Duration> {
+    self.hold_started
+        .map(|started| self.hold_duration.saturating_sub(self.clock.elapsed_since(started)))
+  }
+
+  /// Advance any time based state, such as the water temperature or exiting hold mode once it
+  /// has expired.  Expected to be called periodically by the main board.
+  pub fn tick(&mut self) {
+    if self.hold_remaining() == Some(Duration::ZERO) {
+      self.hold_started = None;
+      self.update_run_state();
+    }
+
+    // Only recompute the run state when the heater actually switches so that states forced
+    // externally (e.g. by a scenario) aren't immediately overridden.
+    let was_heating = self.thermal.is_heater_on();
+    let heating_allowed = matches!(self.run_state, MockSpaState::Heating | MockSpaState::Idle);
+    self.thermal.update(self.clock.now(), &self.settings.set_temperature, heating_allowed);
+    if self.thermal.is_heater_on() != was_heating {
+      self.update_run_state();
+    }
+  }
+
+  pub fn adjust_temperature(&mut self, value: SetTemperature) -> Temperature {
+    let limits = self.min_max_temps();
+    let applied = self.settings.adjust_temperature(value, &limits);
     self.update_run_state();
+    applied
   }
 
   fn update_run_state(&mut self) {
     let new_state = if self.init_finished {
-      if self.settings.set_temperature.as_celsius() < DEFAULT_HEATING_TEMP_C {
+      if self.hold_started.is_some() {
         MockSpaState::Hold
-      } else {
+      } else if self.thermal.needs_heat(&self.settings.set_temperature) {
         MockSpaState::Heating
+      } else {
+        MockSpaState::Idle
       }
     } else {
       MockSpaState::Initializing
@@ -115,48 +489,56 @@ impl MockSpa {
   pub fn as_status(&self) -> StatusUpdateMessage {
     let run_status = self.run_state.as_status();
     let hw_status = self.hardware.as_status();
-    let user_status = self.settings.as_status();
+    let user_status = self.settings.as_status(self.clock.now());
 
-    let current_temperature = match run_status.current_temperature {
-      CurrentTemperatureState::Unknown => None,
-      CurrentTemperatureState::Low => {
-        Some(user_status.temperature_scale
-            .new_protocol_temperature(Temperature::from_celsius(DEFAULT_HEATING_TEMP_C)).unwrap())
-      },
-      CurrentTemperatureState::AtTarget => Some(user_status.set_temperature.clone()),
+    let current_temperature = match run_status.temperature_known {
+      true => user_status.temperature_scale
+          .new_protocol_temperature(self.thermal.water_temperature())
+          .ok(),
+      false => None,
     };
 
+    // Heating forces otherwise idle pumps to run at low speed to keep water moving over the
+    // heater, but anything the user explicitly turned on is left alone.
     let pump_status = hw_status.pumps.into_iter()
         .map(|p| {
-          match run_status.pumps_forced_low {
-            Some(true) => ParsedEnum::new(PumpStatus::Low),
+          match (run_status.pumps_forced_low, p.as_ref()) {
+            (Some(true), Some(PumpStatus::Off)) => ParsedEnum::new(PumpStatus::Low),
             _ => p
           }
         })
         .collect();
 
+    let spa_state = match self.gfci_test_started {
+      Some(_) => SpaState::TestMode,
+      None => run_status.spa_mode,
+    };
+
     let status = StatusUpdateResponseV1 {
-      spa_state: ParsedEnum::new(run_status.spa_mode),
+      spa_state: ParsedEnum::new(spa_state),
       init_mode: ParsedEnum::new(run_status.init_mode),
       current_temperature,
       time: user_status.time,
       heating_mode: ParsedEnum::new(run_status.heating_mode),
       reminder_type: ParsedEnum::new(ReminderType::None),
-      hold_timer: None,
-      filter_mode: ParsedEnum::new(FilterMode::Off),
-      panel_locked: false,
+      hold_timer: self.hold_remaining()
+          .and_then(|remaining| ProtocolTime::from_duration(remaining).ok()),
+      filter_mode: ParsedEnum::new(self.filter_mode(&user_status.time)),
+      panel_locked: self.settings.panel_locked,
       temperate_range: user_status.temperature_range,
       clock_mode: ParsedEnum::new(user_status.clock_mode),
       needs_heat: run_status.needs_heat,
       heating_state: ParsedEnum::new(run_status.heating_state),
-      mister_on: ParsedEnum::new(Boolean::False),
+      mister_on: hw_status.mister,
       set_temperature: user_status.set_temperature,
       pump_status,
-      circulation_pump_on: ParsedEnum::new(Boolean::from(run_status.circulation_pump_on)),
+      circulation_pump_on: ParsedEnum::new(Boolean::from(
+          run_status.circulation_pump_on && self.hardware.has_circulation_pump)),
       blower_status: hw_status.blower,
       light_status: hw_status.lights,
-      reminder_set: ParsedEnum::new(Boolean::False),
+      reminder_set: ParsedEnum::new(Boolean::from(user_status.reminders)),
       notification_set: ParsedEnum::new(Boolean::False),
+      settings_locked: self.settings.settings_locked,
     };
     StatusUpdateMessage {
       v1: status,
@@ -166,31 +548,99 @@ impl MockSpa {
   }
 
   pub fn as_settings0x04(&self) -> Settings0x04ResponseMessage {
+    Settings0x04ResponseMessage {
+      min_max_temps: self.min_max_temps(),
+    }
+  }
+
+  fn min_max_temps(&self) -> TemperatureMinMax {
     let temps: Vec<_> = [50, 90, 80, 104].into_iter()
         .map(|t| {
           Temperature::from_fahrenheit(f64::from(t))
         })
         .collect();
-    Settings0x04ResponseMessage {
-      min_max_temps: TemperatureMinMax {
-        low_range: (temps[0], temps[1]),
-        high_range: (temps[2], temps[3]),
-      },
+    TemperatureMinMax {
+      low_range: (temps[0], temps[1]),
+      high_range: (temps[2], temps[3]),
     }
   }
 
+  pub fn set_gfci_test_config(&mut self, config: GfciTestConfig) {
+    self.gfci_test_config = config;
+  }
+
+  /// Begin a GFCI test, during which the spa reports [SpaState::TestMode].  Restarts the test if
+  /// one is already in progress.
+  pub fn start_gfci_test(&mut self) {
+    self.gfci_test_started = Some(self.clock.now());
+  }
+
+  /// Check on a running GFCI test, yielding the result exactly once after the configured
+  /// duration has elapsed.  Failures are also recorded in the fault log as they would be on a
+  /// real board.
+  pub fn poll_gfci_test(&mut self) -> Option<GfciTestResult> {
+    let started = self.gfci_test_started?;
+    if self.clock.elapsed_since(started) < self.gfci_test_config.duration {
+      return None;
+    }
+    self.gfci_test_started = None;
+    let result = self.gfci_test_config.result;
+    if result == GfciTestResult::Fail {
+      self.raise_fault(FaultCode::GfciTestFailed);
+    }
+    Some(result)
+  }
+
   pub fn as_configuration(&self) -> ConfigurationResponseMessage {
     self.hardware.as_configuration()
   }
 
-  pub fn as_fault_log(&self, _entry_num: u8) -> FaultResponseMessage {
-    FaultResponseMessage {
-      total_entries: 0,
-      entry_number: 0,
-      fault_code: ParsedEnum::from_raw(0),
-      days_ago: 0,
-      time: ProtocolTime::from_hm(0, 0),
-      set_temperature: 0,
+  /// Record a fault as the most recent fault log entry.  `set_temperature` is the raw protocol
+  /// value at the time of the fault.
+  pub fn push_fault(&mut self, fault_code: FaultCode, days_ago: u8, time: ProtocolTime, set_temperature: u8) {
+    if self.fault_log.len() >= MAX_FAULT_LOG_ENTRIES {
+      self.fault_log.remove(0);
+    }
+    self.fault_log.push(FaultLogEntry {
+      fault_code,
+      days_ago,
+      time,
+      set_temperature,
+    });
+  }
+
+  /// Record a fault happening right now.
+  pub fn raise_fault(&mut self, fault_code: FaultCode) {
+    let user_status = self.settings.as_status(self.clock.now());
+    self.push_fault(fault_code, 0, user_status.time, user_status.set_temperature.raw_value());
+  }
+
+  /// Look up a fault log entry by index.  Like the real hardware, out of range entries (in
+  /// particular 0xff) yield the most recent fault.
+  pub fn as_fault_log(&self, entry_num: u8) -> FaultResponseMessage {
+    let total_entries = u8::try_from(self.fault_log.len()).unwrap();
+    let index = usize::from(entry_num).min(self.fault_log.len().saturating_sub(1));
+    match self.fault_log.get(index) {
+      None => {
+        FaultResponseMessage {
+          total_entries: 0,
+          entry_number: 0,
+          fault_code: ParsedEnum::from_raw(0),
+          days_ago: 0,
+          time: ProtocolTime::from_hm(0, 0),
+          set_temperature: 0,
+        }
+      }
+      Some(entry) => {
+        FaultResponseMessage {
+          total_entries,
+          entry_number: u8::try_from(index).unwrap(),
+          fault_code: ParsedEnum::new(entry.fault_code.clone()),
+          days_ago: entry.days_ago,
+          time: entry.time,
+          set_temperature: entry.set_temperature,
+        }
+      }
     }
   }
 }
@@ -202,7 +652,7 @@ impl MockSpaState {
         RuntimeStatus {
           spa_mode: SpaState::Initializing,
           init_mode: InitializationMode::PrimingMode,
-          current_temperature: CurrentTemperatureState::Unknown,
+          temperature_known: false,
           heating_mode: HeatingMode::Rest,
           needs_heat: true,
           heating_state: HeatingState::Off,
@@ -214,7 +664,7 @@ impl MockSpaState {
         RuntimeStatus {
           spa_mode: SpaState::Running,
           init_mode: InitializationMode::Idle,
-          current_temperature: CurrentTemperatureState::Low,
+          temperature_known: true,
           heating_mode: HeatingMode::Ready,
           needs_heat: true,
           heating_state: HeatingState::Heating,
@@ -222,11 +672,23 @@ impl MockSpaState {
           pumps_forced_low: Some(true),
         }
       }
+      MockSpaState::Idle => {
+        RuntimeStatus {
+          spa_mode: SpaState::Running,
+          init_mode: InitializationMode::Idle,
+          temperature_known: true,
+          heating_mode: HeatingMode::Ready,
+          needs_heat: false,
+          heating_state: HeatingState::Off,
+          circulation_pump_on: false,
+          pumps_forced_low: None,
+        }
+      }
       MockSpaState::Hold => {
         RuntimeStatus {
           spa_mode: SpaState::HoldMode,
           init_mode: InitializationMode::Idle,
-          current_temperature: CurrentTemperatureState::AtTarget,
+          temperature_known: true,
           heating_mode: HeatingMode::ReadyInRest,
           needs_heat: false,
           heating_state: HeatingState::HeatWaiting,
@@ -243,7 +705,7 @@ impl MockSpaState {
 pub struct RuntimeStatus {
   spa_mode: SpaState,
   init_mode: InitializationMode,
-  current_temperature: CurrentTemperatureState,
+  temperature_known: bool,
   heating_mode: HeatingMode,
   needs_heat: bool,
   heating_state: HeatingState,
@@ -251,19 +713,73 @@ pub struct RuntimeStatus {
   pumps_forced_low: Option<bool>,
 }
 
-#[derive(Debug, Clone)]
-pub enum CurrentTemperatureState {
-  Unknown,
-  Low,
-  AtTarget,
-}
-
 impl UserSettings {
-  pub fn as_status(&self) -> UserSettingsStatus {
-    let now = Utc::now();
-    let time = ProtocolTime::from_hm(
-      u8::try_from(now.hour()).unwrap(),
-      u8::try_from(now.minute()).unwrap());
+  /// Apply a set temperature request, interpreted in the configured scale and clamped to the
+  /// limits of the active temperature range just like the real hardware does.  Returns the
+  /// temperature that was actually applied.
+  pub fn adjust_temperature(&mut self, value: SetTemperature, limits: &TemperatureMinMax) -> Temperature {
+    let requested = self.temperature_scale.new_protocol_temperature_from_set(value).temperature;
+    let (min, max) = match self.temp_range {
+      TemperatureRange::Low => limits.low_range,
+      TemperatureRange::High => limits.high_range,
+    };
+    let applied = if requested < min {
+      min
+    } else if requested > max {
+      max
+    } else {
+      requested
+    };
+    self.set_temperature = applied;
+    applied
+  }
+
+  pub fn set_time(&mut self, time: ProtocolTime, now: Instant) {
+    self.clock = Some(SpaClock {
+      time_at_set: time,
+      set_at: now,
+    });
+  }
+
+  pub fn current_time(&self, now: Instant) -> ProtocolTime {
+    match &self.clock {
+      None => host_time_of_day(),
+      Some(clock) => {
+        let elapsed = clock.time_at_set.as_duration() +
+            now.saturating_duration_since(clock.set_at);
+        let secs = elapsed.as_secs() % SECS_PER_DAY;
+        ProtocolTime::from_duration(Duration::from_secs(secs)).unwrap()
+      }
+    }
+  }
+
+  /// Apply a preference change from a client.  Returns false for preferences that we don't
+  /// model, which are otherwise ignored.
+  pub fn set_preference(&mut self, preference: &SetPreferenceMessage) -> bool {
+    match preference {
+      SetPreferenceMessage::Reminders(enabled) => self.reminders = *enabled,
+      SetPreferenceMessage::TemperatureScale(scale) => self.temperature_scale = *scale,
+      SetPreferenceMessage::ClockMode(mode) => self.clock_mode = *mode,
+      SetPreferenceMessage::CleanupCycle(_) |
+      SetPreferenceMessage::DolphinAddress(_) |
+      SetPreferenceMessage::M8ArtificialIntelligence(_) => return false,
+    }
+    true
+  }
+
+  pub fn as_preferences(&self) -> PreferencesResponseMessage {
+    PreferencesResponseMessage {
+      reminder_set: ParsedEnum::new(Boolean::from(self.reminders)),
+      temperature_scale: ParsedEnum::new(self.temperature_scale),
+      clock_mode: ParsedEnum::new(self.clock_mode),
+      cleanup_cycle: ParsedEnum::from_raw(0),
+      dolphin_address: 0,
+      m8_artificial_intelligence: ParsedEnum::new(Boolean::False),
+    }
+  }
+
+  pub fn as_status(&self, now: Instant) -> UserSettingsStatus {
+    let time = self.current_time(now);
     let set_temperature = self.temperature_scale.new_protocol_temperature(
         self.set_temperature).unwrap();
     UserSettingsStatus {
@@ -272,6 +788,7 @@ impl UserSettings {
       temperature_range: self.temp_range,
       clock_mode: self.clock_mode,
       set_temperature,
+      reminders: self.reminders,
     }
   }
 }
@@ -283,6 +800,7 @@ pub struct UserSettingsStatus {
   temperature_range: TemperatureRange,
   clock_mode: ClockMode,
   set_temperature: ProtocolTemperature,
+  reminders: bool,
 }
 
 impl MockHardware {
@@ -293,10 +811,14 @@ impl MockHardware {
     let lights = self.lights.iter()
         .map(|d| ParsedEnum::new(d.status))
         .collect();
+    let relay_status = |relay: &Option<RelayDevice>| {
+      relay.as_ref().map(|r| r.status).unwrap_or(RelayStatus::Off)
+    };
     HardwareStatus {
       pumps,
-      blower: ParsedEnum::new(self.blower.status),
+      blower: ParsedEnum::new(relay_status(&self.blower)),
       lights,
+      mister: ParsedEnum::new(Boolean::from(relay_status(&self.mister) == RelayStatus::On)),
     }
   }
 
@@ -307,13 +829,16 @@ impl MockHardware {
     let has_lights = self.lights.iter()
         .map(|_| ParsedEnum::new(Boolean::True))
         .collect();
+    let has_aux = self.aux.iter()
+        .map(|_| ParsedEnum::new(Boolean::True))
+        .collect();
     ConfigurationResponseMessage {
       pumps,
       has_lights,
-      has_blower: true,
-      has_circulation_pump: true,
-      has_aux: vec![],
-      has_mister: ParsedEnum::new(Boolean::False),
+      has_blower: self.blower.is_some(),
+      has_circulation_pump: self.has_circulation_pump,
+      has_aux,
+      has_mister: ParsedEnum::new(Boolean::from(self.mister.is_some())),
     }
   }
 }
@@ -323,4 +848,249 @@ pub struct HardwareStatus {
   pumps: Vec<ParsedEnum<PumpStatus, u8>>,
   blower: ParsedEnum<RelayStatus, u8>,
   lights: Vec<ParsedEnum<RelayStatus, u8>>,
-}
\ No newline at end of file
+  mister: ParsedEnum<Boolean, u8>,
+}
+
+fn toggle_optional(relay: &mut Option<RelayDevice>) -> bool {
+  match relay {
+    Some(relay) => {
+      relay.toggle();
+      true
+    }
+    None => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use balboa_spa_messages::channel::Channel;
+  use crate::clock::ManualClock;
+  use super::*;
+
+  #[test]
+  fn test_toggle_items_reflected_in_status() {
+    let mut spa = MockSpa::new();
+    spa.init_finished();
+
+    assert!(spa.toggle_item(ItemCode::Pump1));
+    assert!(spa.toggle_item(ItemCode::Pump1));
+    assert!(spa.toggle_item(ItemCode::Light1));
+    assert!(!spa.toggle_item(ItemCode::Pump2));
+    assert!(!spa.toggle_item(ItemCode::Other(0xaa)));
+
+    let status = spa.as_status().v1;
+    assert_eq!(status.pump_status, vec![ParsedEnum::new(PumpStatus::High)]);
+    assert_eq!(status.light_status, vec![ParsedEnum::new(RelayStatus::On)]);
+
+    assert!(spa.toggle_item(ItemCode::Pump1));
+    assert_eq!(spa.hardware.pumps[0].status, PumpStatus::Off);
+  }
+
+  #[test]
+  fn test_adjust_temperature_honors_scale_and_range() {
+    let mut spa = MockSpa::new();
+    let scale = TemperatureScale::Celsius;
+
+    let target = scale.new_set_temperature(&Temperature::from_celsius(37.0)).unwrap();
+    spa.adjust_temperature(target);
+    assert_eq!(spa.as_status().v1.set_temperature.temperature, Temperature::from_celsius(37.0));
+
+    // Way above the 104F limit of the high range.
+    let target = scale.new_set_temperature(&Temperature::from_celsius(45.0)).unwrap();
+    let applied = spa.adjust_temperature(target);
+    assert_eq!(applied, Temperature::from_fahrenheit(104.0));
+  }
+
+  #[test]
+  fn test_water_heats_to_set_point() {
+    let clock = ManualClock::new();
+    let mut spa = MockSpa::new();
+    spa.set_clock(Arc::new(clock.clone()));
+    spa.set_thermal_config(ThermalConfig {
+      initial_celsius: 37.0,
+      ambient_celsius: 20.0,
+      heating_rate_c_per_hour: 2.0,
+      cooling_rate_c_per_hour: 0.5,
+    });
+    spa.init_finished();
+    spa.tick();
+    let current = |spa: &MockSpa| spa.as_status().v1.current_temperature.unwrap().temperature;
+    assert_eq!(current(&spa), Temperature::from_celsius(37.0));
+    assert_eq!(spa.as_status().v1.heating_state, ParsedEnum::new(HeatingState::Heating));
+
+    clock.advance(Duration::from_secs(30 * 60));
+    spa.tick();
+    assert_eq!(current(&spa), Temperature::from_celsius(38.0));
+
+    clock.advance(Duration::from_secs(2 * 60 * 60));
+    spa.tick();
+    assert_eq!(current(&spa), Temperature::from_celsius(DEFAULT_SET_TEMP_C));
+    assert_eq!(spa.as_status().v1.heating_state, ParsedEnum::new(HeatingState::Off));
+  }
+
+  #[test]
+  fn test_set_time() {
+    let mut spa = MockSpa::new();
+    spa.set_time(ProtocolTime::from_hm(13, 37));
+    assert_eq!(spa.as_status().v1.time.as_raw(), ProtocolTime::from_hm(13, 37).as_raw());
+  }
+
+  #[test]
+  fn test_fault_log() {
+    let mut spa = MockSpa::new();
+    assert_eq!(spa.as_fault_log(0xff).total_entries, 0);
+
+    spa.push_fault(FaultCode::WaterFlowLow, 2, ProtocolTime::from_hm(1, 2), 100);
+    spa.push_fault(FaultCode::HeaterIsDry, 0, ProtocolTime::from_hm(3, 4), 102);
+
+    let first = spa.as_fault_log(0);
+    assert_eq!(first.total_entries, 2);
+    assert_eq!(first.entry_number, 0);
+    assert_eq!(first.fault_code.as_raw(), FaultCode::WaterFlowLow as u8);
+    assert_eq!(first.days_ago, 2);
+
+    let latest = spa.as_fault_log(0xff);
+    assert_eq!(latest.entry_number, 1);
+    assert_eq!(latest.fault_code.as_raw(), FaultCode::HeaterIsDry as u8);
+    assert_eq!(latest.set_temperature, 102);
+  }
+
+  #[test]
+  fn test_filter_mode_in_status() {
+    let mut spa = MockSpa::new();
+    spa.set_time(ProtocolTime::from_hm(21, 0));
+    assert_eq!(spa.as_status().v1.filter_mode, ParsedEnum::new(FilterMode::Cycle1));
+
+    spa.set_filter_cycles(vec![
+      FilterCycle::new(true, hours(20), hours(2)),
+      FilterCycle::new(true, hours(21), hours(1)),
+    ]);
+    assert_eq!(spa.as_status().v1.filter_mode, ParsedEnum::new(FilterMode::Cycle1And2));
+
+    spa.set_time(ProtocolTime::from_hm(12, 0));
+    assert_eq!(spa.as_status().v1.filter_mode, ParsedEnum::new(FilterMode::Off));
+  }
+
+  #[test]
+  fn test_preferences() {
+    let mut spa = MockSpa::new();
+    assert!(spa.set_preference(&SetPreferenceMessage::TemperatureScale(TemperatureScale::Fahrenheit)));
+    assert!(spa.set_preference(&SetPreferenceMessage::ClockMode(ClockMode::Hour24)));
+    assert!(spa.set_preference(&SetPreferenceMessage::Reminders(false)));
+    assert!(!spa.set_preference(&SetPreferenceMessage::DolphinAddress(3)));
+
+    let prefs = spa.as_preferences();
+    assert_eq!(prefs.temperature_scale.as_raw(), TemperatureScale::Fahrenheit as u8);
+    assert_eq!(prefs.clock_mode.as_raw(), ClockMode::Hour24 as u8);
+    assert_eq!(prefs.reminder_set.as_raw(), Boolean::False as u8);
+  }
+
+  #[test]
+  fn test_gfci_test_failure() {
+    let mut spa = MockSpa::new();
+    spa.init_finished();
+    spa.set_gfci_test_config(GfciTestConfig {
+      result: GfciTestResult::Fail,
+      duration: Duration::ZERO,
+    });
+    assert_eq!(spa.poll_gfci_test(), None);
+
+    spa.start_gfci_test();
+    assert_eq!(spa.as_status().v1.spa_state, ParsedEnum::new(SpaState::TestMode));
+    assert_eq!(spa.poll_gfci_test(), Some(GfciTestResult::Fail));
+    assert_eq!(spa.poll_gfci_test(), None);
+    assert_eq!(spa.as_status().v1.spa_state, ParsedEnum::new(SpaState::Running));
+
+    let fault = spa.as_fault_log(0xff);
+    assert_eq!(fault.total_entries, 1);
+    assert_eq!(fault.fault_code.as_raw(), FaultCode::GfciTestFailed as u8);
+  }
+
+  #[test]
+  fn test_locks() {
+    let mut spa = MockSpa::new();
+    let toggle = MessageType::ToggleItemRequest { item_code: ParsedEnum::new(ItemCode::Pump1), dummy1: 0 };
+    let set_time = MessageType::SetTimeRequest {
+      time: ProtocolTime::from_hm(1, 0),
+      clock_mode: ClockMode::Hour12,
+    };
+
+    spa.lock(&LockRequestMessage::LockSettings);
+    assert!(spa.as_status().v1.settings_locked);
+    assert!(!spa.is_locked_out(&toggle));
+    assert!(spa.is_locked_out(&set_time));
+
+    spa.lock(&LockRequestMessage::UnlockSettings);
+    spa.lock(&LockRequestMessage::LockPanel);
+    let status = spa.as_status().v1;
+    assert!(status.panel_locked);
+    assert!(!status.settings_locked);
+    assert!(spa.is_locked_out(&toggle));
+
+    spa.lock(&LockRequestMessage::UnlockPanel);
+    assert!(!spa.is_locked_out(&toggle));
+    assert!(!spa.is_locked_out(&set_time));
+  }
+
+  #[test]
+  fn test_hold_mode() {
+    let mut spa = MockSpa::new();
+    spa.init_finished();
+    assert!(spa.toggle_item(ItemCode::Pump1));
+
+    assert!(spa.toggle_item(ItemCode::SoakMode));
+    let status = spa.as_status().v1;
+    assert_eq!(status.spa_state, ParsedEnum::new(SpaState::HoldMode));
+    assert_eq!(status.pump_status[0], ParsedEnum::new(PumpStatus::Off));
+    assert!(status.hold_timer.is_some());
+
+    let message = MessageType::StatusUpdate(spa.as_status())
+        .to_message(Channel::MulticastBroadcast).unwrap();
+    let MessageType::StatusUpdate(parsed) = MessageType::try_from(&message).unwrap() else {
+      panic!("Unexpected message type");
+    };
+    let minutes = parsed.v1.hold_timer.unwrap().as_duration().as_secs() / 60;
+    assert!((59..=60).contains(&minutes), "minutes={minutes}");
+
+    spa.set_hold_duration(Duration::ZERO);
+    spa.tick();
+    let status = spa.as_status().v1;
+    assert_eq!(status.spa_state, ParsedEnum::new(SpaState::Running));
+    assert_eq!(status.hold_timer, None);
+  }
+
+  #[test]
+  fn test_builder_hardware() {
+    let mut spa = MockSpaBuilder::new()
+        .set_pumps(vec![PumpConfig::Speed2, PumpConfig::Speed1, PumpConfig::Speed1])
+        .set_lights(2)
+        .set_blower(false)
+        .set_mister(true)
+        .set_aux(1)
+        .set_circulation_pump(false)
+        .build();
+    spa.init_finished();
+
+    let config = spa.as_configuration();
+    assert_eq!(config.pumps.len(), 3);
+    assert_eq!(config.has_lights.len(), 2);
+    assert!(!config.has_blower);
+    assert!(!config.has_circulation_pump);
+    assert_eq!(config.has_aux.len(), 1);
+    assert_eq!(config.has_mister, ParsedEnum::new(Boolean::True));
+
+    assert!(!spa.toggle_item(ItemCode::Blower));
+    assert!(spa.toggle_item(ItemCode::Mister));
+    assert!(spa.toggle_item(ItemCode::Pump3));
+    assert!(spa.toggle_item(ItemCode::Aux1));
+    assert!(!spa.toggle_item(ItemCode::Aux2));
+
+    let status = spa.as_status().v1;
+    assert_eq!(status.pump_status.len(), 3);
+    assert_eq!(status.pump_status[2], ParsedEnum::new(PumpStatus::High));
+    assert_eq!(status.light_status.len(), 2);
+    assert_eq!(status.mister_on, ParsedEnum::new(Boolean::True));
+    assert_eq!(status.circulation_pump_on, ParsedEnum::new(Boolean::False));
+  }
+}
@@ -0,0 +1,205 @@
+//! Scripted changes to the mock spa over time, letting tests reproduce real-world sequences
+//! (slow init, faults mid-session, etc) without poking at the main board from the outside.
+//!
+//! ```
+//! use std::time::Duration;
+//! use balboa_spa_messages::message_types::FaultCode;
+//! use mock_mainboard_lib::mock_spa::MockSpaState;
+//! use mock_mainboard_lib::scenario::{complete_init, fault, set_state, Scenario};
+//!
+//! let scenario = Scenario::new()
+//!     .after(Duration::from_secs(5), complete_init())
+//!     .after(Duration::from_secs(5), set_state(MockSpaState::Heating))
+//!     .after(Duration::from_secs(60), fault(FaultCode::WaterFlowLow));
+//! ```
+
+use std::fmt::{Debug, Formatter};
+use std::time::{Duration, Instant};
+use balboa_spa_messages::message_types::{FaultCode, ItemCode};
+use balboa_spa_messages::temperature::SetTemperature;
+use balboa_spa_messages::time::ProtocolTime;
+use crate::clock::Clock;
+use crate::mock_spa::{MockSpa, MockSpaState};
+
+/// Ordered list of actions to apply to the mock spa, each at a fixed offset from when the main
+/// board starts running.
+#[derive(Debug, Default)]
+pub struct Scenario {
+  steps: Vec<ScenarioStep>,
+  last_offset: Duration,
+}
+
+#[derive(Debug)]
+struct ScenarioStep {
+  offset: Duration,
+  action: ScenarioAction,
+}
+
+pub enum ScenarioAction {
+  CompleteInit,
+  SetState(MockSpaState),
+  Fault(FaultCode),
+  ToggleItem(ItemCode),
+  SetTemperature(SetTemperature),
+  SetTime(ProtocolTime),
+  Custom(Box<dyn FnOnce(&mut MockSpa) + Send>),
+}
+
+pub fn complete_init() -> ScenarioAction {
+  ScenarioAction::CompleteInit
+}
+
+/// Force the spa into a particular run state.  Note that this only lasts until something else
+/// causes the run state to be recomputed, such as a temperature change.
+pub fn set_state(state: MockSpaState) -> ScenarioAction {
+  ScenarioAction::SetState(state)
+}
+
+pub fn fault(fault_code: FaultCode) -> ScenarioAction {
+  ScenarioAction::Fault(fault_code)
+}
+
+pub fn toggle(item_code: ItemCode) -> ScenarioAction {
+  ScenarioAction::ToggleItem(item_code)
+}
+
+/// Same as a client's SetTemperatureRequest, interpreted in the spa's current temperature scale.
+pub fn set_temperature(temperature: SetTemperature) -> ScenarioAction {
+  ScenarioAction::SetTemperature(temperature)
+}
+
+pub fn set_time(time: ProtocolTime) -> ScenarioAction {
+  ScenarioAction::SetTime(time)
+}
+
+pub fn custom(f: impl FnOnce(&mut MockSpa) + Send + 'static) -> ScenarioAction {
+  ScenarioAction::Custom(Box::new(f))
+}
+
+impl Scenario {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Schedule an action relative to the previously scheduled step (or the start of the
+  /// scenario for the first step).
+  pub fn after(mut self, delay: Duration, action: ScenarioAction) -> Self {
+    let offset = self.last_offset + delay;
+    self.last_offset = offset;
+    self.insert_at(offset, action);
+    self
+  }
+
+  /// Schedule an action at an absolute offset from the start of the scenario, independent of any
+  /// other steps.
+  pub fn at(mut self, offset: Duration, action: ScenarioAction) -> Self {
+    self.insert_at(offset, action);
+    self
+  }
+
+  /// Combine the steps of two scenarios, keeping their offsets intact.
+  pub fn merge(mut self, other: Scenario) -> Self {
+    for step in other.steps {
+      self.insert_at(step.offset, step.action);
+    }
+    self
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.steps.is_empty()
+  }
+
+  fn insert_at(&mut self, offset: Duration, action: ScenarioAction) {
+    let index = self.steps.partition_point(|s| s.offset <= offset);
+    self.steps.insert(index, ScenarioStep { offset, action });
+  }
+
+  pub(crate) fn into_runner(self) -> ScenarioRunner {
+    ScenarioRunner {
+      pending: self.steps,
+      started_at: None,
+    }
+  }
+}
+
+/// Applies a [Scenario] against the mock spa as time passes.  The clock starts on the first call
+/// to [ScenarioRunner::poll].
+#[derive(Debug, Default)]
+pub(crate) struct ScenarioRunner {
+  pending: Vec<ScenarioStep>,
+  started_at: Option<Instant>,
+}
+
+impl ScenarioRunner {
+  pub fn poll(&mut self, clock: &dyn Clock, spa: &mut MockSpa) {
+    let started_at = *self.started_at.get_or_insert_with(|| clock.now());
+    self.poll_at(clock.elapsed_since(started_at), spa);
+  }
+
+  fn poll_at(&mut self, elapsed: Duration, spa: &mut MockSpa) {
+    let due = self.pending.partition_point(|s| s.offset <= elapsed);
+    for step in self.pending.drain(..due) {
+      step.action.apply(spa);
+    }
+  }
+}
+
+impl ScenarioAction {
+  pub(crate) fn apply(self, spa: &mut MockSpa) {
+    match self {
+      ScenarioAction::CompleteInit => spa.init_finished(),
+      ScenarioAction::SetState(state) => spa.run_state = state,
+      ScenarioAction::Fault(fault_code) => spa.raise_fault(fault_code),
+      ScenarioAction::ToggleItem(item_code) => {
+        spa.toggle_item(item_code);
+      }
+      ScenarioAction::SetTemperature(temperature) => {
+        spa.adjust_temperature(temperature);
+      }
+      ScenarioAction::SetTime(time) => spa.set_time(time),
+      ScenarioAction::Custom(f) => f(spa),
+    }
+  }
+}
+
+impl Debug for ScenarioAction {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ScenarioAction::CompleteInit => write!(f, "CompleteInit"),
+      ScenarioAction::SetState(state) => write!(f, "SetState({state:?})"),
+      ScenarioAction::Fault(fault_code) => write!(f, "Fault({fault_code:?})"),
+      ScenarioAction::ToggleItem(item_code) => write!(f, "ToggleItem({item_code:?})"),
+      ScenarioAction::SetTemperature(temperature) => write!(f, "SetTemperature({temperature:?})"),
+      ScenarioAction::SetTime(time) => write!(f, "SetTime({time})"),
+      ScenarioAction::Custom(_) => write!(f, "Custom(..)"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::message_types::SpaState;
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use super::*;
+
+  #[test]
+  fn test_steps_applied_in_order() {
+    let mut spa = MockSpa::new();
+    let mut runner = Scenario::new()
+        .after(Duration::from_secs(5), complete_init())
+        .after(Duration::from_secs(60), fault(FaultCode::WaterFlowLow))
+        .at(Duration::from_secs(10), set_state(MockSpaState::Hold))
+        .into_runner();
+
+    runner.poll_at(Duration::from_secs(1), &mut spa);
+    assert_eq!(spa.as_status().v1.spa_state, ParsedEnum::new(SpaState::Initializing));
+
+    runner.poll_at(Duration::from_secs(10), &mut spa);
+    assert_eq!(spa.as_status().v1.spa_state, ParsedEnum::new(SpaState::HoldMode));
+    assert_eq!(spa.as_fault_log(0xff).total_entries, 0);
+
+    runner.poll_at(Duration::from_secs(65), &mut spa);
+    assert_eq!(spa.as_fault_log(0xff).total_entries, 1);
+    assert!(runner.pending.is_empty());
+  }
+}
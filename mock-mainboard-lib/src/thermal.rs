@@ -0,0 +1,139 @@
+//! Crude simulation of the water temperature so that clients see it move over time, heating
+//! toward the set point when the heater is running and drifting toward ambient otherwise.
+
+use std::time::{Duration, Instant};
+use balboa_spa_messages::temperature::Temperature;
+
+/// Water must drop this far below the set point before heating resumes, matching the real
+/// hardware's reluctance to cycle the heater on and off constantly.
+pub const HEATING_HYSTERESIS_C: f64 = 0.5;
+
+#[derive(Debug, Clone)]
+pub struct ThermalConfig {
+  /// Water temperature when the spa powers on.
+  pub initial_celsius: f64,
+
+  /// Temperature the water cools toward when not being heated.
+  pub ambient_celsius: f64,
+
+  pub heating_rate_c_per_hour: f64,
+  pub cooling_rate_c_per_hour: f64,
+}
+
+impl Default for ThermalConfig {
+  fn default() -> Self {
+    Self {
+      initial_celsius: 38.0,
+      ambient_celsius: 20.0,
+      // Roughly a 4kW heater in a 1500L tub.
+      heating_rate_c_per_hour: 2.0,
+      cooling_rate_c_per_hour: 0.5,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThermalModel {
+  config: ThermalConfig,
+  water_celsius: f64,
+  heater_on: bool,
+  last_update: Option<Instant>,
+}
+
+impl Default for ThermalModel {
+  fn default() -> Self {
+    Self::with_config(ThermalConfig::default())
+  }
+}
+
+impl ThermalModel {
+  pub fn with_config(config: ThermalConfig) -> Self {
+    Self {
+      water_celsius: config.initial_celsius,
+      config,
+      heater_on: false,
+      last_update: None,
+    }
+  }
+
+  pub fn water_temperature(&self) -> Temperature {
+    Temperature::from_celsius(self.water_celsius)
+  }
+
+  pub fn is_heater_on(&self) -> bool {
+    self.heater_on
+  }
+
+  /// Whether the heater should be running to reach `set_point`, taking hysteresis into account.
+  pub fn needs_heat(&self, set_point: &Temperature) -> bool {
+    let set_point = set_point.as_celsius();
+    if self.heater_on {
+      self.water_celsius < set_point
+    } else {
+      self.water_celsius < set_point - HEATING_HYSTERESIS_C
+    }
+  }
+
+  /// Integrate the temperature change since the last update under the current heater state, then
+  /// switch the heater on or off for the next interval.
+  pub fn update(&mut self, now: Instant, set_point: &Temperature, heating_allowed: bool) {
+    let elapsed = self.last_update
+        .map(|last| now.saturating_duration_since(last))
+        .unwrap_or(Duration::ZERO);
+    self.last_update = Some(now);
+
+    let hours = elapsed.as_secs_f64() / 3600.0;
+    if self.heater_on {
+      self.water_celsius = (self.water_celsius + self.config.heating_rate_c_per_hour * hours)
+          .min(set_point.as_celsius().max(self.water_celsius));
+    } else {
+      let ambient = self.config.ambient_celsius;
+      let delta = self.config.cooling_rate_c_per_hour * hours;
+      self.water_celsius = if self.water_celsius > ambient {
+        (self.water_celsius - delta).max(ambient)
+      } else {
+        (self.water_celsius + delta).min(ambient)
+      };
+    }
+
+    self.heater_on = heating_allowed && self.needs_heat(set_point);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_heats_then_cools() {
+    let mut model = ThermalModel::with_config(ThermalConfig {
+      initial_celsius: 30.0,
+      ambient_celsius: 20.0,
+      heating_rate_c_per_hour: 4.0,
+      cooling_rate_c_per_hour: 1.0,
+    });
+    let set_point = Temperature::from_celsius(36.0);
+    let start = Instant::now();
+    let at = |hours: u64| start + Duration::from_secs(hours * 3600);
+
+    model.update(at(0), &set_point, true);
+    assert!(model.is_heater_on());
+    model.update(at(1), &set_point, true);
+    assert_eq!(model.water_temperature(), Temperature::from_celsius(34.0));
+
+    // Overshoot is clamped to the set point and the heater switches off.
+    model.update(at(3), &set_point, true);
+    assert_eq!(model.water_temperature(), Temperature::from_celsius(36.0));
+    assert!(!model.is_heater_on());
+
+    model.update(at(4), &set_point, true);
+    assert_eq!(model.water_temperature(), Temperature::from_celsius(35.0));
+    assert!(model.is_heater_on());
+
+    // Not allowed to heat, e.g. in hold mode.
+    model.update(at(5), &set_point, false);
+    model.update(at(6), &set_point, false);
+    assert!(model.water_temperature().as_celsius() < 36.0);
+    assert!(!model.is_heater_on());
+  }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use balboa_spa_messages::channel::{Channel, CLIENT_CTS_RANGE};
 
 /// Fancy logic encapsulated here that lets us spread the actions out a bit more across the
@@ -9,6 +10,10 @@ pub struct TimerTracker {
   dynamic_tick_helper: DynamicTickHelper,
   clear_to_send_ticks: usize,
   half_clear_to_send_ticks: usize,
+
+  /// Number of times each channel has been scheduled a ClearToSend, used to verify fairness
+  /// between clients.
+  cts_schedule_counts: HashMap<Channel, u64>,
 }
 
 #[derive(Debug, Default)]
@@ -44,6 +49,7 @@ impl TimerTracker {
       dynamic_tick_helper: Default::default(),
       clear_to_send_ticks: cts_ticks,
       half_clear_to_send_ticks: half_cts_ticks,
+      cts_schedule_counts: HashMap::new(),
     }
   }
 
@@ -52,6 +58,10 @@ impl TimerTracker {
     2 + self.clear_to_send_ticks
   }
 
+  pub fn cts_schedule_counts(&self) -> &HashMap<Channel, u64> {
+    &self.cts_schedule_counts
+  }
+
   pub fn next_action(&mut self, available_channels: impl Fn() -> Vec<Channel>) -> TickAction {
     let current = self.next_action;
     let next = match current {
@@ -76,6 +86,9 @@ impl TimerTracker {
       },
     };
     self.next_action = next;
+    if let TickAction::ClearToSend { channel } = current {
+      *self.cts_schedule_counts.entry(channel).or_default() += 1;
+    }
     current
   }
 }
@@ -143,6 +156,18 @@ mod tests {
     assert_eq!(ticks.len(), expected_total_ticks);
   }
 
+  #[test]
+  fn test_cts_schedule_counts_fair() {
+    let mut tracker = TimerTracker::with_cts_ticks(6);
+    let channels: Vec<_> = (0..3).map(|i| Channel::new_client_channel(i).unwrap()).collect();
+    for _ in 0..(tracker.total_ticks_per_cycle() * 10) {
+      tracker.next_action(|| channels.clone());
+    }
+    let counts = tracker.cts_schedule_counts();
+    assert_eq!(counts.len(), channels.len());
+    assert!(channels.iter().all(|c| counts[c] == counts[&channels[0]]));
+  }
+
   fn run_one_pass(mut tracker: TimerTracker, available_channels: Vec<Channel>) -> Vec<TickAction> {
     let mut ticks = Vec::new();
     loop {
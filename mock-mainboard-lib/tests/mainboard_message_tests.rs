@@ -1,14 +1,20 @@
 extern crate core;
 
+use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 use log::{info, LevelFilter};
 use balboa_spa_messages::channel::Channel;
 use balboa_spa_messages::framed_reader::FramedReader;
 use balboa_spa_messages::framed_writer::FramedWriter;
-use balboa_spa_messages::message_types::{MessageType, SettingsRequestMessage};
+use balboa_spa_messages::message_types::{ItemCode, MessageType, MessageTypeKind, RelayStatus, SettingsRequestMessage, SpaState};
+use balboa_spa_messages::parsed_enum::ParsedEnum;
+use mock_mainboard_lib::capture::CapturedFrame;
 use mock_mainboard_lib::channel_manager::CtsEnforcementPolicy;
-use mock_mainboard_lib::main_board::MainBoard;
+use mock_mainboard_lib::clock::{Clock, ManualClock};
+use mock_mainboard_lib::main_board::{MainBoard, MainBoardEvent};
+use mock_mainboard_lib::metrics::ErrorClass;
+use mock_mainboard_lib::mock_spa::MockSpa;
 use common_lib::transport::StdTransport;
 
 #[test]
@@ -92,3 +98,313 @@ enum GetVersionTestState {
   NeedInfoWaitingCTS,
   NeedInfoWaitingInfo,
 }
+
+#[test]
+fn mainboard_counts_messages() -> anyhow::Result<()> {
+  let _ = env_logger::builder().filter_level(LevelFilter::Debug).is_test(true).try_init();
+
+  let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+  let main_board = MainBoard::new(StdTransport::new(server_in, server_out))
+      .set_clear_to_send_policy(CtsEnforcementPolicy::Always, Duration::MAX);
+  let (shutdown_handle, runner) = main_board.into_runner();
+
+  let run_thread = thread::spawn(move || runner.run_loop());
+
+  let mut framed_reader = FramedReader::new(client_in);
+  let mut framed_writer = FramedWriter::new(client_out);
+
+  // Acquire a channel and ask for the board's information once.
+  let (mut requested, mut asked) = (false, false);
+  loop {
+    let message = framed_reader.next_message()?;
+    match MessageType::try_from(&message)? {
+      MessageType::NewClientClearToSend() if !requested => {
+        requested = true;
+        framed_writer.write(
+          &MessageType::ChannelAssignmentRequest {
+            device_type: 0x0,
+            client_hash: 0xcafe,
+          }.to_message(Channel::MulticastChannelAssignment)?)?;
+      }
+      MessageType::ChannelAssignmentResponse { channel, .. } => {
+        framed_writer.write(&MessageType::ChannelAssignmentAck().to_message(channel)?)?;
+      }
+      MessageType::ClearToSend() if !asked => {
+        framed_writer.write(
+          &MessageType::SettingsRequest(SettingsRequestMessage::Information)
+              .to_message(message.channel)?)?;
+        asked = true;
+      }
+      MessageType::InformationResponse(_) => break,
+      _ => {}
+    }
+  }
+
+  // Keep reading so the board doesn't block on writes while we query it.
+  let drain_thread = thread::spawn(move || {
+    while framed_reader.next_message().is_ok() {}
+  });
+
+  let metrics = shutdown_handle.metrics()?;
+  assert_eq!(metrics.sent_count(MessageTypeKind::ChannelAssignmentResponse), 1);
+  assert_eq!(metrics.sent_count(MessageTypeKind::InformationResponse), 1);
+  assert_eq!(metrics.received_count(MessageTypeKind::SettingsRequest), 1);
+  assert_eq!(metrics.error_count(ErrorClass::Fatal), 0);
+
+  shutdown_handle.request_shutdown();
+  drop(framed_writer);
+  run_thread.join().unwrap()?;
+  drain_thread.join().unwrap();
+
+  Ok(())
+}
+
+#[test]
+fn mainboard_evicts_silent_client() -> anyhow::Result<()> {
+  let _ = env_logger::builder().filter_level(LevelFilter::Debug).is_test(true).try_init();
+
+  let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+  let (event_tx, event_rx) = mpsc::channel();
+  let main_board = MainBoard::new(StdTransport::new(server_in, server_out))
+      .set_clear_to_send_policy(CtsEnforcementPolicy::Always, Duration::from_millis(50))
+      .set_max_cts_failures(3)
+      .set_event_listener(event_tx);
+  let (shutdown_handle, runner) = main_board.into_runner();
+
+  let run_thread = thread::spawn(move || runner.run_loop());
+
+  let mut framed_reader = FramedReader::new(client_in);
+  let mut framed_writer = FramedWriter::new(client_out);
+
+  // Acquire a channel and then go silent, ignoring every ClearToSend that follows.
+  let my_channel = loop {
+    let message = framed_reader.next_message()?;
+    match MessageType::try_from(&message)? {
+      MessageType::NewClientClearToSend() => {
+        framed_writer.write(
+          &MessageType::ChannelAssignmentRequest {
+            device_type: 0x0,
+            client_hash: 0xcafe,
+          }.to_message(Channel::MulticastChannelAssignment)?)?;
+      }
+      MessageType::ChannelAssignmentResponse { channel, .. } => {
+        framed_writer.write(&MessageType::ChannelAssignmentAck().to_message(channel)?)?;
+        break channel;
+      }
+      _ => {}
+    }
+  };
+
+  let drain_thread = thread::spawn(move || {
+    while framed_reader.next_message().is_ok() {}
+  });
+
+  let event = event_rx.recv_timeout(Duration::from_secs(5))?;
+  match event {
+    MainBoardEvent::ChannelEvicted { channel, client_hash, .. } => {
+      assert_eq!(channel, my_channel);
+      assert_eq!(client_hash, 0xcafe);
+    }
+    other => panic!("Unexpected event={other:?}"),
+  }
+
+  shutdown_handle.request_shutdown();
+  drop(framed_writer);
+  run_thread.join().unwrap()?;
+  drain_thread.join().unwrap();
+
+  Ok(())
+}
+
+#[test]
+fn mainboard_init_follows_manual_clock() -> anyhow::Result<()> {
+  let _ = env_logger::builder().filter_level(LevelFilter::Debug).is_test(true).try_init();
+
+  let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+  let clock = ManualClock::new();
+  let start = clock.now();
+  let main_board = MainBoard::new(StdTransport::new(server_in, server_out))
+      .set_init_delay(Duration::from_secs(10))
+      .set_clock(clock.clone());
+  let (shutdown_handle, runner) = main_board.into_runner();
+
+  let run_thread = thread::spawn(move || runner.run_loop());
+
+  // Advance in small steps from another thread as the runner will block writing to us while we
+  // aren't reading.
+  let driver_clock = clock.clone();
+  let driver_thread = thread::spawn(move || {
+    for _ in 0..120 {
+      driver_clock.advance(Duration::from_millis(100));
+    }
+  });
+
+  let mut framed_reader = FramedReader::new(client_in);
+  let mut saw_initializing = false;
+  loop {
+    let message = framed_reader.next_message()?;
+    if let MessageType::StatusUpdate(status) = MessageType::try_from(&message)? {
+      if status.v1.spa_state == ParsedEnum::new(SpaState::Initializing) {
+        saw_initializing = true;
+      } else {
+        break;
+      }
+    }
+  }
+
+  assert!(saw_initializing);
+  assert!(clock.elapsed_since(start) >= Duration::from_secs(10));
+
+  let drain_thread = thread::spawn(move || {
+    while framed_reader.next_message().is_ok() {}
+  });
+  driver_thread.join().unwrap();
+  shutdown_handle.request_shutdown();
+  drop(client_out);
+  run_thread.join().unwrap()?;
+  drain_thread.join().unwrap();
+
+  Ok(())
+}
+
+#[test]
+fn mainboard_replays_capture() -> anyhow::Result<()> {
+  let _ = env_logger::builder().filter_level(LevelFilter::Debug).is_test(true).try_init();
+
+  // Captured spa was already running whereas ours is still initializing, so we can tell which
+  // status updates came from the capture.
+  let mut captured_spa = MockSpa::new();
+  captured_spa.init_finished();
+  let frames = vec![
+    CapturedFrame {
+      offset: Duration::from_millis(100),
+      message: MessageType::NewClientClearToSend().to_message(Channel::MulticastChannelAssignment)?,
+    },
+    CapturedFrame {
+      offset: Duration::from_millis(200),
+      message: MessageType::StatusUpdate(captured_spa.as_status())
+          .to_message(Channel::MulticastBroadcast)?,
+    },
+  ];
+
+  let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+  let clock = ManualClock::new();
+  let (event_tx, event_rx) = mpsc::channel();
+  let main_board = MainBoard::new(StdTransport::new(server_in, server_out))
+      .set_replay(frames)
+      .set_event_listener(event_tx)
+      .set_clock(clock.clone());
+  let (shutdown_handle, runner) = main_board.into_runner();
+
+  let run_thread = thread::spawn(move || runner.run_loop());
+  let driver_thread = thread::spawn(move || {
+    for _ in 0..30 {
+      clock.advance(Duration::from_millis(100));
+    }
+  });
+
+  let mut framed_reader = FramedReader::new(client_in);
+  let first_status = loop {
+    let message = framed_reader.next_message()?;
+    if let MessageType::StatusUpdate(status) = MessageType::try_from(&message)? {
+      break status;
+    }
+  };
+  assert_eq!(first_status.v1.spa_state, ParsedEnum::new(SpaState::Running));
+
+  let drain_thread = thread::spawn(move || {
+    while framed_reader.next_message().is_ok() {}
+  });
+  assert_eq!(event_rx.recv_timeout(Duration::from_secs(5))?, MainBoardEvent::ReplayFinished);
+
+  driver_thread.join().unwrap();
+  shutdown_handle.request_shutdown();
+  drop(client_out);
+  run_thread.join().unwrap()?;
+  drain_thread.join().unwrap();
+
+  Ok(())
+}
+
+#[test]
+fn mainboard_services_wifi_module_channel() -> anyhow::Result<()> {
+  let _ = env_logger::builder().filter_level(LevelFilter::Debug).is_test(true).try_init();
+
+  let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+  let main_board = MainBoard::new(StdTransport::new(server_in, server_out))
+      .set_clear_to_send_policy(CtsEnforcementPolicy::Always, Duration::MAX)
+      .set_wifi_module_enabled(true);
+  let (shutdown_handle, runner) = main_board.into_runner();
+
+  let run_thread = thread::spawn(move || runner.run_loop());
+
+  let mut framed_reader = FramedReader::new(client_in);
+  let mut framed_writer = FramedWriter::new(client_out);
+
+  // No channel assignment, the Wi-Fi module is polled on its fixed channel straight away.
+  let board_model = loop {
+    let message = framed_reader.next_message()?;
+    match (message.channel, MessageType::try_from(&message)?) {
+      (Channel::WifiModule, MessageType::ClearToSend()) => {
+        framed_writer.write(
+          &MessageType::SettingsRequest(SettingsRequestMessage::Information)
+              .to_message(Channel::WifiModule)?)?;
+      }
+      (Channel::WifiModule, MessageType::InformationResponse(info)) => {
+        break info.system_model_number;
+      }
+      (Channel::WifiModule, mt) => panic!("Unexpected {mt:?}"),
+      _ => {}
+    }
+  };
+  assert_eq!(board_model, "Mock Spa");
+
+  let drain_thread = thread::spawn(move || {
+    while framed_reader.next_message().is_ok() {}
+  });
+  shutdown_handle.request_shutdown();
+  drop(framed_writer);
+  run_thread.join().unwrap()?;
+  drain_thread.join().unwrap();
+
+  Ok(())
+}
+
+#[test]
+fn mainboard_applies_runtime_changes() -> anyhow::Result<()> {
+  let _ = env_logger::builder().filter_level(LevelFilter::Debug).is_test(true).try_init();
+
+  let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
+  let main_board = MainBoard::new(StdTransport::new(server_in, server_out));
+  let (control_handle, runner) = main_board.into_runner();
+
+  let run_thread = thread::spawn(move || runner.run_loop());
+
+  // Read from another thread as we'll otherwise be blocking the board while sending commands.
+  let (light_tx, light_rx) = mpsc::channel();
+  let reader_thread = thread::spawn(move || {
+    let mut framed_reader = FramedReader::new(client_in);
+    while let Ok(message) = framed_reader.next_message() {
+      if let Ok(MessageType::StatusUpdate(status)) = MessageType::try_from(&message) {
+        let _ = light_tx.send(status.v1.light_status[0].clone());
+      }
+    }
+  });
+
+  let light_on = ParsedEnum::new(RelayStatus::On);
+  assert_ne!(light_rx.recv_timeout(Duration::from_secs(5))?, light_on);
+
+  control_handle.toggle_item(ItemCode::Light1)?;
+  loop {
+    if light_rx.recv_timeout(Duration::from_secs(5))? == light_on {
+      break;
+    }
+  }
+
+  control_handle.request_shutdown();
+  drop(client_out);
+  run_thread.join().unwrap()?;
+  reader_thread.join().unwrap();
+
+  Ok(())
+}
@@ -1,5 +1,6 @@
 use std::fmt::{Display, Formatter};
 use std::net::{AddrParseError, IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
 use clap::{Parser, ValueEnum};
 
@@ -7,13 +8,20 @@ const DEFAULT_TCP_PORT: u16 = 4257;
 
 #[derive(Parser, Debug)]
 pub struct Args {
-  /// Choose main board target (omit for in memory mock spa, use "-" to discover via broadcast)
+  /// Choose main board target (omit for in memory mock spa, use "-" to discover via broadcast,
+  /// or "serial:<port>" to talk to a real spa through an RS485 adapter, which needs the `serial`
+  /// feature)
   #[arg(short, long, value_parser = connect_mode_parser, default_value_t = ConnectMode::MockSpa)]
   pub connect_to: ConnectMode,
 
   /// Mock Wi-Fi behaviour
   #[arg(short, long, value_enum, default_value_t = WifiMode::Normal)]
   pub wifi_mode: WifiMode,
+
+  /// Directory to persist negotiated channels in, so that restarts reclaim the same channels
+  /// rather than growing the main board's channel table
+  #[arg(long)]
+  pub state_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -22,6 +30,7 @@ pub enum ConnectMode {
   MockSpa,
   ScanAndConnect,
   ConnectTo(SocketAddr),
+  Serial(String),
 }
 
 #[derive(ValueEnum, Debug, Clone)]
@@ -29,6 +38,12 @@ pub enum WifiMode {
   /// Simulate first run, showing a QR code to scan for a brief period
   Provision,
 
+  /// Simulate first run, offering Bluetooth provisioning alongside the QR code
+  ProvisionBle,
+
+  /// Simulate first run with a phone lacking Wi-Fi Easy Connect, falling back to SoftAP
+  ProvisionSoftAp,
+
   /// Simulate first run, but never move on from the provisioning screen
   ProvisionForever,
 
@@ -49,6 +64,7 @@ impl Display for ConnectMode {
       ConnectMode::MockSpa => write!(f, "mock"),
       ConnectMode::ScanAndConnect => write!(f, "-"),
       ConnectMode::ConnectTo(a) => write!(f, "{a}"),
+      ConnectMode::Serial(path) => write!(f, "serial:{path}"),
     }
   }
 }
@@ -58,6 +74,7 @@ fn connect_mode_parser(s: &str) -> Result<ConnectMode, String> {
     "none" => Ok(ConnectMode::None),
     "mock" => Ok(ConnectMode::MockSpa),
     "-" => Ok(ConnectMode::ScanAndConnect),
+    _ if s.starts_with("serial:") => Ok(ConnectMode::Serial(s["serial:".len()..].to_owned())),
     _ => {
       let addr = parse_with_default_port(s, DEFAULT_TCP_PORT)
           .map_err(|e| format!("Can't parse {s}: {e}"))?;
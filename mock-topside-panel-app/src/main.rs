@@ -2,14 +2,16 @@ use std::thread;
 use std::time::Duration;
 use std::io::Write;
 use log::info;
+use common_lib::assignment_store::FileAssignmentStore;
 use common_lib::transport::StdTransport;
 use clap::Parser;
 use mock_wifi_manager::MockWifiManager;
 use topside_panel_lib::app::status_printer::{BoardMonitor, NoopBoardMonitor};
 use topside_panel_lib::app::topside_panel_app::TopsidePanelApp;
+use wifi_module_lib::settings::FileSettingsStore;
 use crate::args::{Args, WifiMode};
 use crate::peer_runner::PeerManager;
-use crate::simulator_window::{SimulatorDevice, SleepDelay};
+use crate::simulator_window::{SimulatorDevice, SleepDelay, TerminalBell};
 
 mod simulator_window;
 mod args;
@@ -17,6 +19,8 @@ mod mock_wifi_manager;
 mod peer_runner;
 mod peer_mock_spa;
 mod peer_deadend;
+#[cfg(feature = "serial")]
+mod peer_serial;
 
 const GRACEFUL_SHUTDOWN_PERIOD: Duration = Duration::from_secs(3);
 
@@ -42,24 +46,33 @@ fn main() -> anyhow::Result<()> {
   let ((client_in, server_out), (server_in, client_out)) = (pipe::pipe(), pipe::pipe());
   let peer_manager = PeerManager::create(
       args.connect_to,
-      StdTransport::new(server_in, server_out));
+      StdTransport::new(server_in, server_out))?;
 
   let mock_wifi = MockWifiManager::new();
   let wifi_mode_control = mock_wifi.new_control_handle();
   match args.wifi_mode {
     WifiMode::Provision => wifi_mode_control.drive_first_run(),
+    WifiMode::ProvisionBle => wifi_mode_control.drive_first_run_ble(),
+    WifiMode::ProvisionSoftAp => wifi_mode_control.drive_first_run_soft_ap(),
     WifiMode::ProvisionForever => wifi_mode_control.drive_dpp_forever(),
     WifiMode::Normal => wifi_mode_control.drive_subsequent_run(),
     WifiMode::Fail => wifi_mode_control.drive_cant_connect(),
     WifiMode::DriverFail => wifi_mode_control.drive_init_failed(),
   }
 
-  let topside_app = TopsidePanelApp::new(
+  let mut topside_app = TopsidePanelApp::new(
       StdTransport::new(client_in, client_out),
       SimulatorDevice,
       Some(mock_wifi),
       SleepDelay,
-      None::<NoopBoardMonitor>);
+      None::<NoopBoardMonitor>)
+      .set_feedback_device(Box::new(TerminalBell));
+  if let Some(state_dir) = args.state_dir {
+    topside_app = topside_app.set_assignment_stores(
+        Box::new(FileAssignmentStore::new(state_dir.join("topside_channel"))),
+        Box::new(FileAssignmentStore::new(state_dir.join("wifi_channel"))))
+        .set_settings_store(Box::new(FileSettingsStore::new(state_dir.join("settings"))));
+  }
 
   let mut peer_handle = peer_manager.control_handle;
   let peer_runner = peer_manager.runner;
@@ -1,11 +1,15 @@
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::cell::RefCell;
+use std::net::Ipv4Addr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::{mem, thread};
 use std::time::Duration;
 use enum_kinds::EnumKind;
+use log::info;
 use MockWifiCommand::{AnswerInit, AnswerStaConnect};
 use wifi_module_lib::advertisement::Advertisement;
-use wifi_module_lib::wifi_manager::{StaAssociationError, WifiDppBootstrapped, WifiManager};
-use crate::mock_wifi_manager::MockWifiCommand::{AnswerDppListenThenWait, AnswerStaNetworkName, AnswerWaitWhileConnected, AnswerDppGenerateQr, Sleep, AnswerStoreCredentials};
+use wifi_module_lib::ip_config::StaticIpConfig;
+use wifi_module_lib::wifi_manager::{BleProvisioningParams, ConnectionInfo, StaAssociationError, WifiDppBootstrapped, WifiManager, WifiSoftApBootstrapped};
+use crate::mock_wifi_manager::MockWifiCommand::{AnswerDppListenThenWait, AnswerStaNetworkName, AnswerWaitWhileConnected, AnswerDppGenerateQr, Sleep, AnswerStoreCredentials, AnswerSoftApBootstrap, AnswerSoftApWait, OfferBle};
 
 const DEFAULT_CONNECT_DELAY: Duration = Duration::from_secs(2);
 
@@ -13,6 +17,7 @@ pub struct MockWifiManager {
   command_tx: Sender<MockWifiCommand>,
   command_rx: Receiver<MockWifiCommand>,
   advertisement: Advertisement,
+  ble: RefCell<Option<BleProvisioningParams>>,
 }
 
 impl MockWifiManager {
@@ -22,6 +27,7 @@ impl MockWifiManager {
       command_tx,
       command_rx,
       advertisement: Advertisement::fake_balboa(),
+      ble: RefCell::new(None),
     }
   }
 
@@ -32,10 +38,24 @@ impl MockWifiManager {
   }
 
   fn next_command(&self) -> MockWifiCommand {
+    self.next_command_within(None).unwrap()
+  }
+
+  /// Like [Self::next_command], but gives up with None if nothing arrives within `timeout`.
+  fn next_command_within(&self, timeout: Option<Duration>) -> Option<MockWifiCommand> {
     loop {
-      match self.command_rx.recv().unwrap() {
+      let cmd = match timeout {
+        None => self.command_rx.recv().unwrap(),
+        Some(timeout) => match self.command_rx.recv_timeout(timeout) {
+          Ok(cmd) => cmd,
+          Err(RecvTimeoutError::Timeout) => return None,
+          Err(e) => panic!("{e}"),
+        },
+      };
+      match cmd {
         Sleep(d) => thread::sleep(d),
-        other => return other,
+        OfferBle(params) => *self.ble.borrow_mut() = Some(params),
+        other => return Some(other),
       }
     }
   }
@@ -59,6 +79,9 @@ impl WifiManager<'static> for MockWifiManager {
   type DppBootstrapped<'d> = MockDppBootstrapped<'d>
   where Self: 'd;
 
+  type SoftApBootstrapped<'d> = MockSoftApBootstrapped<'d>
+  where Self: 'd;
+
   fn advertisement(&self) -> &Advertisement {
     &self.advertisement
   }
@@ -85,6 +108,20 @@ impl WifiManager<'static> for MockWifiManager {
 
     Ok(MockDppBootstrapped {
       qr_code,
+      ble: self.ble.borrow().clone(),
+      wifi_manager: self,
+    })
+  }
+
+  fn soft_ap_bootstrap(&mut self) -> Result<Self::SoftApBootstrapped<'_>, Self::Error> {
+    let ssid = match self.expect_command(MockWifiCommandKind::AnswerSoftApBootstrap)? {
+      AnswerSoftApBootstrap(r) => r?,
+      _ => panic!(),
+    };
+
+    Ok(MockSoftApBootstrapped {
+      ssid,
+      portal_url: "http://192.168.71.1/".to_owned(),
       wifi_manager: self,
     })
   }
@@ -96,6 +133,11 @@ impl WifiManager<'static> for MockWifiManager {
     }
   }
 
+  fn set_static_ip(&mut self, config: Option<&StaticIpConfig>) -> Result<(), Self::Error> {
+    // Nothing to actually configure, just show that it made it here.
+    info!("Mock Wi-Fi using {config:?}");
+    Ok(())
+  }
 
   fn sta_connect(&mut self) -> Result<(), StaAssociationError> {
     match self.expect_command(MockWifiCommandKind::AnswerStaConnect) {
@@ -105,16 +147,30 @@ impl WifiManager<'static> for MockWifiManager {
     }
   }
 
-  fn wait_while_connected(&mut self) -> Result<(), Self::Error> {
-    match self.expect_command(MockWifiCommandKind::AnswerWaitWhileConnected)? {
-      AnswerWaitWhileConnected(r) => r,
-      _ => panic!(),
+  fn wait_while_connected(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+    // Silence means we're still connected.
+    match self.next_command_within(Some(timeout)) {
+      None => Ok(true),
+      Some(AnswerWaitWhileConnected(r)) => r.map(|_| false),
+      Some(other) => {
+        let actual = MockWifiCommandKind::from(&other);
+        Err(format!("Got {actual:?}, expected {:?}", MockWifiCommandKind::AnswerWaitWhileConnected))
+      }
     }
   }
+
+  fn connection_info(&mut self) -> Result<ConnectionInfo, Self::Error> {
+    Ok(ConnectionInfo {
+      rssi: -58,
+      ip: Some(Ipv4Addr::new(192, 168, 1, 42)),
+      channel: 6,
+    })
+  }
 }
 
 pub struct MockDppBootstrapped<'b> {
   qr_code: String,
+  ble: Option<BleProvisioningParams>,
   wifi_manager: &'b mut MockWifiManager,
 }
 
@@ -127,6 +183,10 @@ impl<'d> WifiDppBootstrapped<'d, 'static> for MockDppBootstrapped<'d> {
     &self.qr_code
   }
 
+  fn get_ble_provisioning(&self) -> Option<&BleProvisioningParams> {
+    self.ble.as_ref()
+  }
+
   fn listen_then_wait(self) -> Result<Self::Credentials, Self::Error> {
     match self.wifi_manager.expect_command(MockWifiCommandKind::AnswerDppListenThenWait)? {
       AnswerDppListenThenWait(r) => r,
@@ -135,6 +195,33 @@ impl<'d> WifiDppBootstrapped<'d, 'static> for MockDppBootstrapped<'d> {
   }
 }
 
+pub struct MockSoftApBootstrapped<'b> {
+  ssid: String,
+  portal_url: String,
+  wifi_manager: &'b mut MockWifiManager,
+}
+
+impl<'d> WifiSoftApBootstrapped<'d, 'static> for MockSoftApBootstrapped<'d> {
+  type Error = String;
+
+  type Credentials = String;
+
+  fn get_ap_ssid(&self) -> &str {
+    &self.ssid
+  }
+
+  fn get_portal_url(&self) -> &str {
+    &self.portal_url
+  }
+
+  fn wait_for_credentials(self) -> Result<Self::Credentials, Self::Error> {
+    match self.wifi_manager.expect_command(MockWifiCommandKind::AnswerSoftApWait)? {
+      AnswerSoftApWait(r) => r,
+      _ => panic!(),
+    }
+  }
+}
+
 pub struct ControlHandle {
   command_tx: Sender<MockWifiCommand>,
 }
@@ -169,6 +256,43 @@ impl ControlHandle {
     ].as_slice());
   }
 
+  /// First run offering BLE provisioning alongside Wi-Fi Easy Connect.
+  pub fn drive_first_run_ble(self) {
+    self.send_cmds([
+      AnswerInit(Ok(())),
+      AnswerStaNetworkName(Ok(None)),
+      OfferBle(BleProvisioningParams {
+        device_name: "PROV_010203".to_owned(),
+        proof_of_possession: "1234abcd".to_owned(),
+      }),
+      Sleep(Duration::from_secs(1)),
+      AnswerDppGenerateQr(Ok("Hello, world".to_owned())),
+      Sleep(Duration::from_secs(5)),
+      AnswerDppListenThenWait(Ok("mynetwork".to_owned())),
+      AnswerStoreCredentials(Ok("mynetwork".to_owned())),
+      Sleep(DEFAULT_CONNECT_DELAY),
+      AnswerStaConnect(Ok(())),
+      // Never AnswerWaitWhileConnected, just stay connected...
+    ].as_slice());
+  }
+
+  /// First run where the phone can't do Wi-Fi Easy Connect, so we fall back to SoftAP.
+  pub fn drive_first_run_soft_ap(self) {
+    self.send_cmds([
+      AnswerInit(Ok(())),
+      AnswerStaNetworkName(Ok(None)),
+      Sleep(Duration::from_secs(1)),
+      AnswerDppGenerateQr(Err("DPP not supported".to_owned())),
+      AnswerSoftApBootstrap(Ok("BWGS99-setup".to_owned())),
+      Sleep(Duration::from_secs(5)),
+      AnswerSoftApWait(Ok("mynetwork".to_owned())),
+      AnswerStoreCredentials(Ok("mynetwork".to_owned())),
+      Sleep(DEFAULT_CONNECT_DELAY),
+      AnswerStaConnect(Ok(())),
+      // Never AnswerWaitWhileConnected, just stay connected...
+    ].as_slice());
+  }
+
   pub fn drive_subsequent_run(self) {
     self.send_cmds([
       AnswerInit(Ok(())),
@@ -212,10 +336,14 @@ impl ControlHandle {
 #[enum_kind(MockWifiCommandKind)]
 pub enum MockWifiCommand {
   Sleep(Duration),
+  /// Makes the next DPP session offer BLE provisioning too.
+  OfferBle(BleProvisioningParams),
   AnswerInit(Result<(), String>),
   AnswerStaNetworkName(Result<Option<String>, String>),
   AnswerDppGenerateQr(Result<String, String>),
   AnswerDppListenThenWait(Result<String, String>),
+  AnswerSoftApBootstrap(Result<String, String>),
+  AnswerSoftApWait(Result<String, String>),
   AnswerStoreCredentials(Result<String, String>),
   AnswerStaConnect(Result<(), StaAssociationError>),
   AnswerWaitWhileConnected(Result<(), String>),
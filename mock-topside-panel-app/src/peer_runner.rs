@@ -5,6 +5,8 @@ use mock_mainboard_lib::main_board::MainBoard;
 use std::time::Duration;
 use peer_deadend::new_peer_deadend;
 use peer_mock_spa::new_peer_mock_spa;
+#[cfg(feature = "serial")]
+use crate::peer_serial::new_peer_serial;
 use crate::args::ConnectMode;
 use crate::{peer_deadend, peer_mock_spa};
 use crate::peer_mock_spa::{MockSpaControlHandle, MockSpaRunner};
@@ -15,16 +17,21 @@ pub struct PeerManager {
 }
 
 impl PeerManager {
-  pub fn create<R, W>(mode: ConnectMode, transport: StdTransport<R, W>) -> Self
+  pub fn create<R, W>(mode: ConnectMode, transport: StdTransport<R, W>) -> anyhow::Result<Self>
   where
       R: Read + Send + 'static,
       W: Write + Send + 'static,
   {
-    match mode {
+    let manager = match mode {
       ConnectMode::MockSpa => new_peer_mock_spa(transport),
       ConnectMode::None => new_peer_deadend(transport),
+      #[cfg(feature = "serial")]
+      ConnectMode::Serial(path) => new_peer_serial(transport, &path)?,
+      #[cfg(not(feature = "serial"))]
+      ConnectMode::Serial(_) => anyhow::bail!("Built without serial support, try --features serial"),
       _ => todo!(),
-    }
+    };
+    Ok(manager)
   }
 }
 
@@ -0,0 +1,78 @@
+use std::io;
+use std::io::{Read, Write};
+use std::thread;
+use log::info;
+use common_lib::serial_transport::{SerialRx, SerialTransport};
+use common_lib::transport::{StdTransport, Transport};
+use crate::peer_runner::{PeerControlHandle, PeerManager, PeerRunner};
+
+/// Bridges the simulator's side of the in-memory bus to a real spa over a serial port.
+pub fn new_peer_serial<R, W>(
+    transport: StdTransport<R, W>,
+    path: &str,
+) -> anyhow::Result<PeerManager>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+  let serial = SerialTransport::open(path)?;
+  Ok(PeerManager {
+    control_handle: Box::new(SerialControlHandle),
+    runner: Box::new(SerialRunner { transport, serial }),
+  })
+}
+
+struct SerialControlHandle;
+
+impl PeerControlHandle for SerialControlHandle {
+  fn request_shutdown(&mut self) {
+    // Nothing to do, the topside panel dropping its end of the pipe is enough to end the
+    // runner.  We can't interrupt the real spa anyway.
+  }
+}
+
+struct SerialRunner<R, W> {
+  transport: StdTransport<R, W>,
+  serial: SerialTransport,
+}
+
+impl<R, W> PeerRunner for SerialRunner<R, W>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+  fn run_loop(self: Box<Self>) -> anyhow::Result<()> {
+    let (mut pipe_rx, pipe_tx) = self.transport.split();
+    let (serial_rx, mut serial_tx) = self.serial.split();
+
+    // Detached because the serial read can't be interrupted; it'll notice the pipe went away on
+    // the next byte from the spa.
+    thread::Builder::new()
+        .name("Serial Rx".to_owned())
+        .spawn(move || copy_from_spa(serial_rx, pipe_tx))?;
+
+    io::copy(&mut pipe_rx, &mut FlushingWriter(&mut serial_tx))?;
+    info!("Topside panel disconnected from serial bridge");
+    Ok(())
+  }
+}
+
+fn copy_from_spa<W: Write>(mut serial_rx: SerialRx, mut pipe_tx: W) -> io::Result<u64> {
+  io::copy(&mut serial_rx, &mut FlushingWriter(&mut pipe_tx))
+}
+
+/// Flushes after every write so that frames go out on the bus as soon as they're produced
+/// rather than waiting on a buffer to fill.
+struct FlushingWriter<'a, W>(&'a mut W);
+
+impl<W: Write> Write for FlushingWriter<'_, W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = self.0.write(buf)?;
+    self.0.flush()?;
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.0.flush()
+  }
+}
@@ -1,12 +1,17 @@
+use std::cell::Cell;
+use std::io::Write;
+use std::rc::Rc;
 use std::thread;
 use std::time::Duration;
 use embedded_graphics_simulator::{OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window};
 use embedded_graphics::pixelcolor::Rgb565;
-use embedded_graphics::geometry::Size;
+use embedded_graphics::geometry::{Point, Size};
 use embedded_graphics_simulator::sdl2::Keycode;
 use log::info;
 use topside_panel_lib::model::key_event::{Key, KeyEvent};
+use topside_panel_lib::view::feedback_device::{Feedback, FeedbackDevice};
 use topside_panel_lib::view::lcd_device::{BacklightBrightness, BacklightControl, LcdDevice};
+use topside_panel_lib::view::touch_input::TouchInput;
 use topside_panel_lib::view::ui_handler::UiDelayMs;
 use topside_panel_lib::view::user_input_event::UserInputEvent;
 use topside_panel_lib::view::window_proxy::WindowProxy;
@@ -27,19 +32,37 @@ impl LcdDevice for SimulatorDevice {
   type Display = SimulatorDisplay<Rgb565>;
   type Window = SimulatorWindowProxy;
   type Backlight = MockBacklight;
+  type Touch = MouseTouch;
 
-  fn setup(self) -> (Self::Display, Self::Window, Self::Backlight) {
+  fn setup(self) -> (Self::Display, Self::Window, Self::Backlight, Self::Touch) {
     let display = SimulatorDisplay::<Rgb565>::new(Size::new(480, 320));
     let output_settings = OutputSettingsBuilder::new()
         .scale(2)
         .build();
     let window = Window::new("Mock Topside Panel", &output_settings);
-    (display, SimulatorWindowProxy { window }, MockBacklight)
+    let mouse = Rc::new(Cell::new(None));
+    let touch = MouseTouch { mouse: mouse.clone() };
+    (display, SimulatorWindowProxy { window, mouse }, MockBacklight, touch)
+  }
+}
+
+/// Treats holding down a mouse button as touching the screen.  The mouse is only seen through
+/// the window's events, so [SimulatorWindowProxy] keeps track of it for us.
+pub struct MouseTouch {
+  mouse: Rc<Cell<Option<Point>>>,
+}
+
+impl TouchInput for MouseTouch {
+  fn read(&mut self) -> Option<Point> {
+    self.mouse.get()
   }
 }
 
 pub struct SimulatorWindowProxy {
   window: Window,
+
+  /// Where the mouse is while a button is held down, see [MouseTouch].
+  mouse: Rc<Cell<Option<Point>>>,
 }
 
 impl WindowProxy<SimulatorDisplay<Rgb565>> for SimulatorWindowProxy {
@@ -59,6 +82,18 @@ impl WindowProxy<SimulatorDisplay<Rgb565>> for SimulatorWindowProxy {
               map_keycode(keycode)
                   .map(|key| UserInputEvent::KeyEvent(KeyEvent::KeyDown { key }))
             },
+            SimulatorEvent::MouseButtonDown { point, .. } => {
+              self.mouse.set(Some(*point));
+              None
+            }
+            SimulatorEvent::MouseMove { point } if self.mouse.get().is_some() => {
+              self.mouse.set(Some(*point));
+              None
+            }
+            SimulatorEvent::MouseButtonUp { .. } => {
+              self.mouse.set(None);
+              None
+            }
             SimulatorEvent::Quit => Some(UserInputEvent::Quit),
             _ => None,
           }
@@ -77,6 +112,7 @@ fn map_keycode(keycode: &Keycode) -> Option<Key> {
     Keycode::Down => Some(Key::Down),
     Keycode::J => Some(Key::Jets1),
     Keycode::L => Some(Key::Light),
+    Keycode::M => Some(Key::Menu),
     k => {
       info!("Got: {k:?}");
       None
@@ -90,4 +126,17 @@ impl BacklightControl for MockBacklight {
   fn set_brightness(&mut self, value: BacklightBrightness) {
     info!("set_brightness={value:?}");
   }
-}
\ No newline at end of file
+}
+
+/// Rings the terminal bell for anything worth hearing, key clicks would just get annoying.
+pub struct TerminalBell;
+
+impl FeedbackDevice for TerminalBell {
+  fn play(&mut self, feedback: Feedback) {
+    info!("feedback={feedback:?}");
+    if feedback != Feedback::KeyPress {
+      let mut stdout = std::io::stdout();
+      let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+    }
+  }
+}
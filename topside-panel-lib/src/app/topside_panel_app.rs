@@ -6,15 +6,23 @@ use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::pixelcolor::PixelColor;
 use log::info;
 use lvgl::Color;
-use common_lib::bus_transport::BusTransport;
+use common_lib::assignment_store::AssignmentStore;
+use common_lib::bus_transport::{BusTransport, BusTransportRx, BusTransportTx, OverflowPolicy};
 use common_lib::transport::Transport;
+use wifi_module_lib::settings::SettingsStore;
 use wifi_module_lib::wifi_manager::WifiManager;
 use wifi_module_lib::wifi_module_client::WifiModuleClient;
 use crate::app::status_printer::BoardMonitor;
 use crate::network::topside_panel_client::TopsidePanelClient;
+use crate::view::feedback_device::FeedbackDevice;
 use crate::view::lcd_device::LcdDevice;
 use crate::view::ui_handler::{UiDelayMs, UiHandler};
 
+/// The Wi-Fi module client as [TopsidePanelApp] builds it, on a connection of its bus switch.
+pub type PanelWifiModuleClient<WIFI> = WifiModuleClient<BusTransportRx, BusTransportTx, WIFI>;
+
+type WifiModuleSetup<WIFI> = Box<dyn FnOnce(PanelWifiModuleClient<WIFI>) -> PanelWifiModuleClient<WIFI> + Send>;
+
 pub struct TopsidePanelApp<R, W, T, LCD, WIFI, DELAY, STATUS> {
   transport: T,
   _phantom_rw: PhantomData<(R, W)>,
@@ -22,6 +30,12 @@ pub struct TopsidePanelApp<R, W, T, LCD, WIFI, DELAY, STATUS> {
   wifi_manager: Option<WIFI>,
   delay: DELAY,
   status_printer: Option<STATUS>,
+  topside_assignment_store: Option<Box<dyn AssignmentStore>>,
+  wifi_assignment_store: Option<Box<dyn AssignmentStore>>,
+  settings_store: Option<Box<dyn SettingsStore>>,
+  idle_timeout: Option<Duration>,
+  feedback_device: Option<Box<dyn FeedbackDevice + Send>>,
+  wifi_module_setup: Option<WifiModuleSetup<WIFI>>,
 }
 
 impl<R, W, T, LCD, WIFI, DELAY, STATUS> TopsidePanelApp<R, W, T, LCD, WIFI, DELAY, STATUS>
@@ -49,10 +63,57 @@ where
       lcd_device,
       wifi_manager,
       delay,
-      status_printer
+      status_printer,
+      topside_assignment_store: None,
+      wifi_assignment_store: None,
+      settings_store: None,
+      idle_timeout: None,
+      feedback_device: None,
+      wifi_module_setup: None,
     }
   }
 
+  /// Persist the topside and Wi-Fi module channel assignments.  Each client needs its own store
+  /// as they are assigned different channels.
+  pub fn set_assignment_stores(
+      mut self,
+      topside: Box<dyn AssignmentStore>,
+      wifi: Box<dyn AssignmentStore>,
+  ) -> Self {
+    self.topside_assignment_store = Some(topside);
+    self.wifi_assignment_store = Some(wifi);
+    self
+  }
+
+  /// See [TopsidePanelClient::set_settings_store].
+  pub fn set_settings_store(mut self, store: Box<dyn SettingsStore>) -> Self {
+    self.settings_store = Some(store);
+    self
+  }
+
+  /// See [UiHandler::set_idle_timeout].
+  pub fn set_idle_timeout(mut self, timeout: Duration) -> Self {
+    self.idle_timeout = Some(timeout);
+    self
+  }
+
+  /// See [UiHandler::set_feedback_device].
+  pub fn set_feedback_device(mut self, device: Box<dyn FeedbackDevice + Send>) -> Self {
+    self.feedback_device = Some(device);
+    self
+  }
+
+  /// Enable whatever else the Wi-Fi module should do (settings, MQTT, the HTTP API and so on),
+  /// as most of that depends on the platform and its features.  Called after the assignment
+  /// store is set.  Does nothing without a [WifiManager].
+  pub fn set_wifi_module_setup(
+      mut self,
+      setup: impl FnOnce(PanelWifiModuleClient<WIFI>) -> PanelWifiModuleClient<WIFI> + Send + 'static,
+  ) -> Self {
+    self.wifi_module_setup = Some(Box::new(setup));
+    self
+  }
+
   pub fn run_loop(self) -> anyhow::Result<()> {
     let (
       bus_switch,
@@ -63,16 +124,31 @@ where
         (None, HomogenousTransport::new(self.transport), None)
       },
       Some(wifi_manager) => {
-        let mut switch = BusTransport::new_switch(self.transport);
+        // Both parties are on tight timing windows against the main board, so a stalled UI or
+        // Wi-Fi thread is better off missing stale frames than holding up the other.
+        let mut switch = BusTransport::new_switch(self.transport)
+            .set_overflow_policy(OverflowPolicy::DropOldest);
         let topside_transport = HomogenousTransport::new(switch.new_connection());
-        let wifi = WifiModuleClient::new(
+        let mut wifi = WifiModuleClient::new(
           switch.new_connection(),
           wifi_manager);
+        if let Some(store) = self.wifi_assignment_store {
+          wifi = wifi.set_assignment_store(store);
+        }
+        if let Some(setup) = self.wifi_module_setup {
+          wifi = setup(wifi);
+        }
         (Some(switch), topside_transport, Some(wifi))
       }
     };
 
-    let topside_client = TopsidePanelClient::new(topside_transport);
+    let mut topside_client = TopsidePanelClient::new(topside_transport);
+    if let Some(store) = self.topside_assignment_store {
+      topside_client = topside_client.set_assignment_store(store);
+    }
+    if let Some(store) = self.settings_store {
+      topside_client = topside_client.set_settings_store(store);
+    }
 
     if let Some(bus_switch) = bus_switch {
       info!("Starting bus switch...");
@@ -116,10 +192,16 @@ where
         .name("UiThread".to_owned())
         .spawn(move || {
           info!("In UI thread...");
-          let handler = UiHandler::new(
+          let mut handler = UiHandler::new(
               self.lcd_device,
               topside_control,
               topside_events);
+          if let Some(timeout) = self.idle_timeout {
+            handler = handler.set_idle_timeout(timeout);
+          }
+          if let Some(device) = self.feedback_device {
+            handler = handler.set_feedback_device(device);
+          }
           handler.run_loop(self.delay).unwrap()
         })?;
 
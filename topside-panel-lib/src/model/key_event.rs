@@ -4,10 +4,14 @@ pub enum KeyEvent {
   KeyUp { key: Key },
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Key {
   Up,
   Down,
   Jets1,
   Light,
+
+  /// Opens settings from the main screen, or goes straight back there from anywhere else.
+  /// Panels without one can hold Light instead.
+  Menu,
 }
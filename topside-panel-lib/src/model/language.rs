@@ -0,0 +1,33 @@
+/// Key for [Language::code] in the panel's [wifi_module_lib::settings::SettingsStore].
+pub const LANGUAGE_SETTING: &str = "language";
+
+/// Language for everything shown on the panel, see [crate::view::strings].  This is our own
+/// preference, the main board has no idea.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Language {
+  #[default]
+  English,
+  Spanish,
+}
+
+impl Language {
+  /// In the order the settings screen steps through them.
+  pub const ALL: [Language; 2] = [Language::English, Language::Spanish];
+
+  /// ISO 639-1 code, as stored under [LANGUAGE_SETTING].
+  pub fn code(self) -> &'static str {
+    match self {
+      Language::English => "en",
+      Language::Spanish => "es",
+    }
+  }
+
+  pub fn from_code(code: &str) -> Option<Self> {
+    Self::ALL.into_iter().find(|l| l.code() == code)
+  }
+
+  pub fn next(self) -> Self {
+    let index = Self::ALL.iter().position(|l| *l == self).unwrap();
+    Self::ALL[(index + 1) % Self::ALL.len()]
+  }
+}
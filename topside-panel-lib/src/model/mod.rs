@@ -1,3 +1,5 @@
 pub mod view_model;
 pub mod temperature_model;
 pub mod key_event;
+pub mod language;
+pub mod theme;
@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter};
 use log::info;
 use balboa_spa_messages::message_types::{TemperatureRange, TemperatureMinMax};
 use balboa_spa_messages::temperature::{ProtocolTemperature, TemperatureScale};
@@ -91,3 +92,13 @@ impl TemperatureDisplay {
     }
   }
 }
+
+/// Both parts on one line, for when there's no room to paint them separately.
+impl Display for TemperatureDisplay {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self.little_part {
+      None => write!(f, "{}", self.big_part),
+      Some(little) => write!(f, "{}.{little}", self.big_part),
+    }
+  }
+}
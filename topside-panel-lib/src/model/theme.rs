@@ -0,0 +1,35 @@
+/// Key for [Theme::code] in the panel's [wifi_module_lib::settings::SettingsStore].
+pub const THEME_SETTING: &str = "theme";
+
+/// Colors the screens are painted with, see [crate::view::palette::theme_palettes].  Like
+/// [crate::model::language::Language] this is the panel's own preference.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Theme {
+  #[default]
+  Default,
+
+  /// Black and saturated colors that stay readable with the sun on the screen.
+  HighContrast,
+}
+
+impl Theme {
+  /// In the order the settings screen steps through them.
+  pub const ALL: [Theme; 2] = [Theme::Default, Theme::HighContrast];
+
+  /// As stored under [THEME_SETTING].
+  pub fn code(self) -> &'static str {
+    match self {
+      Theme::Default => "default",
+      Theme::HighContrast => "high_contrast",
+    }
+  }
+
+  pub fn from_code(code: &str) -> Option<Self> {
+    Self::ALL.into_iter().find(|t| t.code() == code)
+  }
+
+  pub fn next(self) -> Self {
+    let index = Self::ALL.iter().position(|t| *t == self).unwrap();
+    Self::ALL[(index + 1) % Self::ALL.len()]
+  }
+}
@@ -2,9 +2,12 @@ use measurements::Temperature;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::time::Instant;
-use balboa_spa_messages::message_types::TemperatureRange;
+use balboa_spa_messages::message_types::{ClockMode, FilterCycle, TemperatureRange};
 use balboa_spa_messages::temperature::{ProtocolTemperature, TemperatureScale};
+use balboa_spa_messages::time::ProtocolTime;
 use wifi_module_lib::wifi_module_client::WifiModuleClient;
+use crate::model::language::Language;
+use crate::model::theme::Theme;
 use crate::model::temperature_model::{TemperatureModel, TemperatureRangeModel};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -12,6 +15,29 @@ pub struct ViewModel {
   pub conn_state: ConnectionState,
   pub last_model: Option<HotTubModel>,
   pub wifi_model: Option<wifi_module_lib::view_model::ViewModel>,
+
+  /// Set while the jets screen is open, to the highlighted entry of the [DeviceCategory::Jet]
+  /// devices.
+  pub jets_selection: Option<usize>,
+
+  /// Set while the settings screen is open, to the highlighted entry of [SettingsItem::ALL].
+  pub settings_selection: Option<usize>,
+  pub settings: SettingsModel,
+
+  /// Wi-Fi details opened from the settings screen, only shown if we have a [Self::wifi_model].
+  pub wifi_screen_open: bool,
+
+  /// Likewise for the fault log, which pages through [Self::fault] one entry at a time.
+  pub fault_log_open: bool,
+  pub fault: Option<FaultModel>,
+
+  /// Set by [crate::view::ui_handler::UiHandler] rather than the network side, once nobody has
+  /// touched the panel in a while.
+  pub screensaver: bool,
+
+  /// Shown over whatever screen is up for as long as it lasts, so that stale readings aren't
+  /// mistaken for current ones.
+  pub comm_problem: Option<CommProblem>,
 }
 
 impl Default for ViewModel {
@@ -20,6 +46,14 @@ impl Default for ViewModel {
       conn_state: ConnectionState::WaitingForPeer,
       last_model: None,
       wifi_model: None,
+      jets_selection: None,
+      settings_selection: None,
+      settings: SettingsModel::default(),
+      wifi_screen_open: false,
+      fault_log_open: false,
+      fault: None,
+      screensaver: false,
+      comm_problem: None,
     }
   }
 }
@@ -32,19 +66,64 @@ pub enum ConnectionState {
   Idle,
 }
 
+/// Trouble talking to the main board, most pressing first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CommProblem {
+  /// Nothing at all heard from the main board in a while.
+  MainboardSilent,
+
+  /// The channel we were assigned has been dropped and we're negotiating a new one.
+  ChannelLost,
+
+  /// A burst of frames that didn't decode, usually wiring or interference.
+  Garbled,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct HotTubModel {
   pub received_at: Instant,
   pub current_temp: Option<TemperatureModel>,
   pub set_temp: TemperatureModel,
   pub is_heating: bool,
+
+  /// As the main board reports temperatures, which the models above are already in.
+  pub scale: TemperatureScale,
+
+  /// Time of day according to the main board, shown as per [Self::clock_mode].
+  pub time: ProtocolTime,
+  pub clock_mode: ClockMode,
+
+  /// The main board wants attention, usually for a fault that's now in the fault log.
+  pub notification: bool,
   pub temp_range: TemperatureRangeModel,
   pub devices: HashMap<DeviceCategory, Vec<DeviceModel>>,
 }
 
+impl HotTubModel {
+  pub fn clock_text(&self) -> String {
+    let minutes = self.time.as_duration().as_secs() / 60;
+    let (hour, minute) = (minutes / 60, minutes % 60);
+    match self.clock_mode {
+      ClockMode::Hour24 => format!("{hour:02}:{minute:02}"),
+      ClockMode::Hour12 => {
+        let suffix = if hour < 12 { "AM" } else { "PM" };
+        let hour = match hour % 12 {
+          0 => 12,
+          h => h,
+        };
+        format!("{hour}:{minute:02} {suffix}")
+      }
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct DeviceModel {
   pub category: DeviceCategory,
+
+  /// Position among all devices of this category the main board knows of, configured or not.
+  /// For jets that makes it the pump number less one.
+  pub index: usize,
   pub current_level: DeviceLevel,
   pub available_levels: Vec<DeviceLevel>,
 }
@@ -62,3 +141,62 @@ pub enum DeviceLevel {
   PartialOn,
   FullOn,
 }
+
+/// User preferences as last read back from the main board, each unknown until it has answered.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SettingsModel {
+  pub temperature_scale: Option<TemperatureScale>,
+  pub clock_mode: Option<ClockMode>,
+  pub reminders: Option<bool>,
+  pub filter_cycles: Option<Vec<FilterCycle>>,
+
+  /// Kept by the panel itself rather than the main board, so always known.
+  pub language: Language,
+  pub theme: Theme,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultModel {
+  /// Zero is the oldest entry.
+  pub entry_number: u8,
+  pub total_entries: u8,
+
+  /// Described by [crate::view::strings::fault_text].
+  pub fault_code: u8,
+  pub days_ago: u8,
+  pub time: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SettingsItem {
+  TemperatureScale,
+  ClockMode,
+  Language,
+  Theme,
+  Reminders,
+  Filter1Start,
+  Filter1Hours,
+  Filter2Enabled,
+  Filter2Start,
+  Filter2Hours,
+  Wifi,
+  FaultLog,
+}
+
+impl SettingsItem {
+  /// In the order they're listed on the settings screen.
+  pub const ALL: [SettingsItem; 12] = [
+    SettingsItem::TemperatureScale,
+    SettingsItem::ClockMode,
+    SettingsItem::Language,
+    SettingsItem::Theme,
+    SettingsItem::Reminders,
+    SettingsItem::Filter1Start,
+    SettingsItem::Filter1Hours,
+    SettingsItem::Filter2Enabled,
+    SettingsItem::Filter2Start,
+    SettingsItem::Filter2Hours,
+    SettingsItem::Wifi,
+    SettingsItem::FaultLog,
+  ];
+}
@@ -1,28 +1,46 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::time::Instant;
-use balboa_spa_messages::message_types::{Boolean, ConfigurationResponseMessage, HeatingState, PumpConfig, PumpStatus, RelayStatus, StatusUpdateMessage, StatusUpdateResponseV1};
+use balboa_spa_messages::message_types::{Boolean, ClockMode, ConfigurationResponseMessage, HeatingState, PumpConfig, PumpStatus, RelayStatus, StatusUpdateMessage, StatusUpdateResponseV1};
 use common_lib::channel_filter::ChannelFilter;
+use common_lib::cts_metrics::CtsMetrics;
 use crate::network::topside_state_machine::{TopsideStateKind, TopsideStateMachine};
 use common_lib::cts_state_machine::{CtsStateKind, CtsStateMachine};
+use crate::model::language::Language;
+use crate::model::theme::Theme;
 use crate::model::temperature_model::{TemperatureModel, TemperatureRangeModel};
-use crate::model::view_model::{ConnectionState, DeviceCategory, DeviceLevel, DeviceModel, HotTubModel, ViewModel};
+use crate::model::view_model::{CommProblem, ConnectionState, DeviceCategory, DeviceLevel, DeviceModel, FaultModel, HotTubModel, SettingsModel, ViewModel};
 
 #[derive(Debug)]
 pub(crate) struct AppState {
   pub cts_state_machine: CtsStateMachine,
   pub topside_state_machine: TopsideStateMachine,
   pub wifi_model: Option<wifi_module_lib::view_model::ViewModel>,
+  pub jets_selection: Option<usize>,
+  pub settings_selection: Option<usize>,
+  pub wifi_screen_open: bool,
+  pub fault_log_open: bool,
+  pub language: Language,
+  pub theme: Theme,
+  pub comm_problem: Option<CommProblem>,
 }
 
 impl Default for AppState {
   fn default() -> Self {
     let mut topside_state_machine = TopsideStateMachine::new();
     topside_state_machine.set_channel_filter(ChannelFilter::BlockEverything);
+    topside_state_machine.set_cts_metrics(CtsMetrics::new("topside"));
     Self {
       cts_state_machine: CtsStateMachine::default(),
       topside_state_machine,
       wifi_model: None,
+      jets_selection: None,
+      settings_selection: None,
+      wifi_screen_open: false,
+      fault_log_open: false,
+      language: Language::default(),
+      theme: Theme::default(),
+      comm_problem: None,
     }
   }
 }
@@ -35,6 +53,8 @@ impl AppState {
       cts_state: self.cts_state_machine.state_kind(),
       topside_state: self.topside_state_machine.state_kind(),
       status,
+      settings: self.generate_settings_model(),
+      fault: self.generate_fault_model(),
     }
   }
 
@@ -45,13 +65,22 @@ impl AppState {
       conn_state,
       last_model,
       wifi_model: self.wifi_model.clone(),
+      jets_selection: self.jets_selection,
+      settings_selection: self.settings_selection,
+      settings: self.generate_settings_model(),
+      wifi_screen_open: self.wifi_screen_open,
+      fault_log_open: self.fault_log_open,
+      fault: self.generate_fault_model(),
+      screensaver: false,
+      comm_problem: self.comm_problem,
     }
   }
 
   fn generate_conn_state(&self) -> ConnectionState {
     match self.cts_state_machine.state_kind() {
       CtsStateKind::WaitingForNewClientCTS => ConnectionState::WaitingForPeer,
-      CtsStateKind::WaitingForChannelAssignment => ConnectionState::Negotiating,
+      CtsStateKind::WaitingForChannelAssignment |
+      CtsStateKind::ReclaimingChannel => ConnectionState::Negotiating,
       CtsStateKind::ChannelAssigned => {
         match self.topside_state_machine.state_kind() {
           TopsideStateKind::ReadingStatus => ConnectionState::Idle,
@@ -61,7 +90,31 @@ impl AppState {
     }
   }
 
-  fn generate_hot_tub_model(&self) -> Option<HotTubModel> {
+  pub fn generate_settings_model(&self) -> SettingsModel {
+    let context = &self.topside_state_machine.context;
+    let preferences = context.preferences.as_ref();
+    SettingsModel {
+      temperature_scale: preferences.and_then(|p| p.temperature_scale.as_ref().copied()),
+      clock_mode: preferences.and_then(|p| p.clock_mode.as_ref().copied()),
+      reminders: preferences.and_then(|p| p.reminder_set.as_ref().map(bool::from)),
+      filter_cycles: context.filter_cycles.clone(),
+      language: self.language,
+      theme: self.theme,
+    }
+  }
+
+  pub fn generate_fault_model(&self) -> Option<FaultModel> {
+    let fault = self.topside_state_machine.context.fault.as_ref()?;
+    Some(FaultModel {
+      entry_number: fault.entry_number,
+      total_entries: fault.total_entries,
+      fault_code: fault.fault_code.as_raw(),
+      days_ago: fault.days_ago,
+      time: fault.time.to_string(),
+    })
+  }
+
+  pub fn generate_hot_tub_model(&self) -> Option<HotTubModel> {
     let info = &self.topside_state_machine.context.info;
     let temp_ranges = &self.topside_state_machine.context.settings0x04;
     let config = &self.topside_state_machine.context.config;
@@ -93,6 +146,10 @@ impl AppState {
               current_temp,
               set_temp,
               is_heating,
+              scale: status_v1.set_temperature.raw_scale,
+              time: status_v1.time,
+              clock_mode: status_v1.clock_mode.as_ref().copied().unwrap_or(ClockMode::Hour12),
+              notification: bool::from(status_v1.notification_set.as_ref().unwrap_or(&Boolean::False)),
               devices,
               temp_range,
             };
@@ -110,6 +167,8 @@ pub struct FastSnapshot {
   cts_state: CtsStateKind,
   topside_state: TopsideStateKind,
   status: Option<StatusUpdateMessage>,
+  settings: SettingsModel,
+  fault: Option<FaultModel>,
 }
 
 struct DeviceMapper;
@@ -118,19 +177,21 @@ impl DeviceMapper {
     let mut out = HashMap::new();
 
     let jets_zipped = config.pumps.iter()
-        .zip(&status.pump_status);
+        .zip(&status.pump_status)
+        .enumerate();
     let jets: Vec<_> = jets_zipped
-        .filter_map(|(c, s)| {
-          Self::convert_pump(c.as_ref(), s.as_ref())
+        .filter_map(|(i, (c, s))| {
+          Self::convert_pump(i, c.as_ref(), s.as_ref())
         })
         .collect();
     out.insert(DeviceCategory::Jet, jets);
 
     let lights_zipped = config.has_lights.iter()
-        .zip(&status.light_status);
+        .zip(&status.light_status)
+        .enumerate();
     let lights: Vec<_> = lights_zipped
-        .filter_map(|(c, s)| {
-          Self::convert_relay(DeviceCategory::Light, c.as_ref(), s.as_ref())
+        .filter_map(|(i, (c, s))| {
+          Self::convert_relay(DeviceCategory::Light, i, c.as_ref(), s.as_ref())
         })
         .collect();
     out.insert(DeviceCategory::Light, lights);
@@ -140,6 +201,7 @@ impl DeviceMapper {
 
   fn convert_relay(
       category: DeviceCategory,
+      index: usize,
       config: Option<&Boolean>,
       status: Option<&RelayStatus>
   ) -> Option<DeviceModel> {
@@ -155,6 +217,7 @@ impl DeviceMapper {
         };
         Some(DeviceModel {
           category,
+          index,
           available_levels: vec![current_level],
           current_level,
         })
@@ -163,7 +226,7 @@ impl DeviceMapper {
     }
   }
 
-  fn convert_pump(config: Option<&PumpConfig>, status: Option<&PumpStatus>) -> Option<DeviceModel> {
+  fn convert_pump(index: usize, config: Option<&PumpConfig>, status: Option<&PumpStatus>) -> Option<DeviceModel> {
     let level = config.unwrap_or(&PumpConfig::None);
     let available_levels = match level {
       PumpConfig::None => return None,
@@ -179,6 +242,7 @@ impl DeviceMapper {
         };
         Some(DeviceModel {
           category: DeviceCategory::Jet,
+          index,
           available_levels,
           current_level,
         })
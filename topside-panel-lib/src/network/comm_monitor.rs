@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::model::view_model::CommProblem;
+
+/// The main board normally sends status several times a second, this long without hearing
+/// anything at all and it's presumed gone.
+const SILENCE: Duration = Duration::from_secs(3);
+
+/// This many frames that couldn't be decoded within [ERROR_WINDOW] is a garbled line rather
+/// than the odd glitch.
+const ERROR_SPIKE: usize = 5;
+const ERROR_WINDOW: Duration = Duration::from_secs(10);
+
+/// Works out whether there's a [CommProblem] worth telling the user about from what's been
+/// happening on the bus.
+///
+/// Time is passed in rather than read so that this can be exercised without hardware.
+#[derive(Debug, Default)]
+pub(crate) struct CommMonitor {
+  last_message: Option<Instant>,
+  had_channel: bool,
+  channel_lost: bool,
+
+  /// When each recent decode error happened, oldest first.
+  errors: VecDeque<Instant>,
+}
+
+impl CommMonitor {
+  pub fn on_message(&mut self, now: Instant) {
+    self.last_message = Some(now);
+  }
+
+  pub fn on_decode_error(&mut self, now: Instant) {
+    self.errors.push_back(now);
+  }
+
+  /// Whether the CTS state machine currently has a channel.  Not having one yet at startup is
+  /// expected, only losing one we had is a problem.
+  pub fn on_channel(&mut self, assigned: bool) {
+    self.channel_lost = self.had_channel && !assigned;
+    self.had_channel |= assigned;
+  }
+
+  /// The most fundamental problem first, a silent main board explains a lost channel too.
+  pub fn problem(&mut self, now: Instant) -> Option<CommProblem> {
+    while self.errors.front().is_some_and(|at| now - *at >= ERROR_WINDOW) {
+      self.errors.pop_front();
+    }
+    if self.last_message.is_some_and(|at| now - at >= SILENCE) {
+      Some(CommProblem::MainboardSilent)
+    } else if self.channel_lost {
+      Some(CommProblem::ChannelLost)
+    } else if self.errors.len() >= ERROR_SPIKE {
+      Some(CommProblem::Garbled)
+    } else {
+      None
+    }
+  }
+
+  /// When [Self::problem] may next change with nothing else happening in between, as the main
+  /// board falls silent or old errors age out.
+  pub fn next_deadline(&self, now: Instant) -> Option<Instant> {
+    let silent_at = self.last_message
+        .map(|at| at + SILENCE)
+        .filter(|deadline| *deadline > now);
+    let error_expires = self.errors.front().map(|at| *at + ERROR_WINDOW);
+    silent_at.into_iter().chain(error_expires).min()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_silence() {
+    let mut monitor = CommMonitor::default();
+    let start = Instant::now();
+
+    // Never heard from at all is just the loading screen, not a problem.
+    assert_eq!(monitor.problem(start + SILENCE * 2), None);
+
+    monitor.on_message(start);
+    assert_eq!(monitor.next_deadline(start), Some(start + SILENCE));
+    assert_eq!(monitor.problem(start + SILENCE / 2), None);
+    assert_eq!(monitor.problem(start + SILENCE), Some(CommProblem::MainboardSilent));
+    assert_eq!(monitor.next_deadline(start + SILENCE), None);
+
+    monitor.on_message(start + SILENCE * 2);
+    assert_eq!(monitor.problem(start + SILENCE * 2), None);
+  }
+
+  #[test]
+  fn test_channel_lost() {
+    let mut monitor = CommMonitor::default();
+    monitor.on_channel(false);
+    assert_eq!(monitor.problem(Instant::now()), None);
+    monitor.on_channel(true);
+    monitor.on_channel(false);
+    assert_eq!(monitor.problem(Instant::now()), Some(CommProblem::ChannelLost));
+    monitor.on_channel(true);
+    assert_eq!(monitor.problem(Instant::now()), None);
+  }
+
+  #[test]
+  fn test_error_spike() {
+    let mut monitor = CommMonitor::default();
+    let start = Instant::now();
+    for i in 0..ERROR_SPIKE {
+      assert_eq!(monitor.problem(start), None);
+      monitor.on_decode_error(start + Duration::from_secs(i as u64));
+    }
+    let last = start + Duration::from_secs(ERROR_SPIKE as u64 - 1);
+    assert_eq!(monitor.problem(last), Some(CommProblem::Garbled));
+    assert_eq!(monitor.next_deadline(last), Some(start + ERROR_WINDOW));
+    assert_eq!(monitor.problem(start + ERROR_WINDOW), None);
+  }
+}
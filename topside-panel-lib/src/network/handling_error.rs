@@ -24,7 +24,10 @@ impl From<MessageHandlingError> for HandlingError {
 impl From<PayloadEncodeError> for HandlingError {
   fn from(value: PayloadEncodeError) -> Self {
     match value {
-      PayloadEncodeError::GenericError(e) => HandlingError::FatalError(format!("{e:?})")),
+      e @ (PayloadEncodeError::FieldTooLong { .. } |
+          PayloadEncodeError::CleanupCycleOutOfRange(_) |
+          PayloadEncodeError::FilterCycleOutOfRange(_) |
+          PayloadEncodeError::PackingError(_)) => HandlingError::FatalError(format!("{e:?}")),
       PayloadEncodeError::GenericIoError(e) => HandlingError::FatalError(format!("{e:?}")),
       PayloadEncodeError::NotSupported => HandlingError::FatalError("Not supported".to_owned()),
     }
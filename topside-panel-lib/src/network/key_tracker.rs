@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+use crate::model::key_event::{Key, KeyEvent};
+
+/// How long a key must be held for its alternate action.
+const LONG_PRESS: Duration = Duration::from_secs(1);
+
+/// Pause after the first press of a repeating key before it starts repeating.
+const REPEAT_DELAY: Duration = Duration::from_millis(500);
+
+/// Repeats start out this far apart and speed up by [REPEAT_ACCELERATION] each time, down to
+/// [REPEAT_MIN_INTERVAL], so long trips across the temperature range don't take forever.
+const REPEAT_START_INTERVAL: Duration = Duration::from_millis(250);
+const REPEAT_ACCELERATION: Duration = Duration::from_millis(20);
+const REPEAT_MIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What a key press amounts to once timing is taken into account.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum KeyAction {
+  Press(Key),
+  LongPress(Key),
+}
+
+/// Turns raw key up/down events into [KeyAction]s.  Up and Down act as soon as they go down
+/// and then repeat for as long as they're held.  Jets and Light act on release, or as a long
+/// press once held for [LONG_PRESS] without waiting for release.
+///
+/// Time is passed in rather than read so that this can be exercised without hardware.
+#[derive(Debug, Default)]
+pub(crate) struct KeyTracker {
+  held: Option<HeldKey>,
+}
+
+#[derive(Debug)]
+struct HeldKey {
+  key: Key,
+
+  /// When the next repeat or long press is due, None once there's nothing more to do.
+  deadline: Option<Instant>,
+  repeats: u32,
+}
+
+impl KeyTracker {
+  pub fn on_event(&mut self, event: KeyEvent, now: Instant) -> Option<KeyAction> {
+    match event {
+      // Keyboards (the simulator's included) have their own auto-repeat, we do our own.
+      KeyEvent::KeyDown { key } if self.held.as_ref().is_some_and(|held| held.key == key) => None,
+      KeyEvent::KeyDown { key } => {
+        let (deadline, action) = if repeats(key) {
+          (now + REPEAT_DELAY, Some(KeyAction::Press(key)))
+        } else {
+          (now + LONG_PRESS, None)
+        };
+        self.held = Some(HeldKey { key, deadline: Some(deadline), repeats: 0 });
+        action
+      }
+      KeyEvent::KeyUp { key } => {
+        let held = self.held.take().filter(|held| held.key == key)?;
+        // Released before the long press went off, so it was a plain press after all.
+        let short_press = !repeats(key) && held.deadline.is_some();
+        short_press.then_some(KeyAction::Press(key))
+      }
+    }
+  }
+
+  /// Whether `event` starts a press, as opposed to releasing a key or being an auto-repeat of
+  /// one that's already held.
+  pub fn is_new_press(&self, event: &KeyEvent) -> bool {
+    match event {
+      KeyEvent::KeyDown { key } => !self.held.as_ref().is_some_and(|held| held.key == *key),
+      KeyEvent::KeyUp { .. } => false,
+    }
+  }
+
+  /// Repeats or long presses that have come due while a key is held.
+  pub fn poll(&mut self, now: Instant) -> Option<KeyAction> {
+    let held = self.held.as_mut()?;
+    if held.deadline.is_some_and(|deadline| now >= deadline) {
+      if repeats(held.key) {
+        let speedup = REPEAT_ACCELERATION * held.repeats;
+        let interval = REPEAT_START_INTERVAL.saturating_sub(speedup).max(REPEAT_MIN_INTERVAL);
+        held.repeats += 1;
+        held.deadline = Some(now + interval);
+        Some(KeyAction::Press(held.key))
+      } else {
+        held.deadline = None;
+        Some(KeyAction::LongPress(held.key))
+      }
+    } else {
+      None
+    }
+  }
+
+  /// When [Self::poll] next needs calling, if at all.
+  pub fn next_deadline(&self) -> Option<Instant> {
+    self.held.as_ref()?.deadline
+  }
+}
+
+fn repeats(key: Key) -> bool {
+  matches!(key, Key::Up | Key::Down)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_repeat_accelerates() {
+    let mut tracker = KeyTracker::default();
+    let start = Instant::now();
+
+    assert_eq!(tracker.on_event(KeyEvent::KeyDown { key: Key::Up }, start), Some(KeyAction::Press(Key::Up)));
+    assert_eq!(tracker.poll(start + REPEAT_DELAY / 2), None);
+    assert_eq!(tracker.on_event(KeyEvent::KeyDown { key: Key::Up }, start + REPEAT_DELAY / 2), None);
+
+    let mut now = start + REPEAT_DELAY;
+    let mut intervals = Vec::new();
+    while now < start + Duration::from_secs(5) {
+      assert_eq!(tracker.poll(now), Some(KeyAction::Press(Key::Up)));
+      let next = tracker.next_deadline().unwrap();
+      intervals.push(next - now);
+      now = next;
+    }
+    assert_eq!(intervals[0], REPEAT_START_INTERVAL);
+    assert!(intervals.windows(2).all(|w| w[1] <= w[0]));
+    assert_eq!(*intervals.last().unwrap(), REPEAT_MIN_INTERVAL);
+
+    assert_eq!(tracker.on_event(KeyEvent::KeyUp { key: Key::Up }, now), None);
+    assert_eq!(tracker.next_deadline(), None);
+  }
+
+  #[test]
+  fn test_long_press() {
+    let mut tracker = KeyTracker::default();
+    let start = Instant::now();
+
+    assert_eq!(tracker.on_event(KeyEvent::KeyDown { key: Key::Light }, start), None);
+    let released = start + LONG_PRESS / 2;
+    assert_eq!(tracker.on_event(KeyEvent::KeyUp { key: Key::Light }, released), Some(KeyAction::Press(Key::Light)));
+
+    assert_eq!(tracker.on_event(KeyEvent::KeyDown { key: Key::Jets1 }, start), None);
+    assert_eq!(tracker.poll(start + LONG_PRESS), Some(KeyAction::LongPress(Key::Jets1)));
+    assert_eq!(tracker.poll(start + LONG_PRESS * 2), None);
+    assert_eq!(tracker.on_event(KeyEvent::KeyUp { key: Key::Jets1 }, start + LONG_PRESS * 2), None);
+  }
+
+  #[test]
+  fn test_is_new_press() {
+    let mut tracker = KeyTracker::default();
+    let start = Instant::now();
+    let down = KeyEvent::KeyDown { key: Key::Up };
+
+    assert!(tracker.is_new_press(&down));
+    tracker.on_event(down, start);
+    assert!(!tracker.is_new_press(&down));
+    assert!(tracker.is_new_press(&KeyEvent::KeyDown { key: Key::Down }));
+    assert!(!tracker.is_new_press(&KeyEvent::KeyUp { key: Key::Up }));
+    tracker.on_event(KeyEvent::KeyUp { key: Key::Up }, start);
+    assert!(tracker.is_new_press(&down));
+  }
+}
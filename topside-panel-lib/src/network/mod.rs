@@ -2,3 +2,5 @@ pub mod topside_panel_client;
 mod handling_error;
 mod topside_state_machine;
 mod app_state;
+mod key_tracker;
+mod comm_monitor;
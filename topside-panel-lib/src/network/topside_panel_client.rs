@@ -13,22 +13,56 @@ use balboa_spa_messages::channel::Channel;
 use balboa_spa_messages::framed_reader::FramedReader;
 use balboa_spa_messages::framed_writer::FramedWriter;
 use balboa_spa_messages::message::Message;
-use balboa_spa_messages::message_types::{ConfigurationResponseMessage, InformationResponseMessage, MessageType, PayloadEncodeError, PayloadParseError, StatusUpdateMessage};
-use balboa_spa_messages::temperature::Direction;
+use balboa_spa_messages::message_types::{ClockMode, ConfigurationResponseMessage, FilterCycle, InformationResponseMessage, ItemCode, MessageType, PayloadEncodeError, PayloadParseError, SetPreferenceMessage, SettingsRequestMessage, StatusUpdateMessage};
+use balboa_spa_messages::parsed_enum::ParsedEnum;
+use balboa_spa_messages::temperature::{Direction, ProtocolTemperature, TemperatureScale};
+use common_lib::assignment_store::AssignmentStore;
 use common_lib::message_logger::{MessageDirection, MessageLogger};
+use common_lib::request_tracker::RequestEvent;
+use common_lib::metrics::{Gauge, Metrics};
 use common_lib::transport::Transport;
+use wifi_module_lib::settings::SettingsStore;
 use HandlingError::ShutdownRequested;
 use crate::network::app_state::AppState;
+use crate::network::comm_monitor::CommMonitor;
+use crate::network::key_tracker::{KeyAction, KeyTracker};
 use common_lib::channel_filter::ChannelFilter;
+use common_lib::cts_state_machine::CtsStateKind;
 use common_lib::view_model_event_handle::{ViewEvent, ViewModelEventHandle};
 use crate::network::handling_error::HandlingError;
 use crate::network::handling_error::HandlingError::FatalError;
-use crate::model::view_model::ViewModel;
+use crate::model::view_model::{DeviceCategory, DeviceLevel, DeviceModel, SettingsItem, ViewModel};
 use crate::model::key_event::{Key, KeyEvent};
+use crate::model::language::{Language, LANGUAGE_SETTING};
+use crate::model::theme::{Theme, THEME_SETTING};
+
+/// Item codes of the pumps in the order the main board reports them.
+const PUMP_ITEMS: [ItemCode; 6] = [
+  ItemCode::Pump1,
+  ItemCode::Pump2,
+  ItemCode::Pump3,
+  ItemCode::Pump4,
+  ItemCode::Pump5,
+  ItemCode::Pump6,
+];
+
+/// Likewise for lights, as per [ConfigurationResponseMessage::has_lights].
+const LIGHT_ITEMS: [ItemCode; 2] = [ItemCode::Light1, ItemCode::Light2];
+
+const HOUR_SECS: u64 = 60 * 60;
+
+/// Out of range entry numbers get the newest fault log entry.
+const LATEST_FAULT_ENTRY: u8 = 0xff;
+
+/// Longest filter cycle offered on the settings screen, the main board allows for more but
+/// stepping through them with one button gets tedious.
+const MAX_FILTER_HOURS: u64 = 12;
 
 pub struct TopsidePanelClient<R, W> {
   framed_reader: FramedReader<R>,
   framed_writer: FramedWriter<W>,
+  assignment_store: Option<Box<dyn AssignmentStore>>,
+  settings_store: Option<Box<dyn SettingsStore>>,
 }
 
 impl<R: Read, W: Write> TopsidePanelClient<R, W> {
@@ -39,18 +73,44 @@ impl<R: Read, W: Write> TopsidePanelClient<R, W> {
     Self {
       framed_reader,
       framed_writer,
+      assignment_store: None,
+      settings_store: None,
     }
   }
 
+  /// Keep the same channel across restarts, see [common_lib::cts_state_machine::CtsStateMachine::set_assignment_store].
+  pub fn set_assignment_store(mut self, store: Box<dyn AssignmentStore>) -> Self {
+    self.assignment_store = Some(store);
+    self
+  }
+
+  /// Where the panel's own preferences live, such as [LANGUAGE_SETTING].  Without one they're
+  /// forgotten on restart.
+  pub fn set_settings_store(mut self, store: Box<dyn SettingsStore>) -> Self {
+    self.settings_store = Some(store);
+    self
+  }
+
   pub fn into_runner(self) -> (ControlHandle, ViewModelEventHandle<ViewModel>, Runner<R, W>) {
     let (commands_tx, commands_rx) = mpsc::sync_channel(32);
     let (events_tx, events_rx) = mpsc::channel();
     let message_reader = MessageReader {
+      frames_with_errors: Metrics::global().gauge("topside.frames_with_errors"),
       message_tx: commands_tx.clone(),
       framed_reader: self.framed_reader,
     };
 
-    let init_view_model = ViewModel::default();
+    let mut state = AppState::default();
+    if let Some(store) = self.assignment_store {
+      state.cts_state_machine.set_assignment_store(store);
+    }
+    if let Some(store) = &self.settings_store {
+      state.language = load_choice(store.as_ref(), LANGUAGE_SETTING, Language::from_code);
+      state.theme = load_choice(store.as_ref(), THEME_SETTING, Theme::from_code);
+    }
+
+    // From the state rather than a default so that the loading screen is in the right language.
+    let init_view_model = state.generate_view_model();
     let _ = events_tx.send(ViewEvent::ModelUpdated(init_view_model.clone()));
     let event_handler = EventHandler {
       commands_rx,
@@ -58,7 +118,11 @@ impl<R: Read, W: Write> TopsidePanelClient<R, W> {
       framed_writer: self.framed_writer,
       message_logger: MessageLogger::new(module_path!()),
       last_view_model: init_view_model,
-      state: AppState::default(),
+      key_tracker: KeyTracker::default(),
+      comm_monitor: CommMonitor::default(),
+      pending_set_temp: None,
+      settings_store: self.settings_store,
+      state,
     };
 
     let control_handle = ControlHandle {
@@ -134,13 +198,22 @@ impl <R: Read + Send + 'static, W: Write + Send + 'static> Runner<R, W> {
 
 struct MessageReader<R> {
   framed_reader: FramedReader<R>,
+  frames_with_errors: Gauge,
   message_tx: SyncSender<Command>,
 }
 
 impl<R: Read + Send> MessageReader<R> {
   pub fn run_loop(mut self) -> Result<(), SendError<Command>> {
+    let mut frames_with_errors = 0;
     loop {
-      match self.framed_reader.next_message() {
+      let result = self.framed_reader.next_message();
+      let new_errors = self.framed_reader.frames_with_errors() - frames_with_errors;
+      if new_errors > 0 {
+        frames_with_errors += new_errors;
+        self.frames_with_errors.set(frames_with_errors as isize);
+        self.message_tx.send(Command::FrameErrors(new_errors))?;
+      }
+      match result {
         Ok(message) => {
           self.message_tx.send(Command::ReceivedMessage(message))?;
         }
@@ -160,17 +233,36 @@ struct EventHandler<W> {
   commands_rx: Receiver<Command>,
   events_tx: Sender<ViewEvent<ViewModel>>,
   last_view_model: ViewModel,
+  key_tracker: KeyTracker,
+  comm_monitor: CommMonitor,
+
+  /// Set point we last asked for while Up/Down is held, as status updates lag behind repeats.
+  pending_set_temp: Option<ProtocolTemperature>,
+  settings_store: Option<Box<dyn SettingsStore>>,
   state: AppState,
 }
 
 impl <W: Write + Send> EventHandler<W> {
   pub fn run_loop(mut self) -> anyhow::Result<()> {
     loop {
-      let command = self.commands_rx.recv()?;
+      let Some(command) = self.next_command()? else {
+        self.handle_key_timers();
+        self.update_comm_problem();
+        continue;
+      };
 
       let result = match command {
-        Command::ReceivedMessage(m) => self.handle_message(m),
+        Command::ReceivedMessage(m) => {
+          self.comm_monitor.on_message(Instant::now());
+          self.handle_message(m)
+        }
         Command::ReadError(e) => Err(FatalError(e.to_string())),
+        Command::FrameErrors(count) => {
+          for _ in 0..count {
+            self.comm_monitor.on_decode_error(Instant::now());
+          }
+          Ok(())
+        }
         Command::KeyEvent(key_event) => {
           self.handle_key_event(key_event);
           Ok(())
@@ -192,9 +284,29 @@ impl <W: Write + Send> EventHandler<W> {
             info!("Graceful shutdown requested...");
             return Ok(())
           }
-          _ => error!("Got {e}"),
+          HandlingError::UnexpectedPayload(_) => {
+            error!("Got {e}");
+            self.comm_monitor.on_decode_error(Instant::now());
+          }
         }
       }
+      self.update_comm_problem();
+    }
+  }
+
+  /// Waits for the next command, or returns None once a held key or [CommMonitor] needs
+  /// attention.
+  fn next_command(&self) -> anyhow::Result<Option<Command>> {
+    let deadline = self.key_tracker.next_deadline().into_iter()
+        .chain(self.comm_monitor.next_deadline(Instant::now()))
+        .min();
+    let Some(deadline) = deadline else {
+      return Ok(Some(self.commands_rx.recv()?));
+    };
+    match self.commands_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+      Ok(command) => Ok(Some(command)),
+      Err(RecvTimeoutError::Timeout) => Ok(None),
+      Err(e) => Err(e.into()),
     }
   }
 
@@ -205,6 +317,9 @@ impl <W: Write + Send> EventHandler<W> {
         .map_err(|e| HandlingError::UnexpectedPayload(e.to_string()))?;
 
     let state_snapshot = self.state.fast_snapshot();
+    let now = Instant::now();
+    self.state.cts_state_machine.tick(&mut self.framed_writer, &self.message_logger, now)?;
+    self.state.topside_state_machine.tick(&mut self.framed_writer, &self.message_logger, now)?;
     self.state.cts_state_machine.handle_message(&mut self.framed_writer, &self.message_logger, &message.channel, &mt)?;
     if let Some(channel) = self.state.cts_state_machine.take_got_channel() {
       info!("Setting channel filter for {:?}", channel);
@@ -212,6 +327,7 @@ impl <W: Write + Send> EventHandler<W> {
           ChannelFilter::RelevantTo(vec![channel]));
     }
     self.state.topside_state_machine.handle_message(&mut self.framed_writer, &self.message_logger, &message.channel, &mt)?;
+    self.handle_request_events(&mt, now);
     if self.state.fast_snapshot() != state_snapshot {
       self.maybe_emit_view_model();
     }
@@ -219,6 +335,40 @@ impl <W: Write + Send> EventHandler<W> {
     Ok(())
   }
 
+  fn handle_request_events(&mut self, mt: &MessageType, now: Instant) {
+    let requests = &mut self.state.topside_state_machine.context.requests;
+    let mut events = requests.poll(now);
+    events.extend(requests.on_message(mt, now));
+    for event in events {
+      match event {
+        RequestEvent::Completed { request, attempts, elapsed, .. } => {
+          debug!("{request:?} acknowledged after {elapsed:?} ({attempts} attempts)");
+        }
+        RequestEvent::Retry { request, attempt, .. } => {
+          info!("No response to {request:?}, retrying (attempt {attempt})...");
+          self.enqueue_message(request);
+        }
+        RequestEvent::TimedOut { request, attempts, .. } => {
+          warn!("Giving up on {request:?} after {attempts} attempts");
+        }
+      }
+    }
+  }
+
+  fn update_comm_problem(&mut self) {
+    let assigned = self.state.cts_state_machine.state_kind() == CtsStateKind::ChannelAssigned;
+    self.comm_monitor.on_channel(assigned);
+    let problem = self.comm_monitor.problem(Instant::now());
+    if self.state.comm_problem != problem {
+      match problem {
+        Some(problem) => warn!("Communication problem: {problem:?}"),
+        None => info!("Communication recovered"),
+      }
+      self.state.comm_problem = problem;
+      self.maybe_emit_view_model();
+    }
+  }
+
   fn maybe_emit_view_model(&mut self) {
     let model = self.state.generate_view_model();
     if self.last_view_model != model {
@@ -229,33 +379,366 @@ impl <W: Write + Send> EventHandler<W> {
   }
 
   fn handle_key_event(&mut self, key_event: KeyEvent) {
-    if let KeyEvent::KeyUp { key } = key_event {
-      let handled = match &key {
-        Key::Up => {
-          self.handle_temp_updown(Direction::Up).is_ok()
-        },
-        Key::Down => {
-          self.handle_temp_updown(Direction::Down).is_ok()
-        },
-        _ => {
-          warn!("Key {key:?} not implemented!");
-          false
+    if self.key_tracker.is_new_press(&key_event) {
+      // Only repeats build on the set point they asked for, a fresh press goes by the status.
+      self.pending_set_temp = None;
+    }
+    if let Some(action) = self.key_tracker.on_event(key_event, Instant::now()) {
+      self.handle_key_action(action);
+    }
+  }
+
+  fn handle_key_timers(&mut self) {
+    if let Some(action) = self.key_tracker.poll(Instant::now()) {
+      self.handle_key_action(action);
+    }
+  }
+
+  fn handle_key_action(&mut self, action: KeyAction) {
+    let (key, long_press) = match action {
+      KeyAction::Press(key) => (key, false),
+      KeyAction::LongPress(key) => (key, true),
+    };
+    let handled = match (self.state.settings_selection, self.state.jets_selection) {
+      (Some(_), _) if self.state.wifi_screen_open => self.handle_wifi_key(key),
+      (Some(_), _) if self.state.fault_log_open => self.handle_fault_log_key(key),
+      (Some(selected), _) => self.handle_settings_key(selected, key),
+      (None, Some(selected)) if long_press => self.handle_jets_long_press(selected, key),
+      (None, Some(selected)) => self.handle_jets_key(selected, key),
+      (None, None) if long_press => self.handle_main_long_press(key),
+      (None, None) => self.handle_main_key(key),
+    };
+    if !handled {
+      warn!("Not handled: {key:?}");
+    }
+    self.maybe_emit_view_model();
+  }
+
+  fn handle_main_key(&mut self, key: Key) -> bool {
+    match key {
+      Key::Up => self.handle_temp_updown(Direction::Up).is_ok(),
+      Key::Down => self.handle_temp_updown(Direction::Down).is_ok(),
+      Key::Jets1 => {
+        self.state.jets_selection = Some(0);
+        true
+      }
+      Key::Light => self.handle_lights_toggle().is_ok(),
+      Key::Menu => {
+        self.open_settings();
+        true
+      }
+    }
+  }
+
+  /// Holding Light opens settings and holding Jets switches between Fahrenheit and Celsius.
+  fn handle_main_long_press(&mut self, key: Key) -> bool {
+    match key {
+      Key::Light => {
+        self.open_settings();
+        true
+      }
+      Key::Jets1 => self.handle_scale_toggle().is_ok(),
+      _ => self.handle_main_key(key),
+    }
+  }
+
+  /// On the jets screen, Up/Down pick a jet, Jets toggles it and Light or Menu goes back.
+  fn handle_jets_key(&mut self, selected: usize, key: Key) -> bool {
+    match key {
+      Key::Up => {
+        self.state.jets_selection = Some(selected.saturating_sub(1));
+        true
+      }
+      Key::Down => {
+        let last = self.jets().len().saturating_sub(1);
+        self.state.jets_selection = Some((selected + 1).min(last));
+        true
+      }
+      Key::Jets1 => self.handle_jet_toggle(selected).is_ok(),
+      Key::Light | Key::Menu => {
+        self.state.jets_selection = None;
+        true
+      }
+    }
+  }
+
+  /// Holding Jets switches the highlighted jet straight off, however many toggles that takes.
+  fn handle_jets_long_press(&mut self, selected: usize, key: Key) -> bool {
+    match key {
+      Key::Jets1 => self.handle_jet_off(selected).is_ok(),
+      _ => self.handle_jets_key(selected, key),
+    }
+  }
+
+  /// Likewise on the settings screen, with Jets stepping the highlighted setting.
+  fn handle_settings_key(&mut self, selected: usize, key: Key) -> bool {
+    match key {
+      Key::Up => {
+        self.state.settings_selection = Some(selected.saturating_sub(1));
+        true
+      }
+      Key::Down => {
+        let last = SettingsItem::ALL.len() - 1;
+        self.state.settings_selection = Some((selected + 1).min(last));
+        true
+      }
+      Key::Jets1 => match SettingsItem::ALL[selected] {
+        SettingsItem::Wifi => {
+          self.state.wifi_screen_open = self.state.wifi_model.is_some();
+          self.state.wifi_screen_open
         }
-      };
-      if !handled {
-        warn!("Not handled: {key:?}");
+        SettingsItem::FaultLog => {
+          self.open_fault_log();
+          true
+        }
+        SettingsItem::Language => {
+          self.change_language();
+          true
+        }
+        SettingsItem::Theme => {
+          self.change_theme();
+          true
+        }
+        item => self.handle_setting_change(item).is_ok(),
+      },
+      Key::Light | Key::Menu => {
+        self.state.settings_selection = None;
+        true
+      }
+    }
+  }
+
+  /// Nothing to change on the Wi-Fi screen, Light goes back to settings.
+  fn handle_wifi_key(&mut self, key: Key) -> bool {
+    match key {
+      Key::Light => {
+        self.state.wifi_screen_open = false;
+        true
+      }
+      Key::Menu => {
+        self.close_settings();
+        true
       }
+      _ => false,
     }
   }
 
+  fn devices(&self, category: DeviceCategory) -> Vec<DeviceModel> {
+    self.state.generate_hot_tub_model()
+        .and_then(|mut model| model.devices.remove(&category))
+        .unwrap_or_default()
+  }
+
+  fn jets(&self) -> Vec<DeviceModel> {
+    self.devices(DeviceCategory::Jet)
+  }
+
+  /// There's only the one button, so it switches off whichever lights are on, or else switches
+  /// them all on.  The main screen catches up from the next status update.
+  fn handle_lights_toggle(&mut self) -> Result<(), ()> {
+    let lights = self.devices(DeviceCategory::Light);
+    if lights.is_empty() {
+      return Err(());
+    }
+    let any_on = lights.iter().any(|l| l.current_level != DeviceLevel::Off);
+    let to_toggle = lights.into_iter()
+        .filter(|l| (l.current_level != DeviceLevel::Off) == any_on);
+    for light in to_toggle {
+      let item_code = *LIGHT_ITEMS.get(light.index).ok_or(())?;
+      info!("Toggling {item_code:?}");
+      self.enqueue_message(MessageType::ToggleItemRequest {
+        item_code: ParsedEnum::new(item_code),
+        dummy1: 0,
+      });
+    }
+    Ok(())
+  }
+
+  fn handle_jet_toggle(&mut self, selected: usize) -> Result<(), ()> {
+    let jet = self.jets().into_iter().nth(selected).ok_or(())?;
+    let item_code = *PUMP_ITEMS.get(jet.index).ok_or(())?;
+    info!("Toggling {item_code:?}");
+    // Not tracked for retries, a toggle that did get through would just be undone.
+    self.enqueue_message(MessageType::ToggleItemRequest {
+      item_code: ParsedEnum::new(item_code),
+      dummy1: 0,
+    });
+    Ok(())
+  }
+
+  /// On the fault log, which lists the newest first, Down pages to older entries and Up back to
+  /// newer ones.  Jets clears the fault notification and Light goes back to settings.
+  fn handle_fault_log_key(&mut self, key: Key) -> bool {
+    let fault = self.state.topside_state_machine.context.fault.as_ref()
+        .map(|f| (f.entry_number, f.total_entries));
+    match (key, fault) {
+      (Key::Down, Some((entry_num, _))) if entry_num > 0 => {
+        self.request_settings(SettingsRequestMessage::FaultLog { entry_num: entry_num - 1 });
+        true
+      }
+      (Key::Up, Some((entry_num, total))) if entry_num + 1 < total => {
+        self.request_settings(SettingsRequestMessage::FaultLog { entry_num: entry_num + 1 });
+        true
+      }
+      (Key::Jets1, _) => {
+        info!("Clearing notification");
+        self.enqueue_message(MessageType::ToggleItemRequest {
+          item_code: ParsedEnum::new(ItemCode::ClearNotification),
+          dummy1: 0,
+        });
+        true
+      }
+      (Key::Light, _) => {
+        self.state.fault_log_open = false;
+        true
+      }
+      (Key::Menu, _) => {
+        self.close_settings();
+        true
+      }
+      _ => false,
+    }
+  }
+
+  /// Starts from the newest entry, which also tells us how many there are.
+  fn open_fault_log(&mut self) {
+    self.state.fault_log_open = true;
+    self.state.topside_state_machine.context.fault = None;
+    self.request_settings(SettingsRequestMessage::FaultLog { entry_num: LATEST_FAULT_ENTRY });
+  }
+
+  /// Goes by the scale in the latest status update rather than our copy of the preferences,
+  /// which we only have once settings has been opened.  The next status update confirms it.
+  fn handle_scale_toggle(&mut self) -> Result<(), ()> {
+    let scale = self.state.topside_state_machine.context.status.as_ref()
+        .map(|s| s.message.v1.set_temperature.raw_scale)
+        .ok_or(())?;
+    let new_scale = match scale {
+      TemperatureScale::Fahrenheit => TemperatureScale::Celsius,
+      TemperatureScale::Celsius => TemperatureScale::Fahrenheit,
+    };
+    info!("Switching to {new_scale:?}");
+    self.enqueue_message(MessageType::SetPreferenceRequest(
+        SetPreferenceMessage::TemperatureScale(new_scale)));
+    Ok(())
+  }
+
+  /// The main board doesn't volunteer preferences or filter cycles, so ask each time the
+  /// screen opens in case they were changed elsewhere.
+  fn open_settings(&mut self) {
+    self.state.settings_selection = Some(0);
+    self.request_settings(SettingsRequestMessage::Preferences);
+    self.request_settings(SettingsRequestMessage::FilterCycles);
+  }
+
+  /// Back to the main screen from settings or anything opened from there.
+  fn close_settings(&mut self) {
+    self.state.wifi_screen_open = false;
+    self.state.fault_log_open = false;
+    self.state.settings_selection = None;
+  }
+
+  /// Changes are only shown once read back from the main board, so a change it ignored (say,
+  /// due to a settings lock) never looks like it took.
+  fn handle_setting_change(&mut self, item: SettingsItem) -> Result<(), ()> {
+    let settings = self.state.generate_settings_model();
+    let preference = match item {
+      SettingsItem::TemperatureScale => {
+        Some(SetPreferenceMessage::TemperatureScale(match settings.temperature_scale.ok_or(())? {
+          TemperatureScale::Fahrenheit => TemperatureScale::Celsius,
+          TemperatureScale::Celsius => TemperatureScale::Fahrenheit,
+        }))
+      }
+      SettingsItem::ClockMode => {
+        Some(SetPreferenceMessage::ClockMode(match settings.clock_mode.ok_or(())? {
+          ClockMode::Hour12 => ClockMode::Hour24,
+          ClockMode::Hour24 => ClockMode::Hour12,
+        }))
+      }
+      SettingsItem::Reminders => {
+        Some(SetPreferenceMessage::Reminders(!settings.reminders.ok_or(())?))
+      }
+      _ => None,
+    };
+    if let Some(preference) = preference {
+      info!("Setting preference: {preference:?}");
+      self.enqueue_message(MessageType::SetPreferenceRequest(preference));
+      self.request_settings(SettingsRequestMessage::Preferences);
+      return Ok(());
+    }
+
+    // Both cycles go out together, so we need to know the current ones first.
+    let mut cycles = settings.filter_cycles.ok_or(())?;
+    let (index, change): (usize, fn(&mut FilterCycle)) = match item {
+      SettingsItem::Filter1Start => (0, step_filter_start),
+      SettingsItem::Filter1Hours => (0, step_filter_hours),
+      SettingsItem::Filter2Enabled => (1, |cycle| cycle.enabled = !cycle.enabled),
+      SettingsItem::Filter2Start => (1, step_filter_start),
+      SettingsItem::Filter2Hours => (1, step_filter_hours),
+      _ => return Err(()),
+    };
+    change(cycles.get_mut(index).ok_or(())?);
+    info!("Setting filter cycles: {cycles:?}");
+    self.enqueue_message(MessageType::FilterCycles { cycles });
+    self.request_settings(SettingsRequestMessage::FilterCycles);
+    Ok(())
+  }
+
+  fn change_language(&mut self) {
+    let language = self.state.language.next();
+    info!("Switching to {language:?}");
+    self.state.language = language;
+    self.save_choice(LANGUAGE_SETTING, language.code());
+  }
+
+  fn change_theme(&mut self) {
+    let theme = self.state.theme.next();
+    info!("Switching to {theme:?} theme");
+    self.state.theme = theme;
+    self.save_choice(THEME_SETTING, theme.code());
+  }
+
+  fn save_choice(&mut self, key: &str, code: &str) {
+    if let Some(store) = &mut self.settings_store {
+      if let Err(e) = store.set(key, code) {
+        warn!("Could not save {key}: {e}");
+      }
+    }
+  }
+
+  fn request_settings(&mut self, request: SettingsRequestMessage) {
+    let mt = MessageType::SettingsRequest(request);
+    self.state.topside_state_machine.context.requests.track(mt.clone(), Instant::now());
+    self.enqueue_message(mt);
+  }
+
+  fn handle_jet_off(&mut self, selected: usize) -> Result<(), ()> {
+    let jet = self.jets().into_iter().nth(selected).ok_or(())?;
+    let item_code = *PUMP_ITEMS.get(jet.index).ok_or(())?;
+    let level = jet.available_levels.iter()
+        .position(|l| *l == jet.current_level)
+        .ok_or(())?;
+    // Toggling steps through the levels in order and wraps back around to off.
+    let toggles = (jet.available_levels.len() - level) % jet.available_levels.len();
+    info!("Switching off {item_code:?} in {toggles} toggles");
+    for _ in 0..toggles {
+      self.enqueue_message(MessageType::ToggleItemRequest {
+        item_code: ParsedEnum::new(item_code),
+        dummy1: 0,
+      });
+    }
+    Ok(())
+  }
+
   fn handle_temp_updown(&mut self, direction: Direction) -> Result<(), ()> {
-    let (current_temp, range) = self.state.topside_state_machine.context.status
+    let (status_temp, range) = self.state.topside_state_machine.context.status
         .as_ref()
         .map(|m| {
           (&m.message.v1.set_temperature,
             &m.message.v1.temperate_range)
         })
         .ok_or(())?;
+    let current_temp = self.pending_set_temp.as_ref().unwrap_or(status_temp);
     let min_maxes = self.state.topside_state_machine.context.settings0x04
         .as_ref()
         .map(|m| &m.min_max_temps)
@@ -268,7 +751,14 @@ impl <W: Write + Send> EventHandler<W> {
       }
     };
     info!("Setting temp to: {temperature:?}");
+    let pending = current_temp.raw_scale.new_protocol_temperature_from_set(temperature.clone());
+    self.pending_set_temp = Some(pending);
     let mt = MessageType::SetTemperatureRequest { temperature };
+    let context = &mut self.state.topside_state_machine.context;
+    let is_set_temp = |mt: &MessageType| matches!(mt, MessageType::SetTemperatureRequest { .. });
+    context.requests.cancel_where(is_set_temp);
+    context.outbound_messages.retain(|mt| !is_set_temp(mt));
+    context.requests.track(mt.clone(), Instant::now());
     self.enqueue_message(mt);
     Ok(())
   }
@@ -283,11 +773,46 @@ impl <W: Write + Send> EventHandler<W> {
   }
 }
 
+/// Reads back one of the panel's own preferences, such as [LANGUAGE_SETTING], falling back to the
+/// default if it was never saved or doesn't make sense anymore.
+fn load_choice<T: Default>(
+    store: &dyn SettingsStore,
+    key: &str,
+    from_code: fn(&str) -> Option<T>,
+) -> T {
+  match store.get(key) {
+    Ok(Some(code)) => from_code(code.trim()).unwrap_or_else(|| {
+      warn!("Unknown {key} {code:?}, using the default");
+      T::default()
+    }),
+    Ok(None) => T::default(),
+    Err(e) => {
+      warn!("Could not load {key}: {e}");
+      T::default()
+    }
+  }
+}
+
+/// Moves the start an hour later, wrapping around at midnight.
+fn step_filter_start(cycle: &mut FilterCycle) {
+  let start_secs = (cycle.start_at.as_secs() + HOUR_SECS) % (24 * HOUR_SECS);
+  cycle.start_at = Duration::from_secs(start_secs);
+}
+
+/// Lengthens the cycle by an hour, back around to one hour after [MAX_FILTER_HOURS].
+fn step_filter_hours(cycle: &mut FilterCycle) {
+  let hours = cycle.duration.as_secs() / HOUR_SECS % MAX_FILTER_HOURS + 1;
+  cycle.duration = Duration::from_secs(hours * HOUR_SECS);
+}
+
 #[derive(Debug)]
 enum Command {
   ReceivedMessage(Message),
   WifiModelUpdated(wifi_module_lib::view_model::ViewModel),
   ReadError(anyhow::Error),
+
+  /// This many more frames were dropped for failing to decode.
+  FrameErrors(usize),
   KeyEvent(KeyEvent),
   Shutdown,
 }
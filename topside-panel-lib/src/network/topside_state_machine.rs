@@ -1,9 +1,10 @@
 use std::collections::VecDeque;
 use std::time::Instant;
 use log::{debug, info};
-use balboa_spa_messages::message_types::{ConfigurationResponseMessage, InformationResponseMessage, MessageType, PreferencesResponseMessage, Settings0x04ResponseMessage, SettingsRequestMessage, StatusUpdateMessage};
+use balboa_spa_messages::message_types::{ConfigurationResponseMessage, FaultResponseMessage, FilterCycle, InformationResponseMessage, MessageType, PreferencesResponseMessage, Settings0x04ResponseMessage, SettingsRequestMessage, StatusUpdateMessage};
 use common_lib::message_state_machine::{MessageState, MessageStateMachine, SmResult, StateArgs};
 use common_lib::message_state_machine::SmResult::{HandledNoReply, NotHandled, SendReply};
+use common_lib::request_tracker::RequestTracker;
 
 pub type TopsideStateMachine = MessageStateMachine<StateWaitingForCts>;
 
@@ -13,7 +14,17 @@ pub struct TopsideContext {
   pub settings0x04: Option<Settings0x04ResponseMessage>,
   pub config: Option<ConfigurationResponseMessage>,
   pub status: Option<ReceivedStatusMessage>,
+
+  /// Only asked for once the settings screen is opened, see [StateReadingStatus].
+  pub preferences: Option<PreferencesResponseMessage>,
+  pub filter_cycles: Option<Vec<FilterCycle>>,
+
+  /// Whichever fault log entry the main board last sent us.
+  pub fault: Option<FaultResponseMessage>,
   pub outbound_messages: VecDeque<MessageType>,
+
+  /// User initiated requests in [Self::outbound_messages] that we expect an answer to.
+  pub requests: RequestTracker,
 }
 
 #[derive(Debug)]
@@ -138,6 +149,21 @@ impl MessageState for StateReadingStatus {
         args.context.status = Some(ReceivedStatusMessage::received(m.clone()));
         HandledNoReply
       }
+      MessageType::PreferencesResponse(m) => {
+        debug!("Got preferences: {m:?}");
+        args.context.preferences = Some(m.clone());
+        HandledNoReply
+      }
+      MessageType::FilterCycles { cycles } => {
+        debug!("Got filter cycles: {cycles:?}");
+        args.context.filter_cycles = Some(cycles.clone());
+        HandledNoReply
+      }
+      MessageType::FaultLogResponse(m) => {
+        debug!("Got fault log entry: {m:?}");
+        args.context.fault = Some(m.clone());
+        HandledNoReply
+      }
       _ => NotHandled,
     }
   }
@@ -1,47 +1,63 @@
 use std::time::{Duration, Instant};
 use crate::view::lcd_device::{BacklightBrightness, BacklightControl};
 
-/// Amount of time to keep the backlight on without user interaction.
-const BACKLIGHT_USER_WAIT: Duration = Duration::from_secs(30);
+/// Amount of time without user interaction before dimming to the screensaver, see
+/// [crate::view::ui_handler::UiHandler::set_idle_timeout].
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// How much longer the dimmed screensaver stays up before the backlight goes off entirely.
+const SCREENSAVER_WAIT: Duration = Duration::from_secs(5 * 60);
+
+/// Tracks user inactivity and steps the backlight down as it grows.
 pub struct BacklightManager<B> {
   backlight: B,
+  idle_timeout: Duration,
   current_value: BacklightBrightness,
   last_user_interaction: Instant,
 }
 
 impl<B: BacklightControl> BacklightManager<B> {
-  pub fn init(mut backlight: B) -> Self {
+  pub fn init(mut backlight: B, idle_timeout: Duration) -> Self {
     let current_value = BacklightBrightness::FullOn;
     backlight.set_brightness(current_value);
     Self {
       backlight,
+      idle_timeout,
       current_value,
       last_user_interaction: Instant::now(),
     }
   }
 
-  pub fn mark_user_activity(&mut self, at_time: Instant) {
+  /// Returns whether this woke us from idle.
+  pub fn mark_user_activity(&mut self, at_time: Instant) -> bool {
+    let was_idle = self.is_idle();
     self.last_user_interaction = at_time;
     self.maybe_set_brightness(BacklightBrightness::FullOn);
-
+    was_idle
   }
 
   pub fn detect_inactivity(&mut self, now: Instant, force_backlight: bool) {
     if force_backlight {
       self.maybe_set_brightness(BacklightBrightness::FullOn);
     } else if self.current_value != BacklightBrightness::Off {
-      let elapsed = now - self.last_user_interaction;
-      if elapsed > BACKLIGHT_USER_WAIT {
+      let elapsed = now.saturating_duration_since(self.last_user_interaction);
+      if elapsed > self.idle_timeout + SCREENSAVER_WAIT {
         self.maybe_set_brightness(BacklightBrightness::Off);
+      } else if elapsed > self.idle_timeout {
+        self.maybe_set_brightness(BacklightBrightness::Dimmed);
       }
     }
   }
 
+  /// Whether the screensaver should be showing.
+  pub fn is_idle(&self) -> bool {
+    self.current_value != BacklightBrightness::FullOn
+  }
+
   fn maybe_set_brightness(&mut self, value: BacklightBrightness) {
     if self.current_value != value {
       self.current_value = value;
       self.backlight.set_brightness(value);
     }
   }
-}
\ No newline at end of file
+}
@@ -6,3 +6,27 @@ pub fn hex_color(c: u32) -> Color {
   let r = (c >> 16) & 0xff;
   Color::from_rgb((r as u8, g as u8, b as u8))
 }
+
+/// Blends two `0xRRGGBB` colors channel by channel, `mix` of 0 being all `from` and 255 all `to`.
+pub fn mix_hex(from: u32, to: u32, mix: u8) -> u32 {
+  let mix = u32::from(mix);
+  [16, 8, 0].into_iter()
+      .map(|shift| {
+        let from = (from >> shift) & 0xff;
+        let to = (to >> shift) & 0xff;
+        ((from * (255 - mix) + to * mix + 127) / 255) << shift
+      })
+      .sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_mix_hex() {
+    assert_eq!(mix_hex(0x393f47, 0xdb742c, 0), 0x393f47);
+    assert_eq!(mix_hex(0x393f47, 0xdb742c, 255), 0xdb742c);
+    assert_eq!(mix_hex(0x000000, 0xfe02ff, 128), 0x7f0180);
+  }
+}
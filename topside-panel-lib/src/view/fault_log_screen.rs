@@ -0,0 +1,147 @@
+use cstr_core::CString;
+use lvgl::{Align, LvResult, Obj, Part, State, Widget};
+use lvgl::style::Style;
+use lvgl::widgets::Label;
+use crate::model::language::Language;
+use crate::model::theme::Theme;
+use crate::model::view_model::{FaultModel, ViewModel};
+use crate::view::color_util::hex_color;
+use crate::view::font::Font;
+use crate::view::lvgl_ext::{label_set_long_mode, LabelLongMode, obj_set_auto_realign, style_set_text_font};
+use crate::view::main_screen::{LABEL_PRIMARY_COLOR, MainScreen};
+use crate::view::palette_styles::ThemeStyles;
+use crate::view::screen_flipper::{BoxedScreen, Screen, ScreenSelector};
+use crate::view::strings::{fault_text, format_text, text, Text};
+
+/// Position, description, when, and the key hint.
+const LINES: usize = 4;
+
+pub struct FaultLogScreen {
+  screen: Obj,
+  styles: Styles,
+  title: Label,
+  rows: Vec<Label>,
+  current_lines: Vec<String>,
+  language: Option<Language>,
+  theme: Option<Theme>,
+}
+
+struct Styles {
+  themes: ThemeStyles,
+}
+
+impl Styles {
+  pub fn new() -> Self {
+    Self {
+      themes: ThemeStyles::new(),
+    }
+  }
+}
+
+impl FaultLogScreen {
+  pub fn new() -> LvResult<Self> {
+    let mut screen = Obj::default();
+    let styles = Styles::new();
+
+    let mut title_style = Style::default();
+    title_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut title_style, State::DEFAULT, Font::MONTSERRAT_32);
+    let mut title = Label::new(&mut screen)?;
+    title.add_style(Part::Main, title_style.clone())?;
+    title.set_align(&mut screen, Align::InTopMid, 0, 16)?;
+    obj_set_auto_realign(&mut title, true)?;
+
+    let mut row_style = Style::default();
+    row_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut row_style, State::DEFAULT, Font::MONTSERRAT_24);
+
+    let mut rows: Vec<Label> = Vec::with_capacity(LINES);
+    for _ in 0..LINES {
+      let mut row = Label::new(&mut screen)?;
+      row.add_style(Part::Main, row_style.clone())?;
+      label_set_long_mode(&mut row, LabelLongMode::Break)?;
+      row.set_width(440)?;
+      match rows.last_mut() {
+        None => row.set_align(&mut title, Align::OutBottomMid, 0, 16)?,
+        Some(above) => row.set_align(above, Align::OutBottomLeft, 0, 8)?,
+      }
+      obj_set_auto_realign(&mut row, true)?;
+      row.set_text(CString::new("").unwrap().as_c_str())?;
+      rows.push(row);
+    }
+
+    Ok(Self {
+      screen,
+      styles,
+      title,
+      rows,
+      current_lines: vec![String::new(); LINES],
+      language: None,
+      theme: None,
+    })
+  }
+
+  fn lines(fault: Option<&FaultModel>, language: Language) -> Vec<String> {
+    match fault {
+      None => vec![text(language, Text::Loading).to_owned()],
+      Some(fault) if fault.total_entries == 0 => vec![text(language, Text::NoFaults).to_owned()],
+      Some(fault) => {
+        let days_ago = match fault.days_ago {
+          0 => text(language, Text::Today).to_owned(),
+          1 => text(language, Text::Yesterday).to_owned(),
+          days => format_text(language, Text::DaysAgo, &[&days]),
+        };
+        // Newest first reads more naturally than the board's numbering.
+        let position = fault.total_entries - fault.entry_number;
+        vec![
+          format_text(language, Text::FaultEntryOf, &[&position, &fault.total_entries]),
+          fault_text(language, fault.fault_code),
+          format_text(language, Text::FaultWhen, &[&days_ago, &fault.time]),
+          text(language, Text::FaultClearHint).to_owned(),
+        ]
+      }
+    }
+  }
+}
+
+impl ScreenSelector for FaultLogScreen {
+  fn kind() -> &'static str {
+    "fault_log"
+  }
+
+  fn create() -> LvResult<BoxedScreen> {
+    Ok(Box::new(FaultLogScreen::new()?))
+  }
+
+  fn accept_model(model: &ViewModel) -> bool {
+    model.fault_log_open && MainScreen::accept_model(model)
+  }
+}
+
+impl Screen for FaultLogScreen {
+  fn get_root(&self) -> &Obj {
+    &self.screen
+  }
+
+  fn bind_model(&mut self, model: ViewModel) -> LvResult<()> {
+    let theme = model.settings.theme;
+    if self.theme != Some(theme) {
+      self.screen.add_style(Part::Main, self.styles.themes.normal(theme).window_bg.clone())?;
+      self.theme = Some(theme);
+    }
+    let language = model.settings.language;
+    if self.language != Some(language) {
+      self.title.set_text(CString::new(text(language, Text::FaultLog)).unwrap().as_c_str())?;
+      self.language = Some(language);
+    }
+    let lines = FaultLogScreen::lines(model.fault.as_ref(), language);
+    for (i, row) in self.rows.iter_mut().enumerate() {
+      let line = lines.get(i).cloned().unwrap_or_default();
+      if self.current_lines[i] != line {
+        row.set_text(CString::new(line.as_str()).unwrap().as_c_str())?;
+        self.current_lines[i] = line;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,92 @@
+use crate::model::key_event::{Key, KeyEvent};
+use crate::model::view_model::ViewModel;
+
+/// Something that beeps, buzzes or otherwise lets the user know what just happened without
+/// having to look at the screen.
+pub trait FeedbackDevice {
+  /// Called from the UI loop, so anything that takes a while should happen in the background.
+  fn play(&mut self, feedback: Feedback);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Feedback {
+  /// A key press that's going to do something.
+  KeyPress,
+
+  /// Up or Down was pressed or held with the set temperature already as far as it goes.
+  LimitReached,
+
+  /// The main board just raised a notification, see
+  /// [crate::model::view_model::HotTubModel::notification].
+  Fault,
+}
+
+#[derive(Debug, Default)]
+pub struct NoFeedback;
+
+impl FeedbackDevice for NoFeedback {
+  fn play(&mut self, _feedback: Feedback) {
+  }
+}
+
+/// Works out when to give [Feedback] from the keys the UI sends on and the models it gets back.
+#[derive(Debug, Default)]
+pub(crate) struct FeedbackTracker {
+  /// Up or Down while held on the main screen, so that hitting the limit part way through a
+  /// repeat can be caught as the set temperature catches up.
+  held_temp_key: Option<Key>,
+  at_limit: bool,
+  notification: bool,
+}
+
+impl FeedbackTracker {
+  pub fn on_key_event(&mut self, event: KeyEvent, model: Option<&ViewModel>) -> Option<Feedback> {
+    match event {
+      KeyEvent::KeyDown { key } => {
+        let adjusting_temp = matches!(key, Key::Up | Key::Down) && model.map_or(false, on_main_screen);
+        self.held_temp_key = adjusting_temp.then_some(key);
+        self.at_limit = adjusting_temp && model.map_or(false, |m| at_limit(m, key));
+        Some(if self.at_limit { Feedback::LimitReached } else { Feedback::KeyPress })
+      }
+      KeyEvent::KeyUp { key } => {
+        if self.held_temp_key == Some(key) {
+          self.held_temp_key = None;
+        }
+        None
+      }
+    }
+  }
+
+  pub fn on_model(&mut self, model: &ViewModel) -> Option<Feedback> {
+    let notification = model.last_model.as_ref().map_or(false, |m| m.notification);
+    let new_fault = notification && !self.notification;
+    self.notification = notification;
+    if new_fault {
+      return Some(Feedback::Fault);
+    }
+
+    let key = self.held_temp_key?;
+    let at_limit = at_limit(model, key);
+    let reached = at_limit && !self.at_limit;
+    self.at_limit = at_limit;
+    reached.then_some(Feedback::LimitReached)
+  }
+}
+
+/// Up and Down only adjust the temperature on the main screen, elsewhere they move a selection.
+fn on_main_screen(model: &ViewModel) -> bool {
+  model.jets_selection.is_none() && model.settings_selection.is_none()
+}
+
+fn at_limit(model: &ViewModel, key: Key) -> bool {
+  let Some(hot_tub) = &model.last_model else {
+    return false;
+  };
+  let set = hot_tub.set_temp.display.int_value;
+  let (min, max) = &hot_tub.temp_range.display;
+  match key {
+    Key::Up => set >= max.int_value,
+    Key::Down => set <= min.int_value,
+    _ => false,
+  }
+}
@@ -0,0 +1,145 @@
+use cstr_core::CString;
+use lvgl::{Align, LvResult, Obj, Part, State, Widget};
+use lvgl::style::Style;
+use lvgl::widgets::Label;
+use crate::model::language::Language;
+use crate::model::theme::Theme;
+use crate::model::view_model::{DeviceCategory, DeviceLevel, DeviceModel, ViewModel};
+use crate::view::color_util::hex_color;
+use crate::view::font::Font;
+use crate::view::lvgl_ext::{obj_set_auto_realign, style_set_text_font};
+use crate::view::main_screen::{LABEL_PRIMARY_COLOR, MainScreen};
+use crate::view::palette_styles::ThemeStyles;
+use crate::view::screen_flipper::{BoxedScreen, Screen, ScreenSelector};
+use crate::view::strings::{format_text, text, Text};
+
+/// The protocol has room for no more pumps than this.
+const MAX_JETS: usize = 6;
+
+pub struct JetsScreen {
+  screen: Obj,
+  styles: Styles,
+  title: Label,
+  rows: Vec<Label>,
+  current_text: Vec<String>,
+  language: Option<Language>,
+  theme: Option<Theme>,
+}
+
+struct Styles {
+  themes: ThemeStyles,
+}
+
+impl Styles {
+  pub fn new() -> Self {
+    Self {
+      themes: ThemeStyles::new(),
+    }
+  }
+}
+
+impl JetsScreen {
+  pub fn new() -> LvResult<Self> {
+    let mut screen = Obj::default();
+    let styles = Styles::new();
+
+    let mut title_style = Style::default();
+    title_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut title_style, State::DEFAULT, Font::MONTSERRAT_32);
+    let mut title = Label::new(&mut screen)?;
+    title.add_style(Part::Main, title_style.clone())?;
+    title.set_align(&mut screen, Align::InTopMid, 0, 16)?;
+    obj_set_auto_realign(&mut title, true)?;
+
+    let mut row_style = Style::default();
+    row_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut row_style, State::DEFAULT, Font::MONTSERRAT_24);
+
+    // Laid out up front, rows for pumps the spa doesn't have are just left empty.
+    let mut rows: Vec<Label> = Vec::with_capacity(MAX_JETS);
+    for _ in 0..MAX_JETS {
+      let mut row = Label::new(&mut screen)?;
+      row.add_style(Part::Main, row_style.clone())?;
+      match rows.last_mut() {
+        None => row.set_align(&mut title, Align::OutBottomMid, 0, 16)?,
+        Some(above) => row.set_align(above, Align::OutBottomLeft, 0, 8)?,
+      }
+      obj_set_auto_realign(&mut row, true)?;
+      row.set_text(CString::new("").unwrap().as_c_str())?;
+      rows.push(row);
+    }
+
+    Ok(Self {
+      screen,
+      styles,
+      title,
+      rows,
+      current_text: vec![String::new(); MAX_JETS],
+      language: None,
+      theme: None,
+    })
+  }
+
+  fn row_text(jet: &DeviceModel, selected: bool, language: Language) -> String {
+    let level = match jet.current_level {
+      DeviceLevel::Off => Text::Off,
+      DeviceLevel::PartialOn => Text::Low,
+      // Single speed pumps are simply on.
+      DeviceLevel::FullOn if jet.available_levels.len() <= 2 => Text::On,
+      DeviceLevel::FullOn => Text::High,
+    };
+    let marker = if selected { ">" } else { " " };
+    let row = format_text(language, Text::JetRow, &[&(jet.index + 1), &text(language, level)]);
+    format!("{marker} {row}")
+  }
+}
+
+impl ScreenSelector for JetsScreen {
+  fn kind() -> &'static str {
+    "jets"
+  }
+
+  fn create() -> LvResult<BoxedScreen> {
+    Ok(Box::new(JetsScreen::new()?))
+  }
+
+  fn accept_model(model: &ViewModel) -> bool {
+    model.jets_selection.is_some() && MainScreen::accept_model(model)
+  }
+}
+
+impl Screen for JetsScreen {
+  fn get_root(&self) -> &Obj {
+    &self.screen
+  }
+
+  fn bind_model(&mut self, model: ViewModel) -> LvResult<()> {
+    let theme = model.settings.theme;
+    if self.theme != Some(theme) {
+      self.screen.add_style(Part::Main, self.styles.themes.normal(theme).window_bg.clone())?;
+      self.theme = Some(theme);
+    }
+    let language = model.settings.language;
+    if self.language != Some(language) {
+      self.title.set_text(CString::new(text(language, Text::Jets)).unwrap().as_c_str())?;
+      self.language = Some(language);
+    }
+    let selected = model.jets_selection.unwrap_or_default();
+    let jets = model.last_model.as_ref()
+        .and_then(|m| m.devices.get(&DeviceCategory::Jet))
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    for (i, row) in self.rows.iter_mut().enumerate() {
+      let text = match jets.get(i) {
+        Some(jet) => JetsScreen::row_text(jet, i == selected, language),
+        None if i == 0 => text(language, Text::NoJets).to_owned(),
+        None => String::new(),
+      };
+      if self.current_text[i] != text {
+        row.set_text(CString::new(text.as_str()).unwrap().as_c_str())?;
+        self.current_text[i] = text;
+      }
+    }
+    Ok(())
+  }
+}
@@ -1,5 +1,6 @@
 use embedded_graphics::draw_target::DrawTarget;
 use log::info;
+use crate::view::touch_input::TouchInput;
 use crate::view::window_proxy::WindowProxy;
 
 pub trait LcdDevice {
@@ -7,7 +8,10 @@ pub trait LcdDevice {
   type Window: WindowProxy<Self::Display>;
   type Backlight: BacklightControl;
 
-  fn setup(self) -> (Self::Display, Self::Window, Self::Backlight);
+  /// [crate::view::touch_input::NoTouch] for devices without a touch panel.
+  type Touch: TouchInput;
+
+  fn setup(self) -> (Self::Display, Self::Window, Self::Backlight, Self::Touch);
 }
 
 pub trait BacklightControl {
@@ -17,5 +21,9 @@ pub trait BacklightControl {
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BacklightBrightness {
   Off,
+
+  /// Low enough to save power and not light up the yard at night, but still readable.  Devices
+  /// that can't dim may just leave the backlight on.
+  Dimmed,
   FullOn,
 }
@@ -2,29 +2,33 @@ use cstr_core::CString;
 use lvgl::{Align, LvResult, Obj, Part, State, Widget};
 use lvgl::style::Style;
 use lvgl::widgets::Label;
+use crate::model::language::Language;
+use crate::model::theme::Theme;
 use crate::model::view_model::ViewModel;
 use crate::view::color_util::hex_color;
 use crate::view::font::Font;
 use crate::view::lvgl_ext::{obj_set_auto_realign, style_set_text_font};
-use crate::view::main_screen;
 use crate::view::main_screen::LABEL_PRIMARY_COLOR;
-use crate::view::palette_styles::PaletteStyles;
+use crate::view::palette_styles::ThemeStyles;
 use crate::view::screen_flipper::{BoxedScreen, Screen, ScreenOptions, ScreenSelector};
+use crate::view::strings::{text, Text};
 
 pub struct LoadingScreen {
   screen: Obj,
   styles: Styles,
   label: Label,
+  language: Option<Language>,
+  theme: Option<Theme>,
 }
 
 struct Styles {
-  normal: PaletteStyles,
+  themes: ThemeStyles,
 }
 
 impl Styles {
   pub fn new() -> Self {
     Self {
-      normal: PaletteStyles::new(main_screen::NORMAL),
+      themes: ThemeStyles::new(),
     }
   }
 }
@@ -34,8 +38,6 @@ impl LoadingScreen {
     let mut screen = Obj::default();
     let styles = Styles::new();
 
-    screen.add_style(Part::Main, styles.normal.window_bg.clone())?;
-
     let mut style = Style::default();
     style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
     style_set_text_font(&mut style, State::DEFAULT, Font::MONTSERRAT_12);
@@ -44,12 +46,12 @@ impl LoadingScreen {
     label.set_align(&mut screen, Align::InBottomLeft, 10, -10)?;
     obj_set_auto_realign(&mut label, true)?;
 
-    label.set_text(CString::new("Loading...").unwrap().as_c_str())?;
-
     Ok(Self {
       screen,
       styles,
       label,
+      language: None,
+      theme: None,
     })
   }
 }
@@ -80,6 +82,16 @@ impl Screen for LoadingScreen {
   }
 
   fn bind_model(&mut self, model: ViewModel) -> LvResult<()> {
+    let theme = model.settings.theme;
+    if self.theme != Some(theme) {
+      self.screen.add_style(Part::Main, self.styles.themes.normal(theme).window_bg.clone())?;
+      self.theme = Some(theme);
+    }
+    let language = model.settings.language;
+    if self.language != Some(language) {
+      self.label.set_text(CString::new(text(language, Text::Loading)).unwrap().as_c_str())?;
+      self.language = Some(language);
+    }
     Ok(())
   }
 }
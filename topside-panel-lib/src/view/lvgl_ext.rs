@@ -3,8 +3,8 @@ use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 use std::ptr::NonNull;
 use lvgl::style::{Opacity, Style};
-use lvgl::{Color, LvError, LvResult, NativeObject, State};
-use lvgl::widgets::{Canvas, Label};
+use lvgl::{Color, LvError, LvResult, NativeObject, Part, State};
+use lvgl::widgets::{Canvas, Label, Linemeter};
 use lvgl_sys::{lv_color_int_t, lv_color_t, lv_coord_t, LV_IMG_CF_ALPHA_1BIT, LV_IMG_CF_ALPHA_2BIT, LV_IMG_CF_ALPHA_4BIT, LV_IMG_CF_ALPHA_8BIT, LV_IMG_CF_INDEXED_1BIT, LV_IMG_CF_INDEXED_2BIT, LV_IMG_CF_INDEXED_4BIT, LV_IMG_CF_INDEXED_8BIT, lv_img_cf_t, lv_img_dsc_t, LV_LABEL_LONG_BREAK, LV_LABEL_LONG_CROP, LV_LABEL_LONG_DOT, LV_LABEL_LONG_EXPAND, lv_label_long_mode_t, LV_LABEL_LONG_SROLL, LV_LABEL_LONG_SROLL_CIRC, LV_SCROLLBAR_MODE_AUTO};
 use crate::view::font::Font;
 
@@ -218,6 +218,62 @@ pub fn obj_get_height(obj: &impl NativeObject) -> LvResult<lv_coord_t> {
   Ok(retval)
 }
 
+pub fn obj_set_hidden(obj: &mut impl NativeObject, hidden: bool) -> LvResult<()> {
+  unsafe {
+    lvgl_sys::lv_obj_set_hidden(obj.raw()?.as_ptr(), hidden);
+  }
+  Ok(())
+}
+
+/// The default display's top layer, drawn over whichever screen is loaded.
+pub struct LayerTop {
+  raw: NonNull<lvgl_sys::lv_obj_t>,
+}
+
+impl NativeObject for LayerTop {
+  fn raw(&self) -> LvResult<NonNull<lvgl_sys::lv_obj_t>> {
+    Ok(self.raw)
+  }
+}
+
+pub fn layer_top() -> LvResult<LayerTop> {
+  let raw = unsafe {
+    lvgl_sys::lv_disp_get_layer_top(lvgl_sys::lv_disp_get_default())
+  };
+  NonNull::new(raw)
+      .map(|raw| LayerTop { raw })
+      .ok_or(LvError::InvalidReference)
+}
+
+pub fn linemeter_get_value(linemeter: &Linemeter) -> LvResult<i32> {
+  let retval = unsafe {
+    lvgl_sys::lv_linemeter_get_value(linemeter.raw()?.as_ptr())
+  };
+  Ok(retval)
+}
+
+/// Sets a color in the object's local style, which wins over anything added with `add_style`.
+pub fn obj_set_style_local_color(obj: NonNull<lvgl_sys::lv_obj_t>, part: Part, prop: u32, state: State, color: Color) {
+  let native_state: u32 = state.get_bits();
+  unsafe {
+    lvgl_sys::_lv_obj_set_style_local_color(
+      obj.as_ptr(),
+      part.into(),
+      (prop | (native_state << lvgl_sys::LV_STYLE_STATE_POS as u32)) as u16,
+      color.raw());
+  }
+}
+
+/// Something driven frame by frame from an [Anim], see [Anim::set_animated].
+pub trait Animated {
+  fn step(&mut self, value: i32);
+}
+
+pub type AnimExecCb = unsafe extern "C" fn(*mut cty::c_void, lvgl_sys::lv_anim_value_t);
+
+/// Built up and then handed to LVGL with [Self::start], which keeps its own copy so this can be
+/// dropped straight away.  Values are [lvgl_sys::lv_anim_value_t] underneath, so keep them within
+/// a coordinate's range.
 pub struct Anim {
   raw: Box<lvgl_sys::lv_anim_t>,
 }
@@ -231,4 +287,55 @@ impl Anim {
     };
     Ok(Anim { raw })
   }
+
+  pub fn set_values(&mut self, start: i32, end: i32) {
+    self.raw.start = start;
+    self.raw.end = end;
+  }
+
+  pub fn set_time_ms(&mut self, time_ms: u32) {
+    self.raw.time = i32::try_from(time_ms).unwrap_or(i32::MAX);
+  }
+
+  /// Slow at either end rather than the default linear.
+  pub fn set_ease_in_out(&mut self) {
+    self.raw.path.cb = Some(lvgl_sys::lv_anim_path_ease_in_out);
+  }
+
+  /// Animates an LVGL object directly, `exec_cb` gets the object's pointer with each value.
+  /// LVGL stops the animation by itself if the object is deleted.
+  pub fn set_obj_exec_cb(&mut self, obj: &impl NativeObject, exec_cb: AnimExecCb) -> LvResult<()> {
+    self.raw.var = obj.raw()?.as_ptr().cast();
+    self.raw.exec_cb = Some(exec_cb);
+    Ok(())
+  }
+
+  /// Calls [Animated::step] on `target` with each value.
+  ///
+  /// # Safety
+  ///
+  /// `target` must stay where it is until the animation finishes or is stopped with [anim_del].
+  pub unsafe fn set_animated<T: Animated>(&mut self, target: NonNull<T>) {
+    self.raw.var = target.as_ptr().cast();
+    self.raw.exec_cb = Some(animated_exec_cb::<T>);
+  }
+
+  /// Replaces any animation already running with the same target and callback.
+  pub fn start(mut self) {
+    unsafe {
+      lvgl_sys::lv_anim_start(self.raw.as_mut());
+    }
+  }
+}
+
+/// Stops every animation of `target`, without jumping to their end values.
+pub fn anim_del<T>(target: NonNull<T>) -> bool {
+  unsafe {
+    lvgl_sys::lv_anim_del(target.as_ptr().cast(), None)
+  }
+}
+
+unsafe extern "C" fn animated_exec_cb<T: Animated>(var: *mut cty::c_void, value: lvgl_sys::lv_anim_value_t) {
+  let target = &mut *var.cast::<T>();
+  target.step(i32::from(value));
 }
\ No newline at end of file
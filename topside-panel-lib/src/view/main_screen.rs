@@ -3,82 +3,81 @@ use lvgl::{Align, Color, LvResult, NativeObject, Obj, Part, State, UI, Widget};
 use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::pixelcolor::PixelColor;
 use log::warn;
+use lvgl::style::Style;
 use lvgl::widgets::{Arc, ArcPart, Label, Linemeter};
 use wifi_module_lib::view_model::Mode;
-use crate::model::view_model::{HotTubModel, ViewModel};
-use crate::view::palette::{Palette, PaletteAware};
-use crate::view::palette_styles::PaletteStyles;
+use crate::model::key_event::Key;
+use crate::model::language::Language;
+use crate::model::view_model::{DeviceCategory, DeviceLevel, HotTubModel, ViewModel};
+use crate::view::color_util::hex_color;
+use crate::view::font::Font;
+use crate::view::lvgl_ext::{obj_set_auto_realign, style_set_text_font};
+use crate::model::theme::Theme;
+use crate::view::palette::theme_palettes;
+use crate::view::palette_fade::PaletteFade;
 use crate::view::screen_flipper::{BoxedScreen, Screen, ScreenSelector};
+use crate::view::strings::{text, Text};
 use crate::view::temperature_widget::TemperatureWidget;
+use crate::view::touch_keys::{SYMBOL_MINUS, SYMBOL_PLUS, SYMBOL_SETTINGS, TouchKeys};
 
 pub(crate) const WIDGET_FG_STROKE_COLOR: u32 = 0xfffffff;
 pub(crate) const LABEL_PRIMARY_COLOR: u32 = 0xffffff;
 
-pub(crate) const NORMAL: Palette = Palette {
-  window_bg: 0x393f47,
-  widget_fill: 0x3d444b,
-  widget_bg_stroke: 0x434a52,
-};
-
-const HEATING: Palette = Palette {
-  window_bg: 0xdb742c,
-  widget_fill: 0xdd7e2f,
-  widget_bg_stroke: 0xdf8631,
-};
-
 pub struct MainScreen {
   screen: Obj,
-  styles: Styles,
+  palette_fade: PaletteFade,
   temperature_widget: TemperatureWidget,
-  is_heating_palette: Option<bool>,
-}
-
-struct Styles {
-  normal: PaletteStyles,
-  heating: PaletteStyles,
-}
-
-impl Styles {
-  pub fn new() -> Self {
-    Self {
-      normal: PaletteStyles::new(NORMAL),
-      heating: PaletteStyles::new(HEATING),
-    }
-  }
-
-  pub fn select_palette(&self, is_heating: bool) -> &PaletteStyles {
-    match is_heating {
-      true => &self.heating,
-      false => &self.normal,
-    }
-  }
+  lights_label: Label,
+  clock_label: Label,
+  clock_text: String,
+  touch_keys: TouchKeys,
+  language: Option<Language>,
 }
 
 impl MainScreen {
   pub fn new() -> LvResult<Self> {
     let mut screen = Obj::default();
 
-    let styles = Styles::new();
     let temperature_widget = TemperatureWidget::new(&mut screen)?;
+    let palette_fade = PaletteFade::new(&screen, &[&temperature_widget])?;
+
+    let mut lights_style = Style::default();
+    lights_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut lights_style, State::DEFAULT, Font::MONTSERRAT_16);
+    let mut lights_label = Label::new(&mut screen)?;
+    lights_label.add_style(Part::Main, lights_style.clone())?;
+    lights_label.set_align(&mut screen, Align::InBottomMid, 0, -10)?;
+    obj_set_auto_realign(&mut lights_label, true)?;
+
+    let mut clock_label = Label::new(&mut screen)?;
+    clock_label.add_style(Part::Main, lights_style.clone())?;
+    clock_label.set_align(&mut screen, Align::InTopRight, -10, 10)?;
+    obj_set_auto_realign(&mut clock_label, true)?;
+
+    // Either side of the temperature arc, with the clock keeping the top right corner.
+    let mut touch_keys = TouchKeys::new();
+    touch_keys.add(&mut screen, Key::Menu, SYMBOL_SETTINGS, Align::InTopLeft, 8, 8)?;
+    touch_keys.add(&mut screen, Key::Down, SYMBOL_MINUS, Align::InLeftMid, 8, 0)?;
+    touch_keys.add(&mut screen, Key::Up, SYMBOL_PLUS, Align::InRightMid, -8, 0)?;
+    touch_keys.add(&mut screen, Key::Jets1, "", Align::InBottomLeft, 8, -8)?;
+    touch_keys.add(&mut screen, Key::Light, "", Align::InBottomRight, -8, -8)?;
 
     Ok(Self {
       screen,
-      styles,
+      palette_fade,
       temperature_widget,
-      is_heating_palette: None,
+      lights_label,
+      clock_label,
+      clock_text: String::new(),
+      touch_keys,
+      language: None,
     })
   }
 
-  fn set_is_heating(&mut self, is_heating: bool) -> LvResult<()> {
-    if self.is_heating_palette != Some(is_heating) {
-      self.is_heating_palette = Some(is_heating);
-
-      let palette = self.styles.select_palette(is_heating);
-
-      self.screen.add_style(Part::Main, palette.window_bg.clone())?;
-      self.temperature_widget.apply(palette)?;
-    }
-    Ok(())
+  fn set_palette(&mut self, theme: Theme, is_heating: bool) -> LvResult<()> {
+    let palettes = theme_palettes(theme);
+    let palette = if is_heating { palettes.heating } else { palettes.normal };
+    self.palette_fade.fade_to(palette)
   }
 
   fn get_hot_tub_model(model: &ViewModel) -> Option<&HotTubModel> {
@@ -117,15 +116,33 @@ impl Screen for MainScreen {
   }
 
   fn bind_model(&mut self, model: ViewModel) -> LvResult<()> {
+    let language = model.settings.language;
+    let theme = model.settings.theme;
+    if self.language != Some(language) {
+      self.touch_keys.set_text(Key::Jets1, text(language, Text::Jets))?;
+      self.touch_keys.set_text(Key::Light, text(language, Text::Light))?;
+      self.language = Some(language);
+    }
     let model = MainScreen::get_hot_tub_model(&model).unwrap();
-    self.set_is_heating(model.is_heating)?;
+    self.set_palette(theme, model.is_heating)?;
     let range = model.temp_range.display;
     self.temperature_widget.set_range(&range.0, &range.1)?;
+    self.temperature_widget.set_scale(model.scale)?;
     self.temperature_widget.set_target(&model.set_temp.display)?;
     self.temperature_widget.set_current(
-        model.current_temp.as_ref().map(|t| &t.display))?;
-    let action_label = if model.is_heating { "HEATING" } else { "" };
+        model.current_temp.as_ref().map(|t| &t.display), language)?;
+    let action_label = if model.is_heating { text(language, Text::Heating) } else { "" };
     self.temperature_widget.set_action_text(action_label)?;
+    let lights_on = model.devices.get(&DeviceCategory::Light)
+        .is_some_and(|lights| lights.iter().any(|l| l.current_level != DeviceLevel::Off));
+    let lights_text = if lights_on { text(language, Text::LightsOn) } else { "" };
+    self.lights_label.set_text(CString::new(lights_text).unwrap().as_c_str())?;
+    // Only redraw when the minute actually ticks over, not for every other change on screen.
+    let clock_text = model.clock_text();
+    if self.clock_text != clock_text {
+      self.clock_label.set_text(CString::new(clock_text.as_str()).unwrap().as_c_str())?;
+      self.clock_text = clock_text;
+    }
     Ok(())
   }
 }
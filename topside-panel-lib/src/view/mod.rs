@@ -2,15 +2,26 @@ pub mod ui_handler;
 pub mod main_screen;
 pub mod lcd_device;
 pub mod window_proxy;
+pub mod touch_input;
+pub mod touch_keys;
 pub mod user_input_event;
 pub mod temperature_widget;
 pub mod lvgl_ext;
 pub mod color_util;
 pub mod font;
+pub mod strings;
 pub mod palette;
 pub mod palette_styles;
+pub mod palette_fade;
 pub mod backlight_manager;
+pub mod feedback_device;
 pub mod provisioning_screen;
 pub mod screen_flipper;
 pub mod qr_code_widget;
 pub mod loading_screen;
+pub mod jets_screen;
+pub mod settings_screen;
+pub mod wifi_screen;
+pub mod fault_log_screen;
+pub mod screensaver_screen;
+pub mod toast_widget;
\ No newline at end of file
@@ -1,10 +1,14 @@
-use lvgl::LvResult;
-use crate::view::palette_styles::PaletteStyles;
+use lvgl::NativeObject;
+use crate::model::theme::Theme;
+use crate::view::color_util::mix_hex;
 
+/// A widget painted with [Palette::widget_fill] and [Palette::widget_bg_stroke], see
+/// [crate::view::palette_fade::PaletteFade].
 pub trait PaletteAware {
-  fn apply(&self, styles: &PaletteStyles) -> LvResult<()>;
+  fn palette_objects(&self) -> Vec<&dyn NativeObject>;
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Palette {
   /// Color that the whole window is painted
   pub window_bg: u32,
@@ -15,3 +19,54 @@ pub struct Palette {
   /// Muted stroke color that non-foreground strokes use
   pub widget_bg_stroke: u32,
 }
+
+impl Palette {
+  /// Part way from this palette to `to`, see [mix_hex].
+  pub fn mix(&self, to: &Palette, mix: u8) -> Palette {
+    Palette {
+      window_bg: mix_hex(self.window_bg, to.window_bg, mix),
+      widget_fill: mix_hex(self.widget_fill, to.widget_fill, mix),
+      widget_bg_stroke: mix_hex(self.widget_bg_stroke, to.widget_bg_stroke, mix),
+    }
+  }
+}
+
+/// Everything a [Theme] decides: the main screen switches between these as the heater comes and
+/// goes, the other screens always use [Self::normal].  Text stays white in every theme so each
+/// palette has to be dark enough to read it against.
+#[derive(Debug, Copy, Clone)]
+pub struct ThemePalettes {
+  pub normal: Palette,
+  pub heating: Palette,
+}
+
+pub fn theme_palettes(theme: Theme) -> ThemePalettes {
+  match theme {
+    Theme::Default => ThemePalettes {
+      normal: Palette {
+        window_bg: 0x393f47,
+        widget_fill: 0x3d444b,
+        widget_bg_stroke: 0x434a52,
+      },
+      heating: Palette {
+        window_bg: 0xdb742c,
+        widget_fill: 0xdd7e2f,
+        widget_bg_stroke: 0xdf8631,
+      },
+    },
+    // The default's subtle shading washes out in direct sun, so fills match the background and
+    // the unlit part of the scale is drawn bright enough to still make out.
+    Theme::HighContrast => ThemePalettes {
+      normal: Palette {
+        window_bg: 0x000000,
+        widget_fill: 0x000000,
+        widget_bg_stroke: 0x8c8c8c,
+      },
+      heating: Palette {
+        window_bg: 0x8a2e00,
+        widget_fill: 0x8a2e00,
+        widget_bg_stroke: 0xffa060,
+      },
+    },
+  }
+}
@@ -0,0 +1,112 @@
+use std::ptr::NonNull;
+use lvgl::{LvResult, NativeObject, Part, State};
+use lvgl_sys::lv_obj_t;
+use crate::view::color_util::hex_color;
+use crate::view::lvgl_ext::{anim_del, Anim, Animated, obj_set_style_local_color};
+use crate::view::palette::{Palette, PaletteAware};
+
+/// Long enough to read as a fade rather than a flicker, short enough not to look laggy when the
+/// heater comes on right after a key press.
+const FADE_TIME_MS: u32 = 800;
+
+/// Paints a window and its [PaletteAware] widgets, crossfading when the palette changes rather
+/// than swapping styles outright.  Colors go in the objects' local styles, the same way
+/// [crate::view::palette_styles::PaletteStyles] would apply them, so they win over anything
+/// added with `add_style`.
+pub struct PaletteFade {
+  /// Boxed so that it stays put while LVGL is animating it.
+  state: Box<FadeState>,
+}
+
+struct FadeState {
+  window: NonNull<lv_obj_t>,
+  widgets: Vec<NonNull<lv_obj_t>>,
+  from: Palette,
+  to: Palette,
+
+  /// None until the first palette is painted.
+  shown: Option<Palette>,
+}
+
+impl PaletteFade {
+  pub fn new(window: &impl NativeObject, widgets: &[&dyn PaletteAware]) -> LvResult<Self> {
+    let widgets = widgets.iter()
+        .flat_map(|w| w.palette_objects())
+        .map(|o| o.raw())
+        .collect::<LvResult<Vec<_>>>()?;
+    let placeholder = Palette { window_bg: 0, widget_fill: 0, widget_bg_stroke: 0 };
+    Ok(Self {
+      state: Box::new(FadeState {
+        window: window.raw()?,
+        widgets,
+        from: placeholder,
+        to: placeholder,
+        shown: None,
+      }),
+    })
+  }
+
+  /// Fades from whatever is on screen now, even part way through another fade.  The very first
+  /// palette is painted straight away, there's nothing to fade from.
+  pub fn fade_to(&mut self, palette: Palette) -> LvResult<()> {
+    let state = self.state.as_mut();
+    let Some(shown) = state.shown else {
+      state.to = palette;
+      state.paint(palette);
+      return Ok(());
+    };
+    if state.to == palette {
+      return Ok(());
+    }
+    state.from = shown;
+    state.to = palette;
+
+    let mut anim = Anim::new()?;
+    anim.set_values(0, 255);
+    anim.set_time_ms(FADE_TIME_MS);
+    anim.set_ease_in_out();
+    // SAFETY: the state is boxed and the animation is stopped when we're dropped.
+    unsafe {
+      anim.set_animated(NonNull::from(state));
+    }
+    anim.start();
+    Ok(())
+  }
+}
+
+impl Drop for PaletteFade {
+  fn drop(&mut self) {
+    anim_del(NonNull::from(self.state.as_mut()));
+  }
+}
+
+impl FadeState {
+  fn paint(&mut self, palette: Palette) {
+    obj_set_style_local_color(
+        self.window, Part::Main, lvgl_sys::LV_STYLE_BG_COLOR as u32, State::DEFAULT,
+        hex_color(palette.window_bg));
+    for &widget in &self.widgets {
+      obj_set_style_local_color(
+          widget, Part::Main, lvgl_sys::LV_STYLE_BG_COLOR as u32, State::DEFAULT,
+          hex_color(palette.widget_fill));
+      let stroke_props = [
+        lvgl_sys::LV_STYLE_LINE_COLOR as u32,
+        lvgl_sys::LV_STYLE_SCALE_GRAD_COLOR as u32,
+        lvgl_sys::LV_STYLE_SCALE_END_COLOR as u32,
+      ];
+      for prop in stroke_props {
+        obj_set_style_local_color(
+            widget, Part::Main, prop, State::DEFAULT, hex_color(palette.widget_bg_stroke));
+      }
+    }
+    self.shown = Some(palette);
+  }
+}
+
+impl Animated for FadeState {
+  fn step(&mut self, value: i32) {
+    let mix = value.clamp(0, 255) as u8;
+    let palette = self.from.mix(&self.to, mix);
+    self.paint(palette);
+  }
+}
@@ -1,7 +1,8 @@
 use lvgl::style::{Opacity, Style};
 use lvgl::State;
 use crate::view::color_util;
-use crate::view::palette::Palette;
+use crate::model::theme::Theme;
+use crate::view::palette::{Palette, theme_palettes};
 
 pub struct PaletteStyles {
   pub window_bg: Style,
@@ -29,3 +30,26 @@ impl PaletteStyles {
     }
   }
 }
+
+/// [PaletteStyles] for every [Theme]'s normal palette, built up front so that switching theme
+/// only has to pick which ones to add.  The main screen fades between palettes on its own, see
+/// [crate::view::palette_fade::PaletteFade].
+pub struct ThemeStyles {
+  normal: Vec<PaletteStyles>,
+}
+
+impl ThemeStyles {
+  pub fn new() -> Self {
+    Self {
+      normal: Theme::ALL.iter().map(|t| PaletteStyles::new(theme_palettes(*t).normal)).collect(),
+    }
+  }
+
+  pub fn normal(&self, theme: Theme) -> &PaletteStyles {
+    &self.normal[Self::index(theme)]
+  }
+
+  fn index(theme: Theme) -> usize {
+    Theme::ALL.iter().position(|t| *t == theme).unwrap()
+  }
+}
@@ -2,12 +2,13 @@ use log::{info, warn};
 use lvgl::{LvResult, Obj, Part, State, Widget};
 use lvgl::style::Style;
 use color_util::hex_color;
-use wifi_module_lib::view_model::{Mode, UnprovisionedModel};
+use wifi_module_lib::view_model::{Mode, ProvisioningMethod, UnprovisionedModel};
 use crate::model::view_model::ViewModel;
 use crate::view::{color_util};
 use crate::view::qr_code_widget::{QrCodeWidget, SetFromSourceError};
 use crate::view::qr_code_widget::Source::Text;
 use crate::view::screen_flipper::{BoxedScreen, Screen, ScreenOptions, ScreenSelector};
+use crate::view::strings::{format_text, text, Text};
 
 pub(crate) const LABEL_PRIMARY_COLOR: u32 = 0x000000;
 
@@ -84,20 +85,53 @@ impl Screen for ProvisioningScreen {
   }
 
   fn bind_model(&mut self, model: ViewModel) -> LvResult<()> {
+    let language = model.settings.language;
     let unprovisioned =
         ProvisioningScreen::get_unprovisioned_model(&model).unwrap();
-    let code = &unprovisioned.params.dpp_qr_code;
+    let (code, ok_text) = match &unprovisioned.params.method {
+      ProvisioningMethod::Dpp { qr_code, ble: None } => {
+        (qr_code.clone(), text(language, Text::ProvisionScan).to_owned())
+      }
+      ProvisioningMethod::Dpp { qr_code, ble: Some(ble) } => {
+        (
+          qr_code.clone(),
+          format_text(
+            language,
+            Text::ProvisionScanOrBle,
+            &[&ble.device_name, &ble.proof_of_possession]),
+        )
+      }
+      ProvisioningMethod::SoftAp { ssid, portal_url } => {
+        // Standard Wi-Fi network QR code, which phones offer to join when scanned.
+        (
+          format!("WIFI:S:{};T:nopass;;", escape_wifi_qr(ssid)),
+          format_text(language, Text::ProvisionScanToJoin, &[&ssid, &portal_url]),
+        )
+      }
+    };
     info!("Generating QR code from: {code}");
 
-    let help_text = match self.qr_widget.set_qr_code_from_src(Some(Text(code.clone()))) {
-      Ok(_) => "Scan the above code in your phone's Wi-Fi settings",
+    let help_text = match self.qr_widget.set_qr_code_from_src(Some(Text(code))) {
+      Ok(_) => ok_text,
       Err(SetFromSourceError::EncodeError(e)) => {
         warn!("QR code encode failed: {e:?}");
-        "QR code error!"
+        text(language, Text::QrCodeError).to_owned()
       }
       Err(SetFromSourceError::LvglError(e)) => return Err(e),
     };
-    self.qr_widget.set_help_text(help_text)?;
+    self.qr_widget.set_help_text(&help_text)?;
     Ok(())
   }
 }
+
+/// Special characters in `WIFI:` QR code fields must be backslash escaped.
+fn escape_wifi_qr(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
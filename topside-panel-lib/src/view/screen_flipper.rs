@@ -5,10 +5,15 @@ use log::info;
 use lvgl::{LvResult, Obj};
 
 use crate::model::view_model::ViewModel;
+use crate::view::jets_screen::JetsScreen;
 use crate::view::loading_screen::LoadingScreen;
 use crate::view::lvgl_ext::disp_load_scr;
 use crate::view::main_screen::MainScreen;
 use crate::view::provisioning_screen::ProvisioningScreen;
+use crate::view::settings_screen::SettingsScreen;
+use crate::view::screensaver_screen::ScreensaverScreen;
+use crate::view::fault_log_screen::FaultLogScreen;
+use crate::view::wifi_screen::WifiScreen;
 
 pub trait ScreenSelector {
   fn kind() -> &'static str;
@@ -73,6 +78,16 @@ impl ScreenFlipper {
   fn select_screen(&mut self, model: &ViewModel) -> &'static str {
     if ProvisioningScreen::accept_model(model) {
       ProvisioningScreen::kind()
+    } else if ScreensaverScreen::accept_model(model) {
+      ScreensaverScreen::kind()
+    } else if FaultLogScreen::accept_model(model) {
+      FaultLogScreen::kind()
+    } else if WifiScreen::accept_model(model) {
+      WifiScreen::kind()
+    } else if SettingsScreen::accept_model(model) {
+      SettingsScreen::kind()
+    } else if JetsScreen::accept_model(model) {
+      JetsScreen::kind()
     } else if MainScreen::accept_model(model) {
       MainScreen::kind()
     } else if LoadingScreen::accept_model(model) {
@@ -85,6 +100,16 @@ impl ScreenFlipper {
   fn create_screen(kind: &'static str) -> LvResult<BoxedScreen> {
     if ptr::eq(ProvisioningScreen::kind(), kind) {
       ProvisioningScreen::create()
+    } else if ptr::eq(ScreensaverScreen::kind(), kind) {
+      ScreensaverScreen::create()
+    } else if ptr::eq(FaultLogScreen::kind(), kind) {
+      FaultLogScreen::create()
+    } else if ptr::eq(WifiScreen::kind(), kind) {
+      WifiScreen::create()
+    } else if ptr::eq(SettingsScreen::kind(), kind) {
+      SettingsScreen::create()
+    } else if ptr::eq(JetsScreen::kind(), kind) {
+      JetsScreen::create()
     } else if ptr::eq(MainScreen::kind(), kind) {
       MainScreen::create()
     } else if ptr::eq(LoadingScreen::kind(), kind) {
@@ -0,0 +1,106 @@
+use cstr_core::CString;
+use lvgl::{Align, LvResult, Obj, Part, State, Widget};
+use lvgl::style::Style;
+use lvgl::widgets::Label;
+use crate::model::view_model::ViewModel;
+use crate::view::color_util::hex_color;
+use crate::view::font::Font;
+use crate::view::lvgl_ext::{obj_set_auto_realign, style_set_text_font};
+use crate::view::main_screen::{LABEL_PRIMARY_COLOR, MainScreen};
+use crate::view::screen_flipper::{BoxedScreen, Screen, ScreenSelector};
+use crate::view::temperature_widget;
+
+/// Just the time and water temperature on black, to go easy on a dimmed backlight.
+pub struct ScreensaverScreen {
+  screen: Obj,
+  styles: Styles,
+  temperature_label: Label,
+  time_label: Label,
+  current_text: (String, String),
+}
+
+struct Styles {
+  window_bg: Style,
+}
+
+impl Styles {
+  pub fn new() -> Self {
+    let mut window_bg = Style::default();
+    window_bg.set_bg_color(State::DEFAULT, hex_color(0x000000));
+
+    Self {
+      window_bg,
+    }
+  }
+}
+
+impl ScreensaverScreen {
+  pub fn new() -> LvResult<Self> {
+    let mut screen = Obj::default();
+    let styles = Styles::new();
+
+    screen.add_style(Part::Main, styles.window_bg.clone())?;
+
+    let mut temperature_style = Style::default();
+    temperature_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut temperature_style, State::DEFAULT, Font::MONTSERRAT_48);
+    let mut temperature_label = Label::new(&mut screen)?;
+    temperature_label.add_style(Part::Main, temperature_style.clone())?;
+    temperature_label.set_align(&mut screen, Align::Center, 0, -16)?;
+    obj_set_auto_realign(&mut temperature_label, true)?;
+
+    let mut time_style = Style::default();
+    time_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut time_style, State::DEFAULT, Font::MONTSERRAT_24);
+    let mut time_label = Label::new(&mut screen)?;
+    time_label.add_style(Part::Main, time_style.clone())?;
+    time_label.set_align(&mut temperature_label, Align::OutBottomMid, 0, 8)?;
+    obj_set_auto_realign(&mut time_label, true)?;
+
+    Ok(Self {
+      screen,
+      styles,
+      temperature_label,
+      time_label,
+      current_text: (String::new(), String::new()),
+    })
+  }
+}
+
+impl ScreenSelector for ScreensaverScreen {
+  fn kind() -> &'static str {
+    "screensaver"
+  }
+
+  fn create() -> LvResult<BoxedScreen> {
+    Ok(Box::new(ScreensaverScreen::new()?))
+  }
+
+  fn accept_model(model: &ViewModel) -> bool {
+    model.screensaver && MainScreen::accept_model(model)
+  }
+}
+
+impl Screen for ScreensaverScreen {
+  fn get_root(&self) -> &Obj {
+    &self.screen
+  }
+
+  fn bind_model(&mut self, model: ViewModel) -> LvResult<()> {
+    let model = model.last_model.unwrap();
+    // Until the main board has a reading, the set point is the best we have.
+    let temperature = model.current_temp.as_ref().unwrap_or(&model.set_temp);
+    let text = (
+      format!("{}{}", temperature.display, temperature_widget::unit_text(model.scale)),
+      model.clock_text(),
+    );
+    if self.current_text.0 != text.0 {
+      self.temperature_label.set_text(CString::new(text.0.as_str()).unwrap().as_c_str())?;
+    }
+    if self.current_text.1 != text.1 {
+      self.time_label.set_text(CString::new(text.1.as_str()).unwrap().as_c_str())?;
+    }
+    self.current_text = text;
+    Ok(())
+  }
+}
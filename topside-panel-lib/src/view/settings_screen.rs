@@ -0,0 +1,185 @@
+use cstr_core::CString;
+use lvgl::{Align, LvResult, Obj, Part, State, Widget};
+use lvgl::style::Style;
+use lvgl::widgets::Label;
+use balboa_spa_messages::message_types::{ClockMode, FilterCycle};
+use crate::model::key_event::Key;
+use crate::model::language::Language;
+use crate::model::theme::Theme;
+use crate::model::view_model::{SettingsItem, ViewModel};
+use crate::view::color_util::hex_color;
+use crate::view::font::Font;
+use crate::view::lvgl_ext::{obj_set_auto_realign, style_set_text_font};
+use crate::view::main_screen::{LABEL_PRIMARY_COLOR, MainScreen};
+use crate::view::palette_styles::ThemeStyles;
+use crate::view::screen_flipper::{BoxedScreen, Screen, ScreenSelector};
+use crate::view::strings::{language_name, text, theme_name, Text};
+use crate::view::temperature_widget;
+use crate::view::touch_keys::{SYMBOL_DOWN, SYMBOL_LEFT, SYMBOL_OK, SYMBOL_UP, TouchKeys};
+use crate::view::wifi_screen;
+
+/// Shown for anything the main board hasn't told us about yet.
+const UNKNOWN: &str = "--";
+
+pub struct SettingsScreen {
+  screen: Obj,
+  styles: Styles,
+  title: Label,
+  rows: Vec<Label>,
+  current_text: Vec<String>,
+  language: Option<Language>,
+  theme: Option<Theme>,
+  _touch_keys: TouchKeys,
+}
+
+struct Styles {
+  themes: ThemeStyles,
+}
+
+impl Styles {
+  pub fn new() -> Self {
+    Self {
+      themes: ThemeStyles::new(),
+    }
+  }
+}
+
+impl SettingsScreen {
+  pub fn new() -> LvResult<Self> {
+    let mut screen = Obj::default();
+    let styles = Styles::new();
+
+    let mut title_style = Style::default();
+    title_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut title_style, State::DEFAULT, Font::MONTSERRAT_32);
+    let mut title = Label::new(&mut screen)?;
+    title.add_style(Part::Main, title_style.clone())?;
+    title.set_align(&mut screen, Align::InTopMid, 0, 8)?;
+    obj_set_auto_realign(&mut title, true)?;
+
+    // Smaller and tighter than the jets screen so that every setting fits without scrolling.
+    let mut row_style = Style::default();
+    row_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut row_style, State::DEFAULT, Font::MONTSERRAT_16);
+
+    let mut rows: Vec<Label> = Vec::with_capacity(SettingsItem::ALL.len());
+    for _ in SettingsItem::ALL {
+      let mut row = Label::new(&mut screen)?;
+      row.add_style(Part::Main, row_style.clone())?;
+      match rows.last_mut() {
+        None => row.set_align(&mut title, Align::OutBottomMid, 0, 8)?,
+        Some(above) => row.set_align(above, Align::OutBottomLeft, 0, 2)?,
+      }
+      obj_set_auto_realign(&mut row, true)?;
+      row.set_text(CString::new("").unwrap().as_c_str())?;
+      rows.push(row);
+    }
+
+    // In the corners, clear of the title and the rows running down the middle.
+    let mut touch_keys = TouchKeys::new();
+    touch_keys.add(&mut screen, Key::Menu, SYMBOL_LEFT, Align::InTopLeft, 8, 8)?;
+    touch_keys.add(&mut screen, Key::Up, SYMBOL_UP, Align::InTopRight, -8, 8)?;
+    touch_keys.add(&mut screen, Key::Jets1, SYMBOL_OK, Align::InBottomLeft, 8, -8)?;
+    touch_keys.add(&mut screen, Key::Down, SYMBOL_DOWN, Align::InBottomRight, -8, -8)?;
+
+    Ok(Self {
+      screen,
+      styles,
+      title,
+      rows,
+      current_text: vec![String::new(); SettingsItem::ALL.len()],
+      language: None,
+      theme: None,
+      _touch_keys: touch_keys,
+    })
+  }
+
+  fn row_text(item: SettingsItem, model: &ViewModel, selected: bool) -> String {
+    let marker = if selected { ">" } else { " " };
+    let settings = &model.settings;
+    let language = settings.language;
+    let cycle = |index: usize| {
+      settings.filter_cycles.as_ref().and_then(|cycles| cycles.get(index))
+    };
+    let on_off = |on: bool| text(language, if on { Text::On } else { Text::Off }).to_owned();
+    let (label, value) = match item {
+      SettingsItem::TemperatureScale => (Text::TempScale, settings.temperature_scale.map(|s| {
+        temperature_widget::unit_text(s).to_owned()
+      })),
+      SettingsItem::ClockMode => (Text::Clock, settings.clock_mode.map(|m| {
+        match m {
+          ClockMode::Hour12 => "12h".to_owned(),
+          ClockMode::Hour24 => "24h".to_owned(),
+        }
+      })),
+      SettingsItem::Language => (Text::Language, Some(language_name(language).to_owned())),
+      SettingsItem::Theme => (Text::Theme, Some(theme_name(language, settings.theme).to_owned())),
+      SettingsItem::Reminders => (Text::Reminders, settings.reminders.map(on_off)),
+      SettingsItem::Filter1Start => (Text::Filter1Start, cycle(0).map(start_text)),
+      SettingsItem::Filter1Hours => (Text::Filter1Length, cycle(0).map(hours_text)),
+      SettingsItem::Filter2Enabled => (Text::Filter2, cycle(1).map(|c| on_off(c.enabled))),
+      SettingsItem::Filter2Start => (Text::Filter2Start, cycle(1).map(start_text)),
+      SettingsItem::Filter2Hours => (Text::Filter2Length, cycle(1).map(hours_text)),
+      SettingsItem::Wifi => (Text::Wifi, model.wifi_model.as_ref().map(|w| {
+        wifi_screen::summary(&w.mode, language).to_owned()
+      })),
+      // Opens its own screen, nothing to show here.
+      SettingsItem::FaultLog => return format!("{marker} {}", text(language, Text::FaultLog)),
+    };
+    let label = text(language, label);
+    let value = value.as_deref().unwrap_or(UNKNOWN);
+    format!("{marker} {label}: {value}")
+  }
+}
+
+fn start_text(cycle: &FilterCycle) -> String {
+  let minutes = cycle.start_at.as_secs() / 60;
+  format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+fn hours_text(cycle: &FilterCycle) -> String {
+  let hours = cycle.duration.as_secs_f32() / (60.0 * 60.0);
+  format!("{hours}h")
+}
+
+impl ScreenSelector for SettingsScreen {
+  fn kind() -> &'static str {
+    "settings"
+  }
+
+  fn create() -> LvResult<BoxedScreen> {
+    Ok(Box::new(SettingsScreen::new()?))
+  }
+
+  fn accept_model(model: &ViewModel) -> bool {
+    model.settings_selection.is_some() && MainScreen::accept_model(model)
+  }
+}
+
+impl Screen for SettingsScreen {
+  fn get_root(&self) -> &Obj {
+    &self.screen
+  }
+
+  fn bind_model(&mut self, model: ViewModel) -> LvResult<()> {
+    let theme = model.settings.theme;
+    if self.theme != Some(theme) {
+      self.screen.add_style(Part::Main, self.styles.themes.normal(theme).window_bg.clone())?;
+      self.theme = Some(theme);
+    }
+    let language = model.settings.language;
+    if self.language != Some(language) {
+      self.title.set_text(CString::new(text(language, Text::Settings)).unwrap().as_c_str())?;
+      self.language = Some(language);
+    }
+    let selected = model.settings_selection.unwrap_or_default();
+    for (i, row) in self.rows.iter_mut().enumerate() {
+      let text = SettingsScreen::row_text(SettingsItem::ALL[i], &model, i == selected);
+      if self.current_text[i] != text {
+        row.set_text(CString::new(text.as_str()).unwrap().as_c_str())?;
+        self.current_text[i] = text;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,317 @@
+//! Everything the panel shows in words, looked up by [Language] so that adding a language is a
+//! matter of adding a table here rather than touching every screen.
+//!
+//! Texts that take values use `{}` placeholders, filled in order by [format_text].  The built-in
+//! Montserrat fonts only cover ASCII (plus °), so translations stick to unaccented spelling
+//! until fonts with more glyphs are generated.
+
+use std::fmt::Display;
+use balboa_spa_messages::message_types::FaultCode;
+use num_traits::FromPrimitive;
+use crate::model::language::Language;
+use crate::model::theme::Theme;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Text {
+  Loading,
+  Heating,
+  LightsOn,
+  Light,
+  /// Water temperature, e.g. "Water 101°".
+  WaterTemp,
+  WaterTempUnknown,
+
+  Jets,
+  /// Jet number and its level.
+  JetRow,
+  NoJets,
+  Off,
+  On,
+  Low,
+  High,
+
+  Settings,
+  TempScale,
+  Clock,
+  Language,
+  Theme,
+  ThemeDefault,
+  ThemeHighContrast,
+  Reminders,
+  Filter1Start,
+  Filter1Length,
+  Filter2,
+  Filter2Start,
+  Filter2Length,
+  Wifi,
+  FaultLog,
+
+  WifiStartingUp,
+  WifiFailedRecover,
+  WifiNotSetUp,
+  WifiCantConnectCheck,
+  WifiNetwork,
+  WifiSignal,
+  WifiAddress,
+  WifiStarting,
+  WifiFailed,
+  WifiCantConnect,
+  WifiNotConnected,
+  WifiConnecting,
+  WifiGettingAddress,
+  WifiConnected,
+
+  NoFaults,
+  /// Position in the fault log and how many entries it has.
+  FaultEntryOf,
+  Today,
+  Yesterday,
+  DaysAgo,
+  /// The day, then the time of day.
+  FaultWhen,
+  FaultClearHint,
+  UnknownFault,
+
+  MainboardSilent,
+  ChannelLost,
+  Garbled,
+
+  ProvisionScan,
+  /// Bluetooth device name and PIN.
+  ProvisionScanOrBle,
+  /// Network name and portal URL.
+  ProvisionScanToJoin,
+  QrCodeError,
+}
+
+pub fn text(language: Language, text: Text) -> &'static str {
+  match language {
+    Language::English => english(text),
+    Language::Spanish => spanish(text),
+  }
+}
+
+/// [text] with each `{}` replaced by the next of `args`.
+pub fn format_text(language: Language, text_key: Text, args: &[&dyn Display]) -> String {
+  let mut pieces = text(language, text_key).split("{}");
+  let mut result = pieces.next().unwrap_or_default().to_owned();
+  let mut args = args.iter();
+  for piece in pieces {
+    if let Some(arg) = args.next() {
+      result.push_str(&arg.to_string());
+    }
+    result.push_str(piece);
+  }
+  result
+}
+
+/// What a fault log entry's code means.
+pub fn fault_text(language: Language, fault_code: u8) -> String {
+  let Some(code) = FaultCode::from_u8(fault_code) else {
+    return format_text(language, Text::UnknownFault, &[&fault_code]);
+  };
+  match language {
+    // The protocol crate already describes them in English.
+    Language::English => code.to_string(),
+    Language::Spanish => spanish_fault(code).to_owned(),
+  }
+}
+
+/// What to call each language in its own words, for picking one.
+pub fn language_name(language: Language) -> &'static str {
+  match language {
+    Language::English => "English",
+    Language::Spanish => "Espanol",
+  }
+}
+
+pub fn theme_name(language: Language, theme: Theme) -> &'static str {
+  let key = match theme {
+    Theme::Default => Text::ThemeDefault,
+    Theme::HighContrast => Text::ThemeHighContrast,
+  };
+  text(language, key)
+}
+
+fn english(text: Text) -> &'static str {
+  match text {
+    Text::Loading => "Loading...",
+    Text::Heating => "HEATING",
+    Text::LightsOn => "LIGHTS ON",
+    Text::Light => "Light",
+    Text::WaterTemp => "Water {}°",
+    Text::WaterTempUnknown => "Water --",
+
+    Text::Jets => "Jets",
+    Text::JetRow => "Jet {}: {}",
+    Text::NoJets => "No jets",
+    Text::Off => "Off",
+    Text::On => "On",
+    Text::Low => "Low",
+    Text::High => "High",
+
+    Text::Settings => "Settings",
+    Text::TempScale => "Temp scale",
+    Text::Clock => "Clock",
+    Text::Language => "Language",
+    Text::Theme => "Theme",
+    Text::ThemeDefault => "Default",
+    Text::ThemeHighContrast => "High contrast",
+    Text::Reminders => "Reminders",
+    Text::Filter1Start => "Filter 1 start",
+    Text::Filter1Length => "Filter 1 length",
+    Text::Filter2 => "Filter 2",
+    Text::Filter2Start => "Filter 2 start",
+    Text::Filter2Length => "Filter 2 length",
+    Text::Wifi => "Wi-Fi",
+    Text::FaultLog => "Fault log",
+
+    Text::WifiStartingUp => "Starting up...",
+    Text::WifiFailedRecover => "Wi-Fi failed, power cycle or factory reset to recover",
+    Text::WifiNotSetUp => "Not set up",
+    Text::WifiCantConnectCheck => "Can't connect, check the access point is on and in range",
+    Text::WifiNetwork => "Network: {}",
+    Text::WifiSignal => "Signal: {} dBm",
+    Text::WifiAddress => "Address: {}",
+    Text::WifiStarting => "Starting",
+    Text::WifiFailed => "Failed",
+    Text::WifiCantConnect => "Can't connect",
+    Text::WifiNotConnected => "Not connected",
+    Text::WifiConnecting => "Connecting",
+    Text::WifiGettingAddress => "Getting address",
+    Text::WifiConnected => "Connected",
+
+    Text::NoFaults => "No faults",
+    Text::FaultEntryOf => "{} of {}",
+    Text::Today => "Today",
+    Text::Yesterday => "Yesterday",
+    Text::DaysAgo => "{} days ago",
+    Text::FaultWhen => "{} at {}",
+    Text::FaultClearHint => "Jets clears the notification",
+    Text::UnknownFault => "Unknown fault {}",
+
+    Text::MainboardSilent => "No response from the spa",
+    Text::ChannelLost => "Lost connection to the spa, reconnecting...",
+    Text::Garbled => "Trouble reading from the spa, check the wiring",
+
+    Text::ProvisionScan => "Scan the above code in your phone's Wi-Fi settings",
+    Text::ProvisionScanOrBle =>
+      "Scan the above code in your phone's Wi-Fi settings, or provision via Bluetooth: {} (PIN {})",
+    Text::ProvisionScanToJoin => "Scan to join {}, then visit {} if setup doesn't open",
+    Text::QrCodeError => "QR code error!",
+  }
+}
+
+fn spanish(text: Text) -> &'static str {
+  match text {
+    Text::Loading => "Cargando...",
+    Text::Heating => "CALENTANDO",
+    Text::LightsOn => "LUCES ENCENDIDAS",
+    Text::Light => "Luz",
+    Text::WaterTemp => "Agua {}°",
+    Text::WaterTempUnknown => "Agua --",
+
+    Text::Jets => "Chorros",
+    Text::JetRow => "Chorro {}: {}",
+    Text::NoJets => "Sin chorros",
+    Text::Off => "Apagado",
+    Text::On => "Encendido",
+    Text::Low => "Bajo",
+    Text::High => "Alto",
+
+    Text::Settings => "Ajustes",
+    Text::TempScale => "Escala",
+    Text::Clock => "Reloj",
+    Text::Language => "Idioma",
+    Text::Theme => "Tema",
+    Text::ThemeDefault => "Predeterminado",
+    Text::ThemeHighContrast => "Alto contraste",
+    Text::Reminders => "Recordatorios",
+    Text::Filter1Start => "Inicio filtro 1",
+    Text::Filter1Length => "Duracion filtro 1",
+    Text::Filter2 => "Filtro 2",
+    Text::Filter2Start => "Inicio filtro 2",
+    Text::Filter2Length => "Duracion filtro 2",
+    Text::Wifi => "Wi-Fi",
+    Text::FaultLog => "Registro de fallos",
+
+    Text::WifiStartingUp => "Iniciando...",
+    Text::WifiFailedRecover => "Fallo del Wi-Fi, reinicie o restablezca de fabrica para recuperarlo",
+    Text::WifiNotSetUp => "Sin configurar",
+    Text::WifiCantConnectCheck =>
+      "No se puede conectar, compruebe que el punto de acceso este encendido y al alcance",
+    Text::WifiNetwork => "Red: {}",
+    Text::WifiSignal => "Senal: {} dBm",
+    Text::WifiAddress => "Direccion: {}",
+    Text::WifiStarting => "Iniciando",
+    Text::WifiFailed => "Fallo",
+    Text::WifiCantConnect => "Sin conexion",
+    Text::WifiNotConnected => "Desconectado",
+    Text::WifiConnecting => "Conectando",
+    Text::WifiGettingAddress => "Obteniendo direccion",
+    Text::WifiConnected => "Conectado",
+
+    Text::NoFaults => "Sin fallos",
+    Text::FaultEntryOf => "{} de {}",
+    Text::Today => "Hoy",
+    Text::Yesterday => "Ayer",
+    Text::DaysAgo => "Hace {} dias",
+    Text::FaultWhen => "{} a las {}",
+    Text::FaultClearHint => "Chorros borra el aviso",
+    Text::UnknownFault => "Fallo desconocido {}",
+
+    Text::MainboardSilent => "El spa no responde",
+    Text::ChannelLost => "Conexion con el spa perdida, reconectando...",
+    Text::Garbled => "Problemas leyendo del spa, revise el cableado",
+
+    Text::ProvisionScan => "Escanee el codigo en los ajustes Wi-Fi de su telefono",
+    Text::ProvisionScanOrBle =>
+      "Escanee el codigo en los ajustes Wi-Fi de su telefono, o configure por Bluetooth: {} (PIN {})",
+    Text::ProvisionScanToJoin => "Escanee para unirse a {} y visite {} si no se abre la configuracion",
+    Text::QrCodeError => "Error del codigo QR",
+  }
+}
+
+fn spanish_fault(code: FaultCode) -> &'static str {
+  match code {
+    FaultCode::SensorsOutOfSync => "Los sensores no estan sincronizados",
+    FaultCode::WaterFlowLow => "El caudal de agua es bajo",
+    FaultCode::WaterFlowFailed => "El caudal de agua ha fallado",
+    FaultCode::SettingsReset1 | FaultCode::SettingsReset2 => "Los ajustes se han restablecido",
+    FaultCode::PrimingMode => "Modo de cebado",
+    FaultCode::ClockFailed => "El reloj ha fallado",
+    FaultCode::ProgramMemoryFailure => "Fallo de la memoria de programa",
+    FaultCode::SensorsOutOfSyncCallForService =>
+      "Los sensores no estan sincronizados -- llame al servicio tecnico",
+    FaultCode::HeaterIsDry => "El calentador esta seco",
+    FaultCode::HeaterMayBeDry => "El calentador puede estar seco",
+    FaultCode::WaterTooHot => "El agua esta demasiado caliente",
+    FaultCode::HeaterTooHot => "El calentador esta demasiado caliente",
+    FaultCode::SensorAFault => "Fallo del sensor A",
+    FaultCode::SensorBFault => "Fallo del sensor B",
+    FaultCode::PumpMayBeStuckOn => "Una bomba puede haberse quedado encendida",
+    FaultCode::HotFault => "Fallo por sobrecalentamiento",
+    FaultCode::GfciTestFailed => "La prueba del GFCI ha fallado",
+    FaultCode::StandbyMode => "Modo de espera",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_format_text() {
+    let text = format_text(Language::English, Text::FaultEntryOf, &[&1, &3]);
+    assert_eq!(text, "1 of 3");
+    let text = format_text(Language::Spanish, Text::FaultEntryOf, &[&1, &3]);
+    assert_eq!(text, "1 de 3");
+  }
+
+  #[test]
+  fn test_fault_text() {
+    assert_eq!(fault_text(Language::English, 16), "The water flow is low");
+    assert_eq!(fault_text(Language::Spanish, 16), "El caudal de agua es bajo");
+    assert_eq!(fault_text(Language::Spanish, 1), "Fallo desconocido 1");
+  }
+}
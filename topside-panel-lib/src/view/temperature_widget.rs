@@ -3,18 +3,30 @@ use lvgl::style::Style;
 use lvgl::widgets::{Label, Linemeter};
 use cstr_core::CString;
 use log::info;
+use balboa_spa_messages::temperature::TemperatureScale;
+use crate::model::language::Language;
 use crate::model::temperature_model::TemperatureDisplay;
 use crate::view::color_util::hex_color;
 use crate::view::font::Font;
-use crate::view::lvgl_ext::{obj_set_auto_realign, style_set_text_font};
+use crate::view::lvgl_ext::{Anim, linemeter_get_value, obj_set_auto_realign, style_set_text_font};
 use crate::view::main_screen::LABEL_PRIMARY_COLOR;
 use crate::view::palette::PaletteAware;
-use crate::view::palette_styles::PaletteStyles;
+use crate::view::strings::{self, format_text, Text};
 
+const ARC_SWEEP_MS: u32 = 600;
+
+/// The arc follows the water temperature while the big label shows the set point, so it's easy
+/// to see at a glance how far off it is.
 pub struct TemperatureWidget {
   linemeter: Linemeter,
   main_label: TemperatureLabel,
   action_label: Label,
+  current_label: Label,
+  unit_label: Label,
+  range_min: i32,
+
+  /// Where the arc is headed, it may still be sweeping there.
+  arc_value: Option<i32>,
 }
 
 impl TemperatureWidget {
@@ -48,40 +60,97 @@ impl TemperatureWidget {
     action_label.set_align(&mut main_label.large_label, Align::OutTopMid, 0, 0)?;
     obj_set_auto_realign(&mut action_label, true)?;
 
+    let mut current_style = Style::default();
+    current_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut current_style, State::DEFAULT, Font::MONTSERRAT_16);
+    let mut current_label = Label::new(&mut linemeter)?;
+    current_label.add_style(Part::Main, current_style.clone())?;
+    current_label.set_align(&mut main_label.large_label, Align::OutBottomMid, 0, 4)?;
+    obj_set_auto_realign(&mut current_label, true)?;
+
+    let mut unit_label = Label::new(&mut linemeter)?;
+    unit_label.add_style(Part::Main, current_style.clone())?;
+    unit_label.set_align(&mut main_label.large_label, Align::OutRightBottom, 2, 0)?;
+    obj_set_auto_realign(&mut unit_label, true)?;
+
     Ok(Self {
       linemeter,
       main_label,
       action_label,
+      current_label,
+      unit_label,
+      range_min: 0,
+      arc_value: None,
     })
   }
 
   pub fn set_range(&mut self, min: &TemperatureDisplay, max: &TemperatureDisplay) -> LvResult<()> {
+    self.range_min = min.int_value;
     self.linemeter.set_range(min.int_value, max.int_value)
   }
 
+  pub fn set_scale(&mut self, scale: TemperatureScale) -> LvResult<()> {
+    self.unit_label.set_text(CString::new(unit_text(scale)).unwrap().as_c_str())
+  }
+
   pub fn set_target(&mut self, value: &TemperatureDisplay) -> LvResult<()> {
-    self.linemeter.set_value(value.int_value)?;
-    self.main_label.set_temperature(value)?;
-    Ok(())
+    self.main_label.set_temperature(value)
   }
 
   pub fn set_action_text(&mut self, value: &str) -> LvResult<()> {
     self.action_label.set_text(CString::new(value).unwrap().as_c_str())
   }
 
-  pub fn set_current(&mut self, value: Option<&TemperatureDisplay>) -> LvResult<()> {
+  /// None while the main board doesn't know, such as when priming or just after the pumps have
+  /// been off for a while.  The arc is left empty rather than guessing.
+  pub fn set_current(&mut self, value: Option<&TemperatureDisplay>, language: Language) -> LvResult<()> {
+    let (arc_value, text) = match value {
+      Some(value) => (value.int_value, format_text(language, Text::WaterTemp, &[value])),
+      None => (self.range_min, strings::text(language, Text::WaterTempUnknown).to_owned()),
+    };
+    self.set_arc_value(arc_value)?;
+    self.current_label.set_text(CString::new(text).unwrap().as_c_str())
+  }
+
+  /// Sweeps the arc over to `value` from wherever it is now, even part way through the last
+  /// sweep, instead of jumping there.
+  fn set_arc_value(&mut self, value: i32) -> LvResult<()> {
+    if self.arc_value == Some(value) {
+      return Ok(());
+    }
+    if self.arc_value.is_none() {
+      // Nothing on screen yet to sweep from.
+      self.linemeter.set_value(value)?;
+    } else {
+      let mut anim = Anim::new()?;
+      anim.set_values(linemeter_get_value(&self.linemeter)?, value);
+      anim.set_time_ms(ARC_SWEEP_MS);
+      anim.set_ease_in_out();
+      anim.set_obj_exec_cb(&self.linemeter, linemeter_value_cb)?;
+      anim.start();
+    }
+    self.arc_value = Some(value);
     Ok(())
   }
 }
 
+pub(crate) fn unit_text(scale: TemperatureScale) -> &'static str {
+  match scale {
+    TemperatureScale::Fahrenheit => "°F",
+    TemperatureScale::Celsius => "°C",
+  }
+}
+
 impl PaletteAware for TemperatureWidget {
-  fn apply(&self, styles: &PaletteStyles) -> LvResult<()> {
-    self.linemeter.add_style(Part::Main, styles.widget_fill.clone())?;
-    self.linemeter.add_style(Part::Main, styles.widget_bg_stroke.clone())?;
-    Ok(())
+  fn palette_objects(&self) -> Vec<&dyn NativeObject> {
+    vec![&self.linemeter]
   }
 }
 
+unsafe extern "C" fn linemeter_value_cb(var: *mut cty::c_void, value: lvgl_sys::lv_anim_value_t) {
+  lvgl_sys::lv_linemeter_set_value(var.cast(), i32::from(value));
+}
+
 struct TemperatureLabel {
   current_value: Option<TemperatureDisplay>,
   large_label: Label,
@@ -0,0 +1,74 @@
+use cstr_core::CString;
+use lvgl::{Align, LvResult, Part, State, Widget};
+use lvgl::style::{Opacity, Style};
+use lvgl::widgets::{Label, LabelAlign};
+use crate::model::language::Language;
+use crate::model::view_model::{CommProblem, ViewModel};
+use crate::view::color_util::hex_color;
+use crate::view::font::Font;
+use crate::view::lvgl_ext::{label_set_long_mode, LabelLongMode, layer_top, obj_set_auto_realign, obj_set_hidden, style_set_text_font};
+use crate::view::main_screen::LABEL_PRIMARY_COLOR;
+use crate::view::strings::{text, Text};
+
+const TOAST_BG_COLOR: u32 = 0xa32020;
+
+/// Banner across the top for a [CommProblem], gone again as soon as the problem is.  Lives on
+/// LVGL's top layer rather than in any one screen so that it shows over all of them.
+pub struct ToastWidget {
+  label: Label,
+  shown: Option<(CommProblem, Language)>,
+}
+
+impl ToastWidget {
+  pub fn new() -> LvResult<Self> {
+    let mut layer = layer_top()?;
+
+    let mut style = Style::default();
+    style.set_bg_color(State::DEFAULT, hex_color(TOAST_BG_COLOR));
+    style.set_bg_opa(State::DEFAULT, Opacity::OPA_COVER);
+    style.set_radius(State::DEFAULT, 6);
+    style.set_pad_left(State::DEFAULT, 8);
+    style.set_pad_right(State::DEFAULT, 8);
+    style.set_pad_top(State::DEFAULT, 6);
+    style.set_pad_bottom(State::DEFAULT, 6);
+    style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut style, State::DEFAULT, Font::MONTSERRAT_16);
+
+    let mut label = Label::new(&mut layer)?;
+    label_set_long_mode(&mut label, LabelLongMode::Break)?;
+    label.set_width(440)?;
+    label.set_label_align(LabelAlign::Center)?;
+    label.add_style(Part::Main, style.clone())?;
+    label.set_align(&mut layer, Align::InTopMid, 0, 8)?;
+    obj_set_auto_realign(&mut label, true)?;
+    obj_set_hidden(&mut label, true)?;
+
+    Ok(Self {
+      label,
+      shown: None,
+    })
+  }
+
+  pub fn bind_model(&mut self, model: &ViewModel) -> LvResult<()> {
+    let language = model.settings.language;
+    let wanted = model.comm_problem.map(|problem| (problem, language));
+    if self.shown == wanted {
+      return Ok(());
+    }
+    if let Some((problem, language)) = wanted {
+      let message = text(language, problem_text(problem));
+      self.label.set_text(CString::new(message).unwrap().as_c_str())?;
+    }
+    obj_set_hidden(&mut self.label, wanted.is_none())?;
+    self.shown = wanted;
+    Ok(())
+  }
+}
+
+fn problem_text(problem: CommProblem) -> Text {
+  match problem {
+    CommProblem::MainboardSilent => Text::MainboardSilent,
+    CommProblem::ChannelLost => Text::ChannelLost,
+    CommProblem::Garbled => Text::Garbled,
+  }
+}
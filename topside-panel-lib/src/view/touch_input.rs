@@ -0,0 +1,37 @@
+use embedded_graphics::geometry::Point;
+
+/// A touch panel laid over the display, polled alongside [crate::view::window_proxy::WindowProxy]
+/// and fed to LVGL as a pointer so that on-screen buttons can be pressed directly.
+pub trait TouchInput {
+  /// Where the panel is being touched right now in display coordinates, or None if it isn't.
+  /// Panels that report multiple touch points should only report the first.
+  fn read(&mut self) -> Option<Point>;
+
+  /// Whether there's a panel at all, which decides if screens show
+  /// [crate::view::touch_keys::TouchKeys].
+  fn is_present(&self) -> bool {
+    true
+  }
+}
+
+/// For panels with only the physical keys.
+#[derive(Debug, Default)]
+pub struct NoTouch;
+
+impl TouchInput for NoTouch {
+  fn read(&mut self) -> Option<Point> {
+    None
+  }
+
+  fn is_present(&self) -> bool {
+    false
+  }
+}
+
+/// What the LVGL pointer driver last saw.  LVGL wants to know where the touch ended when it's
+/// released, so the point sticks around after the finger goes away.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct PointerState {
+  pub point: Point,
+  pub pressed: bool,
+}
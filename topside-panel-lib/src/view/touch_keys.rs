@@ -0,0 +1,90 @@
+//! On-screen buttons for panels with a [crate::view::touch_input::TouchInput], sending the same
+//! [KeyEvent]s as the physical keys.  LVGL calls back from inside `task_handler` on the UI
+//! thread, so presses are queued here for [crate::view::ui_handler::UiHandler] to pick up along
+//! with the rest of the input.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use cstr_core::CString;
+use lvgl::{Align, Event, LvResult, NativeObject, Widget};
+use lvgl::widgets::{Btn, Label};
+use crate::model::key_event::{Key, KeyEvent};
+
+/// LVGL's built in symbols, which every Montserrat size includes.
+pub(crate) const SYMBOL_OK: &str = "\u{f00c}";
+pub(crate) const SYMBOL_SETTINGS: &str = "\u{f013}";
+pub(crate) const SYMBOL_LEFT: &str = "\u{f053}";
+pub(crate) const SYMBOL_PLUS: &str = "\u{f067}";
+pub(crate) const SYMBOL_MINUS: &str = "\u{f068}";
+pub(crate) const SYMBOL_UP: &str = "\u{f077}";
+pub(crate) const SYMBOL_DOWN: &str = "\u{f078}";
+
+const BUTTON_WIDTH: i16 = 56;
+const BUTTON_HEIGHT: i16 = 44;
+
+thread_local! {
+  static ENABLED: Cell<bool> = Cell::new(false);
+  static PENDING: RefCell<VecDeque<KeyEvent>> = RefCell::new(VecDeque::new());
+}
+
+/// Whether screens created from now on get buttons at all, as they'd only be in the way without
+/// a touch panel.
+pub(crate) fn set_enabled(enabled: bool) {
+  ENABLED.with(|e| e.set(enabled));
+}
+
+/// Everything pressed or released since the last call.
+pub(crate) fn take_events() -> Vec<KeyEvent> {
+  PENDING.with(|pending| pending.borrow_mut().drain(..).collect())
+}
+
+fn push_event(event: KeyEvent) {
+  PENDING.with(|pending| pending.borrow_mut().push_back(event));
+}
+
+/// A screen's buttons, each holding its key down for as long as it's pressed so that holds and
+/// repeats work just like on the membrane.  Empty if touch isn't [set_enabled].
+#[derive(Default)]
+pub(crate) struct TouchKeys {
+  buttons: Vec<(Key, Btn, Label)>,
+}
+
+impl TouchKeys {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn add<C: NativeObject>(
+      &mut self,
+      parent: &mut C,
+      key: Key,
+      text: &str,
+      align: Align,
+      x_mod: i16,
+      y_mod: i16,
+  ) -> LvResult<()> {
+    if !ENABLED.with(|e| e.get()) {
+      return Ok(());
+    }
+    let mut button = Btn::new(parent)?;
+    button.set_size(BUTTON_WIDTH, BUTTON_HEIGHT)?;
+    button.set_align(parent, align, x_mod, y_mod)?;
+    button.on_event(move |_, event| match event {
+      Event::Pressed => push_event(KeyEvent::KeyDown { key }),
+      Event::Released | Event::PressLost => push_event(KeyEvent::KeyUp { key }),
+      _ => {}
+    })?;
+    let mut label = Label::new(&mut button)?;
+    label.set_text(CString::new(text).unwrap().as_c_str())?;
+    self.buttons.push((key, button, label));
+    Ok(())
+  }
+
+  /// Relabel the button for `key`, e.g. when the language changes.
+  pub fn set_text(&mut self, key: Key, text: &str) -> LvResult<()> {
+    for (_, _, label) in self.buttons.iter_mut().filter(|(k, _, _)| *k == key) {
+      label.set_text(CString::new(text).unwrap().as_c_str())?;
+    }
+    Ok(())
+  }
+}
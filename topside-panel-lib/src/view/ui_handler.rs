@@ -1,8 +1,12 @@
 use std::borrow::Borrow;
+use std::cell::Cell;
+use std::rc::Rc;
 use embedded_graphics::draw_target::DrawTarget;
 use lvgl::{Align, Color, LvResult, Part, State, UI, Widget};
+use lvgl::input_device::{InputData, Pointer};
 use lvgl::style::Style;
 use lvgl::widgets::{Arc, Label};
+use std::sync::mpsc::RecvTimeoutError;
 use std::time::{Duration, Instant};
 use std::thread;
 use cstr_core::{CStr, CString};
@@ -15,8 +19,13 @@ use crate::view::lcd_device::{LcdDevice};
 use crate::view::user_input_event::UserInputEvent;
 use crate::view::window_proxy::WindowProxy;
 use crate::model::view_model::ViewModel;
-use crate::view::backlight_manager::BacklightManager;
+use crate::view::backlight_manager::{BacklightManager, DEFAULT_IDLE_TIMEOUT};
+use crate::view::feedback_device::{FeedbackDevice, FeedbackTracker, NoFeedback};
+use crate::model::key_event::KeyEvent;
 use crate::view::screen_flipper::{ScreenFlipper, ScreenOptions};
+use crate::view::toast_widget::ToastWidget;
+use crate::view::touch_input::{PointerState, TouchInput};
+use crate::view::touch_keys;
 
 /// Approximate time between each frame draw.
 const TARGET_DRAW_INTERVAL: Duration = Duration::from_millis(20);
@@ -25,6 +34,8 @@ pub struct UiHandler<DEV> {
   lcd_device: DEV,
   control_handle: ControlHandle,
   app_events: ViewModelEventHandle<ViewModel>,
+  idle_timeout: Duration,
+  feedback_device: Box<dyn FeedbackDevice + Send>,
 }
 
 pub trait UiDelayMs {
@@ -47,31 +58,69 @@ where
       lcd_device: lcd_panel,
       control_handle,
       app_events,
+      idle_timeout: DEFAULT_IDLE_TIMEOUT,
+      feedback_device: Box::new(NoFeedback),
     }
   }
 
+  /// How long without a key press before dimming to the screensaver.
+  pub fn set_idle_timeout(mut self, timeout: Duration) -> Self {
+    self.idle_timeout = timeout;
+    self
+  }
+
+  /// Beeper or similar to confirm key presses and call attention to faults.
+  pub fn set_feedback_device(mut self, device: Box<dyn FeedbackDevice + Send>) -> Self {
+    self.feedback_device = device;
+    self
+  }
+
   pub fn run_loop<DELAY: UiDelayMs>(mut self, mut delay: DELAY) -> LvResult<()> {
     info!("Setting up display...");
-    let (display, mut window, backlight) =
+    let (display, mut window, backlight, mut touch) =
         self.lcd_device.setup();
 
     info!("Initializing lvgl display driver...");
     let mut ui = UI::init()?;
     ui.disp_drv_register(display)?;
 
-    let mut screen_flipper = ScreenFlipper::new();
+    // LVGL reads the pointer on its own schedule from inside task_handler, so it just gets the
+    // latest state from our polling below.
+    let pointer_state = Rc::new(Cell::new(PointerState::default()));
+    let mut pointer = {
+      let pointer_state = pointer_state.clone();
+      Pointer::new(move || {
+        let state = pointer_state.get();
+        let data = InputData::Touch(state.point);
+        if state.pressed {
+          data.pressed().once()
+        } else {
+          data.released().once()
+        }
+      })
+    };
+    ui.indev_drv_register(&mut pointer)?;
+    // Before any screen exists, as they only add their buttons at creation.
+    touch_keys::set_enabled(touch.is_present());
 
-    let event_update_interval_ms = {
-      let update_interval = window.event_update_interval();
-      assert!(update_interval <= TARGET_DRAW_INTERVAL);
+    let mut screen_flipper = ScreenFlipper::new();
+    let mut toast = ToastWidget::new()?;
 
-      u32::try_from(update_interval.as_millis()).unwrap()
-    };
+    let event_update_interval = window.event_update_interval();
+    assert!(event_update_interval <= TARGET_DRAW_INTERVAL);
+    let event_update_interval_ms = u32::try_from(event_update_interval.as_millis()).unwrap();
 
     info!("Starting UI event loop...");
     let mut last_tick = Instant::now();
-    let mut backlight_manager = BacklightManager::init(backlight);
+    let mut backlight_manager = BacklightManager::init(backlight, self.idle_timeout);
     let mut current_options = None::<ScreenOptions>;
+    let mut pending_model = None::<ViewModel>;
+    let mut last_model = None::<ViewModel>;
+    let mut showing_screensaver = false;
+    let mut swallowing_wake_key = false;
+    let mut feedback_tracker = FeedbackTracker::default();
+    let mut touching = false;
+    let mut swallowing_wake_touch = false;
     loop {
       ui.task_handler();
 
@@ -89,20 +138,73 @@ where
               return Ok(());
             }
             UserInputEvent::KeyEvent(b) => {
-              self.control_handle.send_key_event(b);
-              backlight_manager.mark_user_activity(Instant::now());
+              let woke = backlight_manager.mark_user_activity(Instant::now());
+              if woke || swallowing_wake_key {
+                // The press that wakes the panel up shouldn't also change anything.
+                swallowing_wake_key = matches!(b, KeyEvent::KeyDown { .. });
+              } else {
+                self.control_handle.send_key_event(b);
+                if let Some(feedback) = feedback_tracker.on_key_event(b, last_model.as_ref()) {
+                  self.feedback_device.play(feedback);
+                }
+              }
             }
           }
         }
 
-        delay.delay_ms(event_update_interval_ms);
+        let touch_point = touch.read();
+        if let Some(point) = touch_point {
+          let woke = backlight_manager.mark_user_activity(Instant::now());
+          if !touching {
+            // Same as keys, a tap to wake shouldn't land on whatever is under it.
+            swallowing_wake_touch = woke;
+          }
+          if !swallowing_wake_touch {
+            pointer_state.set(PointerState { point, pressed: true });
+          }
+        } else {
+          swallowing_wake_touch = false;
+          pointer_state.set(PointerState { pressed: false, ..pointer_state.get() });
+        }
+        touching = touch_point.is_some();
+
+        // The wake-up tap was already kept from LVGL above, so these are all meant.
+        for b in touch_keys::take_events() {
+          self.control_handle.send_key_event(b);
+          if let Some(feedback) = feedback_tracker.on_key_event(b, last_model.as_ref()) {
+            self.feedback_device.play(feedback);
+          }
+        }
+
+        // Sleep on the model channel instead of a plain delay so that updates are drawn as soon
+        // as they arrive.  Once the network side goes away there's nothing to wait on though.
+        match self.app_events.recv_latest_timeout(event_update_interval) {
+          Ok(model) => {
+            pending_model = Some(model);
+            break 'event_handler;
+          }
+          Err(RecvTimeoutError::Timeout) => {}
+          Err(RecvTimeoutError::Disconnected) => delay.delay_ms(event_update_interval_ms),
+        }
 
         if last_tick.elapsed() >= TARGET_DRAW_INTERVAL {
           break 'event_handler;
         }
       }
 
-      if let Some(model) = self.app_events.try_recv_latest().unwrap() {
+      let idle = backlight_manager.is_idle();
+      if idle != showing_screensaver {
+        showing_screensaver = idle;
+        pending_model = pending_model.or_else(|| last_model.clone());
+      }
+
+      if let Some(model) = pending_model.take() {
+        if let Some(feedback) = feedback_tracker.on_model(&model) {
+          self.feedback_device.play(feedback);
+        }
+        last_model = Some(model.clone());
+        let model = ViewModel { screensaver: showing_screensaver, ..model };
+        toast.bind_model(&model)?;
         if let Some(new_options) = screen_flipper.bind_model(model)? {
           current_options = Some(new_options);
         }
@@ -0,0 +1,174 @@
+use cstr_core::CString;
+use lvgl::{Align, LvResult, Obj, Part, State, Widget};
+use lvgl::style::Style;
+use lvgl::widgets::Label;
+use wifi_module_lib::view_model::{ConnectionState, Mode};
+use crate::model::language::Language;
+use crate::model::theme::Theme;
+use crate::model::view_model::ViewModel;
+use crate::view::color_util::hex_color;
+use crate::view::font::Font;
+use crate::view::lvgl_ext::{label_set_long_mode, LabelLongMode, obj_set_auto_realign, style_set_text_font};
+use crate::view::main_screen::LABEL_PRIMARY_COLOR;
+use crate::view::palette_styles::ThemeStyles;
+use crate::view::screen_flipper::{BoxedScreen, Screen, ScreenSelector};
+use crate::view::strings::{format_text, text, Text};
+
+/// Most lines any [Mode] needs, see [WifiScreen::lines].
+const MAX_LINES: usize = 4;
+
+/// Provisioning is left to [crate::view::provisioning_screen::ProvisioningScreen], which takes
+/// over by itself, so this only needs to cover the other modes.
+pub struct WifiScreen {
+  screen: Obj,
+  styles: Styles,
+  title: Label,
+  rows: Vec<Label>,
+  current_lines: Vec<String>,
+  language: Option<Language>,
+  theme: Option<Theme>,
+}
+
+struct Styles {
+  themes: ThemeStyles,
+}
+
+impl Styles {
+  pub fn new() -> Self {
+    Self {
+      themes: ThemeStyles::new(),
+    }
+  }
+}
+
+impl WifiScreen {
+  pub fn new() -> LvResult<Self> {
+    let mut screen = Obj::default();
+    let styles = Styles::new();
+
+    let mut title_style = Style::default();
+    title_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut title_style, State::DEFAULT, Font::MONTSERRAT_32);
+    let mut title = Label::new(&mut screen)?;
+    title.add_style(Part::Main, title_style.clone())?;
+    title.set_align(&mut screen, Align::InTopMid, 0, 16)?;
+    obj_set_auto_realign(&mut title, true)?;
+
+    let mut row_style = Style::default();
+    row_style.set_text_color(State::DEFAULT, hex_color(LABEL_PRIMARY_COLOR));
+    style_set_text_font(&mut row_style, State::DEFAULT, Font::MONTSERRAT_24);
+
+    let mut rows: Vec<Label> = Vec::with_capacity(MAX_LINES);
+    for _ in 0..MAX_LINES {
+      let mut row = Label::new(&mut screen)?;
+      row.add_style(Part::Main, row_style.clone())?;
+      // Error details can run long, wrap them rather than run off the screen.
+      label_set_long_mode(&mut row, LabelLongMode::Break)?;
+      row.set_width(440)?;
+      match rows.last_mut() {
+        None => row.set_align(&mut title, Align::OutBottomMid, 0, 16)?,
+        Some(above) => row.set_align(above, Align::OutBottomLeft, 0, 8)?,
+      }
+      obj_set_auto_realign(&mut row, true)?;
+      row.set_text(CString::new("").unwrap().as_c_str())?;
+      rows.push(row);
+    }
+
+    Ok(Self {
+      screen,
+      styles,
+      title,
+      rows,
+      current_lines: vec![String::new(); MAX_LINES],
+      language: None,
+      theme: None,
+    })
+  }
+
+  fn lines(mode: &Mode, language: Language) -> Vec<String> {
+    match mode {
+      Mode::Initializing => vec![text(language, Text::WifiStartingUp).to_owned()],
+      Mode::UnrecoverableError(e) => vec![
+        text(language, Text::WifiFailedRecover).to_owned(),
+        e.clone(),
+      ],
+      Mode::NeedsProvisioning(_) => vec![text(language, Text::WifiNotSetUp).to_owned()],
+      Mode::TroubleAssociating(trouble) => vec![
+        text(language, Text::WifiCantConnectCheck).to_owned(),
+        trouble.error.to_string(),
+      ],
+      Mode::Nominal(nominal) => {
+        let mut lines = vec![
+          summary(mode, language).to_owned(),
+          format_text(language, Text::WifiNetwork, &[&nominal.network_name]),
+        ];
+        if let Some(info) = &nominal.connection_info {
+          lines.push(format_text(language, Text::WifiSignal, &[&info.rssi]));
+          if let Some(ip) = info.ip {
+            lines.push(format_text(language, Text::WifiAddress, &[&ip]));
+          }
+        }
+        lines
+      }
+    }
+  }
+}
+
+/// One word or so on how Wi-Fi is doing, for places with no room for [WifiScreen].
+pub(crate) fn summary(mode: &Mode, language: Language) -> &'static str {
+  let summary = match mode {
+    Mode::Initializing => Text::WifiStarting,
+    Mode::UnrecoverableError(_) => Text::WifiFailed,
+    Mode::NeedsProvisioning(_) => Text::WifiNotSetUp,
+    Mode::TroubleAssociating(_) => Text::WifiCantConnect,
+    Mode::Nominal(nominal) => match nominal.connection_state {
+      ConnectionState::NotAssociated => Text::WifiNotConnected,
+      ConnectionState::Associating => Text::WifiConnecting,
+      ConnectionState::Associated => Text::WifiGettingAddress,
+      ConnectionState::Connected => Text::WifiConnected,
+    },
+  };
+  text(language, summary)
+}
+
+impl ScreenSelector for WifiScreen {
+  fn kind() -> &'static str {
+    "wifi"
+  }
+
+  fn create() -> LvResult<BoxedScreen> {
+    Ok(Box::new(WifiScreen::new()?))
+  }
+
+  fn accept_model(model: &ViewModel) -> bool {
+    model.wifi_screen_open && model.wifi_model.is_some()
+  }
+}
+
+impl Screen for WifiScreen {
+  fn get_root(&self) -> &Obj {
+    &self.screen
+  }
+
+  fn bind_model(&mut self, model: ViewModel) -> LvResult<()> {
+    let theme = model.settings.theme;
+    if self.theme != Some(theme) {
+      self.screen.add_style(Part::Main, self.styles.themes.normal(theme).window_bg.clone())?;
+      self.theme = Some(theme);
+    }
+    let language = model.settings.language;
+    if self.language != Some(language) {
+      self.title.set_text(CString::new(text(language, Text::Wifi)).unwrap().as_c_str())?;
+      self.language = Some(language);
+    }
+    let lines = WifiScreen::lines(&model.wifi_model.unwrap().mode, language);
+    for (i, row) in self.rows.iter_mut().enumerate() {
+      let line = lines.get(i).cloned().unwrap_or_default();
+      if self.current_lines[i] != line {
+        row.set_text(CString::new(line.as_str()).unwrap().as_c_str())?;
+        self.current_lines[i] = line;
+      }
+    }
+    Ok(())
+  }
+}
@@ -4,6 +4,9 @@ use std::fmt::Write;
 pub struct Advertisement {
   pub name: String,
   pub mac: [u8; 6],
+
+  /// Resolvable as `<hostname>.local` via mDNS.  Derived from the name unless set explicitly.
+  pub hostname: String,
   pub(crate) payload: Vec<u8>,
 }
 
@@ -16,19 +19,51 @@ impl Advertisement {
   }
 
   pub fn new(name: String, mac: [u8; 6]) -> Self {
-    let mac_str = mac.iter().fold(String::new(), |mut out, b| {
-      if !out.is_empty() {
-        out.push('-');
-      }
-      write!(out, "{:02X}", b).unwrap();
-      out
-    });
-
+    let mac_str = format_mac(&mac);
     let payload = format!("{name}\r\n{mac_str}\r\n").as_bytes().to_vec();
+    let hostname = derive_hostname(&name);
     Self {
       name,
       mac,
+      hostname,
       payload
     }
   }
+
+  pub fn set_hostname(mut self, hostname: impl Into<String>) -> Self {
+    self.hostname = hostname.into();
+    self
+  }
+
+  /// Advertise under a different name, e.g. to tell two modules on one network apart.  The
+  /// hostname follows along unless it was set explicitly.
+  pub fn set_name(self, name: impl Into<String>) -> Self {
+    let renamed = Self::new(name.into(), self.mac);
+    if self.hostname == derive_hostname(&self.name) {
+      renamed
+    } else {
+      renamed.set_hostname(self.hostname)
+    }
+  }
+
+  /// MAC formatted the way the official module does, e.g. `00-15-27-01-02-03`.
+  pub fn mac_string(&self) -> String {
+    format_mac(&self.mac)
+  }
+}
+
+fn derive_hostname(name: &str) -> String {
+  name.chars()
+      .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+      .collect()
+}
+
+fn format_mac(mac: &[u8; 6]) -> String {
+  mac.iter().fold(String::new(), |mut out, b| {
+    if !out.is_empty() {
+      out.push('-');
+    }
+    write!(out, "{:02X}", b).unwrap();
+    out
+  })
 }
@@ -0,0 +1,335 @@
+//! Tells somebody when the spa needs attention: a new entry in the main board's fault log, the
+//! heater running without getting anywhere, or the RS485 link going quiet altogether.
+//! [crate::frost] raises its own through the same channels.
+//!
+//! Alerts go to the log, to MQTT as `<base topic>/alert` and, if configured, as a JSON POST to
+//! a webhook (plain HTTP only, e.g. a Home Assistant or ntfy instance on the LAN).  Each
+//! condition is reported once when it starts, and for the link also when it recovers.
+
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+use balboa_spa_messages::message_types::{FaultResponseMessage, HeatingMode, MessageType, StatusUpdateResponseV1};
+use balboa_spa_messages::temperature::{ProtocolTemperature, TemperatureScale};
+
+/// Long enough that a big temperature change on a cold day doesn't count.
+pub const DEFAULT_HEATING_STALL: Duration = Duration::from_secs(60 * 60);
+
+/// The board sends status updates several times a second, so this is a dead link, not a hiccup.
+pub const DEFAULT_BUS_SILENCE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct AlertConfig {
+  pub(crate) webhook_url: Option<String>,
+  pub(crate) heating_stall: Option<Duration>,
+  pub(crate) bus_silence: Option<Duration>,
+}
+
+impl Default for AlertConfig {
+  fn default() -> Self {
+    Self {
+      webhook_url: None,
+      heating_stall: Some(DEFAULT_HEATING_STALL),
+      bus_silence: Some(DEFAULT_BUS_SILENCE),
+    }
+  }
+}
+
+impl AlertConfig {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Where to POST alerts, e.g. `http://homeassistant.local:8123/api/webhook/spa`.  Requires
+  /// the `http` feature.
+  pub fn set_webhook_url(mut self, url: &str) -> Self {
+    self.webhook_url = Some(url.to_owned());
+    self
+  }
+
+  /// How long the water may stay below the set point without warming at all, or None to never
+  /// complain about it.
+  pub fn set_heating_stall(mut self, after: Option<Duration>) -> Self {
+    self.heating_stall = after;
+    self
+  }
+
+  /// How long without hearing from the main board before alerting, or None to never.
+  pub fn set_bus_silence(mut self, after: Option<Duration>) -> Self {
+    self.bus_silence = after;
+    self
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Alert {
+  Fault { code: u8, description: String },
+  HeatingStalled { current: f64, set: f64, scale: TemperatureScale },
+  BusSilent { secs: u64 },
+  BusRestored,
+  Frost { current: f64, scale: TemperatureScale },
+}
+
+impl Alert {
+  #[cfg(any(feature = "http", feature = "mqtt"))]
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Alert::Fault { .. } => "fault",
+      Alert::HeatingStalled { .. } => "heating_stalled",
+      Alert::BusSilent { .. } => "bus_silent",
+      Alert::BusRestored => "bus_restored",
+      Alert::Frost { .. } => "frost",
+    }
+  }
+
+  /// `{"alert": <kind>, "message": <human readable>, ...details}`.
+  #[cfg(any(feature = "http", feature = "mqtt"))]
+  pub fn to_json(&self) -> serde_json::Value {
+    let mut json = serde_json::json!({
+      "alert": self.kind(),
+      "message": self.to_string(),
+    });
+    match self {
+      Alert::Fault { code, .. } => json["code"] = (*code).into(),
+      Alert::HeatingStalled { current, set, .. } => {
+        json["current_temperature"] = (*current).into();
+        json["set_temperature"] = (*set).into();
+      }
+      Alert::BusSilent { secs } => json["silent_secs"] = (*secs).into(),
+      Alert::BusRestored => {}
+      Alert::Frost { current, .. } => json["current_temperature"] = (*current).into(),
+    }
+    json
+  }
+}
+
+impl Display for Alert {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Alert::Fault { description, .. } => write!(f, "Spa fault: {description}"),
+      Alert::HeatingStalled { current, set, scale } => {
+        let unit = unit(scale);
+        write!(f, "Heating stalled at {current}{unit}, set to {set}{unit}")
+      }
+      Alert::BusSilent { secs } => write!(f, "No word from the spa's main board for {secs}s"),
+      Alert::BusRestored => write!(f, "Main board is talking again"),
+      Alert::Frost { current, scale } =>
+        write!(f, "Water down to {current}{}, turning on heat and circulation", unit(scale)),
+    }
+  }
+}
+
+fn unit(scale: &TemperatureScale) -> &'static str {
+  match scale {
+    TemperatureScale::Fahrenheit => "F",
+    TemperatureScale::Celsius => "C",
+  }
+}
+
+/// Watches bus traffic for the conditions above.
+#[derive(Debug)]
+pub(crate) struct AlertMonitor {
+  heating_stall: Option<Duration>,
+  bus_silence: Option<Duration>,
+  last_message: Instant,
+  bus_silent: bool,
+
+  /// None until we've seen the log once, so the faults from before we started aren't news.
+  newest_fault: Option<Option<FaultKey>>,
+  stall: Option<StallWatch>,
+}
+
+/// What identifies a fault log entry across days, `days_ago` aside.
+type FaultKey = (u8, u16, u8);
+
+#[derive(Debug)]
+struct StallWatch {
+  /// When the temperature last rose, or we started watching.
+  since: Instant,
+  warmest: f64,
+  reported: bool,
+}
+
+impl AlertMonitor {
+  pub fn new(config: &AlertConfig) -> Self {
+    Self {
+      heating_stall: config.heating_stall,
+      bus_silence: config.bus_silence,
+      last_message: Instant::now(),
+      bus_silent: false,
+      newest_fault: None,
+      stall: None,
+    }
+  }
+
+  /// Call for every message from the main board.
+  pub fn on_message(&mut self, mt: &MessageType, now: Instant) -> Option<Alert> {
+    self.last_message = now;
+    if self.bus_silent {
+      self.bus_silent = false;
+      return Some(Alert::BusRestored);
+    }
+    match mt {
+      MessageType::FaultLogResponse(fault) => self.on_fault(fault),
+      MessageType::StatusUpdate(status) => self.on_status(&status.v1, now),
+      _ => None,
+    }
+  }
+
+  /// Call regularly, even when nothing is arriving.
+  pub fn poll(&mut self, now: Instant) -> Option<Alert> {
+    let silence = self.bus_silence?;
+    let silent_for = now.saturating_duration_since(self.last_message);
+    if self.bus_silent || silent_for < silence {
+      return None;
+    }
+    self.bus_silent = true;
+    Some(Alert::BusSilent { secs: silent_for.as_secs() })
+  }
+
+  fn on_fault(&mut self, fault: &FaultResponseMessage) -> Option<Alert> {
+    let newest = match fault.total_entries {
+      0 => None,
+      total if fault.entry_number + 1 == total => Some((
+          fault.fault_code.as_raw(),
+          fault.time.as_raw(),
+          fault.set_temperature)),
+      _ => return None,
+    };
+    let previous = self.newest_fault.replace(newest);
+    if previous.is_none() || previous == Some(newest) {
+      return None;
+    }
+    let description = match fault.fault_code.as_ref() {
+      Some(code) => code.to_string(),
+      None => format!("Unknown fault {}", fault.fault_code.as_raw()),
+    };
+    Some(Alert::Fault { code: fault.fault_code.as_raw(), description })
+  }
+
+  fn on_status(&mut self, status: &StatusUpdateResponseV1, now: Instant) -> Option<Alert> {
+    let stall_after = self.heating_stall?;
+    // In rest mode the heater only runs during filter cycles, so falling behind is expected.
+    let ready = matches!(status.heating_mode.as_ref(), Some(HeatingMode::Ready));
+    let current = status.current_temperature.as_ref().map(degrees);
+    let set = degrees(&status.set_temperature);
+    let Some(current) = current.filter(|current| ready && *current < set) else {
+      self.stall = None;
+      return None;
+    };
+    let watch = self.stall.get_or_insert(StallWatch { since: now, warmest: current, reported: false });
+    if current > watch.warmest {
+      watch.since = now;
+      watch.warmest = current;
+      watch.reported = false;
+    }
+    if watch.reported || now < watch.since + stall_after {
+      return None;
+    }
+    watch.reported = true;
+    Some(Alert::HeatingStalled { current, set, scale: status.set_temperature.raw_scale })
+  }
+}
+
+/// Straight from the raw value, which is exact, unlike converting back from
+/// [ProtocolTemperature::temperature].
+fn degrees(temperature: &ProtocolTemperature) -> f64 {
+  let raw = f64::from(temperature.raw_value());
+  match temperature.raw_scale {
+    TemperatureScale::Fahrenheit => raw,
+    TemperatureScale::Celsius => raw / 2.0,
+  }
+}
+
+/// POSTs alerts to a webhook from its own thread, so a slow or missing server never holds up
+/// the bus.
+#[cfg(feature = "http")]
+pub(crate) struct WebhookSender {
+  pub url: String,
+  pub alerts_rx: std::sync::mpsc::Receiver<Alert>,
+}
+
+#[cfg(feature = "http")]
+impl WebhookSender {
+  pub fn run_loop(self) {
+    while let Ok(alert) = self.alerts_rx.recv() {
+      if let Err(e) = post_json(&self.url, &alert.to_json()) {
+        log::warn!("Webhook for {} alert failed: {e}", alert.kind());
+      }
+    }
+  }
+}
+
+#[cfg(feature = "http")]
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(feature = "http")]
+fn post_json(url: &str, body: &serde_json::Value) -> anyhow::Result<()> {
+  use std::io::{BufRead, BufReader, Write};
+  use std::net::TcpStream;
+
+  let (host, port, path) = crate::ota::parse_http_url(url)?;
+  let stream = TcpStream::connect((host.as_str(), port))?;
+  stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+  let body = body.to_string();
+  write!(
+    &stream,
+    "POST {path} HTTP/1.0\r\nHost: {host}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+    body.len())?;
+  let mut status_line = String::new();
+  BufReader::new(&stream).read_line(&mut status_line)?;
+  match status_line.split_whitespace().nth(1) {
+    Some(status) if status.starts_with('2') => Ok(()),
+    _ => Err(anyhow::anyhow!("Unexpected response {:?}", status_line.trim_end())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::message_types::{HeatingMode, StatusUpdateMessage};
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use super::*;
+
+  fn status(current: u8, set: u8) -> MessageType {
+    let mut status = StatusUpdateMessage::try_from([0u8; 24].as_slice()).unwrap();
+    let scale = status.v1.set_temperature.raw_scale;
+    status.v1.heating_mode = ParsedEnum::new(HeatingMode::Ready);
+    status.v1.current_temperature = Some(scale.new_protocol_temperature_from_raw(current));
+    status.v1.set_temperature = scale.new_protocol_temperature_from_raw(set);
+    MessageType::StatusUpdate(status)
+  }
+
+  fn fault(entry_number: u8, total_entries: u8, code: u8) -> MessageType {
+    let payload = [total_entries, entry_number, code, 0, 12, 30, 0, 100, 0, 0];
+    MessageType::FaultLogResponse(FaultResponseMessage::try_from(payload.as_slice()).unwrap())
+  }
+
+  #[test]
+  fn test_heating_stall() {
+    let mut monitor = AlertMonitor::new(&AlertConfig::new());
+    let now = Instant::now();
+    assert_eq!(monitor.on_message(&status(90, 100), now), None);
+    assert_eq!(monitor.on_message(&status(91, 100), now + DEFAULT_HEATING_STALL / 2), None);
+    assert_eq!(monitor.on_message(&status(91, 100), now + DEFAULT_HEATING_STALL), None, "Still warming");
+
+    let later = now + DEFAULT_HEATING_STALL * 3 / 2;
+    assert!(matches!(
+        monitor.on_message(&status(91, 100), later),
+        Some(Alert::HeatingStalled { current, set, .. }) if current == 91.0 && set == 100.0));
+    assert_eq!(monitor.on_message(&status(91, 100), later), None, "Only reported once");
+  }
+
+  #[test]
+  fn test_faults_and_silence() {
+    let mut monitor = AlertMonitor::new(&AlertConfig::new());
+    let now = Instant::now();
+    assert_eq!(monitor.on_message(&fault(1, 2, 16), now), None, "Old news");
+    assert_eq!(monitor.on_message(&fault(0, 2, 17), now), None);
+    assert_eq!(monitor.on_message(&fault(1, 2, 16), now), None);
+    assert!(matches!(monitor.on_message(&fault(2, 3, 19), now), Some(Alert::Fault { code: 19, .. })));
+
+    assert_eq!(monitor.poll(now + DEFAULT_BUS_SILENCE / 2), None);
+    assert_eq!(monitor.poll(now + DEFAULT_BUS_SILENCE), Some(Alert::BusSilent { secs: DEFAULT_BUS_SILENCE.as_secs() }));
+    assert_eq!(monitor.poll(now + DEFAULT_BUS_SILENCE * 2), None);
+    assert_eq!(monitor.on_message(&fault(2, 3, 19), now + DEFAULT_BUS_SILENCE * 2), Some(Alert::BusRestored));
+  }
+}
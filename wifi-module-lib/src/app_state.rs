@@ -1,4 +1,5 @@
 use common_lib::channel_filter::ChannelFilter;
+use common_lib::cts_metrics::CtsMetrics;
 use common_lib::cts_state_machine::CtsStateMachine;
 use crate::advertisement::Advertisement;
 use crate::wifi_state_machine::{WifiStateMachine};
@@ -14,6 +15,7 @@ impl AppState {
   pub fn new(advertisement: Advertisement) -> Self {
     let mut wifi_state_machine = WifiStateMachine::default();
     wifi_state_machine.set_channel_filter(ChannelFilter::BlockEverything);
+    wifi_state_machine.set_cts_metrics(CtsMetrics::new("wifi_module"));
     Self {
       cts_state_machine: CtsStateMachine::default(),
       wifi_state_machine,
@@ -0,0 +1,188 @@
+//! Fallback provisioning for phones without Wi-Fi Easy Connect: once the [crate::wifi_manager]
+//! implementation has brought up an open access point, this serves a single page form asking
+//! for the network's SSID and password.
+//!
+//! Every DNS lookup on the access point resolves to us and every page is the form, so phones
+//! notice the "captive portal" and open it by themselves soon after joining.
+
+use std::io;
+use std::io::{BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use log::{debug, info, warn};
+use crate::http_request::HttpRequest;
+use crate::mdns::answer_everything;
+
+const HTTP_PORT: u16 = 80;
+const DNS_PORT: u16 = 53;
+
+/// Limits from the 802.11 spec, also what fits in typical driver configuration structs.
+const MAX_SSID_LEN: usize = 32;
+const MAX_PASSWORD_LEN: usize = 64;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the DNS thread checks whether it's still needed.
+const DNS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const FORM_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta name="viewport" content="width=device-width, initial-scale=1"><title>Spa Wi-Fi setup</title></head>
+<body><h2>Connect your spa to Wi-Fi</h2>
+<form method="post" action="/connect">
+<p><label>Network name<br><input name="ssid" required maxlength="32"></label></p>
+<p><label>Password<br><input name="password" type="password" maxlength="64"></label></p>
+<p><button type="submit">Connect</button></p>
+</form></body></html>
+"#;
+
+const DONE_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta name="viewport" content="width=device-width, initial-scale=1"><title>Spa Wi-Fi setup</title></head>
+<body><h2>Connecting...</h2><p>This network will now go away.  Check the spa's display to see how it went.</p></body></html>
+"#;
+
+/// What the user typed into the form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortalCredentials {
+  pub ssid: String,
+  pub password: String,
+}
+
+pub struct CaptivePortal {
+  ap_addr: Ipv4Addr,
+  http: TcpListener,
+  dns: UdpSocket,
+}
+
+impl CaptivePortal {
+  /// `ap_addr` is our address on the access point, which all lookups will resolve to.
+  pub fn setup(ap_addr: Ipv4Addr) -> io::Result<Self> {
+    let http = TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, HTTP_PORT)))?;
+    let dns = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, DNS_PORT)))?;
+    dns.set_read_timeout(Some(DNS_POLL_INTERVAL))?;
+    Ok(Self { ap_addr, http, dns })
+  }
+
+  /// Serves the portal until somebody submits the form.
+  pub fn wait_for_credentials(self) -> io::Result<PortalCredentials> {
+    info!("Captive portal up at http://{}/", self.ap_addr);
+    let done = AtomicBool::new(false);
+    crossbeam::thread::scope(|s| {
+      s.builder()
+          .name("PortalDns".into())
+          .spawn(|_| run_dns(&self.dns, self.ap_addr, &done))
+          .unwrap();
+
+      let result = self.run_http();
+      done.store(true, Ordering::Relaxed);
+      result
+    }).unwrap()
+  }
+
+  fn run_http(&self) -> io::Result<PortalCredentials> {
+    loop {
+      let (stream, peer) = self.http.accept()?;
+      stream.set_read_timeout(Some(READ_TIMEOUT))?;
+      match handle_connection(&stream) {
+        Ok(Some(credentials)) => {
+          info!("Got credentials for {} from {peer}", credentials.ssid);
+          return Ok(credentials);
+        }
+        Ok(None) => {}
+        Err(e) => debug!("Portal request from {peer} failed: {e}"),
+      }
+    }
+  }
+}
+
+fn run_dns(socket: &UdpSocket, ap_addr: Ipv4Addr, done: &AtomicBool) {
+  let mut buf = [0u8; 512];
+  while !done.load(Ordering::Relaxed) {
+    let (n, peer) = match socket.recv_from(&mut buf) {
+      Ok(received) => received,
+      Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+      Err(e) => {
+        warn!("Portal DNS failed: {e}");
+        return;
+      }
+    };
+    if let Some(response) = answer_everything(&buf[..n], ap_addr) {
+      let _ = socket.send_to(&response, peer);
+    }
+  }
+}
+
+fn handle_connection(stream: &TcpStream) -> anyhow::Result<Option<PortalCredentials>> {
+  let request = HttpRequest::read_from(&mut BufReader::new(stream))?;
+  if request.method == "POST" && request.path == "/connect" {
+    if let Some(credentials) = parse_form(&request.body) {
+      write_page(stream, DONE_PAGE)?;
+      return Ok(Some(credentials));
+    }
+  }
+  write_page(stream, FORM_PAGE)?;
+  Ok(None)
+}
+
+fn write_page(mut stream: &TcpStream, page: &str) -> io::Result<()> {
+  write!(
+    stream,
+    "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{page}",
+    page.len())?;
+  stream.flush()
+}
+
+/// Decodes an `application/x-www-form-urlencoded` body, requiring a non-empty SSID and that
+/// both fields fit in the usual limits.
+fn parse_form(body: &[u8]) -> Option<PortalCredentials> {
+  let body = std::str::from_utf8(body).ok()?;
+  let mut ssid = None;
+  let mut password = String::new();
+  for pair in body.split('&') {
+    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+    match key {
+      "ssid" => ssid = Some(url_decode(value)?),
+      "password" => password = url_decode(value)?,
+      _ => {}
+    }
+  }
+  let ssid = ssid.filter(|s| !s.is_empty() && s.len() <= MAX_SSID_LEN)?;
+  if password.len() > MAX_PASSWORD_LEN {
+    return None;
+  }
+  Some(PortalCredentials { ssid, password })
+}
+
+fn url_decode(value: &str) -> Option<String> {
+  let mut out = Vec::with_capacity(value.len());
+  let mut bytes = value.bytes();
+  while let Some(b) = bytes.next() {
+    match b {
+      b'+' => out.push(b' '),
+      b'%' => {
+        let hex = [bytes.next()?, bytes.next()?];
+        out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+      }
+      b => out.push(b),
+    }
+  }
+  String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_form() {
+    assert_eq!(
+      parse_form(b"ssid=My+Network&password=p%40ss%26word"),
+      Some(PortalCredentials { ssid: "My Network".into(), password: "p@ss&word".into() }));
+    assert_eq!(
+      parse_form(b"ssid=open"),
+      Some(PortalCredentials { ssid: "open".into(), password: "".into() }));
+    assert_eq!(parse_form(b"ssid=&password=x"), None);
+    assert_eq!(parse_form(b"ssid=%zz"), None);
+    assert_eq!(parse_form(format!("ssid={}", "x".repeat(33)).as_bytes()), None);
+  }
+}
@@ -1,9 +1,13 @@
 use balboa_spa_messages::message::Message;
+use crate::relay_event::RelayClientId;
 
 #[derive(Debug)]
 pub(crate) enum Command {
   ReceivedMainboardMessage(Message),
   ReadError(anyhow::Error),
-  RelayIpMessage(Message),
+  RelayIpMessage(RelayClientId, Message),
+  PassthroughFrame(RelayClientId, Message),
+  Spa(Box<crate::spa_client::SpaRequest>),
+  SetSchedule(crate::schedule::Schedule),
   Shutdown,
 }
@@ -0,0 +1,179 @@
+//! Asks the main board for its configuration, preferences, filter cycles, temperature limits and
+//! system information when there's no topside panel around to do it.
+//!
+//! The board only sends these in answer to a request, and on a normal install the panel asks
+//! for them as it starts.  Headless installs have nobody asking, so IP clients (and our own
+//! caches) would never learn more than the status updates.  Once we have a channel we request
+//! each in turn and then refresh them periodically, skipping anything somebody else's request
+//! already got answered recently.  Hearing from a panel at all puts polling on hold.
+
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use balboa_spa_messages::channel::Channel;
+use balboa_spa_messages::message_types::{MessageType, SettingsRequestMessage};
+
+/// These change only when someone fiddles with the panel (or the app), so there's no need to
+/// ask often.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// A panel answers every ClearToSend, so this long without hearing one means it's gone.
+const PANEL_TIMEOUT: Duration = Duration::from_secs(30);
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Item {
+  Configuration,
+  Preferences,
+  FilterCycles,
+  TemperatureLimits,
+  Information,
+}
+
+const ITEMS: [Item; 5] = [
+  Item::Configuration,
+  Item::Preferences,
+  Item::FilterCycles,
+  Item::TemperatureLimits,
+  Item::Information,
+];
+
+impl Item {
+  fn answered_by(mt: &MessageType) -> Option<Self> {
+    match mt {
+      MessageType::ConfigurationResponse(_) => Some(Item::Configuration),
+      MessageType::PreferencesResponse(_) => Some(Item::Preferences),
+      MessageType::FilterCycles { .. } => Some(Item::FilterCycles),
+      MessageType::Settings0x04Response(_) => Some(Item::TemperatureLimits),
+      MessageType::InformationResponse(_) => Some(Item::Information),
+      _ => None,
+    }
+  }
+
+  fn request(self) -> MessageType {
+    MessageType::SettingsRequest(match self {
+      Item::Configuration => SettingsRequestMessage::Configuration,
+      Item::Preferences => SettingsRequestMessage::Preferences,
+      Item::FilterCycles => SettingsRequestMessage::FilterCycles,
+      Item::TemperatureLimits => SettingsRequestMessage::Settings0x04,
+      Item::Information => SettingsRequestMessage::Information,
+    })
+  }
+}
+
+/// Decides which settings request to send next, if any.  Like
+/// [crate::fault_log::FaultLogPoller] only one is outstanding at a time.
+#[derive(Debug)]
+pub(crate) struct ConfigPoller {
+  interval: Option<Duration>,
+  last_answered: [Option<Instant>; ITEMS.len()],
+  panel_seen: Option<Instant>,
+  in_flight: Option<(Item, Instant)>,
+}
+
+impl ConfigPoller {
+  /// Without an `interval` nothing is ever requested.
+  pub fn new(interval: Option<Duration>) -> Self {
+    Self { interval, last_answered: Default::default(), panel_seen: None, in_flight: None }
+  }
+
+  /// `our_channel` is the one we were assigned, if any, so as not to mistake our own messages
+  /// for a panel's.
+  pub fn on_message(
+      &mut self,
+      channel: &Channel,
+      mt: &MessageType,
+      our_channel: Option<&Channel>,
+      now: Instant,
+  ) {
+    if let Some(item) = Item::answered_by(mt) {
+      self.last_answered[item as usize] = Some(now);
+      if self.in_flight.is_some_and(|(requested, _)| requested == item) {
+        self.in_flight = None;
+      }
+      return;
+    }
+    // Only clients send NothingToSend, the board's traffic all looks alike.
+    let from_client = matches!(channel, Channel::Client(_) | Channel::ClientNoCTS(_));
+    if from_client && Some(channel) != our_channel && matches!(mt, MessageType::NothingToSend()) {
+      if self.panel_seen.is_none_or(|seen| now >= seen + PANEL_TIMEOUT) {
+        info!("Topside panel found on {channel:?}, leaving settings requests to it");
+      }
+      self.panel_seen = Some(now);
+    }
+  }
+
+  /// The request to send now, if any.  Only call once we have a channel to send on.
+  pub fn poll(&mut self, now: Instant) -> Option<MessageType> {
+    let interval = self.interval?;
+    if self.panel_seen.is_some_and(|seen| now < seen + PANEL_TIMEOUT) {
+      return None;
+    }
+    if let Some((item, sent)) = self.in_flight {
+      if now < sent + RESPONSE_TIMEOUT {
+        return None;
+      }
+      warn!("No answer to {item:?} request, trying again later");
+      self.in_flight = None;
+      // Pretend it was answered so we move on to the next item rather than hammer this one.
+      self.last_answered[item as usize] = Some(now);
+    }
+    let item = ITEMS.into_iter()
+        .find(|item| self.last_answered[*item as usize].is_none_or(|at| now >= at + interval))?;
+    debug!("Requesting {item:?}");
+    self.in_flight = Some((item, now));
+    Some(item.request())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn answer(poller: &mut ConfigPoller, request: Option<MessageType>, now: Instant) {
+    let mt = match request {
+      Some(MessageType::SettingsRequest(SettingsRequestMessage::Configuration)) =>
+        MessageType::ConfigurationResponse([0u8; 6].as_slice().try_into().unwrap()),
+      Some(MessageType::SettingsRequest(SettingsRequestMessage::Preferences)) =>
+        MessageType::PreferencesResponse([0u8; 32].as_slice().try_into().unwrap()),
+      Some(MessageType::SettingsRequest(SettingsRequestMessage::FilterCycles)) =>
+        MessageType::FilterCycles { cycles: vec![] },
+      Some(MessageType::SettingsRequest(SettingsRequestMessage::Settings0x04)) =>
+        MessageType::Settings0x04Response([0u8; 6].as_slice().try_into().unwrap()),
+      other => panic!("Unexpected {other:?}"),
+    };
+    poller.on_message(&Channel::Client(0x11), &mt, Some(&Channel::Client(0x11)), now);
+  }
+
+  #[test]
+  fn test_poll() {
+    let mut poller = ConfigPoller::new(Some(DEFAULT_REFRESH_INTERVAL));
+    let ours = Channel::Client(0x11);
+    let now = Instant::now();
+
+    let request = poller.poll(now);
+    assert!(matches!(request, Some(MessageType::SettingsRequest(SettingsRequestMessage::Configuration))));
+    assert!(poller.poll(now).is_none(), "Must wait for the answer");
+    answer(&mut poller, request, now);
+    let request = poller.poll(now);
+    assert!(matches!(request, Some(MessageType::SettingsRequest(SettingsRequestMessage::Preferences))));
+    answer(&mut poller, request, now);
+    let request = poller.poll(now);
+    assert!(matches!(request, Some(MessageType::SettingsRequest(SettingsRequestMessage::FilterCycles))));
+    answer(&mut poller, request, now);
+    let request = poller.poll(now);
+    assert!(matches!(request, Some(MessageType::SettingsRequest(SettingsRequestMessage::Settings0x04))));
+    answer(&mut poller, request, now);
+
+    // Information never comes, which mustn't hold up everything else.
+    assert!(poller.poll(now).is_some());
+    assert!(poller.poll(now + RESPONSE_TIMEOUT).is_none());
+
+    let later = now + DEFAULT_REFRESH_INTERVAL;
+    poller.on_message(&Channel::Client(0x10), &MessageType::NothingToSend(), Some(&ours), later);
+    assert!(poller.poll(later).is_none(), "A panel is attached");
+    poller.on_message(&ours, &MessageType::NothingToSend(), Some(&ours), later + PANEL_TIMEOUT);
+    let request = poller.poll(later + PANEL_TIMEOUT);
+    assert!(matches!(request, Some(MessageType::SettingsRequest(SettingsRequestMessage::Configuration))));
+  }
+}
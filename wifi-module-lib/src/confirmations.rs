@@ -0,0 +1,225 @@
+//! Checks that temperature changes actually took effect.  The protocol has no acknowledgements,
+//! and a request that misses its ClearToSend window or gets garbled on the bus simply vanishes,
+//! so instead we watch the status updates that follow.
+//!
+//! A change that isn't visible within [CONFIRM_TIMEOUT] is sent once more, and if that doesn't
+//! do it either, it's reported as failed to whoever is listening (WebSocket clients, MQTT and
+//! [crate::spa_client::PendingCommand]).  This applies whoever sent it, relay clients included.
+//!
+//! Only commands that are safe to repeat are tracked.  Toggles are not: if the spa acted on the
+//! first attempt but we missed it (or its status lags), a retry would switch the item straight
+//! back.
+
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use log::{debug, info};
+use balboa_spa_messages::message_types::{MessageType, StatusUpdateResponseV1};
+use common_lib::metrics::{Counter, Metrics};
+use crate::outbound_queue::MessageSource;
+use crate::spa_client::SpaClientError;
+
+/// Per attempt.  Status updates come several times a second, so this allows for a busy
+/// outbound queue.
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MAX_ATTEMPTS: u32 = 2;
+
+pub(crate) type DoneSender = Sender<Result<(), SpaClientError>>;
+
+/// Commands waiting on a status update to show they worked.
+#[derive(Debug)]
+pub(crate) struct Confirmations {
+  pending: Vec<PendingConfirmation>,
+  confirmed: Counter,
+  retried: Counter,
+  failed: Counter,
+}
+
+#[derive(Debug)]
+struct PendingConfirmation {
+  source: MessageSource,
+  mt: MessageType,
+  expected: Expected,
+  attempts: u32,
+  deadline: Instant,
+  done: Option<DoneSender>,
+}
+
+#[derive(Debug)]
+enum Expected {
+  SetTemperature(u8),
+}
+
+/// What to do about a command that hasn't been confirmed in time.
+#[derive(Debug)]
+pub(crate) enum Unconfirmed {
+  /// Send it again.
+  Retry(MessageSource, MessageType),
+
+  /// Out of attempts, with a description of the command for humans.
+  Failed(String),
+}
+
+impl Default for Confirmations {
+  fn default() -> Self {
+    let metrics = Metrics::global();
+    Self {
+      pending: vec![],
+      confirmed: metrics.counter("wifi_module.commands_confirmed"),
+      retried: metrics.counter("wifi_module.commands_retried"),
+      failed: metrics.counter("wifi_module.commands_failed"),
+    }
+  }
+}
+
+impl Confirmations {
+  /// Call as `mt` is first queued.  Anything that isn't tracked is reported done straight away.
+  pub fn track(
+      &mut self,
+      source: MessageSource,
+      mt: &MessageType,
+      done: Option<DoneSender>,
+      now: Instant,
+  ) {
+    let expected = match mt {
+      MessageType::SetTemperatureRequest { temperature } =>
+        Some(Expected::SetTemperature(temperature.raw_value())),
+      _ => None,
+    };
+    match expected {
+      Some(expected) => {
+        self.pending.push(PendingConfirmation {
+          source,
+          mt: mt.clone(),
+          expected,
+          attempts: 1,
+          deadline: now + CONFIRM_TIMEOUT,
+          done,
+        });
+      }
+      None => {
+        if let Some(done) = done {
+          // Not safe to retry, the best we can say is that it's on its way.
+          debug!("Not confirming {mt:?}");
+          let _ = done.send(Ok(()));
+        }
+      }
+    }
+  }
+
+  pub fn on_message(&mut self, mt: &MessageType, now: Instant) -> Vec<Unconfirmed> {
+    let status = match mt {
+      MessageType::StatusUpdate(status) => Some(&status.v1),
+      _ => None,
+    };
+    let mut unconfirmed = vec![];
+    self.pending.retain_mut(|pending| {
+      if status.is_some_and(|status| pending.expected.is_met_by(status)) {
+        debug!("Confirmed {:?}", pending.expected);
+        self.confirmed.inc();
+        if let Some(done) = &pending.done {
+          let _ = done.send(Ok(()));
+        }
+        false
+      } else if now < pending.deadline {
+        true
+      } else if pending.attempts < MAX_ATTEMPTS {
+        info!("Spa didn't act on {:?}, sending it again", pending.expected);
+        self.retried.inc();
+        pending.attempts += 1;
+        pending.deadline = now + CONFIRM_TIMEOUT;
+        unconfirmed.push(Unconfirmed::Retry(pending.source, pending.mt.clone()));
+        true
+      } else {
+        info!("Spa didn't act on {:?} from {:?}, giving up", pending.expected, pending.source);
+        self.failed.inc();
+        if let Some(done) = &pending.done {
+          let _ = done.send(Err(SpaClientError::NotConfirmed));
+        }
+        unconfirmed.push(Unconfirmed::Failed(pending.expected.to_string()));
+        false
+      }
+    });
+    unconfirmed
+  }
+}
+
+impl Expected {
+  fn is_met_by(&self, status: &StatusUpdateResponseV1) -> bool {
+    match self {
+      Expected::SetTemperature(raw) => status.set_temperature.raw_value() == *raw,
+    }
+  }
+}
+
+impl std::fmt::Display for Expected {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Expected::SetTemperature(raw) => write!(f, "set temperature {raw}"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc::channel;
+  use balboa_spa_messages::message_types::{ItemCode, StatusUpdateMessage};
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use balboa_spa_messages::temperature::{Temperature, TemperatureScale};
+  use super::*;
+
+  fn status() -> StatusUpdateMessage {
+    StatusUpdateMessage::try_from([0u8; 24].as_slice()).unwrap()
+  }
+
+  fn set_temperature(fahrenheit: f64) -> MessageType {
+    MessageType::SetTemperatureRequest {
+      temperature: TemperatureScale::Fahrenheit.new_set_temperature(&Temperature::from_fahrenheit(fahrenheit)).unwrap(),
+    }
+  }
+
+  #[test]
+  fn test_confirmations() {
+    let mut confirmations = Confirmations::default();
+    let now = Instant::now();
+    let initial = status();
+
+    let (done_tx, set_done) = channel();
+    confirmations.track(MessageSource::SpaClient, &set_temperature(101.0), Some(done_tx), now);
+    let toggle = MessageType::ToggleItemRequest { item_code: ParsedEnum::new(ItemCode::Pump1), dummy1: 0 };
+    let (done_tx, toggled) = channel();
+    confirmations.track(MessageSource::SpaClient, &toggle, Some(done_tx), now);
+    assert!(matches!(toggled.try_recv(), Ok(Ok(()))), "Toggles aren't safe to retry so aren't tracked");
+
+    assert!(confirmations.on_message(&MessageType::StatusUpdate(initial.clone()), now).is_empty());
+    assert!(set_done.try_recv().is_err(), "Nothing changed yet");
+
+    let mut changed = initial.clone();
+    changed.v1.set_temperature = TemperatureScale::Fahrenheit.new_protocol_temperature_from_raw(101);
+    assert!(confirmations.on_message(&MessageType::StatusUpdate(changed), now).is_empty());
+    assert!(matches!(set_done.try_recv(), Ok(Ok(()))));
+  }
+
+  #[test]
+  fn test_retry_then_fail() {
+    let mut confirmations = Confirmations::default();
+    let now = Instant::now();
+    let initial = MessageType::StatusUpdate(status());
+    let set = set_temperature(101.0);
+    let (done_tx, done_rx) = channel();
+    confirmations.track(MessageSource::SpaClient, &set, Some(done_tx), now);
+
+    let later = now + CONFIRM_TIMEOUT;
+    let unconfirmed = confirmations.on_message(&initial, later);
+    assert!(matches!(
+        unconfirmed.as_slice(),
+        [Unconfirmed::Retry(MessageSource::SpaClient, MessageType::SetTemperatureRequest { .. })]));
+    assert!(done_rx.try_recv().is_err(), "Still worth waiting for the retry");
+
+    let later = later + CONFIRM_TIMEOUT;
+    let unconfirmed = confirmations.on_message(&initial, later);
+    assert!(matches!(unconfirmed.as_slice(), [Unconfirmed::Failed(description)] if description == "set temperature 101"));
+    assert!(matches!(done_rx.try_recv(), Ok(Err(SpaClientError::NotConfirmed))));
+    assert!(confirmations.on_message(&initial, later + CONFIRM_TIMEOUT).is_empty());
+  }
+}
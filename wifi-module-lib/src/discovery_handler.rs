@@ -1,33 +1,81 @@
-use std::net::UdpSocket;
-use std::io;
-use log::{error, info};
+//! Lets clients on the LAN find us.  Two mechanisms are supported:
+//!
+//! * The official app's own scheme: it broadcasts to UDP port 30303 and expects a unicast reply
+//!   of `<name>\r\n<mac>\r\n` back to the port it sent from, e.g.
+//!   `BWGS99\r\n00-15-27-01-02-03\r\n`.  The known query is `Discovery: Who is out there?`,
+//!   but any datagram is answered rather than depend on its exact contents.  See
+//!   [Advertisement::fake_balboa] for the name and MAC we reply with.
+//! * mDNS, for everything else (see [crate::mdns]).
+//!
+//! Both answer on IPv6 as well as IPv4 where the host has it, see [crate::dual_stack].
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::{io, thread};
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
 use crate::advertisement::Advertisement;
+use crate::dual_stack::{bind_udp, canonical, HostAddrs};
+use crate::mdns::{MDNS_GROUP, MDNS_GROUP_V6, MDNS_PORT, MdnsResponder, MdnsService};
 
 const DISCOVERY_PORT: u16 = 30303;
 
+const DISCOVERY_QUERY: &[u8] = b"Discovery: Who is out there?";
+
+/// How long to trust [HostAddrs::towards] for a peer, which takes a socket per family to find
+/// out.  Short enough to notice a new DHCP lease soon after.
+const HOST_ADDRS_TTL: Duration = Duration::from_secs(30);
+
+/// Peers remembered in [MdnsHandler::host_addrs], as there's no telling how busy the LAN is.
+const MAX_CACHED_PEERS: usize = 16;
+
 pub struct DiscoveryHandler {
   advertisement: Advertisement,
   socket: UdpSocket,
+  mdns: Option<MdnsHandler>,
 }
 
 impl DiscoveryHandler {
-  pub fn setup(advertisement: Advertisement) -> io::Result<Self> {
-    let socket = UdpSocket::bind(format!("0.0.0.0:{}", DISCOVERY_PORT))?;
+  /// Also advertises `services` over mDNS when possible.  That's best effort as something else
+  /// on the host (e.g. Avahi) may already own the port.
+  pub fn setup(advertisement: Advertisement, services: Vec<MdnsService>) -> io::Result<Self> {
+    let socket = bind_udp(DISCOVERY_PORT)?;
     socket.set_read_timeout(None)?;
+    let mdns = match MdnsHandler::setup(&advertisement, services) {
+      Ok(mdns) => Some(mdns),
+      Err(e) => {
+        warn!("mDNS unavailable, only answering UDP discovery: {e}");
+        None
+      }
+    };
     Ok(Self {
       advertisement,
       socket,
+      mdns,
     })
   }
 
   pub fn run_loop(self) -> anyhow::Result<()> {
+    if let Some(mdns) = self.mdns {
+      thread::Builder::new()
+          .name("MdnsResponder".into())
+          .spawn(move || {
+            if let Err(e) = mdns.run_loop() {
+              error!("mDNS responder exited: {e}");
+            }
+          })
+          .unwrap();
+    }
+
     let mut buf = [0u8; 512];
     loop {
       let (n, addr) = self.socket.recv_from(&mut buf)?;
 
-      let received = String::from_utf8(buf[0..n].to_vec())
-          .unwrap_or_else(|_| format!("{:?}", &buf[0..n]));
-      info!("{addr} looking for us: {received}");
+      if is_discovery_query(&buf[0..n]) {
+        info!("{} looking for us", canonical(addr));
+      } else {
+        info!("{} looking for us with an unfamiliar query: {:?}", canonical(addr), String::from_utf8_lossy(&buf[0..n]));
+      }
 
       let reply = &self.advertisement.payload;
       let reply_len = reply.len();
@@ -44,3 +92,122 @@ impl DiscoveryHandler {
     }
   }
 }
+
+/// Only tells known queries apart for logging.  Tolerates trailing junk some versions of the app
+/// might add, like a NUL or line ending.
+fn is_discovery_query(datagram: &[u8]) -> bool {
+  let trimmed = datagram.iter()
+      .rposition(|b| !b.is_ascii_whitespace() && *b != 0)
+      .map_or(&datagram[..0], |end| &datagram[..=end]);
+  trimmed == DISCOVERY_QUERY
+}
+
+struct MdnsHandler {
+  responder: MdnsResponder,
+  socket: UdpSocket,
+
+  /// Whether `socket` is IPv6, in which case IPv4 must be addressed as mapped.
+  dual_stack: bool,
+
+  /// Our addresses as seen by each recent peer, and when we looked.
+  host_addrs: HashMap<IpAddr, (HostAddrs, Instant)>,
+}
+
+impl MdnsHandler {
+  fn setup(advertisement: &Advertisement, services: Vec<MdnsService>) -> io::Result<Self> {
+    let (socket, dual_stack) = match bind_mdns_dual_stack() {
+      Ok(socket) => (socket, true),
+      Err(e) => {
+        debug!("mDNS over IPv4 only: {e}");
+        let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)))?;
+        socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_ttl_v4(255)?;
+        (socket, false)
+      }
+    };
+    info!("Advertising {}.local over mDNS", advertisement.hostname);
+    let responder = MdnsResponder::new(&advertisement.name, &advertisement.hostname, services);
+    Ok(Self { responder, socket, dual_stack, host_addrs: HashMap::new() })
+  }
+
+  fn run_loop(mut self) -> io::Result<()> {
+    let mut buf = [0u8; 1500];
+    loop {
+      let (n, peer) = self.socket.recv_from(&mut buf)?;
+      // Most of what arrives is other hosts' traffic.
+      if !self.responder.is_about_us(&buf[..n]) {
+        continue;
+      }
+      let addrs = self.host_addrs_towards(peer);
+      if addrs == HostAddrs::default() {
+        debug!("No route back to mDNS peer {}", canonical(peer));
+        continue;
+      }
+      let legacy = peer.port() != MDNS_PORT;
+      if let Some(response) = self.responder.answer(&buf[..n], addrs, legacy) {
+        debug!("Answering mDNS query from {}", canonical(peer));
+        let dest = if legacy { peer } else { self.group_towards(peer) };
+        if let Err(e) = self.socket.send_to(&response, dest) {
+          warn!("Unable to send mDNS response to {dest}: {e}");
+        }
+      }
+    }
+  }
+
+  /// Our addresses on the interface `peer` is reachable through, which is what it should be
+  /// told to connect to.
+  fn host_addrs_towards(&mut self, peer: SocketAddr) -> HostAddrs {
+    let now = Instant::now();
+    let ip = canonical(peer).ip();
+    if let Some((addrs, at)) = self.host_addrs.get(&ip) {
+      if now.duration_since(*at) < HOST_ADDRS_TTL {
+        return *addrs;
+      }
+    }
+    self.host_addrs.retain(|_, (_, at)| now.duration_since(*at) < HOST_ADDRS_TTL);
+    if self.host_addrs.len() >= MAX_CACHED_PEERS {
+      self.host_addrs.clear();
+    }
+    let addrs = HostAddrs::towards(peer);
+    self.host_addrs.insert(ip, (addrs, now));
+    addrs
+  }
+
+  /// The multicast group on the same network as `peer`.
+  fn group_towards(&self, peer: SocketAddr) -> SocketAddr {
+    match canonical(peer) {
+      SocketAddr::V4(_) if self.dual_stack => SocketAddr::from((MDNS_GROUP.to_ipv6_mapped(), MDNS_PORT)),
+      SocketAddr::V4(_) => SocketAddr::from((MDNS_GROUP, MDNS_PORT)),
+      SocketAddr::V6(v6) => SocketAddr::V6(SocketAddrV6::new(MDNS_GROUP_V6, MDNS_PORT, 0, v6.scope_id())),
+    }
+  }
+}
+
+/// A single socket for both groups, which can't share the port with an IPv4 one on Linux.
+fn bind_mdns_dual_stack() -> io::Result<UdpSocket> {
+  let socket = UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, MDNS_PORT)))?;
+  socket.join_multicast_v6(&MDNS_GROUP_V6, 0)?;
+  socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+  socket.set_multicast_ttl_v4(255)?;
+  Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_discovery_exchange() {
+    assert!(is_discovery_query(b"Discovery: Who is out there?"));
+    assert!(is_discovery_query(b"Discovery: Who is out there?\r\n\0"));
+    assert!(!is_discovery_query(b"Discovery: Who is out there"));
+    assert!(!is_discovery_query(b""));
+
+    let advertisement = Advertisement::fake_balboa();
+    assert_eq!(advertisement.payload, b"BWGS99\r\n00-15-27-01-02-03\r\n");
+
+    let renamed = advertisement.set_name("BWGS Back Deck");
+    assert_eq!(renamed.payload, b"BWGS Back Deck\r\n00-15-27-01-02-03\r\n");
+    assert_eq!(renamed.hostname, "bwgs-back-deck");
+  }
+}
@@ -0,0 +1,111 @@
+//! Sockets that serve IPv6 and IPv4 clients alike.
+//!
+//! Binding to `[::]` gets us both on Linux and lwIP as long as nobody has set `IPV6_V6ONLY`,
+//! with IPv4 peers showing up as mapped addresses (`::ffff:a.b.c.d`).  On a network or host
+//! without IPv6 that bind fails and we fall back to IPv4 alone, as before.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+use log::debug;
+
+/// Any global unicast address will do, nothing is ever sent to it.
+const GLOBAL_V6_PROBE: SocketAddr =
+    SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0x2000, 0, 0, 0, 0, 0, 0, 1)), 9);
+
+/// Likewise, routable from anywhere with a default route.
+const GLOBAL_V4_PROBE: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 9);
+
+pub(crate) fn bind_tcp(port: u16) -> io::Result<TcpListener> {
+  TcpListener::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))).or_else(|e| {
+    debug!("No IPv6 on port {port} ({e}), listening on IPv4 only");
+    TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+  })
+}
+
+pub(crate) fn bind_udp(port: u16) -> io::Result<UdpSocket> {
+  UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))).or_else(|e| {
+    debug!("No IPv6 on port {port} ({e}), listening on IPv4 only");
+    UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+  })
+}
+
+/// Undoes IPv4 mapping so that peers read as they would to an IPv4-only listener.
+pub(crate) fn canonical(peer: SocketAddr) -> SocketAddr {
+  SocketAddr::new(peer.ip().to_canonical(), peer.port())
+}
+
+/// Our addresses, as far as we can tell, on whichever interface reaches `peer`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub(crate) struct HostAddrs {
+  pub v4: Option<Ipv4Addr>,
+  pub v6: Option<Ipv6Addr>,
+}
+
+impl HostAddrs {
+  /// Asks the routing table by connecting (which sends nothing) a UDP socket: towards `peer`
+  /// for its own address family and towards somewhere global for the other.  Link-local only
+  /// IPv6 is only found when `peer` is itself on IPv6.
+  pub fn towards(peer: SocketAddr) -> Self {
+    let peer = canonical(peer);
+    let (v4_target, v6_target) = match peer {
+      SocketAddr::V4(_) => (peer, GLOBAL_V6_PROBE),
+      SocketAddr::V6(_) => (GLOBAL_V4_PROBE, peer),
+    };
+    let v4 = match local_addr_towards(v4_target) {
+      Ok(IpAddr::V4(addr)) => Some(addr),
+      _ => None,
+    };
+    let v6 = match local_addr_towards(v6_target) {
+      Ok(IpAddr::V6(addr)) => Some(addr),
+      _ => None,
+    };
+    Self { v4, v6 }
+  }
+}
+
+fn local_addr_towards(target: SocketAddr) -> io::Result<IpAddr> {
+  let unspecified = match target {
+    SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+  };
+  let probe = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+  probe.connect(target)?;
+  Ok(probe.local_addr()?.ip())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::TcpStream;
+  use super::*;
+
+  #[test]
+  fn test_canonical() {
+    let mapped: SocketAddr = "[::ffff:192.168.1.5]:4257".parse().unwrap();
+    assert_eq!(canonical(mapped), "192.168.1.5:4257".parse().unwrap());
+
+    for addr in ["192.168.1.5:4257", "[fe80::1]:4257", "[::1]:4257"] {
+      let addr: SocketAddr = addr.parse().unwrap();
+      assert_eq!(canonical(addr), addr);
+    }
+  }
+
+  #[test]
+  fn test_ipv4_peer_reads_as_ipv4() -> anyhow::Result<()> {
+    // Whether or not the host has IPv6 and so whether we fell back.
+    let listener = bind_tcp(0)?;
+    let port = listener.local_addr()?.port();
+    let _client = TcpStream::connect((Ipv4Addr::LOCALHOST, port))?;
+    let (_, peer) = listener.accept()?;
+    assert_eq!(canonical(peer).ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+    Ok(())
+  }
+
+  #[test]
+  fn test_host_addrs_towards_mapped_peer() {
+    let plain = HostAddrs::towards("127.0.0.1:9".parse().unwrap());
+    let mapped = HostAddrs::towards("[::ffff:127.0.0.1]:9".parse().unwrap());
+    assert_eq!(plain.v4, Some(Ipv4Addr::LOCALHOST));
+    assert_eq!(mapped.v4, plain.v4);
+  }
+}
@@ -0,0 +1,219 @@
+//! The main board's fault log, fetched in the background so that it can be served from memory
+//! rather than only relaying whatever a panel happens to ask for.
+//!
+//! Every poll interval we ask for the newest entry, and if it isn't what we remember the rest
+//! of the log is fetched again one entry per request, as the board renumbers entries once its
+//! log is full.  Responses to anyone else's requests are cached just the same.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+use balboa_spa_messages::message_types::{FaultResponseMessage, MessageType, SettingsRequestMessage};
+
+/// Faults are rare, there's no point bothering the board about them more often.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Out of range entry numbers get the newest entry.
+const LATEST_ENTRY: u8 = 0xff;
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The log as far as we know it, shared with the local APIs.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FaultLogCache {
+  inner: Arc<Mutex<CachedFaultLog>>,
+}
+
+#[derive(Debug, Default)]
+struct CachedFaultLog {
+  total_entries: Option<u8>,
+  entries: BTreeMap<u8, FaultResponseMessage>,
+}
+
+impl FaultLogCache {
+  /// Returns whether `fault` told us anything new.  Anything that doesn't agree with what we
+  /// had means the board's numbering moved on, so the other entries are forgotten to be fetched
+  /// again.
+  pub fn update(&self, fault: &FaultResponseMessage) -> bool {
+    let mut log = self.inner.lock().unwrap();
+    let known = log.entries.get(&fault.entry_number);
+    if log.total_entries == Some(fault.total_entries)
+        && (fault.total_entries == 0 || known.is_some_and(|known| same_entry(known, fault))) {
+      return false;
+    }
+    if log.total_entries != Some(fault.total_entries) || known.is_some() {
+      log.entries.clear();
+    }
+    log.total_entries = Some(fault.total_entries);
+    if fault.total_entries > 0 {
+      log.entries.insert(fault.entry_number, fault.clone());
+    }
+    true
+  }
+
+  /// Cached entries, oldest first.  May have gaps until [Self::first_missing] returns None.
+  #[cfg(any(feature = "http", feature = "mqtt"))]
+  pub fn entries(&self) -> Vec<FaultResponseMessage> {
+    self.inner.lock().unwrap().entries.values().cloned().collect()
+  }
+
+  #[cfg(feature = "http")]
+  pub fn total_entries(&self) -> Option<u8> {
+    self.inner.lock().unwrap().total_entries
+  }
+
+  /// Like the board, out of range entry numbers get the newest entry.  None if we don't have
+  /// the entry or the log is empty.
+  pub fn get(&self, entry_num: u8) -> Option<FaultResponseMessage> {
+    let log = self.inner.lock().unwrap();
+    let total_entries = log.total_entries.filter(|total| *total > 0)?;
+    log.entries.get(&entry_num.min(total_entries - 1)).cloned()
+  }
+
+  fn first_missing(&self) -> Option<u8> {
+    let log = self.inner.lock().unwrap();
+    (0..log.total_entries?).find(|n| !log.entries.contains_key(n))
+  }
+}
+
+fn same_entry(a: &FaultResponseMessage, b: &FaultResponseMessage) -> bool {
+  a.fault_code.as_raw() == b.fault_code.as_raw()
+      && a.days_ago == b.days_ago
+      && a.time.as_raw() == b.time.as_raw()
+      && a.set_temperature == b.set_temperature
+}
+
+/// Decides which fault log request to send next, if any.  Only one is outstanding at a time so
+/// as not to crowd out relayed traffic.
+#[derive(Debug)]
+pub(crate) struct FaultLogPoller {
+  cache: FaultLogCache,
+  interval: Option<Duration>,
+  next_poll: Option<Instant>,
+  in_flight: Option<Instant>,
+  refreshing: bool,
+}
+
+impl FaultLogPoller {
+  /// Without an `interval` the log is only cached, never requested.
+  pub fn new(cache: FaultLogCache, interval: Option<Duration>) -> Self {
+    Self { cache, interval, next_poll: None, in_flight: None, refreshing: false }
+  }
+
+  /// Returns whether the cached log changed.
+  pub fn on_message(&mut self, mt: &MessageType) -> bool {
+    let MessageType::FaultLogResponse(fault) = mt else {
+      return false;
+    };
+    self.in_flight = None;
+    let changed = self.cache.update(fault);
+    if changed {
+      debug!("Fault log entry {} of {} updated", fault.entry_number, fault.total_entries);
+      self.refreshing = true;
+    }
+    changed
+  }
+
+  /// The request to send now, if any.
+  pub fn poll(&mut self, now: Instant) -> Option<MessageType> {
+    let interval = self.interval?;
+    if let Some(sent) = self.in_flight {
+      if now < sent + RESPONSE_TIMEOUT {
+        return None;
+      }
+      warn!("No answer to fault log request, trying again later");
+      self.in_flight = None;
+      self.refreshing = false;
+    }
+    let entry_num = if self.next_poll.is_none_or(|next| now >= next) {
+      self.next_poll = Some(now + interval);
+      LATEST_ENTRY
+    } else if self.refreshing {
+      match self.cache.first_missing() {
+        Some(entry_num) => entry_num,
+        None => {
+          self.refreshing = false;
+          return None;
+        }
+      }
+    } else {
+      return None;
+    };
+    self.in_flight = Some(now);
+    Some(MessageType::SettingsRequest(SettingsRequestMessage::FaultLog { entry_num }))
+  }
+}
+
+#[cfg(any(feature = "http", feature = "mqtt"))]
+pub(crate) fn fault_json(fault: &FaultResponseMessage) -> serde_json::Value {
+  let description = match fault.fault_code.as_ref() {
+    Some(code) => code.to_string(),
+    None => format!("Unknown fault {}", fault.fault_code.as_raw()),
+  };
+  serde_json::json!({
+    "entry": fault.entry_number,
+    "code": fault.fault_code.as_raw(),
+    "description": description,
+    "days_ago": fault.days_ago,
+    "time": fault.time.to_string(),
+    "set_temperature": fault.set_temperature,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use balboa_spa_messages::time::ProtocolTime;
+  use super::*;
+
+  fn fault(entry_number: u8, total_entries: u8, fault_code: u8) -> MessageType {
+    MessageType::FaultLogResponse(FaultResponseMessage {
+      total_entries,
+      entry_number,
+      fault_code: ParsedEnum::from_raw(fault_code),
+      days_ago: 0,
+      time: ProtocolTime::from_hm(12, 0),
+      set_temperature: 100,
+    })
+  }
+
+  fn requested_entry(request: Option<MessageType>) -> Option<u8> {
+    match request? {
+      MessageType::SettingsRequest(SettingsRequestMessage::FaultLog { entry_num }) => Some(entry_num),
+      other => panic!("Unexpected {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_poll() {
+    let cache = FaultLogCache::default();
+    let mut poller = FaultLogPoller::new(cache.clone(), Some(DEFAULT_POLL_INTERVAL));
+    let now = Instant::now();
+
+    assert_eq!(requested_entry(poller.poll(now)), Some(LATEST_ENTRY));
+    assert_eq!(requested_entry(poller.poll(now)), None, "Must wait for the answer");
+    assert!(poller.on_message(&fault(2, 3, 16)));
+    assert_eq!(requested_entry(poller.poll(now)), Some(0));
+    assert!(poller.on_message(&fault(0, 3, 17)));
+    assert_eq!(requested_entry(poller.poll(now)), Some(1));
+    assert!(poller.on_message(&fault(1, 3, 18)));
+    assert_eq!(requested_entry(poller.poll(now)), None);
+    assert!((0..3).all(|n| cache.get(n).is_some()));
+    assert_eq!(cache.get(LATEST_ENTRY).map(|f| f.entry_number), Some(2));
+
+    // Nothing new.
+    let later = now + DEFAULT_POLL_INTERVAL;
+    assert_eq!(requested_entry(poller.poll(later)), Some(LATEST_ENTRY));
+    assert!(!poller.on_message(&fault(2, 3, 16)));
+    assert_eq!(requested_entry(poller.poll(later)), None);
+
+    // A new fault pushed the oldest out of a full log.
+    let later = later + DEFAULT_POLL_INTERVAL;
+    assert_eq!(requested_entry(poller.poll(later)), Some(LATEST_ENTRY));
+    assert!(poller.on_message(&fault(2, 3, 19)));
+    assert!(cache.get(0).is_none());
+    assert!(cache.get(2).is_some());
+    assert_eq!(requested_entry(poller.poll(later)), Some(0));
+  }
+}
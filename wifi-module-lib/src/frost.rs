@@ -0,0 +1,213 @@
+//! Last line of defence against a frozen spa.  Main boards have their own freeze protection,
+//! but only if they're left in a mode that lets the heater run, and a spa someone put in rest
+//! mode with a low set point before leaving for the winter can still get dangerously cold.
+//!
+//! Once the water drops below [FrostConfig::set_threshold] without the heater running we raise
+//! an [Alert::Frost] and, one step at a time, switch to ready mode, raise the set point and get
+//! the first pump circulating.  Nothing is put back afterwards, that's for a human to decide.
+//!
+//! Main boards stop reporting the water temperature once nothing has circulated for a while,
+//! which is just when it's most likely to freeze.  We go by the last reading until then, and
+//! once that's [STALE_AFTER] old take over anyway if it wasn't comfortably warm.
+
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use balboa_spa_messages::message_types::{HeatingMode, HeatingState, ItemCode, MessageType, PumpStatus, StatusUpdateResponseV1};
+use balboa_spa_messages::parsed_enum::ParsedEnum;
+use balboa_spa_messages::temperature::{ProtocolTemperature, Temperature, TemperatureScale};
+use common_lib::metrics::{Counter, Metrics};
+use crate::alerts::Alert;
+
+/// Degrees F the water must recover above the threshold before we stand down.
+const HYSTERESIS_F: f64 = 4.0;
+
+/// Degrees F above the threshold to set the heater to.
+const HEADROOM_F: f64 = 10.0;
+
+/// Gives each step time to show up in the status before we try the next.
+const ACTION_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to trust the last reading while the board isn't reporting one.
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct FrostConfig {
+  pub(crate) threshold: Temperature,
+}
+
+impl Default for FrostConfig {
+  fn default() -> Self {
+    Self { threshold: Temperature::from_fahrenheit(40.0) }
+  }
+}
+
+impl FrostConfig {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Water temperature to act below, 40F by default.
+  pub fn set_threshold(mut self, threshold: Temperature) -> Self {
+    self.threshold = threshold;
+    self
+  }
+}
+
+#[derive(Debug)]
+pub(crate) struct FrostGuard {
+  threshold_f: f64,
+  status: Option<StatusUpdateResponseV1>,
+  last_reading: Option<(ProtocolTemperature, Instant)>,
+  active: bool,
+  last_action: Option<Instant>,
+  activations: Counter,
+}
+
+impl FrostGuard {
+  pub fn new(config: &FrostConfig) -> Self {
+    Self {
+      threshold_f: config.threshold.as_fahrenheit(),
+      status: None,
+      last_reading: None,
+      active: false,
+      last_action: None,
+      activations: Metrics::global().counter("wifi_module.frost_activations"),
+    }
+  }
+
+  /// Call for every message from the main board, returns an alert as protection kicks in.
+  pub fn on_message(&mut self, mt: &MessageType, now: Instant) -> Option<Alert> {
+    let MessageType::StatusUpdate(status) = mt else {
+      return None;
+    };
+    self.status = Some(status.v1.clone());
+    let fresh = status.v1.current_temperature.is_some();
+    if let Some(current) = &status.v1.current_temperature {
+      self.last_reading = Some((current.clone(), now));
+    }
+    let (current, read_at) = self.last_reading.as_ref()?;
+    let current_f = current.temperature.as_fahrenheit();
+    let stale = !fresh
+        && now.duration_since(*read_at) > STALE_AFTER
+        && current_f < self.threshold_f + HEADROOM_F;
+    let heating = matches!(status.v1.heating_state.as_ref(), Some(HeatingState::Heating));
+    if !self.active && (current_f < self.threshold_f || stale) && !heating {
+      if stale {
+        warn!("No water temperature for over {}m, last {current_f:.1}F, taking over",
+            STALE_AFTER.as_secs() / 60);
+      } else {
+        warn!("Water at {current_f:.1}F with the heater off, taking over");
+      }
+      self.active = true;
+      self.activations.inc();
+      let scale = current.raw_scale;
+      let current = match scale {
+        TemperatureScale::Fahrenheit => current_f,
+        TemperatureScale::Celsius => current.temperature.as_celsius(),
+      };
+      return Some(Alert::Frost { current: current.round(), scale });
+    }
+    if self.active && fresh && current_f >= self.threshold_f + HYSTERESIS_F {
+      info!("Water back up to {current_f:.1}F, frost protection standing down");
+      self.active = false;
+    }
+    None
+  }
+
+  /// The next step to take, if any.  Only call once we have a channel to send on.
+  pub fn poll(&mut self, now: Instant) -> Option<MessageType> {
+    if !self.active || self.last_action.is_some_and(|last| now < last + ACTION_INTERVAL) {
+      return None;
+    }
+    let status = self.status.as_ref()?;
+    let set_point = Temperature::from_fahrenheit(self.threshold_f + HEADROOM_F);
+    let toggle = |item| MessageType::ToggleItemRequest { item_code: ParsedEnum::new(item), dummy1: 0 };
+    let request = if matches!(status.heating_mode.as_ref(), Some(HeatingMode::Rest)) {
+      toggle(ItemCode::HeatMode)
+    } else if status.set_temperature.temperature.as_fahrenheit() < set_point.as_fahrenheit() {
+      let temperature = status.set_temperature.raw_scale.new_set_temperature(&set_point).ok()?;
+      MessageType::SetTemperatureRequest { temperature }
+    } else if matches!(status.pump_status.first().and_then(|p| p.as_ref()), Some(PumpStatus::Off)) {
+      toggle(ItemCode::Pump1)
+    } else {
+      return None;
+    };
+    info!("Frost protection sending {request:?}");
+    self.last_action = Some(now);
+    Some(request)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::message_types::StatusUpdateMessage;
+  use super::*;
+
+  fn status(current: u8, set: u8, mode: HeatingMode) -> MessageType {
+    status_with(Some(current), set, mode)
+  }
+
+  fn status_with(current: Option<u8>, set: u8, mode: HeatingMode) -> MessageType {
+    let mut status = StatusUpdateMessage::try_from([0u8; 24].as_slice()).unwrap();
+    let scale = status.v1.set_temperature.raw_scale;
+    status.v1.heating_mode = ParsedEnum::new(mode);
+    status.v1.current_temperature = current.map(|raw| scale.new_protocol_temperature_from_raw(raw));
+    status.v1.set_temperature = scale.new_protocol_temperature_from_raw(set);
+    MessageType::StatusUpdate(status)
+  }
+
+  #[test]
+  fn test_frost() {
+    let mut guard = FrostGuard::new(&FrostConfig::new());
+    let now = Instant::now();
+    assert_eq!(guard.on_message(&status(45, 45, HeatingMode::Rest), now), None);
+    assert!(guard.poll(now).is_none());
+
+    assert!(matches!(guard.on_message(&status(39, 45, HeatingMode::Rest), now), Some(Alert::Frost { .. })));
+    assert_eq!(guard.on_message(&status(39, 45, HeatingMode::Rest), now), None, "Only alerted once");
+    assert!(matches!(
+        guard.poll(now),
+        Some(MessageType::ToggleItemRequest { item_code, .. }) if item_code.as_ref() == Some(&ItemCode::HeatMode)));
+    assert!(guard.poll(now).is_none(), "Waiting to see it take");
+
+    guard.on_message(&status(39, 45, HeatingMode::Ready), now);
+    let later = now + ACTION_INTERVAL;
+    assert!(matches!(
+        guard.poll(later),
+        Some(MessageType::SetTemperatureRequest { temperature }) if temperature.raw_value() == 50));
+    guard.on_message(&status(39, 50, HeatingMode::Ready), now);
+    let later = later + ACTION_INTERVAL;
+    assert!(matches!(
+        guard.poll(later),
+        Some(MessageType::ToggleItemRequest { item_code, .. }) if item_code.as_ref() == Some(&ItemCode::Pump1)));
+
+    guard.on_message(&status(44, 50, HeatingMode::Ready), now);
+    assert!(guard.poll(later + ACTION_INTERVAL).is_none(), "Warm enough again");
+  }
+
+  #[test]
+  fn test_unknown_temperature() {
+    let mut guard = FrostGuard::new(&FrostConfig::new());
+    let now = Instant::now();
+    assert_eq!(guard.on_message(&status_with(None, 45, HeatingMode::Rest), now), None, "Nothing to go on");
+    assert_eq!(guard.on_message(&status(41, 45, HeatingMode::Rest), now), None);
+
+    let later = now + Duration::from_secs(60);
+    assert_eq!(guard.on_message(&status_with(None, 45, HeatingMode::Rest), later), None, "Last reading still good");
+
+    let later = now + STALE_AFTER + Duration::from_secs(1);
+    assert!(matches!(guard.on_message(&status_with(None, 45, HeatingMode::Rest), later), Some(Alert::Frost { .. })));
+    assert!(guard.poll(later).is_some());
+    guard.on_message(&status_with(None, 45, HeatingMode::Ready), later);
+    assert!(guard.poll(later + ACTION_INTERVAL).is_some(), "Not standing down without a reading");
+  }
+
+  #[test]
+  fn test_unknown_temperature_while_warm() {
+    let mut guard = FrostGuard::new(&FrostConfig::new());
+    let now = Instant::now();
+    assert_eq!(guard.on_message(&status(80, 100, HeatingMode::Rest), now), None);
+    let later = now + STALE_AFTER * 2;
+    assert_eq!(guard.on_message(&status_with(None, 100, HeatingMode::Rest), later), None);
+  }
+}
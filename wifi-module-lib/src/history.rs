@@ -0,0 +1,151 @@
+//! The last couple of days of spa behaviour, kept in memory so the local APIs can chart it
+//! without anyone having to run their own logging.
+//!
+//! Status updates are boiled down to one [Sample] every [SAMPLE_INTERVAL]: temperatures as of
+//! the end of the interval, and whether the heater or any pumps ran at any point during it, so
+//! short bursts aren't missed.  Nothing survives a restart.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use balboa_spa_messages::message_types::{HeatingState, PumpStatus, StatusUpdateResponseV1};
+use balboa_spa_messages::temperature::{Temperature, TemperatureScale};
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How far back we remember, 48h at a few dozen bytes per sample.
+pub const MAX_HOURS: u64 = 48;
+
+/// When the client doesn't say how much it wants.
+pub const DEFAULT_HISTORY_HOURS: u64 = 24;
+
+const MAX_SAMPLES: usize = (MAX_HOURS * 60 * 60 / SAMPLE_INTERVAL.as_secs()) as usize;
+
+/// Shared between the bus thread recording it and the APIs reading it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+  inner: Arc<Mutex<HistoryInner>>,
+}
+
+#[derive(Debug, Default)]
+struct HistoryInner {
+  samples: VecDeque<Sample>,
+  pending: Option<Sample>,
+
+  /// Of the latest status, which is what we answer in.
+  scale: Option<TemperatureScale>,
+}
+
+#[derive(Debug, Clone)]
+struct Sample {
+  started: Instant,
+  current_temperature: Option<Temperature>,
+  set_temperature: Temperature,
+  heating: bool,
+  pumps_running: usize,
+}
+
+impl History {
+  pub fn on_status(&self, status: &StatusUpdateResponseV1, now: Instant) {
+    let heating = matches!(status.heating_state.as_ref(), Some(HeatingState::Heating));
+    let pumps_running = status.pump_status.iter()
+        .filter(|p| !matches!(p.as_ref(), Some(PumpStatus::Off)))
+        .count();
+    let current_temperature = status.current_temperature.as_ref().map(|t| t.temperature);
+    let set_temperature = status.set_temperature.temperature;
+
+    let mut inner = self.inner.lock().unwrap();
+    inner.scale = Some(status.set_temperature.raw_scale);
+    if let Some(pending) = &mut inner.pending {
+      if now < pending.started + SAMPLE_INTERVAL {
+        pending.current_temperature = current_temperature;
+        pending.set_temperature = set_temperature;
+        pending.heating |= heating;
+        pending.pumps_running = pending.pumps_running.max(pumps_running);
+        return;
+      }
+    }
+    if let Some(finished) = inner.pending.take() {
+      if inner.samples.len() == MAX_SAMPLES {
+        inner.samples.pop_front();
+      }
+      inner.samples.push_back(finished);
+    }
+    inner.pending = Some(Sample {
+      started: now,
+      current_temperature,
+      set_temperature,
+      heating,
+      pumps_running,
+    });
+  }
+
+  /// Samples from the last `hours`, oldest first, including the one still being filled in.
+  /// Each is timestamped by how many seconds ago its interval started.
+  pub fn to_json(&self, hours: u64, now: Instant) -> Value {
+    let inner = self.inner.lock().unwrap();
+    let scale = inner.scale.unwrap_or(TemperatureScale::Fahrenheit);
+    let in_scale = |t: &Temperature| match scale {
+      TemperatureScale::Fahrenheit => t.as_fahrenheit(),
+      TemperatureScale::Celsius => t.as_celsius(),
+    };
+    let window = Duration::from_secs(hours.min(MAX_HOURS) * 60 * 60);
+    let samples = inner.samples.iter()
+        .chain(inner.pending.as_ref())
+        .filter(|s| now.saturating_duration_since(s.started) < window)
+        .map(|s| json!({
+          "age_secs": now.saturating_duration_since(s.started).as_secs(),
+          "current_temperature": s.current_temperature.as_ref().map(in_scale),
+          "set_temperature": in_scale(&s.set_temperature),
+          "heating": s.heating,
+          "pumps_running": s.pumps_running,
+        }))
+        .collect::<Vec<_>>();
+    json!({
+      "interval_secs": SAMPLE_INTERVAL.as_secs(),
+      "temperature_scale": match scale {
+        TemperatureScale::Fahrenheit => "F",
+        TemperatureScale::Celsius => "C",
+      },
+      "samples": samples,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::message_types::{HeatingState, StatusUpdateMessage};
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use super::*;
+
+  #[test]
+  fn test_samples() -> anyhow::Result<()> {
+    let history = History::default();
+    let start = Instant::now();
+    let idle = StatusUpdateMessage::try_from([0u8; 24].as_slice())?.v1;
+    let mut heating = idle.clone();
+    heating.heating_state = ParsedEnum::new(HeatingState::Heating);
+
+    history.on_status(&idle, start);
+    history.on_status(&heating, start + Duration::from_secs(60));
+    history.on_status(&idle, start + Duration::from_secs(120));
+    history.on_status(&idle, start + SAMPLE_INTERVAL);
+
+    let now = start + SAMPLE_INTERVAL + Duration::from_secs(1);
+    let json = history.to_json(MAX_HOURS, now);
+    let samples = json["samples"].as_array().unwrap();
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[0]["heating"], true, "Heating part way through counts");
+    assert_eq!(samples[0]["age_secs"], SAMPLE_INTERVAL.as_secs() + 1);
+    assert_eq!(samples[1]["heating"], false);
+
+    for i in 2..MAX_SAMPLES as u32 + 10 {
+      history.on_status(&idle, start + SAMPLE_INTERVAL * i);
+    }
+    let now = start + SAMPLE_INTERVAL * (MAX_SAMPLES as u32 + 9) + Duration::from_secs(1);
+    assert_eq!(history.to_json(MAX_HOURS * 2, now)["samples"].as_array().unwrap().len(), MAX_SAMPLES);
+    assert_eq!(history.to_json(1, now)["samples"].as_array().unwrap().len(), 12);
+    Ok(())
+  }
+}
@@ -0,0 +1,560 @@
+//! Small local HTTP API for people without the BWA app:
+//!
+//! * `GET /` - a browser UI built on the endpoints below, see `web_ui.html`.
+//! * `GET /status` - latest status (and configuration, when known) as JSON.
+//! * `POST /temperature` - body `{"temperature": 101}` in the spa's current scale.
+//! * `POST /toggle/{item}` - toggle an item such as `pump1` or `light1`.
+//! * `GET /faults` - the main board's fault log, oldest first, see [crate::fault_log].
+//! * `GET /wifi` - network, connection state, signal strength and address.
+//! * `GET /history?hours=24` - temperatures, heating and pumps over time, see [crate::history].
+//! * `GET /events` - WebSocket stream of changes, see [crate::websocket].
+//! * `POST /ota` - firmware image as the body, installed then restarted into, see [crate::ota].
+//! * `POST /ota/pull` - body `{"sha256": "...", "url": "http://..."}`, fetch an image and do the
+//!   same.  Both need a token and the image's digest, see [crate::ota].
+//! * `GET /metrics` - [common_lib::metrics] in Prometheus' text format, for scraping.
+//!
+//! Requests are answered from the cached bus state and commands join the same outbound queue as
+//! relayed app messages, so they go out on the next ClearToSend.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::{io, thread};
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use balboa_spa_messages::temperature::{Temperature, TemperatureScale};
+use common_lib::metrics::Metrics;
+use crate::broadcaster::BroadcastReceiver;
+use crate::dual_stack::{bind_tcp, canonical};
+use crate::fault_log::fault_json;
+use crate::history::{DEFAULT_HISTORY_HOURS, History};
+use crate::http_request::{HeadError, HttpRequest};
+use crate::ota::{OtaError, OtaService, parse_sha256};
+use crate::relay_event::RelayEvent;
+use crate::server_stream::StreamAcceptor;
+use crate::spa_client::{PendingCommand, SpaClient, SpaClientError};
+use crate::spa_json::{configuration_json, parse_item_code, status_json};
+use crate::spa_state_cache::SpaStateCache;
+use crate::view_model::{Mode, ViewModel};
+use crate::websocket::{write_handshake, WebSocketSession};
+
+pub const DEFAULT_HTTP_PORT: u16 = 80;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Each connection has a thread, and WebSocket clients keep theirs, so this bounds memory.
+const MAX_CONNECTIONS: usize = 6;
+
+const WEB_UI: &str = include_str!("web_ui.html");
+
+/// Where `GET /wifi` gets its answer, see [crate::wifi_handler::WifiHandler::model_source].
+pub(crate) type WifiStatus = Arc<dyn Fn() -> Option<ViewModel> + Send + Sync>;
+
+/// Prepended to every metric name, so ours don't collide with anyone else's in Prometheus.
+const METRICS_PREFIX: &str = "balboa_";
+
+/// What requests are answered from, shared by every connection.
+#[derive(Clone)]
+pub(crate) struct HttpState {
+  pub cache: SpaStateCache,
+  pub history: History,
+  pub spa: SpaClient,
+  pub wifi: WifiStatus,
+  pub events_rx: BroadcastReceiver<RelayEvent>,
+  pub ota: Option<OtaService>,
+}
+
+pub(crate) struct HttpApiHandler {
+  port: u16,
+  acceptor: StreamAcceptor,
+  state: HttpState,
+  connections: Arc<AtomicUsize>,
+}
+
+impl HttpApiHandler {
+  pub fn new(port: u16, acceptor: StreamAcceptor, state: HttpState) -> Self {
+    Self { port, acceptor, state, connections: Arc::default() }
+  }
+
+  /// Only binds once `provisioned` fires: until then the captive portal may need the same port,
+  /// see [crate::captive_portal].  Returns right away if provisioning never finishes.
+  pub fn run_loop(self, provisioned: Receiver<()>) -> anyhow::Result<()> {
+    if provisioned.recv().is_err() {
+      return Ok(());
+    }
+    let listener = bind_tcp(self.port)?;
+    let refused = Metrics::global().counter("wifi_module.http_refused");
+    loop {
+      let (stream, peer) = listener.accept()?;
+      let peer = canonical(peer);
+      let Some(slot) = ConnectionSlot::claim(&self.connections) else {
+        warn!("Refusing HTTP connection from {peer}, {MAX_CONNECTIONS} already open");
+        refused.inc();
+        continue;
+      };
+      stream.set_read_timeout(Some(READ_TIMEOUT))?;
+      let connection = HttpConnection {
+        peer,
+        acceptor: self.acceptor.clone(),
+        state: self.state.clone(),
+      };
+      let spawned = thread::Builder::new()
+          .name(format!("Http-{peer}"))
+          .spawn(move || {
+            let _slot = slot;
+            if let Err(e) = connection.handle(stream) {
+              warn!("HTTP request from {peer} failed: {e}");
+            }
+          });
+      if let Err(e) = spawned {
+        warn!("Dropping HTTP connection from {peer}: {e}");
+      }
+    }
+  }
+}
+
+/// One of [MAX_CONNECTIONS], given back when dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+  fn claim(connections: &Arc<AtomicUsize>) -> Option<Self> {
+    connections.fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+      (open < MAX_CONNECTIONS).then_some(open + 1)
+    }).ok()?;
+    Some(Self(connections.clone()))
+  }
+}
+
+impl Drop for ConnectionSlot {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::AcqRel);
+  }
+}
+
+pub(crate) struct HttpResponse {
+  pub status: u16,
+  pub body: HttpBody,
+}
+
+pub(crate) enum HttpBody {
+  Json(Value),
+  Text { content_type: &'static str, text: String },
+}
+
+impl HttpResponse {
+  fn ok(body: Value) -> Self {
+    Self::json(200, body)
+  }
+
+  fn json(status: u16, body: Value) -> Self {
+    Self { status, body: HttpBody::Json(body) }
+  }
+
+  fn text(content_type: &'static str, text: impl ToString) -> Self {
+    Self { status: 200, body: HttpBody::Text { content_type, text: text.to_string() } }
+  }
+
+  fn error(status: u16, message: impl ToString) -> Self {
+    Self::json(status, json!({ "error": message.to_string() }))
+  }
+
+  fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+    let (content_type, body) = match &self.body {
+      HttpBody::Json(value) => ("application/json", value.to_string()),
+      HttpBody::Text { content_type, text } => (*content_type, text.clone()),
+    };
+    let reason = match self.status {
+      200 => "OK",
+      202 => "Accepted",
+      400 => "Bad Request",
+      404 => "Not Found",
+      405 => "Method Not Allowed",
+      409 => "Conflict",
+      411 => "Length Required",
+      431 => "Request Header Fields Too Large",
+      500 => "Internal Server Error",
+      503 => "Service Unavailable",
+      _ => "Error",
+    };
+    write!(
+      writer,
+      "HTTP/1.1 {} {reason}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+      self.status,
+      body.len())?;
+    writer.flush()
+  }
+}
+
+struct HttpConnection {
+  peer: SocketAddr,
+  acceptor: StreamAcceptor,
+  state: HttpState,
+}
+
+impl HttpConnection {
+  fn handle(self, stream: TcpStream) -> anyhow::Result<()> {
+    let stream = self.acceptor.accept(stream)?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = match HttpRequest::read_head(&mut reader) {
+      Ok(request) => request,
+      Err(e) => {
+        if let Some(head_error) = e.downcast_ref::<HeadError>() {
+          HttpResponse::error(head_error.status(), head_error).write_to(&mut &stream)?;
+        }
+        return Err(e);
+      }
+    };
+    debug!("{} {} from {}", request.method, request.path, self.peer);
+    if request.method == "POST" && request.path == "/ota" {
+      // Far too big for read_body, it's streamed straight to the OTA target instead.
+      let (response, installed) = self.push_ota(&request, &mut reader);
+      response.write_to(&mut &stream)?;
+      if let (Some(ota), true) = (&self.state.ota, installed) {
+        ota.restart();
+      }
+      return Ok(());
+    }
+    request.read_body(&mut reader)?;
+    if request.method == "GET" && request.path == "/events" {
+      if let Some(key) = request.header("sec-websocket-key") {
+        info!("Streaming events to {}", self.peer);
+        write_handshake(&mut &stream, key)?;
+        let session = WebSocketSession {
+          stream,
+          cache: self.state.cache,
+          history: self.state.history,
+          events_rx: self.state.events_rx,
+        };
+        session.run_loop();
+        return Ok(());
+      }
+    }
+    let response = route(&request, &self.state);
+    response.write_to(&mut &stream)?;
+    Ok(())
+  }
+
+  /// The response, and whether a new image was installed that we should restart into once
+  /// it's been sent.
+  fn push_ota(&self, request: &HttpRequest, reader: &mut impl BufRead) -> (HttpResponse, bool) {
+    let Some(ota) = &self.state.ota else {
+      return (HttpResponse::error(404, "Firmware updates are not enabled"), false);
+    };
+    if !ota.authorize(request.header("authorization")) {
+      warn!("Rejecting firmware update from {}, bad or missing token", self.peer);
+      return (unauthorized(), false);
+    }
+    let Some(sha256) = request.header("x-firmware-sha256").and_then(parse_sha256) else {
+      return (HttpResponse::error(400, "Expected the image's digest as X-Firmware-Sha256"), false);
+    };
+    let Ok(Some(total)) = request.content_length() else {
+      return (HttpResponse::error(411, "Expected the image size as Content-Length"), false);
+    };
+    info!("Firmware update pushed by {}", self.peer);
+    match ota.install(reader, Some(total), &sha256) {
+      Ok(()) => (HttpResponse::ok(json!({ "installed": true })), true),
+      Err(e) => (ota_error(e), false),
+    }
+  }
+}
+
+fn route(request: &HttpRequest, state: &HttpState) -> HttpResponse {
+  let HttpState { cache, history, spa, wifi, ota, .. } = state;
+  let segments = request.path.trim_matches('/').split('/').collect::<Vec<_>>();
+  match (request.method.as_str(), segments.as_slice()) {
+    ("GET", [""]) => HttpResponse::text("text/html; charset=utf-8", WEB_UI),
+    ("GET", ["status"]) => get_status(cache),
+    ("GET", ["wifi"]) => get_wifi(wifi),
+    ("GET", ["faults"]) => get_faults(cache),
+    ("GET", ["history"]) => get_history(request, history),
+    ("GET", ["metrics"]) => get_metrics(),
+    ("POST", ["temperature"]) => post_temperature(request, spa),
+    ("POST", ["toggle", item]) => post_toggle(item, spa),
+    ("GET", ["events"]) => HttpResponse::error(400, "Expected a WebSocket upgrade"),
+    ("POST", ["ota", "pull"]) => post_ota_pull(request, ota.as_ref()),
+    (_, ["status"] | ["faults"] | ["temperature"] | ["toggle", _] | ["events"] | ["ota"] | ["ota", "pull"] | ["metrics"] | ["history"] | ["wifi"] | [""]) =>
+      HttpResponse::error(405, "Method not allowed"),
+    _ => HttpResponse::error(404, format!("No such endpoint {}", request.path)),
+  }
+}
+
+fn get_status(cache: &SpaStateCache) -> HttpResponse {
+  let state = cache.snapshot();
+  let Some(status) = state.status else {
+    return HttpResponse::error(503, "No status received from the spa yet");
+  };
+  let mut body = status_json(&status.v1);
+  if let Some(configuration) = &state.configuration {
+    body["configuration"] = configuration_json(configuration);
+  }
+  HttpResponse::ok(body)
+}
+
+fn get_wifi(wifi: &WifiStatus) -> HttpResponse {
+  let Some(model) = wifi() else {
+    return HttpResponse::ok(json!({ "mode": "initializing" }));
+  };
+  let body = match &model.mode {
+    Mode::Nominal(nominal) => {
+      let info = nominal.connection_info.as_ref();
+      json!({
+        "mode": "nominal",
+        "network": nominal.network_name,
+        "state": format!("{:?}", nominal.connection_state),
+        "rssi": info.map(|info| info.rssi),
+        "ip": info.and_then(|info| info.ip).map(|ip| ip.to_string()),
+        "channel": info.map(|info| info.channel),
+      })
+    }
+    Mode::Initializing => json!({ "mode": "initializing" }),
+    Mode::NeedsProvisioning(_) => json!({ "mode": "needs_provisioning" }),
+    Mode::TroubleAssociating(trouble) =>
+      json!({ "mode": "trouble_associating", "error": format!("{:?}", trouble.error) }),
+    Mode::UnrecoverableError(e) => json!({ "mode": "error", "error": e }),
+  };
+  HttpResponse::ok(body)
+}
+
+fn get_faults(cache: &SpaStateCache) -> HttpResponse {
+  let fault_log = cache.fault_log();
+  let Some(total_entries) = fault_log.total_entries() else {
+    return HttpResponse::error(503, "Fault log not received from the spa yet");
+  };
+  let entries = fault_log.entries().iter().map(fault_json).collect::<Vec<_>>();
+  HttpResponse::ok(json!({
+    "total_entries": total_entries,
+    "entries": entries,
+  }))
+}
+
+fn get_history(request: &HttpRequest, history: &History) -> HttpResponse {
+  let hours = match request.query_param("hours").map(str::parse) {
+    None => DEFAULT_HISTORY_HOURS,
+    Some(Ok(hours)) => hours,
+    Some(Err(_)) => return HttpResponse::error(400, "Expected hours=<whole number>"),
+  };
+  HttpResponse::ok(history.to_json(hours, Instant::now()))
+}
+
+fn get_metrics() -> HttpResponse {
+  let text = Metrics::global().snapshot().to_prometheus(METRICS_PREFIX);
+  HttpResponse::text("text/plain; version=0.0.4", text)
+}
+
+fn post_temperature(request: &HttpRequest, spa: &SpaClient) -> HttpResponse {
+  let Some(status) = spa.status() else {
+    return HttpResponse::error(503, "Temperature scale not known yet");
+  };
+  let value = match serde_json::from_slice::<Value>(&request.body) {
+    Ok(body) => body.get("temperature").and_then(Value::as_f64),
+    Err(_) => None,
+  };
+  let Some(value) = value else {
+    return HttpResponse::error(400, "Expected {\"temperature\": <number>}");
+  };
+  let target = match status.v1.set_temperature.raw_scale {
+    TemperatureScale::Fahrenheit => Temperature::from_fahrenheit(value),
+    TemperatureScale::Celsius => Temperature::from_celsius(value),
+  };
+  queued(spa.set_temperature(target))
+}
+
+fn post_toggle(item: &str, spa: &SpaClient) -> HttpResponse {
+  let Some(item_code) = parse_item_code(item) else {
+    return HttpResponse::error(404, format!("Unknown item {item}"));
+  };
+  queued(spa.toggle(item_code))
+}
+
+fn post_ota_pull(request: &HttpRequest, ota: Option<&OtaService>) -> HttpResponse {
+  let Some(ota) = ota else {
+    return HttpResponse::error(404, "Firmware updates are not enabled");
+  };
+  if !ota.authorize(request.header("authorization")) {
+    return unauthorized();
+  }
+  let Ok(body) = serde_json::from_slice::<Value>(&request.body) else {
+    return HttpResponse::error(400, "Expected {\"sha256\": <string>, \"url\": <string>}");
+  };
+  let Some(sha256) = body.get("sha256").and_then(Value::as_str).and_then(parse_sha256) else {
+    return HttpResponse::error(400, "Expected the image's digest as {\"sha256\": <hex>}");
+  };
+  let url = match body.get("url") {
+    None => None,
+    Some(Value::String(url)) => Some(url.clone()),
+    Some(_) => return HttpResponse::error(400, "Expected {\"url\": <string>}"),
+  };
+  let Some(url) = url.or_else(|| ota.pull_url().map(str::to_owned)) else {
+    return HttpResponse::error(400, "No URL given and none configured");
+  };
+  if ota.is_busy() {
+    return ota_error(OtaError::Busy);
+  }
+
+  let ota = ota.clone();
+  let response = HttpResponse::json(202, json!({ "pulling": url }));
+  thread::Builder::new()
+      .name("OtaPull".into())
+      .spawn(move || {
+        match ota.pull(&url, &sha256) {
+          Ok(()) => ota.restart(),
+          Err(e) => warn!("Firmware update from {url} failed: {e}"),
+        }
+      })
+      .unwrap();
+  response
+}
+
+fn unauthorized() -> HttpResponse {
+  HttpResponse::error(401, "Expected the OTA token as Authorization: Bearer <token>")
+}
+
+fn ota_error(e: OtaError) -> HttpResponse {
+  let status = match e {
+    OtaError::Busy => 409,
+    OtaError::BadUrl(_) | OtaError::DigestMismatch => 400,
+    _ => 500,
+  };
+  HttpResponse::error(status, e)
+}
+
+/// We don't hang around for the spa to confirm, that's what `/status` and `/events` are for.
+fn queued(result: Result<PendingCommand, SpaClientError>) -> HttpResponse {
+  match result {
+    Ok(_) => HttpResponse::json(202, json!({ "queued": true })),
+    Err(e @ SpaClientError::BadTemperature(_)) => HttpResponse::error(400, e),
+    Err(e) => HttpResponse::error(503, e),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc::{sync_channel, SyncSender};
+  use balboa_spa_messages::message_types::{FaultResponseMessage, MessageType, StatusUpdateMessage};
+  use crate::broadcaster::broadcast_channel;
+  use crate::command::Command;
+  use crate::ota::FileOtaTarget;
+  use super::*;
+
+  fn request(raw: &str) -> HttpRequest {
+    HttpRequest::read_from(&mut raw.as_bytes()).unwrap()
+  }
+
+  fn json_body(response: &HttpResponse) -> &Value {
+    match &response.body {
+      HttpBody::Json(value) => value,
+      HttpBody::Text { .. } => panic!("Expected a JSON body"),
+    }
+  }
+
+  fn state(commands_tx: SyncSender<Command>, ota: Option<OtaService>) -> HttpState {
+    let cache = SpaStateCache::default();
+    HttpState {
+      spa: SpaClient::new(commands_tx, cache.clone()),
+      cache,
+      history: History::default(),
+      wifi: Arc::new(|| None),
+      events_rx: broadcast_channel(1).1,
+      ota,
+    }
+  }
+
+  #[test]
+  fn test_routes() -> anyhow::Result<()> {
+    let (commands_tx, commands_rx) = sync_channel(4);
+    let state = state(commands_tx, None);
+    let cache = &state.cache;
+
+    let response = route(&request("GET /status HTTP/1.1\r\n\r\n"), &state);
+    assert_eq!(response.status, 503);
+
+    let status = StatusUpdateMessage::try_from([0u8; 24].as_slice())?;
+    cache.update(&MessageType::StatusUpdate(status));
+    let response = route(&request("GET /status HTTP/1.1\r\n\r\n"), &state);
+    assert_eq!(response.status, 200);
+    assert_eq!(json_body(&response)["temperature_scale"], "F");
+
+    assert_eq!(route(&request("GET /faults HTTP/1.1\r\n\r\n"), &state).status, 503);
+    let fault = FaultResponseMessage::try_from([1u8, 0, 16, 2, 12, 30, 0, 100, 0, 0].as_slice())?;
+    cache.fault_log().update(&fault);
+    let response = route(&request("GET /faults HTTP/1.1\r\n\r\n"), &state);
+    assert_eq!(response.status, 200);
+    assert_eq!(json_body(&response)["entries"][0]["code"], 16);
+
+    let set_temperature = |temperature: u32| {
+      let body = format!(r#"{{"temperature": {temperature}}}"#);
+      let raw = format!("POST /temperature HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+      route(&request(&raw), &state).status
+    };
+    assert_eq!(set_temperature(100), 503, "Limits not known yet");
+    cache.update(&MessageType::Settings0x04Response([0, 0, 50, 104, 80, 104].as_slice().try_into()?));
+    assert_eq!(set_temperature(110), 400);
+    assert_eq!(set_temperature(100), 202);
+    assert!(matches!(
+        commands_rx.try_recv()?,
+        Command::Spa(request) if matches!(request.mt, MessageType::SetTemperatureRequest { .. })));
+
+    let response = route(&request("POST /toggle/pump2 HTTP/1.1\r\n\r\n"), &state);
+    assert_eq!(response.status, 202);
+    assert!(matches!(
+        commands_rx.try_recv()?,
+        Command::Spa(request) if matches!(request.mt, MessageType::ToggleItemRequest { .. })));
+
+    assert_eq!(route(&request("POST /toggle/jets HTTP/1.1\r\n\r\n"), &state).status, 404);
+    assert_eq!(route(&request("GET /toggle/pump1 HTTP/1.1\r\n\r\n"), &state).status, 405);
+    assert_eq!(route(&request("POST /ota/pull HTTP/1.1\r\n\r\n"), &state).status, 404);
+    assert_eq!(route(&request("GET /ota HTTP/1.1\r\n\r\n"), &state).status, 405);
+
+    let response = route(&request("GET /history?hours=1 HTTP/1.1\r\n\r\n"), &state);
+    assert_eq!(response.status, 200);
+    assert_eq!(json_body(&response)["samples"].as_array().map(Vec::len), Some(0));
+    assert_eq!(route(&request("GET /history?hours=x HTTP/1.1\r\n\r\n"), &state).status, 400);
+
+    Metrics::global().counter("wifi_module.test_requests").inc();
+    let response = route(&request("GET /metrics HTTP/1.1\r\n\r\n"), &state);
+    assert_eq!(response.status, 200);
+    assert!(matches!(
+        &response.body,
+        HttpBody::Text { text, .. } if text.contains("balboa_wifi_module_test_requests 1\n")));
+
+    let response = route(&request("GET / HTTP/1.1\r\n\r\n"), &state);
+    assert!(matches!(response.body, HttpBody::Text { content_type, .. } if content_type.starts_with("text/html")));
+    let response = route(&request("GET /wifi HTTP/1.1\r\n\r\n"), &state);
+    assert_eq!(json_body(&response)["mode"], "initializing");
+    Ok(())
+  }
+  #[test]
+  fn test_ota_pull_needs_token_and_digest() {
+    let (commands_tx, _commands_rx) = sync_channel(4);
+    let target = FileOtaTarget::new(std::env::temp_dir().join("ota-route-test.bin"));
+    let ota = OtaService::new(Box::new(target), "s3cret".to_owned(), None, |_| {});
+    let state = state(commands_tx, Some(ota));
+    let pull = |headers: &str, body: &str| {
+      let raw = format!(
+        "POST /ota/pull HTTP/1.1\r\n{headers}Content-Length: {}\r\n\r\n{body}",
+        body.len());
+      route(&request(&raw), &state).status
+    };
+
+    let sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    let body = format!(r#"{{"sha256": "{sha256}"}}"#);
+    assert_eq!(pull("", &body), 401);
+    assert_eq!(pull("Authorization: Bearer wrong\r\n", &body), 401);
+    assert_eq!(pull("Authorization: Bearer s3cret\r\n", r#"{"url": "http://10.0.0.2/fw.bin"}"#), 400);
+    assert_eq!(pull("Authorization: Bearer s3cret\r\n", &body), 400, "No URL given or configured");
+  }
+
+  #[test]
+  fn test_connection_cap() {
+    let connections = Arc::default();
+    let slots = (0..MAX_CONNECTIONS)
+        .map(|_| ConnectionSlot::claim(&connections).unwrap())
+        .collect::<Vec<_>>();
+    assert!(ConnectionSlot::claim(&connections).is_none());
+    drop(slots);
+    assert!(ConnectionSlot::claim(&connections).is_some());
+  }
+}
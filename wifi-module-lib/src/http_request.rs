@@ -0,0 +1,167 @@
+//! Bare minimum HTTP/1.1 request parsing shared by our small embedded servers.
+
+use std::io::{BufRead, Read};
+
+/// Nothing we accept comes close, this just bounds what a misbehaving client can make us buffer.
+const MAX_BODY_LEN: usize = 4096;
+
+/// Likewise for the request line and each header line, including the line ending.
+const MAX_LINE_LEN: usize = 2048;
+
+/// Browsers send about a dozen.
+const MAX_HEADERS: usize = 32;
+
+/// Why a request's head couldn't be read, for answering with the right status.
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum HeadError {
+  #[error("Malformed request line: {0:?}")]
+  Malformed(String),
+
+  #[error("Line longer than {MAX_LINE_LEN} bytes")]
+  LineTooLong,
+
+  #[error("More than {MAX_HEADERS} headers")]
+  TooManyHeaders,
+}
+
+impl HeadError {
+  /// The captive portal doesn't bother answering.
+  #[cfg_attr(not(feature = "http"), allow(dead_code))]
+  pub fn status(&self) -> u16 {
+    match self {
+      HeadError::Malformed(_) => 400,
+      HeadError::LineTooLong | HeadError::TooManyHeaders => 431,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub(crate) struct HttpRequest {
+  pub method: String,
+  pub path: String,
+
+  /// Whatever followed the `?`, if anything.  The captive portal has no use for it.
+  #[cfg_attr(not(feature = "http"), allow(dead_code))]
+  pub query: Option<String>,
+  pub headers: Vec<(String, String)>,
+  pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+  pub fn read_from(reader: &mut impl BufRead) -> anyhow::Result<Self> {
+    let mut request = Self::read_head(reader)?;
+    request.read_body(reader)?;
+    Ok(request)
+  }
+
+  /// Reads up to the body, which is left for the caller, e.g. to stream something too large
+  /// for [Self::read_body].
+  pub fn read_head(reader: &mut impl BufRead) -> anyhow::Result<Self> {
+    let mut line = String::new();
+    read_line(reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+      return Err(HeadError::Malformed(line.trim_end().to_owned()).into());
+    };
+    let (path, query) = match path.split_once('?') {
+      Some((path, query)) => (path, Some(query.to_owned())),
+      None => (path, None),
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+    let headers = read_headers(reader)?;
+    Ok(Self { method, path, query, headers, body: vec![] })
+  }
+
+  pub fn read_body(&mut self, reader: &mut impl BufRead) -> anyhow::Result<()> {
+    let content_length = self.content_length()?.unwrap_or(0);
+    if content_length > MAX_BODY_LEN {
+      anyhow::bail!("Body too long: {content_length}");
+    }
+    self.body.resize(content_length, 0);
+    reader.read_exact(&mut self.body)?;
+    Ok(())
+  }
+
+  pub fn content_length(&self) -> anyhow::Result<Option<usize>> {
+    Ok(self.header("content-length").map(|v| v.parse()).transpose()?)
+  }
+
+  pub fn header(&self, name: &str) -> Option<&str> {
+    header(&self.headers, name)
+  }
+
+  /// The value of `name=value` in the query string, taken as is (we've no use for escapes).
+  #[cfg(feature = "http")]
+  pub fn query_param(&self, name: &str) -> Option<&str> {
+    self.query.as_deref()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+  }
+}
+
+/// Header lines up to and including the blank line ending them, with names lowercased.  Also
+/// used to parse responses when we're the client.
+pub(crate) fn read_headers(reader: &mut impl BufRead) -> anyhow::Result<Vec<(String, String)>> {
+  let mut headers = vec![];
+  let mut line = String::new();
+  loop {
+    line.clear();
+    if read_line(reader, &mut line)? == 0 {
+      anyhow::bail!("Connection closed mid-headers");
+    }
+    let header = line.trim_end();
+    if header.is_empty() {
+      return Ok(headers);
+    }
+    if headers.len() == MAX_HEADERS {
+      Err(HeadError::TooManyHeaders)?;
+    }
+    if let Some((name, value)) = header.split_once(':') {
+      headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+    }
+  }
+}
+
+/// [BufRead::read_line] that gives up on lines longer than [MAX_LINE_LEN] rather than
+/// buffer all of them.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> anyhow::Result<usize> {
+  let len = reader.by_ref().take(MAX_LINE_LEN as u64).read_line(line)?;
+  if len == MAX_LINE_LEN && !line.ends_with('\n') {
+    Err(HeadError::LineTooLong)?;
+  }
+  Ok(len)
+}
+
+pub(crate) fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+  headers.iter()
+      .find(|(n, _)| n == name)
+      .map(|(_, v)| v.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn head_error(raw: &str) -> Option<u16> {
+    let e = HttpRequest::read_head(&mut raw.as_bytes()).unwrap_err();
+    e.downcast_ref::<HeadError>().map(HeadError::status)
+  }
+
+  #[test]
+  fn test_head_limits() {
+    let request = HttpRequest::read_head(&mut "GET /status?x=1 HTTP/1.1\r\nHost: spa\r\n\r\n".as_bytes())
+        .unwrap();
+    assert_eq!((request.method.as_str(), request.path.as_str()), ("GET", "/status"));
+    assert_eq!(request.header("host"), Some("spa"));
+
+    assert_eq!(head_error("\r\n\r\n"), Some(400));
+    let long_path = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+    assert_eq!(head_error(&long_path), Some(431));
+    let long_header = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+    assert_eq!(head_error(&long_header), Some(431));
+    let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEADERS + 1));
+    assert_eq!(head_error(&many_headers), Some(431));
+    let enough_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEADERS));
+    assert!(HttpRequest::read_head(&mut enough_headers.as_bytes()).is_ok());
+  }
+}
@@ -0,0 +1,100 @@
+//! Optional static IPv4 configuration for the station interface, for installations where the
+//! router can't hand out a DHCP reservation but the spa still needs a stable address.
+//!
+//! Kept in [crate::settings::STATIC_IP] and applied through
+//! [crate::wifi_manager::WifiManager::set_static_ip] before every connection attempt.  Nothing
+//! saved means DHCP, as before this existed.
+
+use std::net::Ipv4Addr;
+use anyhow::{anyhow, Context};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticIpConfig {
+  pub ip: Ipv4Addr,
+
+  /// Subnet mask in CIDR notation, e.g. 24 for 255.255.255.0.
+  pub prefix_len: u8,
+
+  pub gateway: Ipv4Addr,
+
+  pub dns: Option<Ipv4Addr>,
+
+  pub secondary_dns: Option<Ipv4Addr>,
+}
+
+impl StaticIpConfig {
+  pub fn new(ip: Ipv4Addr, prefix_len: u8, gateway: Ipv4Addr) -> Self {
+    Self { ip, prefix_len, gateway, dns: None, secondary_dns: None }
+  }
+
+  /// The first call sets the primary server, the second the secondary.
+  pub fn add_dns(mut self, server: Ipv4Addr) -> Self {
+    if self.dns.is_none() {
+      self.dns = Some(server);
+    } else {
+      self.secondary_dns = Some(server);
+    }
+    self
+  }
+}
+
+/// Serialized form for settings: `ip=<addr>/<len>`, `gateway=<addr>` and up to two
+/// `dns=<addr>` lines.
+pub fn encode_ip_config(config: &StaticIpConfig) -> String {
+  let mut data = format!("ip={}/{}\ngateway={}\n", config.ip, config.prefix_len, config.gateway);
+  for dns in config.dns.iter().chain(&config.secondary_dns) {
+    data.push_str(&format!("dns={dns}\n"));
+  }
+  data
+}
+
+pub fn decode_ip_config(data: &str) -> anyhow::Result<StaticIpConfig> {
+  let mut ip = None;
+  let mut gateway = None;
+  let mut dns = vec![];
+  for line in data.lines().map(str::trim).filter(|l| !l.is_empty()) {
+    match line.split_once('=') {
+      Some(("ip", value)) => {
+        let (addr, prefix_len) = value.split_once('/')
+            .ok_or_else(|| anyhow!("Expected <addr>/<prefix len>, got {value:?}"))?;
+        let prefix_len: u8 = prefix_len.parse()
+            .with_context(|| format!("Bad prefix length {prefix_len:?}"))?;
+        if prefix_len > 32 {
+          return Err(anyhow!("Bad prefix length {prefix_len}"));
+        }
+        ip = Some((parse_addr(addr)?, prefix_len));
+      }
+      Some(("gateway", value)) => gateway = Some(parse_addr(value)?),
+      Some(("dns", value)) if dns.len() < 2 => dns.push(parse_addr(value)?),
+      _ => return Err(anyhow!("Unexpected line {line:?}")),
+    }
+  }
+  let (ip, prefix_len) = ip.ok_or_else(|| anyhow!("Missing ip"))?;
+  let gateway = gateway.ok_or_else(|| anyhow!("Missing gateway"))?;
+  Ok(dns.into_iter().fold(StaticIpConfig::new(ip, prefix_len, gateway), StaticIpConfig::add_dns))
+}
+
+fn parse_addr(addr: &str) -> anyhow::Result<Ipv4Addr> {
+  addr.parse().with_context(|| format!("Bad address {addr:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_round_trip() -> anyhow::Result<()> {
+    let config = StaticIpConfig::new(Ipv4Addr::new(192, 168, 1, 50), 24, Ipv4Addr::new(192, 168, 1, 1))
+        .add_dns(Ipv4Addr::new(1, 1, 1, 1))
+        .add_dns(Ipv4Addr::new(8, 8, 8, 8));
+    assert_eq!(decode_ip_config(&encode_ip_config(&config))?, config);
+
+    let minimal = decode_ip_config("ip=10.0.0.5/8\ngateway=10.0.0.1\n")?;
+    assert_eq!(minimal, StaticIpConfig::new(Ipv4Addr::new(10, 0, 0, 5), 8, Ipv4Addr::new(10, 0, 0, 1)));
+
+    assert!(decode_ip_config("ip=10.0.0.5\ngateway=10.0.0.1").is_err());
+    assert!(decode_ip_config("ip=10.0.0.5/33\ngateway=10.0.0.1").is_err());
+    assert!(decode_ip_config("ip=10.0.0.5/8").is_err());
+    Ok(())
+  }
+}
@@ -3,11 +3,44 @@ mod handling_error;
 mod app_state;
 mod wifi_state_machine;
 mod discovery_handler;
-mod tcp_handler;
+mod mdns;
+mod dual_stack;
+pub mod tcp_handler;
+pub mod relay_auth;
+pub mod relay_hello;
+mod server_stream;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "http")]
+pub mod http_handler;
+mod http_request;
+#[cfg(feature = "http")]
+pub mod ota;
+#[cfg(feature = "http")]
+mod spa_json;
+mod spa_state_cache;
+#[cfg(feature = "http")]
+mod websocket;
+#[cfg(feature = "http")]
+mod history;
+pub mod spa_client;
+mod confirmations;
 mod command;
+mod outbound_queue;
 mod broadcaster;
 pub mod advertisement;
 pub mod wifi_manager;
+pub mod ip_config;
+pub mod settings;
+pub mod time_sync;
+pub mod fault_log;
+pub mod config_poller;
+pub mod alerts;
+pub mod schedule;
+pub mod frost;
+pub mod captive_portal;
 mod relay_event;
 pub mod view_model;
 mod wifi_handler;
@@ -0,0 +1,444 @@
+//! Just enough of a multicast DNS responder (RFC 6762 / 6763) to make the module findable by
+//! name: `<hostname>.local` resolves to us (over IPv6 too, where we have it), and browsing for
+//! `_balboa._tcp` (or `_http._tcp` when the HTTP API is enabled) finds the relay.
+//!
+//! We only ever answer for our own names and never probe for conflicts, so pick a unique
+//! hostname if there's more than one module on the network.
+
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use crate::dual_stack::HostAddrs;
+
+pub(crate) const MDNS_PORT: u16 = 5353;
+pub(crate) const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub(crate) const MDNS_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Short enough that clients notice quickly if we go away or change address.
+const TTL_SECS: u32 = 120;
+
+/// The most we may give legacy unicast queriers, whose caches don't know to flush, see RFC 6762
+/// section 6.7.
+const LEGACY_TTL_SECS: u32 = 10;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+
+/// Set on records that only we can answer for, so caches can drop stale copies.
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// Also stops us chasing compression pointers around in circles.
+const MAX_NAME_LABELS: usize = 32;
+
+const SERVICES_META_QUERY: &str = "_services._dns-sd._udp.local";
+
+/// A DNS-SD service we offer, such as `_balboa._tcp`.
+#[derive(Debug, Clone)]
+pub(crate) struct MdnsService {
+  pub service_type: String,
+  pub port: u16,
+  pub txt: Vec<String>,
+}
+
+impl MdnsService {
+  pub fn new(service_type: impl Into<String>, port: u16) -> Self {
+    Self { service_type: service_type.into(), port, txt: vec![] }
+  }
+
+  pub fn add_txt(mut self, entry: impl Into<String>) -> Self {
+    self.txt.push(entry.into());
+    self
+  }
+
+  fn fqdn(&self) -> String {
+    format!("{}.local", self.service_type)
+  }
+}
+
+#[derive(Debug)]
+pub(crate) struct MdnsResponder {
+  instance: String,
+  hostname: String,
+  services: Vec<MdnsService>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Question {
+  name: String,
+  qtype: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Record {
+  name: String,
+  rtype: u16,
+  unique: bool,
+  rdata: Vec<u8>,
+}
+
+impl MdnsResponder {
+  /// `instance` is the human-readable service instance name, `hostname` excludes `.local`.
+  pub fn new(instance: &str, hostname: &str, services: Vec<MdnsService>) -> Self {
+    Self {
+      instance: instance.to_owned(),
+      hostname: format!("{hostname}.local"),
+      services,
+    }
+  }
+
+  /// Whether `query` asks about any of our names, which is cheaper to find out than the
+  /// addresses [Self::answer] needs.
+  pub fn is_about_us(&self, query: &[u8]) -> bool {
+    parse_query(query).is_some_and(|(_, questions)| {
+      questions.iter().any(|q| self.is_our_name(&q.name))
+    })
+  }
+
+  fn is_our_name(&self, name: &str) -> bool {
+    name.eq_ignore_ascii_case(&self.hostname)
+        || name.eq_ignore_ascii_case(SERVICES_META_QUERY)
+        || self.services.iter().any(|service| {
+          name.eq_ignore_ascii_case(&service.fqdn())
+              || name.eq_ignore_ascii_case(&self.instance_name(service))
+        })
+  }
+
+  /// Response to `query`, or None if it isn't a query or isn't about us.  Replies to legacy
+  /// unicast queriers (anything not sending from [MDNS_PORT]) must set `legacy` so that the
+  /// id and questions are echoed as a regular DNS server would.
+  pub fn answer(&self, query: &[u8], addrs: HostAddrs, legacy: bool) -> Option<Vec<u8>> {
+    let (id, questions) = parse_query(query)?;
+    let mut answers = vec![];
+    let mut additional = vec![];
+    for question in &questions {
+      self.answer_question(question, addrs, &mut answers, &mut additional);
+    }
+    if answers.is_empty() {
+      return None;
+    }
+    let answered = answers.iter().cloned().collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+    additional.retain(|r| !answered.contains(r) && seen.insert(r.clone()));
+    let mut seen = HashSet::new();
+    answers.retain(|r| seen.insert(r.clone()));
+    if legacy {
+      Some(encode_response(id, &questions, &answers, &additional, true))
+    } else {
+      Some(encode_response(0, &[], &answers, &additional, false))
+    }
+  }
+
+  fn answer_question(
+      &self,
+      question: &Question,
+      addrs: HostAddrs,
+      answers: &mut Vec<Record>,
+      additional: &mut Vec<Record>,
+  ) {
+    let wants = |rtype| question.qtype == rtype || question.qtype == TYPE_ANY;
+    if question.name.eq_ignore_ascii_case(&self.hostname) {
+      // Whichever family wasn't asked about goes along as additional, see RFC 6762 section 6.2.
+      for record in self.address_records(addrs) {
+        if wants(record.rtype) {
+          answers.push(record);
+        } else {
+          additional.push(record);
+        }
+      }
+    }
+    if question.name.eq_ignore_ascii_case(SERVICES_META_QUERY) && wants(TYPE_PTR) {
+      for service in &self.services {
+        answers.push(Record {
+          name: SERVICES_META_QUERY.to_owned(),
+          rtype: TYPE_PTR,
+          unique: false,
+          rdata: encode_name(&service.fqdn()),
+        });
+      }
+    }
+    for service in &self.services {
+      if question.name.eq_ignore_ascii_case(&service.fqdn()) && wants(TYPE_PTR) {
+        answers.push(self.ptr_record(service));
+        additional.extend([self.srv_record(service), self.txt_record(service)]);
+        additional.extend(self.address_records(addrs));
+      }
+      if question.name.eq_ignore_ascii_case(&self.instance_name(service)) {
+        if wants(TYPE_SRV) {
+          answers.push(self.srv_record(service));
+          additional.extend(self.address_records(addrs));
+        }
+        if wants(TYPE_TXT) {
+          answers.push(self.txt_record(service));
+        }
+      }
+    }
+  }
+
+  fn instance_name(&self, service: &MdnsService) -> String {
+    format!("{}.{}", self.instance, service.fqdn())
+  }
+
+  fn address_records(&self, addrs: HostAddrs) -> Vec<Record> {
+    let record = |rtype, rdata| Record {
+      name: self.hostname.clone(),
+      rtype,
+      unique: true,
+      rdata,
+    };
+    let mut records = vec![];
+    if let Some(v4) = addrs.v4 {
+      records.push(record(TYPE_A, v4.octets().to_vec()));
+    }
+    if let Some(v6) = addrs.v6 {
+      records.push(record(TYPE_AAAA, v6.octets().to_vec()));
+    }
+    records
+  }
+
+  fn ptr_record(&self, service: &MdnsService) -> Record {
+    Record {
+      name: service.fqdn(),
+      rtype: TYPE_PTR,
+      unique: false,
+      rdata: encode_name(&self.instance_name(service)),
+    }
+  }
+
+  fn srv_record(&self, service: &MdnsService) -> Record {
+    // Priority and weight are meaningless with a single target.
+    let mut rdata = vec![0, 0, 0, 0];
+    rdata.extend(service.port.to_be_bytes());
+    rdata.extend(encode_name(&self.hostname));
+    Record {
+      name: self.instance_name(service),
+      rtype: TYPE_SRV,
+      unique: true,
+      rdata,
+    }
+  }
+
+  fn txt_record(&self, service: &MdnsService) -> Record {
+    let mut rdata = vec![];
+    for entry in &service.txt {
+      let entry = &entry.as_bytes()[..entry.len().min(255)];
+      rdata.push(entry.len() as u8);
+      rdata.extend(entry);
+    }
+    if rdata.is_empty() {
+      // An empty TXT record still has to hold a single empty string.
+      rdata.push(0);
+    }
+    Record {
+      name: self.instance_name(service),
+      rtype: TYPE_TXT,
+      unique: true,
+      rdata,
+    }
+  }
+}
+
+/// Plain unicast DNS response claiming that every name asked about resolves to `addr`, which is
+/// how captive portals get phones to show them whatever URL they try.
+pub(crate) fn answer_everything(query: &[u8], addr: Ipv4Addr) -> Option<Vec<u8>> {
+  let (id, questions) = parse_query(query)?;
+  let answers = questions.iter()
+      .filter(|q| q.qtype == TYPE_A || q.qtype == TYPE_ANY)
+      .map(|q| Record {
+        name: q.name.clone(),
+        rtype: TYPE_A,
+        unique: false,
+        rdata: addr.octets().to_vec(),
+      })
+      .collect::<Vec<_>>();
+  Some(encode_response(id, &questions, &answers, &[], true))
+}
+
+fn parse_query(packet: &[u8]) -> Option<(u16, Vec<Question>)> {
+  let header = packet.get(..12)?;
+  let id = u16::from_be_bytes([header[0], header[1]]);
+  let is_response = header[2] & 0x80 != 0;
+  if is_response {
+    return None;
+  }
+  let qdcount = u16::from_be_bytes([header[4], header[5]]);
+  let mut pos = 12;
+  let mut questions = vec![];
+  for _ in 0..qdcount {
+    let (name, next) = read_name(packet, pos)?;
+    let fields = packet.get(next..next + 4)?;
+    questions.push(Question {
+      name,
+      qtype: u16::from_be_bytes([fields[0], fields[1]]),
+    });
+    pos = next + 4;
+  }
+  Some((id, questions))
+}
+
+/// Reads the possibly compressed name at `pos`, returning it dotted and the position just
+/// after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+  let mut labels = vec![];
+  let mut end = None;
+  for _ in 0..MAX_NAME_LABELS {
+    let len = *packet.get(pos)?;
+    match len {
+      0 => {
+        return Some((labels.join("."), end.unwrap_or(pos + 1)));
+      }
+      len if len & 0xc0 == 0xc0 => {
+        let offset = usize::from(u16::from_be_bytes([len & 0x3f, *packet.get(pos + 1)?]));
+        end.get_or_insert(pos + 2);
+        pos = offset;
+      }
+      len => {
+        let label = packet.get(pos + 1..pos + 1 + usize::from(len))?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += 1 + usize::from(len);
+      }
+    }
+  }
+  None
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+  let mut out = vec![];
+  for label in name.split('.').filter(|l| !l.is_empty()) {
+    let label = &label.as_bytes()[..label.len().min(63)];
+    out.push(label.len() as u8);
+    out.extend(label);
+  }
+  out.push(0);
+  out
+}
+
+/// `unicast` for plain DNS answers, which mustn't use the mDNS cache-flush bit or be cached
+/// for long.
+fn encode_response(
+    id: u16,
+    questions: &[Question],
+    answers: &[Record],
+    additional: &[Record],
+    unicast: bool,
+) -> Vec<u8> {
+  let ttl = if unicast { LEGACY_TTL_SECS } else { TTL_SECS };
+  let mut out = vec![];
+  out.extend(id.to_be_bytes());
+  // Response, authoritative.
+  out.extend(0x8400u16.to_be_bytes());
+  out.extend((questions.len() as u16).to_be_bytes());
+  out.extend((answers.len() as u16).to_be_bytes());
+  out.extend(0u16.to_be_bytes());
+  out.extend((additional.len() as u16).to_be_bytes());
+  for question in questions {
+    out.extend(encode_name(&question.name));
+    out.extend(question.qtype.to_be_bytes());
+    out.extend(CLASS_IN.to_be_bytes());
+  }
+  for record in answers.iter().chain(additional) {
+    out.extend(encode_name(&record.name));
+    out.extend(record.rtype.to_be_bytes());
+    let class = if record.unique && !unicast { CLASS_IN | CACHE_FLUSH } else { CLASS_IN };
+    out.extend(class.to_be_bytes());
+    out.extend(ttl.to_be_bytes());
+    out.extend((record.rdata.len() as u16).to_be_bytes());
+    out.extend(&record.rdata);
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn query(id: u16, questions: &[(&str, u16)]) -> Vec<u8> {
+    let questions = questions.iter()
+        .map(|(name, qtype)| Question { name: name.to_string(), qtype: *qtype })
+        .collect::<Vec<_>>();
+    let mut packet = encode_response(id, &questions, &[], &[], true);
+    // Turn it back into a query.
+    packet[2] = 0;
+    packet[3] = 0;
+    packet
+  }
+
+  fn counts(packet: &[u8]) -> (u16, u16, u16) {
+    let count = |i: usize| u16::from_be_bytes([packet[i], packet[i + 1]]);
+    (count(4), count(6), count(10))
+  }
+
+  #[test]
+  fn test_answers() {
+    let responder = MdnsResponder::new(
+        "BWGS99",
+        "bwgs99",
+        vec![MdnsService::new("_balboa._tcp", 4257).add_txt("mac=00-15-27-01-02-03")]);
+    let addr = HostAddrs { v4: Some(Ipv4Addr::new(192, 168, 1, 50)), v6: None };
+
+    let response = responder.answer(&query(0, &[("_balboa._tcp.local", TYPE_PTR)]), addr, false)
+        .unwrap();
+    assert_eq!(counts(&response), (0, 1, 3));
+    let (name, _) = read_name(&response, 12).unwrap();
+    assert_eq!(name, "_balboa._tcp.local");
+
+    let response = responder.answer(&query(7, &[("BWGS99.local", TYPE_A)]), addr, true).unwrap();
+    assert_eq!(&response[..2], &[0, 7]);
+    assert_eq!(counts(&response), (1, 1, 0));
+    assert!(response.ends_with(&[4, 192, 168, 1, 50]));
+    let class_and_ttl = &response[response.len() - 12..response.len() - 6];
+    assert_eq!(class_and_ttl, &[0, 1, 0, 0, 0, 10], "no cache flush, short TTL");
+
+    let response = responder.answer(&query(0, &[("BWGS99._balboa._tcp.local", TYPE_ANY)]), addr, false)
+        .unwrap();
+    assert_eq!(counts(&response), (0, 2, 1));
+
+    let response = responder.answer(&query(0, &[("BWGS99.local", TYPE_A)]), addr, false).unwrap();
+    let class_and_ttl = &response[response.len() - 12..response.len() - 6];
+    assert_eq!(class_and_ttl, &[0x80, 1, 0, 0, 0, 120]);
+
+    assert!(responder.is_about_us(&query(0, &[("printer.local", TYPE_A), ("bwgs99.local", TYPE_A)])));
+    assert!(!responder.is_about_us(&query(0, &[("printer.local", TYPE_A)])));
+    assert!(responder.answer(&query(0, &[("printer.local", TYPE_A)]), addr, false).is_none());
+    let mut not_a_query = query(0, &[("bwgs99.local", TYPE_A)]);
+    not_a_query[2] = 0x84;
+    assert!(responder.answer(&not_a_query, addr, false).is_none());
+
+    let v6 = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0x50);
+    let both = HostAddrs { v6: Some(v6), ..addr };
+    let response = responder.answer(&query(0, &[("bwgs99.local", TYPE_AAAA)]), both, false).unwrap();
+    assert_eq!(counts(&response), (0, 1, 1), "A as additional");
+    let response = responder.answer(&query(0, &[("_balboa._tcp.local", TYPE_PTR)]), both, false)
+        .unwrap();
+    assert_eq!(counts(&response), (0, 1, 4));
+    assert!(response.ends_with(&v6.octets()));
+  }
+
+  #[test]
+  fn test_answer_everything() {
+    let addr = Ipv4Addr::new(192, 168, 4, 1);
+    let response = answer_everything(&query(9, &[("captive.apple.com", TYPE_A)]), addr).unwrap();
+    assert_eq!(&response[..2], &[0, 9]);
+    assert_eq!(counts(&response), (1, 1, 0));
+    assert!(response.ends_with(&[4, 192, 168, 4, 1]));
+  }
+
+  #[test]
+  fn test_compressed_names() {
+    let mut packet = vec![0u8; 12];
+    packet.extend(encode_name("_balboa._tcp.local"));
+    // "bwgs99" followed by a pointer back to the name above.
+    packet.extend([6, b'b', b'w', b'g', b's', b'9', b'9', 0xc0, 12]);
+    let (name, end) = read_name(&packet, 32).unwrap();
+    assert_eq!(name, "bwgs99._balboa._tcp.local");
+    assert_eq!(end, packet.len());
+
+    // Pointer to itself.
+    packet.extend([0xc0, packet.len() as u8]);
+    assert!(read_name(&packet, packet.len() - 2).is_none());
+  }
+}
@@ -0,0 +1,505 @@
+//! Publishes spa status to an MQTT broker and accepts commands from it, announcing everything via
+//! [Home Assistant MQTT discovery](https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery)
+//! so that a climate entity plus pump and light switches appear without any configuration.
+//!
+//! The broker connection itself is left to the platform (e.g. `EspMqttClient` on device) behind
+//! [MqttClient]; inbound publishes on our command topics are handed to
+//! [crate::wifi_module_client::WifiModuleClient::set_mqtt] as [MqttIncoming].
+//!
+//! Publishing happens on a thread of its own fed by a bounded queue, so that a slow or
+//! unreachable broker costs us updates rather than holding up the bus.  Commands go through
+//! [SpaClient] like any other local user of the spa.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use anyhow::anyhow;
+use log::{debug, info, warn};
+use serde_json::json;
+use balboa_spa_messages::message_types::{FaultResponseMessage, HeatingState, ItemCode, MessageType, PumpStatus, RelayStatus, StatusUpdateResponseV1};
+use balboa_spa_messages::temperature::{Temperature, TemperatureScale};
+use common_lib::metrics::{Counter, Metrics};
+use crate::alerts::Alert;
+use crate::fault_log::fault_json;
+use crate::spa_client::SpaClient;
+
+const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Status updates come several times a second, so this is a few seconds' worth.
+const MAX_QUEUED_EVENTS: usize = 32;
+
+/// What little we need from an MQTT client.
+pub trait MqttClient: Send {
+  fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> anyhow::Result<()>;
+  fn subscribe(&mut self, topic: &str) -> anyhow::Result<()>;
+}
+
+/// A publish received from the broker.
+#[derive(Debug, Clone)]
+pub struct MqttIncoming {
+  pub topic: String,
+  pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+  /// Stable identifier used in topics and Home Assistant unique ids, e.g. derived from the MAC.
+  pub device_id: String,
+  pub device_name: String,
+  pub base_topic: String,
+  pub discovery_prefix: String,
+}
+
+impl MqttConfig {
+  pub fn new(device_id: &str) -> Self {
+    Self {
+      device_id: device_id.to_owned(),
+      device_name: "Spa".to_owned(),
+      base_topic: format!("balboa/{device_id}"),
+      discovery_prefix: DEFAULT_DISCOVERY_PREFIX.to_owned(),
+    }
+  }
+
+  pub fn set_device_name(mut self, name: &str) -> Self {
+    self.device_name = name.to_owned();
+    self
+  }
+
+  pub fn set_base_topic(mut self, topic: &str) -> Self {
+    self.base_topic = topic.to_owned();
+    self
+  }
+
+  pub fn set_discovery_prefix(mut self, prefix: &str) -> Self {
+    self.discovery_prefix = prefix.to_owned();
+    self
+  }
+}
+
+pub struct MqttBridge {
+  client: Box<dyn MqttClient>,
+  config: MqttConfig,
+  state: Option<SpaState>,
+
+  /// Last payload per state topic so we only publish changes.
+  published: HashMap<String, String>,
+}
+
+/// Just enough of the last status to publish discovery and interpret commands.  None where
+/// the spa reported a state we don't recognise.
+#[derive(Debug, Clone, PartialEq)]
+struct SpaState {
+  scale: TemperatureScale,
+  pumps: Vec<Option<bool>>,
+  lights: Vec<Option<bool>>,
+}
+
+/// Everything the publisher thread acts on.
+#[derive(Debug)]
+pub(crate) enum MqttEvent {
+  Message(MessageType),
+  FaultLog(Vec<FaultResponseMessage>),
+  Alert(Alert),
+  CommandFailed(String),
+  Incoming(MqttIncoming),
+}
+
+/// The event handler's end of the queue, which drops events rather than wait for room.
+#[derive(Debug)]
+pub(crate) struct MqttSender {
+  events_tx: SyncSender<MqttEvent>,
+  dropped: Counter,
+}
+
+impl MqttSender {
+  pub fn send(&self, event: MqttEvent) {
+    match self.events_tx.try_send(event) {
+      Ok(()) => {}
+      Err(TrySendError::Full(event)) => {
+        debug!("MQTT falling behind, dropped {event:?}");
+        self.dropped.inc();
+      }
+      Err(TrySendError::Disconnected(_)) => {}
+    }
+  }
+}
+
+/// Owns the [MqttBridge] on its own thread, see the module docs.
+pub(crate) struct MqttPublisher {
+  bridge: MqttBridge,
+  events_rx: Receiver<MqttEvent>,
+  spa_client: SpaClient,
+}
+
+/// Also returns a plain sender for [MqttIncoming]s, which unlike bus events are never dropped.
+pub(crate) fn mqtt_publisher(
+    bridge: MqttBridge,
+    spa_client: SpaClient,
+) -> (MqttSender, SyncSender<MqttEvent>, MqttPublisher) {
+  let (events_tx, events_rx) = sync_channel(MAX_QUEUED_EVENTS);
+  let sender = MqttSender {
+    events_tx: events_tx.clone(),
+    dropped: Metrics::global().counter("wifi_module.mqtt_dropped"),
+  };
+  (sender, events_tx, MqttPublisher { bridge, events_rx, spa_client })
+}
+
+impl MqttPublisher {
+  /// Exits once the event handler and the incoming forwarder are both gone.
+  pub fn run_loop(mut self) {
+    for event in self.events_rx {
+      let result = match event {
+        MqttEvent::Message(mt) => self.bridge.on_message(&mt),
+        MqttEvent::FaultLog(entries) => self.bridge.on_fault_log(&entries),
+        MqttEvent::Alert(alert) => self.bridge.on_alert(&alert),
+        MqttEvent::CommandFailed(description) => self.bridge.on_command_failed(&description),
+        MqttEvent::Incoming(incoming) => {
+          if let Err(e) = self.bridge.on_command(&incoming, &self.spa_client) {
+            warn!("Ignoring MQTT command on {}: {e}", incoming.topic);
+          }
+          Ok(())
+        }
+      };
+      if let Err(e) = result {
+        warn!("Failed to publish to MQTT: {e:?}");
+      }
+    }
+  }
+}
+
+impl MqttBridge {
+  pub fn new(client: Box<dyn MqttClient>, config: MqttConfig) -> Self {
+    Self {
+      client,
+      config,
+      state: None,
+      published: HashMap::new(),
+    }
+  }
+
+  /// Publish whatever `mt` tells us about the spa.  Discovery is announced on the first status
+  /// update, as that's when we learn the temperature scale and how many pumps and lights exist.
+  pub fn on_message(&mut self, mt: &MessageType) -> anyhow::Result<()> {
+    match mt {
+      MessageType::StatusUpdate(status) => self.on_status(&status.v1),
+      MessageType::FaultLogResponse(fault) => self.on_fault(fault),
+      _ => Ok(()),
+    }
+  }
+
+  fn on_status(&mut self, status: &StatusUpdateResponseV1) -> anyhow::Result<()> {
+    let state = SpaState {
+      scale: status.set_temperature.raw_scale,
+      pumps: status.pump_status.iter()
+          .map(|p| p.as_ref().map(|p| !matches!(p, PumpStatus::Off)))
+          .collect(),
+      lights: status.light_status.iter()
+          .map(|l| l.as_ref().map(|l| matches!(l, RelayStatus::On)))
+          .collect(),
+    };
+    let needs_announce = match &self.state {
+      None => true,
+      Some(old) => old.scale != state.scale
+          || old.pumps.len() != state.pumps.len()
+          || old.lights.len() != state.lights.len(),
+    };
+    if needs_announce {
+      self.announce(&state)?;
+    }
+
+    let heating = matches!(status.heating_state.as_ref(), Some(HeatingState::Heating));
+    if let Some(current) = &status.current_temperature {
+      self.publish_state("current_temperature", format_temperature(current.raw_scale, &current.temperature))?;
+    }
+    self.publish_state(
+      "target_temperature",
+      format_temperature(status.set_temperature.raw_scale, &status.set_temperature.temperature))?;
+    self.publish_state("action", if heating { "heating" } else { "idle" }.to_owned())?;
+    self.publish_state("heater", on_off(Some(heating)))?;
+    for (i, on) in state.pumps.iter().enumerate() {
+      self.publish_state(&format!("pump{}", i + 1), on_off(*on))?;
+    }
+    for (i, on) in state.lights.iter().enumerate() {
+      self.publish_state(&format!("light{}", i + 1), on_off(*on))?;
+    }
+    self.state = Some(state);
+    Ok(())
+  }
+
+  fn on_fault(&mut self, fault: &FaultResponseMessage) -> anyhow::Result<()> {
+    // Older entries turn up too now that the whole log is fetched in the background.
+    if fault.total_entries == 0 || fault.entry_number + 1 != fault.total_entries {
+      return Ok(());
+    }
+    let description = match fault.fault_code.as_ref() {
+      Some(code) => code.to_string(),
+      None => format!("Unknown fault {}", fault.fault_code.as_raw()),
+    };
+    self.publish_state("fault", description)
+  }
+
+  /// Publish the whole fault log, oldest first, as attributes of the last fault sensor.
+  pub fn on_fault_log(&mut self, entries: &[FaultResponseMessage]) -> anyhow::Result<()> {
+    let entries = entries.iter().map(fault_json).collect::<Vec<_>>();
+    self.publish_state("fault_log", json!({ "entries": entries }).to_string())
+  }
+
+  /// Not retained, see [crate::alerts].
+  pub(crate) fn on_alert(&mut self, alert: &Alert) -> anyhow::Result<()> {
+    let topic = format!("{}/alert", self.config.base_topic);
+    self.client.publish(&topic, alert.to_json().to_string().as_bytes(), false)
+  }
+
+  /// Not retained, it's only news to whoever is listening at the time.
+  pub fn on_command_failed(&mut self, description: &str) -> anyhow::Result<()> {
+    let topic = format!("{}/command_failed", self.config.base_topic);
+    self.client.publish(&topic, description.as_bytes(), false)
+  }
+
+  /// Act on a publish to one of our command topics.  Switches only have a toggle on the wire,
+  /// so requests that match the current state are dropped, as are any for a switch whose state
+  /// we don't know.
+  pub fn on_command(&self, incoming: &MqttIncoming, spa: &SpaClient) -> anyhow::Result<()> {
+    let state = self.state.as_ref()
+        .ok_or_else(|| anyhow!("Command before first status update"))?;
+    let Some(command) = incoming.topic.strip_prefix(&format!("{}/", self.config.base_topic))
+        .and_then(|t| t.strip_suffix("/set")) else {
+      return Ok(());
+    };
+    let payload = std::str::from_utf8(&incoming.payload)?.trim();
+    debug!("Got MQTT command {command}={payload}");
+
+    if command == "target_temperature" {
+      let value = payload.parse::<f64>()?;
+      let target = match state.scale {
+        TemperatureScale::Fahrenheit => Temperature::from_fahrenheit(value),
+        TemperatureScale::Celsius => Temperature::from_celsius(value),
+      };
+      spa.set_temperature(target)?;
+      return Ok(());
+    }
+
+    let (current, item_code) = if let Some(n) = command.strip_prefix("pump") {
+      let index = parse_index(n, state.pumps.len())?;
+      (state.pumps[index], pump_item_code(index)?)
+    } else if let Some(n) = command.strip_prefix("light") {
+      let index = parse_index(n, state.lights.len())?;
+      let item_code = if index == 0 { ItemCode::Light1 } else { ItemCode::Light2 };
+      (state.lights[index], item_code)
+    } else {
+      return Err(anyhow!("Unknown command {command}"));
+    };
+    let desired = match payload {
+      "ON" => true,
+      "OFF" => false,
+      other => return Err(anyhow!("Expected ON or OFF, got {other}")),
+    };
+    match current {
+      None => Err(anyhow!("State of {command} unknown, not toggling")),
+      Some(current) if current == desired => Ok(()),
+      Some(_) => {
+        spa.toggle(item_code)?;
+        Ok(())
+      }
+    }
+  }
+
+  fn announce(&mut self, state: &SpaState) -> anyhow::Result<()> {
+    info!("Announcing Home Assistant discovery for {}", self.config.device_id);
+    let base = self.config.base_topic.clone();
+    let (unit, min_temp, max_temp, step) = match state.scale {
+      TemperatureScale::Fahrenheit => ("F", 50.0, 104.0, 1.0),
+      TemperatureScale::Celsius => ("C", 10.0, 40.0, 0.5),
+    };
+    self.publish_discovery("climate", "climate", json!({
+      "name": null,
+      "modes": ["heat"],
+      "current_temperature_topic": format!("{base}/current_temperature"),
+      "temperature_state_topic": format!("{base}/target_temperature"),
+      "temperature_command_topic": format!("{base}/target_temperature/set"),
+      "action_topic": format!("{base}/action"),
+      "temperature_unit": unit,
+      "min_temp": min_temp,
+      "max_temp": max_temp,
+      "temp_step": step,
+    }))?;
+    self.publish_discovery("binary_sensor", "heater", json!({
+      "name": "Heater",
+      "device_class": "heat",
+      "state_topic": format!("{base}/heater"),
+    }))?;
+    self.publish_discovery("sensor", "fault", json!({
+      "name": "Last fault",
+      "state_topic": format!("{base}/fault"),
+      "json_attributes_topic": format!("{base}/fault_log"),
+    }))?;
+
+    let switches = (1..=state.pumps.len()).map(|n| (format!("pump{n}"), format!("Pump {n}")))
+        .chain((1..=state.lights.len()).map(|n| (format!("light{n}"), format!("Light {n}"))))
+        .collect::<Vec<_>>();
+    for (object_id, name) in switches {
+      let command_topic = format!("{base}/{object_id}/set");
+      self.publish_discovery("switch", &object_id, json!({
+        "name": name,
+        "state_topic": format!("{base}/{object_id}"),
+        "command_topic": command_topic,
+      }))?;
+      self.client.subscribe(&command_topic)?;
+    }
+    self.client.subscribe(&format!("{base}/target_temperature/set"))?;
+    Ok(())
+  }
+
+  fn publish_discovery(
+      &mut self,
+      component: &str,
+      object_id: &str,
+      mut config: serde_json::Value,
+  ) -> anyhow::Result<()> {
+    let device_id = &self.config.device_id;
+    config["unique_id"] = json!(format!("{device_id}_{object_id}"));
+    config["device"] = json!({
+      "identifiers": [device_id],
+      "name": self.config.device_name,
+      "manufacturer": "Balboa",
+    });
+    let topic = format!("{}/{component}/{device_id}/{object_id}/config", self.config.discovery_prefix);
+    self.client.publish(&topic, config.to_string().as_bytes(), true)
+  }
+
+  fn publish_state(&mut self, name: &str, value: String) -> anyhow::Result<()> {
+    let topic = format!("{}/{name}", self.config.base_topic);
+    if self.published.get(&topic) == Some(&value) {
+      return Ok(());
+    }
+    self.client.publish(&topic, value.as_bytes(), true)?;
+    self.published.insert(topic, value);
+    Ok(())
+  }
+}
+
+fn format_temperature(scale: TemperatureScale, temperature: &Temperature) -> String {
+  match scale {
+    TemperatureScale::Fahrenheit => format!("{:.1}", temperature.as_fahrenheit()),
+    TemperatureScale::Celsius => format!("{:.1}", temperature.as_celsius()),
+  }
+}
+
+/// Home Assistant takes `None` to mean unknown.
+fn on_off(on: Option<bool>) -> String {
+  match on {
+    Some(true) => "ON",
+    Some(false) => "OFF",
+    None => "None",
+  }.to_owned()
+}
+
+/// Parse the 1-based index in a topic like `pump2`.
+fn parse_index(n: &str, count: usize) -> anyhow::Result<usize> {
+  match n.parse::<usize>()? {
+    n if (1..=count).contains(&n) => Ok(n - 1),
+    n => Err(anyhow!("No such item {n}")),
+  }
+}
+
+fn pump_item_code(index: usize) -> anyhow::Result<ItemCode> {
+  let codes = [
+    ItemCode::Pump1,
+    ItemCode::Pump2,
+    ItemCode::Pump3,
+    ItemCode::Pump4,
+    ItemCode::Pump5,
+    ItemCode::Pump6,
+  ];
+  codes.get(index).cloned().ok_or_else(|| anyhow!("No such pump {}", index + 1))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use balboa_spa_messages::message_types::StatusUpdateMessage;
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use crate::command::Command;
+  use crate::spa_state_cache::SpaStateCache;
+  use super::*;
+
+  #[derive(Default, Clone)]
+  struct RecordingClient {
+    published: Arc<Mutex<Vec<(String, String)>>>,
+    subscribed: Arc<Mutex<Vec<String>>>,
+  }
+
+  impl MqttClient for RecordingClient {
+    fn publish(&mut self, topic: &str, payload: &[u8], _retain: bool) -> anyhow::Result<()> {
+      let payload = String::from_utf8(payload.to_vec())?;
+      self.published.lock().unwrap().push((topic.to_owned(), payload));
+      Ok(())
+    }
+
+    fn subscribe(&mut self, topic: &str) -> anyhow::Result<()> {
+      self.subscribed.lock().unwrap().push(topic.to_owned());
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_discovery_state_and_commands() -> anyhow::Result<()> {
+    let client = RecordingClient::default();
+    let mut bridge = MqttBridge::new(Box::new(client.clone()), MqttConfig::new("spa1"));
+
+    let mut status = StatusUpdateMessage::try_from([0u8; 24].as_slice())?;
+    status.v1.pump_status = vec![
+      ParsedEnum::new(PumpStatus::Off),
+      ParsedEnum::new(PumpStatus::High),
+      ParsedEnum::from_raw(7),
+    ];
+    status.v1.light_status = vec![ParsedEnum::new(RelayStatus::Off)];
+    let mt = MessageType::StatusUpdate(status);
+    bridge.on_message(&mt)?;
+
+    let published = client.published.lock().unwrap().clone();
+    let topics = published.iter().map(|(t, _)| t.as_str()).collect::<Vec<_>>();
+    assert!(topics.contains(&"homeassistant/climate/spa1/climate/config"));
+    assert!(topics.contains(&"homeassistant/switch/spa1/pump2/config"));
+    assert!(published.contains(&("balboa/spa1/pump2".to_owned(), "ON".to_owned())));
+    assert!(client.subscribed.lock().unwrap().contains(&"balboa/spa1/light1/set".to_owned()));
+
+    // Unchanged state isn't published again.
+    bridge.on_message(&mt)?;
+    assert_eq!(client.published.lock().unwrap().len(), published.len());
+
+    assert!(published.contains(&("balboa/spa1/pump3".to_owned(), "None".to_owned())));
+
+    let (commands_tx, commands_rx) = sync_channel(8);
+    let cache = SpaStateCache::default();
+    cache.update(&mt);
+    cache.update(&MessageType::Settings0x04Response([0, 0, 50, 104, 80, 104].as_slice().try_into()?));
+    let spa = SpaClient::new(commands_tx, cache);
+    let command = |topic: &str, payload: &str| {
+      let incoming = MqttIncoming {
+        topic: topic.to_owned(),
+        payload: payload.as_bytes().to_vec(),
+      };
+      let result = bridge.on_command(&incoming, &spa);
+      let sent = commands_rx.try_iter()
+          .map(|command| match command {
+            Command::Spa(request) => request.mt,
+            other => panic!("Unexpected {other:?}"),
+          })
+          .collect::<Vec<_>>();
+      (result, sent)
+    };
+    assert!(matches!(
+        command("balboa/spa1/pump1/set", "ON"),
+        (Ok(()), sent) if matches!(sent.as_slice(), [MessageType::ToggleItemRequest { .. }])));
+    assert!(matches!(command("balboa/spa1/pump2/set", "ON"), (Ok(()), sent) if sent.is_empty()));
+    assert!(matches!(command("balboa/spa1/pump3/set", "OFF"), (Err(_), sent) if sent.is_empty()));
+    assert!(matches!(command("balboa/spa1/pump4/set", "ON"), (Err(_), sent) if sent.is_empty()));
+    assert!(matches!(
+        command("balboa/spa1/target_temperature/set", "100"),
+        (Ok(()), sent) if matches!(sent.as_slice(), [MessageType::SetTemperatureRequest { .. }])));
+    assert!(matches!(
+        command("balboa/spa1/target_temperature/set", "110"),
+        (Err(_), sent) if sent.is_empty()));
+    Ok(())
+  }
+}
@@ -0,0 +1,423 @@
+//! Firmware updates over the network.  An image can either be pushed to `POST /ota` on the
+//! [crate::http_handler] or pulled by the module itself from an `http://` URL, either given to
+//! `POST /ota/pull` or configured up front with
+//! [crate::wifi_module_client::WifiModuleClient::enable_ota].
+//!
+//! Images stream straight into an [OtaTarget] (the inactive app partition on the ESP32) so
+//! nothing bigger than a small buffer is held in memory, and the device restarts once the
+//! target has accepted the image.  Progress is surfaced as [crate::view_model::ViewModel::ota].
+//!
+//! Both routes require the token given to `enable_ota` as `Authorization: Bearer <token>`, and
+//! the SHA-256 digest of the image in hex, as an `X-Firmware-Sha256` header when pushing or
+//! `{"sha256": "..."}` when pulling.  An image that doesn't match is aborted before the target
+//! is told to [OtaTarget::finish], so it never becomes bootable.  The digest is what makes
+//! pulling over plain HTTP safe, as long as whoever supplies it is trusted with the token.
+
+use std::fmt::Debug;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::info;
+use sha2::{Digest, Sha256};
+use crate::http_request::{header, read_headers};
+use crate::relay_auth::constant_time_eq;
+use crate::view_model::OtaProgress;
+
+/// Bytes written between progress reports.
+const PROGRESS_STEP: usize = 64 * 1024;
+
+const CHUNK_LEN: usize = 4096;
+
+const PULL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wherever new firmware goes.  Calls always come in the order begin, write..., then exactly
+/// one of finish or abort.
+pub trait OtaTarget: Debug + Send {
+  /// Prepare for an image of `size` bytes, if the sender told us.
+  fn begin(&mut self, size: Option<usize>) -> anyhow::Result<()>;
+
+  fn write(&mut self, data: &[u8]) -> anyhow::Result<()>;
+
+  /// Validate the image and arrange for it to be used from the next boot.
+  fn finish(&mut self) -> anyhow::Result<()>;
+
+  /// Throw away a partially written image.
+  fn abort(&mut self);
+
+  /// Boot into the image from the last successful [Self::finish].  Need not return.
+  fn restart(&mut self);
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OtaError {
+  #[error("An update is already in progress")]
+  Busy,
+
+  #[error("Unsupported URL {0:?}, expected http://host[:port]/path")]
+  BadUrl(String),
+
+  #[error("Server responded with HTTP {0}")]
+  HttpStatus(u16),
+
+  #[error("Malformed response: {0}")]
+  BadResponse(String),
+
+  #[error("Image ended after {received} of {expected} bytes")]
+  Truncated { received: usize, expected: usize },
+
+  #[error("Image doesn't match the expected SHA-256 digest")]
+  DigestMismatch,
+
+  #[error("Image rejected: {0}")]
+  Rejected(anyhow::Error),
+
+  #[error("I/O error: {0}")]
+  IoError(#[from] io::Error),
+}
+
+/// Shared by every HTTP connection, only one of which gets to install at a time.
+#[derive(Clone)]
+pub(crate) struct OtaService {
+  target: Arc<Mutex<Box<dyn OtaTarget>>>,
+  token: String,
+  pull_url: Option<String>,
+  on_progress: Arc<dyn Fn(Option<OtaProgress>) + Send + Sync>,
+}
+
+impl OtaService {
+  pub fn new(
+      target: Box<dyn OtaTarget>,
+      token: String,
+      pull_url: Option<String>,
+      on_progress: impl Fn(Option<OtaProgress>) + Send + Sync + 'static,
+  ) -> Self {
+    Self {
+      target: Arc::new(Mutex::new(target)),
+      token,
+      pull_url,
+      on_progress: Arc::new(on_progress),
+    }
+  }
+
+  /// Where to pull from when not told otherwise.
+  pub fn pull_url(&self) -> Option<&str> {
+    self.pull_url.as_deref()
+  }
+
+  pub fn is_busy(&self) -> bool {
+    self.target.try_lock().is_err()
+  }
+
+  /// Whether the value of a request's `Authorization` header grants access.
+  pub fn authorize(&self, authorization: Option<&str>) -> bool {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()))
+  }
+
+  /// Streams an image of `total` bytes (or up to EOF, if not known) from `reader` into the
+  /// target, which only gets to keep it if it hashes to `sha256`.  Call [Self::restart]
+  /// afterwards to boot into it.
+  pub fn install(
+      &self,
+      reader: &mut impl Read,
+      total: Option<usize>,
+      sha256: &[u8; 32],
+  ) -> Result<(), OtaError> {
+    let mut target = self.target.try_lock().map_err(|_| OtaError::Busy)?;
+    info!("Receiving firmware image ({total:?} bytes)...");
+    let result = self.write_image(target.as_mut(), reader, total, sha256);
+    match &result {
+      Ok(received) => info!("Installed {received} byte firmware image"),
+      Err(e) => {
+        target.abort();
+        (self.on_progress)(Some(OtaProgress::Failed(e.to_string())));
+      }
+    }
+    result.map(|_| ())
+  }
+
+  fn write_image(
+      &self,
+      target: &mut dyn OtaTarget,
+      reader: &mut impl Read,
+      total: Option<usize>,
+      sha256: &[u8; 32],
+  ) -> Result<usize, OtaError> {
+    (self.on_progress)(Some(OtaProgress::Receiving { received: 0, total }));
+    target.begin(total).map_err(OtaError::Rejected)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_LEN];
+    let mut received = 0;
+    let mut last_reported = 0;
+    loop {
+      let want = total.map_or(CHUNK_LEN, |total| CHUNK_LEN.min(total - received));
+      if want == 0 {
+        break;
+      }
+      let n = reader.read(&mut buf[..want])?;
+      if n == 0 {
+        if let Some(expected) = total {
+          return Err(OtaError::Truncated { received, expected });
+        }
+        break;
+      }
+      target.write(&buf[..n]).map_err(OtaError::Rejected)?;
+      hasher.update(&buf[..n]);
+      received += n;
+      if received - last_reported >= PROGRESS_STEP {
+        last_reported = received;
+        (self.on_progress)(Some(OtaProgress::Receiving { received, total }));
+      }
+    }
+    if hasher.finalize().as_slice() != sha256 {
+      return Err(OtaError::DigestMismatch);
+    }
+    target.finish().map_err(OtaError::Rejected)?;
+    Ok(received)
+  }
+
+  /// Downloads and installs the image at `url`, which must hash to `sha256`.  Only plain HTTP
+  /// is supported.
+  pub fn pull(&self, url: &str, sha256: &[u8; 32]) -> Result<(), OtaError> {
+    if self.is_busy() {
+      return Err(OtaError::Busy);
+    }
+    let (host, port, path) = parse_http_url(url)?;
+    info!("Pulling firmware from {url}...");
+    let stream = TcpStream::connect((host.as_str(), port))?;
+    stream.set_read_timeout(Some(PULL_TIMEOUT))?;
+    write!(&stream, "GET {path} HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n\r\n")?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line.split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| OtaError::BadResponse(format!("Status line {status_line:?}")))?;
+    let headers = read_headers(&mut reader)
+        .map_err(|e| OtaError::BadResponse(e.to_string()))?;
+    if status != 200 {
+      return Err(OtaError::HttpStatus(status));
+    }
+    let total = header(&headers, "content-length")
+        .map(|len| len.parse::<usize>())
+        .transpose()
+        .map_err(|e| OtaError::BadResponse(format!("Content-Length: {e}")))?;
+    self.install(&mut reader, total, sha256)
+  }
+
+  pub fn restart(&self) {
+    info!("Restarting into new firmware...");
+    (self.on_progress)(Some(OtaProgress::Restarting));
+    self.target.lock().unwrap().restart();
+  }
+}
+
+/// A SHA-256 digest written out as 64 hex digits, in either case.
+pub(crate) fn parse_sha256(hex: &str) -> Option<[u8; 32]> {
+  if hex.len() != 64 || !hex.is_ascii() {
+    return None;
+  }
+  let mut digest = [0u8; 32];
+  for (i, b) in digest.iter_mut().enumerate() {
+    *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+  }
+  Some(digest)
+}
+
+pub(crate) fn parse_http_url(url: &str) -> Result<(String, u16, String), OtaError> {
+  let bad_url = || OtaError::BadUrl(url.to_owned());
+  let rest = url.strip_prefix("http://").ok_or_else(bad_url)?;
+  let (authority, path) = match rest.find('/') {
+    Some(i) => rest.split_at(i),
+    None => (rest, "/"),
+  };
+  let (host, port) = match authority.rsplit_once(':') {
+    Some((host, port)) => (host, port.parse().map_err(|_| bad_url())?),
+    None => (authority, 80),
+  };
+  if host.is_empty() {
+    return Err(bad_url());
+  }
+  Ok((host.to_owned(), port, path.to_owned()))
+}
+
+/// Saves images to a file, for desktop builds where there's nothing to boot into.  The file is
+/// only replaced once an image has been received in full.
+#[derive(Debug)]
+pub struct FileOtaTarget {
+  path: PathBuf,
+  partial: Option<File>,
+}
+
+impl FileOtaTarget {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self { path: path.into(), partial: None }
+  }
+
+  fn partial_path(&self) -> PathBuf {
+    self.path.with_extension("part")
+  }
+}
+
+impl OtaTarget for FileOtaTarget {
+  fn begin(&mut self, _size: Option<usize>) -> anyhow::Result<()> {
+    self.partial = Some(File::create(self.partial_path())?);
+    Ok(())
+  }
+
+  fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+    let file = self.partial.as_mut().ok_or_else(|| anyhow::anyhow!("Not started"))?;
+    file.write_all(data)?;
+    Ok(())
+  }
+
+  fn finish(&mut self) -> anyhow::Result<()> {
+    let file = self.partial.take().ok_or_else(|| anyhow::anyhow!("Not started"))?;
+    file.sync_all()?;
+    fs::rename(self.partial_path(), &self.path)?;
+    Ok(())
+  }
+
+  fn abort(&mut self) {
+    if self.partial.take().is_some() {
+      let _ = fs::remove_file(self.partial_path());
+    }
+  }
+
+  fn restart(&mut self) {
+    info!("New firmware saved to {}, restart manually to use it", self.path.display());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::TcpListener;
+  use std::thread;
+  use super::*;
+
+  #[derive(Debug, Default, Clone)]
+  struct MemoryTarget {
+    installed: Arc<Mutex<Option<Vec<u8>>>>,
+    partial: Vec<u8>,
+  }
+
+  impl OtaTarget for MemoryTarget {
+    fn begin(&mut self, _size: Option<usize>) -> anyhow::Result<()> {
+      self.partial.clear();
+      Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+      self.partial.extend_from_slice(data);
+      Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+      *self.installed.lock().unwrap() = Some(std::mem::take(&mut self.partial));
+      Ok(())
+    }
+
+    fn abort(&mut self) {
+      self.partial.clear();
+    }
+
+    fn restart(&mut self) {}
+  }
+
+  fn new_service(target: MemoryTarget) -> (OtaService, Arc<Mutex<Vec<OtaProgress>>>) {
+    let reports = Arc::new(Mutex::new(vec![]));
+    let reports_for_service = reports.clone();
+    let service = OtaService::new(Box::new(target), "s3cret".to_owned(), None, move |progress| {
+      reports_for_service.lock().unwrap().extend(progress);
+    });
+    (service, reports)
+  }
+
+  #[test]
+  fn test_parse_http_url() {
+    assert_eq!(
+        parse_http_url("http://updates.local:8080/spa/fw.bin").unwrap(),
+        ("updates.local".to_owned(), 8080, "/spa/fw.bin".to_owned()));
+    assert_eq!(
+        parse_http_url("http://10.0.0.2").unwrap(),
+        ("10.0.0.2".to_owned(), 80, "/".to_owned()));
+    assert!(parse_http_url("https://updates.local/fw.bin").is_err());
+    assert!(parse_http_url("http://:80/fw.bin").is_err());
+  }
+
+  fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+  }
+
+  #[test]
+  fn test_parse_sha256() {
+    let hex = "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824";
+    assert_eq!(parse_sha256(hex), Some(sha256(b"hello")));
+    assert_eq!(parse_sha256(&hex.to_ascii_lowercase()), Some(sha256(b"hello")));
+    assert_eq!(parse_sha256(&hex[2..]), None);
+    assert_eq!(parse_sha256(&hex.replace('C', "x")), None);
+  }
+
+  #[test]
+  fn test_authorize() {
+    let (service, _) = new_service(MemoryTarget::default());
+    assert!(service.authorize(Some("Bearer s3cret")));
+    assert!(!service.authorize(Some("Bearer s3cre")));
+    assert!(!service.authorize(Some("s3cret")));
+    assert!(!service.authorize(None));
+  }
+
+  #[test]
+  fn test_install() {
+    let target = MemoryTarget::default();
+    let (service, reports) = new_service(target.clone());
+
+    let image = vec![0xa5u8; PROGRESS_STEP + 100];
+    service.install(&mut image.as_slice(), Some(image.len()), &sha256(&image)).unwrap();
+    assert_eq!(target.installed.lock().unwrap().as_ref(), Some(&image));
+    let total = Some(image.len());
+    assert_eq!(*reports.lock().unwrap(), vec![
+      OtaProgress::Receiving { received: 0, total },
+      OtaProgress::Receiving { received: PROGRESS_STEP, total },
+    ]);
+
+    let result = service.install(&mut [1u8, 2, 3].as_slice(), Some(10), &sha256(&[1, 2, 3]));
+    assert!(matches!(result, Err(OtaError::Truncated { received: 3, expected: 10 })));
+    assert_eq!(target.installed.lock().unwrap().as_ref(), Some(&image), "Must keep the old image");
+    assert!(matches!(reports.lock().unwrap().last(), Some(OtaProgress::Failed(_))));
+
+    let result = service.install(&mut [1u8, 2, 3].as_slice(), Some(3), &sha256(&image));
+    assert!(matches!(result, Err(OtaError::DigestMismatch)));
+    assert_eq!(target.installed.lock().unwrap().as_ref(), Some(&image), "Must keep the old image");
+  }
+
+  #[test]
+  fn test_pull() -> anyhow::Result<()> {
+    let server = TcpListener::bind("127.0.0.1:0")?;
+    let port = server.local_addr()?.port();
+    let server_thread = thread::spawn(move || {
+      let (stream, _) = server.accept().unwrap();
+      let mut reader = BufReader::new(&stream);
+      let mut request_line = String::new();
+      reader.read_line(&mut request_line).unwrap();
+      read_headers(&mut reader).unwrap();
+      write!(&stream, "HTTP/1.0 200 OK\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+      request_line
+    });
+
+    let target = MemoryTarget::default();
+    let (service, _) = new_service(target.clone());
+    service.pull(&format!("http://127.0.0.1:{port}/fw.bin"), &sha256(b"hello"))?;
+    assert_eq!(server_thread.join().unwrap(), "GET /fw.bin HTTP/1.0\r\n");
+    assert_eq!(target.installed.lock().unwrap().as_deref(), Some(b"hello".as_slice()));
+    Ok(())
+  }
+}
@@ -0,0 +1,125 @@
+//! Messages waiting for our next ClearToSend.  We only get one message per CTS slot, so
+//! anything flooding this queue (a runaway automation script, say) would otherwise delay every
+//! request behind it and keep the bus busy at the expense of the topside panel.
+//!
+//! Each source may only have a few messages waiting, and a message identical to one already
+//! waiting is dropped as it would only repeat the first.  Both show up in metrics.
+
+use std::collections::VecDeque;
+use log::warn;
+use balboa_spa_messages::channel::Channel;
+use balboa_spa_messages::message_types::MessageType;
+use common_lib::metrics::{Counter, Gauge, Metrics};
+use crate::relay_event::RelayClientId;
+
+/// Plenty for anybody sending at a sane rate, CTS comes around several times a second.
+const MAX_QUEUED_PER_SOURCE: usize = 8;
+
+/// Who wanted a message sent, so that one can't crowd out the others.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum MessageSource {
+  Relay(RelayClientId),
+  SpaClient,
+
+  /// Our own housekeeping, such as setting the clock.
+  Internal,
+}
+
+#[derive(Debug)]
+pub(crate) struct OutboundQueue {
+  queue: VecDeque<QueuedMessage>,
+  coalesced: Counter,
+  dropped: Counter,
+  depth: Gauge,
+}
+
+#[derive(Debug)]
+struct QueuedMessage {
+  source: MessageSource,
+  mt: MessageType,
+
+  /// Encoded form, for spotting duplicates.  None if it doesn't encode, which will be
+  /// discovered soon enough when it's sent.
+  encoded: Option<(u8, Vec<u8>)>,
+}
+
+impl Default for OutboundQueue {
+  fn default() -> Self {
+    let metrics = Metrics::global();
+    Self {
+      queue: VecDeque::new(),
+      coalesced: metrics.counter("wifi_module.outbound_coalesced"),
+      dropped: metrics.counter("wifi_module.outbound_dropped"),
+      depth: metrics.gauge("wifi_module.outbound_queue_depth"),
+    }
+  }
+}
+
+impl OutboundQueue {
+  /// Returns whether `mt` was queued.
+  pub fn push(&mut self, source: MessageSource, mt: MessageType) -> bool {
+    let encoded = encode(&mt);
+    // Toggles aren't idempotent, two in a row is how you get a pump to high.
+    let coalesce = !matches!(mt, MessageType::ToggleItemRequest { .. });
+    if coalesce && encoded.is_some() && self.queue.iter().any(|q| q.encoded == encoded) {
+      self.coalesced.inc();
+      return false;
+    }
+    if self.queue.iter().filter(|q| q.source == source).count() >= MAX_QUEUED_PER_SOURCE {
+      warn!("Dropping {mt:?} from {source:?}, too many messages already waiting");
+      self.dropped.inc();
+      return false;
+    }
+    self.queue.push_back(QueuedMessage { source, mt, encoded });
+    self.depth.set(self.queue.len() as isize);
+    true
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.queue.is_empty()
+  }
+
+  pub fn pop_front(&mut self) -> Option<MessageType> {
+    let queued = self.queue.pop_front()?;
+    self.depth.set(self.queue.len() as isize);
+    Some(queued.mt)
+  }
+}
+
+fn encode(mt: &MessageType) -> Option<(u8, Vec<u8>)> {
+  let message = mt.clone().to_message(Channel::WifiModule).ok()?;
+  Some((message.message_type, message.payload))
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::message_types::{ItemCode, SettingsRequestMessage};
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use super::*;
+
+  #[test]
+  fn test_coalesce_and_cap() {
+    let mut queue = OutboundQueue::default();
+    let client = MessageSource::Relay(RelayClientId(1));
+    let request = || MessageType::SettingsRequest(SettingsRequestMessage::Configuration);
+    let toggle = || MessageType::ToggleItemRequest {
+      item_code: ParsedEnum::new(ItemCode::Pump1),
+      dummy1: 0,
+    };
+
+    assert!(queue.push(client, request()));
+    assert!(!queue.push(client, request()));
+    assert!(!queue.push(MessageSource::Internal, request()), "Duplicates from anyone are dropped");
+    assert!(queue.push(client, toggle()));
+    assert!(queue.push(client, toggle()), "Toggles must never be coalesced");
+
+    for _ in 3..MAX_QUEUED_PER_SOURCE {
+      assert!(queue.push(client, toggle()));
+    }
+    assert!(!queue.push(client, toggle()));
+    assert!(queue.push(MessageSource::Internal, toggle()), "Other sources are unaffected");
+
+    assert!(matches!(queue.pop_front(), Some(MessageType::SettingsRequest(_))));
+    assert!(queue.push(client, request()), "No longer a duplicate once sent");
+  }
+}
@@ -0,0 +1,163 @@
+//! Optional gate in front of the IP relay, which otherwise hands full control of the spa to
+//! anybody on the LAN.
+//!
+//! A [RelayAccessPolicy] may restrict clients to an allowlist of addresses, require a
+//! pre-shared token, or both.  Clients prove they know the token by sending a single line
+//! `AUTH <token>\n` immediately after connecting, before anything else.  Note that the official
+//! app knows nothing of tokens, so only use one if all your clients do.
+//!
+//! Policies are kept in [crate::settings::RELAY_ACCESS] so that they can be changed without a
+//! rebuild.
+
+use std::io::Read;
+use std::net::IpAddr;
+use anyhow::{anyhow, Context};
+
+const AUTH_PREFIX: &[u8] = b"AUTH ";
+
+/// Generous for any sensible token, but stops a client from making us read forever.
+const MAX_AUTH_LINE_LEN: usize = 128;
+
+/// Who may use the relay.  The default lets everyone in, as before this existed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RelayAccessPolicy {
+  token: Option<String>,
+  allowlist: Vec<IpAddr>,
+}
+
+impl RelayAccessPolicy {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Require clients to present `token` before anything is relayed.
+  pub fn set_token(mut self, token: impl Into<String>) -> Self {
+    self.token = Some(token.into());
+    self
+  }
+
+  /// Only accept connections from `addr` (and any other allowed address).
+  pub fn allow(mut self, addr: IpAddr) -> Self {
+    self.allowlist.push(addr);
+    self
+  }
+
+  pub fn requires_token(&self) -> bool {
+    self.token.is_some()
+  }
+
+  /// Whether `peer` may connect at all.  An empty allowlist allows everyone.
+  pub fn allows_peer(&self, peer: &IpAddr) -> bool {
+    self.allowlist.is_empty() || self.allowlist.iter().any(|a| a == &peer.to_canonical())
+  }
+
+  /// Reads the client's `AUTH` line, if the policy requires one, and checks the token.
+  /// Reads nothing past the newline so that the relay protocol can pick up from there.
+  pub fn authenticate(&self, reader: &mut impl Read) -> Result<(), RelayAuthError> {
+    let Some(token) = &self.token else {
+      return Ok(());
+    };
+    let mut line = Vec::with_capacity(MAX_AUTH_LINE_LEN);
+    loop {
+      let mut b = [0u8; 1];
+      reader.read_exact(&mut b)?;
+      if b[0] == b'\n' {
+        break;
+      }
+      if line.len() == MAX_AUTH_LINE_LEN {
+        return Err(RelayAuthError::Malformed);
+      }
+      line.push(b[0]);
+    }
+    if line.last() == Some(&b'\r') {
+      line.pop();
+    }
+    let presented = line.strip_prefix(AUTH_PREFIX).ok_or(RelayAuthError::Malformed)?;
+    if constant_time_eq(presented, token.as_bytes()) {
+      Ok(())
+    } else {
+      Err(RelayAuthError::BadToken)
+    }
+  }
+}
+
+/// Doesn't let how long a comparison takes give away how much of a guessed token was right.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RelayAuthError {
+  #[error("Expected AUTH line")]
+  Malformed,
+
+  #[error("Wrong token")]
+  BadToken,
+
+  #[error("I/O error: {0}")]
+  IoError(#[from] std::io::Error),
+}
+
+/// Serialized form for settings: one `token=...` or `allow=...` per line.
+pub fn encode_policy(policy: &RelayAccessPolicy) -> String {
+  let token = policy.token.iter().map(|t| format!("token={t}\n"));
+  let allowed = policy.allowlist.iter().map(|a| format!("allow={a}\n"));
+  token.chain(allowed).collect()
+}
+
+pub fn decode_policy(data: &str) -> anyhow::Result<RelayAccessPolicy> {
+  let mut policy = RelayAccessPolicy::new();
+  for line in data.lines().map(str::trim).filter(|l| !l.is_empty()) {
+    match line.split_once('=') {
+      Some(("token", token)) => policy = policy.set_token(token),
+      Some(("allow", addr)) => {
+        let addr = addr.parse()
+            .with_context(|| format!("Bad allowlist address {addr:?}"))?;
+        policy = policy.allow(addr);
+      }
+      _ => return Err(anyhow!("Unexpected line {line:?}")),
+    }
+  }
+  Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::Ipv4Addr;
+  use super::*;
+
+  #[test]
+  fn test_policy() {
+    let open = RelayAccessPolicy::new();
+    assert!(open.allows_peer(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9))));
+    assert!(open.authenticate(&mut [].as_slice()).is_ok());
+
+    let policy = RelayAccessPolicy::new()
+        .set_token("s3cret")
+        .allow(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)));
+    assert!(policy.allows_peer(&"192.168.1.20".parse().unwrap()));
+    assert!(policy.allows_peer(&"::ffff:192.168.1.20".parse().unwrap()));
+    assert!(!policy.allows_peer(&"192.168.1.21".parse().unwrap()));
+
+    let mut input = b"AUTH s3cret\r\n\x7e".as_slice();
+    assert!(policy.authenticate(&mut input).is_ok());
+    assert_eq!(input, b"\x7e");
+    assert!(matches!(policy.authenticate(&mut b"AUTH guess\n".as_slice()), Err(RelayAuthError::BadToken)));
+    assert!(matches!(policy.authenticate(&mut b"\x7e\x05".as_slice()), Err(RelayAuthError::IoError(_))));
+    assert!(matches!(policy.authenticate(&mut [b'x'; 200].as_slice()), Err(RelayAuthError::Malformed)));
+  }
+
+  #[test]
+  fn test_encoding_round_trip() -> anyhow::Result<()> {
+    let policy = RelayAccessPolicy::new()
+        .set_token("abc")
+        .allow("10.0.0.2".parse()?)
+        .allow("fe80::1".parse()?);
+    assert_eq!(decode_policy(&encode_policy(&policy))?, policy);
+    assert!(decode_policy("allow=nope").is_err());
+    Ok(())
+  }
+}
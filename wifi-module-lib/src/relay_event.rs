@@ -1,6 +1,44 @@
 use balboa_spa_messages::message::Message;
 
+/// Identifies one connected IP client for the lifetime of its connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct RelayClientId(pub u32);
+
 #[derive(Debug, Clone)]
 pub(crate) enum RelayEvent {
+  /// Traffic from the main board that every client should see.
   MessageForIpClient(Message),
-}
\ No newline at end of file
+
+  /// Reply to something only `client` asked for.
+  MessageForClient(RelayClientId, Message),
+
+  /// Every frame read from the bus, untouched, for passthrough clients.
+  BusFrame(Message),
+
+  /// A command the spa never acted on, described for humans.  See [crate::confirmations].
+  /// Only WebSocket clients have any way to hear about it.
+  #[cfg(feature = "http")]
+  CommandFailed(String),
+}
+
+impl RelayEvent {
+  /// Passthrough clients get the bus as is, and nothing else.
+  pub fn frame_for_passthrough(&self) -> Option<&Message> {
+    match self {
+      RelayEvent::BusFrame(message) => Some(message),
+      _ => None,
+    }
+  }
+
+  /// The message `client` should be sent for this event, if any.
+  pub fn message_for(&self, client: RelayClientId) -> Option<&Message> {
+    match self {
+      RelayEvent::MessageForIpClient(message) => Some(message),
+      RelayEvent::MessageForClient(target, message) if *target == client => Some(message),
+      RelayEvent::MessageForClient(..) => None,
+      RelayEvent::BusFrame(_) => None,
+      #[cfg(feature = "http")]
+      RelayEvent::CommandFailed(_) => None,
+    }
+  }
+}
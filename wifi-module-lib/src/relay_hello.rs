@@ -0,0 +1,230 @@
+//! Optional version exchange for IP relay clients, so that apps written against this module can
+//! find out what it supports rather than guessing from how it behaves.
+//!
+//! A client that wants to know sends a frame on [Channel::WifiModule] with message type
+//! [HELLO_REQUEST] and its own [RELAY_PROTOCOL_VERSION] as the only payload byte.  We answer
+//! just that client with a [HELLO_RESPONSE] frame carrying a [RelayHello].  Neither type is used
+//! by the spa itself, and clients that never ask (the official app included) see no difference.
+//!
+//! The response payload is, in order:
+//!
+//! * protocol version (1 byte)
+//! * [RelayFeatures] bits (2 bytes, big endian)
+//! * module firmware version (1 length byte then UTF-8)
+//! * spa model number (1 length byte then UTF-8), empty if the main board hasn't told us yet,
+//!   otherwise followed by the main board software version (4 bytes)
+//!
+//! Later versions may append fields, so ignore anything past what you understand.
+
+use balboa_spa_messages::channel::Channel;
+use balboa_spa_messages::message::Message;
+use balboa_spa_messages::message_types::{InformationResponseMessage, SoftwareVersion};
+
+pub const HELLO_REQUEST: u8 = 0xf0;
+pub const HELLO_RESPONSE: u8 = 0xf1;
+
+/// Bumped whenever the response changes in a way old clients can't just ignore.
+pub const RELAY_PROTOCOL_VERSION: u8 = 1;
+
+/// Frames are at most 255 bytes, this leaves plenty of room for the rest.
+const MAX_STRING_LEN: usize = 64;
+
+/// Optional extras this module has turned on.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RelayFeatures(u16);
+
+impl RelayFeatures {
+  /// Fault log requests are answered straight from the module's copy, see [crate::fault_log].
+  pub const FAULT_LOG_CACHE: RelayFeatures = RelayFeatures(1 << 0);
+  pub const HTTP_API: RelayFeatures = RelayFeatures(1 << 1);
+  pub const MQTT: RelayFeatures = RelayFeatures(1 << 2);
+  pub const PASSTHROUGH: RelayFeatures = RelayFeatures(1 << 3);
+  pub const TLS: RelayFeatures = RelayFeatures(1 << 4);
+
+  pub fn with(self, other: RelayFeatures) -> Self {
+    RelayFeatures(self.0 | other.0)
+  }
+
+  pub fn contains(&self, other: RelayFeatures) -> bool {
+    self.0 & other.0 == other.0
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpaModel {
+  pub system_model_number: String,
+  pub software_version: SoftwareVersion,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayHello {
+  pub protocol_version: u8,
+  pub features: RelayFeatures,
+  pub firmware_version: String,
+  pub spa_model: Option<SpaModel>,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum HelloError {
+  #[error("Not a hello response: type {0:#04x}")]
+  WrongType(u8),
+
+  #[error("Response ended early")]
+  Truncated,
+
+  #[error("Invalid UTF-8 in response")]
+  InvalidString,
+}
+
+/// What a client sends to ask for a [RelayHello].
+pub fn hello_request() -> Message {
+  Message {
+    channel: Channel::WifiModule,
+    message_type: HELLO_REQUEST,
+    payload: vec![RELAY_PROTOCOL_VERSION],
+  }
+}
+
+/// Whether a relay client's `message` is a [hello_request].  Only on [Channel::WifiModule], as
+/// the type may well mean something else to whoever owns another channel.
+pub fn is_hello_request(message: &Message) -> bool {
+  message.channel == Channel::WifiModule && message.message_type == HELLO_REQUEST
+}
+
+impl RelayHello {
+  /// This hello with the spa model as `information` describes it, if the main board has told
+  /// us yet.
+  pub fn with_spa(&self, information: Option<&InformationResponseMessage>) -> Self {
+    Self {
+      spa_model: information.map(|info| SpaModel {
+        system_model_number: info.system_model_number.clone(),
+        software_version: info.software_version.clone(),
+      }),
+      ..self.clone()
+    }
+  }
+
+  pub fn to_message(&self) -> Message {
+    let mut payload = vec![self.protocol_version];
+    payload.extend_from_slice(&self.features.0.to_be_bytes());
+    push_string(&mut payload, &self.firmware_version);
+    match &self.spa_model {
+      Some(model) => {
+        push_string(&mut payload, &model.system_model_number);
+        payload.extend_from_slice(&model.software_version.version);
+      }
+      None => push_string(&mut payload, ""),
+    }
+    Message {
+      channel: Channel::WifiModule,
+      message_type: HELLO_RESPONSE,
+      payload,
+    }
+  }
+
+  pub fn from_message(message: &Message) -> Result<Self, HelloError> {
+    if message.message_type != HELLO_RESPONSE {
+      return Err(HelloError::WrongType(message.message_type));
+    }
+    let mut rest = message.payload.as_slice();
+    let protocol_version = *take(&mut rest, 1)?.first().unwrap();
+    let features = RelayFeatures(u16::from_be_bytes(take(&mut rest, 2)?.try_into().unwrap()));
+    let firmware_version = take_string(&mut rest)?;
+    let system_model_number = take_string(&mut rest)?;
+    let spa_model = if system_model_number.is_empty() {
+      None
+    } else {
+      let version = take(&mut rest, 4)?.try_into().unwrap();
+      Some(SpaModel { system_model_number, software_version: SoftwareVersion { version } })
+    };
+    Ok(Self { protocol_version, features, firmware_version, spa_model })
+  }
+}
+
+fn push_string(payload: &mut Vec<u8>, s: &str) {
+  let mut len = s.len().min(MAX_STRING_LEN);
+  while !s.is_char_boundary(len) {
+    len -= 1;
+  }
+  payload.push(len as u8);
+  payload.extend_from_slice(&s.as_bytes()[..len]);
+}
+
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> Result<&'a [u8], HelloError> {
+  if rest.len() < n {
+    return Err(HelloError::Truncated);
+  }
+  let (taken, remaining) = rest.split_at(n);
+  *rest = remaining;
+  Ok(taken)
+}
+
+fn take_string(rest: &mut &[u8]) -> Result<String, HelloError> {
+  let len = take(rest, 1)?[0];
+  let bytes = take(rest, usize::from(len))?;
+  String::from_utf8(bytes.to_vec()).map_err(|_| HelloError::InvalidString)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_round_trip() -> anyhow::Result<()> {
+    let hello = RelayHello {
+      protocol_version: RELAY_PROTOCOL_VERSION,
+      features: RelayFeatures::FAULT_LOG_CACHE.with(RelayFeatures::MQTT),
+      firmware_version: "1.2.3".to_owned(),
+      spa_model: Some(SpaModel {
+        system_model_number: "BP2100G1".to_owned(),
+        software_version: SoftwareVersion { version: [100, 1, 11, 0] },
+      }),
+    };
+    let message = Message::from_bytes(&hello.to_message().to_bytes()?)?;
+    let decoded = RelayHello::from_message(&message)?;
+    assert_eq!(decoded.features, hello.features);
+    assert!(decoded.features.contains(RelayFeatures::MQTT));
+    assert!(!decoded.features.contains(RelayFeatures::HTTP_API));
+    assert_eq!(decoded.firmware_version, "1.2.3");
+    let model = decoded.spa_model.unwrap();
+    assert_eq!(model.system_model_number, "BP2100G1");
+    assert_eq!(model.software_version.version, [100, 1, 11, 0]);
+
+    let unknown = RelayHello { spa_model: None, ..hello };
+    assert!(RelayHello::from_message(&unknown.to_message())?.spa_model.is_none());
+
+    let mut truncated = unknown.to_message();
+    truncated.payload.pop();
+    assert_eq!(RelayHello::from_message(&truncated).unwrap_err(), HelloError::Truncated);
+    assert_eq!(RelayHello::from_message(&hello_request()).unwrap_err(), HelloError::WrongType(HELLO_REQUEST));
+    Ok(())
+  }
+
+  #[test]
+  fn test_answer_for_spa() -> anyhow::Result<()> {
+    assert!(is_hello_request(&hello_request()));
+    let elsewhere = Message { channel: Channel::Client(0x10), ..hello_request() };
+    assert!(!is_hello_request(&elsewhere));
+
+    let hello = RelayHello {
+      protocol_version: RELAY_PROTOCOL_VERSION,
+      features: RelayFeatures::FAULT_LOG_CACHE.with(RelayFeatures::PASSTHROUGH),
+      firmware_version: "1.2.3".to_owned(),
+      spa_model: None,
+    };
+    let before = RelayHello::from_message(&hello.with_spa(None).to_message())?;
+    assert!(before.spa_model.is_none());
+    assert_eq!(before.features, hello.features);
+
+    let information = InformationResponseMessage::try_from(
+        [100, 1, 11, 0, b'B', b'P', b'2', b'1', b'0', b'0', b'G', b'1', 0, 0, 0, 0, 0, 1, 1, 0, 0]
+            .as_slice())?;
+    let after = RelayHello::from_message(&hello.with_spa(Some(&information)).to_message())?;
+    assert_eq!(after.features, hello.features);
+    assert!(after.features.contains(RelayFeatures::PASSTHROUGH));
+    let model = after.spa_model.unwrap();
+    assert_eq!(model.system_model_number, "BP2100G1");
+    assert_eq!(model.software_version.version, [100, 1, 11, 0]);
+    Ok(())
+  }
+}
@@ -0,0 +1,440 @@
+//! Changes the set temperature and temperature range at set times of the week, e.g. dropping to
+//! the low range overnight and back up before the morning soak.
+//!
+//! The [Schedule] lives in [crate::settings::SCHEDULE], one entry per line:
+//!
+//! ```text
+//! daily 23:00 range=low
+//! weekdays 06:30 range=high
+//! sat,sun 08:00 temp=102F
+//! ```
+//!
+//! Days are `daily`, `weekdays`, `weekends` or a comma separated list of `mon` to `sun`, and
+//! temperatures are `F` or `C`.  Whatever was last scheduled is applied on start and whenever
+//! the schedule changes, so a power cut or restart doesn't lose a setback.  If somebody changes
+//! the spa by hand afterwards we leave it be until the next entry comes round.
+
+use std::cmp::Reverse;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context};
+use log::{info, warn};
+use balboa_spa_messages::message_types::{ItemCode, MessageType, StatusUpdateResponseV1, TemperatureRange};
+use balboa_spa_messages::parsed_enum::ParsedEnum;
+use balboa_spa_messages::temperature::{Temperature, TemperatureScale};
+use balboa_spa_messages::time::ProtocolTime;
+use common_lib::metrics::{Counter, Metrics};
+use crate::time_sync::{LocalClock, Weekday};
+
+/// How long the spa gets to reflect a scheduled change before we stop waiting for it.
+const APPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// A set of days of the week.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Days(u8);
+
+impl Days {
+  pub const DAILY: Days = Days(0b111_1111);
+  pub const WEEKDAYS: Days = Days(0b001_1111);
+  pub const WEEKENDS: Days = Days(0b110_0000);
+
+  pub fn of(days: &[Weekday]) -> Self {
+    Days(days.iter().fold(0, |bits, day| bits | 1 << *day as u8))
+  }
+
+  pub fn contains(&self, day: Weekday) -> bool {
+    self.0 & 1 << day as u8 != 0
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScheduleAction {
+  /// Of whichever range is current at the time.
+  SetTemperature { degrees: f64, scale: TemperatureScale },
+  SetRange(TemperatureRange),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleEntry {
+  pub days: Days,
+  pub at: ProtocolTime,
+  pub action: ScheduleAction,
+}
+
+impl ScheduleEntry {
+  pub fn new(days: Days, at: ProtocolTime, action: ScheduleAction) -> Self {
+    Self { days, at, action }
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+  entries: Vec<ScheduleEntry>,
+}
+
+impl Schedule {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn add_entry(mut self, entry: ScheduleEntry) -> Self {
+    self.entries.push(entry);
+    self
+  }
+
+  pub fn entries(&self) -> &[ScheduleEntry] {
+    &self.entries
+  }
+}
+
+pub fn encode_schedule(schedule: &Schedule) -> String {
+  schedule.entries.iter().map(|entry| format!("{entry}\n")).collect()
+}
+
+pub fn decode_schedule(data: &str) -> anyhow::Result<Schedule> {
+  let mut schedule = Schedule::new();
+  for line in data.lines().map(str::trim).filter(|l| !l.is_empty()) {
+    let entry = decode_entry(line).with_context(|| format!("Bad schedule entry {line:?}"))?;
+    schedule = schedule.add_entry(entry);
+  }
+  Ok(schedule)
+}
+
+fn decode_entry(line: &str) -> anyhow::Result<ScheduleEntry> {
+  let [days, at, action] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+    return Err(anyhow!("Expected <days> <HH:MM> <action>"));
+  };
+  let days = match days {
+    "daily" => Days::DAILY,
+    "weekdays" => Days::WEEKDAYS,
+    "weekends" => Days::WEEKENDS,
+    list => {
+      let days = list.split(',').map(decode_weekday).collect::<anyhow::Result<Vec<_>>>()?;
+      Days::of(&days)
+    }
+  };
+  let (hour, minute) = at.split_once(':').ok_or_else(|| anyhow!("Bad time {at:?}"))?;
+  let (hour, minute) = (hour.parse::<u8>()?, minute.parse::<u8>()?);
+  if hour >= 24 || minute >= 60 {
+    return Err(anyhow!("Bad time {at:?}"));
+  }
+  let action = match action.split_once('=') {
+    Some(("range", "low")) => ScheduleAction::SetRange(TemperatureRange::Low),
+    Some(("range", "high")) => ScheduleAction::SetRange(TemperatureRange::High),
+    Some(("temp", temp)) => {
+      let (degrees, scale) = if let Some(degrees) = temp.strip_suffix('F') {
+        (degrees, TemperatureScale::Fahrenheit)
+      } else if let Some(degrees) = temp.strip_suffix('C') {
+        (degrees, TemperatureScale::Celsius)
+      } else {
+        return Err(anyhow!("Temperature {temp:?} needs F or C"));
+      };
+      ScheduleAction::SetTemperature { degrees: degrees.parse()?, scale }
+    }
+    _ => return Err(anyhow!("Unknown action {action:?}")),
+  };
+  Ok(ScheduleEntry::new(days, ProtocolTime::from_hm(hour, minute), action))
+}
+
+fn decode_weekday(day: &str) -> anyhow::Result<Weekday> {
+  let index = DAY_NAMES.iter().position(|name| *name == day)
+      .ok_or_else(|| anyhow!("Unknown day {day:?}"))?;
+  Ok(Weekday::ALL[index])
+}
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl Display for ScheduleEntry {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self.days {
+      Days::DAILY => write!(f, "daily")?,
+      Days::WEEKDAYS => write!(f, "weekdays")?,
+      Days::WEEKENDS => write!(f, "weekends")?,
+      days => {
+        let names = Weekday::ALL.iter()
+            .filter(|day| days.contains(**day))
+            .map(|day| DAY_NAMES[*day as usize])
+            .collect::<Vec<_>>();
+        write!(f, "{}", names.join(","))?
+      }
+    }
+    write!(f, " {} ", self.at)?;
+    match &self.action {
+      ScheduleAction::SetRange(TemperatureRange::Low) => write!(f, "range=low"),
+      ScheduleAction::SetRange(TemperatureRange::High) => write!(f, "range=high"),
+      ScheduleAction::SetTemperature { degrees, scale } => {
+        let unit = match scale {
+          TemperatureScale::Fahrenheit => "F",
+          TemperatureScale::Celsius => "C",
+        };
+        write!(f, "temp={degrees}{unit}")
+      }
+    }
+  }
+}
+
+/// Range and temperature entries are tracked separately, so that e.g. a range change doesn't
+/// end an earlier temperature entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+  Range,
+  Temperature,
+}
+
+impl ScheduleAction {
+  fn kind(&self) -> Kind {
+    match self {
+      ScheduleAction::SetRange(_) => Kind::Range,
+      ScheduleAction::SetTemperature { .. } => Kind::Temperature,
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+  /// Index of the entry in effect and the minute of the week it came into effect, None before
+  /// the first poll or with no entries of this kind.
+  entry: Option<(usize, u32)>,
+  state: SlotState,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+enum SlotState {
+  #[default]
+  Pending,
+  Sent(Instant),
+
+  /// In effect as of the range and raw set temperature at the time.
+  Applied(TemperatureRange, u8),
+
+  /// Changed by hand, or the spa wouldn't take it.  Nothing happens until the next entry.
+  LeftAlone,
+}
+
+/// Applies the [Schedule] to the spa, driven by the main board traffic we see.
+#[derive(Debug)]
+pub(crate) struct Scheduler {
+  schedule: Schedule,
+  clock: Box<dyn LocalClock>,
+  status: Option<StatusUpdateResponseV1>,
+  range: Slot,
+  temperature: Slot,
+  overrides: Counter,
+}
+
+impl Scheduler {
+  pub fn new(schedule: Schedule, clock: Box<dyn LocalClock>) -> Self {
+    Self {
+      schedule,
+      clock,
+      status: None,
+      range: Slot::default(),
+      temperature: Slot::default(),
+      overrides: Metrics::global().counter("wifi_module.schedule_overrides"),
+    }
+  }
+
+  /// Starts over with `schedule`, applying whatever it says should be in effect right now.
+  pub fn set_schedule(&mut self, schedule: Schedule) {
+    info!("Schedule now has {} entries", schedule.entries.len());
+    self.schedule = schedule;
+    self.range = Slot::default();
+    self.temperature = Slot::default();
+  }
+
+  pub fn on_message(&mut self, mt: &MessageType) {
+    if let MessageType::StatusUpdate(status) = mt {
+      self.status = Some(status.v1.clone());
+    }
+  }
+
+  /// The request to send now, if any.  Only call once we have a channel to send on.
+  pub fn poll(&mut self, now: Instant) -> Option<MessageType> {
+    if self.schedule.entries.is_empty() {
+      return None;
+    }
+    let status = self.status.as_ref()?;
+    let time = self.clock.local_time()?;
+    let weekday = self.clock.local_weekday()?;
+    let minute_of_week = weekday as u32 * MINUTES_PER_DAY + minute_of_day(&time);
+    let observed = (status.temperate_range, status.set_temperature.raw_value());
+
+    // Range first, as a temperature set before it would land in the wrong one.
+    for kind in [Kind::Range, Kind::Temperature] {
+      let active = active_entry(&self.schedule, kind, minute_of_week);
+      let slot = match kind {
+        Kind::Range => &mut self.range,
+        Kind::Temperature => &mut self.temperature,
+      };
+      if slot.entry != active {
+        if let Some((index, _)) = active {
+          info!("Scheduled: {}", self.schedule.entries[index]);
+        }
+        *slot = Slot { entry: active, state: SlotState::Pending };
+      }
+      let Some((index, _)) = slot.entry else {
+        continue;
+      };
+      let entry = &self.schedule.entries[index];
+      match slot.state {
+        SlotState::Pending | SlotState::Sent(_) => {
+          let request = match request_for(&entry.action, status) {
+            Ok(request) => request,
+            Err(e) => {
+              warn!("Can't apply scheduled {entry}: {e}");
+              slot.state = SlotState::LeftAlone;
+              continue;
+            }
+          };
+          match (request, slot.state) {
+            (None, _) => slot.state = SlotState::Applied(observed.0, observed.1),
+            (Some(request), SlotState::Pending) => {
+              slot.state = SlotState::Sent(now);
+              return Some(request);
+            }
+            (Some(_), SlotState::Sent(sent)) if now < sent + APPLY_TIMEOUT => return None,
+            (Some(_), _) => {
+              warn!("Spa didn't take scheduled {entry}, waiting for the next entry");
+              slot.state = SlotState::LeftAlone;
+            }
+          }
+        }
+        SlotState::Applied(range, set_raw) => {
+          // A set temperature belongs to its range, so only the range slot minds a range change.
+          let changed = match kind {
+            Kind::Range => observed.0 != range,
+            Kind::Temperature => observed.0 == range && observed.1 != set_raw,
+          };
+          if changed {
+            info!("Spa changed by hand, leaving {entry} be until the next entry");
+            self.overrides.inc();
+            slot.state = SlotState::LeftAlone;
+          }
+        }
+        SlotState::LeftAlone => {}
+      }
+    }
+    None
+  }
+}
+
+fn minute_of_day(time: &ProtocolTime) -> u32 {
+  (time.as_duration().as_secs() / 60) as u32
+}
+
+/// The most recent occurrence of an entry of `kind` at or before `minute_of_week`, wrapping
+/// round to last week's if need be.  Later entries win ties.
+fn active_entry(schedule: &Schedule, kind: Kind, minute_of_week: u32) -> Option<(usize, u32)> {
+  let week = 7 * MINUTES_PER_DAY;
+  schedule.entries.iter().enumerate()
+      .filter(|(_, entry)| entry.action.kind() == kind)
+      .flat_map(|(index, entry)| {
+        Weekday::ALL.into_iter()
+            .filter(|day| entry.days.contains(*day))
+            .map(move |day| (index, day as u32 * MINUTES_PER_DAY + minute_of_day(&entry.at)))
+      })
+      .min_by_key(|(index, minute)| ((minute_of_week + week - minute) % week, Reverse(*index)))
+}
+
+/// The request that brings `action` about, or None if it's already in effect.
+fn request_for(
+    action: &ScheduleAction,
+    status: &StatusUpdateResponseV1,
+) -> anyhow::Result<Option<MessageType>> {
+  match action {
+    ScheduleAction::SetRange(range) => {
+      if status.temperate_range == *range {
+        return Ok(None);
+      }
+      Ok(Some(MessageType::ToggleItemRequest {
+        item_code: ParsedEnum::new(ItemCode::TemperatureRange),
+        dummy1: 0,
+      }))
+    }
+    ScheduleAction::SetTemperature { degrees, scale } => {
+      let target = match scale {
+        TemperatureScale::Fahrenheit => Temperature::from_fahrenheit(*degrees),
+        TemperatureScale::Celsius => Temperature::from_celsius(*degrees),
+      };
+      let temperature = status.set_temperature.raw_scale.new_set_temperature(&target)?;
+      if temperature.raw_value() == status.set_temperature.raw_value() {
+        return Ok(None);
+      }
+      Ok(Some(MessageType::SetTemperatureRequest { temperature }))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use balboa_spa_messages::message_types::StatusUpdateMessage;
+  use super::*;
+
+  #[derive(Debug, Clone)]
+  struct FakeClock(Arc<Mutex<(Weekday, ProtocolTime)>>);
+
+  impl LocalClock for FakeClock {
+    fn local_time(&self) -> Option<ProtocolTime> {
+      Some(self.0.lock().unwrap().1)
+    }
+
+    fn local_weekday(&self) -> Option<Weekday> {
+      Some(self.0.lock().unwrap().0)
+    }
+  }
+
+  fn status(range: TemperatureRange, set_raw: u8) -> MessageType {
+    let mut status = StatusUpdateMessage::try_from([0u8; 24].as_slice()).unwrap();
+    status.v1.temperate_range = range;
+    status.v1.set_temperature = status.v1.set_temperature.raw_scale.new_protocol_temperature_from_raw(set_raw);
+    MessageType::StatusUpdate(status)
+  }
+
+  #[test]
+  fn test_encoding() -> anyhow::Result<()> {
+    let data = "daily 23:00 range=low\nweekdays 06:30 range=high\nmon,sat 08:05 temp=38.5C\n";
+    let schedule = decode_schedule(data)?;
+    assert_eq!(schedule.entries()[2], ScheduleEntry::new(
+        Days::of(&[Weekday::Monday, Weekday::Saturday]),
+        ProtocolTime::from_hm(8, 5),
+        ScheduleAction::SetTemperature { degrees: 38.5, scale: TemperatureScale::Celsius }));
+    assert_eq!(encode_schedule(&schedule), data);
+
+    assert!(decode_schedule("daily 24:00 range=low").is_err());
+    assert!(decode_schedule("someday 08:00 range=low").is_err());
+    assert!(decode_schedule("daily 08:00 temp=100").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_scheduler() {
+    let schedule = decode_schedule("daily 23:00 range=low\ndaily 07:00 range=high\nsun 08:00 temp=102F").unwrap();
+    let clock = FakeClock(Arc::new(Mutex::new((Weekday::Monday, ProtocolTime::from_hm(1, 0)))));
+    let mut scheduler = Scheduler::new(schedule, Box::new(clock.clone()));
+    let now = Instant::now();
+    assert!(scheduler.poll(now).is_none(), "Must wait for a status update");
+
+    // Sunday's temperature still holds on Monday night, in whatever range is current.
+    scheduler.on_message(&status(TemperatureRange::High, 100));
+    assert!(matches!(
+        scheduler.poll(now),
+        Some(MessageType::ToggleItemRequest { item_code, .. }) if item_code.as_ref() == Some(&ItemCode::TemperatureRange)));
+    assert!(scheduler.poll(now).is_none(), "Waiting on the range");
+    scheduler.on_message(&status(TemperatureRange::Low, 90));
+    assert!(matches!(
+        scheduler.poll(now),
+        Some(MessageType::SetTemperatureRequest { temperature }) if temperature.raw_value() == 102));
+    assert!(scheduler.poll(now + APPLY_TIMEOUT).is_none(), "Too hot for the low range, so given up on");
+
+    // Changed by hand, and left that way.
+    scheduler.on_message(&status(TemperatureRange::High, 100));
+    assert!(scheduler.poll(now).is_none());
+    assert!(scheduler.poll(now).is_none());
+
+    *clock.0.lock().unwrap() = (Weekday::Monday, ProtocolTime::from_hm(23, 0));
+    assert!(matches!(scheduler.poll(now), Some(MessageType::ToggleItemRequest { .. })));
+  }
+}
@@ -0,0 +1,86 @@
+//! Accepted connections for our TCP servers, which may or may not be wrapped in TLS depending
+//! on how the client was configured (see [crate::tls]).
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(feature = "tls")]
+use crate::tls::{TlsAcceptor, TlsStream};
+
+/// Turns each accepted socket into a [ServerStream], doing the TLS handshake if configured.
+#[derive(Clone, Default)]
+pub(crate) struct StreamAcceptor {
+  #[cfg(feature = "tls")]
+  tls: Option<TlsAcceptor>,
+}
+
+impl StreamAcceptor {
+  #[cfg(feature = "tls")]
+  pub fn tls(acceptor: TlsAcceptor) -> Self {
+    Self { tls: Some(acceptor) }
+  }
+
+  /// May block on the client, so it's best done off the accept loop with a read timeout set.
+  pub fn accept(&self, stream: TcpStream) -> io::Result<ServerStream> {
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &self.tls {
+      return Ok(ServerStream::Tls(Box::new(tls.accept(stream)?)));
+    }
+    Ok(ServerStream::Plain(stream))
+  }
+}
+
+/// Like [TcpStream], reads and writes go through shared references so that a connection can
+/// be serviced by separate reader and writer threads.
+pub(crate) enum ServerStream {
+  Plain(TcpStream),
+  #[cfg(feature = "tls")]
+  Tls(Box<TlsStream>),
+}
+
+impl ServerStream {
+  pub fn get_ref(&self) -> &TcpStream {
+    match self {
+      ServerStream::Plain(stream) => stream,
+      #[cfg(feature = "tls")]
+      ServerStream::Tls(stream) => stream.get_ref(),
+    }
+  }
+
+  /// Closes both directions, unblocking any thread stuck reading.
+  pub fn shutdown(&self) -> io::Result<()> {
+    match self {
+      ServerStream::Plain(stream) => stream.shutdown(Shutdown::Both),
+      #[cfg(feature = "tls")]
+      ServerStream::Tls(stream) => stream.shutdown(),
+    }
+  }
+}
+
+impl Read for &ServerStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      ServerStream::Plain(stream) => (&*stream).read(buf),
+      #[cfg(feature = "tls")]
+      ServerStream::Tls(stream) => (&**stream).read(buf),
+    }
+  }
+}
+
+impl Write for &ServerStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      ServerStream::Plain(stream) => (&*stream).write(buf),
+      #[cfg(feature = "tls")]
+      ServerStream::Tls(stream) => (&**stream).write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      ServerStream::Plain(stream) => (&*stream).flush(),
+      #[cfg(feature = "tls")]
+      ServerStream::Tls(stream) => (&**stream).flush(),
+    }
+  }
+}
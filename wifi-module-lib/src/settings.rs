@@ -0,0 +1,333 @@
+//! Persistent key-value configuration for the wifi module: relay port and access policy, static
+//! IP, MQTT topics and so on, kept together in one [SettingsStore] rather than a store per
+//! feature.
+//!
+//! Values are strings, encoded by the feature they belong to (e.g.
+//! [crate::relay_auth::encode_policy]), and keys stay within NVS's 15 character limit.
+//! [Settings] shares a store between threads and tells subscribers what changed, so subsystems
+//! such as the relay's access policy can pick up new configuration without a restart.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use anyhow::{anyhow, Context};
+use crate::ip_config::{decode_ip_config, encode_ip_config, StaticIpConfig};
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttConfig;
+use crate::relay_auth::{decode_policy, encode_policy, RelayAccessPolicy};
+use crate::schedule::{decode_schedule, encode_schedule, Schedule};
+#[cfg(feature = "tls")]
+use crate::tls::{decode_identity, encode_identity, TlsIdentity};
+
+/// TCP port of the raw IP relay.  Applies from the next start.
+pub const RELAY_PORT: &str = "relay_port";
+
+/// Name to advertise on the network instead of the platform's default, see
+/// [crate::advertisement::Advertisement::set_name].  Applies from the next start.
+pub const DEVICE_NAME: &str = "device_name";
+
+/// [RelayAccessPolicy] as per [encode_policy].  Applies to new connections immediately.
+pub const RELAY_ACCESS: &str = "relay_access";
+
+/// [StaticIpConfig] as per [encode_ip_config].  Applies from the next start.
+pub const STATIC_IP: &str = "static_ip";
+
+/// Broker URL, for the platform's MQTT client.
+pub const MQTT_BROKER: &str = "mqtt_broker";
+
+pub const MQTT_BASE_TOPIC: &str = "mqtt_topic";
+
+pub const MQTT_DEVICE_NAME: &str = "mqtt_name";
+
+pub const MQTT_DISCOVERY: &str = "mqtt_discovery";
+
+/// Default URL to pull firmware updates from, see [crate::ota].
+pub const OTA_URL: &str = "ota_url";
+
+/// Bearer token the platform passes to
+/// [crate::wifi_module_client::WifiModuleClient::enable_ota].  Applies from the next start.
+pub const OTA_TOKEN: &str = "ota_token";
+
+/// POSIX TZ string (e.g. `PST8PDT,M3.2.0,M11.1.0`) for the platform's clock, see
+/// [crate::time_sync::LocalClock].  Applies from the next start.
+pub const TIMEZONE: &str = "timezone";
+
+/// [crate::schedule::Schedule] as per [encode_schedule].  Applies immediately.
+pub const SCHEDULE: &str = "schedule";
+
+/// Where to POST alerts if [crate::alerts::AlertConfig] doesn't say.  Applies from the next
+/// start.
+pub const WEBHOOK_URL: &str = "webhook_url";
+
+/// [crate::tls::TlsIdentity] as per [crate::tls::encode_identity], generated on first use if
+/// TLS is enabled.  Applies from the next start.
+pub const TLS_IDENTITY: &str = "tls_identity";
+
+const MAX_KEY_LEN: usize = 15;
+
+pub trait SettingsStore: Debug + Send {
+  fn get(&self, key: &str) -> anyhow::Result<Option<String>>;
+
+  fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()>;
+
+  /// Does nothing if `key` isn't set.
+  fn remove(&mut self, key: &str) -> anyhow::Result<()>;
+}
+
+/// Keeps each setting in a file of the same name under a directory, for desktop builds.
+#[derive(Debug)]
+pub struct FileSettingsStore {
+  dir: PathBuf,
+}
+
+impl FileSettingsStore {
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self { dir: dir.into() }
+  }
+}
+
+impl SettingsStore for FileSettingsStore {
+  fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+    match fs::read_to_string(self.dir.join(key)) {
+      Ok(value) => Ok(Some(value)),
+      Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+    fs::create_dir_all(&self.dir)?;
+    fs::write(self.dir.join(key), value)?;
+    Ok(())
+  }
+
+  fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+    match fs::remove_file(self.dir.join(key)) {
+      Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+      _ => Ok(()),
+    }
+  }
+}
+
+/// Doesn't outlive the process, mostly useful for tests.  Clones share the same settings.
+#[derive(Debug, Default, Clone)]
+pub struct MemorySettingsStore {
+  values: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MemorySettingsStore {
+  pub fn new() -> Self {
+    Default::default()
+  }
+}
+
+impl SettingsStore for MemorySettingsStore {
+  fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+    Ok(self.values.lock().unwrap().get(key).cloned())
+  }
+
+  fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+    self.values.lock().unwrap().insert(key.to_owned(), value.to_owned());
+    Ok(())
+  }
+
+  fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+    self.values.lock().unwrap().remove(key);
+    Ok(())
+  }
+}
+
+/// A [SettingsStore] shared between threads, with change notifications.  Clones share the same
+/// store and subscribers.
+#[derive(Debug, Clone)]
+pub struct Settings {
+  inner: Arc<Mutex<SettingsInner>>,
+}
+
+#[derive(Debug)]
+struct SettingsInner {
+  store: Box<dyn SettingsStore>,
+  subscribers: Vec<Sender<String>>,
+}
+
+impl Settings {
+  pub fn new(store: Box<dyn SettingsStore>) -> Self {
+    Self {
+      inner: Arc::new(Mutex::new(SettingsInner { store, subscribers: vec![] })),
+    }
+  }
+
+  pub fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+    self.inner.lock().unwrap().store.get(key)
+  }
+
+  pub fn get_parsed<T>(&self, key: &str) -> anyhow::Result<Option<T>>
+  where
+      T: FromStr,
+      T::Err: Display,
+  {
+    self.get(key)?
+        .map(|value| value.trim().parse::<T>().map_err(|e| anyhow!("Bad {key} {value:?}: {e}")))
+        .transpose()
+  }
+
+  /// Subscribers are only told if the value actually changed.
+  pub fn set(&self, key: &str, value: &str) -> anyhow::Result<()> {
+    check_key(key)?;
+    let mut inner = self.inner.lock().unwrap();
+    if inner.store.get(key)?.as_deref() == Some(value) {
+      return Ok(());
+    }
+    inner.store.set(key, value)?;
+    inner.notify(key);
+    Ok(())
+  }
+
+  pub fn remove(&self, key: &str) -> anyhow::Result<()> {
+    let mut inner = self.inner.lock().unwrap();
+    if inner.store.get(key)?.is_none() {
+      return Ok(());
+    }
+    inner.store.remove(key)?;
+    inner.notify(key);
+    Ok(())
+  }
+
+  /// Receives the key of every setting changed from now on.  Drop it to unsubscribe.
+  pub fn subscribe(&self) -> Receiver<String> {
+    let (tx, rx) = channel();
+    self.inner.lock().unwrap().subscribers.push(tx);
+    rx
+  }
+
+  /// The saved policy, or the default (open) one.
+  pub fn relay_access(&self) -> anyhow::Result<RelayAccessPolicy> {
+    self.get(RELAY_ACCESS)?
+        .map(|data| decode_policy(&data).context("Corrupt relay access setting"))
+        .transpose()
+        .map(Option::unwrap_or_default)
+  }
+
+  pub fn set_relay_access(&self, policy: &RelayAccessPolicy) -> anyhow::Result<()> {
+    self.set(RELAY_ACCESS, &encode_policy(policy))
+  }
+
+  /// The saved schedule, or an empty one.
+  pub fn schedule(&self) -> anyhow::Result<Schedule> {
+    self.get(SCHEDULE)?
+        .map(|data| decode_schedule(&data).context("Corrupt schedule setting"))
+        .transpose()
+        .map(Option::unwrap_or_default)
+  }
+
+  pub fn set_schedule(&self, schedule: &Schedule) -> anyhow::Result<()> {
+    self.set(SCHEDULE, &encode_schedule(schedule))
+  }
+
+  /// The saved static address, or None to use DHCP.
+  pub fn static_ip(&self) -> anyhow::Result<Option<StaticIpConfig>> {
+    self.get(STATIC_IP)?
+        .map(|data| decode_ip_config(&data).context("Corrupt static IP setting"))
+        .transpose()
+  }
+
+  pub fn set_static_ip(&self, config: &StaticIpConfig) -> anyhow::Result<()> {
+    self.set(STATIC_IP, &encode_ip_config(config))
+  }
+
+  /// The saved identity, or a newly generated self-signed one for `hostname` which is saved
+  /// for next time.
+  #[cfg(feature = "tls")]
+  pub fn tls_identity(&self, hostname: &str) -> anyhow::Result<TlsIdentity> {
+    if let Some(data) = self.get(TLS_IDENTITY)? {
+      return decode_identity(&data).context("Corrupt TLS identity setting");
+    }
+    let identity = TlsIdentity::generate_self_signed(hostname)?;
+    self.set(TLS_IDENTITY, &encode_identity(&identity))?;
+    Ok(identity)
+  }
+
+  /// `MqttConfig::new(device_id)` with whatever the MQTT settings override.
+  #[cfg(feature = "mqtt")]
+  pub fn mqtt_config(&self, device_id: &str) -> anyhow::Result<MqttConfig> {
+    let mut config = MqttConfig::new(device_id);
+    if let Some(name) = self.get(MQTT_DEVICE_NAME)? {
+      config = config.set_device_name(&name);
+    }
+    if let Some(topic) = self.get(MQTT_BASE_TOPIC)? {
+      config = config.set_base_topic(&topic);
+    }
+    if let Some(prefix) = self.get(MQTT_DISCOVERY)? {
+      config = config.set_discovery_prefix(&prefix);
+    }
+    Ok(config)
+  }
+}
+
+impl SettingsInner {
+  fn notify(&mut self, key: &str) {
+    self.subscribers.retain(|tx| tx.send(key.to_owned()).is_ok());
+  }
+}
+
+fn check_key(key: &str) -> anyhow::Result<()> {
+  let valid_chars = key.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+  if key.is_empty() || key.len() > MAX_KEY_LEN || !valid_chars {
+    return Err(anyhow!("Bad setting key {key:?}"));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc::TryRecvError;
+  use super::*;
+
+  #[test]
+  fn test_notifications() -> anyhow::Result<()> {
+    let settings = Settings::new(Box::new(MemorySettingsStore::new()));
+    let changes = settings.subscribe();
+
+    settings.set(RELAY_PORT, "4300")?;
+    assert_eq!(settings.get_parsed::<u16>(RELAY_PORT)?, Some(4300));
+    assert_eq!(changes.try_recv(), Ok(RELAY_PORT.to_owned()));
+
+    settings.set(RELAY_PORT, "4300")?;
+    assert_eq!(changes.try_recv(), Err(TryRecvError::Empty), "Unchanged values are not news");
+
+    let policy = RelayAccessPolicy::new().set_token("hunter2");
+    settings.set_relay_access(&policy)?;
+    assert_eq!(settings.relay_access()?, policy);
+    assert_eq!(changes.try_recv(), Ok(RELAY_ACCESS.to_owned()));
+
+    settings.remove(RELAY_ACCESS)?;
+    assert_eq!(settings.relay_access()?, RelayAccessPolicy::default());
+    assert_eq!(changes.try_recv(), Ok(RELAY_ACCESS.to_owned()));
+
+    assert_eq!(settings.static_ip()?, None);
+    let config = StaticIpConfig::new("10.0.0.5".parse()?, 8, "10.0.0.1".parse()?);
+    settings.set_static_ip(&config)?;
+    assert_eq!(settings.static_ip()?, Some(config));
+    assert_eq!(changes.try_recv(), Ok(STATIC_IP.to_owned()));
+
+    assert!(settings.set("Not a key", "x").is_err());
+    assert!(settings.set("much_too_long_a_key", "x").is_err());
+    Ok(())
+  }
+
+  #[cfg(feature = "tls")]
+  #[test]
+  fn test_tls_identity() -> anyhow::Result<()> {
+    let settings = Settings::new(Box::new(MemorySettingsStore::new()));
+    let identity = settings.tls_identity("spa.local")?;
+    assert_eq!(settings.tls_identity("spa.local")?, identity);
+    assert_eq!(decode_identity(&encode_identity(&identity))?, identity);
+    assert!(decode_identity(identity.cert_pem()).is_err());
+    Ok(())
+  }
+}
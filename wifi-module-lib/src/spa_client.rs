@@ -0,0 +1,122 @@
+//! Typed access to the spa for code running alongside the wifi module, such as the local HTTP
+//! API, so that it needn't deal in raw messages or ClearToSend timing.
+//!
+//! Commands join the same outbound queue as everything else and return a [PendingCommand],
+//! which resolves once a later status update shows the change took effect, or fails if none
+//! does even after a retry.  The protocol has no acknowledgements of its own.
+
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::time::Duration;
+use log::info;
+use balboa_spa_messages::message_types::{ConfigurationResponseMessage, ItemCode, MessageType, StatusUpdateMessage};
+use balboa_spa_messages::parsed_enum::ParsedEnum;
+use balboa_spa_messages::temperature::Temperature;
+use crate::command::Command;
+use crate::confirmations::DoneSender;
+use crate::spa_state_cache::SpaStateCache;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SpaClientError {
+  #[error("No status received from the spa yet")]
+  NoStatus,
+
+  #[error("Temperature limits not received from the spa yet")]
+  NoTemperatureLimits,
+
+  #[error("Invalid temperature: {0}")]
+  BadTemperature(String),
+
+  #[error("Spa didn't confirm the change")]
+  NotConfirmed,
+
+  #[error("Shutting down")]
+  ShuttingDown,
+}
+
+/// Cheap to clone, see [crate::wifi_module_client::Runner::spa_client].
+#[derive(Debug, Clone)]
+pub struct SpaClient {
+  commands_tx: SyncSender<Command>,
+  cache: SpaStateCache,
+}
+
+impl SpaClient {
+  pub(crate) fn new(commands_tx: SyncSender<Command>, cache: SpaStateCache) -> Self {
+    Self { commands_tx, cache }
+  }
+
+  /// The latest status update, if any yet.
+  pub fn status(&self) -> Option<StatusUpdateMessage> {
+    self.cache.snapshot().status
+  }
+
+  /// What's fitted, once anyone has asked the board.
+  pub fn configuration(&self) -> Option<ConfigurationResponseMessage> {
+    self.cache.snapshot().configuration
+  }
+
+  /// In whichever scale the spa is currently using, rounded to its resolution.  Must be within
+  /// the spa's limits for the current temperature range.
+  pub fn set_temperature(&self, target: Temperature) -> Result<PendingCommand, SpaClientError> {
+    let state = self.cache.snapshot();
+    let status = state.status.ok_or(SpaClientError::NoStatus)?;
+    let limits = state.temperature_limits.ok_or(SpaClientError::NoTemperatureLimits)?;
+    limits.check(&status.v1.temperate_range, target)
+        .map_err(|e| SpaClientError::BadTemperature(e.to_string()))?;
+    let temperature = status.v1.set_temperature.raw_scale.new_set_temperature(&target)
+        .map_err(|e| SpaClientError::BadTemperature(e.to_string()))?;
+    self.send(MessageType::SetTemperatureRequest { temperature })
+  }
+
+  /// Pumps step through their speeds, everything else switches on or off.
+  pub fn toggle(&self, item: ItemCode) -> Result<PendingCommand, SpaClientError> {
+    self.send(MessageType::ToggleItemRequest { item_code: ParsedEnum::new(item), dummy1: 0 })
+  }
+
+  fn send(&self, mt: MessageType) -> Result<PendingCommand, SpaClientError> {
+    info!("Queueing {mt:?} from SpaClient");
+    let (done_tx, done_rx) = channel();
+    self.commands_tx.send(Command::Spa(Box::new(SpaRequest { mt, done: done_tx })))
+        .map_err(|_| SpaClientError::ShuttingDown)?;
+    Ok(PendingCommand { done_rx })
+  }
+}
+
+/// The outcome of a command, once it's known.
+#[derive(Debug)]
+pub struct PendingCommand {
+  done_rx: Receiver<Result<(), SpaClientError>>,
+}
+
+impl PendingCommand {
+  /// Blocks until the spa confirms the change, or gives up on it after
+  /// [crate::confirmations::CONFIRM_TIMEOUT] for each attempt.  Commands that aren't confirmed,
+  /// such as toggles, are done as soon as they're queued.
+  pub fn wait(self) -> Result<(), SpaClientError> {
+    self.done_rx.recv().unwrap_or(Err(SpaClientError::ShuttingDown))
+  }
+
+  /// Like [Self::wait] but gives up after `timeout`.
+  pub fn wait_timeout(self, timeout: Duration) -> Result<(), SpaClientError> {
+    match self.done_rx.recv_timeout(timeout) {
+      Ok(result) => result,
+      Err(RecvTimeoutError::Timeout) => Err(SpaClientError::NotConfirmed),
+      Err(RecvTimeoutError::Disconnected) => Err(SpaClientError::ShuttingDown),
+    }
+  }
+
+  /// None while still waiting to hear.
+  pub fn try_result(&self) -> Option<Result<(), SpaClientError>> {
+    match self.done_rx.try_recv() {
+      Ok(result) => Some(result),
+      Err(TryRecvError::Empty) => None,
+      Err(TryRecvError::Disconnected) => Some(Err(SpaClientError::ShuttingDown)),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub(crate) struct SpaRequest {
+  pub mt: MessageType,
+  pub done: DoneSender,
+}
@@ -0,0 +1,83 @@
+//! JSON views of spa state for the local HTTP API.
+
+use serde_json::{json, Value};
+use balboa_spa_messages::message_types::{Boolean, ConfigurationResponseMessage, HeatingState, ItemCode, PumpStatus, RelayStatus, StatusUpdateResponseV1};
+use balboa_spa_messages::parsed_enum::ParsedEnum;
+use balboa_spa_messages::temperature::{ProtocolTemperature, TemperatureScale};
+
+pub(crate) fn status_json(status: &StatusUpdateResponseV1) -> Value {
+  json!({
+    "current_temperature": status.current_temperature.as_ref().map(temperature_value),
+    "set_temperature": temperature_value(&status.set_temperature),
+    "temperature_scale": match status.set_temperature.raw_scale {
+      TemperatureScale::Fahrenheit => "F",
+      TemperatureScale::Celsius => "C",
+    },
+    "temperature_range": format!("{:?}", status.temperate_range),
+    "heating_mode": format!("{:?}", status.heating_mode),
+    "heating": matches!(status.heating_state.as_ref(), Some(HeatingState::Heating)),
+    "time": status.time.to_string(),
+    "pumps": status.pump_status.iter()
+        .map(|p| match p.as_ref() {
+          Some(PumpStatus::Off) => "off".to_owned(),
+          Some(PumpStatus::Low) => "low".to_owned(),
+          Some(PumpStatus::High) => "high".to_owned(),
+          None => format!("{p:?}"),
+        })
+        .collect::<Vec<_>>(),
+    "lights": status.light_status.iter().map(relay_on).collect::<Vec<_>>(),
+    "blower": relay_on(&status.blower_status),
+    "circulation_pump": is_true(&status.circulation_pump_on),
+    "mister": is_true(&status.mister_on),
+    "panel_locked": status.panel_locked,
+  })
+}
+
+pub(crate) fn configuration_json(configuration: &ConfigurationResponseMessage) -> Value {
+  json!({
+    "pumps": configuration.pumps.iter().map(|p| format!("{p:?}")).collect::<Vec<_>>(),
+    "lights": configuration.has_lights.iter().map(is_true).collect::<Vec<_>>(),
+    "blower": configuration.has_blower,
+    "circulation_pump": configuration.has_circulation_pump,
+    "aux": configuration.has_aux.iter().map(is_true).collect::<Vec<_>>(),
+    "mister": is_true(&configuration.has_mister),
+  })
+}
+
+/// Item names accepted by the APIs, e.g. `pump1` or `light2`.
+pub(crate) fn parse_item_code(name: &str) -> Option<ItemCode> {
+  let code = match name {
+    "pump1" => ItemCode::Pump1,
+    "pump2" => ItemCode::Pump2,
+    "pump3" => ItemCode::Pump3,
+    "pump4" => ItemCode::Pump4,
+    "pump5" => ItemCode::Pump5,
+    "pump6" => ItemCode::Pump6,
+    "blower" => ItemCode::Blower,
+    "mister" => ItemCode::Mister,
+    "light1" => ItemCode::Light1,
+    "light2" => ItemCode::Light2,
+    "aux1" => ItemCode::Aux1,
+    "aux2" => ItemCode::Aux2,
+    "hold_mode" => ItemCode::HoldMode,
+    "temperature_range" => ItemCode::TemperatureRange,
+    "heat_mode" => ItemCode::HeatMode,
+    _ => return None,
+  };
+  Some(code)
+}
+
+fn temperature_value(temperature: &ProtocolTemperature) -> f64 {
+  match temperature.raw_scale {
+    TemperatureScale::Fahrenheit => temperature.temperature.as_fahrenheit(),
+    TemperatureScale::Celsius => temperature.temperature.as_celsius(),
+  }
+}
+
+fn relay_on(relay: &ParsedEnum<RelayStatus, u8>) -> bool {
+  matches!(relay.as_ref(), Some(RelayStatus::On))
+}
+
+fn is_true(value: &ParsedEnum<Boolean, u8>) -> bool {
+  matches!(value.as_ref(), Some(Boolean::True))
+}
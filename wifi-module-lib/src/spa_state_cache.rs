@@ -0,0 +1,52 @@
+use std::sync::{Arc, Mutex};
+use balboa_spa_messages::message_types::{ConfigurationResponseMessage, InformationResponseMessage, MessageType, StatusUpdateMessage, TemperatureMinMax};
+use crate::fault_log::FaultLogCache;
+
+/// Latest spa state seen on the bus, shared with the local APIs so they can answer without
+/// waiting on the main board.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SpaStateCache {
+  inner: Arc<Mutex<CachedSpaState>>,
+  fault_log: FaultLogCache,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CachedSpaState {
+  pub status: Option<StatusUpdateMessage>,
+  pub configuration: Option<ConfigurationResponseMessage>,
+  pub information: Option<InformationResponseMessage>,
+  pub temperature_limits: Option<TemperatureMinMax>,
+}
+
+impl SpaStateCache {
+  /// `fault_log` is kept up to date separately, see [crate::fault_log::FaultLogPoller].
+  pub fn new(fault_log: FaultLogCache) -> Self {
+    Self { inner: Default::default(), fault_log }
+  }
+
+  pub fn update(&self, mt: &MessageType) {
+    match mt {
+      MessageType::StatusUpdate(status) => {
+        self.inner.lock().unwrap().status = Some(status.clone());
+      }
+      MessageType::ConfigurationResponse(configuration) => {
+        self.inner.lock().unwrap().configuration = Some(configuration.clone());
+      }
+      MessageType::InformationResponse(information) => {
+        self.inner.lock().unwrap().information = Some(information.clone());
+      }
+      MessageType::Settings0x04Response(settings) => {
+        self.inner.lock().unwrap().temperature_limits = Some(settings.min_max_temps.clone());
+      }
+      _ => {}
+    }
+  }
+
+  pub fn snapshot(&self) -> CachedSpaState {
+    self.inner.lock().unwrap().clone()
+  }
+
+  pub fn fault_log(&self) -> &FaultLogCache {
+    &self.fault_log
+  }
+}
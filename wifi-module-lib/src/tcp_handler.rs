@@ -1,82 +1,247 @@
+use std::convert::Infallible;
 use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::{io, thread};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::mpsc::{SyncSender};
 use std::time::Duration;
-use log::{debug, info, warn};
+use anyhow::anyhow;
+use log::{debug, error, info, warn};
 use balboa_spa_messages::framed_reader::FramedReader;
 use balboa_spa_messages::framed_writer::FramedWriter;
 use common_lib::message_logger::{MessageDirection, MessageLogger};
+use common_lib::metrics::Metrics;
 use crate::broadcaster::BroadcastReceiver;
 use crate::command::Command;
-use crate::relay_event::RelayEvent;
+use crate::dual_stack::{bind_tcp, canonical};
+use crate::relay_auth::RelayAccessPolicy;
+use crate::relay_event::{RelayClientId, RelayEvent};
+use crate::server_stream::{ServerStream, StreamAcceptor};
 
-const TCP_PORT: u16 = 4257;
+pub const TCP_PORT: u16 = 4257;
+
+/// Not known to match anything official, see [RelayProtocol::Passthrough].
+pub const PASSTHROUGH_TCP_PORT: u16 = 4259;
 
 const READ_TIMEOUT: Duration = Duration::from_secs(120);
 
+/// Clients that need a TLS handshake or to authenticate do so straight away or not at all.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Before rebinding a listener that failed, so a persistent problem doesn't spin.
+const RESTART_DELAY: Duration = if cfg!(test) {
+  Duration::from_millis(100)
+} else {
+  Duration::from_secs(5)
+};
+
+/// Before accepting again after an error that [is_transient], so running out of sockets
+/// doesn't spin either.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Shared by all listeners so that ids are unique regardless of protocol.
+static NEXT_CLIENT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// How IP clients frame the messages we relay for them.
+#[derive(Clone)]
+pub(crate) enum RelayProtocol {
+  /// Exactly what is on the RS-485 bus.
+  Raw,
+
+  /// Like [Self::Raw] but as if plugged straight into the bus, for tools written against
+  /// serial dongles: every frame on any channel is forwarded as read, without being parsed or
+  /// readdressed, and frames from the client go out unchanged in our next ClearToSend window.
+  /// The client is on its own as far as channel assignment goes.
+  Passthrough,
+}
+
 pub(crate) struct TcpListenerHandler {
   logger: MessageLogger,
-  listener: TcpListener,
+  port: u16,
+
+  /// Taken while accepting, so that the socket is closed before we bind afresh.
+  listener: Option<TcpListener>,
+  protocol: RelayProtocol,
+  acceptor: StreamAcceptor,
+  access: Arc<RwLock<RelayAccessPolicy>>,
   commands_tx: SyncSender<Command>,
   events_rx: BroadcastReceiver<RelayEvent>,
 }
 
 impl TcpListenerHandler {
+  /// `access` may be replaced while running, it's consulted afresh for each connection.
   pub fn setup(
       logger: MessageLogger,
+      protocol: RelayProtocol,
+      port: u16,
+      acceptor: StreamAcceptor,
+      access: Arc<RwLock<RelayAccessPolicy>>,
       commands_tx: SyncSender<Command>,
       events_rx: BroadcastReceiver<RelayEvent>
   ) -> io::Result<Self> {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", TCP_PORT))?;
+    let listener = bind_tcp(port)?;
+    // Whatever we were given, as restarts need to come back on the same port.
+    let port = listener.local_addr()?.port();
     Ok(Self {
       logger,
-      listener,
+      port,
+      listener: Some(listener),
+      protocol,
+      acceptor,
+      access,
       commands_tx,
       events_rx,
     })
   }
 
-  pub fn run_loop(self) -> anyhow::Result<()> {
+  /// Serve each connection on its own threads.  Clients are independent: each gets its own
+  /// logger and send queue (a client that falls too far behind is disconnected rather than
+  /// holding up the others) while their requests are funneled through the single command queue
+  /// towards the main board.
+  ///
+  /// Never returns: if accepting fails, the listener is bound afresh and we carry on, as the
+  /// module is no use as a relay without it.
+  pub fn run_loop(mut self) {
+    let restarts = Metrics::global().counter("wifi_module.relay_restarts");
     loop {
-      let (stream, peer) = self.listener.accept()?;
-      info!("Accepted connection from: {peer}");
+      let listener = match self.listener.take() {
+        Some(listener) => listener,
+        None => match bind_tcp(self.port) {
+          Ok(listener) => {
+            info!("Relay listener on port {} restarted", self.port);
+            listener
+          }
+          Err(e) => {
+            warn!("Can't rebind port {}: {e}", self.port);
+            thread::sleep(RESTART_DELAY);
+            continue;
+          }
+        },
+      };
+      let Err(e) = self.accept_loop(&listener);
+      error!("Relay listener on port {} failed: {e}", self.port);
+      drop(listener);
+      restarts.inc();
+      thread::sleep(RESTART_DELAY);
+    }
+  }
 
-      stream.set_read_timeout(Some(READ_TIMEOUT))?;
+  fn accept_loop(&self, listener: &TcpListener) -> anyhow::Result<Infallible> {
+    let connected = Metrics::global().gauge("wifi_module.ip_clients");
+    let rejected = Metrics::global().counter("wifi_module.ip_clients_rejected");
+    loop {
+      let (stream, peer) = match listener.accept() {
+        Ok(accepted) => accepted,
+        Err(e) if is_transient(&e) => {
+          warn!("Accept on port {} failed, retrying: {e}", self.port);
+          thread::sleep(ACCEPT_RETRY_DELAY);
+          continue;
+        }
+        Err(e) => Err(e)?,
+      };
+      let peer = canonical(peer);
+      if !self.access.read().unwrap().allows_peer(&peer.ip()) {
+        warn!("Rejecting connection from {peer}, not in the allowlist");
+        rejected.inc();
+        continue;
+      }
+      let client = RelayClientId(NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed));
+      info!("Accepted connection from: {peer} ({client:?})");
 
       let stream_handler = TcpStreamHandler {
-        stream,
         peer,
+        client,
+        protocol: self.protocol.clone(),
+        acceptor: self.acceptor.clone(),
+        access: self.access.clone(),
         commands_tx: self.commands_tx.clone(),
         events_rx: self.events_rx.clone(),
-        logger: self.logger.clone(),
+        logger: self.logger.fork(),
       };
 
+      let connected = connected.clone();
+      let rejected = rejected.clone();
       thread::Builder::new()
           .name(format!("TcpHandler-{peer}").to_owned())
-          .spawn(move || stream_handler.run_loop())
-          .unwrap();
+          .spawn(move || {
+            connected.add(1);
+            match stream_handler.open(stream) {
+              Ok(stream) => {
+                if let Err(e) = stream_handler.run_loop(stream) {
+                  error!("Handler for {peer} ({client:?}) failed: {e}");
+                }
+              }
+              Err(e) => {
+                warn!("Rejecting connection from {peer}: {e}");
+                rejected.inc();
+              }
+            }
+            connected.add(-1);
+            info!("Disconnected from {peer} ({client:?})");
+          })?;
     }
   }
 }
 
+/// Errors that concern one connection, or a momentary shortage, rather than the listener.
+fn is_transient(e: &io::Error) -> bool {
+  match e.kind() {
+    io::ErrorKind::ConnectionAborted
+    | io::ErrorKind::ConnectionReset
+    | io::ErrorKind::Interrupted
+    | io::ErrorKind::TimedOut => true,
+    // ENFILE and EMFILE, numbered alike on lwIP and Linux.
+    _ => matches!(e.raw_os_error(), Some(23 | 24)),
+  }
+}
+
 struct TcpStreamHandler {
-  stream: TcpStream,
   peer: SocketAddr,
+  client: RelayClientId,
+  protocol: RelayProtocol,
+  acceptor: StreamAcceptor,
+  access: Arc<RwLock<RelayAccessPolicy>>,
   commands_tx: SyncSender<Command>,
   events_rx: BroadcastReceiver<RelayEvent>,
   logger: MessageLogger,
 }
 
 impl TcpStreamHandler {
-  pub fn run_loop(self) {
+  /// Completes the TLS handshake and checks the client's token, as configured, before it is
+  /// trusted with the relay.
+  fn open(&self, stream: TcpStream) -> anyhow::Result<ServerStream> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let stream = self.acceptor.accept(stream)?;
+    // Not held while waiting on the client.
+    let access = self.access.read().unwrap().clone();
+    if access.requires_token() {
+      access.authenticate(&mut &stream)?;
+      debug!("Authenticated {:?}", self.client);
+    }
+    stream.get_ref().set_read_timeout(Some(READ_TIMEOUT))?;
+    Ok(stream)
+  }
+
+  pub fn run_loop(self, stream: ServerStream) -> anyhow::Result<()> {
+    match self.protocol {
+      RelayProtocol::Raw => self.run_raw(&stream, false),
+      RelayProtocol::Passthrough => self.run_raw(&stream, true),
+    }
+  }
+
+  fn run_raw(self, stream: &ServerStream, passthrough: bool) -> anyhow::Result<()> {
     crossbeam::thread::scope(|s| {
       let reader = TcpStreamReader {
-        reader: FramedReader::new(&self.stream),
+        reader: FramedReader::new(stream),
+        client: self.client,
+        passthrough,
         commands_tx: self.commands_tx,
         logger: &self.logger,
       };
       let writer = TcpStreamWriter {
-        writer: FramedWriter::new(&self.stream),
+        writer: FramedWriter::new(stream),
+        client: self.client,
+        passthrough,
         events_rx: self.events_rx,
         logger: &self.logger,
       };
@@ -87,20 +252,26 @@ impl TcpStreamHandler {
             if let Err(e) = writer.run_loop() {
               warn!("TcpWriter: {e}");
             }
-          })
-          .unwrap();
+            // Likely dropped for falling behind, make sure the reader notices too.
+            let _ = stream.shutdown();
+          })?;
 
       if let Err(e) = reader.run_loop() {
         warn!("TcpReader: {e}");
       }
 
-      writer_thread.join().unwrap()
-    }).unwrap();
+      // The writer only notices once it next fails to write.
+      let _ = stream.shutdown();
+
+      writer_thread.join().map_err(|_| anyhow!("TcpWriter panicked"))
+    }).map_err(|_| anyhow!("TcpReader panicked"))?
   }
 }
 
 struct TcpStreamReader<'a> {
-  reader: FramedReader<&'a TcpStream>,
+  reader: FramedReader<&'a ServerStream>,
+  client: RelayClientId,
+  passthrough: bool,
   commands_tx: SyncSender<Command>,
   logger: &'a MessageLogger,
 }
@@ -110,13 +281,20 @@ impl<'a> TcpStreamReader<'a> {
     loop {
       let message = self.reader.next_message()?;
       self.logger.log(MessageDirection::Inbound, &message);
-      self.commands_tx.send(Command::RelayIpMessage(message))?;
+      let command = if self.passthrough {
+        Command::PassthroughFrame(self.client, message)
+      } else {
+        Command::RelayIpMessage(self.client, message)
+      };
+      self.commands_tx.send(command)?;
     }
   }
 }
 
 struct TcpStreamWriter<'a> {
-  writer: FramedWriter<&'a TcpStream>,
+  writer: FramedWriter<&'a ServerStream>,
+  client: RelayClientId,
+  passthrough: bool,
   events_rx: BroadcastReceiver<RelayEvent>,
   logger: &'a MessageLogger,
 }
@@ -124,11 +302,129 @@ struct TcpStreamWriter<'a> {
 impl<'a> TcpStreamWriter<'a> {
   pub fn run_loop(mut self) -> anyhow::Result<()> {
     loop {
-      match self.events_rx.rx().recv()? {
-        RelayEvent::MessageForIpClient(message) => {
-          self.logger.log(MessageDirection::Outbound, &message);
-          self.writer.write(&message)?
-        }
+      let event = self.events_rx.rx().recv()?;
+      let message = if self.passthrough {
+        event.frame_for_passthrough()
+      } else {
+        event.message_for(self.client)
+      };
+      if let Some(message) = message {
+        self.logger.log(MessageDirection::Outbound, message);
+        self.writer.write(message)?
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::Ipv4Addr;
+  use std::sync::mpsc::{Receiver, sync_channel};
+  use balboa_spa_messages::channel::Channel;
+  use balboa_spa_messages::message::Message;
+  use crate::broadcaster::broadcast_channel;
+  use super::*;
+
+  fn message(message_type: u8, payload: &[u8]) -> Message {
+    Message {
+      channel: Channel::WifiModule,
+      message_type,
+      payload: payload.to_vec(),
+    }
+  }
+
+  /// Sends `request` from `stream` and works out which client the relay took it to be from.
+  fn identify(
+      stream: &TcpStream,
+      request: &Message,
+      commands_rx: &Receiver<Command>,
+  ) -> anyhow::Result<RelayClientId> {
+    FramedWriter::new(stream).write(request)?;
+    match commands_rx.recv_timeout(Duration::from_secs(5))? {
+      Command::RelayIpMessage(client, message) if message == *request => Ok(client),
+      _ => Err(anyhow::anyhow!("Unexpected command for {request:?}")),
+    }
+  }
+
+  #[test]
+  fn test_replies_routed_per_client() -> anyhow::Result<()> {
+    let (commands_tx, commands_rx) = sync_channel(8);
+    let (mut events_tx, events_rx) = broadcast_channel(8);
+    let handler = TcpListenerHandler::setup(
+      MessageLogger::new("TcpTest"),
+      RelayProtocol::Raw,
+      0,
+      StreamAcceptor::default(),
+      Arc::new(RwLock::new(RelayAccessPolicy::new())),
+      commands_tx,
+      events_rx)?;
+    let port = handler.port;
+    thread::spawn(move || handler.run_loop());
+
+    let first = TcpStream::connect((Ipv4Addr::LOCALHOST, port))?;
+    let second = TcpStream::connect((Ipv4Addr::LOCALHOST, port))?;
+    for stream in [&first, &second] {
+      stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    }
+    let first_id = identify(&first, &message(0x01, b"first"), &commands_rx)?;
+    let second_id = identify(&second, &message(0x01, b"second"), &commands_rx)?;
+    assert_ne!(first_id, second_id);
+
+    let first_reply = message(0x02, b"for first");
+    let second_reply = message(0x02, b"for second");
+    let broadcast = message(0x03, b"for everyone");
+    events_tx.send_to_all(&RelayEvent::MessageForClient(second_id, second_reply.clone()));
+    events_tx.send_to_all(&RelayEvent::MessageForClient(first_id, first_reply.clone()));
+    events_tx.send_to_all(&RelayEvent::MessageForIpClient(broadcast.clone()));
+
+    let mut first_reader = FramedReader::new(&first);
+    assert_eq!(first_reader.next_message()?, first_reply);
+    assert_eq!(first_reader.next_message()?, broadcast);
+
+    let mut second_reader = FramedReader::new(&second);
+    assert_eq!(second_reader.next_message()?, second_reply);
+    assert_eq!(second_reader.next_message()?, broadcast);
+    Ok(())
+  }
+
+  #[test]
+  fn test_recovers_from_failed_accept() -> anyhow::Result<()> {
+    let (commands_tx, commands_rx) = sync_channel(8);
+    let (_events_tx, events_rx) = broadcast_channel(8);
+    let handler = TcpListenerHandler::setup(
+      MessageLogger::new("TcpTest"),
+      RelayProtocol::Raw,
+      0,
+      StreamAcceptor::default(),
+      Arc::new(RwLock::new(RelayAccessPolicy::new())),
+      commands_tx,
+      events_rx)?;
+    let port = handler.port;
+
+    // Shares the socket's flags, so the handler's first accept fails with WouldBlock.
+    let saboteur = handler.listener.as_ref().unwrap().try_clone()?;
+    saboteur.set_nonblocking(true)?;
+    drop(saboteur);
+    let restarts = Metrics::global().counter("wifi_module.relay_restarts");
+    let restarts_before = restarts.get();
+    thread::spawn(move || handler.run_loop());
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while restarts.get() == restarts_before {
+      anyhow::ensure!(std::time::Instant::now() < deadline, "Listener never failed");
+      thread::sleep(Duration::from_millis(10));
+    }
+
+    let request = message(0x01, b"after restart");
+    loop {
+      // Either refused outright or accepted by the dying listener's backlog and then closed.
+      let attempt = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+          .map_err(anyhow::Error::from)
+          .and_then(|stream| identify(&stream, &request, &commands_rx));
+      match attempt {
+        Ok(_) => return Ok(()),
+        Err(_) if std::time::Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+        Err(e) => return Err(e),
       }
     }
   }
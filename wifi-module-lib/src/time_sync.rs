@@ -0,0 +1,201 @@
+//! Keeps the spa's clock right.  Main boards keep time poorly and forget it entirely after a
+//! power cut, so once we know the real time we periodically push it with a `SetTimeRequest`.
+//!
+//! Where the time comes from is up to the [LocalClock]: on the ESP32 that's SNTP, which starts
+//! answering once Wi-Fi is connected, and on the desktop the host's own (already synced) clock.
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use balboa_spa_messages::message_types::{ClockMode, MessageType};
+use balboa_spa_messages::time::ProtocolTime;
+use log::info;
+
+/// How often to correct the spa's clock once we know the time.
+pub const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How soon to check again when the local time isn't known yet.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub trait LocalClock: Debug + Send {
+  /// Current wall clock time in the spa's timezone, or None until it's known (e.g. SNTP hasn't
+  /// synced yet).
+  fn local_time(&self) -> Option<ProtocolTime>;
+
+  /// Current day of the week in the same timezone, for [crate::schedule].  Entries that only
+  /// apply on some days never fire without it.
+  fn local_weekday(&self) -> Option<Weekday> {
+    None
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Weekday {
+  Monday = 0,
+  Tuesday,
+  Wednesday,
+  Thursday,
+  Friday,
+  Saturday,
+  Sunday,
+}
+
+impl Weekday {
+  pub const ALL: [Weekday; 7] = [
+    Weekday::Monday,
+    Weekday::Tuesday,
+    Weekday::Wednesday,
+    Weekday::Thursday,
+    Weekday::Friday,
+    Weekday::Saturday,
+    Weekday::Sunday,
+  ];
+
+  /// 0 for Sunday through 6 for Saturday, as in C's `tm_wday`.
+  pub fn from_sunday_index(index: u8) -> Option<Self> {
+    (index < 7).then(|| Self::ALL[(usize::from(index) + 6) % 7])
+  }
+}
+
+/// The host's clock, shifted by a fixed offset from UTC since there's no timezone database to
+/// work out daylight saving with.
+#[derive(Debug, Default, Clone)]
+pub struct SystemLocalClock {
+  utc_offset_minutes: i32,
+}
+
+impl SystemLocalClock {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// E.g. -480 for PST.
+  pub fn set_utc_offset_minutes(mut self, minutes: i32) -> Self {
+    self.utc_offset_minutes = minutes;
+    self
+  }
+}
+
+impl LocalClock for SystemLocalClock {
+  fn local_time(&self) -> Option<ProtocolTime> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(time_of_day(since_epoch.as_secs(), self.utc_offset_minutes))
+  }
+
+  fn local_weekday(&self) -> Option<Weekday> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(weekday(since_epoch.as_secs(), self.utc_offset_minutes))
+  }
+}
+
+fn time_of_day(utc_secs: u64, utc_offset_minutes: i32) -> ProtocolTime {
+  let offset_secs = i64::from(utc_offset_minutes) * 60;
+  let secs = (utc_secs as i64 + offset_secs).rem_euclid(SECS_PER_DAY as i64) as u64;
+  ProtocolTime::from_duration(Duration::from_secs(secs - secs % 60)).unwrap()
+}
+
+fn weekday(utc_secs: u64, utc_offset_minutes: i32) -> Weekday {
+  let offset_secs = i64::from(utc_offset_minutes) * 60;
+  let days = (utc_secs as i64 + offset_secs).div_euclid(SECS_PER_DAY as i64);
+  // The epoch was a Thursday.
+  Weekday::ALL[(days + 3).rem_euclid(7) as usize]
+}
+
+/// Decides when the spa needs its clock set, driven by the main board traffic we see.
+#[derive(Debug)]
+pub(crate) struct TimeSync {
+  clock: Box<dyn LocalClock>,
+  interval: Duration,
+  next_sync: Option<Instant>,
+  spa_clock: Option<(ProtocolTime, ClockMode)>,
+}
+
+impl TimeSync {
+  pub fn new(clock: Box<dyn LocalClock>, interval: Duration) -> Self {
+    Self { clock, interval, next_sync: None, spa_clock: None }
+  }
+
+  /// Tracks the spa's current time and clock mode from its status updates.
+  pub fn on_message(&mut self, mt: &MessageType) {
+    if let MessageType::StatusUpdate(status) = mt {
+      if let Some(clock_mode) = status.v1.clock_mode.as_ref() {
+        self.spa_clock = Some((status.v1.time, *clock_mode));
+      }
+    }
+  }
+
+  /// The request to send now, if the spa's clock is due a correction.  Nothing is sent until
+  /// we've seen a status update, otherwise we'd clobber the user's 12/24 hour preference.
+  pub fn poll(&mut self, now: Instant) -> Option<MessageType> {
+    if self.next_sync.is_some_and(|next| now < next) {
+      return None;
+    }
+    let (spa_time, clock_mode) = self.spa_clock?;
+    let Some(time) = self.clock.local_time() else {
+      self.next_sync = Some(now + RETRY_INTERVAL);
+      return None;
+    };
+    self.next_sync = Some(now + self.interval);
+    if time.as_duration() == spa_time.as_duration() {
+      return None;
+    }
+    info!("Correcting spa clock from {spa_time} to {time}");
+    Some(MessageType::SetTimeRequest { time, clock_mode })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::message_types::StatusUpdateMessage;
+  use super::*;
+
+  #[derive(Debug)]
+  struct FixedClock(Option<ProtocolTime>);
+
+  impl LocalClock for FixedClock {
+    fn local_time(&self) -> Option<ProtocolTime> {
+      self.0
+    }
+  }
+
+  #[test]
+  fn test_time_of_day() {
+    // 2023-03-14T01:30:59Z
+    let utc_secs = 1678757459;
+    assert_eq!(time_of_day(utc_secs, 0), ProtocolTime::from_hm(1, 30));
+    assert_eq!(time_of_day(utc_secs, -480), ProtocolTime::from_hm(17, 30));
+    assert_eq!(time_of_day(utc_secs, 330), ProtocolTime::from_hm(7, 0));
+    assert_eq!(weekday(utc_secs, 0), Weekday::Tuesday);
+    assert_eq!(weekday(utc_secs, -480), Weekday::Monday);
+    assert_eq!(Weekday::from_sunday_index(0), Some(Weekday::Sunday));
+    assert_eq!(Weekday::from_sunday_index(2), Some(Weekday::Tuesday));
+  }
+
+  #[test]
+  fn test_sync() {
+    let status_bytes = vec![0u8; 24];
+    let mut status = StatusUpdateMessage::try_from(status_bytes.as_slice()).unwrap();
+    status.v1.time = ProtocolTime::from_hm(9, 0);
+    let status = MessageType::StatusUpdate(status);
+
+    let now = Instant::now();
+    let mut sync = TimeSync::new(Box::new(FixedClock(Some(ProtocolTime::from_hm(10, 15)))), DEFAULT_SYNC_INTERVAL);
+    assert!(sync.poll(now).is_none(), "Must wait for the spa's clock mode");
+
+    sync.on_message(&status);
+    match sync.poll(now) {
+      Some(MessageType::SetTimeRequest { time, clock_mode }) => {
+        assert_eq!(time, ProtocolTime::from_hm(10, 15));
+        assert_eq!(clock_mode, ClockMode::Hour12);
+      }
+      other => panic!("Unexpected {other:?}"),
+    }
+    assert!(sync.poll(now + Duration::from_secs(60)).is_none());
+    assert!(sync.poll(now + DEFAULT_SYNC_INTERVAL).is_some());
+
+    let mut unsynced = TimeSync::new(Box::new(FixedClock(None)), DEFAULT_SYNC_INTERVAL);
+    unsynced.on_message(&status);
+    assert!(unsynced.poll(now).is_none());
+  }
+}
@@ -1,9 +1,12 @@
 use std::fmt::Debug;
-use crate::wifi_manager::StaAssociationError;
+use crate::wifi_manager::{BleProvisioningParams, ConnectionInfo, StaAssociationError};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ViewModel {
   pub mode: Mode,
+
+  /// Firmware update in progress (or that just failed), see [crate::ota].
+  pub ota: Option<OtaProgress>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -40,9 +43,19 @@ pub struct TroubleAssociatingModel {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProvisioningParams {
+  pub method: ProvisioningMethod,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProvisioningMethod {
   /// Convert to an image and have a compatible phone use the Wi-Fi Easy Connect (DPP) feature
-  /// to scan the barcode which delivers network credentials to us.
-  pub dpp_qr_code: String,
+  /// to scan the barcode which delivers network credentials to us.  If `ble` is set, the user
+  /// can alternatively provision via Bluetooth at the same time.
+  Dpp { qr_code: String, ble: Option<BleProvisioningParams> },
+
+  /// Fallback for phones without Easy Connect: the user joins our open network `ssid` and
+  /// types the credentials into the page at `portal_url`, which most phones open by themselves.
+  SoftAp { ssid: String, portal_url: String },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -52,6 +65,22 @@ pub struct NominalModel {
 
   /// Actual state of the Wi-Fi connection.
   pub connection_state: ConnectionState,
+
+  /// Signal strength, address and so on, refreshed periodically once
+  /// [ConnectionState::Connected].
+  pub connection_info: Option<ConnectionInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OtaProgress {
+  /// Writing the new image as it arrives.  `total` is only known if the sender said.
+  Receiving { received: usize, total: Option<usize> },
+
+  /// New image installed, the device is about to restart into it.
+  Restarting,
+
+  /// The last update didn't work out, the running firmware is unaffected.
+  Failed(String),
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -0,0 +1,269 @@
+//! Pushes spa state to browsers and automation clients as it changes, served from
+//! `GET /events` on the [crate::http_handler] port.
+//!
+//! Each connection subscribes to the same relay broadcast the TCP clients use, so there's no
+//! additional load on the bus.  Every text frame is JSON of the form
+//! `{"type": "status" | "configuration", "data": {...}}`, starting with whatever is cached at
+//! connect time and afterwards only when the content changes.  Commands the spa never acted on
+//! (see [crate::confirmations]) are reported as `{"type": "command_failed", "data":
+//! {"command": "toggle Pump1"}}`.
+//!
+//! Clients may send `{"type": "history", "hours": 24}` to be sent a `history` event with the
+//! same data as `GET /history` (see [crate::history]).  Anything else the client sends other
+//! than ping and close is ignored.
+
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use std::time::Instant;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use balboa_spa_messages::message_types::MessageType;
+use crate::broadcaster::BroadcastReceiver;
+use crate::history::{DEFAULT_HISTORY_HOURS, History};
+use crate::relay_event::RelayEvent;
+use crate::server_stream::ServerStream;
+use crate::spa_json::{configuration_json, status_json};
+use crate::spa_state_cache::SpaStateCache;
+
+/// From RFC 6455, appended to the client's key to prove we understood the upgrade.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Clients have no reason to send more than control frames (at most 125 bytes), so be stingy.
+const MAX_CLIENT_PAYLOAD_LEN: usize = 1024;
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+pub(crate) fn accept_key(client_key: &str) -> String {
+  let mut sha1 = sha1_smol::Sha1::new();
+  sha1.update(client_key.as_bytes());
+  sha1.update(ACCEPT_GUID.as_bytes());
+  BASE64.encode(sha1.digest().bytes())
+}
+
+pub(crate) fn write_handshake(writer: &mut impl Write, client_key: &str) -> io::Result<()> {
+  write!(
+    writer,
+    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+    accept_key(client_key))?;
+  writer.flush()
+}
+
+/// Writes an unmasked frame, as servers must.
+pub(crate) fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+  let mut frame = Vec::with_capacity(payload.len() + 10);
+  frame.push(0x80 | opcode);
+  match payload.len() {
+    len @ 0..=125 => frame.push(len as u8),
+    len @ 126..=0xffff => {
+      frame.push(126);
+      frame.extend((len as u16).to_be_bytes());
+    }
+    len => {
+      frame.push(127);
+      frame.extend((len as u64).to_be_bytes());
+    }
+  }
+  frame.extend(payload);
+  writer.write_all(&frame)?;
+  writer.flush()
+}
+
+/// Reads one frame from the client, returning the opcode and unmasked payload.
+pub(crate) fn read_frame(reader: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+  let mut header = [0u8; 2];
+  reader.read_exact(&mut header)?;
+  let opcode = header[0] & 0x0f;
+  let masked = header[1] & 0x80 != 0;
+  let len = match header[1] & 0x7f {
+    126 => {
+      let mut buf = [0u8; 2];
+      reader.read_exact(&mut buf)?;
+      usize::from(u16::from_be_bytes(buf))
+    }
+    127 => {
+      let mut buf = [0u8; 8];
+      reader.read_exact(&mut buf)?;
+      usize::try_from(u64::from_be_bytes(buf)).unwrap_or(usize::MAX)
+    }
+    len => usize::from(len),
+  };
+  if len > MAX_CLIENT_PAYLOAD_LEN {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Frame too long: {len}")));
+  }
+  let mut mask = [0u8; 4];
+  if masked {
+    reader.read_exact(&mut mask)?;
+  }
+  let mut payload = vec![0u8; len];
+  reader.read_exact(&mut payload)?;
+  if masked {
+    payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= mask[i % 4]);
+  }
+  Ok((opcode, payload))
+}
+
+/// Tracks what each client has already been sent so only changes go out.
+#[derive(Debug, Default)]
+pub(crate) struct ChangeFilter {
+  last_status: Option<Value>,
+  last_configuration: Option<Value>,
+}
+
+impl ChangeFilter {
+  /// The event to push for `mt`, if it's something we stream and differs from last time.
+  pub fn event_for(&mut self, mt: &MessageType) -> Option<Value> {
+    let (kind, data, last) = match mt {
+      MessageType::StatusUpdate(status) => ("status", status_json(&status.v1), &mut self.last_status),
+      MessageType::ConfigurationResponse(configuration) => {
+        ("configuration", configuration_json(configuration), &mut self.last_configuration)
+      }
+      _ => return None,
+    };
+    if last.as_ref() == Some(&data) {
+      return None;
+    }
+    *last = Some(data.clone());
+    Some(json!({ "type": kind, "data": data }))
+  }
+}
+
+pub(crate) struct WebSocketSession {
+  pub stream: ServerStream,
+  pub cache: SpaStateCache,
+  pub history: History,
+  pub events_rx: BroadcastReceiver<RelayEvent>,
+}
+
+impl WebSocketSession {
+  /// Runs until either side goes away.  The handshake must already have been written.
+  pub fn run_loop(self) {
+    let stream = &self.stream;
+    let writer = Mutex::new(stream);
+    crossbeam::thread::scope(|s| {
+      let reader_thread = s.builder()
+          .name("WebSocketReader".into())
+          .spawn(|_| {
+            if let Err(e) = read_loop(stream, &writer, &self.history) {
+              debug!("WebSocket reader: {e}");
+            }
+            // Unblock the writer should it be idle.
+            let _ = stream.shutdown();
+          })
+          .unwrap();
+
+      if let Err(e) = write_loop(&self.cache, &self.events_rx, &writer) {
+        debug!("WebSocket writer: {e}");
+      }
+      let _ = stream.shutdown();
+      reader_thread.join().unwrap();
+    }).unwrap();
+  }
+}
+
+fn read_loop(
+    mut stream: &ServerStream,
+    writer: &Mutex<&ServerStream>,
+    history: &History,
+) -> io::Result<()> {
+  loop {
+    let (opcode, payload) = read_frame(&mut stream)?;
+    match opcode {
+      OPCODE_TEXT => {
+        if let Some(event) = answer_request(&payload, history) {
+          write_frame(&mut *writer.lock().unwrap(), OPCODE_TEXT, event.to_string().as_bytes())?;
+        }
+      }
+      OPCODE_PING => write_frame(&mut *writer.lock().unwrap(), OPCODE_PONG, &payload)?,
+      OPCODE_CLOSE => {
+        write_frame(&mut *writer.lock().unwrap(), OPCODE_CLOSE, &payload)?;
+        return Ok(());
+      }
+      _ => {}
+    }
+  }
+}
+
+/// The reply to something the client sent, if it was a request we know.
+fn answer_request(payload: &[u8], history: &History) -> Option<Value> {
+  let request = serde_json::from_slice::<Value>(payload).ok()?;
+  if request["type"] != "history" {
+    return None;
+  }
+  let hours = request["hours"].as_u64().unwrap_or(DEFAULT_HISTORY_HOURS);
+  Some(json!({ "type": "history", "data": history.to_json(hours, Instant::now()) }))
+}
+
+fn write_loop(
+    cache: &SpaStateCache,
+    events_rx: &BroadcastReceiver<RelayEvent>,
+    writer: &Mutex<&ServerStream>,
+) -> anyhow::Result<()> {
+  let mut changes = ChangeFilter::default();
+  let cached = cache.snapshot();
+  let initial = cached.status.map(MessageType::StatusUpdate).into_iter()
+      .chain(cached.configuration.map(MessageType::ConfigurationResponse));
+  for mt in initial {
+    if let Some(event) = changes.event_for(&mt) {
+      write_frame(&mut *writer.lock().unwrap(), OPCODE_TEXT, event.to_string().as_bytes())?;
+    }
+  }
+
+  loop {
+    let message = match events_rx.rx().recv()? {
+      RelayEvent::MessageForIpClient(message) => message,
+      RelayEvent::CommandFailed(command) => {
+        let event = json!({ "type": "command_failed", "data": { "command": command } });
+        write_frame(&mut *writer.lock().unwrap(), OPCODE_TEXT, event.to_string().as_bytes())?;
+        continue;
+      }
+      // Meant for TCP clients.
+      RelayEvent::MessageForClient(..) | RelayEvent::BusFrame(_) => continue,
+    };
+    let mt = match MessageType::try_from(&message) {
+      Ok(mt) => mt,
+      Err(e) => {
+        warn!("Not streaming unparseable {message:?}: {e}");
+        continue;
+      }
+    };
+    if let Some(event) = changes.event_for(&mt) {
+      write_frame(&mut *writer.lock().unwrap(), OPCODE_TEXT, event.to_string().as_bytes())?;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::message_types::StatusUpdateMessage;
+  use super::*;
+
+  #[test]
+  fn test_accept_key() {
+    // Example from RFC 6455 section 1.3.
+    assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+  }
+
+  #[test]
+  fn test_masked_frame_and_changes() -> anyhow::Result<()> {
+    let mask = [1, 2, 3, 4];
+    let mut frame = vec![0x80 | OPCODE_PING, 0x80 | 2];
+    frame.extend(mask);
+    frame.extend([b'h' ^ 1, b'i' ^ 2]);
+    assert_eq!(read_frame(&mut frame.as_slice())?, (OPCODE_PING, b"hi".to_vec()));
+
+    let mut changes = ChangeFilter::default();
+    let mut status = StatusUpdateMessage::try_from([0u8; 24].as_slice())?;
+    assert!(changes.event_for(&MessageType::StatusUpdate(status.clone())).is_some());
+    assert!(changes.event_for(&MessageType::StatusUpdate(status.clone())).is_none());
+    status.v1.panel_locked = true;
+    let event = changes.event_for(&MessageType::StatusUpdate(status)).unwrap();
+    assert_eq!(event["data"]["panel_locked"], true);
+    Ok(())
+  }
+}
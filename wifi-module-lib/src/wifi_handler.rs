@@ -1,25 +1,36 @@
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::sync::mpsc::{Sender, SyncSender};
+#[cfg(feature = "http")]
+use std::sync::mpsc::{channel, Receiver};
 use std::thread;
 use std::time::{Duration, Instant};
 use anyhow::anyhow;
 use log::{error, info, warn};
+use common_lib::metrics::{Counter, Metrics};
 use common_lib::view_model_event_handle::ViewEvent;
 use crate::command::Command;
-use crate::view_model::{ConnectionState, Mode, NominalModel, ProvisioningParams, TroubleAssociatingModel, UnprovisionedModel, ViewModel};
-use crate::wifi_manager::{StaAssociationError, WifiDppBootstrapped, WifiManager};
+use crate::ip_config::StaticIpConfig;
+use crate::view_model::{ConnectionState, Mode, NominalModel, OtaProgress, ProvisioningMethod, ProvisioningParams, TroubleAssociatingModel, UnprovisionedModel, ViewModel};
+use crate::wifi_manager::{ConnectionInfo, StaAssociationError, WifiDppBootstrapped, WifiManager, WifiSoftApBootstrapped};
 
 /// Amount of time to allow for a successful connection before signaling to the UI that
 /// something might be wrong.
 const CONNECTING_GRACE_PERIOD: Duration = Duration::from_secs(60);
 
+/// How often to refresh [ConnectionInfo] while connected.
+const CONNECTION_INFO_INTERVAL: Duration = Duration::from_secs(10);
+
 /// Time to wait between disconnect before attempting connect again.
 const RECONNECT_DELAY: Duration = Duration::from_secs(1);
 
 pub struct WifiHandler<W> {
   wifi_manager: W,
-  model_manager: ModelManager,
+  static_ip: Option<StaticIpConfig>,
+  model_manager: Arc<Mutex<ModelManager>>,
+  reconnects: Counter,
+  provisioned_txs: Vec<Sender<()>>,
 }
 
 struct ModelManager {
@@ -33,18 +44,20 @@ struct AppState {
   target_ssid: Option<String>,
   unrecoverable_error: Option<UnrecoverableError>,
   connection_state: ConnectionState,
+  connection_info: Option<ConnectionInfo>,
   connection_stalled: Option<StaAssociationError>,
-  waiting_for_dpp: Option<QrCode>,
+  waiting_for_provisioning: Option<ProvisioningMethod>,
+  ota: Option<OtaProgress>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(thiserror::Error, Debug, Clone)]
 enum UnrecoverableError {
+  #[error("Wi-Fi driver failed")]
   WifiDriverFailed,
-  DppBootstrap(String),
-}
 
-#[derive(Debug, Default)]
-struct QrCode(String);
+  #[error("SoftAP setup failed: {0}")]
+  SoftApBootstrap(String),
+}
 
 impl<'a, W: WifiManager<'a>> WifiHandler<W> {
   pub fn new(
@@ -53,19 +66,55 @@ impl<'a, W: WifiManager<'a>> WifiHandler<W> {
   ) -> Self {
     Self {
       wifi_manager,
-      model_manager: ModelManager {
+      static_ip: None,
+      model_manager: Arc::new(Mutex::new(ModelManager {
         view_events_tx,
         state: Default::default(),
         last_model: None,
-      }
+      })),
+      reconnects: Metrics::global().counter("wifi_module.wifi_reconnects"),
+      provisioned_txs: Vec::new(),
     }
   }
 
+  /// Lets the [crate::ota] service report progress from its own threads.
+  #[cfg(feature = "http")]
+  pub(crate) fn ota_progress_reporter(&self) -> impl Fn(Option<OtaProgress>) + Send + Sync + 'static {
+    let model_manager = self.model_manager.clone();
+    move |progress| {
+      let mut model_manager = model_manager.lock().unwrap();
+      model_manager.state.ota = progress;
+      model_manager.maybe_emit_view_model();
+    }
+  }
+
+  /// The latest [ViewModel], for the HTTP API's Wi-Fi status.
+  #[cfg(feature = "http")]
+  pub(crate) fn model_source(&self) -> impl Fn() -> Option<ViewModel> + Send + Sync + 'static {
+    let model_manager = self.model_manager.clone();
+    move || model_manager.lock().unwrap().last_model.clone()
+  }
+
+  /// Signalled once we have credentials, i.e. when any provisioning access point and its
+  /// captive portal are gone.  Disconnected instead if we never get that far.
+  #[cfg(feature = "http")]
+  pub(crate) fn provisioned_signal(&mut self) -> Receiver<()> {
+    let (tx, rx) = channel();
+    self.provisioned_txs.push(tx);
+    rx
+  }
+
+  /// Connect with a fixed address rather than asking DHCP for one.
+  pub fn set_static_ip(mut self, config: StaticIpConfig) -> Self {
+    self.static_ip = Some(config);
+    self
+  }
+
   pub fn run_loop(mut self) -> anyhow::Result<()> {
     self.maybe_emit_view_model();
     if let Err((reported_e, actual_e)) = self.do_run_loop() {
       error!("Critical error {reported_e:?}: {actual_e}");
-      self.state_mut().unrecoverable_error = Some(reported_e);
+      self.model_manager().state.unrecoverable_error = Some(reported_e);
       self.maybe_emit_view_model();
       Err(anyhow!("{actual_e:?}"))
     } else {
@@ -78,9 +127,14 @@ impl<'a, W: WifiManager<'a>> WifiHandler<W> {
     let target = self.maybe_wait_for_config()?;
     self.maybe_emit_view_model();
 
+    if let Some(config) = &self.static_ip {
+      info!("Using static IP {}/{} via {}", config.ip, config.prefix_len, config.gateway);
+    }
+    self.wifi_manager.set_static_ip(self.static_ip.as_ref()).map_err(map_wifi_err::<W>)?;
+
     loop {
       info!("Connecting to {target}...");
-      self.state_mut().connection_state = ConnectionState::Associating;
+      self.model_manager().state.connection_state = ConnectionState::Associating;
       self.maybe_emit_view_model();
       let initial_connection_time = Instant::now();
       while let Err(e) = self.wifi_manager.sta_connect() {
@@ -91,26 +145,46 @@ impl<'a, W: WifiManager<'a>> WifiHandler<W> {
           warn!(
               "Time since last connection exceeded grace period: {}s!",
               time_since_first_try.as_secs());
-          self.state_mut().connection_stalled = Some(e);
+          self.model_manager().state.connection_stalled = Some(e);
           self.maybe_emit_view_model();
         }
       }
 
       info!("Connected to {target}");
-      self.state_mut().connection_stalled = None;
-      self.state_mut().connection_state = ConnectionState::Connected;
-      self.maybe_emit_view_model();
-      self.wifi_manager.wait_while_connected().map_err(map_wifi_err::<W>)?;
+      self.model_manager().state.connection_stalled = None;
+      self.model_manager().state.connection_state = ConnectionState::Connected;
+      loop {
+        self.refresh_connection_info();
+        self.maybe_emit_view_model();
+        let still_connected = self.wifi_manager.wait_while_connected(CONNECTION_INFO_INTERVAL)
+            .map_err(map_wifi_err::<W>)?;
+        if !still_connected {
+          break;
+        }
+      }
       info!("Lost connection to {target}!");
+      self.model_manager().state.connection_info = None;
+      self.reconnects.inc();
 
       self.wait_for_reconnect();
     }
   }
 
+  fn refresh_connection_info(&mut self) {
+    let info = match self.wifi_manager.connection_info() {
+      Ok(info) => Some(info),
+      Err(e) => {
+        warn!("Unable to get connection info: {e}");
+        None
+      }
+    };
+    self.model_manager().state.connection_info = info;
+  }
+
   fn wait_for_reconnect(&mut self) {
     if !RECONNECT_DELAY.is_zero() {
       info!("Waiting for {}s to reconnect...", RECONNECT_DELAY.as_secs());
-      self.state_mut().connection_state = ConnectionState::NotAssociated;
+      self.model_manager().state.connection_state = ConnectionState::NotAssociated;
       self.maybe_emit_view_model();
       thread::sleep(RECONNECT_DELAY);
     }
@@ -122,19 +196,12 @@ impl<'a, W: WifiManager<'a>> WifiHandler<W> {
     let network_name = match self.wifi_manager.get_sta_network_name().map_err(map_wifi_err::<W>)? {
       None => {
         info!("No credentials stored, preparing to use Wi-Fi Easy Connect...");
-        let creds = {
-          let dpp_bootstrapped =
-              self.wifi_manager.dpp_bootstrap().map_err(map_dpp_err::<W>)?;
-
-          info!("Generating QR code...");
-          let qr_code = dpp_bootstrapped.get_qr_code().to_owned();
-
-          let model_manager = &mut self.model_manager;
-          model_manager.state.waiting_for_dpp = Some(QrCode(qr_code));
-          model_manager.maybe_emit_view_model();
-
-          info!("Got QR code, waiting for user to provision...");
-          dpp_bootstrapped.listen_then_wait().map_err(map_dpp_err::<W>)?
+        let creds = match self.wait_for_dpp() {
+          Ok(creds) => creds,
+          Err(e) => {
+            warn!("Wi-Fi Easy Connect failed, falling back to SoftAP: {e}");
+            self.wait_for_soft_ap().map_err(map_soft_ap_err::<W>)?
+          }
         };
 
         self.wifi_manager.store_credentials(creds).map_err(map_wifi_err::<W>)?
@@ -142,17 +209,55 @@ impl<'a, W: WifiManager<'a>> WifiHandler<W> {
       Some(name) => name,
     };
 
-    self.model_manager.state.waiting_for_dpp = None;
-    self.state_mut().target_ssid = Some(network_name.clone());
+    self.model_manager().state.waiting_for_provisioning = None;
+    self.model_manager().state.target_ssid = Some(network_name.clone());
+    for tx in self.provisioned_txs.drain(..) {
+      let _ = tx.send(());
+    }
     Ok(network_name)
   }
 
-  fn state_mut(&mut self) -> &mut AppState {
-    &mut self.model_manager.state
+  fn wait_for_dpp(&mut self) -> Result<W::Credentials, W::Error> {
+    let dpp_bootstrapped = self.wifi_manager.dpp_bootstrap()?;
+
+    info!("Generating QR code...");
+    let qr_code = dpp_bootstrapped.get_qr_code().to_owned();
+    let ble = dpp_bootstrapped.get_ble_provisioning().cloned();
+    if let Some(ble) = &ble {
+      info!("Also accepting BLE provisioning as {}", ble.device_name);
+    }
+
+    let mut model_manager = self.model_manager.lock().unwrap();
+    model_manager.state.waiting_for_provisioning = Some(ProvisioningMethod::Dpp { qr_code, ble });
+    model_manager.maybe_emit_view_model();
+    drop(model_manager);
+
+    info!("Got QR code, waiting for user to provision...");
+    dpp_bootstrapped.listen_then_wait()
+  }
+
+  fn wait_for_soft_ap(&mut self) -> Result<W::Credentials, W::Error> {
+    let soft_ap_bootstrapped = self.wifi_manager.soft_ap_bootstrap()?;
+
+    let ssid = soft_ap_bootstrapped.get_ap_ssid().to_owned();
+    let portal_url = soft_ap_bootstrapped.get_portal_url().to_owned();
+
+    let mut model_manager = self.model_manager.lock().unwrap();
+    model_manager.state.waiting_for_provisioning =
+        Some(ProvisioningMethod::SoftAp { ssid, portal_url });
+    model_manager.maybe_emit_view_model();
+    drop(model_manager);
+
+    info!("Access point up, waiting for user to provision...");
+    soft_ap_bootstrapped.wait_for_credentials()
+  }
+
+  fn model_manager(&self) -> MutexGuard<'_, ModelManager> {
+    self.model_manager.lock().unwrap()
   }
 
   fn maybe_emit_view_model(&mut self) {
-    self.model_manager.maybe_emit_view_model();
+    self.model_manager().maybe_emit_view_model();
   }
 }
 
@@ -171,7 +276,7 @@ impl AppState {
   fn generate_model(&self) -> ViewModel {
     // Order matters a lot here.  Must be informed by the logic in run_loop.
     let mode = if let Some(e) = &self.unrecoverable_error {
-      Mode::UnrecoverableError(format!("{e:?}"))
+      Mode::UnrecoverableError(e.to_string())
     } else if let Some(target) = &self.target_ssid {
       if let Some(stalled_e) = &self.connection_stalled {
         Mode::TroubleAssociating(TroubleAssociatingModel {
@@ -181,18 +286,19 @@ impl AppState {
         Mode::Nominal(NominalModel {
           network_name: target.clone(),
           connection_state: self.connection_state,
+          connection_info: self.connection_info.clone(),
         })
       }
-    } else if let Some(qr_code) = &self.waiting_for_dpp {
+    } else if let Some(method) = &self.waiting_for_provisioning {
       Mode::NeedsProvisioning(UnprovisionedModel {
         params: ProvisioningParams {
-          dpp_qr_code: qr_code.0.clone(),
+          method: method.clone(),
         }
       })
     } else {
       Mode::Initializing
     };
-    ViewModel { mode }
+    ViewModel { mode, ota: self.ota.clone() }
   }
 }
 
@@ -200,6 +306,6 @@ fn map_wifi_err<'a, W: WifiManager<'a>>(e: W::Error) -> (UnrecoverableError, W::
   (UnrecoverableError::WifiDriverFailed, e)
 }
 
-fn map_dpp_err<'a, W: WifiManager<'a>>(e: W::Error) -> (UnrecoverableError, W::Error) {
-  (UnrecoverableError::DppBootstrap(e.to_string()), e)
+fn map_soft_ap_err<'a, W: WifiManager<'a>>(e: W::Error) -> (UnrecoverableError, W::Error) {
+  (UnrecoverableError::SoftApBootstrap(e.to_string()), e)
 }
\ No newline at end of file
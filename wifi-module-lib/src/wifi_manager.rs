@@ -1,5 +1,8 @@
 use std::fmt::{Debug, Display};
+use std::net::Ipv4Addr;
+use std::time::Duration;
 use crate::advertisement::Advertisement;
+use crate::ip_config::StaticIpConfig;
 
 pub trait WifiManager<'w> {
   /// Unrecoverable error type that indicates a failure out of band of Wi-Fi (e.g. the driver
@@ -15,6 +18,11 @@ pub trait WifiManager<'w> {
   type DppBootstrapped<'d>: WifiDppBootstrapped<'d, 'w, Credentials = Self::Credentials, Error = Self::Error>
   where 'w: 'd, Self: 'd;
 
+  /// Type of the associated SoftAP flow manager, used when the user's phone can't do Wi-Fi
+  /// Easy Connect.  Separate for the same reason as [Self::DppBootstrapped].
+  type SoftApBootstrapped<'d>: WifiSoftApBootstrapped<'d, 'w, Credentials = Self::Credentials, Error = Self::Error>
+  where 'w: 'd, Self: 'd;
+
   /// Provides the advertisement we'll use when peers are trying to discover us.
   fn advertisement(&self) -> &Advertisement;
 
@@ -29,19 +37,32 @@ pub trait WifiManager<'w> {
   /// method assumes [Self::get_sta_network_name] was None.
   fn dpp_bootstrap(&mut self) -> Result<Self::DppBootstrapped<'_>, Self::Error>;
 
-  /// Store credentials provided by [Self::dpp_bootstrap].  Returns the network name
-  /// as a convenience to avoid calling [Self::get_sta_network_name] again.
+  /// Fallback for when [Self::dpp_bootstrap] isn't possible: bring up an open access point
+  /// serving a captive portal (see [crate::captive_portal::CaptivePortal]) where the user can
+  /// type in credentials.  Same assumptions as [Self::dpp_bootstrap].
+  fn soft_ap_bootstrap(&mut self) -> Result<Self::SoftApBootstrapped<'_>, Self::Error>;
+
+  /// Store credentials provided by [Self::dpp_bootstrap] or [Self::soft_ap_bootstrap].  Returns
+  /// the network name as a convenience to avoid calling [Self::get_sta_network_name] again.
   fn store_credentials(&mut self, credentials: Self::Credentials) -> Result<String, Self::Error>;
 
+  /// Use `config` for the station interface on subsequent connects, or DHCP if None (the
+  /// default).
+  fn set_static_ip(&mut self, config: Option<&StaticIpConfig>) -> Result<(), Self::Error>;
+
   /// Perform a blocking station-mode connect operation then block the calling thread while we
   /// remain connected.  This is designed to be run in a dedicated thread that just loops to
   /// reconnect.
   fn sta_connect(&mut self) -> Result<(), StaAssociationError>;
 
-  /// Perform a blocking wait until we are disconnected.  Expected that the caller will just
-  /// loop forever interleaving between [Self::sta_connect] and [Self::wait_while_connected]
-  /// and updating any internal state accordingly to show the user.
-  fn wait_while_connected(&mut self) -> Result<(), Self::Error>;
+  /// Perform a blocking wait until we are disconnected or `timeout` elapses, returning whether
+  /// we're still connected.  Expected that the caller will just loop forever interleaving
+  /// between [Self::sta_connect] and [Self::wait_while_connected] and updating any internal
+  /// state accordingly to show the user (e.g. from [Self::connection_info] on each timeout).
+  fn wait_while_connected(&mut self, timeout: Duration) -> Result<bool, Self::Error>;
+
+  /// Diagnostics about the current connection.  Only meaningful while connected.
+  fn connection_info(&mut self) -> Result<ConnectionInfo, Self::Error>;
 }
 
 pub trait WifiDppBootstrapped<'d, 'w> {
@@ -52,11 +73,57 @@ pub trait WifiDppBootstrapped<'d, 'w> {
   /// Access the QR code associated with this bootstrapped DPP session.
   fn get_qr_code(&self) -> &str;
 
+  /// Implementations that also accept credentials over Bluetooth LE while listening (e.g. ESP
+  /// BLE provisioning) return what the user needs to find us.  [Self::listen_then_wait] then
+  /// completes with whichever credentials arrive first.
+  fn get_ble_provisioning(&self) -> Option<&BleProvisioningParams> {
+    None
+  }
+
   /// Listen for Wi-Fi Easy Connect credentials.  This method blocks until the credentials are
-  /// available or a non-recoverable error occurs.
+  /// available or a non-recoverable error occurs.  Implementations should also give up with an
+  /// error once it's clear the user can't finish, as only then is
+  /// [WifiManager::soft_ap_bootstrap] tried.
   fn listen_then_wait(self) -> Result<Self::Credentials, Self::Error>;
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+  /// Signal strength of the access point in dBm.
+  pub rssi: i8,
+
+  /// None until an address has been assigned.
+  pub ip: Option<Ipv4Addr>,
+
+  pub channel: u8,
+}
+
+/// What the user needs to provision us from a BLE provisioning app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BleProvisioningParams {
+  /// Name we're advertising under.
+  pub device_name: String,
+
+  /// Proof of possession the app will ask for, proving the user can see our screen.
+  pub proof_of_possession: String,
+}
+
+pub trait WifiSoftApBootstrapped<'d, 'w> {
+  type Error: Debug + Display;
+
+  type Credentials;
+
+  /// Name of the open network the user should join.
+  fn get_ap_ssid(&self) -> &str;
+
+  /// Address of the portal page, for phones that don't open it by themselves.
+  fn get_portal_url(&self) -> &str;
+
+  /// Serve the captive portal until the user submits credentials.  This method blocks until the
+  /// credentials are available or a non-recoverable error occurs.
+  fn wait_for_credentials(self) -> Result<Self::Credentials, Self::Error>;
+}
+
 #[derive(thiserror::Error, Debug, PartialEq, Eq, Clone)]
 pub enum DppListenError {
   #[error("Unknown underlying system error: {0}")]
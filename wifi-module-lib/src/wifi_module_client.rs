@@ -1,27 +1,60 @@
 use std::{io, thread};
 use std::io::{Read, Write};
-use std::sync::mpsc::{channel, Receiver, SendError, sync_channel, SyncSender};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError, SendError, sync_channel, SyncSender};
+#[cfg(feature = "http")]
+use std::sync::mpsc::Sender;
 use anyhow::anyhow;
 use log::{debug, error, info, warn};
 use balboa_spa_messages::channel::Channel;
 use balboa_spa_messages::framed_reader::FramedReader;
 use balboa_spa_messages::framed_writer::FramedWriter;
 use balboa_spa_messages::message::Message;
-use balboa_spa_messages::message_types::{MessageType, WifiModuleIdentificationMessage};
+use balboa_spa_messages::message_types::{MessageType, SettingsRequestMessage, WifiModuleIdentificationMessage};
+use common_lib::assignment_store::AssignmentStore;
 use common_lib::channel_filter::ChannelFilter;
 use common_lib::message_logger::{MessageDirection, MessageLogger};
+use common_lib::metrics::{Gauge, Metrics};
 use common_lib::transport::Transport;
 use common_lib::view_model_event_handle::ViewModelEventHandle;
+use crate::alerts::{Alert, AlertConfig, AlertMonitor};
+#[cfg(feature = "http")]
+use crate::alerts::WebhookSender;
 use crate::app_state::AppState;
 use crate::broadcaster::{broadcast_channel, BroadcastSender};
+#[cfg(feature = "mqtt")]
+use crate::mqtt::{mqtt_publisher, MqttBridge, MqttEvent, MqttIncoming, MqttPublisher, MqttSender};
+#[cfg(feature = "http")]
+use crate::history::History;
+#[cfg(feature = "http")]
+use crate::http_handler::{HttpApiHandler, HttpState};
+#[cfg(feature = "http")]
+use crate::ota::{OtaService, OtaTarget};
+use crate::spa_client::{SpaClient, SpaRequest};
+use crate::spa_state_cache::SpaStateCache;
 use crate::command::Command;
 use crate::discovery_handler::DiscoveryHandler;
+use crate::config_poller::{ConfigPoller, DEFAULT_REFRESH_INTERVAL};
+use crate::confirmations::{Confirmations, DoneSender, Unconfirmed};
+use crate::frost::{FrostConfig, FrostGuard};
+use crate::fault_log::{DEFAULT_POLL_INTERVAL, FaultLogCache, FaultLogPoller};
 use crate::handling_error::HandlingError;
+use crate::mdns::MdnsService;
+use crate::outbound_queue::MessageSource;
 use crate::handling_error::HandlingError::{FatalError, ShutdownRequested};
-use crate::relay_event::RelayEvent;
+use crate::relay_auth::RelayAccessPolicy;
+use crate::relay_hello::{is_hello_request, RELAY_PROTOCOL_VERSION, RelayFeatures, RelayHello};
+use crate::schedule::Scheduler;
+use crate::relay_event::{RelayClientId, RelayEvent};
 use crate::relay_event::RelayEvent::MessageForIpClient;
-use crate::tcp_handler::TcpListenerHandler;
+use crate::server_stream::StreamAcceptor;
+use crate::settings::{DEVICE_NAME, RELAY_ACCESS, RELAY_PORT, SCHEDULE, Settings};
+use crate::tcp_handler::{RelayProtocol, TCP_PORT, TcpListenerHandler};
+#[cfg(feature = "tls")]
+use crate::tls::{TlsAcceptor, TlsIdentity};
 use crate::view_model::ViewModel;
+use crate::time_sync::{LocalClock, TimeSync};
 use crate::wifi_handler::WifiHandler;
 use crate::wifi_manager::WifiManager;
 
@@ -29,6 +62,26 @@ pub struct WifiModuleClient<R, W, WIFI> {
   framed_reader: FramedReader<R>,
   framed_writer: FramedWriter<W>,
   wifi_manager: WIFI,
+  assignment_store: Option<Box<dyn AssignmentStore>>,
+  settings: Option<Settings>,
+  time_sync: Option<TimeSync>,
+  schedule_clock: Option<Box<dyn LocalClock>>,
+  frost: Option<FrostConfig>,
+  fault_log_poll_interval: Option<Duration>,
+  config_refresh_interval: Option<Duration>,
+  passthrough_port: Option<u16>,
+  alerts: AlertConfig,
+  firmware_version: String,
+  relay_port: Option<u16>,
+  device_name: Option<String>,
+  #[cfg(feature = "mqtt")]
+  mqtt: Option<(MqttBridge, Receiver<MqttIncoming>)>,
+  #[cfg(feature = "http")]
+  http_port: Option<u16>,
+  #[cfg(feature = "http")]
+  ota: Option<(Box<dyn OtaTarget>, String, Option<String>)>,
+  #[cfg(feature = "tls")]
+  tls: bool,
 }
 
 impl <R: Read, W: Write, WIFI: WifiManager<'static>> WifiModuleClient<R, W, WIFI> {
@@ -40,43 +93,397 @@ impl <R: Read, W: Write, WIFI: WifiManager<'static>> WifiModuleClient<R, W, WIFI
       framed_reader,
       framed_writer,
       wifi_manager,
+      assignment_store: None,
+      settings: None,
+      time_sync: None,
+      schedule_clock: None,
+      frost: None,
+      fault_log_poll_interval: Some(DEFAULT_POLL_INTERVAL),
+      config_refresh_interval: Some(DEFAULT_REFRESH_INTERVAL),
+      passthrough_port: None,
+      alerts: AlertConfig::default(),
+      firmware_version: env!("CARGO_PKG_VERSION").to_owned(),
+      relay_port: None,
+      device_name: None,
+      #[cfg(feature = "mqtt")]
+      mqtt: None,
+      #[cfg(feature = "http")]
+      http_port: None,
+      #[cfg(feature = "http")]
+      ota: None,
+      #[cfg(feature = "tls")]
+      tls: false,
     }
   }
 
+  /// Keep the same channel across restarts, see [common_lib::cts_state_machine::CtsStateMachine::set_assignment_store].
+  pub fn set_assignment_store(mut self, store: Box<dyn AssignmentStore>) -> Self {
+    self.assignment_store = Some(store);
+    self
+  }
+
+  /// Take the relay port, device name, relay access policy (see [crate::relay_auth]), static
+  /// IP (see [crate::ip_config]), default OTA URL and webhook URL from `settings` (see
+  /// [crate::settings]).  Without settings anybody may use the relay, over DHCP.  Changes to
+  /// the access policy apply to new connections straight away, the rest from the next start.
+  pub fn set_settings(mut self, settings: Settings) -> Self {
+    self.settings = Some(settings);
+    self
+  }
+
+  /// Set the spa's clock from `clock` every `interval` (see
+  /// [crate::time_sync::DEFAULT_SYNC_INTERVAL]), preserving its 12/24 hour mode.
+  pub fn enable_time_sync(mut self, clock: Box<dyn LocalClock>, interval: Duration) -> Self {
+    self.time_sync = Some(TimeSync::new(clock, interval));
+    self
+  }
+
+  /// Follow the schedule in [Self::set_settings] (see [crate::schedule]), by `clock`'s idea of
+  /// the time and day.  Changes to it apply straight away.
+  pub fn enable_scheduler(mut self, clock: Box<dyn LocalClock>) -> Self {
+    self.schedule_clock = Some(clock);
+    self
+  }
+
+  /// Step in if the water gets near freezing with the heater off, see [crate::frost].
+  pub fn enable_frost_protection(mut self, config: FrostConfig) -> Self {
+    self.frost = Some(config);
+    self
+  }
+
+  /// How often to check the main board's fault log for new entries (see [crate::fault_log]),
+  /// or None to only cache what others ask for.  Defaults to
+  /// [crate::fault_log::DEFAULT_POLL_INTERVAL].
+  pub fn set_fault_log_poll_interval(mut self, interval: Option<Duration>) -> Self {
+    self.fault_log_poll_interval = interval;
+    self
+  }
+
+  /// How often to refresh the spa's configuration, preferences, filter cycles and information
+  /// when no topside panel is attached (see [crate::config_poller]), or None to leave it to
+  /// whoever else is on the bus.  Defaults to [crate::config_poller::DEFAULT_REFRESH_INTERVAL].
+  pub fn set_config_refresh_interval(mut self, interval: Option<Duration>) -> Self {
+    self.config_refresh_interval = interval;
+    self
+  }
+
+  /// Additionally let tools written for serial dongles onto the bus itself, typically on
+  /// [crate::tcp_handler::PASSTHROUGH_TCP_PORT].  See
+  /// [crate::tcp_handler::RelayProtocol::Passthrough] for what that means.
+  pub fn enable_passthrough(mut self, port: u16) -> Self {
+    self.passthrough_port = Some(port);
+    self
+  }
+
+  /// What to raise alerts about and where to send them, see [crate::alerts].  Without a
+  /// webhook URL here, the one in [Self::set_settings] is used, if any.
+  pub fn set_alerts(mut self, config: AlertConfig) -> Self {
+    self.alerts = config;
+    self
+  }
+
+  /// Mirror spa status to MQTT and act on commands from it.  `incoming` must deliver publishes
+  /// on the topics the bridge subscribes to.
+  #[cfg(feature = "mqtt")]
+  pub fn set_mqtt(mut self, bridge: MqttBridge, incoming: Receiver<MqttIncoming>) -> Self {
+    self.mqtt = Some((bridge, incoming));
+    self
+  }
+
+  /// Serve the raw IP relay on `port` rather than [crate::tcp_handler::TCP_PORT] or whatever
+  /// [crate::settings::RELAY_PORT] says.  Useful with more than one module on the network,
+  /// though note the official app only knows the default.
+  pub fn set_relay_port(mut self, port: u16) -> Self {
+    self.relay_port = Some(port);
+    self
+  }
+
+  /// Advertise as `name` rather than whatever [crate::settings::DEVICE_NAME] or the
+  /// [WifiManager] says.  The official app ignores modules not named `BWGS...`.
+  pub fn set_device_name(mut self, name: impl Into<String>) -> Self {
+    self.device_name = Some(name.into());
+    self
+  }
+
+  /// Reported to IP clients that ask, see [crate::relay_hello].  Defaults to the version of
+  /// this library, which is unlikely to mean much to anyone.
+  pub fn set_firmware_version(mut self, version: impl Into<String>) -> Self {
+    self.firmware_version = version.into();
+    self
+  }
+
+  /// Serve the local HTTP API described in [crate::http_handler], typically on
+  /// [crate::http_handler::DEFAULT_HTTP_PORT].
+  #[cfg(feature = "http")]
+  pub fn enable_http_api(mut self, port: u16) -> Self {
+    self.http_port = Some(port);
+    self
+  }
+
+  /// Accept firmware updates through the HTTP API (see [crate::ota]) from whoever presents
+  /// `token`, pulling from `pull_url` when asked to without being given one.  Does nothing
+  /// without [Self::enable_http_api].
+  #[cfg(feature = "http")]
+  pub fn enable_ota(
+      mut self,
+      target: Box<dyn OtaTarget>,
+      token: impl Into<String>,
+      pull_url: Option<String>,
+  ) -> Self {
+    self.ota = Some((target, token.into(), pull_url));
+    self
+  }
+
+  /// Require TLS on every listener, using the certificate in [crate::settings::TLS_IDENTITY]
+  /// or a self-signed one generated on first use.  See [crate::tls] for the caveats.
+  #[cfg(feature = "tls")]
+  pub fn enable_tls(mut self) -> Self {
+    self.tls = true;
+    self
+  }
+
+  fn relay_features(&self) -> RelayFeatures {
+    let mut features = RelayFeatures::FAULT_LOG_CACHE;
+    if self.passthrough_port.is_some() {
+      features = features.with(RelayFeatures::PASSTHROUGH);
+    }
+    #[cfg(feature = "mqtt")]
+    if self.mqtt.is_some() {
+      features = features.with(RelayFeatures::MQTT);
+    }
+    #[cfg(feature = "http")]
+    if self.http_port.is_some() {
+      features = features.with(RelayFeatures::HTTP_API);
+    }
+    #[cfg(feature = "tls")]
+    if self.tls {
+      features = features.with(RelayFeatures::TLS);
+    }
+    features
+  }
+
   pub fn into_runner(
       self
   ) -> io::Result<(ViewModelEventHandle<ViewModel>, Runner<R, W, WIFI>)> {
+    let hello = RelayHello {
+      protocol_version: RELAY_PROTOCOL_VERSION,
+      features: self.relay_features(),
+      firmware_version: self.firmware_version.clone(),
+      spa_model: None,
+    };
     let (commands_tx, commands_rx) = sync_channel(32);
     let (relay_events_tx, relay_events_rx) =
         broadcast_channel(16);
+    // Bus frames go out several times faster than anything else, so they get a broadcaster of
+    // their own rather than crowd everyone else's queues.
+    let (bus_frames_tx, bus_frames_rx) = match self.passthrough_port {
+      Some(_) => {
+        let (tx, rx) = broadcast_channel(64);
+        (Some(tx), Some(rx))
+      }
+      None => (None, None),
+    };
     let message_reader = MessageReader {
+      frames_with_errors: Metrics::global().gauge("wifi_module.frames_with_errors"),
       framed_reader: self.framed_reader,
       commands_tx: commands_tx.clone(),
     };
-    let advertisement = self.wifi_manager.advertisement();
+    let device_name = match (self.device_name, &self.settings) {
+      (None, Some(settings)) => settings.get(DEVICE_NAME).map_err(io::Error::other)?,
+      (name, _) => name,
+    };
+    let advertisement = match device_name {
+      Some(name) => self.wifi_manager.advertisement().clone().set_name(name.trim()),
+      None => self.wifi_manager.advertisement().clone(),
+    };
+    let (view_events_tx, view_model_event_handle) =
+        ViewModelEventHandle::new();
+    let mut wifi_handler = WifiHandler::new(
+        self.wifi_manager,
+        view_events_tx);
+    let static_ip = match &self.settings {
+      None => None,
+      Some(settings) => settings.static_ip().map_err(io::Error::other)?,
+    };
+    if let Some(config) = static_ip {
+      wifi_handler = wifi_handler.set_static_ip(config);
+    }
+    #[cfg(feature = "tls")]
+    let acceptor = if self.tls {
+      let identity = match &self.settings {
+        Some(settings) => settings.tls_identity(&advertisement.name),
+        None => {
+          warn!("No settings to keep the TLS identity in, clients will see a new one every start");
+          TlsIdentity::generate_self_signed(&advertisement.name)
+        }
+      };
+      let tls = identity
+          .and_then(|identity| TlsAcceptor::new(&identity))
+          .map_err(io::Error::other)?;
+      StreamAcceptor::tls(tls)
+    } else {
+      StreamAcceptor::default()
+    };
+    #[cfg(not(feature = "tls"))]
+    let acceptor = StreamAcceptor::default();
+    let mut state = AppState::new(advertisement.clone());
+    if let Some(store) = self.assignment_store {
+      state.cts_state_machine.set_assignment_store(store);
+    }
+    let fault_log = FaultLogCache::default();
+    let spa_cache = SpaStateCache::new(fault_log.clone());
+    let spa_client = SpaClient::new(commands_tx.clone(), spa_cache.clone());
+    #[cfg(feature = "mqtt")]
+    let (mqtt, mqtt_forwarder, mqtt_publisher) = match self.mqtt {
+      Some((bridge, incoming)) => {
+        let (sender, events_tx, publisher) = mqtt_publisher(bridge, spa_client.clone());
+        let forwarder = MqttForwarder { incoming, events_tx };
+        (Some(sender), Some(forwarder), Some(publisher))
+      }
+      None => (None, None, None),
+    };
+    #[cfg(feature = "http")]
+    let history = History::default();
+    #[cfg(feature = "http")]
+    let ota = match self.ota {
+      Some((target, token, pull_url)) => {
+        let pull_url = match (pull_url, &self.settings) {
+          (None, Some(settings)) => settings.get(crate::settings::OTA_URL).map_err(io::Error::other)?,
+          (pull_url, _) => pull_url,
+        };
+        Some(OtaService::new(target, token, pull_url, wifi_handler.ota_progress_reporter()))
+      }
+      None => None,
+    };
+    #[cfg(feature = "http")]
+    let webhook_url = match (self.alerts.webhook_url.clone(), &self.settings) {
+      (None, Some(settings)) => settings.get(crate::settings::WEBHOOK_URL).map_err(io::Error::other)?,
+      (url, _) => url,
+    };
+    #[cfg(feature = "http")]
+    let (webhook_tx, webhook_sender) = match webhook_url {
+      Some(url) => {
+        let (alerts_tx, alerts_rx) = channel();
+        (Some(alerts_tx), Some(WebhookSender { url, alerts_rx }))
+      }
+      None => (None, None),
+    };
+    #[cfg(not(feature = "http"))]
+    if self.alerts.webhook_url.is_some() {
+      warn!("Alert webhooks need the http feature, ignoring");
+    }
+    #[cfg(feature = "http")]
+    let http_handler = match self.http_port {
+      Some(port) => {
+        let state = HttpState {
+          cache: spa_cache.clone(),
+          history: history.clone(),
+          spa: spa_client.clone(),
+          wifi: Arc::new(wifi_handler.model_source()),
+          events_rx: relay_events_rx.clone(),
+          ota,
+        };
+        Some((HttpApiHandler::new(port, acceptor.clone(), state), wifi_handler.provisioned_signal()))
+      }
+      None => None,
+    };
+    let scheduler = match (self.schedule_clock, &self.settings) {
+      (Some(clock), Some(settings)) => {
+        let schedule = settings.schedule().map_err(io::Error::other)?;
+        Some(Scheduler::new(schedule, clock))
+      }
+      (Some(_), None) => {
+        warn!("Scheduler needs settings to keep the schedule in, ignoring");
+        None
+      }
+      (None, _) => None,
+    };
     let event_handler = EventHandler {
       framed_writer: self.framed_writer,
       mainboard_logger: MessageLogger::new(module_path!()),
       commands_rx,
       events_tx: relay_events_tx,
-      state: AppState::new(advertisement.clone()),
+      state,
+      bus_frames_tx,
+      time_sync: self.time_sync,
+      scheduler,
+      frost_guard: self.frost.as_ref().map(FrostGuard::new),
+      fault_log_poller: FaultLogPoller::new(fault_log, self.fault_log_poll_interval),
+      config_poller: ConfigPoller::new(self.config_refresh_interval),
+      #[cfg(feature = "mqtt")]
+      mqtt,
+      spa_cache,
+      #[cfg(feature = "http")]
+      history,
+      confirmations: Confirmations::default(),
+      alerts: AlertMonitor::new(&self.alerts),
+      #[cfg(feature = "http")]
+      webhook_tx,
+      hello,
+    };
+    let relay_port = match (self.relay_port, &self.settings) {
+      (None, Some(settings)) => settings.get_parsed(RELAY_PORT).map_err(io::Error::other)?,
+      (port, _) => port,
+    };
+    let relay_port = relay_port.unwrap_or(TCP_PORT);
+    #[allow(unused_mut)]
+    let mut mdns_services = vec![
+      MdnsService::new("_balboa._tcp", relay_port)
+          .add_txt(format!("mac={}", advertisement.mac_string())),
+    ];
+    #[cfg(feature = "http")]
+    if let Some(port) = self.http_port {
+      mdns_services.push(MdnsService::new("_http._tcp", port));
+    }
+    let discovery_handler = DiscoveryHandler::setup(advertisement.clone(), mdns_services)?;
+    let relay_access = match &self.settings {
+      // Refuse to start rather than quietly open up a relay that was meant to be locked down.
+      Some(settings) => settings.relay_access().map_err(io::Error::other)?,
+      None => RelayAccessPolicy::default(),
+    };
+    let relay_access = Arc::new(RwLock::new(relay_access));
+    let settings_watcher = self.settings.map(|settings| SettingsWatcher {
+      changes: settings.subscribe(),
+      settings,
+      relay_access: relay_access.clone(),
+      commands_tx: commands_tx.clone(),
+    });
+    let passthrough_handler = match (self.passthrough_port, bus_frames_rx) {
+      (Some(port), Some(bus_frames_rx)) => Some(TcpListenerHandler::setup(
+          MessageLogger::new("passthrough"),
+          RelayProtocol::Passthrough,
+          port,
+          acceptor.clone(),
+          relay_access.clone(),
+          commands_tx.clone(),
+          bus_frames_rx)?),
+      _ => None,
     };
-    let discovery_handler = DiscoveryHandler::setup(advertisement.clone())?;
     let tcp_handler = TcpListenerHandler::setup(
         MessageLogger::new("ip_relay"),
+        RelayProtocol::Raw,
+        relay_port,
+        acceptor,
+        relay_access,
         commands_tx,
         relay_events_rx)?;
-    let (view_events_tx, view_model_event_handle) =
-        ViewModelEventHandle::new();
-    let wifi_handler = WifiHandler::new(
-        self.wifi_manager,
-        view_events_tx);
     let runner = Runner {
       message_reader,
       event_handler,
       discovery_handler,
       tcp_handler,
+      passthrough_handler,
       wifi_handler,
+      settings_watcher,
+      spa_client,
+      #[cfg(feature = "mqtt")]
+      mqtt_forwarder,
+      #[cfg(feature = "mqtt")]
+      mqtt_publisher,
+      #[cfg(feature = "http")]
+      http_handler,
+      #[cfg(feature = "http")]
+      webhook_sender,
     };
     Ok((view_model_event_handle, runner))
   }
@@ -87,7 +494,18 @@ pub struct Runner<R, W, WIFI> {
   event_handler: EventHandler<W>,
   discovery_handler: DiscoveryHandler,
   tcp_handler: TcpListenerHandler,
+  passthrough_handler: Option<TcpListenerHandler>,
   wifi_handler: WifiHandler<WIFI>,
+  settings_watcher: Option<SettingsWatcher>,
+  spa_client: SpaClient,
+  #[cfg(feature = "mqtt")]
+  mqtt_forwarder: Option<MqttForwarder>,
+  #[cfg(feature = "mqtt")]
+  mqtt_publisher: Option<MqttPublisher>,
+  #[cfg(feature = "http")]
+  http_handler: Option<(HttpApiHandler, Receiver<()>)>,
+  #[cfg(feature = "http")]
+  webhook_sender: Option<WebhookSender>,
 }
 
 impl <R, W, WIFI> Runner<R, W, WIFI>
@@ -96,6 +514,12 @@ where
     W: Write + Send + 'static,
     WIFI: WifiManager<'static> + Send + 'static
 {
+  /// For controlling the spa from the same process, see [crate::spa_client].  Grab one before
+  /// calling [Self::run_loop].
+  pub fn spa_client(&self) -> SpaClient {
+    self.spa_client.clone()
+  }
+
   pub fn run_loop(self) -> anyhow::Result<()> {
     let reader_thread = thread::Builder::new()
         .name("MessageReader".into())
@@ -116,10 +540,64 @@ where
     let tcp_thread = thread::Builder::new()
         .name("TcpListener".into())
         .spawn(move || {
-          self.tcp_handler.run_loop().unwrap()
+          self.tcp_handler.run_loop()
         })
         .unwrap();
 
+    let passthrough_thread = self.passthrough_handler.map(|passthrough_handler| {
+      thread::Builder::new()
+          .name("PassthroughListener".into())
+          .spawn(move || {
+            passthrough_handler.run_loop()
+          })
+          .unwrap()
+    });
+
+    if let Some(watcher) = self.settings_watcher {
+      // Not joined, it never exits as it holds on to the settings itself.
+      thread::Builder::new()
+          .name("SettingsWatcher".into())
+          .spawn(move || watcher.run_loop())
+          .unwrap();
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(forwarder) = self.mqtt_forwarder {
+      // Not joined, it only exits once the MQTT client goes away.
+      thread::Builder::new()
+          .name("MqttForwarder".into())
+          .spawn(move || forwarder.run_loop())
+          .unwrap();
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(publisher) = self.mqtt_publisher {
+      // Not joined, it exits along with the event handler and forwarder.
+      thread::Builder::new()
+          .name("MqttPublisher".into())
+          .spawn(move || publisher.run_loop())
+          .unwrap();
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(sender) = self.webhook_sender {
+      // Not joined, it exits along with the event handler.
+      thread::Builder::new()
+          .name("Webhook".into())
+          .spawn(move || sender.run_loop())
+          .unwrap();
+    }
+
+    #[cfg(feature = "http")]
+    let http_thread = self.http_handler.map(|(http_handler, provisioned)| {
+      thread::Builder::new()
+          .name("HttpApi".into())
+          .spawn(move || {
+            http_handler.run_loop(provisioned).unwrap()
+          })
+          .unwrap()
+    });
+
     let wifi_thread = thread::Builder::new()
         .name("WifiThread".into())
         .spawn(move || {
@@ -136,6 +614,13 @@ where
     reader_thread.join().unwrap();
     discovery_thread.join().unwrap();
     tcp_thread.join().unwrap();
+    if let Some(passthrough_thread) = passthrough_thread {
+      passthrough_thread.join().unwrap();
+    }
+    #[cfg(feature = "http")]
+    if let Some(http_thread) = http_thread {
+      http_thread.join().unwrap();
+    }
     wifi_thread.join().unwrap();
 
     result
@@ -144,13 +629,16 @@ where
 
 struct MessageReader<R> {
   framed_reader: FramedReader<R>,
+  frames_with_errors: Gauge,
   commands_tx: SyncSender<Command>,
 }
 
 impl<R: Read + Send> MessageReader<R> {
   pub fn run_loop(mut self) -> Result<(), SendError<Command>> {
     loop {
-      match self.framed_reader.next_message() {
+      let result = self.framed_reader.next_message();
+      self.frames_with_errors.set(self.framed_reader.frames_with_errors() as isize);
+      match result {
         Ok(message) => {
           self.commands_tx.send(Command::ReceivedMainboardMessage(message))?;
         }
@@ -164,24 +652,124 @@ impl<R: Read + Send> MessageReader<R> {
   }
 }
 
+/// Applies settings that can change on the fly.
+struct SettingsWatcher {
+  changes: Receiver<String>,
+  settings: Settings,
+  relay_access: Arc<RwLock<RelayAccessPolicy>>,
+  commands_tx: SyncSender<Command>,
+}
+
+impl SettingsWatcher {
+  pub fn run_loop(self) {
+    for key in self.changes {
+      if key == RELAY_ACCESS {
+        match self.settings.relay_access() {
+          Ok(policy) => {
+            info!("Relay access policy changed");
+            *self.relay_access.write().unwrap() = policy;
+          }
+          Err(e) => warn!("Keeping the old relay access policy: {e:?}"),
+        }
+      } else if key == SCHEDULE {
+        match self.settings.schedule() {
+          Ok(schedule) => {
+            if self.commands_tx.send(Command::SetSchedule(schedule)).is_err() {
+              break;
+            }
+          }
+          Err(e) => warn!("Keeping the old schedule: {e:?}"),
+        }
+      }
+    }
+  }
+}
+
+#[cfg(feature = "mqtt")]
+struct MqttForwarder {
+  incoming: Receiver<MqttIncoming>,
+  events_tx: SyncSender<MqttEvent>,
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttForwarder {
+  pub fn run_loop(self) {
+    for incoming in self.incoming {
+      if self.events_tx.send(MqttEvent::Incoming(incoming)).is_err() {
+        break;
+      }
+    }
+  }
+}
+
 struct EventHandler<W> {
   framed_writer: FramedWriter<W>,
   mainboard_logger: MessageLogger,
   commands_rx: Receiver<Command>,
   events_tx: BroadcastSender<RelayEvent>,
+
+  /// Only passthrough listeners get [RelayEvent::BusFrame]s, and only if there are any.
+  bus_frames_tx: Option<BroadcastSender<RelayEvent>>,
   state: AppState,
+  time_sync: Option<TimeSync>,
+  scheduler: Option<Scheduler>,
+  frost_guard: Option<FrostGuard>,
+  fault_log_poller: FaultLogPoller,
+  config_poller: ConfigPoller,
+  #[cfg(feature = "mqtt")]
+  mqtt: Option<MqttSender>,
+  spa_cache: SpaStateCache,
+  #[cfg(feature = "http")]
+  history: History,
+  confirmations: Confirmations,
+  alerts: AlertMonitor,
+  #[cfg(feature = "http")]
+  webhook_tx: Option<Sender<Alert>>,
+
+  /// Everything but the spa model, which we fill in as we learn it.
+  hello: RelayHello,
 }
 
+/// We get one ClearToSend window at a time, so a client sending faster than that is broken.
+const MAX_PASSTHROUGH_FRAMES: usize = 16;
+
+/// How often to check for a silent bus when nothing else is happening.
+const ALERT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 impl <W: Write + Send> EventHandler<W> {
   pub fn run_loop(mut self) -> anyhow::Result<()> {
     loop {
-      let command = self.commands_rx.recv()?;
+      let command = match self.commands_rx.recv_timeout(ALERT_POLL_INTERVAL) {
+        Ok(command) => Some(command),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => Err(RecvError)?,
+      };
+      if let Some(alert) = self.alerts.poll(Instant::now()) {
+        self.raise(alert);
+      }
+      let Some(command) = command else {
+        continue;
+      };
 
       let result = match command {
         Command::ReceivedMainboardMessage(m) => self.handle_mainboard_message(m),
         Command::ReadError(e) => Err(FatalError(e.to_string())),
         Command::Shutdown => Err(ShutdownRequested),
-        Command::RelayIpMessage(m) => self.handle_relay_message(m),
+        Command::RelayIpMessage(client, m) => self.handle_relay_message(client, m),
+        Command::PassthroughFrame(client, m) => {
+          self.handle_passthrough_frame(client, m);
+          Ok(())
+        }
+        Command::Spa(request) => {
+          self.handle_spa_request(*request);
+          Ok(())
+        }
+        Command::SetSchedule(schedule) => {
+          if let Some(scheduler) = &mut self.scheduler {
+            scheduler.set_schedule(schedule);
+          }
+          Ok(())
+        }
       };
 
       if let Err(ref e) = result {
@@ -202,10 +790,77 @@ impl <W: Write + Send> EventHandler<W> {
 
   fn handle_mainboard_message(&mut self, message: Message) -> Result<(), HandlingError> {
     self.mainboard_logger.log(MessageDirection::Inbound, &message);
+    if let Some(bus_frames_tx) = &mut self.bus_frames_tx {
+      bus_frames_tx.send_to_all(&RelayEvent::BusFrame(message.clone()));
+    }
 
     let mt = MessageType::try_from(&message)
         .map_err(|e| HandlingError::UnexpectedPayload(e.to_string()))?;
 
+    let now = Instant::now();
+    for unconfirmed in self.confirmations.on_message(&mt, now) {
+      self.on_unconfirmed(unconfirmed);
+    }
+    self.spa_cache.update(&mt);
+    if let Some(alert) = self.alerts.on_message(&mt, now) {
+      self.raise(alert);
+    }
+    #[cfg(feature = "http")]
+    if let MessageType::StatusUpdate(status) = &mt {
+      self.history.on_status(&status.v1, now);
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = &self.mqtt {
+      if matches!(mt, MessageType::StatusUpdate(_) | MessageType::FaultLogResponse(_)) {
+        mqtt.send(MqttEvent::Message(mt.clone()));
+      }
+    }
+
+    if self.fault_log_poller.on_message(&mt) {
+      #[cfg(feature = "mqtt")]
+      if let Some(mqtt) = &self.mqtt {
+        mqtt.send(MqttEvent::FaultLog(self.spa_cache.fault_log().entries()));
+      }
+    }
+
+    let our_channel = self.state.cts_state_machine.current_assignment().map(|a| a.channel);
+    self.config_poller.on_message(&message.channel, &mt, our_channel.as_ref(), now);
+    if our_channel.is_some() {
+      if let Some(request) = self.config_poller.poll(now) {
+        self.enqueue_message_to_board(MessageSource::Internal, request);
+      }
+      if let Some(request) = self.fault_log_poller.poll(now) {
+        self.enqueue_message_to_board(MessageSource::Internal, request);
+      }
+    }
+    if let Some(frost_guard) = &mut self.frost_guard {
+      let alert = frost_guard.on_message(&mt, now);
+      let request = our_channel.and_then(|_| frost_guard.poll(now));
+      if let Some(alert) = alert {
+        self.raise(alert);
+      }
+      if let Some(request) = request {
+        self.enqueue_message_to_board(MessageSource::Internal, request);
+      }
+    }
+    if let Some(scheduler) = &mut self.scheduler {
+      scheduler.on_message(&mt);
+      if our_channel.is_some() {
+        if let Some(request) = scheduler.poll(now) {
+          self.enqueue_message_to_board(MessageSource::Internal, request);
+        }
+      }
+    }
+    if let Some(time_sync) = &mut self.time_sync {
+      time_sync.on_message(&mt);
+      if let Some(request) = time_sync.poll(now) {
+        self.enqueue_message_to_board(MessageSource::Internal, request);
+      }
+    }
+
+    self.state.cts_state_machine.tick(&mut self.framed_writer, &self.mainboard_logger, now)?;
+    self.state.wifi_state_machine.tick(&mut self.framed_writer, &self.mainboard_logger, now)?;
     self.state.cts_state_machine.handle_message(&mut self.framed_writer, &self.mainboard_logger, &message.channel, &mt)?;
     if let Some(channel) = self.state.cts_state_machine.take_got_channel() {
       info!("Setting channel filter for {:?}", channel);
@@ -222,32 +877,102 @@ impl <W: Write + Send> EventHandler<W> {
     Ok(())
   }
 
-  fn handle_relay_message(&mut self, message: Message) -> Result<(), HandlingError> {
+  fn handle_relay_message(&mut self, client: RelayClientId, message: Message) -> Result<(), HandlingError> {
+    if is_hello_request(&message) {
+      self.answer_hello(client, &message);
+      return Ok(());
+    }
     let mt = MessageType::try_from(&message)?;
 
     match mt {
       MessageType::ExistingClientRequest() => {
         if message.channel == Channel::WifiModule {
           info!("Interpreting ExistingClientRequest as Wifi Config request...");
-          self.enqueue_message_to_app(MessageType::WifiModuleConfigurationResponse(
+          let response = MessageType::WifiModuleConfigurationResponse(
             WifiModuleIdentificationMessage {
               mac: self.state.advertisement.mac,
             }
-          ).to_message(Channel::WifiModule)?);
+          ).to_message(Channel::WifiModule)?;
+          // Only the asking client cares, the others may be mid-conversation with the board.
+          self.events_tx.send_to_all(&RelayEvent::MessageForClient(client, response));
         } else {
           info!("Got existing channel request on channel={:?} ???", message.channel);
         }
       }
+      MessageType::SettingsRequest(SettingsRequestMessage::FaultLog { entry_num }) => {
+        match self.spa_cache.fault_log().get(entry_num) {
+          Some(fault) => {
+            debug!("Answering fault log request for entry {entry_num} from cache");
+            let response = MessageType::FaultLogResponse(fault).to_message(Channel::WifiModule)?;
+            self.events_tx.send_to_all(&RelayEvent::MessageForClient(client, response));
+          }
+          None => self.enqueue_message_to_board(MessageSource::Relay(client), mt),
+        }
+      }
       mt => {
-        self.enqueue_message_to_board(mt);
+        self.enqueue_message_to_board(MessageSource::Relay(client), mt);
       }
     }
 
     Ok(())
   }
 
-  fn enqueue_message_to_board(&mut self, message: MessageType) {
-    self.state.wifi_state_machine.context.outbound_messages.push_back(message);
+  fn answer_hello(&mut self, client: RelayClientId, request: &Message) {
+    debug!("Hello from {client:?}, speaking version {:?}", request.payload.first());
+    let hello = self.hello.with_spa(self.spa_cache.snapshot().information.as_ref());
+    self.events_tx.send_to_all(&RelayEvent::MessageForClient(client, hello.to_message()));
+  }
+
+  fn handle_passthrough_frame(&mut self, client: RelayClientId, message: Message) {
+    let frames = &mut self.state.wifi_state_machine.context.passthrough_frames;
+    if frames.len() >= MAX_PASSTHROUGH_FRAMES {
+      warn!("Dropping passthrough frame from {client:?}, too many waiting for a ClearToSend");
+      return;
+    }
+    frames.push_back(message);
+  }
+
+  fn handle_spa_request(&mut self, request: SpaRequest) {
+    self.enqueue_tracked(MessageSource::SpaClient, request.mt, Some(request.done));
+  }
+
+  fn on_unconfirmed(&mut self, unconfirmed: Unconfirmed) {
+    match unconfirmed {
+      Unconfirmed::Retry(source, mt) => {
+        self.state.wifi_state_machine.context.outbound_messages.push(source, mt);
+      }
+      Unconfirmed::Failed(command) => {
+        warn!("Gave up on {command}");
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &self.mqtt {
+          mqtt.send(MqttEvent::CommandFailed(command.clone()));
+        }
+        #[cfg(feature = "http")]
+        self.events_tx.send_to_all(&RelayEvent::CommandFailed(command));
+      }
+    }
+  }
+
+  fn raise(&mut self, alert: Alert) {
+    warn!("Alert: {alert}");
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = &self.mqtt {
+      mqtt.send(MqttEvent::Alert(alert.clone()));
+    }
+    #[cfg(feature = "http")]
+    if let Some(webhook_tx) = &self.webhook_tx {
+      let _ = webhook_tx.send(alert);
+    }
+  }
+
+  fn enqueue_message_to_board(&mut self, source: MessageSource, message: MessageType) {
+    self.enqueue_tracked(source, message, None);
+  }
+
+  /// Queues `message`, watching for it to take effect if it's a command.
+  fn enqueue_tracked(&mut self, source: MessageSource, message: MessageType, done: Option<DoneSender>) {
+    self.confirmations.track(source, &message, done, Instant::now());
+    self.state.wifi_state_machine.context.outbound_messages.push(source, message);
   }
 
   fn enqueue_message_to_app(&mut self, mut message: Message) {
@@ -5,13 +5,23 @@ use balboa_spa_messages::message::Message;
 use balboa_spa_messages::message_types::{MessageType, WifiModuleIdentificationMessage};
 use common_lib::message_state_machine::{MessageState, MessageStateMachine, SmResult, StateArgs};
 use common_lib::message_state_machine::SmResult::{HandledNoReply, NotHandled, SendReply};
+use common_lib::metrics::Metrics;
+use crate::outbound_queue::OutboundQueue;
 
 pub type WifiStateMachine = MessageStateMachine<StateRelaying>;
 
 #[derive(Default, Debug)]
 pub struct WifiContext {
   pub for_relay_messages: VecDeque<Message>,
-  pub outbound_messages: VecDeque<MessageType>,
+  pub outbound_messages: OutboundQueue,
+
+  /// Frames from passthrough clients, sent exactly as they came.  See
+  /// [crate::tcp_handler::RelayProtocol::Passthrough].
+  pub passthrough_frames: VecDeque<Message>,
+
+  /// Whose turn the next ClearToSend is when both passthrough frames and outbound messages
+  /// are waiting, so neither can starve the other.
+  pub passthrough_turn: bool,
 }
 
 #[derive(Default, Debug)]
@@ -28,9 +38,19 @@ impl MessageState for StateRelaying {
   fn handle_message(&self, args: &mut StateArgs<Self::Kind, Self::Context>) -> SmResult {
     match args.mt {
       MessageType::ClearToSend() => {
-        let reply = args.context.outbound_messages.pop_front()
-            .unwrap_or(MessageType::NothingToSend());
-        SendReply(reply.to_message(*args.channel))
+        let context = &mut *args.context;
+        let passthrough_first = context.passthrough_turn || context.outbound_messages.is_empty();
+        if passthrough_first {
+          if let Some(frame) = context.passthrough_frames.pop_front() {
+            context.passthrough_turn = false;
+            return SendReply(Ok(frame));
+          }
+        }
+        if let Some(mt) = context.outbound_messages.pop_front() {
+          context.passthrough_turn = true;
+          return SendReply(mt.to_message(*args.channel));
+        }
+        SendReply(MessageType::NothingToSend().to_message(*args.channel))
       }
       mt => {
         let relay_channel = match args.channel {
@@ -40,6 +60,7 @@ impl MessageState for StateRelaying {
         let message = mt.clone().to_message(relay_channel)
             .expect("Failed to re-encode message");
         args.context.for_relay_messages.push_back(message);
+        Metrics::global().counter("wifi_module.relayed_messages").inc();
 
         // No reply yet.  We'll forward this to our peer over Wi-Fi and if they have something
         // to say we'll put it into outbound_messages queue and send on the next CTS window.
@@ -53,3 +74,38 @@ impl MessageState for StateRelaying {
 pub enum WifiStateKind {
   Relaying,
 }
+
+#[cfg(test)]
+mod tests {
+  use balboa_spa_messages::framed_writer::FramedWriter;
+  use balboa_spa_messages::message_types::ItemCode;
+  use balboa_spa_messages::parsed_enum::ParsedEnum;
+  use common_lib::message_logger::MessageLogger;
+  use crate::outbound_queue::MessageSource;
+  use super::*;
+
+  #[test]
+  fn test_passthrough_takes_turns() -> anyhow::Result<()> {
+    let mut sm = WifiStateMachine::default();
+    let mut writer = FramedWriter::new(Vec::new());
+    let logger = MessageLogger::new("test");
+    let frame = MessageType::NothingToSend().to_message(Channel::WifiModule)?;
+    sm.context.passthrough_frames.extend([frame.clone(), frame.clone(), frame]);
+    for _ in 0..2 {
+      sm.context.outbound_messages.push(MessageSource::SpaClient, MessageType::ToggleItemRequest {
+        item_code: ParsedEnum::new(ItemCode::Pump1),
+        dummy1: 0,
+      });
+    }
+
+    let mut sent_passthrough = vec![];
+    for _ in 0..5 {
+      let before = sm.context.passthrough_frames.len();
+      sm.handle_message(&mut writer, &logger, &Channel::WifiModule, &MessageType::ClearToSend())?;
+      sent_passthrough.push(sm.context.passthrough_frames.len() < before);
+    }
+    assert_eq!(sent_passthrough, vec![false, true, false, true, true]);
+    assert!(sm.context.outbound_messages.is_empty());
+    Ok(())
+  }
+}